@@ -0,0 +1,128 @@
+//! G.711 companding, the 8-bit µ-law and A-law encodings of telephony.
+//!
+//! Both map 16-bit samples to 8 bits with a resolution that follows the loudness of
+//! the signal, as in the reference implementation of the ITU. µ-law is used in North
+//! America and Japan, A-law elsewhere.
+
+/// The sample rate of G.711 audio on telephone networks
+pub const TELEPHONY_SAMPLE_RATE: usize = 8000;
+
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 32635;
+const SIGN_BIT: u8 = 0x80;
+const QUANT_MASK: u8 = 0x0f;
+const SEG_SHIFT: u8 = 4;
+const SEG_MASK: u8 = 0x70;
+/// The largest 13-bit magnitude of each A-law segment
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+
+/// Encode a 16-bit sample as µ-law
+pub fn linear_to_mulaw(sample: i16) -> u8 {
+    let sample = sample as i32;
+    let sign = if sample < 0 { SIGN_BIT } else { 0 };
+    let magnitude = sample.abs().min(MULAW_CLIP) + MULAW_BIAS;
+    // The segment is the position of the highest bit above the 7 lowest ones
+    let exponent = 7 - ((magnitude >> 7) as u8).leading_zeros() as u8;
+    let mantissa = (magnitude >> (exponent + 3)) as u8 & QUANT_MASK;
+    !(sign | exponent << SEG_SHIFT | mantissa)
+}
+
+/// Decode a µ-law sample to 16 bits
+pub fn mulaw_to_linear(mulaw: u8) -> i16 {
+    let mulaw = !mulaw;
+    let magnitude =
+        ((((mulaw & QUANT_MASK) as i32) << 3) + MULAW_BIAS) << ((mulaw & SEG_MASK) >> SEG_SHIFT);
+    if mulaw & SIGN_BIT != 0 {
+        (MULAW_BIAS - magnitude) as i16
+    } else {
+        (magnitude - MULAW_BIAS) as i16
+    }
+}
+
+/// Encode a 16-bit sample as A-law
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    // A-law works on 13 bits, and inverts every other bit
+    let sample = (sample >> 3) as i32;
+    let (mask, magnitude) = if sample >= 0 {
+        (0xd5, sample)
+    } else {
+        (0x55, -sample - 1)
+    };
+    let Some(segment) = ALAW_SEGMENT_ENDS.iter().position(|end| magnitude <= *end) else {
+        return 0x7f ^ mask;
+    };
+    let shift = if segment < 2 { 1 } else { segment };
+    let alaw = (segment as u8) << SEG_SHIFT | (magnitude >> shift) as u8 & QUANT_MASK;
+    alaw ^ mask
+}
+
+/// Decode an A-law sample to 16 bits
+pub fn alaw_to_linear(alaw: u8) -> i16 {
+    let alaw = alaw ^ 0x55;
+    let mut magnitude = ((alaw & QUANT_MASK) as i32) << 4;
+    let segment = (alaw & SEG_MASK) >> SEG_SHIFT;
+    magnitude += if segment == 0 { 8 } else { 0x108 };
+    if segment > 1 {
+        magnitude <<= segment - 1;
+    }
+    if alaw & SIGN_BIT != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mulaw() {
+        assert_eq!(linear_to_mulaw(0), 0xff);
+        assert_eq!(linear_to_mulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_mulaw(i16::MIN), 0x00);
+        assert_eq!(mulaw_to_linear(0xff), 0);
+        assert_eq!(mulaw_to_linear(0x80), 32124);
+        assert_eq!(mulaw_to_linear(0x00), -32124);
+        // Every code decodes to a value that encodes to the same code, except the
+        // negative zero
+        for mulaw in 0..=u8::MAX {
+            if mulaw != 0x7f {
+                assert_eq!(linear_to_mulaw(mulaw_to_linear(mulaw)), mulaw);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alaw() {
+        assert_eq!(linear_to_alaw(0), 0xd5);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xaa);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2a);
+        assert_eq!(alaw_to_linear(0xd5), 8);
+        assert_eq!(alaw_to_linear(0xaa), 32256);
+        assert_eq!(alaw_to_linear(0x2a), -32256);
+        for alaw in 0..=u8::MAX {
+            assert_eq!(linear_to_alaw(alaw_to_linear(alaw)), alaw);
+        }
+    }
+
+    #[test]
+    fn test_companding_error() {
+        // The error grows with the magnitude, staying within a few percent of it
+        for sample in (-32000..32000).step_by(97) {
+            let sample = sample as i16;
+            for decoded in [
+                mulaw_to_linear(linear_to_mulaw(sample)),
+                alaw_to_linear(linear_to_alaw(sample)),
+            ] {
+                let error = (decoded as i32 - sample as i32).abs();
+                assert!(
+                    error <= (sample as i32).abs() / 16 + 16,
+                    "{} -> {}",
+                    sample,
+                    decoded
+                );
+            }
+        }
+    }
+}
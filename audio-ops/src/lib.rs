@@ -1,6 +1,20 @@
+mod g711;
+pub(crate) mod hanning_window;
+mod overlap;
 mod samples;
+mod wave_metadata;
+mod wave_reader;
 mod wave_writer;
-pub(crate) mod hanning_window;
 
-pub use samples::{Audio, AudioInfo, AudioSamples};
-pub use wave_writer::{write_wave_samples_to_buffer, write_wave_samples_to_file, WaveWriterError};
+pub use g711::{
+    alaw_to_linear, linear_to_alaw, linear_to_mulaw, mulaw_to_linear, TELEPHONY_SAMPLE_RATE,
+};
+pub use overlap::OverlapWindow;
+pub use samples::{Audio, AudioFormatError, AudioInfo, AudioSamples, StageTimings};
+pub use wave_metadata::{WaveMetadata, MAX_COMMENT_LEN};
+pub use wave_reader::{read_wave_bytes, read_wave_file, WaveReaderError};
+pub use wave_writer::{
+    streaming_wave_header, write_float_samples_to_buffer, write_float_samples_to_file,
+    write_wave_samples_to_buffer, write_wave_samples_to_file,
+    write_wave_samples_to_file_with_metadata, WaveFileWriter, WaveSampleFormat, WaveWriterError,
+};
@@ -0,0 +1,87 @@
+//! Joining consecutive blocks of audio that share some samples.
+
+use std::str::FromStr;
+
+const PI: f32 = std::f32::consts::PI;
+
+/// The fades applied where two blocks of audio overlap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverlapWindow {
+    /// Raised-cosine fades. Their gains add up to one, and they are smooth at both ends
+    #[default]
+    Hann,
+    /// Straight-line fades
+    Linear,
+    /// No fades: the overlap is cut in its middle
+    None,
+}
+
+impl OverlapWindow {
+    /// Gain of the incoming block at sample `index` of an overlap of `len` samples.
+    /// The outgoing block gets the complementary gain
+    pub fn fade_in_gain(&self, index: usize, len: usize) -> f32 {
+        let position = (index as f32 + 0.5) / len as f32;
+        match self {
+            Self::Hann => (position * PI / 2.0).sin().powi(2),
+            Self::Linear => position,
+            Self::None => {
+                if index < len / 2 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+    /// Mix `tail`, the end of a block, into `head`, the start of the block that follows
+    /// it. Only the samples both have are mixed
+    pub fn overlap_add(&self, tail: &[f32], head: &mut [f32]) {
+        let len = tail.len().min(head.len());
+        for (i, (outgoing, incoming)) in tail.iter().zip(head.iter_mut()).enumerate() {
+            let gain = self.fade_in_gain(i, len);
+            *incoming = *incoming * gain + *outgoing * (1.0 - gain);
+        }
+    }
+}
+
+impl FromStr for OverlapWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hann" => Ok(Self::Hann),
+            "linear" => Ok(Self::Linear),
+            "none" => Ok(Self::None),
+            other => Err(format!(
+                "Unknown overlap window `{}`. Expected `hann`, `linear` or `none`",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlap_add() {
+        for window in [
+            OverlapWindow::Hann,
+            OverlapWindow::Linear,
+            OverlapWindow::None,
+        ] {
+            let mut head = vec![0.5; 64];
+            window.overlap_add(&[0.5; 64], &mut head);
+            assert!(head.iter().all(|s| (s - 0.5).abs() < 1e-6), "{:?}", window);
+        }
+        let mut head = vec![1.0; 4];
+        OverlapWindow::None.overlap_add(&[0.0; 4], &mut head);
+        assert_eq!(head, vec![0.0, 0.0, 1.0, 1.0]);
+        let mut head = vec![1.0; 4];
+        OverlapWindow::Linear.overlap_add(&[0.0; 2], &mut head);
+        assert_eq!(head, vec![0.25, 0.75, 1.0, 1.0]);
+        assert_eq!("Hann".parse(), Ok(OverlapWindow::Hann));
+        assert!("cosine".parse::<OverlapWindow>().is_err());
+    }
+}
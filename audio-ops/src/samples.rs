@@ -1,18 +1,33 @@
 use crate::hanning_window;
+use std::ops::AddAssign;
 use std::path::Path;
+use std::time::Duration;
 
 const PI: f32 = std::f32::consts::PI;
 const I16MIN_F32: f32 = i16::MIN as f32;
 const I16MAX_F32: f32 = i16::MAX as f32;
-const MAX_WAV_VALUE_I16: f32 = 32767.0;
+pub(crate) const MAX_WAV_VALUE_I16: f32 = 32767.0;
+pub(crate) const MAX_WAV_VALUE_I24: f32 = 8388607.0;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AudioInfo {
     pub sample_rate: usize,
     pub num_channels: usize,
     pub sample_width: usize,
 }
 
+/// Audio of different formats was combined
+#[derive(Debug)]
+pub struct AudioFormatError(String);
+
+impl std::error::Error for AudioFormatError {}
+
+impl std::fmt::Display for AudioFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 #[must_use]
 pub struct AudioSamples(Vec<f32>);
@@ -36,11 +51,9 @@ impl AudioSamples {
     pub fn take(&mut self) -> Vec<f32> {
         std::mem::take(&mut self.0)
     }
-    pub fn take_range(&mut self, mut  sample_range: std::ops::Range<usize>) -> Vec<f32> {
+    pub fn take_range(&mut self, mut sample_range: std::ops::Range<usize>) -> Vec<f32> {
         sample_range.end = sample_range.end.min(self.len());
-        Vec::from_iter(
-            self.0.drain(sample_range)
-        )
+        Vec::from_iter(self.0.drain(sample_range))
     }
     pub fn len(&self) -> usize {
         self.0.len()
@@ -49,30 +62,34 @@ impl AudioSamples {
         self.0.is_empty()
     }
     pub fn to_i16_vec(&self) -> Vec<i16> {
-        if self.is_empty() {
-            return Default::default();
-        }
-        let min_audio_value = self
-            .0
-            .iter()
-            .min_by(|x, y| x.partial_cmp(y).unwrap())
-            .unwrap();
-        let max_audio_value = self
-            .0
-            .iter()
-            .max_by(|x, y| x.partial_cmp(y).unwrap())
-            .unwrap();
-        let abs_max = max_audio_value
-            .abs()
-            .max(min_audio_value.abs())
-            .max(f32::EPSILON);
-        let audio_scale = MAX_WAV_VALUE_I16 / abs_max;
+        let audio_scale = MAX_WAV_VALUE_I16 / self.peak();
         Vec::from_iter(
             self.0
                 .iter()
                 .map(|f| (f * audio_scale).clamp(I16MIN_F32, I16MAX_F32) as i16),
         )
     }
+    /// 24-bit samples (in the low bytes of each `i32`), normalized like [`Self::to_i16_vec`]
+    pub fn to_i24_vec(&self) -> Vec<i32> {
+        let audio_scale = MAX_WAV_VALUE_I24 / self.peak();
+        Vec::from_iter(
+            self.0.iter().map(|f| {
+                (f * audio_scale).clamp(-MAX_WAV_VALUE_I24 - 1.0, MAX_WAV_VALUE_I24) as i32
+            }),
+        )
+    }
+    /// Samples scaled so the peak is at full scale (`1.0`), like the integer conversions
+    pub fn to_full_scale_f32_vec(&self) -> Vec<f32> {
+        let audio_scale = 1.0 / self.peak();
+        Vec::from_iter(self.0.iter().map(|f| f * audio_scale))
+    }
+    /// The largest absolute sample value
+    fn peak(&self) -> f32 {
+        self.0
+            .iter()
+            .fold(0.0f32, |peak, f| peak.max(f.abs()))
+            .max(f32::EPSILON)
+    }
     pub fn as_wave_bytes(&self) -> Vec<u8> {
         Vec::from_iter(self.to_i16_vec().into_iter().flat_map(|i| i.to_le_bytes()))
     }
@@ -182,6 +199,53 @@ impl AudioSamples {
     pub fn to_decibel(&self) -> Vec<f32> {
         Vec::from_iter(self.0.iter().map(|x| 20.0 * x.abs().log10()))
     }
+    /// Resample from `from_rate` to `to_rate` using linear interpolation
+    pub fn resample(&self, from_rate: usize, to_rate: usize) -> Self {
+        if from_rate == to_rate || self.is_empty() {
+            return self.clone();
+        }
+        let ratio = from_rate as f64 / to_rate as f64;
+        let out_len = ((self.len() as f64) / ratio).round() as usize;
+        let last = self.len() - 1;
+        Self::new(Vec::from_iter((0..out_len).map(|i| {
+            let pos = i as f64 * ratio;
+            let idx = (pos.floor() as usize).min(last);
+            let frac = (pos - idx as f64) as f32;
+            let next = self.0[(idx + 1).min(last)];
+            self.0[idx] + (next - self.0[idx]) * frac
+        })))
+    }
+    /// Like [`Self::resample`], but when downsampling, filter out the frequencies above
+    /// the new Nyquist frequency first, which would otherwise fold back as noise
+    pub fn resample_antialiased(&self, from_rate: usize, to_rate: usize) -> Self {
+        if to_rate >= from_rate || self.is_empty() {
+            return self.resample(from_rate, to_rate);
+        }
+        // A windowed sinc, with the cutoff a little below the new Nyquist frequency
+        let cutoff = 0.45 * to_rate as f32 / from_rate as f32;
+        let half_len = (4.0 / cutoff).ceil() as usize;
+        let window = hanning_window::get_hann_window(half_len * 2 + 1);
+        let mut kernel = Vec::from_iter(window.iter().enumerate().map(|(i, w)| {
+            let x = 2.0 * PI * cutoff * (i as f32 - half_len as f32);
+            let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+            sinc * w
+        }));
+        let gain: f32 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|k| *k /= gain);
+        // Samples past the ends repeat the first and last ones
+        let last = self.len() as isize - 1;
+        let filtered = Self::new(Vec::from_iter((0..self.len()).map(|i| {
+            kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let idx = (i as isize + k as isize - half_len as isize).clamp(0, last);
+                    self.0[idx as usize] * weight
+                })
+                .sum()
+        })));
+        filtered.resample(from_rate, to_rate)
+    }
 }
 
 impl From<AudioSamples> for Vec<f32> {
@@ -205,12 +269,52 @@ impl IntoIterator for AudioSamples {
     }
 }
 
+/// Time spent in each stage of synthesis, in milliseconds.
+///
+/// Normalization, phonemization and tashkeel run once per utterance, so they are only
+/// set on utterance totals. Encoder and decoder are zero for models that run as a
+/// single graph, whose inference is only in `inference_ms`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StageTimings {
+    pub normalization_ms: f32,
+    pub phonemization_ms: f32,
+    pub tashkeel_ms: f32,
+    pub encoder_ms: f32,
+    pub decoder_ms: f32,
+    pub postprocessing_ms: f32,
+}
+
+impl StageTimings {
+    pub fn total_ms(&self) -> f32 {
+        self.normalization_ms
+            + self.phonemization_ms
+            + self.tashkeel_ms
+            + self.encoder_ms
+            + self.decoder_ms
+            + self.postprocessing_ms
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.normalization_ms += other.normalization_ms;
+        self.phonemization_ms += other.phonemization_ms;
+        self.tashkeel_ms += other.tashkeel_ms;
+        self.encoder_ms += other.encoder_ms;
+        self.decoder_ms += other.decoder_ms;
+        self.postprocessing_ms += other.postprocessing_ms;
+    }
+}
+
 #[derive(Debug, Clone)]
 #[must_use]
 pub struct Audio {
     pub samples: AudioSamples,
     pub info: AudioInfo,
     pub inference_ms: Option<f32>,
+    /// Per-stage breakdown of the time spent synthesizing this audio
+    pub timings: StageTimings,
 }
 
 impl Audio {
@@ -218,6 +322,7 @@ impl Audio {
         Self {
             samples,
             inference_ms,
+            timings: StageTimings::default(),
             info: AudioInfo {
                 sample_rate,
                 num_channels: 1,
@@ -242,8 +347,53 @@ impl Audio {
         self.samples.is_empty()
     }
 
+    /// Number of frames, i.e. samples per channel
+    pub fn num_frames(&self) -> usize {
+        self.len() / self.info.num_channels.max(1)
+    }
+
     pub fn duration_ms(&self) -> f32 {
-        (self.len() as f32 / self.info.sample_rate as f32) * 1000.0f32
+        (self.num_frames() as f32 / self.info.sample_rate as f32) * 1000.0f32
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.num_frames() as f64 / self.info.sample_rate as f64)
+    }
+
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        self.samples.as_slice().to_vec()
+    }
+
+    /// Iterate over the frames, each holding one sample per channel
+    pub fn iter_frames(&self) -> std::slice::ChunksExact<'_, f32> {
+        self.samples
+            .as_slice()
+            .chunks_exact(self.info.num_channels.max(1))
+    }
+
+    /// Render the audio as interleaved stereo, placed at `pan` between the left
+    /// (`-1.0`) and the right (`1.0`) channel.
+    ///
+    /// Uses constant-power panning, so the perceived loudness doesn't depend on the
+    /// position. Multi-channel audio is mixed down to mono first.
+    pub fn to_stereo(&self, pan: f32) -> Audio {
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+        let (left_gain, right_gain) = (angle.cos(), angle.sin());
+        let mut samples = Vec::with_capacity(self.num_frames() * 2);
+        for frame in self.iter_frames() {
+            let value = frame.iter().sum::<f32>() / frame.len() as f32;
+            samples.push(value * left_gain);
+            samples.push(value * right_gain);
+        }
+        Audio {
+            samples: samples.into(),
+            info: AudioInfo {
+                num_channels: 2,
+                ..self.info.clone()
+            },
+            inference_ms: self.inference_ms,
+            timings: self.timings,
+        }
     }
 
     pub fn inference_ms(&self) -> Option<f32> {
@@ -259,6 +409,11 @@ impl Audio {
         Some(infer_ms / audio_duration)
     }
 
+    /// Write the audio to a WAV file
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<(), crate::WaveWriterError> {
+        self.save_to_file(path.as_ref())
+    }
+
     pub fn save_to_file(&self, filename: &Path) -> Result<(), crate::WaveWriterError> {
         crate::write_wave_samples_to_file(
             filename,
@@ -268,6 +423,152 @@ impl Audio {
             self.info.sample_width as u32,
         )
     }
+
+    /// Join `parts` back to back. They must all have the same sample rate and channels.
+    pub fn concat(parts: &[Audio]) -> Result<Audio, AudioFormatError> {
+        let Some(first) = parts.first() else {
+            return Err(AudioFormatError("No audio to concatenate".to_string()));
+        };
+        let mut samples = Vec::with_capacity(parts.iter().map(Audio::len).sum());
+        for part in parts {
+            first.check_same_format(part)?;
+            samples.extend_from_slice(part.samples.as_slice());
+        }
+        let inference_ms = parts.iter().map(|part| part.inference_ms).sum();
+        let mut timings = StageTimings::default();
+        for part in parts {
+            timings += part.timings;
+        }
+        Ok(Audio {
+            samples: samples.into(),
+            info: first.info.clone(),
+            inference_ms,
+            timings,
+        })
+    }
+
+    pub fn append_silence(&mut self, duration_ms: u32) {
+        let num_samples = self.ms_to_frames(duration_ms) * self.info.num_channels;
+        let samples = self.samples.as_mut_vec();
+        samples.resize(samples.len() + num_samples, 0.0);
+    }
+
+    /// Remove the frames before the first one with a sample louder than `threshold`
+    pub fn trim_leading_silence(&mut self, threshold: f32) {
+        let num_silent = self
+            .iter_frames()
+            .take_while(|frame| frame.iter().all(|s| s.abs() <= threshold))
+            .count();
+        self.samples
+            .as_mut_vec()
+            .drain(..num_silent * self.info.num_channels);
+    }
+
+    /// Remove the frames after the last one with a sample louder than `threshold`
+    pub fn trim_trailing_silence(&mut self, threshold: f32) {
+        let num_silent = self
+            .iter_frames()
+            .rev()
+            .take_while(|frame| frame.iter().all(|s| s.abs() <= threshold))
+            .count();
+        let len = (self.iter_frames().len() - num_silent) * self.info.num_channels;
+        self.samples.as_mut_vec().truncate(len);
+    }
+
+    /// Add `other` to this audio starting at `offset_ms`, extending it as needed
+    pub fn mix(&mut self, other: &Audio, offset_ms: u32) -> Result<(), AudioFormatError> {
+        self.check_same_format(other)?;
+        let offset = self.ms_to_frames(offset_ms) * self.info.num_channels;
+        let samples = self.samples.as_mut_vec();
+        let end = offset + other.len();
+        if samples.len() < end {
+            samples.resize(end, 0.0);
+        }
+        for (sample, value) in samples[offset..end]
+            .iter_mut()
+            .zip(other.samples.as_slice())
+        {
+            *sample += value;
+        }
+        Ok(())
+    }
+
+    /// Append `other`, crossfading the last `overlap_ms` of this audio into its start
+    pub fn append_with_crossfade(
+        &mut self,
+        other: &Audio,
+        overlap_ms: u32,
+    ) -> Result<(), AudioFormatError> {
+        self.check_same_format(other)?;
+        let num_channels = self.info.num_channels.max(1);
+        let overlap = self
+            .ms_to_frames(overlap_ms)
+            .min(self.num_frames())
+            .min(other.num_frames());
+        let start = self.len() - overlap * num_channels;
+        let samples = self.samples.as_mut_vec();
+        let other_samples = other.samples.as_slice();
+        for (i, (sample, value)) in samples[start..].iter_mut().zip(other_samples).enumerate() {
+            // Equal-power fade, the same for each channel of a frame
+            let t = ((i / num_channels) as f32 + 0.5) / overlap as f32 * PI / 2.0;
+            *sample = *sample * t.cos() + value * t.sin();
+        }
+        samples.extend_from_slice(&other_samples[overlap * num_channels..]);
+        self.inference_ms = [self.inference_ms, other.inference_ms].into_iter().sum();
+        self.timings += other.timings;
+        Ok(())
+    }
+
+    fn ms_to_frames(&self, duration_ms: u32) -> usize {
+        self.info.sample_rate * duration_ms as usize / 1000
+    }
+
+    fn check_same_format(&self, other: &Audio) -> Result<(), AudioFormatError> {
+        if (self.info.sample_rate, self.info.num_channels)
+            != (other.info.sample_rate, other.info.num_channels)
+        {
+            return Err(AudioFormatError(format!(
+                "Audio formats differ: {} Hz with {} channels and {} Hz with {} channels",
+                self.info.sample_rate,
+                self.info.num_channels,
+                other.info.sample_rate,
+                other.info.num_channels
+            )));
+        }
+        Ok(())
+    }
+
+    /// Save with the given sample encoding, and metadata if any
+    pub fn save_to_file_as(
+        &self,
+        filename: &Path,
+        format: crate::WaveSampleFormat,
+        metadata: Option<&crate::WaveMetadata>,
+    ) -> Result<(), crate::WaveWriterError> {
+        crate::write_float_samples_to_file(
+            filename,
+            &self.samples,
+            self.info.sample_rate as u32,
+            self.info.num_channels as u32,
+            format,
+            metadata,
+        )
+    }
+
+    pub fn save_to_file_with_metadata(
+        &self,
+        filename: &Path,
+        metadata: &crate::WaveMetadata,
+    ) -> Result<(), crate::WaveWriterError> {
+        crate::write_wave_samples_to_file_with_metadata(
+            filename,
+            self.samples.to_i16_vec().iter(),
+            self.info.sample_rate as u32,
+            self.info.num_channels as u32,
+            self.info.sample_width as u32,
+            metadata,
+        )
+    }
 }
 
 impl IntoIterator for Audio {
@@ -347,4 +648,114 @@ mod tests {
         s1.strip_silence(0..s1.len());
         assert_eq!(s1.len(), 4);
     }
+
+    #[test]
+    fn test_resample() {
+        let data = vec![0.0, 1.0, 2.0, 3.0];
+        let s1 = AudioSamples::from(data.clone());
+        let upsampled = s1.resample(16000, 32000);
+        assert_eq!(upsampled.len(), 8);
+        assert_eq!(upsampled.0[1], 0.5);
+        let downsampled = upsampled.resample(32000, 16000);
+        assert_eq!(downsampled.0, data);
+    }
+
+    #[test]
+    fn test_resample_antialiased() {
+        let sine = |frequency: f32| {
+            AudioSamples::from(Vec::from_iter(
+                (0..22050).map(|i| (2.0 * PI * frequency * i as f32 / 22050.0).sin()),
+            ))
+        };
+        let peak = |samples: AudioSamples| {
+            samples.0[100..samples.len() - 100]
+                .iter()
+                .fold(0.0f32, |p, f| p.max(f.abs()))
+        };
+        // Speech frequencies pass, those above 4 kHz are filtered out rather than
+        // folded back
+        let speech = sine(1000.0).resample_antialiased(22050, 8000);
+        assert_eq!(speech.len(), 8000);
+        assert!(peak(speech) > 0.95);
+        let hiss = sine(6000.0).resample_antialiased(22050, 8000);
+        assert!(peak(hiss) < 0.05);
+        assert!(peak(sine(6000.0).resample(22050, 8000)) > 0.5);
+    }
+
+    #[test]
+    fn test_to_stereo() {
+        let audio = Audio::new(vec![1.0, -0.5].into(), 16000, None);
+        let left = audio.to_stereo(-1.0);
+        assert_eq!(left.info.num_channels, 2);
+        assert_eq!(left.num_frames(), 2);
+        assert_eq!(left.samples.as_slice()[0], 1.0);
+        assert!(left.samples.as_slice()[1].abs() < 1e-6);
+        let center = audio.to_stereo(0.0);
+        let [l, r, ..] = center.samples.as_slice() else {
+            unreachable!()
+        };
+        assert_eq!(l, r);
+        assert!((l * l + r * r - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_concat_and_mix() {
+        let a = Audio::new(vec![1.0; 10].into(), 1000, Some(2.0));
+        let b = Audio::new(vec![0.5; 20].into(), 1000, Some(3.0));
+        let mut joined = Audio::concat(&[a.clone(), b.clone()]).unwrap();
+        assert_eq!(joined.len(), 30);
+        assert_eq!(joined.inference_ms, Some(5.0));
+        let mut timed = a.clone();
+        timed.timings.decoder_ms = 1.5;
+        let timings = Audio::concat(&[timed.clone(), timed]).unwrap().timings;
+        assert_eq!(timings.decoder_ms, 3.0);
+        assert_eq!(timings.total_ms(), 3.0);
+        assert!(Audio::concat(&[a.clone(), Audio::new(vec![0.0].into(), 2000, None)]).is_err());
+        assert!(Audio::concat(&[]).is_err());
+
+        joined.append_silence(10);
+        assert_eq!(joined.len(), 40);
+        joined.mix(&b, 35).unwrap();
+        assert_eq!(joined.len(), 55);
+        assert_eq!(joined.samples.as_slice()[..2], [1.0, 1.0]);
+        assert_eq!(joined.samples.as_slice()[36], 0.5);
+
+        let mut faded = a.clone();
+        faded.append_with_crossfade(&b, 4).unwrap();
+        assert_eq!(faded.len(), 26);
+        let samples = faded.samples.as_slice();
+        assert_eq!((samples[5], samples[10]), (1.0, 0.5));
+        assert!(samples[9] < 1.0 && samples[9] > 0.5);
+    }
+
+    #[test]
+    fn test_audio_frames() {
+        let mut audio = Audio::new(AudioSamples::from(vec![0.5; 32000]), 16000, None);
+        assert_eq!(audio.duration(), Duration::from_secs(2));
+        audio.info.num_channels = 2;
+        assert_eq!(audio.duration(), Duration::from_secs(1));
+        assert_eq!(audio.duration_ms(), 1000.0);
+        assert_eq!(audio.iter_frames().count(), 16000);
+        assert!(audio.iter_frames().all(|frame| frame == [0.5, 0.5]));
+        assert_eq!(audio.to_f32_vec().len(), 32000);
+    }
+
+    #[test]
+    fn test_trim_silence() {
+        let samples = vec![0.0, 0.001, -0.5, 0.2, 0.0, 0.3, 0.0, 0.0];
+        let mut audio = Audio::new(samples.into(), 16000, None);
+        audio.trim_leading_silence(0.01);
+        assert_eq!(audio.len(), 6);
+        audio.trim_trailing_silence(0.01);
+        assert_eq!(audio.samples.as_slice(), [-0.5, 0.2, 0.0, 0.3]);
+        // Stereo frames are only silent if both channels are
+        let samples = vec![0.0, 0.0, 0.0, 0.4, 0.1, 0.0, 0.0, 0.0];
+        let mut stereo = Audio::new(samples.into(), 16000, None);
+        stereo.info.num_channels = 2;
+        stereo.trim_leading_silence(0.01);
+        stereo.trim_trailing_silence(0.01);
+        assert_eq!(stereo.samples.as_slice(), [0.0, 0.4, 0.1, 0.0]);
+        stereo.trim_leading_silence(1.0);
+        assert!(stereo.is_empty());
+    }
 }
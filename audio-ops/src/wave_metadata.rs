@@ -0,0 +1,234 @@
+//! Descriptive metadata for WAV files, written as a RIFF `LIST/INFO` chunk and,
+//! optionally, the Broadcast Wave Format `bext` chunk expected by broadcast tools.
+
+use crate::WaveWriterError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest comment (e.g. the synthesized text) stored in the INFO chunk, in bytes
+pub const MAX_COMMENT_LEN: usize = 1024;
+
+const BEXT_DESCRIPTION_LEN: usize = 256;
+const BEXT_ORIGINATOR_LEN: usize = 32;
+const BEXT_ORIGINATOR_REFERENCE_LEN: usize = 32;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WaveMetadata {
+    /// `INAM`
+    pub title: Option<String>,
+    /// `IART`, e.g. the name of the voice
+    pub artist: Option<String>,
+    /// `ILNG`
+    pub language: Option<String>,
+    /// `ICMT`, truncated to [`MAX_COMMENT_LEN`]
+    pub comment: Option<String>,
+    /// `ISFT`
+    pub software: Option<String>,
+    /// `ICRD` and the bext origination date. Defaults to the time of writing
+    pub created: Option<SystemTime>,
+    /// Also write a `bext` chunk
+    pub broadcast_extension: bool,
+}
+
+impl WaveMetadata {
+    /// Add the metadata chunks to the bytes of a complete WAV file
+    pub fn add_to_wave_bytes(&self, wave_bytes: &mut Vec<u8>) -> Result<(), WaveWriterError> {
+        if wave_bytes.len() < 12 || &wave_bytes[0..4] != b"RIFF" || &wave_bytes[8..12] != b"WAVE" {
+            return Err(WaveWriterError("Not a RIFF/WAVE file".to_string()));
+        }
+        let (chunks_before_data, info) = self.chunks();
+        // bext should come before the audio data, so insert it right after the header
+        wave_bytes.splice(12..12, chunks_before_data);
+        wave_bytes.extend(info);
+        let riff_size = u32::try_from(wave_bytes.len() - 8)
+            .map_err(|_| WaveWriterError("Wave file too large".to_string()))?;
+        wave_bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        Ok(())
+    }
+
+    /// The chunks that go before the audio data, and those that go after it
+    pub(crate) fn chunks(&self) -> (Vec<u8>, Vec<u8>) {
+        let created = self.created.unwrap_or_else(SystemTime::now);
+        let bext = match self.broadcast_extension {
+            true => self.bext_chunk(created),
+            false => Vec::new(),
+        };
+        (bext, self.info_chunk(created))
+    }
+
+    fn info_chunk(&self, created: SystemTime) -> Vec<u8> {
+        let (date, _) = format_date_time(created);
+        let comment = self
+            .comment
+            .as_deref()
+            .map(|comment| truncate(comment, MAX_COMMENT_LEN));
+        let entries = [
+            (b"INAM", self.title.as_deref()),
+            (b"IART", self.artist.as_deref()),
+            (b"ILNG", self.language.as_deref()),
+            (b"ICMT", comment),
+            (b"ISFT", self.software.as_deref()),
+            (b"ICRD", Some(date.as_str())),
+        ];
+        let mut list = b"INFO".to_vec();
+        for (id, value) in entries {
+            let Some(value) = value.filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            // Values are NUL-terminated strings
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            push_chunk(&mut list, id, &data);
+        }
+        let mut chunk = Vec::new();
+        push_chunk(&mut chunk, b"LIST", &list);
+        chunk
+    }
+
+    fn bext_chunk(&self, created: SystemTime) -> Vec<u8> {
+        let (date, time) = format_date_time(created);
+        let description = self.title.as_deref().or(self.comment.as_deref());
+        let originator = self.artist.as_deref().or(self.software.as_deref());
+        let mut data = Vec::with_capacity(602);
+        push_fixed(
+            &mut data,
+            description.unwrap_or_default(),
+            BEXT_DESCRIPTION_LEN,
+        );
+        push_fixed(
+            &mut data,
+            originator.unwrap_or_default(),
+            BEXT_ORIGINATOR_LEN,
+        );
+        push_fixed(&mut data, "", BEXT_ORIGINATOR_REFERENCE_LEN);
+        push_fixed(&mut data, &date, 10);
+        push_fixed(&mut data, &time, 8);
+        // Time reference, in samples since midnight
+        data.extend(0u64.to_le_bytes());
+        // Version 2, with the loudness fields left unset
+        data.extend(2u16.to_le_bytes());
+        // UMID
+        data.extend([0u8; 64]);
+        // Loudness value, range, max true peak, max momentary and short-term loudness
+        data.extend([0x7fffu16; 5].iter().flat_map(|value| value.to_le_bytes()));
+        // Reserved
+        data.extend([0u8; 180]);
+        let mut chunk = Vec::new();
+        push_chunk(&mut chunk, b"bext", &data);
+        chunk
+    }
+}
+
+fn push_chunk(buf: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    buf.extend(id);
+    buf.extend((data.len() as u32).to_le_bytes());
+    buf.extend(data);
+    // Chunks are word aligned
+    if data.len() % 2 == 1 {
+        buf.push(0);
+    }
+}
+
+/// Push `value` as a NUL-padded field of `len` bytes
+fn push_fixed(buf: &mut Vec<u8>, value: &str, len: usize) {
+    let value = truncate(value, len);
+    buf.extend(value.as_bytes());
+    buf.resize(buf.len() + len - value.len(), 0);
+}
+
+/// The longest prefix of `value` of at most `max_len` bytes that ends on a char boundary
+fn truncate(value: &str, max_len: usize) -> &str {
+    let mut end = value.len().min(max_len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// The UTC date (`yyyy-mm-dd`) and time (`hh:mm:ss`) of `time`
+fn format_date_time(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // Days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:02}:{:02}:{:02}",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// The data of the first chunk `id` at or after `pos`
+    fn find_chunk<'a>(bytes: &'a [u8], mut pos: usize, id: &[u8; 4]) -> Option<&'a [u8]> {
+        while pos + 8 <= bytes.len() {
+            let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            if &bytes[pos..pos + 4] == id {
+                return Some(&bytes[pos + 8..pos + 8 + size]);
+            }
+            pos += 8 + size + size % 2;
+        }
+        None
+    }
+
+    #[test]
+    fn test_wave_metadata() {
+        let mut bytes = Vec::new();
+        crate::write_wave_samples_to_buffer(
+            std::io::Cursor::new(&mut bytes),
+            [0i16, 100, -100].iter(),
+            22050,
+            1,
+            2,
+        )
+        .unwrap();
+        let metadata = WaveMetadata {
+            artist: Some("amy".to_string()),
+            language: Some("en-us".to_string()),
+            comment: Some("é".repeat(MAX_COMMENT_LEN)),
+            // 2024-02-29 13:05:09 UTC
+            created: Some(UNIX_EPOCH + Duration::from_secs(1709211909)),
+            broadcast_extension: true,
+            ..Default::default()
+        };
+        metadata.add_to_wave_bytes(&mut bytes).unwrap();
+
+        let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size, bytes.len() - 8);
+        assert_eq!(find_chunk(&bytes, 12, b"data").unwrap().len(), 6);
+        let bext = find_chunk(&bytes, 12, b"bext").unwrap();
+        assert_eq!(bext.len(), 602);
+        assert_eq!(&bext[256..259], b"amy");
+        assert_eq!(&bext[320..338], b"2024-02-2913:05:09");
+
+        let info = find_chunk(&bytes, 12, b"LIST").unwrap();
+        assert_eq!(&info[0..4], b"INFO");
+        assert_eq!(find_chunk(info, 4, b"IART").unwrap(), b"amy\0");
+        assert_eq!(find_chunk(info, 4, b"ICRD").unwrap(), b"2024-02-29\0");
+        assert!(find_chunk(info, 4, b"INAM").is_none());
+        // Truncated on a char boundary, plus the NUL
+        assert_eq!(
+            find_chunk(info, 4, b"ICMT").unwrap().len(),
+            MAX_COMMENT_LEN + 1
+        );
+    }
+}
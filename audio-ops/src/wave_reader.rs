@@ -0,0 +1,190 @@
+use crate::{alaw_to_linear, mulaw_to_linear, Audio, AudioSamples};
+use std::fmt;
+use std::path::Path;
+
+/// `WAVE_FORMAT_PCM`
+const FORMAT_PCM: u16 = 1;
+/// `WAVE_FORMAT_IEEE_FLOAT`
+const FORMAT_IEEE_FLOAT: u16 = 3;
+/// `WAVE_FORMAT_ALAW`
+const FORMAT_ALAW: u16 = 6;
+/// `WAVE_FORMAT_MULAW`
+const FORMAT_MULAW: u16 = 7;
+/// `WAVE_FORMAT_EXTENSIBLE`, whose actual format is in the sub-format GUID
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+#[derive(Debug)]
+pub struct WaveReaderError(pub(crate) String);
+
+impl std::error::Error for WaveReaderError {}
+
+impl fmt::Display for WaveReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Read a wave file of integer PCM (8 to 32 bits), 32-bit float or G.711 samples
+pub fn read_wave_file(filename: &Path) -> Result<Audio, WaveReaderError> {
+    let bytes = std::fs::read(filename).map_err(|e| {
+        WaveReaderError(format!(
+            "Failed to read wave file `{}`. Error: {}",
+            filename.display(),
+            e
+        ))
+    })?;
+    read_wave_bytes(&bytes)
+}
+
+/// Like [`read_wave_file`], for the bytes of a wave file
+pub fn read_wave_bytes(bytes: &[u8]) -> Result<Audio, WaveReaderError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(WaveReaderError("Not a wave file".to_string()));
+    }
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let chunk_id = &bytes[position..position + 4];
+        let chunk_len = u32_at(bytes, position + 4) as usize;
+        let chunk_start = position + 8;
+        // Files written while streaming may leave the length of the data chunk unset
+        let chunk_end = chunk_start.saturating_add(chunk_len).min(bytes.len());
+        let chunk = &bytes[chunk_start..chunk_end];
+        match chunk_id {
+            b"fmt " => format = Some(WaveFormat::parse(chunk)?),
+            b"data" => {
+                let Some(format) = format else {
+                    return Err(WaveReaderError(
+                        "Invalid wave file: the data chunk comes before the format".to_string(),
+                    ));
+                };
+                let mut audio = Audio::new(format.decode(chunk), format.sample_rate, None);
+                audio.info.num_channels = format.num_channels;
+                audio.info.sample_width = format.bits_per_sample / 8;
+                return Ok(audio);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        position = chunk_end + (chunk_len & 1);
+    }
+    Err(WaveReaderError(
+        "Invalid wave file: it has no data chunk".to_string(),
+    ))
+}
+
+#[derive(Clone, Copy)]
+struct WaveFormat {
+    format_tag: u16,
+    num_channels: usize,
+    sample_rate: usize,
+    bits_per_sample: usize,
+}
+
+impl WaveFormat {
+    fn parse(chunk: &[u8]) -> Result<Self, WaveReaderError> {
+        if chunk.len() < 16 {
+            return Err(WaveReaderError(
+                "Invalid wave file: the format chunk is too short".to_string(),
+            ));
+        }
+        let mut format_tag = u16_at(chunk, 0);
+        if format_tag == FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+            format_tag = u16_at(chunk, 24);
+        }
+        let format = Self {
+            format_tag,
+            num_channels: u16_at(chunk, 2) as usize,
+            sample_rate: u32_at(chunk, 4) as usize,
+            bits_per_sample: u16_at(chunk, 14) as usize,
+        };
+        let is_supported = match format_tag {
+            FORMAT_PCM => matches!(format.bits_per_sample, 8 | 16 | 24 | 32),
+            FORMAT_IEEE_FLOAT => format.bits_per_sample == 32,
+            FORMAT_ALAW | FORMAT_MULAW => format.bits_per_sample == 8,
+            _ => false,
+        };
+        if !is_supported || format.num_channels == 0 {
+            return Err(WaveReaderError(format!(
+                "Unsupported wave format {} with {}-bit samples",
+                format_tag, format.bits_per_sample
+            )));
+        }
+        Ok(format)
+    }
+    /// Samples of `data` between `-1.0` and `1.0`
+    fn decode(&self, data: &[u8]) -> AudioSamples {
+        let sample_width = self.bits_per_sample / 8;
+        let samples = data.chunks_exact(sample_width).map(|sample| {
+            match self.format_tag {
+                FORMAT_IEEE_FLOAT => return f32::from_le_bytes(sample.try_into().unwrap()),
+                FORMAT_ALAW => return alaw_to_linear(sample[0]) as f32 / 32768.0,
+                FORMAT_MULAW => return mulaw_to_linear(sample[0]) as f32 / 32768.0,
+                _ => {}
+            }
+            if sample_width == 1 {
+                // 8-bit samples are unsigned
+                return (sample[0] as f32 - 128.0) / 128.0;
+            }
+            // Put the sample in the high bytes of an i32, keeping its sign
+            let mut bytes = [0u8; 4];
+            bytes[4 - sample_width..].copy_from_slice(sample);
+            i32::from_le_bytes(bytes) as f32 / -(i32::MIN as f32)
+        });
+        AudioSamples::new(samples.collect())
+    }
+}
+
+fn u16_at(bytes: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes([bytes[position], bytes[position + 1]])
+}
+
+fn u32_at(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_float_samples_to_buffer, WaveSampleFormat};
+
+    #[test]
+    fn test_read_wave_bytes() {
+        let samples = AudioSamples::from(vec![0.0, 0.5, -1.0, 1.0]);
+        for format in [
+            WaveSampleFormat::S16,
+            WaveSampleFormat::S24,
+            WaveSampleFormat::F32,
+            WaveSampleFormat::MuLaw,
+            WaveSampleFormat::ALaw,
+        ] {
+            let mut bytes = Vec::new();
+            write_float_samples_to_buffer(
+                std::io::Cursor::new(&mut bytes),
+                &samples,
+                16000,
+                2,
+                format,
+            )
+            .unwrap();
+            let audio = read_wave_bytes(&bytes).unwrap();
+            assert_eq!(audio.info.sample_rate, 16000);
+            assert_eq!(audio.info.num_channels, 2);
+            assert_eq!(audio.info.sample_width, format.sample_width() as usize);
+            // Companding keeps less of the resolution of loud samples
+            let tolerance = if format.is_g711() { 0.02 } else { 1e-3 };
+            for (read, written) in audio.samples.as_slice().iter().zip(samples.as_slice()) {
+                assert!(
+                    (read - written).abs() < tolerance,
+                    "{:?}: {} != {}",
+                    format,
+                    read,
+                    written
+                );
+            }
+            assert_eq!(audio.len(), samples.len(), "{:?}", format);
+        }
+        assert!(read_wave_bytes(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(read_wave_bytes(b"not a wave file").is_err());
+    }
+}
@@ -1,11 +1,16 @@
+use crate::g711::{linear_to_alaw, linear_to_mulaw};
+use crate::samples::{MAX_WAV_VALUE_I16, MAX_WAV_VALUE_I24};
+use crate::{AudioSamples, WaveMetadata};
 use riff_wave::WaveWriter;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug)]
-pub struct WaveWriterError(String);
+pub struct WaveWriterError(pub(crate) String);
 
 impl std::error::Error for WaveWriterError {}
 
@@ -15,6 +20,76 @@ impl fmt::Display for WaveWriterError {
     }
 }
 
+/// Sample encoding of written wave files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WaveSampleFormat {
+    /// 16-bit integer PCM
+    #[default]
+    S16,
+    /// 24-bit integer PCM
+    S24,
+    /// 32-bit IEEE float
+    F32,
+    /// 8-bit G.711 µ-law, for telephony
+    MuLaw,
+    /// 8-bit G.711 A-law, for telephony
+    ALaw,
+}
+
+impl WaveSampleFormat {
+    /// Bytes per sample
+    pub fn sample_width(&self) -> u32 {
+        match self {
+            Self::S16 => 2,
+            Self::S24 => 3,
+            Self::F32 => 4,
+            Self::MuLaw | Self::ALaw => 1,
+        }
+    }
+    /// Whether this is one of the G.711 telephony encodings, which are meant to be
+    /// written at [`crate::TELEPHONY_SAMPLE_RATE`]
+    pub fn is_g711(&self) -> bool {
+        matches!(self, Self::MuLaw | Self::ALaw)
+    }
+    /// The format tag of the format chunk of wave files
+    fn format_tag(&self) -> u16 {
+        match self {
+            // WAVE_FORMAT_PCM
+            Self::S16 | Self::S24 => 1,
+            // WAVE_FORMAT_IEEE_FLOAT
+            Self::F32 => 3,
+            // WAVE_FORMAT_ALAW and WAVE_FORMAT_MULAW
+            Self::ALaw => 6,
+            Self::MuLaw => 7,
+        }
+    }
+    /// Encode a 16-bit sample in a format of 8-bit samples
+    fn encode_i16(&self, sample: i16) -> u8 {
+        match self {
+            Self::ALaw => linear_to_alaw(sample),
+            _ => linear_to_mulaw(sample),
+        }
+    }
+}
+
+impl FromStr for WaveSampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s16" => Ok(Self::S16),
+            "s24" => Ok(Self::S24),
+            "f32" => Ok(Self::F32),
+            "mulaw" | "ulaw" => Ok(Self::MuLaw),
+            "alaw" => Ok(Self::ALaw),
+            _ => Err(format!(
+                "Unknown sample format `{}`. Expected one of `s16`, `s24`, `f32`, `mulaw` or `alaw`",
+                s
+            )),
+        }
+    }
+}
+
 pub fn write_wave_samples_to_buffer<'a, I, B>(
     buf: B,
     samples: I,
@@ -66,8 +141,263 @@ where
         num_channels,
         sample_width,
     )?;
+    write_bytes_to_file(filename, &out)
+}
+
+/// Like [`write_wave_samples_to_file`], with INFO (and optionally bext) metadata chunks
+pub fn write_wave_samples_to_file_with_metadata<'a, I>(
+    filename: &Path,
+    samples: I,
+    sample_rate: u32,
+    num_channels: u32,
+    sample_width: u32,
+    metadata: &WaveMetadata,
+) -> Result<(), WaveWriterError>
+where
+    I: Iterator<Item = &'a i16>,
+{
+    let mut out: Vec<u8> = Vec::new();
+    write_wave_samples_to_buffer(
+        std::io::Cursor::new(&mut out),
+        samples,
+        sample_rate,
+        num_channels,
+        sample_width,
+    )?;
+    metadata.add_to_wave_bytes(&mut out)?;
+    write_bytes_to_file(filename, &out)
+}
+
+/// Encode float samples in `format`, which keeps the resolution of the model output
+/// when writing 24-bit or float files
+pub fn write_float_samples_to_buffer<B>(
+    mut buf: B,
+    samples: &AudioSamples,
+    sample_rate: u32,
+    num_channels: u32,
+    format: WaveSampleFormat,
+) -> Result<(), WaveWriterError>
+where
+    B: Seek + Write,
+{
+    match format {
+        WaveSampleFormat::S16 => write_wave_samples_to_buffer(
+            buf,
+            samples.to_i16_vec().iter(),
+            sample_rate,
+            num_channels,
+            format.sample_width(),
+        ),
+        WaveSampleFormat::S24 => {
+            let Ok(mut wave_writer) = WaveWriter::new(num_channels as u16, sample_rate, 24, buf)
+            else {
+                return Err(WaveWriterError(
+                    "Failed to initialize wave writer".to_string(),
+                ));
+            };
+            let any_fail = samples
+                .to_i24_vec()
+                .into_iter()
+                .map(|i| wave_writer.write_sample_i24(i))
+                .any(|r| r.is_err());
+            if any_fail {
+                return Err(WaveWriterError("Failed to write wave samples".to_string()));
+            }
+            if wave_writer.sync_header().is_err() {
+                return Err(WaveWriterError("Failed to update wave header".to_string()));
+            }
+            Ok(())
+        }
+        WaveSampleFormat::F32 => {
+            // riff-wave only writes integer PCM, so write the float header here
+            let samples = samples.to_full_scale_f32_vec();
+            let mut header = wave_header(format, sample_rate, num_channels, samples.len(), 0);
+            header.extend(samples.iter().flat_map(|f| f.to_le_bytes()));
+            buf.write_all(&header)
+                .map_err(|e| WaveWriterError(format!("Failed to write wave samples. Error: {}", e)))
+        }
+        WaveSampleFormat::MuLaw | WaveSampleFormat::ALaw => {
+            let samples = samples.to_i16_vec();
+            let mut header = wave_header(format, sample_rate, num_channels, samples.len(), 0);
+            header.extend(samples.into_iter().map(|i| format.encode_i16(i)));
+            buf.write_all(&header)
+                .map_err(|e| WaveWriterError(format!("Failed to write wave samples. Error: {}", e)))
+        }
+    }
+}
+
+/// Writes a wave file part by part, for audio too long to be held in memory.
+///
+/// The number of samples must be known in advance. Samples are written as given, so
+/// scale them to full scale first to match the other writers.
+pub struct WaveFileWriter {
+    out: BufWriter<File>,
+    format: WaveSampleFormat,
+    remaining_samples: usize,
+    chunks_after_data: Vec<u8>,
+}
+
+impl WaveFileWriter {
+    pub fn create(
+        filename: &Path,
+        num_samples: usize,
+        sample_rate: u32,
+        num_channels: u32,
+        format: WaveSampleFormat,
+        metadata: Option<&WaveMetadata>,
+    ) -> Result<Self, WaveWriterError> {
+        let (chunks_before_data, chunks_after_data) =
+            metadata.map(WaveMetadata::chunks).unwrap_or_default();
+        let mut header = wave_header(
+            format,
+            sample_rate,
+            num_channels,
+            num_samples,
+            chunks_before_data.len() + chunks_after_data.len(),
+        );
+        header.splice(12..12, chunks_before_data);
+        let file = File::create(filename).map_err(|e| {
+            WaveWriterError(format!(
+                "Failed to create file `{}` for writing. Error: {}",
+                filename.display(),
+                e
+            ))
+        })?;
+        let mut writer = Self {
+            out: BufWriter::new(file),
+            format,
+            remaining_samples: num_samples,
+            chunks_after_data,
+        };
+        writer.write_bytes(&header)?;
+        Ok(writer)
+    }
+    /// Append `samples`, which are between `-1.0` and `1.0`
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), WaveWriterError> {
+        if samples.len() > self.remaining_samples {
+            return Err(WaveWriterError(
+                "More samples were written than announced".to_string(),
+            ));
+        }
+        self.remaining_samples -= samples.len();
+        let mut bytes = Vec::with_capacity(samples.len() * self.format.sample_width() as usize);
+        let to_i16 = |sample: f32| {
+            (sample * MAX_WAV_VALUE_I16).clamp(i16::MIN as f32, MAX_WAV_VALUE_I16) as i16
+        };
+        for sample in samples {
+            match self.format {
+                WaveSampleFormat::S16 => bytes.extend(to_i16(*sample).to_le_bytes()),
+                WaveSampleFormat::S24 => bytes.extend(
+                    &((sample * MAX_WAV_VALUE_I24)
+                        .clamp(-MAX_WAV_VALUE_I24 - 1.0, MAX_WAV_VALUE_I24)
+                        as i32)
+                        .to_le_bytes()[..3],
+                ),
+                WaveSampleFormat::F32 => bytes.extend(sample.to_le_bytes()),
+                WaveSampleFormat::MuLaw | WaveSampleFormat::ALaw => {
+                    bytes.push(self.format.encode_i16(to_i16(*sample)))
+                }
+            }
+        }
+        self.write_bytes(&bytes)
+    }
+    /// Write the metadata and flush the file
+    pub fn finish(mut self) -> Result<(), WaveWriterError> {
+        if self.remaining_samples != 0 {
+            return Err(WaveWriterError(format!(
+                "{} announced samples were not written",
+                self.remaining_samples
+            )));
+        }
+        let chunks_after_data = std::mem::take(&mut self.chunks_after_data);
+        self.write_bytes(&chunks_after_data)?;
+        self.out
+            .flush()
+            .map_err(|e| WaveWriterError(format!("Failed to write wave file. Error: {}", e)))
+    }
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), WaveWriterError> {
+        self.out
+            .write_all(bytes)
+            .map_err(|e| WaveWriterError(format!("Failed to write wave file. Error: {}", e)))
+    }
+}
+
+/// The header of a wave file of `num_samples` samples up to the start of the data,
+/// followed by `extra_chunks_len` bytes of other chunks
+fn wave_header(
+    format: WaveSampleFormat,
+    sample_rate: u32,
+    num_channels: u32,
+    num_samples: usize,
+    extra_chunks_len: usize,
+) -> Vec<u8> {
+    let is_pcm = format.format_tag() == 1;
+    let data_len = (num_samples * format.sample_width() as usize) as u32;
+    let block_align = num_channels * format.sample_width();
+    // Non-PCM formats have a longer format chunk, and a fact chunk
+    let header_len = if is_pcm { 44 } else { 58 };
+    let mut header = Vec::with_capacity(header_len);
+    header.extend(b"RIFF");
+    header.extend((header_len as u32 - 8 + data_len + extra_chunks_len as u32).to_le_bytes());
+    header.extend(b"WAVEfmt ");
+    header.extend(if is_pcm { 16u32 } else { 18u32 }.to_le_bytes());
+    header.extend(format.format_tag().to_le_bytes());
+    header.extend((num_channels as u16).to_le_bytes());
+    header.extend(sample_rate.to_le_bytes());
+    header.extend((sample_rate * block_align).to_le_bytes());
+    header.extend((block_align as u16).to_le_bytes());
+    header.extend((format.sample_width() as u16 * 8).to_le_bytes());
+    if !is_pcm {
+        header.extend(0u16.to_le_bytes());
+        header.extend(b"fact");
+        header.extend(4u32.to_le_bytes());
+        header.extend((num_samples as u32 / num_channels.max(1)).to_le_bytes());
+    }
+    header.extend(b"data");
+    header.extend(data_len.to_le_bytes());
+    header
+}
+
+/// The header of a wave stream of unknown length, e.g. one written to a pipe. Its
+/// lengths are left at their maximum, which readers take as "until the end of the file"
+pub fn streaming_wave_header(
+    format: WaveSampleFormat,
+    sample_rate: u32,
+    num_channels: u32,
+) -> Vec<u8> {
+    let mut header = wave_header(format, sample_rate, num_channels, 0, 0);
+    let data_len_position = header.len() - 4;
+    header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    header[data_len_position..].copy_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+/// Write float samples in `format`, with metadata chunks if given
+pub fn write_float_samples_to_file(
+    filename: &Path,
+    samples: &AudioSamples,
+    sample_rate: u32,
+    num_channels: u32,
+    format: WaveSampleFormat,
+    metadata: Option<&WaveMetadata>,
+) -> Result<(), WaveWriterError> {
+    let mut out: Vec<u8> = Vec::new();
+    write_float_samples_to_buffer(
+        std::io::Cursor::new(&mut out),
+        samples,
+        sample_rate,
+        num_channels,
+        format,
+    )?;
+    if let Some(metadata) = metadata {
+        metadata.add_to_wave_bytes(&mut out)?;
+    }
+    write_bytes_to_file(filename, &out)
+}
+
+fn write_bytes_to_file(filename: &Path, bytes: &[u8]) -> Result<(), WaveWriterError> {
     match File::create(filename) {
-        Ok(mut file) => match file.write(out.as_slice()) {
+        Ok(mut file) => match file.write(bytes) {
             Ok(_) => Ok(()),
             Err(e) => {
                 std::fs::remove_file(filename).ok();
@@ -85,3 +415,99 @@ where
         ))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(samples: &AudioSamples, format: WaveSampleFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_float_samples_to_buffer(std::io::Cursor::new(&mut bytes), samples, 16000, 1, format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_sample_formats() {
+        let samples = AudioSamples::from(vec![0.0, 0.25, -0.5]);
+        let u16_at = |bytes: &[u8], pos: usize| u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+
+        let s16 = encode(&samples, WaveSampleFormat::S16);
+        assert_eq!((u16_at(&s16, 20), u16_at(&s16, 34)), (1, 16));
+        assert_eq!(s16.len(), 44 + 6);
+
+        let s24 = encode(&samples, WaveSampleFormat::S24);
+        assert_eq!((u16_at(&s24, 20), u16_at(&s24, 34)), (1, 24));
+        assert_eq!(s24.len(), 44 + 9);
+        // The peak is at full scale
+        assert_eq!(&s24[50..53], &(-8388607i32).to_le_bytes()[..3]);
+
+        let f32 = encode(&samples, WaveSampleFormat::F32);
+        assert_eq!((u16_at(&f32, 20), u16_at(&f32, 34)), (3, 32));
+        assert_eq!(&f32[50..54], b"data");
+        let data = Vec::from_iter(
+            f32[58..]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap())),
+        );
+        assert_eq!(data, [0.0, 0.5, -1.0]);
+        let riff_size = u32::from_le_bytes(f32[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size, f32.len() - 8);
+
+        let mulaw = encode(&samples, WaveSampleFormat::MuLaw);
+        assert_eq!((u16_at(&mulaw, 20), u16_at(&mulaw, 34)), (7, 8));
+        assert_eq!(&mulaw[50..54], b"data");
+        assert_eq!(&mulaw[58..], [0xff, 0x8f, 0x00]);
+        let alaw = encode(&samples, WaveSampleFormat::ALaw);
+        assert_eq!((u16_at(&alaw, 20), u16_at(&alaw, 34)), (6, 8));
+        assert_eq!(&alaw[58..], [0xd5, 0xba, 0x2a]);
+
+        assert_eq!("F32".parse(), Ok(WaveSampleFormat::F32));
+        assert_eq!("ulaw".parse(), Ok(WaveSampleFormat::MuLaw));
+        assert!("u8".parse::<WaveSampleFormat>().is_err());
+    }
+
+    #[test]
+    fn test_streaming_wave_header() {
+        let mut bytes = streaming_wave_header(WaveSampleFormat::S16, 16000, 1);
+        assert_eq!(bytes.len(), 44);
+        assert_eq!(&bytes[40..44], &u32::MAX.to_le_bytes());
+        bytes.extend(AudioSamples::from(vec![0.5, -1.0]).as_wave_bytes());
+        let audio = crate::read_wave_bytes(&bytes).unwrap();
+        assert_eq!(audio.info.sample_rate, 16000);
+        assert_eq!(audio.len(), 2);
+    }
+
+    #[test]
+    fn test_wave_file_writer() {
+        let samples = AudioSamples::from(vec![0.0, 0.25, -0.5, 0.5]);
+        let metadata = WaveMetadata {
+            title: Some("title".to_string()),
+            created: Some(std::time::UNIX_EPOCH),
+            broadcast_extension: true,
+            ..Default::default()
+        };
+        let filename = std::env::temp_dir().join(format!("sonata-wave-{}.wav", std::process::id()));
+        for format in [
+            WaveSampleFormat::S16,
+            WaveSampleFormat::S24,
+            WaveSampleFormat::F32,
+            WaveSampleFormat::MuLaw,
+            WaveSampleFormat::ALaw,
+        ] {
+            let full_scale = samples.to_full_scale_f32_vec();
+            let mut writer =
+                WaveFileWriter::create(&filename, 4, 16000, 1, format, Some(&metadata)).unwrap();
+            writer.write_samples(&full_scale[..1]).unwrap();
+            writer.write_samples(&full_scale[1..]).unwrap();
+            writer.finish().unwrap();
+            let mut expected = encode(&samples, format);
+            metadata.add_to_wave_bytes(&mut expected).unwrap();
+            assert_eq!(std::fs::read(&filename).unwrap(), expected, "{:?}", format);
+        }
+        let mut writer =
+            WaveFileWriter::create(&filename, 1, 16000, 1, WaveSampleFormat::S16, None).unwrap();
+        assert!(writer.write_samples(&[0.0, 0.0]).is_err());
+        std::fs::remove_file(&filename).unwrap();
+    }
+}
@@ -1,11 +1,42 @@
-use cmake;
+use std::env;
+
+/// Directory of a prebuilt espeak-ng library to link instead of building `deps/espeak-ng`
+const LIB_DIR_ENV_VAR: &str = "SONATA_ESPEAKNG_LIB_DIR";
+/// How to link espeak-ng: `static` (the default) or `dylib`
+const LINK_ENV_VAR: &str = "SONATA_ESPEAKNG_LINK";
 
 fn main() {
     println!("cargo:rerun-if-changed=../deps/espeak-ng/src");
-    println!("cargo:rustc-link-lib=static=espeak-ng");
-    println!("cargo:rustc-link-lib=static=ucd");
-    println!("cargo:rustc-link-lib=static=ucd");
+    println!("cargo:rerun-if-env-changed={}", LIB_DIR_ENV_VAR);
+    println!("cargo:rerun-if-env-changed={}", LINK_ENV_VAR);
+
+    let link_kind = match env::var(LINK_ENV_VAR).as_deref() {
+        Ok("static") | Err(_) => "static",
+        Ok("dylib") => "dylib",
+        Ok(other) => panic!(
+            "Invalid `{}`: `{}`. Expected `static` or `dylib`",
+            LINK_ENV_VAR, other
+        ),
+    };
+    let lib_dir = match env::var(LIB_DIR_ENV_VAR) {
+        Ok(lib_dir) => lib_dir,
+        Err(_) if link_kind == "static" => build_vendored(),
+        Err(_) => panic!(
+            "Linking espeak-ng dynamically requires `{}` to point to the library",
+            LIB_DIR_ENV_VAR
+        ),
+    };
+    println!(r"cargo:rustc-link-search=native={}", lib_dir);
+    println!("cargo:rustc-link-lib={}=espeak-ng", link_kind);
+    if link_kind == "static" {
+        // The unicode database espeak-ng is linked against
+        println!("cargo:rustc-link-lib=static=ucd");
+    }
+}
 
+/// Build the bundled espeak-ng source as a static library, and return the directory
+/// holding it. The `cmake` crate picks the C compiler of the target (`CC_<target>`)
+fn build_vendored() -> String {
     let build_dir = cmake::Config::new("../deps/espeak-ng")
         .configure_arg("-DUSE_ASYNC:BOOL=OFF")
         .configure_arg("-DUSE_MBROLA:BOOL=OFF")
@@ -15,9 +46,5 @@ fn main() {
         .configure_arg("-DUSE_SPEECHPLAYER:BOOL=OFF")
         .configure_arg("-DBUILD_SHARED_LIBS:BOOL=OFF")
         .build();
-
-    println!(
-        r"cargo:rustc-link-search={}",
-        build_dir.join("lib").display()
-    );
+    build_dir.join("lib").display().to_string()
 }
@@ -6,7 +6,7 @@
 /* hand crafted to work around some errors  */
 
 pub type espeak_ERROR = ::std::os::raw::c_int;
-pub const espeak_ERROR_EE_OK: espeak_ERROR = 0;
+// pub const espeak_ERROR_EE_OK: espeak_ERROR = 0;
 // pub const espeak_ERROR_EE_INTERNAL_ERROR: espeak_ERROR = -1;
 // pub const espeak_ERROR_EE_BUFFER_FULL: espeak_ERROR = 1;
 // pub const espeak_ERROR_EE_NOT_FOUND: espeak_ERROR = 2;
@@ -17,14 +17,30 @@ pub const espeak_AUDIO_OUTPUT_AUDIO_OUTPUT_RETRIEVAL: espeak_AUDIO_OUTPUT = 1;
 // pub const espeak_AUDIO_OUTPUT_AUDIO_OUTPUT_SYNCHRONOUS: espeak_AUDIO_OUTPUT = 2;
 // pub const espeak_AUDIO_OUTPUT_AUDIO_OUTPUT_SYNCH_PLAYBACK: espeak_AUDIO_OUTPUT = 3;
 
+pub type espeak_ng_STATUS = ::std::os::raw::c_uint;
+pub const espeak_ng_STATUS_ENS_OK: espeak_ng_STATUS = 0;
+
 pub const espeakINITIALIZE_DONT_EXIT: u32 = 32768;
 pub const espeakINITIALIZE_PHONEME_IPA: u32 = 2;
 pub const espeakCHARS_UTF8: u32 = 1;
 
+#[allow(dead_code)]
 extern "C" {
     pub fn espeak_SetVoiceByName(name: *const ::std::os::raw::c_char) -> espeak_ERROR;
 }
 
+extern "C" {
+    pub fn espeak_ng_SetVoiceByName(name: *const ::std::os::raw::c_char) -> espeak_ng_STATUS;
+}
+
+extern "C" {
+    pub fn espeak_ng_GetStatusCodeMessage(
+        status: espeak_ng_STATUS,
+        buffer: *mut ::std::os::raw::c_char,
+        length: usize,
+    );
+}
+
 extern "C" {
     pub fn espeak_Initialize(
         output: espeak_AUDIO_OUTPUT,
@@ -51,3 +67,7 @@ extern "C" {
         terminator: *mut ::std::os::raw::c_int,
     ) -> *const ::std::os::raw::c_char;
 }
+
+extern "C" {
+    pub fn espeak_Terminate() -> espeak_ERROR;
+}
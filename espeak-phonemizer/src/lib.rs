@@ -1,13 +1,17 @@
 mod espeakng;
+mod warnings;
 
-use ffi_support::{rust_string_to_c, FfiStr};
+use ffi_support::FfiStr;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::borrow::Cow;
 use std::env;
 use std::error::Error;
-use std::ffi;
+use std::ffi::{self, CString};
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use warnings::{status_message, voice_warnings};
 
 pub type ESpeakResult<T> = Result<T, ESpeakError>;
 
@@ -31,18 +35,38 @@ impl fmt::Display for ESpeakError {
     }
 }
 
+/// espeak-ng keeps its state in globals, so it phonemizes one text at a time
+static ESPEAK_LOCK: Mutex<()> = Mutex::new(());
+/// espeak-ng reads words into fixed-size buffers, so longer runs of letters are split
+const MAX_WORD_BYTES: usize = 100;
 static LANG_SWITCH_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\([^)]*\)").unwrap());
 static STRESS_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"[ˈˌ]").unwrap());
-static ESPEAKNG_INIT: Lazy<ESpeakResult<()>> = Lazy::new(|| {
+/// The result of initializing espeak-ng, `None` before it is initialized and after it
+/// is terminated
+static ESPEAKNG_INIT: Mutex<Option<ESpeakResult<()>>> = Mutex::new(None);
+
+/// Initialize espeak-ng, unless it already is. A failure is kept, and returned again
+fn ensure_initialized() -> ESpeakResult<()> {
+    let mut init = ESPEAKNG_INIT.lock().unwrap_or_else(PoisonError::into_inner);
+    init.get_or_insert_with(initialize).clone()
+}
+
+/// The directory of `espeak-ng-data`, if it is found where it is looked up rather than
+/// left to espeak-ng
+fn espeak_data_dir() -> Option<PathBuf> {
     let data_dir = match env::var(SONATA_ESPEAKNG_DATA_DIRECTORY) {
         Ok(directory) => PathBuf::from(directory),
-        Err(_) => env::current_exe().unwrap().parent().unwrap().to_path_buf(),
-    };
-    let es_data_path_ptr = if data_dir.join("espeak-ng-data").exists() {
-        rust_string_to_c(data_dir.display().to_string())
-    } else {
-        std::ptr::null()
+        Err(_) => env::current_exe().ok()?.parent()?.to_path_buf(),
     };
+    data_dir.join("espeak-ng-data").exists().then_some(data_dir)
+}
+
+fn initialize() -> ESpeakResult<()> {
+    let es_data_path =
+        espeak_data_dir().and_then(|data_dir| CString::new(data_dir.display().to_string()).ok());
+    let es_data_path_ptr = es_data_path
+        .as_ref()
+        .map_or(std::ptr::null(), |path| path.as_ptr());
     unsafe {
         let es_sample_rate = espeakng::espeak_Initialize(
             espeakng::espeak_AUDIO_OUTPUT_AUDIO_OUTPUT_RETRIEVAL,
@@ -60,7 +84,28 @@ static ESPEAKNG_INIT: Lazy<ESpeakResult<()>> = Lazy::new(|| {
             Ok(())
         }
     }
-});
+}
+
+/// Release the voices, dictionaries and buffers of espeak-ng, after the text being
+/// phonemized is done. Phonemizing text afterwards initializes espeak-ng again
+pub fn terminate() {
+    let _guard = ESPEAK_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut init = ESPEAKNG_INIT.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(Ok(())) = init.take() {
+        unsafe {
+            espeakng::espeak_Terminate();
+        }
+    }
+}
+
+/// Phonemes of a text, and the problems espeak-ng had phonemizing it
+#[derive(Debug, Clone, Default)]
+pub struct Phonemization {
+    /// One entry per sentence
+    pub sentences: Vec<String>,
+    /// e.g. a missing dictionary, which makes the language sound wrong
+    pub warnings: Vec<String>,
+}
 
 pub fn text_to_phonemes(
     text: &str,
@@ -69,17 +114,57 @@ pub fn text_to_phonemes(
     remove_lang_switch_flags: bool,
     remove_stress: bool,
 ) -> ESpeakResult<Vec<String>> {
-    let mut phonemes = Vec::new();
-    for line in text.lines() {
-        phonemes.append(&mut _text_to_phonemes(
-            line,
-            language,
-            phoneme_separator,
-            remove_lang_switch_flags,
-            remove_stress,
-        )?)
+    let phonemization = text_to_phonemes_with_warnings(
+        text,
+        language,
+        phoneme_separator,
+        remove_lang_switch_flags,
+        remove_stress,
+    )?;
+    Ok(phonemization.sentences)
+}
+
+/// Like [`text_to_phonemes`], also returning the problems with the files of the voice,
+/// e.g. a missing dictionary. espeak-ng's own output to `stderr` is not captured: the
+/// warnings are guessed from the data directory, and logged with the `espeak-ng` target
+/// the first time a voice is used
+pub fn text_to_phonemes_with_warnings(
+    text: &str,
+    language: &str,
+    phoneme_separator: Option<char>,
+    remove_lang_switch_flags: bool,
+    remove_stress: bool,
+) -> ESpeakResult<Phonemization> {
+    let _guard = ESPEAK_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = (|| -> ESpeakResult<Vec<String>> {
+        let mut phonemes = Vec::new();
+        for line in text.lines() {
+            phonemes.append(&mut _text_to_phonemes(
+                line,
+                language,
+                phoneme_separator,
+                remove_lang_switch_flags,
+                remove_stress,
+            )?)
+        }
+        Ok(phonemes)
+    })();
+    let warnings = espeak_data_dir()
+        .map(|data_dir| voice_warnings(&data_dir, language))
+        .unwrap_or_default();
+    match result {
+        Ok(sentences) => Ok(Phonemization {
+            sentences,
+            warnings,
+        }),
+        // The warnings tell why it failed, e.g. which file is missing
+        Err(ESpeakError(message)) if !warnings.is_empty() => Err(ESpeakError(format!(
+            "{} ({})",
+            message,
+            warnings.join("; ")
+        ))),
+        Err(e) => Err(e),
     }
-    Ok(phonemes)
 }
 
 pub fn _text_to_phonemes(
@@ -89,15 +174,21 @@ pub fn _text_to_phonemes(
     remove_lang_switch_flags: bool,
     remove_stress: bool,
 ) -> ESpeakResult<Vec<String>> {
-    if let Err(ref e) = Lazy::force(&ESPEAKNG_INIT) {
-        return Err(e.clone());
-    }
-    let set_voice_res = unsafe { espeakng::espeak_SetVoiceByName(rust_string_to_c(language)) };
-    if set_voice_res != espeakng::espeak_ERROR_EE_OK {
+    ensure_initialized()?;
+    let Ok(language_c_string) = CString::new(language) else {
         return Err(ESpeakError(format!(
-            "Failed to set eSpeak-ng voice to: `{}` ",
+            "Invalid eSpeak-ng voice: {:?}",
             language
         )));
+    };
+    let text = ffi_safe_text(text);
+    let status = unsafe { espeakng::espeak_ng_SetVoiceByName(language_c_string.as_ptr()) };
+    if status != espeakng::espeak_ng_STATUS_ENS_OK {
+        return Err(ESpeakError(format!(
+            "Failed to set eSpeak-ng voice to: `{}`: {}",
+            language,
+            status_message(status)
+        )));
     }
     let calculated_phoneme_mode = match phoneme_separator {
         Some(c) => ((c as u32) << 8u32) | espeakng::espeakINITIALIZE_PHONEME_IPA,
@@ -106,7 +197,9 @@ pub fn _text_to_phonemes(
     let phoneme_mode: i32 = calculated_phoneme_mode.try_into().unwrap();
     let mut sent_phonemes = Vec::new();
     let mut phonemes = String::new();
-    let mut text_c_char = rust_string_to_c(text) as *const ffi::c_char;
+    // `ffi_safe_text` has no NULs
+    let text_c_string = CString::new(text.as_ref()).unwrap_or_default();
+    let mut text_c_char = text_c_string.as_ptr() as *const ffi::c_char;
     let text_c_char_ptr = std::ptr::addr_of_mut!(text_c_char);
     let mut terminator: ffi::c_int = 0;
     let terminator_ptr: *mut ffi::c_int = &mut terminator;
@@ -155,6 +248,38 @@ pub fn _text_to_phonemes(
     Ok(sent_phonemes)
 }
 
+/// `text` without the characters that can't be passed to espeak-ng as a C string or
+/// that it has no use for (NULs and other control characters), and with words split to
+/// fit its buffers. Whatever the input, the result is safe to pass to espeak-ng
+fn ffi_safe_text(text: &str) -> Cow<'_, str> {
+    let is_unsafe = |c: char| c.is_control() && !c.is_whitespace();
+    let mut word_bytes = 0;
+    let needs_cleanup = text.chars().any(|c| {
+        word_bytes = match c.is_whitespace() {
+            true => 0,
+            false => word_bytes + c.len_utf8(),
+        };
+        is_unsafe(c) || word_bytes > MAX_WORD_BYTES
+    });
+    if !needs_cleanup {
+        return Cow::Borrowed(text);
+    }
+    let mut safe_text = String::with_capacity(text.len());
+    let mut word_bytes = 0;
+    for c in text.chars().filter(|c| !is_unsafe(*c)) {
+        if c.is_whitespace() {
+            word_bytes = 0;
+        } else if word_bytes + c.len_utf8() > MAX_WORD_BYTES {
+            safe_text.push(' ');
+            word_bytes = c.len_utf8();
+        } else {
+            word_bytes += c.len_utf8();
+        }
+        safe_text.push(c);
+    }
+    Cow::Owned(safe_text)
+}
+
 // ==============================
 
 #[cfg(test)]
@@ -242,6 +367,61 @@ mod tests {
 
         Ok(())
     }
+    #[test]
+    fn test_phonemization_warnings() {
+        let phonemization = text_to_phonemes_with_warnings("test", "en-US", None, false, false);
+        assert!(phonemization.unwrap().warnings.is_empty());
+        assert!(text_to_phonemes_with_warnings("test", "xx-unknown", None, false, false).is_err());
+    }
+
+    #[test]
+    fn test_ffi_safe_text() {
+        assert!(matches!(ffi_safe_text("Hello there"), Cow::Borrowed(_)));
+        assert_eq!(ffi_safe_text("a\0b\u{1B}c\td"), "abc\td");
+        let long_word = "é".repeat(MAX_WORD_BYTES);
+        let safe_text = ffi_safe_text(&long_word);
+        assert!(safe_text
+            .split(' ')
+            .all(|word| !word.is_empty() && word.len() <= MAX_WORD_BYTES));
+        assert_eq!(safe_text.replace(' ', ""), long_word);
+    }
+
+    #[test]
+    fn test_no_input_crashes_phonemization() {
+        const ALPHABET: &[char] = &[
+            'a', 'Z', ' ', '\n', '.', '?', '\0', '\u{1B}', '\u{7F}', '\u{85}', '\u{200B}',
+            '\u{200D}', '\u{202E}', '\u{FEFF}', '\u{FFFF}', 'é', 'ش', '中', '👩', '\u{301}',
+        ];
+        // xorshift, so that failures reproduce
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..200 {
+            let len = (next() % 400) as usize;
+            let text = String::from_iter(
+                (0..len).map(|_| ALPHABET[(next() % ALPHABET.len() as u64) as usize]),
+            );
+            text_to_phonemes(&text, "en-US", None, true, false).unwrap();
+        }
+        assert!(text_to_phonemes("test", "en\0US", None, false, false).is_err());
+    }
+
+    #[test]
+    fn test_terminate() -> ESpeakResult<()> {
+        let phonemes = text_to_phonemes("test", "en-US", None, false, false)?;
+        terminate();
+        terminate();
+        assert_eq!(
+            text_to_phonemes("test", "en-US", None, false, false)?,
+            phonemes
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_line_splitting() -> ESpeakResult<()> {
         let text = "Hello\nThere\nAnd\nWelcome";
@@ -0,0 +1,146 @@
+//! Guessing the problems espeak-ng would print to `stderr`.
+//!
+//! espeak-ng reports problems such as missing dictionaries with `fprintf(stderr, ...)`.
+//! The C `stderr` is shared by the whole process, and can't be pointed elsewhere while
+//! other threads may write to it, so espeak-ng's own output is not captured. Instead,
+//! the problems are guessed from the files in the data directory, which only finds
+//! missing dictionaries. Failures are described by the status codes of the espeak-ng API.
+
+use crate::espeakng;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// A data directory and a voice
+type VoiceKey = (PathBuf, String);
+
+/// The warnings of each data directory and voice, which are looked up once
+static VOICE_WARNINGS: Mutex<Option<HashMap<VoiceKey, Vec<String>>>> = Mutex::new(None);
+
+/// The message of an espeak-ng status code
+pub(crate) fn status_message(status: espeakng::espeak_ng_STATUS) -> String {
+    let mut buffer = [0 as c_char; 512];
+    unsafe {
+        espeakng::espeak_ng_GetStatusCodeMessage(status, buffer.as_mut_ptr(), buffer.len());
+        CStr::from_ptr(buffer.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Problems with the files of `voice` in the `espeak-ng-data` directory of `data_dir`,
+/// e.g. a missing dictionary, which makes the language sound wrong. They are logged
+/// with the `espeak-ng` target when first looked up
+pub(crate) fn voice_warnings(data_dir: &Path, voice: &str) -> Vec<String> {
+    let mut voice_warnings = VOICE_WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    voice_warnings
+        .get_or_insert_with(HashMap::new)
+        .entry((data_dir.to_path_buf(), voice.to_string()))
+        .or_insert_with(|| {
+            let warnings = find_voice_warnings(&data_dir.join("espeak-ng-data"), voice);
+            for warning in warnings.iter() {
+                log::warn!(target: "espeak-ng", "{}", warning);
+            }
+            warnings
+        })
+        .clone()
+}
+
+fn find_voice_warnings(espeak_data: &Path, voice: &str) -> Vec<String> {
+    // Unknown voices fail to load, with a status of their own
+    let Some(voice_file) = find_voice_file(&espeak_data.join("lang"), voice) else {
+        return Vec::new();
+    };
+    let Some(dictionary) = std::fs::read_to_string(voice_file)
+        .ok()
+        .and_then(|voice_file| dictionary_name(&voice_file))
+    else {
+        return Vec::new();
+    };
+    let dictionary_file = format!("{}_dict", dictionary);
+    match espeak_data.join(&dictionary_file).exists() {
+        true => Vec::new(),
+        false => vec![format!(
+            "Can't read dictionary file: '{}'",
+            espeak_data.join(dictionary_file).display()
+        )],
+    }
+}
+
+/// The voice file named `voice`, in the language families under `lang_dir`
+fn find_voice_file(lang_dir: &Path, voice: &str) -> Option<PathBuf> {
+    for entry in std::fs::read_dir(lang_dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(voice_file) = find_voice_file(&path, voice) {
+                return Some(voice_file);
+            }
+        } else if entry
+            .file_name()
+            .to_string_lossy()
+            .eq_ignore_ascii_case(voice)
+        {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// The dictionary a voice file names, or else the one of its first language, as
+/// espeak-ng picks it
+fn dictionary_name(voice_file: &str) -> Option<String> {
+    let mut language = None;
+    for line in voice_file.lines() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("dictionary"), Some(dictionary)) => return Some(dictionary.to_string()),
+            (Some("language"), Some(code)) if language.is_none() => {
+                language = code.split('-').next().map(str::to_string);
+            }
+            _ => {}
+        }
+    }
+    language
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dictionary_name() {
+        assert_eq!(
+            dictionary_name("name English (America)\nlanguage en-us 2\nlanguage en 3\n"),
+            Some("en".to_string())
+        );
+        assert_eq!(
+            dictionary_name("name Norwegian Bokmål\nlanguage nb\ndictionary no\n"),
+            Some("no".to_string())
+        );
+        assert_eq!(dictionary_name("name Nothing\n"), None);
+    }
+
+    #[test]
+    fn test_voice_warnings() {
+        let espeak_data =
+            std::env::temp_dir().join(format!("sonata-espeak-data-{}", std::process::id()));
+        std::fs::create_dir_all(espeak_data.join("lang/gmw")).unwrap();
+        std::fs::write(espeak_data.join("lang/gmw/en-US"), "language en-us\n").unwrap();
+        std::fs::write(
+            espeak_data.join("lang/gmw/nb"),
+            "language nb\ndictionary no\n",
+        )
+        .unwrap();
+        std::fs::write(espeak_data.join("en_dict"), "").unwrap();
+        assert!(find_voice_warnings(&espeak_data, "en-us").is_empty());
+        let warnings = find_voice_warnings(&espeak_data, "nb");
+        std::fs::remove_dir_all(&espeak_data).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("no_dict"), "{}", warnings[0]);
+        // Unknown voices fail to load instead
+        assert!(find_voice_warnings(&espeak_data, "xx").is_empty());
+    }
+}
@@ -15,16 +15,7 @@ use rodio::buffer::SamplesBuffer;
 use sonata_synth::SonataSpeechSynthesizer;
 use std::path::Path;
 
-fn init_ort_environment() {
-    ort::init()
-        .with_name("sonata")
-        .with_execution_providers([ort::ExecutionProviderDispatch::CPU(Default::default())])
-        .commit()
-        .expect("Failed to initialize onnxruntime");
-}
-
 fn main() {
-    init_ort_environment();
     let config_path = std::env::args().nth(1).expect("Please specify config path");
     let text = "Hello! i'm playing audio from memory directly.".to_string();
 
@@ -15,18 +15,7 @@ use sonata_synth::SonataSpeechSynthesizer;
 use sonata_piper::from_config_path;
 use std::path::Path;
 
-fn init_ort_environment() -> anyhow::Result<()> {
-    ort::init()
-        .with_execution_providers([
-            ort::CUDAExecutionProvider::default().with_device_id(0).build()?,
-            ort::CPUExecutionProvider::default().build()?,
-        ])?
-        .commit()?;
-    Ok(())
-}
-
 fn main() -> anyhow::Result<()> {
-    init_ort_environment()?;
     let config_path = std::env::args().nth(1).expect("Please specify config path");
     let output_path = std::env::args().nth(2).expect("Please specify output path");
     let text = "Hello! this is example with sonata".to_string();
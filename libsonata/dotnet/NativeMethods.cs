@@ -0,0 +1,141 @@
+// Copyright (c) Musharraf Omer
+//
+// Licensed under the MIT License
+//
+// P/Invoke declarations mirroring `libsonata.h`.
+// Keep this file in sync with the C header whenever the exported API changes.
+
+using System;
+using System.Runtime.InteropServices;
+
+namespace Sonata.Native
+{
+    internal static class ErrorCodes
+    {
+        public const int SUCCESS = 0;
+        public const int PANIC = -1;
+        public const int INVALID_SYNTHESIS_MODE = 16;
+        public const int FAILED_TO_LOAD_RESOURCE = 17;
+        public const int PHONEMIZATION_ERROR = 18;
+        public const int OPERATION_ERROR = 19;
+        public const int INVALID_UTF8_SEQUENCE = 20;
+        public const int UNKNOWN_ERROR = 21;
+    }
+
+    internal static class SynthEvent
+    {
+        public const int SYNTH_EVENT_SPEECH = 0;
+        public const int SYNTH_EVENT_FINISHED = 1;
+        public const int SYNTH_EVENT_ERROR = 2;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct ExternError
+    {
+        public int Code;
+        public IntPtr Message;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct SynthesisEvent
+    {
+        public int EventType;
+        public IntPtr ErrorPtr;
+        public long Len;
+        public IntPtr Data;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct AudioInfo
+    {
+        public uint SampleRate;
+        public uint NumChannels;
+        public uint SampleWidth;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct PiperSynthConfig
+    {
+        public uint Speaker;
+        public float LengthScale;
+        public float NoiseScale;
+        public float NoiseW;
+    }
+
+    [UnmanagedFunctionPointer(CallingConvention.Cdecl)]
+    internal delegate byte SpeechSynthesisCallback(SynthesisEvent synthEvent);
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct SynthesisParams
+    {
+        public int Mode;
+        public byte Rate;
+        public byte Volume;
+        public byte Pitch;
+        public uint AppendedSilenceMs;
+        public IntPtr Callback;
+        public byte Nonblocking;
+    }
+
+    internal static class NativeMethods
+    {
+        private const string DllName = "libsonata";
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataFreeString(IntPtr stringPtr);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataFreePiperSynthConfig(IntPtr synthConfig);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataFreeSynthesisEvent(SynthesisEvent synthEvent);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataSetOnnxruntimePath(
+            [MarshalAs(UnmanagedType.LPUTF8Str)] string path,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataShutdown(ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern IntPtr libsonataLoadVoiceFromConfigPath(
+            [MarshalAs(UnmanagedType.LPUTF8Str)] string configPath,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataUnloadSonataVoice(IntPtr voicePtr);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataGetAudioInfo(
+            SonataVoiceHandle voicePtr,
+            ref AudioInfo audioInfo,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern IntPtr libsonataGetPiperDefaultSynthConfig(
+            SonataVoiceHandle voicePtr,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataSetPiperSynthConfig(
+            SonataVoiceHandle voicePtr,
+            PiperSynthConfig synthConfig,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern void libsonataSpeak(
+            SonataVoiceHandle voicePtr,
+            [MarshalAs(UnmanagedType.LPUTF8Str)] string text,
+            SynthesisParams synthParams,
+            ref ExternError outError);
+
+        [DllImport(DllName, CallingConvention = CallingConvention.Cdecl)]
+        public static extern byte libsonataSpeakToFile(
+            SonataVoiceHandle voicePtr,
+            [MarshalAs(UnmanagedType.LPUTF8Str)] string text,
+            SynthesisParams synthParams,
+            [MarshalAs(UnmanagedType.LPUTF8Str)] string outFilename,
+            ref ExternError outError);
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright (c) Musharraf Omer
+//
+// Licensed under the MIT License
+
+using System;
+using System.Runtime.InteropServices;
+using System.Threading;
+using Sonata.Native;
+
+namespace Sonata
+{
+    public enum SynthesisMode
+    {
+        Lazy = 0,
+        Parallel = 1,
+        Realtime = 2,
+    }
+
+    public sealed class AudioInfo
+    {
+        public int SampleRate { get; internal set; }
+        public int NumChannels { get; internal set; }
+        public int SampleWidth { get; internal set; }
+    }
+
+    public sealed class PiperSynthesisConfig
+    {
+        public uint Speaker { get; set; }
+        public float LengthScale { get; set; }
+        public float NoiseScale { get; set; }
+        public float NoiseW { get; set; }
+    }
+
+    public sealed class SynthesisOptions
+    {
+        public SynthesisMode Mode { get; set; } = SynthesisMode.Lazy;
+        /// Speaking rate [0 - 100]
+        public byte Rate { get; set; } = 50;
+        /// Speech volume [0 - 100]
+        public byte Volume { get; set; } = 75;
+        /// Speech pitch [0 - 100]
+        public byte Pitch { get; set; } = 50;
+        public uint AppendedSilenceMs { get; set; } = 0;
+    }
+
+    public class SonataException : Exception
+    {
+        public int Code { get; }
+
+        public SonataException(int code, string message) : base(message)
+        {
+            Code = code;
+        }
+
+        internal static void ThrowIfFailed(ref ExternError error)
+        {
+            if (error.Code == ErrorCodes.SUCCESS)
+            {
+                return;
+            }
+            throw FromExternError(ref error);
+        }
+
+        internal static SonataException FromExternError(ref ExternError error)
+        {
+            string message = "Unknown error";
+            if (error.Message != IntPtr.Zero)
+            {
+                message = Utf8.PtrToString(error.Message);
+                NativeMethods.libsonataFreeString(error.Message);
+                error.Message = IntPtr.Zero;
+            }
+            return new SonataException(error.Code, message);
+        }
+    }
+
+    internal sealed class SonataVoiceHandle : SafeHandle
+    {
+        public SonataVoiceHandle() : base(IntPtr.Zero, true) { }
+
+        public override bool IsInvalid => handle == IntPtr.Zero;
+
+        protected override bool ReleaseHandle()
+        {
+            NativeMethods.libsonataUnloadSonataVoice(handle);
+            return true;
+        }
+
+        internal static SonataVoiceHandle FromRaw(IntPtr ptr)
+        {
+            var voiceHandle = new SonataVoiceHandle();
+            voiceHandle.SetHandle(ptr);
+            return voiceHandle;
+        }
+    }
+
+    /// A loaded Sonata voice.
+    ///
+    /// Speech is delivered as 16-bit PCM chunks through `Speak`'s callback,
+    /// or written to a wave file using `SpeakToFile`.
+    public sealed class SonataVoice : IDisposable
+    {
+        private readonly SonataVoiceHandle _handle;
+
+        private SonataVoice(SonataVoiceHandle handle)
+        {
+            _handle = handle;
+        }
+
+        /// Load onnxruntime from the given library (or directory) rather than the one
+        /// next to the application. Must be called before the first voice is loaded,
+        /// with libsonata built with the `ort-dylib` feature.
+        public static void SetOnnxruntimePath(string path)
+        {
+            var error = new ExternError();
+            NativeMethods.libsonataSetOnnxruntimePath(path, ref error);
+            SonataException.ThrowIfFailed(ref error);
+        }
+
+        /// Release espeak-ng and onnxruntime, e.g. before the application unloads
+        /// libsonata. All voices must be disposed of first.
+        public static void Shutdown()
+        {
+            var error = new ExternError();
+            NativeMethods.libsonataShutdown(ref error);
+            SonataException.ThrowIfFailed(ref error);
+        }
+
+        public static SonataVoice FromConfigPath(string configPath)
+        {
+            var error = new ExternError();
+            IntPtr ptr = NativeMethods.libsonataLoadVoiceFromConfigPath(configPath, ref error);
+            SonataException.ThrowIfFailed(ref error);
+            return new SonataVoice(SonataVoiceHandle.FromRaw(ptr));
+        }
+
+        public AudioInfo GetAudioInfo()
+        {
+            var error = new ExternError();
+            var info = new Native.AudioInfo();
+            NativeMethods.libsonataGetAudioInfo(_handle, ref info, ref error);
+            SonataException.ThrowIfFailed(ref error);
+            return new AudioInfo
+            {
+                SampleRate = (int)info.SampleRate,
+                NumChannels = (int)info.NumChannels,
+                SampleWidth = (int)info.SampleWidth,
+            };
+        }
+
+        public PiperSynthesisConfig GetDefaultSynthesisConfig()
+        {
+            var error = new ExternError();
+            IntPtr ptr = NativeMethods.libsonataGetPiperDefaultSynthConfig(_handle, ref error);
+            SonataException.ThrowIfFailed(ref error);
+            try
+            {
+                var config = Marshal.PtrToStructure<PiperSynthConfig>(ptr);
+                return new PiperSynthesisConfig
+                {
+                    Speaker = config.Speaker,
+                    LengthScale = config.LengthScale,
+                    NoiseScale = config.NoiseScale,
+                    NoiseW = config.NoiseW,
+                };
+            }
+            finally
+            {
+                NativeMethods.libsonataFreePiperSynthConfig(ptr);
+            }
+        }
+
+        public void SetSynthesisConfig(PiperSynthesisConfig config)
+        {
+            var error = new ExternError();
+            var nativeConfig = new PiperSynthConfig
+            {
+                Speaker = config.Speaker,
+                LengthScale = config.LengthScale,
+                NoiseScale = config.NoiseScale,
+                NoiseW = config.NoiseW,
+            };
+            NativeMethods.libsonataSetPiperSynthConfig(_handle, nativeConfig, ref error);
+            SonataException.ThrowIfFailed(ref error);
+        }
+
+        /// Synthesize `text` and deliver each chunk of wave samples to `onSpeech`.
+        ///
+        /// Return `false` from `onSpeech` to cancel the remaining synthesis.
+        /// This call blocks until synthesis is finished, cancelled, or failed.
+        public void Speak(string text, SynthesisOptions options, Func<byte[], bool> onSpeech)
+        {
+            using (var session = new SpeechSession(onSpeech))
+            {
+                var error = new ExternError();
+                var synthParams = session.CreateParams(options, false);
+                NativeMethods.libsonataSpeak(_handle, text, synthParams, ref error);
+                SonataException.ThrowIfFailed(ref error);
+                session.ThrowIfFailed();
+            }
+        }
+
+        /// Start synthesizing `text` on libsonata's thread pool.
+        ///
+        /// `onSpeech` is invoked from a background thread. The returned
+        /// `SpeechSession` completes when synthesis finishes or fails,
+        /// and must be kept alive until then.
+        public SpeechSession SpeakAsync(string text, SynthesisOptions options, Func<byte[], bool> onSpeech)
+        {
+            var session = new SpeechSession(onSpeech);
+            var error = new ExternError();
+            var synthParams = session.CreateParams(options, true);
+            NativeMethods.libsonataSpeak(_handle, text, synthParams, ref error);
+            if (error.Code != ErrorCodes.SUCCESS)
+            {
+                session.Dispose();
+                throw SonataException.FromExternError(ref error);
+            }
+            return session;
+        }
+
+        public void SpeakToFile(string text, SynthesisOptions options, string outputFilename)
+        {
+            using (var session = new SpeechSession(_ => true))
+            {
+                var error = new ExternError();
+                var synthParams = session.CreateParams(options, false);
+                byte succeeded = NativeMethods.libsonataSpeakToFile(_handle, text, synthParams, outputFilename, ref error);
+                SonataException.ThrowIfFailed(ref error);
+                if (succeeded == 0)
+                {
+                    throw new SonataException(ErrorCodes.OPERATION_ERROR, $"Failed to write speech to file `{outputFilename}`");
+                }
+            }
+        }
+
+        public void Dispose()
+        {
+            _handle.Dispose();
+        }
+    }
+
+    /// Keeps the native callback alive for the duration of a synthesis call.
+    public sealed class SpeechSession : IDisposable
+    {
+        private readonly Func<byte[], bool> _onSpeech;
+        private readonly SpeechSynthesisCallback _callback;
+        private readonly ManualResetEventSlim _finished = new ManualResetEventSlim(false);
+        private GCHandle _callbackHandle;
+        private SonataException? _error;
+        private volatile bool _cancelled;
+
+        internal SpeechSession(Func<byte[], bool> onSpeech)
+        {
+            _onSpeech = onSpeech;
+            _callback = OnSynthesisEvent;
+            _callbackHandle = GCHandle.Alloc(_callback);
+        }
+
+        public SonataException? Error => _error;
+
+        public bool IsFinished => _finished.IsSet;
+
+        /// Stop delivering audio. Synthesis stops at the next chunk boundary.
+        public void Cancel()
+        {
+            _cancelled = true;
+        }
+
+        public void Wait()
+        {
+            _finished.Wait();
+        }
+
+        public bool Wait(TimeSpan timeout)
+        {
+            return _finished.Wait(timeout);
+        }
+
+        internal SynthesisParams CreateParams(SynthesisOptions options, bool nonblocking)
+        {
+            return new SynthesisParams
+            {
+                Mode = (int)options.Mode,
+                Rate = options.Rate,
+                Volume = options.Volume,
+                Pitch = options.Pitch,
+                AppendedSilenceMs = options.AppendedSilenceMs,
+                Callback = Marshal.GetFunctionPointerForDelegate(_callback),
+                Nonblocking = nonblocking ? (byte)1 : (byte)0,
+            };
+        }
+
+        internal void ThrowIfFailed()
+        {
+            if (_error != null)
+            {
+                throw _error;
+            }
+        }
+
+        private byte OnSynthesisEvent(SynthesisEvent synthEvent)
+        {
+            try
+            {
+                switch (synthEvent.EventType)
+                {
+                    case SynthEvent.SYNTH_EVENT_SPEECH:
+                        var data = new byte[synthEvent.Len];
+                        if (synthEvent.Len > 0)
+                        {
+                            Marshal.Copy(synthEvent.Data, data, 0, data.Length);
+                        }
+                        if (_cancelled || !_onSpeech(data))
+                        {
+                            _cancelled = true;
+                            _finished.Set();
+                            return 1;
+                        }
+                        return 0;
+                    case SynthEvent.SYNTH_EVENT_ERROR:
+                        var error = Marshal.PtrToStructure<ExternError>(synthEvent.ErrorPtr);
+                        _error = new SonataException(error.Code, Utf8.PtrToString(error.Message));
+                        _finished.Set();
+                        return 1;
+                    default:
+                        _finished.Set();
+                        return 0;
+                }
+            }
+            finally
+            {
+                NativeMethods.libsonataFreeSynthesisEvent(synthEvent);
+            }
+        }
+
+        public void Dispose()
+        {
+            if (_callbackHandle.IsAllocated)
+            {
+                _callbackHandle.Free();
+            }
+            _finished.Dispose();
+        }
+    }
+
+    internal static class Utf8
+    {
+        public static string PtrToString(IntPtr ptr)
+        {
+            if (ptr == IntPtr.Zero)
+            {
+                return string.Empty;
+            }
+            int len = 0;
+            while (Marshal.ReadByte(ptr, len) != 0)
+            {
+                len++;
+            }
+            var buffer = new byte[len];
+            Marshal.Copy(ptr, buffer, 0, len);
+            return System.Text.Encoding.UTF8.GetString(buffer);
+        }
+    }
+}
@@ -81,6 +81,10 @@ void libsonataFreePiperSynthConfig(struct PiperSynthConfig *synth_config);
 
 void libsonataFreeSynthesisEvent(struct SynthesisEvent event);
 
+void libsonataSetOnnxruntimePath(FfiStr path_ptr, struct ExternError *out_error);
+
+void libsonataShutdown(struct ExternError *out_error);
+
 struct SonataVoice *libsonataLoadVoiceFromConfigPath(FfiStr config_path_ptr,
                                                      struct ExternError *out_error);
 
@@ -5,7 +5,7 @@ use std::any::Any;
 use std::ops::Deref;
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
-use std::sync::{Arc, Once};
+use std::sync::Arc;
 
 pub type SpeechSynthesisCallback = extern "C" fn(SynthesisEvent) -> u8;
 define_string_destructor!(_internal_libsonataFreeString);
@@ -14,8 +14,6 @@ ffi_support::define_box_destructor!(SonataVoice, _internal_libsonataUnloadSonata
 ffi_support::implement_into_ffi_by_pointer!(PiperSynthConfig);
 ffi_support::define_box_destructor!(PiperSynthConfig, _internal_libsonataFreePiperSynthConfig);
 
-static INIT_ORT_ENVIRONMENT: Once = Once::new();
-
 pub mod error_codes {
     pub const INVALID_SYNTHESIS_MODE: i32 = 16;
     pub const FAILED_TO_LOAD_RESOURCE: i32 = 17;
@@ -80,10 +78,16 @@ impl SonataFFIError {
 
 impl From<SonataError> for SonataFFIError {
     fn from(other: SonataError) -> Self {
-        let (code, message) = match other {
-            SonataError::FailedToLoadResource(msg) => (error_codes::FAILED_TO_LOAD_RESOURCE, msg),
-            SonataError::PhonemizationError(msg) => (error_codes::PHONEMIZATION_ERROR, msg),
-            SonataError::OperationError(msg) => (error_codes::OPERATION_ERROR, msg),
+        let code = match other.without_cause() {
+            SonataError::FailedToLoadResource(_) => error_codes::FAILED_TO_LOAD_RESOURCE,
+            SonataError::PhonemizationError(_) => error_codes::PHONEMIZATION_ERROR,
+            _ => error_codes::OPERATION_ERROR,
+        };
+        let message = match other {
+            SonataError::FailedToLoadResource(msg)
+            | SonataError::PhonemizationError(msg)
+            | SonataError::OperationError(msg) => msg,
+            caused => caused.message_with_causes(),
         };
         Self(code, message)
     }
@@ -159,6 +163,11 @@ impl SynthesisParams {
             volume: Some(self.volume),
             pitch: Some(self.pitch),
             appended_silence_ms: Some(self.appended_silence_ms),
+            time_stretch: None,
+            gain_db: None,
+            rate_multiplier: None,
+            trim_leading_silence: false,
+            trim_trailing_silence: false,
         }
     }
 }
@@ -172,12 +181,17 @@ pub struct PiperSynthConfig {
 }
 
 impl PiperSynthConfig {
-    fn as_piper_synth_config(&self) -> sonata_piper::PiperSynthesisConfig {
+    /// The config of the voice, keeping its prosody variation, which C doesn't set
+    fn as_piper_synth_config(
+        &self,
+        prosody_variation: Option<sonata_piper::ProsodyVariation>,
+    ) -> sonata_piper::PiperSynthesisConfig {
         sonata_piper::PiperSynthesisConfig {
             speaker: Some(self.speaker.into()),
             noise_scale: self.noise_scale,
             length_scale: self.length_scale,
             noise_w: self.noise_w,
+            prosody_variation,
         }
     }
 }
@@ -212,6 +226,24 @@ pub unsafe extern "C" fn libsonataFreeSynthesisEvent(event: SynthesisEvent) {
     });
 }
 
+/// Load onnxruntime from the given shared library, or the directory holding it. Only
+/// supported with the `ort-dylib` feature, and must be called before loading any voice.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn libsonataSetOnnxruntimePath(path_ptr: FfiStr, out_error: &mut ExternError) {
+    call_with_result(out_error, move || _set_onnxruntime_path(path_ptr))
+}
+
+/// Release espeak-ng and onnxruntime before libsonata is unloaded. All voices must be
+/// unloaded first. Voices can be loaded again afterwards.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn libsonataShutdown(out_error: &mut ExternError) {
+    call_with_result(out_error, || {
+        sonata_piper::shutdown().map_err(SonataFFIError::from)
+    })
+}
+
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn libsonataLoadVoiceFromConfigPath(
@@ -292,7 +324,12 @@ pub unsafe extern "C" fn libsonataSetPiperSynthConfig(
 ) {
     let voice = voice_ptr.as_ref().unwrap();
     call_with_result(out_error, move || {
-        let piper_synth_config = synth_config.as_piper_synth_config();
+        let prosody_variation = voice
+            .get_fallback_synthesis_config()
+            .map_err(SonataFFIError::from)?
+            .downcast_ref::<sonata_piper::PiperSynthesisConfig>()
+            .and_then(|config| config.prosody_variation);
+        let piper_synth_config = synth_config.as_piper_synth_config(prosody_variation);
         let config = &piper_synth_config as &dyn Any;
         voice
             .set_fallback_synthesis_config(config)
@@ -335,25 +372,18 @@ pub unsafe extern "C" fn libsonataSpeakToFile(
     })
 }
 
-fn init_ort_environment()  {
-    INIT_ORT_ENVIRONMENT.call_once(|| {
-        let execution_providers = [
-            #[cfg(target_os = "android")]
-            ort::ExecutionProviderDispatch::NNAPI(Default::default()),
-            #[cfg(target_os = "ios")]
-            ort::ExecutionProviderDispatch::CoreML(Default::default()),
-            ort::ExecutionProviderDispatch::CPU(Default::default()),
-        ];
-        ort::init()
-            .with_name("sonata")
-            .with_execution_providers(execution_providers)
-            .commit()
-            .unwrap();
-    });
+fn _set_onnxruntime_path(path_ptr: FfiStr) -> SonataFFIResult<()> {
+    let path = path_ptr
+        .into_opt_string()
+        .ok_or_else(SonataFFIError::invalid_utf8)?;
+    let options = sonata_piper::InitOptions {
+        dylib_path: Some(PathBuf::from(path)),
+        ..Default::default()
+    };
+    sonata_piper::init(options).map_err(SonataFFIError::from)
 }
 
 fn _load_piper_voice(config_path_ptr: FfiStr) -> SonataFFIResult<SonataVoice> {
-    init_ort_environment();
     let config_path = config_path_ptr
         .into_opt_string()
         .ok_or_else(SonataFFIError::invalid_utf8)?;
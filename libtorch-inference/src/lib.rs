@@ -12,6 +12,24 @@ pub enum LibtorchError {
     OperationError(String),
 }
 
+impl std::error::Error for LibtorchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InferenceError(error) => Some(error),
+            Self::OperationError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for LibtorchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InferenceError(_) => write!(f, "Failed to run libtorch inference"),
+            Self::OperationError(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
 impl From<TchError> for LibtorchError {
     fn from(other: TchError) -> Self {
         Self::InferenceError(other)
@@ -81,9 +99,8 @@ mod test {
 fn create_inference_session(model_path: &Path) -> Result<Session, ort::Error> {
     Session::builder()?
         .with_execution_providers([
-            CUDAExecutionProvider::default().with_device_id(0).build()?,
+            CUDAExecutionProvider::default().with_device_id(0).build(),
             // Add other execution providers as needed
         ])?
-        .with_model_from_file(model_path)?
-        .commit()?
+        .commit_from_file(model_path)
 }
\ No newline at end of file
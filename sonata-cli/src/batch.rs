@@ -0,0 +1,420 @@
+//! `sonata batch`: synthesize a corpus of `id,text` lines into one WAV file per line.
+//!
+//! Results are appended to `manifest.jsonl` in the output directory as they complete.
+//! On re-run, lines whose audio was already written are skipped, so an interrupted
+//! batch resumes where it left off and failed lines are retried.
+//!
+//! With `--layout ljspeech`, the output is an LJSpeech-style dataset: the audio goes
+//! to `wavs/` and `metadata.csv` lists `id|text|normalized_text` for every line.
+//!
+//! Lines with the same text as an earlier line, e.g. repeated headers, get a copy of
+//! its audio rather than being synthesized again. The voice and options are the same
+//! for the whole batch, so the text alone decides whether the audio would be the same.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sonata_piper::PiperSynthesisConfig;
+use sonata_synth::{Audio, AudioOutputConfig, AudioSamples, SonataModel, SonataSpeechSynthesizer};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const MANIFEST_FILENAME: &str = "manifest.jsonl";
+const LJSPEECH_METADATA_FILENAME: &str = "metadata.csv";
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// WAV files next to the manifest
+    Plain,
+    /// `wavs/` and `metadata.csv` (`id|text|normalized_text`), as in the LJSpeech dataset
+    Ljspeech,
+}
+
+impl Layout {
+    fn audio_file(&self, id: &str) -> String {
+        match self {
+            Self::Plain => format!("{}.wav", id),
+            Self::Ljspeech => format!("wavs/{}.wav", id),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct BatchArgs {
+    /// Model config
+    config: PathBuf,
+    /// CSV file of `id,text` lines. The text may be quoted, and an `id,text` header is skipped
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: PathBuf,
+    /// Directory for the WAV files and the manifest
+    #[arg(short, long, value_name = "OUT_DIR")]
+    out_dir: PathBuf,
+    /// Output layout
+    #[arg(long, value_enum, default_value_t = Layout::Plain)]
+    layout: Layout,
+    /// Resample the audio to this sample rate (default: the voice's sample rate)
+    #[arg(long)]
+    sample_rate: Option<usize>,
+    /// Number of lines to synthesize in parallel (default: number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Speaker ID for multi-speaker models (default `0`)
+    #[arg(long)]
+    speaker_id: Option<u32>,
+    /// Piper length scale (default `model_default from config file`)
+    #[arg(long)]
+    length_scale: Option<f32>,
+    /// Piper noise scale (default `model_default from config file`)
+    #[arg(long)]
+    noise_scale: Option<f32>,
+    /// Piper noise width (default `model_default from config file`)
+    #[arg(long)]
+    noise_w: Option<f32>,
+    /// Speaking rate [0 - 100] (default `50`)
+    #[arg(long)]
+    rate: Option<u8>,
+    /// Speech pitch [0 - 100] (default `50`)
+    #[arg(long)]
+    pitch: Option<u8>,
+    /// Speech volume [0 - 100] (default `75`)
+    #[arg(long)]
+    volume: Option<u8>,
+    /// Extra silence (in milliseconds) to append to the end of each sentence (default `0`)
+    #[arg(long)]
+    silence: Option<u32>,
+    /// JSON file of text replacement rules, applied before phonemization
+    #[arg(long, value_name = "REPLACEMENTS_FILE")]
+    replacements: Option<PathBuf>,
+    /// Normalize emoji, URLs, dates, currencies and acronyms before phonemization
+    /// (implied by `--layout ljspeech`)
+    #[arg(long)]
+    normalize: bool,
+    /// Synthesize lines of the same text separately instead of copying the audio of the
+    /// first one, e.g. for the varied renditions the noise of the voice gives
+    #[arg(long)]
+    no_dedup: bool,
+}
+
+struct BatchItem {
+    id: String,
+    text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    id: String,
+    /// The WAV file, relative to the output directory
+    file: String,
+    text: String,
+    duration_ms: Option<f32>,
+    error: Option<String>,
+    /// The line of the same text whose audio was copied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<String>,
+}
+
+/// Split a CSV line into its id and text. Everything after the first comma is the
+/// text, so unquoted texts may contain commas.
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let (id, rest) = match line.strip_prefix('"') {
+        Some(quoted) => {
+            let (id, rest) = split_quoted(quoted)?;
+            (id, rest.strip_prefix(',')?)
+        }
+        None => {
+            let (id, rest) = line.split_once(',')?;
+            (id.to_string(), rest)
+        }
+    };
+    let rest = rest.trim();
+    let text = match rest.strip_prefix('"') {
+        Some(quoted) => split_quoted(quoted)?.0,
+        None => rest.to_string(),
+    };
+    Some((id.trim().to_string(), text))
+}
+
+/// Read a quoted field (without its opening quote), unescaping `""`.
+/// Returns the field and what follows the closing quote.
+fn split_quoted(quoted: &str) -> Option<(String, &str)> {
+    let mut field = String::new();
+    let mut chars = quoted.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '"' {
+            field.push(c);
+        } else if matches!(chars.peek(), Some((_, '"'))) {
+            field.push('"');
+            chars.next();
+        } else {
+            return Some((field, &quoted[i + 1..]));
+        }
+    }
+    None
+}
+
+fn read_items(input: &Path) -> anyhow::Result<Vec<BatchItem>> {
+    let reader = BufReader::new(File::open(input)?);
+    let mut items = Vec::new();
+    let mut ids = HashSet::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((id, text)) = parse_line(&line) else {
+            anyhow::bail!("Invalid line {} in `{}`", line_number + 1, input.display());
+        };
+        if line_number == 0 && id == "id" && text == "text" {
+            continue;
+        }
+        if id.is_empty() || !id.chars().all(|c| c.is_alphanumeric() || "-_.".contains(c)) {
+            anyhow::bail!(
+                "Invalid id `{}` on line {}. Ids are used as file names and may only contain letters, digits, `-`, `_` and `.`",
+                id,
+                line_number + 1
+            );
+        }
+        if !ids.insert(id.clone()) {
+            anyhow::bail!("Duplicate id `{}` on line {}", id, line_number + 1);
+        }
+        items.push(BatchItem { id, text });
+    }
+    Ok(items)
+}
+
+/// The manifest entries of the lines whose audio was written by a previous run, by id
+fn read_completed_entries(out_dir: &Path) -> anyhow::Result<HashMap<String, ManifestEntry>> {
+    let manifest_path = out_dir.join(MANIFEST_FILENAME);
+    if !manifest_path.exists() {
+        return Ok(HashMap::new());
+    }
+    // Later entries supersede earlier ones, e.g. a retried failure
+    let mut entries = HashMap::new();
+    for line in BufReader::new(File::open(&manifest_path)?).lines() {
+        let line = line?;
+        match serde_json::from_str::<ManifestEntry>(&line) {
+            Ok(entry) => {
+                entries.insert(entry.id.clone(), entry);
+            }
+            // A truncated line from an interrupted run
+            Err(e) => log::warn!("Ignoring invalid manifest entry. Error: {}", e),
+        }
+    }
+    entries.retain(|_, entry| entry.error.is_none() && out_dir.join(&entry.file).exists());
+    Ok(entries)
+}
+
+fn synthesize_item(
+    synth: &SonataSpeechSynthesizer,
+    item: &BatchItem,
+    output_config: &AudioOutputConfig,
+    sample_rate: Option<usize>,
+    out_file: &Path,
+) -> anyhow::Result<f32> {
+    let mut samples: Vec<f32> = Vec::new();
+    for result in synth.synthesize_lazy(item.text.clone(), Some(output_config.clone()))? {
+        samples.append(&mut result?.into_vec());
+    }
+    if samples.is_empty() {
+        anyhow::bail!("No speech data to write");
+    }
+    let voice_sample_rate = synth.audio_output_info()?.sample_rate;
+    let sample_rate = sample_rate.unwrap_or(voice_sample_rate);
+    let samples = AudioSamples::from(samples).resample(voice_sample_rate, sample_rate);
+    let audio = Audio::new(samples, sample_rate, None);
+    audio.save_to_file(out_file)?;
+    Ok(audio.duration_ms())
+}
+
+/// Write `metadata.csv` for all the lines synthesized so far, in input order
+fn write_ljspeech_metadata(
+    synth: &SonataSpeechSynthesizer,
+    items: &[BatchItem],
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    let completed = read_completed_entries(out_dir)?;
+    // `|` separates the fields and each line is one entry
+    let clean = |text: &str| text.replace(['|', '\n', '\r'], " ");
+    let mut metadata = String::new();
+    for item in items.iter().filter(|item| completed.contains_key(&item.id)) {
+        metadata.push_str(&format!(
+            "{}|{}|{}\n",
+            item.id,
+            clean(&item.text),
+            clean(&synth.normalize_text(&item.text)?)
+        ));
+    }
+    std::fs::write(out_dir.join(LJSPEECH_METADATA_FILENAME), metadata)?;
+    Ok(())
+}
+
+pub fn run(args: BatchArgs) -> anyhow::Result<()> {
+    let synth = crate::load_synthesizer(
+        &args.config,
+        args.replacements.as_deref(),
+        args.normalize || args.layout == Layout::Ljspeech,
+    )?
+    .build()?;
+    let default_config: PiperSynthesisConfig = *synth
+        .get_default_synthesis_config()?
+        .downcast()
+        .expect("Invalid default synthesis config. Expected Piper config.");
+    synth.set_fallback_synthesis_config(&PiperSynthesisConfig {
+        speaker: args.speaker_id.map(i64::from),
+        length_scale: args.length_scale.unwrap_or(default_config.length_scale),
+        noise_scale: args.noise_scale.unwrap_or(default_config.noise_scale),
+        noise_w: args.noise_w.unwrap_or(default_config.noise_w),
+        prosody_variation: default_config.prosody_variation,
+    })?;
+    let output_config = AudioOutputConfig {
+        rate: args.rate,
+        pitch: args.pitch,
+        volume: args.volume,
+        appended_silence_ms: args.silence,
+        time_stretch: None,
+        gain_db: None,
+        rate_multiplier: None,
+        trim_leading_silence: false,
+        trim_trailing_silence: false,
+    };
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    if args.layout == Layout::Ljspeech {
+        std::fs::create_dir_all(args.out_dir.join("wavs"))?;
+    }
+    let items = read_items(&args.input)?;
+    let completed = read_completed_entries(&args.out_dir)?;
+    // The first line of each text, whose audio the later ones copy
+    let mut originals = HashMap::new();
+    if !args.no_dedup {
+        for item in items.iter() {
+            originals.entry(item.text.as_str()).or_insert(item);
+        }
+    }
+    let mut pending = Vec::new();
+    let mut duplicates = Vec::new();
+    for item in items
+        .iter()
+        .filter(|item| !completed.contains_key(&item.id))
+    {
+        match originals.get(item.text.as_str()) {
+            Some(original) if original.id != item.id => duplicates.push((item, *original)),
+            _ => pending.push(item),
+        }
+    }
+    log::info!(
+        "Synthesizing {} lines ({} already done, {} duplicates)",
+        pending.len(),
+        items.len() - pending.len() - duplicates.len(),
+        duplicates.len()
+    );
+
+    let manifest = Mutex::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(args.out_dir.join(MANIFEST_FILENAME))?,
+    );
+    let write_entry = |entry: ManifestEntry| -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        manifest.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()?;
+    let durations = pool.install(|| {
+        pending
+            .par_iter()
+            .map(|item| -> anyhow::Result<(&str, Option<f32>)> {
+                let file = args.layout.audio_file(&item.id);
+                let result = synthesize_item(
+                    &synth,
+                    item,
+                    &output_config,
+                    args.sample_rate,
+                    &args.out_dir.join(&file),
+                );
+                if let Err(ref e) = result {
+                    log::error!("Failed to synthesize `{}`. Error: {:#}", item.id, e);
+                }
+                write_entry(ManifestEntry {
+                    id: item.id.clone(),
+                    file,
+                    text: item.text.clone(),
+                    duration_ms: result.as_ref().ok().copied(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                    duplicate_of: None,
+                })?;
+                Ok((item.id.as_str(), result.ok()))
+            })
+            .collect::<anyhow::Result<HashMap<&str, Option<f32>>>>()
+    })?;
+    let mut num_failed = durations.values().filter(|d| d.is_none()).count();
+
+    let mut num_reused = 0;
+    let mut reused_ms = 0.0;
+    for (item, original) in duplicates.iter() {
+        let source = match completed.get(&original.id) {
+            Some(entry) => Some((entry.file.clone(), entry.duration_ms)),
+            None => durations
+                .get(original.id.as_str())
+                .copied()
+                .flatten()
+                .map(|duration_ms| (args.layout.audio_file(&original.id), Some(duration_ms))),
+        };
+        let file = args.layout.audio_file(&item.id);
+        let result = match source {
+            Some((source_file, duration_ms)) => {
+                std::fs::copy(args.out_dir.join(source_file), args.out_dir.join(&file))
+                    .map(|_| duration_ms)
+                    .map_err(anyhow::Error::from)
+            }
+            None => Err(anyhow::anyhow!(
+                "The line `{}` of the same text failed",
+                original.id
+            )),
+        };
+        match result {
+            Ok(ref duration_ms) => {
+                num_reused += 1;
+                reused_ms += duration_ms.unwrap_or_default();
+            }
+            Err(ref e) => {
+                num_failed += 1;
+                log::error!("Failed to copy the audio of `{}`. Error: {:#}", item.id, e);
+            }
+        }
+        write_entry(ManifestEntry {
+            id: item.id.clone(),
+            file,
+            text: item.text.clone(),
+            duration_ms: result.as_ref().ok().copied().flatten(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            duplicate_of: Some(original.id.clone()),
+        })?;
+    }
+    if num_reused > 0 {
+        log::info!(
+            "Copied the audio of {} duplicate lines instead of synthesizing {:.1} s of audio again",
+            num_reused,
+            reused_ms / 1000.0
+        );
+    }
+    if args.layout == Layout::Ljspeech {
+        write_ljspeech_metadata(&synth, &items, &args.out_dir)?;
+    }
+    if num_failed > 0 {
+        anyhow::bail!(
+            "{} of {} lines failed. See `{}` for details",
+            num_failed,
+            pending.len() + duplicates.len(),
+            args.out_dir.join(MANIFEST_FILENAME).display()
+        );
+    }
+    log::info!("Wrote output to: {}", args.out_dir.display());
+    Ok(())
+}
@@ -1,14 +1,22 @@
-use clap::Parser;
+mod batch;
+mod report;
+mod watermark;
+
+use clap::{Args, Parser, Subcommand};
+use report::{ReportFormat, SynthesisReport};
 use serde::Deserialize;
-use sonata_piper::PiperSynthesisConfig;
+use sonata_piper::{PiperSynthesisConfig, ProsodyVariation, MAX_PROSODY_VARIATION};
 use sonata_synth::{
-    AudioOutputConfig, AudioSamples, SonataModel, SonataResult, SonataSpeechSynthesizer,
+    streaming_wave_header, Audio, AudioOutputConfig, AudioSamples, ChannelLayout, ChunkOverlap,
+    ExecutionProvider, InitOptions, OverlapWindow, ReplacementDictionary, SonataModel,
+    SonataResult, SonataSpeechSynthesizer, SonataSpeechSynthesizerBuilder, SplitPolicy,
+    StereoPanning, SynthesisStats, TextNormalizer, WaveSampleFormat,
 };
 use std::fs::File;
 use std::io::{self, prelude::*};
-use std::path::PathBuf;
-
-static INIT_ORT_ENVIRONMENT: std::sync::Once = std::sync::Once::new();
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Default, Deserialize)]
 enum SynthesisMode {
@@ -29,17 +37,126 @@ impl<'s> From<&'s str> for SynthesisMode {
     }
 }
 
+/// Encoding of the audio written to `stdout`
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum StdoutFormat {
+    /// Raw 16-bit little-endian PCM
+    #[default]
+    S16le,
+    /// Raw 32-bit little-endian float samples
+    F32le,
+    /// 16-bit PCM in a wave stream, whose header leaves the length open
+    Wav,
+}
+
+impl StdoutFormat {
+    fn encode(&self, samples: &AudioSamples) -> Vec<u8> {
+        match self {
+            Self::S16le | Self::Wav => samples.as_wave_bytes(),
+            Self::F32le => Vec::from_iter(
+                samples
+                    .to_full_scale_f32_vec()
+                    .into_iter()
+                    .flat_map(f32::to_le_bytes),
+            ),
+        }
+    }
+}
+
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// Model config
+    /// onnxruntime shared library, or the directory holding it (with the `ort-dylib` feature)
+    #[arg(long, global = true, value_name = "ORT_LIBRARY")]
+    onnxruntime: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    speak: Option<SpeakArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Synthesize speech (the default when no subcommand is given)
+    Speak(Box<SpeakArgs>),
+    /// Synthesize a CSV file of `id,text` lines into one WAV file per line
+    Batch(batch::BatchArgs),
+    /// Tell whether WAV files were synthesized with the watermark of a key
+    DetectWatermark(watermark::DetectArgs),
+}
+
+#[derive(Args)]
+struct SpeakArgs {
+    /// Model config, a `.sonata` voice bundle, or the directory of a sherpa-onnx voice
     config: PathBuf,
     /// Input text file (default `stdin`)
     #[arg(short = 'f', long, value_name = "INPUT_FILE")]
     input_file: Option<PathBuf>,
-    /// Output file (default `stdout`)
-    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    /// Output file, or `-` for `stdout` (default `stdout`)
+    #[arg(short, long, visible_alias = "output", value_name = "OUTPUT_FILE")]
     output_file: Option<PathBuf>,
+    /// Encoding of the audio written to `stdout` (default `s16le`), which is written
+    /// chunk by chunk as it is synthesized, e.g. to pipe it into `aplay`, `sox` or
+    /// `ffmpeg`
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<StdoutFormat>,
+    /// Sample format of the output file: `s16`, `s24`, `f32`, or `mulaw` or `alaw` for
+    /// telephony, which are written at 8 kHz (default `s16`)
+    #[arg(long, requires = "output_file")]
+    sample_format: Option<WaveSampleFormat>,
+    /// Write a stereo file, with the voice panned from `-1.0` (left) to `1.0` (right)
+    #[arg(long, requires = "output_file", allow_negative_numbers = true)]
+    pan: Option<f32>,
+    /// Pan of one speaker of a multi-speaker voice, as `SPEAKER_ID=PAN` (implies stereo)
+    #[arg(long, requires = "output_file", value_parser = parse_speaker_pan)]
+    speaker_pan: Vec<(i64, f32)>,
+    /// Keep at most this much audio in memory while writing the output file, and the
+    /// rest in a temporary file (e.g. for books on small devices)
+    #[arg(long, requires = "output_file", value_name = "MEGABYTES")]
+    memory_limit: Option<usize>,
+    /// Save the progress of the output file to this checkpoint, and resume from it if the
+    /// same job was interrupted
+    #[arg(
+        long,
+        requires = "output_file",
+        conflicts_with = "report",
+        value_name = "CHECKPOINT_FILE"
+    )]
+    checkpoint: Option<PathBuf>,
+    /// Also write a Praat TextGrid of the words and phones of the output file, timed by
+    /// the durations the voice predicted (streaming voices only)
+    #[arg(
+        long,
+        requires = "output_file",
+        conflicts_with_all = ["checkpoint", "memory_limit", "report"],
+        value_name = "TEXTGRID_FILE"
+    )]
+    textgrid: Option<PathBuf>,
+    /// Write numbered files (`book-001.wav`, ...) and their index (`book.json`) instead of
+    /// the output file: one per `sentence`, one per `chapter` (at Markdown headings and
+    /// lines starting with "Chapter"), or one per maximum duration, e.g. `5m`
+    #[arg(
+        long,
+        requires = "output_file",
+        conflicts_with_all = ["checkpoint", "textgrid", "report"],
+        value_name = "POLICY"
+    )]
+    split: Option<SplitPolicy>,
+    /// Read plain text from `stdin`, one utterance per line, and stream the audio as
+    /// lines arrive (e.g. `tail -f app.log | sonata speak voice.json --stdin | aplay ...`)
+    #[arg(long, conflicts_with = "input_file")]
+    stdin: bool,
+    /// Play the audio on the default output device instead of writing it to `stdout`
+    #[cfg(feature = "playback")]
+    #[arg(long, conflicts_with_all = ["output_file", "format"])]
+    play: bool,
     /// Synthesis mode (default `Lazy`)
     #[arg(long)]
     mode: Option<SynthesisMode>,
@@ -55,9 +172,21 @@ struct Cli {
     /// Piper noise width (default `model_default from config file`)
     #[arg(long)]
     noise_w: Option<f32>,
+    /// Vary the noise and length scales of each sentence by up to these fractions, as
+    /// `NOISE,LENGTH`, e.g. `0.1,0.05` for less monotonous audiobooks (default
+    /// `model_default from config file`)
+    #[arg(long, value_name = "NOISE,LENGTH", value_parser = parse_prosody_variation)]
+    prosody_variation: Option<(f32, f32)>,
+    /// Seed of the prosody variation. Other seeds vary the same text otherwise
+    #[arg(long, value_name = "SEED")]
+    prosody_seed: Option<u64>,
     /// Speaking rate [0 - 100] (default `50`)
     #[arg(long)]
     rate: Option<u8>,
+    /// Speak this many times faster [0.5 - 3.0], by the voice's own speaking rate as far
+    /// as it sounds natural and by time-stretching beyond that
+    #[arg(long, value_name = "X")]
+    rate_multiplier: Option<f32>,
     /// Speech pitch [0 - 100] (default `50`)
     #[arg(long)]
     pitch: Option<u8>,
@@ -73,6 +202,149 @@ struct Cli {
     /// Number of mel frames to use for padding current chunk (improves naturalness)
     #[arg(long)]
     chunk_padding: Option<usize>,
+    /// Number of audio samples shared by consecutive chunks, faded into each other
+    /// (default `256`, at most twice the padding)
+    #[arg(long, value_name = "SAMPLES")]
+    chunk_overlap: Option<usize>,
+    /// Fade applied where chunks overlap: `hann`, `linear` or `none` (default `hann`)
+    #[arg(long, value_name = "WINDOW")]
+    overlap_window: Option<OverlapWindow>,
+    /// Target time to first audio (in milliseconds) in realtime mode. Chunks then grow with
+    /// the measured decoding speed, and `--chunk-size` is ignored
+    #[arg(long, value_name = "MILLISECONDS")]
+    latency_target: Option<u64>,
+    /// Number of chunks decoded together in realtime mode (default `1`). Higher values
+    /// speed up streaming on GPUs
+    #[arg(long, value_name = "CHUNKS")]
+    decoder_batch_size: Option<usize>,
+    /// JSON file of text replacement rules, applied before phonemization
+    #[arg(long, value_name = "REPLACEMENTS_FILE")]
+    replacements: Option<PathBuf>,
+    /// Normalize emoji, URLs, dates, currencies and acronyms before phonemization
+    #[arg(long)]
+    normalize: bool,
+    /// Embed an inaudible watermark keyed by the secret in this file, to recognize the
+    /// audio later with `sonata detect-watermark`. Resampled files (`--sample-format
+    /// mulaw` or `alaw`) lose it
+    #[arg(long, value_name = "KEY_FILE")]
+    watermark_key_file: Option<PathBuf>,
+    /// Report per-sentence phonemes, inference time, audio duration and RTF
+    #[arg(long, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+    /// Append the report to this file (default `stdout`, unless audio is written to `stdout`)
+    #[arg(long, value_name = "REPORT_FILE", requires = "report")]
+    report_file: Option<PathBuf>,
+}
+
+impl SpeakArgs {
+    /// Take an output file of `-` to mean `stdout`, and reject the options that don't
+    /// apply to where the audio goes
+    fn check_output(&mut self) -> anyhow::Result<()> {
+        if self.output_file.as_deref() == Some(Path::new("-")) {
+            self.output_file = None;
+            let file_options = [
+                ("--sample-format", self.sample_format.is_some()),
+                ("--pan", self.pan.is_some()),
+                ("--speaker-pan", !self.speaker_pan.is_empty()),
+                ("--memory-limit", self.memory_limit.is_some()),
+                ("--checkpoint", self.checkpoint.is_some()),
+                ("--textgrid", self.textgrid.is_some()),
+                ("--split", self.split.is_some()),
+            ];
+            if let Some((option, _)) = file_options.iter().find(|(_, is_set)| *is_set) {
+                anyhow::bail!("`{}` requires an output file other than `-`", option);
+            }
+        }
+        if self.output_file.is_some() {
+            if self.stdin {
+                anyhow::bail!("`--stdin` writes the audio to `stdout`, not to an output file");
+            }
+            if self.format.is_some() {
+                anyhow::bail!("`--format` applies to `stdout`, use `--sample-format` for files");
+            }
+        }
+        Ok(())
+    }
+    fn channel_layout(&self) -> ChannelLayout {
+        if self.pan.is_none() && self.speaker_pan.is_empty() {
+            return ChannelLayout::Mono;
+        }
+        let mut panning = StereoPanning::new(self.pan.unwrap_or(0.0));
+        for (speaker, pan) in self.speaker_pan.iter() {
+            panning = panning.with_speaker_pan(*speaker, *pan);
+        }
+        ChannelLayout::Stereo(panning)
+    }
+    fn chunk_overlap(&self) -> Option<ChunkOverlap> {
+        if self.chunk_overlap.is_none() && self.overlap_window.is_none() {
+            return None;
+        }
+        let default = ChunkOverlap::default();
+        Some(ChunkOverlap {
+            samples: self.chunk_overlap.unwrap_or(default.samples),
+            window: self.overlap_window.unwrap_or(default.window),
+        })
+    }
+    /// The voice's prosody variation, with the bounds and seed given on the command line
+    fn prosody_variation(&self, voice: Option<ProsodyVariation>) -> Option<ProsodyVariation> {
+        if self.prosody_variation.is_none() && self.prosody_seed.is_none() {
+            return voice;
+        }
+        let mut variation = voice.unwrap_or_default();
+        if let Some((noise_scale, length_scale)) = self.prosody_variation {
+            variation.noise_scale = noise_scale;
+            variation.length_scale = length_scale;
+        }
+        if let Some(seed) = self.prosody_seed {
+            variation.seed = seed;
+        }
+        Some(variation)
+    }
+    fn synthesis_request(&self, text: String) -> SynthesisRequest {
+        SynthesisRequest {
+            text,
+            mode: self.mode.clone(),
+            speaker_id: self.speaker_id,
+            length_scale: self.length_scale,
+            noise_scale: self.noise_scale,
+            noise_w: self.noise_w,
+            rate: self.rate,
+            volume: self.volume,
+            pitch: self.pitch,
+            appended_silence_ms: self.silence,
+            chunk_size: self.chunk_size,
+            chunk_padding: self.chunk_padding,
+        }
+    }
+}
+
+fn parse_speaker_pan(arg: &str) -> Result<(i64, f32), String> {
+    let (speaker, pan) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `SPEAKER_ID=PAN`, got `{}`", arg))?;
+    let speaker = speaker
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid speaker id: {}", e))?;
+    let pan = pan
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid pan: {}", e))?;
+    Ok((speaker, pan))
+}
+
+fn parse_prosody_variation(arg: &str) -> Result<(f32, f32), String> {
+    let (noise_scale, length_scale) = arg
+        .split_once(',')
+        .ok_or_else(|| format!("Expected `NOISE,LENGTH`, got `{}`", arg))?;
+    let parse = |fraction: &str| match fraction.trim().parse::<f32>() {
+        Ok(fraction) if (0.0..=MAX_PROSODY_VARIATION).contains(&fraction) => Ok(fraction),
+        _ => Err(format!(
+            "Invalid variation `{}`: expected a fraction between 0 and {}",
+            fraction, MAX_PROSODY_VARIATION
+        )),
+    };
+    Ok((parse(noise_scale)?, parse(length_scale)?))
 }
 
 #[derive(Deserialize, Default)]
@@ -98,6 +370,7 @@ impl SynthesisRequest {
             length_scale: self.length_scale.unwrap_or(default_config.length_scale),
             noise_scale: self.noise_scale.unwrap_or(default_config.noise_scale),
             noise_w: self.noise_w.unwrap_or(default_config.noise_w),
+            prosody_variation: default_config.prosody_variation,
         }
     }
     fn as_audio_output_config(&self) -> AudioOutputConfig {
@@ -106,6 +379,66 @@ impl SynthesisRequest {
             pitch: self.pitch,
             volume: self.volume,
             appended_silence_ms: self.appended_silence_ms,
+            time_stretch: None,
+            gain_db: None,
+            rate_multiplier: None,
+            trim_leading_silence: false,
+            trim_trailing_silence: false,
+        }
+    }
+}
+
+/// Where synthesized audio is written
+enum AudioSink {
+    /// `stdout`, in the given encoding
+    Stdout(StdoutFormat),
+    #[cfg(feature = "playback")]
+    Device {
+        // Dropping the stream stops playback
+        _stream: rodio::OutputStream,
+        sink: rodio::Sink,
+        sample_rate: u32,
+        num_channels: u16,
+    },
+}
+
+impl AudioSink {
+    #[cfg(feature = "playback")]
+    fn output_device(synth: &SonataSpeechSynthesizer) -> anyhow::Result<Self> {
+        let audio_info = synth.audio_output_info()?;
+        let (stream, handle) = rodio::OutputStream::try_default()?;
+        let sink = rodio::Sink::try_new(&handle)?;
+        Ok(Self::Device {
+            _stream: stream,
+            sink,
+            sample_rate: audio_info.sample_rate as u32,
+            num_channels: audio_info.num_channels as u16,
+        })
+    }
+    fn write(&self, audio: AudioSamples) -> anyhow::Result<()> {
+        match self {
+            Self::Stdout(format) => write_to_stdout(&format.encode(&audio)),
+            #[cfg(feature = "playback")]
+            Self::Device {
+                sink,
+                sample_rate,
+                num_channels,
+                ..
+            } => {
+                sink.append(rodio::buffer::SamplesBuffer::new(
+                    *num_channels,
+                    *sample_rate,
+                    audio.into_vec(),
+                ));
+                Ok(())
+            }
+        }
+    }
+    /// Wait until all the audio has been played
+    fn finish(&self) {
+        #[cfg(feature = "playback")]
+        if let Self::Device { sink, .. } = self {
+            sink.sleep_until_end();
         }
     }
 }
@@ -124,43 +457,96 @@ fn get_synthesis_request_from_stdin() -> anyhow::Result<SynthesisRequest> {
 }
 
 fn process_synthesis_request(
-    args: &Cli,
+    args: &SpeakArgs,
     synth: &SonataSpeechSynthesizer,
     default_synth_config: &PiperSynthesisConfig,
     req: SynthesisRequest,
+    sink: &AudioSink,
 ) -> anyhow::Result<()> {
+    let started = Instant::now();
     synth.set_fallback_synthesis_config(&req.as_piper_synth_config(default_synth_config))?;
     let output_config = Some(req.as_audio_output_config());
+    let mut report = match args.report {
+        Some(_) => Some(SynthesisReport::new(
+            req.text.clone(),
+            synth.phonemize(&req.text)?.to_vec(),
+        )),
+        None => None,
+    };
     if let Some(output_file) = args.output_file.as_ref() {
         if req.mode.is_some() {
             log::warn!("Synthesis mode has no effect when output-file is set");
         }
-        synth.synthesize_to_file(output_file, req.text, output_config)?;
-        return Ok(());
-    }
-    match req.mode.unwrap_or_default() {
-        SynthesisMode::Lazy => {
-            let stream = synth
-                .synthesize_lazy(req.text, output_config)?
-                .map(|res| res.map(|aud| aud.samples));
-            consume_stream(stream)?
-        }
-        SynthesisMode::Parallel => {
-            let stream = synth
-                .synthesize_parallel(req.text, output_config)?
-                .map(|res| res.map(|aud| aud.samples));
-            consume_stream(stream)?
-        }
-        SynthesisMode::Realtime => {
-            let stream = synth.synthesize_streamed(
-                req.text,
-                output_config,
-                req.chunk_size.unwrap_or(100),
-                req.chunk_padding.unwrap_or(3),
+        if let Some(ref textgrid_file) = args.textgrid {
+            let (audio, textgrid) = synth.synthesize_with_textgrid(req.text, output_config)?;
+            synth.prepare_output(audio)?.save_to_file_as(
+                output_file,
+                synth.sample_format(),
+                None,
             )?;
-            consume_stream(stream)?
+            textgrid.save_to_file(textgrid_file)?;
+            return Ok(());
         }
-    };
+        match report {
+            Some(ref mut report) => {
+                let mut samples: Vec<f32> = Vec::new();
+                for result in synth.synthesize_parallel(req.text, output_config)? {
+                    let audio = result?;
+                    report.add_sentence(&audio);
+                    samples.append(&mut audio.into_vec());
+                }
+                let sample_rate = synth.audio_output_info()?.sample_rate;
+                let audio = Audio::new(samples.into(), sample_rate, None);
+                synth.prepare_output(audio)?.save_to_file_as(
+                    output_file,
+                    synth.sample_format(),
+                    None,
+                )?;
+            }
+            None => {
+                match args.checkpoint {
+                    Some(ref checkpoint) => synth.synthesize_to_file_with_checkpoint(
+                        output_file,
+                        req.text,
+                        output_config,
+                        checkpoint,
+                    )?,
+                    None => synth.synthesize_to_file(output_file, req.text, output_config)?,
+                };
+            }
+        }
+    } else {
+        match req.mode.unwrap_or_default() {
+            SynthesisMode::Lazy => {
+                let stream = synth.synthesize_lazy(req.text, output_config)?;
+                consume_stream(stream, sink, report.as_mut())?
+            }
+            SynthesisMode::Parallel => {
+                let stream = synth.synthesize_parallel(req.text, output_config)?;
+                consume_stream(stream, sink, report.as_mut())?
+            }
+            SynthesisMode::Realtime => {
+                let sample_rate = synth.audio_output_info()?.sample_rate;
+                let stream = synth.synthesize_streamed(
+                    req.text,
+                    output_config,
+                    req.chunk_size.unwrap_or(100),
+                    req.chunk_padding.unwrap_or(3),
+                )?;
+                for result in stream {
+                    let samples = result?;
+                    if let Some(ref mut report) = report {
+                        report.add_chunk(samples.len(), sample_rate);
+                    }
+                    sink.write(samples)?;
+                }
+            }
+        };
+    }
+    if let (Some(mut report), Some(format)) = (report, args.report) {
+        report.finish(started.elapsed().as_secs_f32() * 1000.0);
+        report.write(format, args.report_file.as_deref())?;
+    }
     Ok(())
 }
 
@@ -172,64 +558,168 @@ fn write_to_stdout(data: &[u8]) -> anyhow::Result<()> {
 }
 
 #[inline(always)]
-fn consume_stream(stream: impl Iterator<Item = SonataResult<AudioSamples>>) -> anyhow::Result<()> {
+fn consume_stream(
+    stream: impl Iterator<Item = SonataResult<Audio>>,
+    sink: &AudioSink,
+    mut report: Option<&mut SynthesisReport>,
+) -> anyhow::Result<()> {
+    let mut stats = SynthesisStats::default();
     for result in stream {
         let audio = result?;
-        let wav_bytes = audio.as_wave_bytes();
-        write_to_stdout(&wav_bytes)?;
+        stats.add(&audio);
+        if let Some(ref mut report) = report {
+            report.add_sentence(&audio);
+        }
+        sink.write(audio.samples)?;
+    }
+    log::info!("Synthesized {} sentences: {}", stats.num_sentences, stats);
+    Ok(())
+}
+
+/// Speak each line of `stdin` as soon as it is read, until `stdin` is closed
+fn speak_stdin_lines(
+    args: &SpeakArgs,
+    synth: &SonataSpeechSynthesizer,
+    default_synth_config: &PiperSynthesisConfig,
+    sink: &AudioSink,
+) -> anyhow::Result<()> {
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req = args.synthesis_request(line);
+        if let Err(e) = process_synthesis_request(args, synth, default_synth_config, req, sink) {
+            // A closed pipe ends the session, a line that fails to synthesize doesn't
+            if e.downcast_ref::<io::Error>().is_some() {
+                return Err(e);
+            }
+            log::error!("Failed to speak line. Error: {:#}", e);
+        }
     }
     Ok(())
 }
 
-fn init_ort_environment() {
-    INIT_ORT_ENVIRONMENT.call_once(|| {
-        let execution_providers = [
+fn init_ort_environment(dylib_path: Option<&Path>) -> anyhow::Result<()> {
+    let options = InitOptions {
+        dylib_path: dylib_path.map(Path::to_path_buf),
+        execution_providers: vec![
             #[cfg(feature = "cuda")]
-            ort::ExecutionProviderDispatch::CUDA(Default::default()),
-            ort::ExecutionProviderDispatch::CPU(Default::default()),
-        ];
-        ort::init()
-            .with_name("sonata")
-            .with_execution_providers(execution_providers)
-            .commit()
-            .expect("Failed to initialize onnxruntime");
-    });
+            ExecutionProvider::Cuda { device_id: 0 },
+            ExecutionProvider::Cpu,
+        ],
+        ..Default::default()
+    };
+    Ok(sonata_synth::init(options)?)
+}
+
+/// Load the voice, with the user's replacement rules and text normalization if requested.
+/// Further options are left to the caller before building the synthesizer
+fn load_synthesizer(
+    config: &Path,
+    replacements: Option<&Path>,
+    normalize: bool,
+) -> anyhow::Result<SonataSpeechSynthesizerBuilder> {
+    let is_bundle = config
+        .extension()
+        .is_some_and(|ext| ext == sonata_piper::bundle::BUNDLE_EXTENSION);
+    let voice = if is_bundle {
+        sonata_piper::from_bundle(config)?
+    } else if sonata_piper::sherpa::is_sherpa_package(config) {
+        sonata_piper::from_sherpa_package(config)?
+    } else {
+        sonata_piper::from_config_path(config)?
+    };
+    let mut builder = SonataSpeechSynthesizer::builder().voice(voice);
+    log::info!("Using model config: `{}`", config.display());
+    if let Some(replacements_file) = replacements {
+        builder = builder.replacements(Arc::new(ReplacementDictionary::from_file(
+            replacements_file,
+        )?));
+    }
+    if normalize {
+        builder = builder.text_normalizer(Arc::new(TextNormalizer::standard()));
+    }
+    Ok(builder)
 }
 
 fn main() -> anyhow::Result<()> {
     enable_logging();
-    init_ort_environment();
 
-    let mut args = Cli::parse();
+    let cli = Cli::parse();
+    init_ort_environment(cli.onnxruntime.as_deref())?;
+    match cli.command {
+        Some(Command::Speak(args)) => speak(*args),
+        Some(Command::Batch(args)) => batch::run(args),
+        Some(Command::DetectWatermark(args)) => watermark::run(args),
+        None => speak(
+            cli.speak
+                .expect("Arguments are required when no subcommand is given"),
+        ),
+    }
+}
 
-    let synth = {
-        let voice = sonata_piper::from_config_path(&args.config)?;
-        SonataSpeechSynthesizer::new(voice)?
-    };
-    log::info!("Using model config: `{}`", args.config.display());
-    let default_synth_config: PiperSynthesisConfig = *synth
+fn speak(mut args: SpeakArgs) -> anyhow::Result<()> {
+    args.check_output()?;
+    let mut builder = load_synthesizer(&args.config, args.replacements.as_deref(), args.normalize)?
+        .sample_format(args.sample_format.unwrap_or_default())
+        .channel_layout(args.channel_layout());
+    if let Some(memory_limit) = args.memory_limit {
+        builder = builder.memory_limit(memory_limit * 1024 * 1024);
+    }
+    if let Some(chunk_overlap) = args.chunk_overlap() {
+        builder = builder.chunk_overlap(chunk_overlap);
+    }
+    if let Some(latency_target) = args.latency_target {
+        builder = builder.latency_target(Duration::from_millis(latency_target));
+    }
+    if let Some(batch_size) = args.decoder_batch_size {
+        builder = builder.decoder_batch_size(batch_size);
+    }
+    if let Some(rate) = args.rate_multiplier {
+        builder = builder.rate_multiplier(rate);
+    }
+    if let Some(ref split_policy) = args.split {
+        builder = builder.split_policy(split_policy.clone());
+    }
+    if let Some(ref key_file) = args.watermark_key_file {
+        builder = builder.watermark(watermark::load_watermark(key_file)?);
+    }
+    let synth = builder.build()?;
+    let mut default_synth_config: PiperSynthesisConfig = *synth
         .get_default_synthesis_config()?
         .downcast()
         .expect("Invalid default synthesis config. Expected Piper config.");
-    if let Some(ref input_filename) = args.input_file {
+    default_synth_config.prosody_variation =
+        args.prosody_variation(default_synth_config.prosody_variation);
+    #[cfg(feature = "playback")]
+    let sink = if args.play {
+        AudioSink::output_device(&synth)?
+    } else {
+        AudioSink::Stdout(args.format.unwrap_or_default())
+    };
+    #[cfg(not(feature = "playback"))]
+    let sink = AudioSink::Stdout(args.format.unwrap_or_default());
+    let audio_on_stdout = args.output_file.is_none() && matches!(sink, AudioSink::Stdout(_));
+    if args.report.is_some() && args.report_file.is_none() && audio_on_stdout {
+        anyhow::bail!("`--report` requires `--report-file` when audio is written to stdout");
+    }
+    if audio_on_stdout && args.format == Some(StdoutFormat::Wav) {
+        let audio_info = synth.audio_output_info()?;
+        write_to_stdout(&streaming_wave_header(
+            WaveSampleFormat::S16,
+            audio_info.sample_rate as u32,
+            audio_info.num_channels as u32,
+        ))?;
+    }
+    if args.stdin {
+        speak_stdin_lines(&args, &synth, &default_synth_config, &sink)?;
+    } else if let Some(ref input_filename) = args.input_file {
         let mut input_buffer = String::new();
         let mut file = File::open(input_filename)?;
         file.read_to_string(&mut input_buffer)?;
-        let req = SynthesisRequest {
-            text: input_buffer,
-            mode: args.mode.clone(),
-            speaker_id: args.speaker_id,
-            length_scale: args.length_scale,
-            noise_scale: args.noise_scale,
-            noise_w: args.noise_w,
-            rate: args.rate,
-            volume: args.volume,
-            pitch: args.pitch,
-            appended_silence_ms: args.silence,
-            chunk_size: args.chunk_size,
-            chunk_padding: args.chunk_padding,
-        };
-        process_synthesis_request(&args, &synth, &default_synth_config, req)?;
+        let req = args.synthesis_request(input_buffer);
+        process_synthesis_request(&args, &synth, &default_synth_config, req, &sink)?;
     } else {
         for i in 0.. {
             args.output_file = args.output_file.map(|file| {
@@ -247,7 +737,7 @@ fn main() -> anyhow::Result<()> {
             });
             match get_synthesis_request_from_stdin() {
                 Ok(req) => {
-                    process_synthesis_request(&args, &synth, &default_synth_config, req)?;
+                    process_synthesis_request(&args, &synth, &default_synth_config, req, &sink)?;
                     if let Some(ref file) = args.output_file {
                         log::info!("Wrote output to file: {}", file.display());
                     }
@@ -256,5 +746,6 @@ fn main() -> anyhow::Result<()> {
             };
         }
     }
+    sink.finish();
     Ok(())
 }
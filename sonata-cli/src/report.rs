@@ -0,0 +1,105 @@
+use serde::Serialize;
+use sonata_synth::{Audio, StageTimings};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// One JSON object per synthesis request (JSON lines)
+    Json,
+}
+
+#[derive(Serialize)]
+pub struct SentenceReport {
+    pub phonemes: String,
+    pub inference_ms: Option<f32>,
+    pub timings: StageTimings,
+    pub audio_duration_ms: f32,
+    pub real_time_factor: Option<f32>,
+}
+
+/// Timing metrics of one synthesis request
+#[derive(Serialize)]
+pub struct SynthesisReport {
+    pub text: String,
+    /// Empty in realtime mode, which produces chunks rather than sentences
+    pub sentences: Vec<SentenceReport>,
+    pub inference_ms: Option<f32>,
+    /// Sum of the timings of the sentences
+    pub timings: StageTimings,
+    pub audio_duration_ms: f32,
+    /// Wall-clock time from the start of the request until all audio was written
+    pub elapsed_ms: f32,
+    /// Elapsed time divided by audio duration
+    pub real_time_factor: f32,
+    #[serde(skip)]
+    sentence_phonemes: Vec<String>,
+}
+
+impl SynthesisReport {
+    pub fn new(text: String, sentence_phonemes: Vec<String>) -> Self {
+        Self {
+            text,
+            sentences: Vec::with_capacity(sentence_phonemes.len()),
+            inference_ms: None,
+            timings: StageTimings::default(),
+            audio_duration_ms: 0.0,
+            elapsed_ms: 0.0,
+            real_time_factor: 0.0,
+            sentence_phonemes,
+        }
+    }
+    /// Record the audio of the next sentence
+    pub fn add_sentence(&mut self, audio: &Audio) {
+        let phonemes = self
+            .sentence_phonemes
+            .get(self.sentences.len())
+            .cloned()
+            .unwrap_or_default();
+        if let Some(inference_ms) = audio.inference_ms() {
+            *self.inference_ms.get_or_insert(0.0) += inference_ms;
+        }
+        self.timings += audio.timings;
+        self.audio_duration_ms += audio.duration_ms();
+        self.sentences.push(SentenceReport {
+            phonemes,
+            inference_ms: audio.inference_ms(),
+            timings: audio.timings,
+            audio_duration_ms: audio.duration_ms(),
+            real_time_factor: audio.real_time_factor(),
+        });
+    }
+    /// Record a chunk of realtime audio
+    pub fn add_chunk(&mut self, num_samples: usize, sample_rate: usize) {
+        self.audio_duration_ms += (num_samples as f32 / sample_rate as f32) * 1000.0;
+    }
+    pub fn finish(&mut self, elapsed_ms: f32) {
+        self.elapsed_ms = elapsed_ms;
+        self.real_time_factor = if self.audio_duration_ms > 0.0 {
+            elapsed_ms / self.audio_duration_ms
+        } else {
+            0.0
+        };
+    }
+    /// Append the report to `report_file`, or write it to `stdout`
+    pub fn write(&self, format: ReportFormat, report_file: Option<&Path>) -> anyhow::Result<()> {
+        let mut line = match format {
+            ReportFormat::Json => serde_json::to_string(self)?,
+        };
+        line.push('\n');
+        match report_file {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(line.as_bytes())?,
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(line.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
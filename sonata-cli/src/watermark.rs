@@ -0,0 +1,55 @@
+//! `sonata detect-watermark`: tell whether WAV files were synthesized with a watermark.
+//!
+//! The key is read from a file, the same one given to `sonata speak --watermark-key-file`,
+//! so that it doesn't show in the process list or the shell history.
+
+use sonata_synth::{read_wave_file, Watermark};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args)]
+pub struct DetectArgs {
+    /// File holding the secret key of the watermark
+    #[arg(short, long, value_name = "KEY_FILE")]
+    key_file: PathBuf,
+    /// WAV files to look for the watermark in
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+/// The watermark keyed by the contents of `key_file`, without a trailing newline
+pub fn load_watermark(key_file: &Path) -> anyhow::Result<Watermark> {
+    let key = std::fs::read(key_file)?;
+    let key = key.trim_ascii_end();
+    if key.is_empty() {
+        anyhow::bail!("The watermark key file `{}` is empty", key_file.display());
+    }
+    Ok(Watermark::new(key))
+}
+
+/// Print one line per file with its score, and fail if any file is not marked
+pub fn run(args: DetectArgs) -> anyhow::Result<()> {
+    let watermark = load_watermark(&args.key_file)?;
+    let mut num_unmarked = 0;
+    for file in args.files.iter() {
+        let detection = watermark.detect(&read_wave_file(file)?);
+        println!(
+            "{}: {} (score {:.1})",
+            file.display(),
+            if detection.detected {
+                "watermarked"
+            } else {
+                "not watermarked"
+            },
+            detection.score
+        );
+        num_unmarked += usize::from(!detection.detected);
+    }
+    if num_unmarked > 0 {
+        anyhow::bail!(
+            "{} of {} files are not watermarked",
+            num_unmarked,
+            args.files.len()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+//! API-key authentication and per-key quotas.
+//!
+//! Enabled by pointing `SONATA_GRPC_API_KEYS_FILE` at a JSON file listing the keys:
+//!
+//! ```json
+//! [
+//!   {"name": "reader-app", "key": "secret", "requests_per_minute": 60, "characters_per_day": 200000}
+//! ]
+//! ```
+//!
+//! Clients send `authorization: Bearer <key>`. Requests without a valid key fail
+//! with `UNAUTHENTICATED`, and synthesis requests beyond a key's quotas fail with
+//! `RESOURCE_EXHAUSTED`. Both quotas are optional.
+//!
+//! Only the SHA-256 hashes of the keys are kept, and a request's key is looked up by its
+//! hash, so the time it takes doesn't tell how much of a valid key was guessed.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    name: String,
+    key: String,
+    requests_per_minute: Option<u32>,
+    characters_per_day: Option<u64>,
+}
+
+/// Usage of a key in the current quota windows
+struct Usage {
+    minute_start: Instant,
+    requests: u32,
+    day_start: Instant,
+    characters: u64,
+}
+
+struct KeyState {
+    name: String,
+    requests_per_minute: Option<u32>,
+    characters_per_day: Option<u64>,
+    usage: Mutex<Usage>,
+}
+
+/// An authenticated key, attached to the request's extensions by [`ApiKeys::interceptor`]
+#[derive(Clone)]
+pub struct ApiKey(Arc<KeyState>);
+
+impl ApiKey {
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+    /// Count a synthesis request of `num_characters` against the key's quotas
+    pub fn charge(&self, num_characters: usize) -> Result<(), Status> {
+        self.charge_at(num_characters as u64, Instant::now())
+    }
+    fn charge_at(&self, num_characters: u64, now: Instant) -> Result<(), Status> {
+        let state = &self.0;
+        let mut usage = state.usage.lock().unwrap();
+        if now.duration_since(usage.minute_start) >= MINUTE {
+            usage.minute_start = now;
+            usage.requests = 0;
+        }
+        if now.duration_since(usage.day_start) >= DAY {
+            usage.day_start = now;
+            usage.characters = 0;
+        }
+        if let Some(limit) = state.requests_per_minute {
+            if usage.requests >= limit {
+                let retry_after = MINUTE - now.duration_since(usage.minute_start);
+                return Err(Status::resource_exhausted(format!(
+                    "Request quota of {} per minute exceeded for key `{}`. Retry after {} seconds",
+                    limit,
+                    state.name,
+                    retry_after.as_secs() + 1
+                )));
+            }
+        }
+        if let Some(limit) = state.characters_per_day {
+            if usage.characters + num_characters > limit {
+                let retry_after = DAY - now.duration_since(usage.day_start);
+                return Err(Status::resource_exhausted(format!(
+                    "Character quota of {} per day exceeded for key `{}` ({} used). Retry after {} seconds",
+                    limit,
+                    state.name,
+                    usage.characters,
+                    retry_after.as_secs() + 1
+                )));
+            }
+        }
+        usage.requests += 1;
+        usage.characters += num_characters;
+        Ok(())
+    }
+}
+
+/// The keys, by the SHA-256 hash of the key
+pub struct ApiKeys(HashMap<[u8; 32], ApiKey>);
+
+impl ApiKeys {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read API keys file `{}`. Error: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Self::from_json(&contents)
+            .map_err(|e| format!("Invalid API keys file `{}`. Error: {}", path.display(), e))
+    }
+    fn from_json(json: &str) -> Result<Self, String> {
+        let entries: Vec<KeyEntry> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let now = Instant::now();
+        let mut keys = HashMap::new();
+        for entry in entries {
+            if entry.key.is_empty() {
+                return Err(format!("Empty key for `{}`", entry.name));
+            }
+            let state = KeyState {
+                name: entry.name,
+                requests_per_minute: entry.requests_per_minute,
+                characters_per_day: entry.characters_per_day,
+                usage: Mutex::new(Usage {
+                    minute_start: now,
+                    requests: 0,
+                    day_start: now,
+                    characters: 0,
+                }),
+            };
+            if keys
+                .insert(key_hash(&entry.key), ApiKey(Arc::new(state)))
+                .is_some()
+            {
+                return Err("Duplicate key".to_string());
+            }
+        }
+        Ok(Self(keys))
+    }
+    pub fn num_keys(&self) -> usize {
+        self.0.len()
+    }
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<ApiKey, Status> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing `authorization: Bearer` API key"))?;
+        self.0
+            .get(&key_hash(token.trim()))
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))
+    }
+    /// An interceptor authenticating every request, if keys are configured
+    pub fn interceptor(
+        keys: Option<Arc<Self>>,
+    ) -> impl FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, Status> + Clone {
+        move |mut request: tonic::Request<()>| {
+            if let Some(ref keys) = keys {
+                let key = keys.authenticate(request.metadata())?;
+                request.extensions_mut().insert(key);
+            }
+            Ok(request)
+        }
+    }
+}
+
+fn key_hash(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_keys() {
+        let keys = ApiKeys::from_json(
+            r#"[
+                {"name": "limited", "key": "abc", "requests_per_minute": 2, "characters_per_day": 100},
+                {"name": "unlimited", "key": "xyz"}
+            ]"#,
+        )
+        .unwrap();
+        let mut metadata = MetadataMap::new();
+        assert!(keys.authenticate(&metadata).is_err());
+        metadata.insert("authorization", "Bearer nope".parse().unwrap());
+        assert!(keys.authenticate(&metadata).is_err());
+        metadata.insert("authorization", "Bearer abc".parse().unwrap());
+        let key = keys.authenticate(&metadata).unwrap();
+        assert_eq!(key.name(), "limited");
+
+        let now = Instant::now();
+        assert!(key.charge_at(40, now).is_ok());
+        assert!(key.charge_at(70, now).is_err());
+        assert!(key.charge_at(50, now).is_ok());
+        // Third request within the minute
+        assert!(key.charge_at(1, now).is_err());
+        assert!(key.charge_at(10, now + MINUTE).is_ok());
+        assert!(key.charge_at(1, now + MINUTE).is_err());
+        assert!(key.charge_at(100, now + DAY).is_ok());
+
+        metadata.insert("authorization", "Bearer xyz".parse().unwrap());
+        let key = keys.authenticate(&metadata).unwrap();
+        assert!((0..100).all(|_| key.charge_at(1000, now).is_ok()));
+    }
+}
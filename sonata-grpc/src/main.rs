@@ -1,11 +1,21 @@
+// Handlers and interceptors report errors as `tonic::Status`, which is large
+#![allow(clippy::result_large_err)]
+
+use auth::{ApiKey, ApiKeys};
 use grpc::sonata_grpc_server::{SonataGrpc, SonataGrpcServer};
-use sonata_core::{SonataError, SonataModel, SonataResult};
+use metrics::Metrics;
+use preload::{Placement, PreloadTarget, PreloadVoice};
+use scheduler::{InferenceScheduler, SchedulerLimits};
+use sonata_core::{
+    AudioSamples, MemoryUsage, SonataError, SonataModel, SonataResult, TELEPHONY_SAMPLE_RATE,
+};
 use sonata_synth::{AudioOutputConfig, SonataSpeechStreamLazy, SonataSpeechSynthesizer};
 use sonata_piper::PiperSynthesisConfig;
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Server;
@@ -16,6 +26,15 @@ type SonataGrpcResult<T> = Result<T, SonataGrpcError>;
 
 const DEFAULT_SONATA_GRPC_SERVER_PORT: u16 = 49314;
 const VOICE_ID_REDUCTION_FACTOR: u64 = 10000000000000;
+const DEFAULT_VOICES_WATCH_INTERVAL_SECS: u64 = 5;
+const DEFAULT_STREAM_BUFFER_DEPTH: usize = 8;
+
+mod auth;
+mod marytts;
+mod metrics;
+mod preload;
+mod scheduler;
+mod voice_dir;
 
 pub mod grpc {
     tonic::include_proto!("sonata_grpc");
@@ -25,15 +44,27 @@ pub mod grpc {
 enum SonataGrpcError {
     SonataError(SonataError),
     VoiceNotFound(String),
+    NoVoicesDirectory,
 }
 
-impl std::error::Error for SonataGrpcError {}
+impl std::error::Error for SonataGrpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SonataGrpcError::SonataError(e) => e.source(),
+            _ => None,
+        }
+    }
+}
 
 impl std::fmt::Display for SonataGrpcError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SonataGrpcError::SonataError(e) => e.fmt(f),
             SonataGrpcError::VoiceNotFound(msg) => write!(f, "{}", msg),
+            SonataGrpcError::NoVoicesDirectory => write!(
+                f,
+                "No voices directory configured. Set `SONATA_GRPC_VOICES_DIR` to enable reloading"
+            ),
         }
     }
 }
@@ -52,68 +83,302 @@ impl From<SonataGrpcError> for Status {
                     Status::aborted(msg)
                 }
                 SonataError::OperationError(msg) => Status::unknown(msg),
+                SonataError::Caused(..) => match sonata_error.without_cause() {
+                    SonataError::OperationError(_) => {
+                        Status::unknown(sonata_error.message_with_causes())
+                    }
+                    _ => Status::aborted(sonata_error.message_with_causes()),
+                },
             },
             SonataGrpcError::VoiceNotFound(msg) => Status::not_found(msg),
+            SonataGrpcError::NoVoicesDirectory => Status::failed_precondition(other.to_string()),
         }
     }
 }
 
-struct Voice(Arc<SonataSpeechSynthesizer>);
+/// Derive the ID of a voice from the canonical path of its config file
+fn voice_id(config_path: &Path) -> String {
+    let voice_path = config_path.to_string_lossy();
+    (xxh3_64(voice_path.as_bytes()) / VOICE_ID_REDUCTION_FACTOR).to_string()
+}
+
+struct Voice {
+    /// Requests hold their own reference, so they finish on the old synthesizer when
+    /// the voice is reloaded or unloaded
+    synth: Arc<SonataSpeechSynthesizer>,
+    /// Canonical path of the config file
+    config_path: PathBuf,
+    modified: Option<SystemTime>,
+}
 
 impl Voice {
-    fn new(model: Arc<dyn SonataModel + Send + Sync>) -> SonataResult<Self> {
-        let synth = Arc::new(SonataSpeechSynthesizer::new(model)?);
-        Ok(Self(synth))
+    fn load(
+        config_path: PathBuf,
+        stream_buffer_depth: usize,
+        placement: Option<&Placement>,
+    ) -> SonataResult<Self> {
+        let modified = voice_dir::modified(&config_path);
+        let piper_model = match placement {
+            Some(placement) => placement.load(|| sonata_piper::from_config_path(&config_path))?,
+            None => sonata_piper::from_config_path(&config_path)?,
+        };
+        let synth = SonataSpeechSynthesizer::builder()
+            .voice(piper_model)
+            .stream_buffer_depth(stream_buffer_depth)
+            .build()?;
+        let synth = Arc::new(synth);
+        Ok(Self {
+            synth,
+            config_path,
+            modified,
+        })
     }
     fn model_ref(&self) -> &dyn SonataModel {
         self.synth_ref()
     }
     fn synth_ref(&self) -> &SonataSpeechSynthesizer {
-        self.0.as_ref()
+        self.synth.as_ref()
+    }
+}
+
+/// Identify the client of a request for fair scheduling: its API key if
+/// authenticated, then the `x-client-id` metadata, then the client's IP address
+fn client_id<T>(request: &Request<T>) -> String {
+    if let Some(key) = request.extensions().get::<ApiKey>() {
+        return format!("key:{}", key.name());
+    }
+    if let Some(client_id) = request
+        .metadata()
+        .get("x-client-id")
+        .and_then(|value| value.to_str().ok())
+    {
+        return client_id.to_string();
+    }
+    match request.remote_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => "anonymous".to_string(),
     }
 }
 
-struct SonataGrpcService(RwLock<HashMap<String, Voice>>);
+/// Count a synthesis request against the quotas of its API key, if any
+fn charge_quota(request: &Request<grpc::Utterance>) -> Result<(), Status> {
+    match request.extensions().get::<ApiKey>() {
+        Some(key) => key.charge(request.get_ref().text.chars().count()),
+        None => Ok(()),
+    }
+}
+
+struct SonataGrpcService {
+    voices: RwLock<HashMap<String, Voice>>,
+    scheduler: InferenceScheduler,
+    metrics: Arc<Metrics>,
+    /// Directory whose voices are kept loaded, see `_reload_voices`
+    voices_dir: Option<PathBuf>,
+    /// Voice files that failed to load, with their modification time and the error,
+    /// so that they are retried only once they change. Also serializes reloads of the
+    /// voices directory
+    failed_voice_files: Mutex<HashMap<PathBuf, (Option<SystemTime>, String)>>,
+    /// Number of chunks or sentences buffered ahead of a slow client
+    stream_buffer_depth: usize,
+    /// Devices of the voices placed by the preload manifest, by canonical config path
+    placements: HashMap<PathBuf, Placement>,
+    /// Devices of the other voices, if not those of onnxruntime's environment
+    default_placement: Option<Placement>,
+}
 
 impl SonataGrpcService {
-    fn new() -> Self {
-        Self(Default::default())
+    fn new(
+        scheduler: InferenceScheduler,
+        metrics: Arc<Metrics>,
+        voices_dir: Option<PathBuf>,
+        stream_buffer_depth: usize,
+        placements: HashMap<PathBuf, Placement>,
+        default_placement: Option<Placement>,
+    ) -> Self {
+        Self {
+            voices: Default::default(),
+            scheduler,
+            metrics,
+            voices_dir,
+            failed_voice_files: Mutex::new(HashMap::new()),
+            stream_buffer_depth,
+            placements,
+            default_placement,
+        }
+    }
+    /// Memory held by each loaded voice. Voices failing to report it are left out
+    fn _voice_memory_usage(&self) -> Vec<(String, MemoryUsage)> {
+        let voices = self.voices.read().unwrap();
+        Vec::from_iter(voices.iter().filter_map(|(voice_id, voice)| {
+            let usage = voice.model_ref().memory_usage().ok()?;
+            Some((voice_id.clone(), usage))
+        }))
+    }
+    fn _load_voice_file(&self, config_path: PathBuf) -> SonataResult<Voice> {
+        let placement = self
+            .placements
+            .get(&config_path)
+            .or(self.default_placement.as_ref());
+        Voice::load(config_path, self.stream_buffer_depth, placement)
     }
     fn _load_sonata_voice(&self, config_path: PathBuf) -> SonataGrpcResult<grpc::VoiceInfo> {
-        let voice_id = if config_path.is_file() {
-            let voice_path = config_path
-                .canonicalize()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
-            (xxh3_64(voice_path.as_bytes()) / VOICE_ID_REDUCTION_FACTOR).to_string()
+        let config_path = if config_path.is_file() {
+            config_path.canonicalize().unwrap()
         } else {
             return Err(SonataGrpcError::VoiceNotFound(format!(
                 "Config file does not exists: `{}`",
                 config_path.display()
             )));
         };
-        if let Some(voice) = (self.0.read().unwrap()).get(&voice_id) {
+        let voice_id = voice_id(&config_path);
+        if let Some(voice) = (self.voices.read().unwrap()).get(&voice_id) {
             return self._get_voice_info(voice_id, voice.model_ref());
         }
-        let piper_model = sonata_piper::from_config_path(&config_path)?;
+        let voice = self._load_voice_file(config_path.clone())?;
         log::info!(
             "Loaded Vits voice from: `{}`. Voice ID: {}",
             config_path.display(),
             voice_id
         );
-        let voice = Voice::new(piper_model)?;
         let voice_info = self._get_voice_info(voice_id.clone(), voice.model_ref())?;
-        (self.0.write().unwrap()).insert(voice_id, voice);
+        self.metrics.voice_loaded(&voice_id, &config_path);
+        (self.voices.write().unwrap()).insert(voice_id, voice);
         Ok(voice_info)
     }
+    /// Bring the loaded voices in sync with the voices directory: load new voices,
+    /// reload voices whose files changed, and unload voices whose config was removed
+    fn _reload_voices(&self) -> SonataGrpcResult<grpc::VoicesReloaded> {
+        let Some(ref voices_dir) = self.voices_dir else {
+            return Err(SonataGrpcError::NoVoicesDirectory);
+        };
+        let mut failed_voice_files = self.failed_voice_files.lock().unwrap();
+        let voice_files = voice_dir::scan(voices_dir).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to read voices directory `{}`",
+                voices_dir.display()
+            ))
+            .caused_by(e)
+        })?;
+        failed_voice_files.retain(|config_path, _| {
+            voice_files
+                .iter()
+                .any(|file| file.config_path == *config_path)
+        });
+        let mut result = grpc::VoicesReloaded::default();
+        let canonical_dir = voices_dir.canonicalize().unwrap_or(voices_dir.clone());
+        let removed = Vec::from_iter(
+            (self.voices.read().unwrap())
+                .iter()
+                .filter(|(_, voice)| {
+                    voice.config_path.starts_with(&canonical_dir)
+                        && !voice_files
+                            .iter()
+                            .any(|file| file.config_path == voice.config_path)
+                })
+                .map(|(voice_id, _)| voice_id.clone()),
+        );
+        for voice_id in removed {
+            (self.voices.write().unwrap()).remove(&voice_id);
+            self.metrics.voice_unloaded(&voice_id);
+            log::info!("Unloaded voice: {}", voice_id);
+            result.unloaded_voice_ids.push(voice_id);
+        }
+        for file in voice_files {
+            let voice_id = voice_id(&file.config_path);
+            let is_loaded = match (self.voices.read().unwrap()).get(&voice_id) {
+                Some(voice) if voice.modified == file.modified => continue,
+                Some(_) => true,
+                None => false,
+            };
+            if let Some((modified, message)) = failed_voice_files.get(&file.config_path) {
+                if *modified == file.modified {
+                    result.errors.insert(
+                        file.config_path.to_string_lossy().into_owned(),
+                        message.clone(),
+                    );
+                    continue;
+                }
+            }
+            // Load outside the lock, so requests to other voices aren't blocked
+            match self._load_voice_file(file.config_path.clone()) {
+                Ok(voice) => {
+                    failed_voice_files.remove(&file.config_path);
+                    self.metrics.voice_loaded(&voice_id, &file.config_path);
+                    (self.voices.write().unwrap()).insert(voice_id.clone(), voice);
+                    log::info!(
+                        "{} voice from: `{}`. Voice ID: {}",
+                        if is_loaded { "Reloaded" } else { "Loaded" },
+                        file.config_path.display(),
+                        voice_id
+                    );
+                    if is_loaded {
+                        result.reloaded_voice_ids.push(voice_id);
+                    } else {
+                        result.loaded_voice_ids.push(voice_id);
+                    }
+                }
+                Err(e) => {
+                    // A changed voice keeps serving from its previous files
+                    let message = e.message_with_causes();
+                    log::error!(
+                        "Failed to load voice from: `{}`. Error: {}",
+                        file.config_path.display(),
+                        message
+                    );
+                    result.errors.insert(
+                        file.config_path.to_string_lossy().into_owned(),
+                        message.clone(),
+                    );
+                    failed_voice_files.insert(file.config_path, (file.modified, message));
+                }
+            }
+        }
+        Ok(result)
+    }
+    /// Load the voices of the preload manifest that the voices directory didn't, and
+    /// speak a sentence with each of them, so that their first request doesn't pay for
+    /// the lazy initialization of the inference sessions
+    fn _preload_voices(
+        &self,
+        preload_voices: Vec<(PathBuf, PreloadVoice)>,
+    ) -> SonataGrpcResult<()> {
+        for (config_path, preload_voice) in preload_voices {
+            let voice_id = self._load_sonata_voice(config_path)?.voice_id;
+            let synth = match (self.voices.read().unwrap()).get(&voice_id) {
+                Some(voice) => Arc::clone(&voice.synth),
+                None => continue,
+            };
+            let timer = Instant::now();
+            let warmup_result = synth
+                .synthesize_lazy(preload_voice.warmup_text, None)
+                .and_then(|mut stream| stream.try_for_each(|audio| audio.map(|_| ())));
+            match warmup_result {
+                Ok(()) => log::info!(
+                    "Warmed up voice {} on {} in {} ms",
+                    voice_id,
+                    match preload_voice.placement.or(self.default_placement.clone()) {
+                        Some(placement) => placement.to_string(),
+                        None => "the default device".to_string(),
+                    },
+                    timer.elapsed().as_millis()
+                ),
+                // The voice loaded, so it may still serve other texts
+                Err(e) => log::error!(
+                    "Failed to warm up voice {}. Error: {}",
+                    voice_id,
+                    e.message_with_causes()
+                ),
+            }
+        }
+        Ok(())
+    }
     fn _create_speech_synthesis_stream(
         &self,
         voice_id: &str,
         text: String,
         output_config: Option<AudioOutputConfig>,
     ) -> SonataGrpcResult<SonataSpeechStreamLazy> {
-        match (self.0.read().unwrap()).get(voice_id) {
+        match (self.voices.read().unwrap()).get(voice_id) {
             Some(voice) => Ok(voice.synth_ref().synthesize_lazy(text, output_config)?),
             None => Err(SonataGrpcError::VoiceNotFound(format!(
                 "A voice with the key `{}` has not been loaded",
@@ -196,7 +461,7 @@ impl SonataGrpcService {
         })
     }
     fn _get_synth_options(&self, voice_id: &str) -> SonataGrpcResult<grpc::SynthesisOptions> {
-        let voices = self.0.read().unwrap();
+        let voices = self.voices.read().unwrap();
         let voice = match voices.get(voice_id) {
             Some(voice) => voice,
             None => {
@@ -213,7 +478,7 @@ impl SonataGrpcService {
         voice_id: &str,
         synth_opts: grpc::SynthesisOptions,
     ) -> SonataGrpcResult<grpc::SynthesisOptions> {
-        let voices = self.0.read().unwrap();
+        let voices = self.voices.read().unwrap();
         let voice = match voices.get(voice_id) {
             Some(voice) => voice,
             None => {
@@ -237,9 +502,7 @@ impl SonataGrpcService {
             }
         };
         if let Some(sname) = synth_opts.speaker {
-            if let Some(sid) = model.speaker_name_to_id(&sname)? {
-                synth_config.speaker = Some(sid)
-            }
+            synth_config.speaker = Some(model.resolve_speaker(&sname)?);
         }
         if let Some(length_scale) = synth_opts.length_scale {
             synth_config.length_scale = length_scale;
@@ -280,7 +543,7 @@ impl SonataGrpc for SonataGrpcService {
         _request: Request<grpc::VoiceIdentifier>,
     ) -> Result<Response<grpc::VoiceInfo>, Status> {
         let voice_id = _request.into_inner().voice_id;
-        let voices = self.0.read().unwrap();
+        let voices = self.voices.read().unwrap();
         let voice = match voices.get(&voice_id) {
             Some(voice) => voice,
             None => {
@@ -322,34 +585,77 @@ impl SonataGrpc for SonataGrpcService {
         &self,
         _request: Request<grpc::Utterance>,
     ) -> Result<Response<Self::SynthesizeUtteranceStream>, Status> {
+        let mut request_metrics = self.metrics.start_request("synthesize_utterance");
+        if let Err(status) = charge_quota(&_request) {
+            request_metrics.finish(Err(&status));
+            return Err(status);
+        }
+        let client = client_id(&_request);
         let req = _request.into_inner();
+        let permit = match self.scheduler.acquire(&client, &req.voice_id).await {
+            Ok(permit) => permit,
+            Err(status) => {
+                request_metrics.finish(Err(&status));
+                return Err(status);
+            }
+        };
+        let encoding = req
+            .speech_args
+            .as_ref()
+            .map(|args| args.encoding())
+            .unwrap_or_default();
         let output_config = req.speech_args.map(|args| AudioOutputConfig {
             rate: args.rate.map(|i| i as u8),
             volume: args.volume.map(|i| i as u8),
             pitch: args.pitch.map(|i| i as u8),
             appended_silence_ms: args.appended_silence_ms,
+            time_stretch: None,
+            gain_db: args.gain_db,
+            rate_multiplier: args.rate_multiplier,
+            trim_leading_silence: args.trim_leading_silence,
+            trim_trailing_silence: args.trim_trailing_silence,
         });
+        let phonemize_timer = Instant::now();
         let sonata_stream =
-            self._create_speech_synthesis_stream(&req.voice_id, req.text, output_config)?;
-        let (tx, rx) = mpsc::channel(512);
+            match self._create_speech_synthesis_stream(&req.voice_id, req.text, output_config) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let status = Status::from(e);
+                    request_metrics.finish(Err(&status));
+                    return Err(status);
+                }
+            };
+        request_metrics.observe_stage("phonemize", phonemize_timer.elapsed());
+        let (tx, rx) = mpsc::channel(self.stream_buffer_depth);
         tokio::task::spawn_blocking(move || {
+            let _permit = permit;
             for wav_result in sonata_stream {
                 let wav = match wav_result {
                     Ok(wav) => wav,
                     Err(e) => {
-                        let err = Err(SonataGrpcError::from(e).into());
-                        tx.blocking_send(err).ok();
+                        let status = Status::from(SonataGrpcError::from(e));
+                        request_metrics.finish(Err(&status));
+                        tx.blocking_send(Err(status)).ok();
                         return;
                     }
                 };
+                if let Some(inference_ms) = wav.inference_ms() {
+                    request_metrics.observe_stage(
+                        "synthesize",
+                        Duration::from_secs_f32(inference_ms / 1000.0),
+                    );
+                }
+                request_metrics.add_audio(wav.duration_ms());
                 let synth_result = grpc::SynthesisResult {
-                    wav_samples: wav.as_wave_bytes(),
+                    wav_samples: encode_samples(&wav.samples, wav.info.sample_rate, encoding),
                     rtf: wav.real_time_factor().unwrap_or_default(),
                 };
                 if tx.blocking_send(Ok(synth_result)).is_err() {
+                    request_metrics.finish(Err(&Status::cancelled("Client went away")));
                     return;
                 }
             }
+            request_metrics.finish(Ok(()));
         });
         Ok(Response::new(ReceiverStream::new(rx)))
     }
@@ -358,56 +664,196 @@ impl SonataGrpc for SonataGrpcService {
         &self,
         _request: Request<grpc::Utterance>,
     ) -> Result<Response<Self::SynthesizeUtteranceRealtimeStream>, Status> {
+        let mut request_metrics = self.metrics.start_request("synthesize_utterance_realtime");
+        if let Err(status) = charge_quota(&_request) {
+            request_metrics.finish(Err(&status));
+            return Err(status);
+        }
+        let client = client_id(&_request);
         let req = _request.into_inner();
+        let permit = match self.scheduler.acquire(&client, &req.voice_id).await {
+            Ok(permit) => permit,
+            Err(status) => {
+                request_metrics.finish(Err(&status));
+                return Err(status);
+            }
+        };
+        let encoding = req
+            .speech_args
+            .as_ref()
+            .map(|args| args.encoding())
+            .unwrap_or_default();
         let output_config = req.speech_args.map(|args| AudioOutputConfig {
             rate: args.rate.map(|i| i as u8),
             volume: args.volume.map(|i| i as u8),
             pitch: args.pitch.map(|i| i as u8),
             appended_silence_ms: args.appended_silence_ms,
+            time_stretch: None,
+            gain_db: args.gain_db,
+            rate_multiplier: args.rate_multiplier,
+            trim_leading_silence: args.trim_leading_silence,
+            trim_trailing_silence: args.trim_trailing_silence,
         });
         let voice_id = &req.voice_id;
-        let voices = self.0.read().unwrap();
+        let voices = self.voices.read().unwrap();
         let voice = match voices.get(voice_id) {
             Some(voice) => voice,
             None => {
-                return Err(SonataGrpcError::VoiceNotFound(format!(
+                let status: Status = SonataGrpcError::VoiceNotFound(format!(
                     "A voice with the key `{}` has not been loaded",
                     voice_id
                 ))
-                .into())
+                .into();
+                request_metrics.finish(Err(&status));
+                return Err(status);
             }
         };
-        let synth = Arc::clone(&voice.0);
-        let (tx, rx) = mpsc::channel(512);
+        let synth = Arc::clone(&voice.synth);
+        let (tx, rx) = mpsc::channel(self.stream_buffer_depth);
         tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let phonemize_timer = Instant::now();
             let stream_result = synth.synthesize_streamed(req.text, output_config, 55, 3);
             let realtime_speech_stream = match stream_result {
                 Ok(stream) => stream,
                 Err(e) => {
-                    let err = Err(SonataGrpcError::from(e).into());
-                    tx.blocking_send(err).ok();
+                    let status = Status::from(SonataGrpcError::from(e));
+                    request_metrics.finish(Err(&status));
+                    tx.blocking_send(Err(status)).ok();
                     return;
                 }
             };
+            request_metrics.observe_stage("phonemize", phonemize_timer.elapsed());
+            let sample_rate = synth
+                .audio_output_info()
+                .map(|info| info.sample_rate)
+                .unwrap_or_default();
+            let mut stage = "first_chunk";
+            let mut chunk_timer = Instant::now();
             for wav_result in realtime_speech_stream {
                 let wav = match wav_result {
                     Ok(wav) => wav,
                     Err(e) => {
-                        let err = Err(SonataGrpcError::from(e).into());
-                        tx.blocking_send(err).ok();
+                        let status = Status::from(SonataGrpcError::from(e));
+                        request_metrics.finish(Err(&status));
+                        tx.blocking_send(Err(status)).ok();
                         return;
                     }
                 };
+                request_metrics.observe_stage(stage, chunk_timer.elapsed());
+                stage = "decode";
+                if sample_rate > 0 {
+                    request_metrics.add_audio(wav.len() as f32 / sample_rate as f32 * 1000.0);
+                }
                 let synth_result = grpc::WaveSamples {
-                    wav_samples: wav.as_wave_bytes(),
+                    wav_samples: encode_samples(&wav, sample_rate, encoding),
                 };
                 if tx.blocking_send(Ok(synth_result)).is_err() {
+                    request_metrics.finish(Err(&Status::cancelled("Client went away")));
                     return;
                 }
+                // Time spent waiting for the client doesn't count towards decoding
+                chunk_timer = Instant::now();
             }
+            request_metrics.finish(Ok(()));
         });
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+    async fn reload_voices(
+        &self,
+        _request: Request<grpc::Empty>,
+    ) -> Result<Response<grpc::VoicesReloaded>, Status> {
+        let result = tokio::task::block_in_place(|| self._reload_voices())?;
+        Ok(Response::new(result))
+    }
+}
+
+/// The bytes of `samples`, of a voice of `sample_rate`, in `encoding`
+fn encode_samples(
+    samples: &AudioSamples,
+    sample_rate: usize,
+    encoding: grpc::AudioEncoding,
+) -> Vec<u8> {
+    let encode = match encoding {
+        grpc::AudioEncoding::EncodingPcmS16le => return samples.as_wave_bytes(),
+        grpc::AudioEncoding::EncodingMulaw => sonata_core::linear_to_mulaw,
+        grpc::AudioEncoding::EncodingAlaw => sonata_core::linear_to_alaw,
+    };
+    let samples = samples.resample_antialiased(sample_rate, TELEPHONY_SAMPLE_RATE);
+    Vec::from_iter(samples.to_i16_vec().into_iter().map(encode))
+}
+
+/// Reload the voices directory every `interval`, so voices can be added, replaced
+/// and removed without restarting the server
+fn watch_voices_dir(service: Arc<SonataGrpcService>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately, and the initial load already happened
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let service = Arc::clone(&service);
+            match tokio::task::spawn_blocking(move || service._reload_voices()).await {
+                Ok(Err(e)) => log::error!("Failed to reload voices. Error: {}", e),
+                Err(e) => log::error!("Failed to reload voices. Error: {}", e),
+                Ok(Ok(_)) => {}
+            }
+        }
+    });
+}
+
+/// Find the config files of the voices of the preload manifest, looking voice ids up
+/// in the voices directory
+fn resolve_preload_voices(
+    preload_voices: Vec<PreloadVoice>,
+    voices_dir: Option<&Path>,
+) -> SonataGrpcResult<Vec<(PathBuf, PreloadVoice)>> {
+    let mut voice_files = None;
+    let mut resolved = Vec::with_capacity(preload_voices.len());
+    for preload_voice in preload_voices {
+        let config_path = match preload_voice.voice {
+            PreloadTarget::ConfigPath(ref config_path) => {
+                config_path.canonicalize().map_err(|_| {
+                    SonataGrpcError::VoiceNotFound(format!(
+                        "Config file does not exists: `{}`",
+                        config_path.display()
+                    ))
+                })?
+            }
+            PreloadTarget::VoiceId(ref id) => {
+                let Some(voices_dir) = voices_dir else {
+                    return Err(SonataGrpcError::NoVoicesDirectory);
+                };
+                if voice_files.is_none() {
+                    let files = voice_dir::scan(voices_dir).map_err(|e| {
+                        SonataError::FailedToLoadResource(format!(
+                            "Failed to read voices directory `{}`",
+                            voices_dir.display()
+                        ))
+                        .caused_by(e)
+                    })?;
+                    voice_files = Some(files);
+                }
+                let file = voice_files
+                    .iter()
+                    .flatten()
+                    .find(|file| voice_id(&file.config_path) == *id);
+                match file {
+                    Some(file) => file.config_path.clone(),
+                    None => {
+                        return Err(SonataGrpcError::VoiceNotFound(format!(
+                            "No voice with id `{}` in the voices directory `{}`",
+                            id,
+                            voices_dir.display()
+                        )))
+                    }
+                }
+            }
+        };
+        resolved.push((config_path, preload_voice));
+    }
+    Ok(resolved)
 }
 
 fn setup_logging() {
@@ -416,19 +862,14 @@ fn setup_logging() {
 }
 
 fn init_ort_environment() -> bool {
-    ort::init()
-        .with_name("sonata")
-        .with_execution_providers([ort::ExecutionProviderDispatch::CPU(Default::default())])
-        .commit()
-        .is_ok()
+    sonata_piper::init(Default::default()).is_ok()
 }
 
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     setup_logging();
 
-    if !init_ort_environment()  {
+    if !init_ort_environment() {
         log::error!("Could not initialize onnxruntime environment");
     }
 
@@ -437,8 +878,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or(DEFAULT_SONATA_GRPC_SERVER_PORT);
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
 
-    let service = SonataGrpcService::new();
-    let server = SonataGrpcServer::new(service);
+    let limits = SchedulerLimits::from_env();
+    log::info!(
+        "Running up to {} synthesis requests at once ({} per voice), queueing up to {}",
+        limits.max_concurrent,
+        limits.max_concurrent_per_voice,
+        limits.max_queued
+    );
+    let voices_dir = std::env::var_os("SONATA_GRPC_VOICES_DIR").map(PathBuf::from);
+    let stream_buffer_depth = std::env::var("SONATA_GRPC_STREAM_BUFFER_DEPTH")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .filter(|depth| *depth > 0)
+        .unwrap_or(DEFAULT_STREAM_BUFFER_DEPTH);
+    let preload_voices = match std::env::var_os("SONATA_GRPC_PRELOAD_MANIFEST") {
+        Some(manifest_path) => {
+            let preload_voices = preload::load_manifest(Path::new(&manifest_path))?;
+            resolve_preload_voices(preload_voices, voices_dir.as_deref())?
+        }
+        None => Vec::new(),
+    };
+    let placements =
+        HashMap::from_iter(preload_voices.iter().filter_map(|(config_path, voice)| {
+            Some((config_path.clone(), voice.placement.clone()?))
+        }));
+    // Voices without a placement of their own are replicated on these devices
+    let default_placement = match std::env::var("SONATA_GRPC_DEVICES") {
+        Ok(devices) => {
+            let dispatch = match std::env::var("SONATA_GRPC_DISPATCH") {
+                Ok(dispatch) => dispatch.parse()?,
+                Err(_) => Default::default(),
+            };
+            let devices = preload::parse_devices(&devices)?;
+            if devices.is_empty() {
+                None
+            } else {
+                let placement = Placement { devices, dispatch };
+                log::info!("Placing voices on {}", placement);
+                Some(placement)
+            }
+        }
+        Err(_) => None,
+    };
+    let metrics = Arc::new(Metrics::new()?);
+    let service = Arc::new(SonataGrpcService::new(
+        InferenceScheduler::new(limits),
+        Arc::clone(&metrics),
+        voices_dir.clone(),
+        stream_buffer_depth,
+        placements,
+        default_placement,
+    ));
+    if let Some(ref voices_dir) = voices_dir {
+        let loaded = service._reload_voices()?;
+        log::info!(
+            "Loaded {} voices from: `{}`",
+            loaded.loaded_voice_ids.len(),
+            voices_dir.display()
+        );
+        // `0` disables watching, leaving reloads to the `ReloadVoices` call
+        let interval = std::env::var("SONATA_GRPC_VOICES_WATCH_INTERVAL_SECS")
+            .ok()
+            .and_then(|val| val.parse().ok())
+            .unwrap_or(DEFAULT_VOICES_WATCH_INTERVAL_SECS);
+        if interval > 0 {
+            watch_voices_dir(Arc::clone(&service), Duration::from_secs(interval));
+        }
+    }
+    if !preload_voices.is_empty() {
+        let num_voices = preload_voices.len();
+        service._preload_voices(preload_voices)?;
+        log::info!("Preloaded {} voices of the preload manifest", num_voices);
+    }
+    if let Ok(metrics_port) = std::env::var("SONATA_GRPC_METRICS_PORT") {
+        let metrics_port: u16 = metrics_port.parse()?;
+        let metrics_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), metrics_port);
+        let service = Arc::clone(&service);
+        log::info!("Serving metrics at: http://{}/metrics", metrics_addr);
+        tokio::spawn(async move {
+            let render = move || {
+                let scheduler = &service.scheduler;
+                metrics.render(
+                    scheduler.num_queued(),
+                    scheduler.num_running(),
+                    &service._voice_memory_usage(),
+                )
+            };
+            if let Err(e) = metrics::serve(metrics_addr, render).await {
+                log::error!("Metrics server failed. Error: {}", e);
+            }
+        });
+    }
+    let api_keys = match std::env::var_os("SONATA_GRPC_API_KEYS_FILE") {
+        Some(keys_file) => {
+            let keys = ApiKeys::from_file(Path::new(&keys_file))?;
+            log::info!("Authentication enabled with {} API keys", keys.num_keys());
+            Some(Arc::new(keys))
+        }
+        None => None,
+    };
+    if let Ok(marytts_port) = std::env::var("SONATA_GRPC_MARYTTS_PORT") {
+        let marytts_port: u16 = marytts_port.parse()?;
+        let marytts_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), marytts_port);
+        let service = Arc::clone(&service);
+        let api_keys = api_keys.clone();
+        log::info!("Serving the MaryTTS API at: http://{}", marytts_addr);
+        tokio::spawn(async move {
+            if let Err(e) = marytts::serve(marytts_addr, service, api_keys).await {
+                log::error!("MaryTTS server failed. Error: {}", e);
+            }
+        });
+    }
+    let server = tonic::codegen::InterceptedService::new(
+        SonataGrpcServer::from_arc(service),
+        ApiKeys::interceptor(api_keys),
+    );
 
     log::info!("Starting Sonata GRPC server at address: {}", addr);
 
@@ -446,5 +1000,3 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
-
-
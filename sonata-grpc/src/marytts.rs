@@ -0,0 +1,432 @@
+//! A MaryTTS-compatible HTTP API, served on `SONATA_GRPC_MARYTTS_PORT`, for the
+//! home-automation and legacy applications that only speak MaryTTS.
+//!
+//! - `/process`, by `GET` or a `POST`ed form, speaks `INPUT_TEXT` and returns a WAV
+//!   file. The voice is `VOICE`, or else the best voice for `LOCALE`. Only text input
+//!   and WAV output are supported, and other parameters (e.g. effects) are ignored
+//! - `/voices` lists the voices, one `name locale gender type` line each
+//! - `/locales` lists the locales of the voices, one per line
+//! - `/version` names the server
+//!
+//! Voices are named after their config file, e.g. `en_US-amy-low`, and their ids are
+//! accepted as names too. With API keys enabled, requests need the same
+//! `authorization: Bearer <key>` header as gRPC calls, and count against its quotas.
+
+use crate::auth::ApiKeys;
+use crate::{SonataGrpcError, SonataGrpcService};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use sonata_core::{write_float_samples_to_buffer, Audio, SonataError, WaveSampleFormat};
+use std::convert::Infallible;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+
+/// The MaryTTS version whose API is emulated
+const MARYTTS_VERSION: &str = "5.2";
+
+/// A voice as MaryTTS clients see it
+#[derive(Clone, Debug, PartialEq)]
+struct MaryVoice {
+    id: String,
+    name: String,
+    locale: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ProcessRequest {
+    text: String,
+    voice: Option<String>,
+    locale: Option<String>,
+}
+
+/// Read the parameters of `/process`, from a query string or a form
+fn parse_process_request(params: &[u8]) -> Result<ProcessRequest, String> {
+    let mut text = None;
+    let mut request = ProcessRequest::default();
+    for (name, value) in form_urlencoded::parse(params) {
+        let unsupported = match name.as_ref() {
+            "INPUT_TEXT" => {
+                text = Some(value.into_owned());
+                continue;
+            }
+            "VOICE" if !value.is_empty() => {
+                request.voice = Some(value.into_owned());
+                continue;
+            }
+            "LOCALE" if !value.is_empty() => {
+                request.locale = Some(value.into_owned());
+                continue;
+            }
+            "INPUT_TYPE" => value != "TEXT",
+            "OUTPUT_TYPE" => value != "AUDIO",
+            "AUDIO" => !matches!(value.as_ref(), "WAVE_FILE" | "WAVE"),
+            _ => false,
+        };
+        if unsupported {
+            return Err(format!("Unsupported {} `{}`", name, value));
+        }
+    }
+    match text {
+        Some(text) if !text.trim().is_empty() => Ok(ProcessRequest { text, ..request }),
+        _ => Err("Missing INPUT_TEXT".to_string()),
+    }
+}
+
+/// The MaryTTS name of the voice with the config file `config_path`. Streaming voices
+/// with a plain `config.json` are named after their directory
+fn voice_name(config_path: &Path) -> String {
+    let filename = config_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let mut name = filename
+        .strip_suffix(".onnx.json")
+        .or_else(|| filename.strip_suffix(".json"))
+        .unwrap_or(&filename)
+        .to_string();
+    if name == "config" {
+        if let Some(dir_name) = config_path.parent().and_then(|dir| dir.file_name()) {
+            name = dir_name.to_string_lossy().into_owned();
+        }
+    }
+    // Names are separated by spaces in `/voices`
+    name.replace(char::is_whitespace, "_")
+}
+
+/// A language code as MaryTTS writes locales, e.g. `en-us` becomes `en_US`
+fn mary_locale(language: &str) -> String {
+    let mut parts = language.split(['-', '_']);
+    let mut locale = parts.next().unwrap_or_default().to_ascii_lowercase();
+    for part in parts {
+        locale.push('_');
+        if part.len() == 2 {
+            locale.push_str(&part.to_ascii_uppercase());
+        } else {
+            locale.push_str(part);
+        }
+    }
+    locale
+}
+
+/// The voice named `voice`, or else the first voice (by name) of `locale`, preferring
+/// an exact match to the same language, or else the first voice
+fn select_voice<'a>(
+    voices: &'a [MaryVoice],
+    voice: Option<&str>,
+    locale: Option<&str>,
+) -> Result<&'a MaryVoice, String> {
+    if let Some(voice) = voice {
+        return voices
+            .iter()
+            .find(|candidate| candidate.name == voice || candidate.id == voice)
+            .ok_or_else(|| format!("No voice named `{}`", voice));
+    }
+    let Some(locale) = locale else {
+        return voices
+            .first()
+            .ok_or_else(|| "No voices are loaded".to_string());
+    };
+    let locale = mary_locale(locale);
+    let language = |locale: &str| locale.split('_').next().unwrap_or_default().to_string();
+    voices
+        .iter()
+        .find(|candidate| candidate.locale.as_deref() == Some(locale.as_str()))
+        .or_else(|| {
+            voices.iter().find(|candidate| {
+                candidate
+                    .locale
+                    .as_deref()
+                    .is_some_and(|other| language(other) == language(&locale))
+            })
+        })
+        .ok_or_else(|| format!("No voice for locale `{}`", locale))
+}
+
+fn http_status(status: &Status) -> StatusCode {
+    match status.code() {
+        Code::InvalidArgument | Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn text_response(status: StatusCode, body: String) -> hyper::http::Result<Response<Body>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(body))
+}
+
+impl SonataGrpcService {
+    /// The loaded voices, sorted by name
+    fn marytts_voices(&self) -> Vec<MaryVoice> {
+        let voices = self.voices.read().unwrap();
+        let mut mary_voices = Vec::from_iter(voices.iter().map(|(voice_id, voice)| {
+            MaryVoice {
+                id: voice_id.clone(),
+                name: voice_name(&voice.config_path),
+                locale: voice
+                    .model_ref()
+                    .get_language()
+                    .ok()
+                    .flatten()
+                    .map(|language| mary_locale(&language)),
+            }
+        }));
+        mary_voices.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        mary_voices
+    }
+    /// Speak `text` with the voice `voice_id` into the bytes of a WAV file
+    async fn marytts_process(
+        self: Arc<Self>,
+        client: String,
+        voice_id: String,
+        text: String,
+    ) -> Result<Vec<u8>, Status> {
+        let mut request_metrics = self.metrics.start_request("marytts_process");
+        let permit = match self.scheduler.acquire(&client, &voice_id).await {
+            Ok(permit) => permit,
+            Err(status) => {
+                request_metrics.finish(Err(&status));
+                return Err(status);
+            }
+        };
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let synthesize = || {
+                let stream = self._create_speech_synthesis_stream(&voice_id, text, None)?;
+                let mut merged: Option<Audio> = None;
+                for audio in stream {
+                    let audio = audio?;
+                    if let Some(inference_ms) = audio.inference_ms() {
+                        request_metrics.observe_stage(
+                            "synthesize",
+                            Duration::from_secs_f32(inference_ms / 1000.0),
+                        );
+                    }
+                    request_metrics.add_audio(audio.duration_ms());
+                    match merged {
+                        Some(ref mut merged) => merged.samples.merge(audio.samples),
+                        None => merged = Some(audio),
+                    }
+                }
+                let audio = merged.ok_or_else(|| {
+                    SonataError::OperationError("The text has nothing to speak".to_string())
+                })?;
+                let mut wav_bytes = Cursor::new(Vec::new());
+                write_float_samples_to_buffer(
+                    &mut wav_bytes,
+                    &audio.samples,
+                    audio.info.sample_rate as u32,
+                    audio.info.num_channels as u32,
+                    WaveSampleFormat::S16,
+                )
+                .map_err(SonataError::from)?;
+                Ok::<_, SonataGrpcError>(wav_bytes.into_inner())
+            };
+            let result = synthesize().map_err(Status::from);
+            request_metrics.finish(result.as_ref().map(|_| ()));
+            result
+        })
+        .await
+        .unwrap_or_else(|e| Err(Status::internal(e.to_string())))
+    }
+}
+
+async fn handle(
+    service: Arc<SonataGrpcService>,
+    api_keys: Option<Arc<ApiKeys>>,
+    remote_addr: SocketAddr,
+    request: Request<Body>,
+) -> hyper::http::Result<Response<Body>> {
+    let path = request.uri().path().to_string();
+    if !matches!(
+        path.as_str(),
+        "/process" | "/voices" | "/locales" | "/version"
+    ) {
+        return text_response(StatusCode::NOT_FOUND, format!("No such page: {}", path));
+    }
+    let api_key = match api_keys {
+        Some(ref keys) => {
+            match keys.authenticate(&MetadataMap::from_headers(request.headers().clone())) {
+                Ok(key) => Some(key),
+                Err(status) => return text_response(http_status(&status), status.message().into()),
+            }
+        }
+        None => None,
+    };
+    match (request.method(), path.as_str()) {
+        (&Method::GET, "/version") => text_response(
+            StatusCode::OK,
+            format!(
+                "Mary TTS server {} (Sonata {})",
+                MARYTTS_VERSION,
+                env!("CARGO_PKG_VERSION")
+            ),
+        ),
+        (&Method::GET, "/voices") => {
+            let lines = Vec::from_iter(service.marytts_voices().into_iter().map(|voice| {
+                let locale = voice.locale.unwrap_or_else(|| "unknown".to_string());
+                format!("{} {} unknown vits\n", voice.name, locale)
+            }));
+            text_response(StatusCode::OK, lines.concat())
+        }
+        (&Method::GET, "/locales") => {
+            let mut locales = Vec::from_iter(
+                service
+                    .marytts_voices()
+                    .into_iter()
+                    .filter_map(|voice| voice.locale),
+            );
+            locales.sort();
+            locales.dedup();
+            let lines = Vec::from_iter(locales.into_iter().map(|locale| locale + "\n"));
+            text_response(StatusCode::OK, lines.concat())
+        }
+        (&Method::GET, "/process") | (&Method::POST, "/process") => {
+            let params = if request.method() == Method::POST {
+                match hyper::body::to_bytes(request.into_body()).await {
+                    Ok(body) => body.to_vec(),
+                    Err(e) => return text_response(StatusCode::BAD_REQUEST, e.to_string()),
+                }
+            } else {
+                request
+                    .uri()
+                    .query()
+                    .unwrap_or_default()
+                    .as_bytes()
+                    .to_vec()
+            };
+            let process_request = match parse_process_request(&params) {
+                Ok(process_request) => process_request,
+                Err(message) => return text_response(StatusCode::BAD_REQUEST, message),
+            };
+            let voices = service.marytts_voices();
+            let voice = select_voice(
+                &voices,
+                process_request.voice.as_deref(),
+                process_request.locale.as_deref(),
+            );
+            let voice_id = match voice {
+                Ok(voice) => voice.id.clone(),
+                Err(message) => return text_response(StatusCode::BAD_REQUEST, message),
+            };
+            let client = match api_key {
+                Some(ref key) => {
+                    if let Err(status) = key.charge(process_request.text.chars().count()) {
+                        return text_response(http_status(&status), status.message().into());
+                    }
+                    format!("key:{}", key.name())
+                }
+                None => remote_addr.ip().to_string(),
+            };
+            match service
+                .marytts_process(client, voice_id, process_request.text)
+                .await
+            {
+                Ok(wav_bytes) => Response::builder()
+                    .header("Content-Type", "audio/x-wav")
+                    .body(Body::from(wav_bytes)),
+                Err(status) => {
+                    log::error!("MaryTTS request failed. Error: {}", status.message());
+                    text_response(http_status(&status), status.message().into())
+                }
+            }
+        }
+        _ => text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("{} is not supported on {}", request.method(), path),
+        ),
+    }
+}
+
+/// Serve the MaryTTS API over HTTP
+pub async fn serve(
+    addr: SocketAddr,
+    service: Arc<SonataGrpcService>,
+    api_keys: Option<Arc<ApiKeys>>,
+) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let service = Arc::clone(&service);
+        let api_keys = api_keys.clone();
+        let remote_addr = conn.remote_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(Arc::clone(&service), api_keys.clone(), remote_addr, request)
+            }))
+        }
+    });
+    hyper::Server::try_bind(&addr)?.serve(make_service).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_process_request() {
+        let request = parse_process_request(
+            b"INPUT_TEXT=Hello+world%21&INPUT_TYPE=TEXT&OUTPUT_TYPE=AUDIO&AUDIO=WAVE_FILE&LOCALE=en_US&VOICE=",
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            ProcessRequest {
+                text: "Hello world!".to_string(),
+                voice: None,
+                locale: Some("en_US".to_string()),
+            }
+        );
+        assert!(parse_process_request(b"LOCALE=en_US").is_err());
+        let error = parse_process_request(b"INPUT_TEXT=hi&INPUT_TYPE=SSML").unwrap_err();
+        assert_eq!(error, "Unsupported INPUT_TYPE `SSML`");
+        assert!(parse_process_request(b"INPUT_TEXT=hi&AUDIO=AU_FILE").is_err());
+    }
+
+    #[test]
+    fn test_voice_names() {
+        assert_eq!(
+            voice_name(Path::new("/voices/en_US-amy-low.onnx.json")),
+            "en_US-amy-low"
+        );
+        assert_eq!(
+            voice_name(Path::new("/voices/de thorsten/config.json")),
+            "de_thorsten"
+        );
+        assert_eq!(mary_locale("en-us"), "en_US");
+        assert_eq!(mary_locale("fr_FR"), "fr_FR");
+        assert_eq!(mary_locale("cmn"), "cmn");
+    }
+
+    #[test]
+    fn test_select_voice() {
+        let voice = |id: &str, name: &str, locale: &str| MaryVoice {
+            id: id.to_string(),
+            name: name.to_string(),
+            locale: Some(locale.to_string()),
+        };
+        let voices = [
+            voice("1", "de_DE-thorsten", "de_DE"),
+            voice("2", "en_GB-alba", "en_GB"),
+            voice("3", "en_US-amy", "en_US"),
+        ];
+        let select = |voice, locale| select_voice(&voices, voice, locale).map(|voice| &voice.id);
+        assert_eq!(select(Some("en_US-amy"), Some("de")), Ok(&voices[2].id));
+        assert_eq!(select(Some("2"), None), Ok(&voices[1].id));
+        assert_eq!(select(None, Some("en-us")), Ok(&voices[2].id));
+        assert_eq!(select(None, Some("en_AU")), Ok(&voices[1].id));
+        assert_eq!(select(None, None), Ok(&voices[0].id));
+        assert!(select(None, Some("fr")).is_err());
+        assert!(select(Some("en_US-lessac"), None).is_err());
+    }
+}
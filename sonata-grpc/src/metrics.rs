@@ -0,0 +1,263 @@
+//! Prometheus metrics, served at `/metrics` on `SONATA_GRPC_METRICS_PORT`.
+//!
+//! Stage timings are what the server can observe from the outside of a model:
+//! - `phonemize`: text preprocessing and phonemization, before synthesis starts
+//! - `synthesize`: inference of one sentence (encoder and decoder together)
+//! - `first_chunk`: realtime mode, encoder plus decoding of the first chunk
+//! - `decode`: realtime mode, decoding of each further chunk
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sonata_core::MemoryUsage;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+const RTF_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.2, 0.3, 0.5, 0.75, 1.0, 1.5, 2.0];
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    stage_duration: HistogramVec,
+    real_time_factor: HistogramVec,
+    queued_requests: IntGauge,
+    running_requests: IntGauge,
+    voice_model_bytes: IntGaugeVec,
+    voice_memory_bytes: IntGaugeVec,
+    resident_memory_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("sonata_grpc".into()), None)?;
+        let requests = IntCounterVec::new(
+            Opts::new("requests_total", "Synthesis requests by method and status"),
+            &["method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "request_duration_seconds",
+                "Time from receiving a synthesis request until its last audio was sent",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method"],
+        )?;
+        let stage_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "stage_duration_seconds",
+                "Time spent in each synthesis stage",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["stage"],
+        )?;
+        let real_time_factor = HistogramVec::new(
+            HistogramOpts::new(
+                "real_time_factor",
+                "Request duration divided by the duration of the synthesized audio",
+            )
+            .buckets(RTF_BUCKETS.to_vec()),
+            &["method"],
+        )?;
+        let queued_requests = IntGauge::new(
+            "queued_requests",
+            "Synthesis requests waiting for a free slot",
+        )?;
+        let running_requests = IntGauge::new("running_requests", "Synthesis requests running")?;
+        let voice_model_bytes = IntGaugeVec::new(
+            Opts::new(
+                "voice_model_bytes",
+                "Size of each loaded voice's model files, an estimate of its session memory",
+            ),
+            &["voice_id"],
+        )?;
+        let voice_memory_bytes = IntGaugeVec::new(
+            Opts::new(
+                "voice_memory_bytes",
+                "Memory held by each loaded voice, as reported by the voice: the weights of \
+                 its models, the working memory of its sessions and its caches",
+            ),
+            &["voice_id", "kind"],
+        )?;
+        let resident_memory_bytes = IntGauge::new(
+            "resident_memory_bytes",
+            "Resident memory of the server process",
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(stage_duration.clone()))?;
+        registry.register(Box::new(real_time_factor.clone()))?;
+        registry.register(Box::new(queued_requests.clone()))?;
+        registry.register(Box::new(running_requests.clone()))?;
+        registry.register(Box::new(voice_model_bytes.clone()))?;
+        registry.register(Box::new(voice_memory_bytes.clone()))?;
+        registry.register(Box::new(resident_memory_bytes.clone()))?;
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            stage_duration,
+            real_time_factor,
+            queued_requests,
+            running_requests,
+            voice_model_bytes,
+            voice_memory_bytes,
+            resident_memory_bytes,
+        })
+    }
+    pub fn start_request(self: &Arc<Self>, method: &'static str) -> RequestMetrics {
+        RequestMetrics {
+            metrics: Arc::clone(self),
+            method,
+            started: Instant::now(),
+            audio_duration_ms: 0.0,
+        }
+    }
+    pub fn voice_loaded(&self, voice_id: &str, config_path: &Path) {
+        let model_files = [
+            config_path
+                .file_stem()
+                .map(|stem| config_path.with_file_name(stem)),
+            Some(config_path.with_file_name("encoder.onnx")),
+            Some(config_path.with_file_name("decoder.onnx")),
+        ];
+        let num_bytes: u64 = model_files
+            .into_iter()
+            .flatten()
+            .filter(|file| file.extension().is_some_and(|ext| ext == "onnx"))
+            .filter_map(|file| file.metadata().ok())
+            .map(|meta| meta.len())
+            .sum();
+        self.voice_model_bytes
+            .with_label_values(&[voice_id])
+            .set(num_bytes as i64);
+    }
+    pub fn voice_unloaded(&self, voice_id: &str) {
+        self.voice_model_bytes.remove_label_values(&[voice_id]).ok();
+    }
+    /// Render all metrics in the Prometheus text format. `voice_memory` is the memory
+    /// held by each loaded voice, which changes as voices run and cache audio
+    pub fn render(
+        &self,
+        num_queued: usize,
+        num_running: usize,
+        voice_memory: &[(String, MemoryUsage)],
+    ) -> String {
+        self.queued_requests.set(num_queued as i64);
+        self.running_requests.set(num_running as i64);
+        // Voices unloaded since the last render are dropped
+        self.voice_memory_bytes.reset();
+        for (voice_id, usage) in voice_memory {
+            for (kind, num_bytes) in [
+                ("model", usage.model_bytes),
+                ("session", usage.session_bytes),
+                ("cache", usage.cache_bytes),
+            ] {
+                self.voice_memory_bytes
+                    .with_label_values(&[voice_id, kind])
+                    .set(num_bytes as i64);
+            }
+        }
+        if let Some(num_bytes) = resident_memory_bytes() {
+            self.resident_memory_bytes.set(num_bytes as i64);
+        }
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("Failed to encode metrics. Error: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Metrics of one synthesis request, recorded as its audio is produced
+pub struct RequestMetrics {
+    metrics: Arc<Metrics>,
+    method: &'static str,
+    started: Instant,
+    audio_duration_ms: f32,
+}
+
+impl RequestMetrics {
+    pub fn observe_stage(&self, stage: &str, duration: Duration) {
+        self.metrics
+            .stage_duration
+            .with_label_values(&[stage])
+            .observe(duration.as_secs_f64());
+    }
+    pub fn add_audio(&mut self, duration_ms: f32) {
+        self.audio_duration_ms += duration_ms;
+    }
+    pub fn finish(self, status: Result<(), &tonic::Status>) {
+        let elapsed = self.started.elapsed();
+        let status = match status {
+            Ok(()) => "Ok".to_string(),
+            Err(e) => format!("{:?}", e.code()),
+        };
+        let metrics = &self.metrics;
+        metrics
+            .requests
+            .with_label_values(&[self.method, &status])
+            .inc();
+        metrics
+            .request_duration
+            .with_label_values(&[self.method])
+            .observe(elapsed.as_secs_f64());
+        if self.audio_duration_ms > 0.0 {
+            let rtf = elapsed.as_secs_f64() * 1000.0 / self.audio_duration_ms as f64;
+            metrics
+                .real_time_factor
+                .with_label_values(&[self.method])
+                .observe(rtf);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn resident_memory_bytes() -> Option<u64> {
+    // The second field of `statm` is the resident set size, in pages of (usually) 4 KiB
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(resident_pages * 4096)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory_bytes() -> Option<u64> {
+    None
+}
+
+/// Serve `/metrics` over HTTP. `render` produces the response body.
+pub async fn serve(
+    addr: SocketAddr,
+    render: impl Fn() -> String + Send + Sync + 'static,
+) -> hyper::Result<()> {
+    let render = Arc::new(render);
+    let make_service = make_service_fn(move |_| {
+        let render = Arc::clone(&render);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let render = Arc::clone(&render);
+                async move {
+                    if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                        hyper::Response::builder()
+                            .header("Content-Type", prometheus::TEXT_FORMAT)
+                            .body(Body::from(render()))
+                    } else {
+                        hyper::Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                    }
+                }
+            }))
+        }
+    });
+    hyper::Server::try_bind(&addr)?.serve(make_service).await
+}
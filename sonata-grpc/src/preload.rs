@@ -0,0 +1,220 @@
+//! The preload manifest (`SONATA_GRPC_PRELOAD_MANIFEST`): voices to load and warm up
+//! at startup, and the device each of them runs on.
+//!
+//! ```json
+//! {
+//!     "voices": [
+//!         { "voice": "en_US-amy-low.onnx.json", "device": "cuda:1" },
+//!         { "voice": "2417836", "device": "cpu", "warmup_text": "Bonjour." },
+//!         { "voice": "busy.onnx.json", "devices": ["cuda:0", "cuda:1"], "dispatch": "round_robin" }
+//!     ]
+//! }
+//! ```
+//!
+//! A voice is the path of its config file, relative to the manifest, or the id of a
+//! voice of the voices directory. It runs on its `device`, or is replicated on each of
+//! its `devices`, with requests dispatched to the least loaded one unless `dispatch`
+//! says otherwise. Its placement is kept when the voice is reloaded.
+
+use serde::Deserialize;
+use sonata_core::{SonataError, SonataResult};
+use sonata_piper::{DispatchPolicy, ExecutionProvider};
+use std::path::{Path, PathBuf};
+
+/// Spoken by voices without a warm-up text of their own
+pub const DEFAULT_WARMUP_TEXT: &str = "Hello.";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    voices: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    voice: String,
+    device: Option<String>,
+    devices: Option<Vec<String>>,
+    dispatch: Option<String>,
+    warmup_text: Option<String>,
+}
+
+/// The devices a voice runs on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub devices: Vec<ExecutionProvider>,
+    pub dispatch: DispatchPolicy,
+}
+
+impl Placement {
+    /// Run `load` with the voices it loads placed on the devices
+    pub fn load<T>(&self, load: impl FnOnce() -> T) -> T {
+        match self.devices.as_slice() {
+            [device] => sonata_piper::with_execution_providers(vec![*device], load),
+            devices => sonata_piper::with_device_pool(devices.to_vec(), self.dispatch, load),
+        }
+    }
+}
+
+impl std::fmt::Display for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.devices.as_slice() {
+            [device] => write!(f, "{:?}", device),
+            devices => write!(f, "{:?} ({:?})", devices, self.dispatch),
+        }
+    }
+}
+
+/// Parse a comma-separated list of devices, e.g. `cuda:0,cuda:1`
+pub fn parse_devices(devices: &str) -> SonataResult<Vec<ExecutionProvider>> {
+    devices
+        .split(',')
+        .filter(|device| !device.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// A voice of the manifest
+#[derive(Debug, PartialEq)]
+pub struct PreloadVoice {
+    pub voice: PreloadTarget,
+    /// Runs on the devices of the server if `None`
+    pub placement: Option<Placement>,
+    pub warmup_text: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PreloadTarget {
+    ConfigPath(PathBuf),
+    VoiceId(String),
+}
+
+pub fn load_manifest(manifest_path: &Path) -> SonataResult<Vec<PreloadVoice>> {
+    let contents = std::fs::read_to_string(manifest_path).map_err(|e| {
+        SonataError::FailedToLoadResource(format!(
+            "Failed to read preload manifest `{}`",
+            manifest_path.display()
+        ))
+        .caused_by(e)
+    })?;
+    let base_dir = manifest_path.parent().unwrap_or(Path::new(""));
+    parse_manifest(&contents, base_dir).map_err(|e| {
+        SonataError::FailedToLoadResource(format!(
+            "Invalid preload manifest `{}`",
+            manifest_path.display()
+        ))
+        .caused_by(e)
+    })
+}
+
+fn parse_manifest(contents: &str, base_dir: &Path) -> SonataResult<Vec<PreloadVoice>> {
+    let manifest: ManifestFile =
+        serde_json::from_str(contents).map_err(|e| SonataError::OperationError(e.to_string()))?;
+    let mut voices = Vec::with_capacity(manifest.voices.len());
+    for entry in manifest.voices {
+        let devices = match (entry.device, entry.devices) {
+            (Some(_), Some(_)) => {
+                return Err(SonataError::OperationError(format!(
+                    "Voice `{}` has both a `device` and `devices`",
+                    entry.voice
+                )))
+            }
+            (Some(device), None) => vec![device.parse()?],
+            (None, Some(devices)) => Result::from_iter(devices.iter().map(|d| d.parse()))?,
+            (None, None) => Vec::new(),
+        };
+        let dispatch = entry.dispatch.as_deref().map(str::parse).transpose()?;
+        let placement = match (devices.is_empty(), dispatch) {
+            (true, Some(_)) => {
+                return Err(SonataError::OperationError(format!(
+                    "Voice `{}` has a `dispatch` policy but no `devices`",
+                    entry.voice
+                )))
+            }
+            (true, None) => None,
+            (false, dispatch) => Some(Placement {
+                devices,
+                dispatch: dispatch.unwrap_or_default(),
+            }),
+        };
+        // Voice ids are numbers, config files are json
+        let voice = if entry.voice.bytes().all(|b| b.is_ascii_digit()) {
+            PreloadTarget::VoiceId(entry.voice)
+        } else {
+            PreloadTarget::ConfigPath(base_dir.join(entry.voice))
+        };
+        voices.push(PreloadVoice {
+            voice,
+            placement,
+            warmup_text: entry
+                .warmup_text
+                .unwrap_or_else(|| DEFAULT_WARMUP_TEXT.to_string()),
+        });
+    }
+    Ok(voices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = r#"{
+            "voices": [
+                { "voice": "amy/en_US-amy-low.onnx.json", "device": "cuda:1" },
+                { "voice": "2417836", "warmup_text": "Bonjour." },
+                { "voice": "busy.onnx.json", "devices": ["cuda:0", "cuda:1"] }
+            ]
+        }"#;
+        let voices = parse_manifest(manifest, Path::new("/voices")).unwrap();
+        assert_eq!(
+            voices,
+            [
+                PreloadVoice {
+                    voice: PreloadTarget::ConfigPath(PathBuf::from(
+                        "/voices/amy/en_US-amy-low.onnx.json"
+                    )),
+                    placement: Some(Placement {
+                        devices: vec![ExecutionProvider::Cuda { device_id: 1 }],
+                        dispatch: DispatchPolicy::LeastLoaded,
+                    }),
+                    warmup_text: DEFAULT_WARMUP_TEXT.to_string(),
+                },
+                PreloadVoice {
+                    voice: PreloadTarget::VoiceId("2417836".to_string()),
+                    placement: None,
+                    warmup_text: "Bonjour.".to_string(),
+                },
+                PreloadVoice {
+                    voice: PreloadTarget::ConfigPath(PathBuf::from("/voices/busy.onnx.json")),
+                    placement: Some(Placement {
+                        devices: vec![
+                            ExecutionProvider::Cuda { device_id: 0 },
+                            ExecutionProvider::Cuda { device_id: 1 },
+                        ],
+                        dispatch: DispatchPolicy::LeastLoaded,
+                    }),
+                    warmup_text: DEFAULT_WARMUP_TEXT.to_string(),
+                },
+            ]
+        );
+        let invalid = r#"{ "voices": [{ "voice": "2417836", "device": "gpu" }] }"#;
+        let error = parse_manifest(invalid, Path::new("")).unwrap_err();
+        assert!(error.to_string().contains("`gpu`"), "{}", error);
+        let unknown_field = r#"{ "voices": [{ "voice": "2417836", "gpu": 1 }] }"#;
+        assert!(parse_manifest(unknown_field, Path::new("")).is_err());
+        let both = r#"{ "voices": [{ "voice": "1", "device": "cpu", "devices": ["cpu"] }] }"#;
+        assert!(parse_manifest(both, Path::new("")).is_err());
+        let no_devices = r#"{ "voices": [{ "voice": "1", "dispatch": "round_robin" }] }"#;
+        assert!(parse_manifest(no_devices, Path::new("")).is_err());
+        assert_eq!(
+            parse_devices("cuda:0, cuda:1").unwrap(),
+            [
+                ExecutionProvider::Cuda { device_id: 0 },
+                ExecutionProvider::Cuda { device_id: 1 }
+            ]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Scheduling of synthesis requests.
+//!
+//! Each synthesis request holds an [`InferencePermit`] while it runs. The number of
+//! running requests is limited globally and per voice. Requests beyond those limits
+//! wait in a bounded queue that is served round-robin across clients, so a client
+//! sending a burst of requests can't starve the others. Once the queue is full, new
+//! requests are rejected with `RESOURCE_EXHAUSTED` rather than piling up in memory.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tonic::Status;
+
+const DEFAULT_MAX_QUEUED: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerLimits {
+    pub max_concurrent: usize,
+    pub max_concurrent_per_voice: usize,
+    pub max_queued: usize,
+}
+
+impl SchedulerLimits {
+    /// Read the limits from `SONATA_GRPC_MAX_CONCURRENT` (default: number of CPUs),
+    /// `SONATA_GRPC_MAX_CONCURRENT_PER_VOICE` (default: the global limit) and
+    /// `SONATA_GRPC_MAX_QUEUED` (default: 64)
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+        let num_cpus = std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(4);
+        let max_concurrent = var("SONATA_GRPC_MAX_CONCURRENT", num_cpus);
+        Self {
+            max_concurrent,
+            max_concurrent_per_voice: var("SONATA_GRPC_MAX_CONCURRENT_PER_VOICE", max_concurrent),
+            max_queued: var("SONATA_GRPC_MAX_QUEUED", DEFAULT_MAX_QUEUED),
+        }
+    }
+}
+
+struct Waiter {
+    voice_id: String,
+    sender: oneshot::Sender<InferencePermit>,
+}
+
+#[derive(Default)]
+struct RunningRequests {
+    total: usize,
+    per_voice: HashMap<String, usize>,
+}
+
+impl RunningRequests {
+    fn has_capacity(&self, limits: &SchedulerLimits, voice_id: &str) -> bool {
+        self.total < limits.max_concurrent
+            && self.per_voice.get(voice_id).copied().unwrap_or(0) < limits.max_concurrent_per_voice
+    }
+    fn start(&mut self, voice_id: &str) {
+        self.total += 1;
+        *self.per_voice.entry(voice_id.to_string()).or_default() += 1;
+    }
+    fn finish(&mut self, voice_id: &str) {
+        self.total -= 1;
+        if let Some(count) = self.per_voice.get_mut(voice_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_voice.remove(voice_id);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: RunningRequests,
+    /// Waiting requests of each client, oldest first
+    queues: HashMap<String, VecDeque<Waiter>>,
+    /// Clients with waiting requests, in round-robin order
+    clients: VecDeque<String>,
+}
+
+impl SchedulerState {
+    fn num_queued(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+    /// Forget requests whose client went away while waiting
+    fn prune_cancelled(&mut self) {
+        for queue in self.queues.values_mut() {
+            queue.retain(|waiter| !waiter.sender.is_closed());
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        let queues = &self.queues;
+        self.clients.retain(|client| queues.contains_key(client));
+    }
+}
+
+struct SchedulerInner {
+    limits: SchedulerLimits,
+    state: Mutex<SchedulerState>,
+}
+
+impl SchedulerInner {
+    /// Start as many waiting requests as the limits allow, taking one request per
+    /// client in turn
+    fn dispatch(self: &Arc<Self>, state: &mut SchedulerState) {
+        let mut visited_without_grant = 0;
+        while visited_without_grant < state.clients.len() {
+            let Some(client) = state.clients.pop_front() else {
+                break;
+            };
+            let queue = state.queues.get_mut(&client).unwrap();
+            let running = &mut state.running;
+            let runnable = queue
+                .iter()
+                .position(|waiter| running.has_capacity(&self.limits, &waiter.voice_id));
+            if let Some(index) = runnable {
+                let waiter = queue.remove(index).unwrap();
+                running.start(&waiter.voice_id);
+                let permit = InferencePermit {
+                    scheduler: Arc::clone(self),
+                    voice_id: waiter.voice_id.clone(),
+                    armed: true,
+                };
+                if let Err(mut permit) = waiter.sender.send(permit) {
+                    // The client went away, release the slot without re-entering the lock
+                    permit.armed = false;
+                    running.finish(&waiter.voice_id);
+                }
+                visited_without_grant = 0;
+            } else {
+                visited_without_grant += 1;
+            }
+            if queue.is_empty() {
+                state.queues.remove(&client);
+            } else {
+                state.clients.push_back(client);
+            }
+        }
+    }
+}
+
+/// Held by a synthesis request while it runs. Dropping it lets the next request start.
+pub struct InferencePermit {
+    scheduler: Arc<SchedulerInner>,
+    voice_id: String,
+    armed: bool,
+}
+
+impl Drop for InferencePermit {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.running.finish(&self.voice_id);
+        self.scheduler.dispatch(&mut state);
+    }
+}
+
+#[derive(Clone)]
+pub struct InferenceScheduler(Arc<SchedulerInner>);
+
+impl InferenceScheduler {
+    pub fn new(limits: SchedulerLimits) -> Self {
+        Self(Arc::new(SchedulerInner {
+            limits,
+            state: Default::default(),
+        }))
+    }
+    pub fn num_queued(&self) -> usize {
+        self.0.state.lock().unwrap().num_queued()
+    }
+    pub fn num_running(&self) -> usize {
+        self.0.state.lock().unwrap().running.total
+    }
+    /// Wait for a slot to synthesize with `voice_id` on behalf of `client_id`
+    pub async fn acquire(
+        &self,
+        client_id: &str,
+        voice_id: &str,
+    ) -> Result<InferencePermit, Status> {
+        let receiver = {
+            let mut state = self.0.state.lock().unwrap();
+            // Waiting requests can't run, otherwise they would have been dispatched,
+            // so starting this one right away doesn't overtake them
+            if state.running.has_capacity(&self.0.limits, voice_id) {
+                state.running.start(voice_id);
+                return Ok(InferencePermit {
+                    scheduler: Arc::clone(&self.0),
+                    voice_id: voice_id.to_string(),
+                    armed: true,
+                });
+            }
+            state.prune_cancelled();
+            if state.num_queued() >= self.0.limits.max_queued {
+                return Err(Status::resource_exhausted(
+                    "Too many queued synthesis requests. Please retry later",
+                ));
+            }
+            let (sender, receiver) = oneshot::channel();
+            if !state.queues.contains_key(client_id) {
+                state.clients.push_back(client_id.to_string());
+            }
+            state
+                .queues
+                .entry(client_id.to_string())
+                .or_default()
+                .push_back(Waiter {
+                    voice_id: voice_id.to_string(),
+                    sender,
+                });
+            receiver
+        };
+        receiver
+            .await
+            .map_err(|_| Status::unavailable("The synthesis scheduler was shut down"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fair_scheduling() {
+        let scheduler = InferenceScheduler::new(SchedulerLimits {
+            max_concurrent: 1,
+            max_concurrent_per_voice: 1,
+            max_queued: 3,
+        });
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let running = scheduler.acquire("a", "voice").await.unwrap();
+        let mut tasks = Vec::new();
+        for client in ["a", "a", "b"] {
+            let (scheduler, served) = (scheduler.clone(), Arc::clone(&served));
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(client, "voice").await.unwrap();
+                served.lock().unwrap().push(client);
+            }));
+            tokio::task::yield_now().await;
+        }
+        assert!(scheduler.acquire("c", "voice").await.is_err());
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // Client `b` is served before the second request of client `a`
+        assert_eq!(*served.lock().unwrap(), ["a", "b", "a"]);
+
+        // A busy voice doesn't hold back other voices
+        let scheduler = InferenceScheduler::new(SchedulerLimits {
+            max_concurrent: 2,
+            max_concurrent_per_voice: 1,
+            max_queued: 0,
+        });
+        let _running = scheduler.acquire("a", "voice").await.unwrap();
+        assert!(scheduler.acquire("a", "other").await.is_ok());
+    }
+}
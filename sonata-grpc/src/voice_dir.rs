@@ -0,0 +1,99 @@
+//! Discovery of the voices in the voices directory (`SONATA_GRPC_VOICES_DIR`).
+//!
+//! A voice is a Piper config file (`*.onnx.json`), or a `*.json` file next to the
+//! `encoder.onnx` and `decoder.onnx` of a streaming model. Voices are looked up in
+//! the directory itself and in its immediate subdirectories.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub struct VoiceFile {
+    /// Canonical path of the config file
+    pub config_path: PathBuf,
+    pub modified: Option<SystemTime>,
+}
+
+fn is_voice_config(path: &Path) -> bool {
+    let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    if filename.ends_with(".onnx.json") {
+        return true;
+    }
+    filename.ends_with(".json")
+        && path.with_file_name("encoder.onnx").is_file()
+        && path.with_file_name("decoder.onnx").is_file()
+}
+
+/// The latest modification time of a voice's config and model files, so replacing
+/// either of them is picked up
+pub fn modified(config_path: &Path) -> Option<SystemTime> {
+    let mut files = vec![config_path.to_path_buf()];
+    if let Some(onnx_filename) = config_path.file_stem() {
+        files.push(config_path.with_file_name(onnx_filename));
+    }
+    files.push(config_path.with_file_name("encoder.onnx"));
+    files.push(config_path.with_file_name("decoder.onnx"));
+    files
+        .iter()
+        .filter_map(|file| file.metadata().and_then(|meta| meta.modified()).ok())
+        .max()
+}
+
+pub fn scan(dir: &Path) -> io::Result<Vec<VoiceFile>> {
+    let mut candidates = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            // A subdirectory may be removed while scanning
+            if let Ok(entries) = std::fs::read_dir(&path) {
+                candidates.extend(entries.filter_map(|entry| Some(entry.ok()?.path())));
+            }
+        } else {
+            candidates.push(path);
+        }
+    }
+    let mut voices = Vec::from_iter(
+        candidates
+            .into_iter()
+            .filter(|path| path.is_file() && is_voice_config(path))
+            .filter_map(|path| {
+                Some(VoiceFile {
+                    modified: modified(&path),
+                    config_path: path.canonicalize().ok()?,
+                })
+            }),
+    );
+    voices.sort_by(|a, b| a.config_path.cmp(&b.config_path));
+    Ok(voices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("sonata-voices-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("streaming")).unwrap();
+        for file in [
+            "en_US-amy-low.onnx",
+            "en_US-amy-low.onnx.json",
+            "replacements.json",
+            "streaming/encoder.onnx",
+            "streaming/decoder.onnx",
+            "streaming/config.json",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let voices = scan(&dir).unwrap();
+        let names = Vec::from_iter(voices.iter().map(|voice| {
+            let path = voice.config_path.strip_prefix(dir.canonicalize().unwrap());
+            path.unwrap().to_string_lossy().into_owned()
+        }));
+        assert_eq!(names, ["en_US-amy-low.onnx.json", "streaming/config.json"]);
+        assert!(voices.iter().all(|voice| voice.modified.is_some()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,18 +4,17 @@ use sonata_synth::{
     SonataSpeechSynthesizer, RealtimeSpeechStream
 };
 use sonata_piper::PiperSynthesisConfig;
-use libtashkeel_base::{LibtashkeelResult, DynamicInferenceEngine as TashkeelInferenceEngine, do_tashkeel};
-use once_cell::sync::Lazy;
+use libtashkeel_base::{DynamicInferenceEngine as TashkeelInferenceEngine, do_tashkeel};
 use pyo3::create_exception;
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-static LIBTASHKEEL_ENGINE: Lazy<LibtashkeelResult<TashkeelInferenceEngine>>=
-    Lazy::new(|| libtashkeel_base::create_inference_engine(None));
+/// Created on first use, and dropped by `shutdown`
+static LIBTASHKEEL_ENGINE: Mutex<Option<TashkeelInferenceEngine>> = Mutex::new(None);
 type PySonataResult<T> = Result<T, PySonataError>;
 
 create_exception!(
@@ -30,7 +29,7 @@ struct PySonataError(SonataError);
 
 impl From<PySonataError> for PyErr {
     fn from(other: PySonataError) -> Self {
-        SonataException::new_err(other.0.to_string())
+        SonataException::new_err(other.0.message_with_causes())
     }
 }
 
@@ -74,17 +73,37 @@ struct PyAudioOutputConfig(AudioOutputConfig);
 #[pymethods]
 impl PyAudioOutputConfig {
     #[new]
+    #[pyo3(signature = (
+        rate=None,
+        volume=None,
+        pitch=None,
+        appended_silence_ms=None,
+        gain_db=None,
+        rate_multiplier=None,
+        trim_leading_silence=false,
+        trim_trailing_silence=false
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         rate: Option<u8>,
         volume: Option<u8>,
         pitch: Option<u8>,
         appended_silence_ms: Option<u32>,
+        gain_db: Option<f32>,
+        rate_multiplier: Option<f32>,
+        trim_leading_silence: bool,
+        trim_trailing_silence: bool,
     ) -> Self {
         Self(AudioOutputConfig {
             rate,
             volume,
             pitch,
             appended_silence_ms,
+            time_stretch: None,
+            gain_db,
+            rate_multiplier,
+            trim_leading_silence,
+            trim_trailing_silence,
         })
     }
 }
@@ -266,16 +285,7 @@ impl PiperModel {
     }
     #[setter]
     fn set_speaker(&self, name: String) -> PySonataResult<()> {
-        let sid = match self.0.speaker_name_to_id(&name)? {
-            Some(sname) => sname,
-            None => {
-                return Err(SonataError::OperationError(format!(
-                    "A speaker with the given name `{}` was not found",
-                    name
-                ))
-                .into())
-            }
-        };
+        let sid = self.0.resolve_speaker(&name)?;
         match self
             .0
             .get_fallback_synthesis_config()?
@@ -400,9 +410,43 @@ impl Sonata {
     fn speakers(&self) -> PySonataResult<Option<HashMap<i64, String>>> {
         Ok(self.0.get_speakers()?.cloned())
     }
+    #[getter]
+    fn phoneme_id_map(&self) -> PySonataResult<Option<HashMap<char, Vec<i64>>>> {
+        Ok(self.0.phoneme_id_map()?.cloned())
+    }
+    #[getter]
+    fn speaker_id_map(&self) -> PySonataResult<Option<HashMap<String, i64>>> {
+        Ok(self.0.speaker_id_map()?.cloned())
+    }
+    #[getter]
+    fn supported_phonemes(&self) -> PySonataResult<Vec<char>> {
+        Ok(self.0.supported_phonemes()?)
+    }
     fn get_audio_output_info(&self) -> PySonataResult<PyWaveInfo> {
         Ok(self.0.audio_output_info()?.into())
     }
+    fn reload_config(&self, py: Python, config_path: &str) -> PySonataResult<()> {
+        Ok(py.allow_threads(|| self.0.reload_config(&PathBuf::from(config_path)))?)
+    }
+}
+
+/// Load onnxruntime from the given shared library, or the directory holding it.
+/// Requires the `ort-dylib` feature, and must be called before loading any voice.
+#[pyfunction]
+fn set_onnxruntime_path(path: &str) -> PySonataResult<()> {
+    let options = sonata_piper::InitOptions {
+        dylib_path: Some(PathBuf::from(path)),
+        ..Default::default()
+    };
+    Ok(sonata_piper::init(options)?)
+}
+
+/// Release espeak-ng and onnxruntime, e.g. before the interpreter exits.
+/// All voices must be deleted first. Voices can be loaded again afterwards.
+#[pyfunction]
+fn shutdown() -> PySonataResult<()> {
+    LIBTASHKEEL_ENGINE.lock().unwrap().take();
+    Ok(sonata_piper::shutdown()?)
 }
 
 #[pyfunction]
@@ -416,11 +460,14 @@ pub fn phonemize_text(
 ) -> PyResult<Vec<String>> {
     let use_tashkeel = (language  == "ar") && use_tashkeel.unwrap_or(true);
     let text = if use_tashkeel {
-        let engine= match LIBTASHKEEL_ENGINE.as_ref() {
-            Ok(eng) => eng,
-            Err(e) => return Err(SonataException::new_err(e.to_string()))
-        };
-        match do_tashkeel(engine, text, None, false) {
+        let mut engine = LIBTASHKEEL_ENGINE.lock().unwrap();
+        if engine.is_none() {
+            match libtashkeel_base::create_inference_engine(None) {
+                Ok(eng) => *engine = Some(eng),
+                Err(e) => return Err(SonataException::new_err(e.to_string()))
+            }
+        }
+        match do_tashkeel(engine.as_ref().unwrap(), text, None, false) {
             Ok(mashkool) => std::borrow::Cow::from(mashkool),
             Err(e) => return Err(SonataException::new_err(e.to_string()))
         }
@@ -453,5 +500,7 @@ fn pysonata(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<ParallelSpeechStream>()?;
     m.add_class::<PyRealtimeSpeechStream>()?;
     m.add_function(wrap_pyfunction!(phonemize_text, m)?)?;
+    m.add_function(wrap_pyfunction!(set_onnxruntime_path, m)?)?;
+    m.add_function(wrap_pyfunction!(shutdown, m)?)?;
     Ok(())
 }
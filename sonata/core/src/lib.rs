@@ -1,14 +1,36 @@
 use std::any::Any;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 
 
 pub use audio_ops::{
     Audio,
+    AudioFormatError,
     AudioInfo,
     AudioSamples,
-    WaveWriterError
+    OverlapWindow,
+    StageTimings,
+    TELEPHONY_SAMPLE_RATE,
+    WaveFileWriter,
+    WaveMetadata,
+    WaveReaderError,
+    WaveSampleFormat,
+    WaveWriterError,
+    alaw_to_linear,
+    linear_to_alaw,
+    linear_to_mulaw,
+    mulaw_to_linear,
+    read_wave_bytes,
+    read_wave_file,
+    streaming_wave_header,
+    write_float_samples_to_buffer,
 };
 
 
@@ -21,14 +43,47 @@ pub enum SonataError {
     FailedToLoadResource(String),
     PhonemizationError(String),
     OperationError(String),
+    /// An error along with the lower-level error that caused it (e.g. of onnxruntime or
+    /// of the file system), which is its `source()`
+    Caused(Box<SonataError>, Box<dyn Error + Send + Sync>),
 }
 
 impl SonataError {
     pub fn with_message(message: impl Into<String>) -> Self {
         Self::OperationError(message.into())
     }
+    /// Keep `source` as the cause of this error
+    pub fn caused_by(self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self::Caused(Box::new(self), source.into())
+    }
+    /// This error without its cause, i.e. the variant that tells what failed
+    pub fn without_cause(&self) -> &SonataError {
+        match self {
+            SonataError::Caused(error, _) => error.without_cause(),
+            error => error,
+        }
+    }
+    /// The message of this error followed by those of its causes, for callers that only
+    /// pass a message along (e.g. language bindings)
+    pub fn message_with_causes(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = self.source();
+        while let Some(error) = source {
+            message.push_str(&format!(". Caused by: {}", error));
+            source = error.source();
+        }
+        message
+    }
+}
+
+impl Error for SonataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SonataError::Caused(_, source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
 }
-impl Error for SonataError {}
 
 impl fmt::Display for SonataError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -38,6 +93,8 @@ impl fmt::Display for SonataError {
             }
             SonataError::PhonemizationError(msg) => msg.to_string(),
             SonataError::OperationError(msg) => msg.to_string(),
+            // The cause is not part of the message, see `Error::source`
+            SonataError::Caused(error, _) => error.to_string(),
         };
         write!(f, "{}", err_message)
     }
@@ -49,32 +106,325 @@ impl From<WaveWriterError> for SonataError {
     }
 }
 
+impl From<WaveReaderError> for SonataError {
+    fn from(error: WaveReaderError) -> Self {
+        SonataError::FailedToLoadResource(error.to_string())
+    }
+}
+
+impl From<AudioFormatError> for SonataError {
+    fn from(error: AudioFormatError) -> Self {
+        SonataError::OperationError(error.to_string())
+    }
+}
+
+/// Voices with at most this many speakers list all of them in speaker errors
+const MAX_LISTED_SPEAKERS: usize = 10;
+
+/// An error for the speaker `name`, which is not one of `speakers`. Lists the speakers
+/// of small voices, and the closest names of larger ones.
+pub fn unknown_speaker_error(name: &str, speakers: &HashMap<i64, String>) -> SonataError {
+    let mut message = format!("Speaker `{}` was not found.", name);
+    if speakers.is_empty() {
+        message.push_str(" The voice has a single speaker");
+    } else if speakers.len() <= MAX_LISTED_SPEAKERS {
+        let mut listed = Vec::from_iter(speakers.iter());
+        listed.sort_unstable();
+        let listed = Vec::from_iter(
+            listed
+                .into_iter()
+                .map(|(sid, sname)| format!("`{}` ({})", sname, sid)),
+        );
+        message.push_str(&format!(" Available speakers: {}", listed.join(", ")));
+    } else {
+        let name = name.to_lowercase();
+        let mut closest = Vec::from_iter(
+            speakers
+                .values()
+                .map(|sname| (edit_distance(&name, &sname.to_lowercase()), sname.as_str())),
+        );
+        closest.sort_unstable();
+        let max_distance = (name.chars().count() / 3).max(2);
+        let suggestions = Vec::from_iter(
+            closest
+                .into_iter()
+                .take(3)
+                .filter(|(distance, _)| *distance <= max_distance)
+                .map(|(_, sname)| format!("`{}`", sname)),
+        );
+        if !suggestions.is_empty() {
+            message.push_str(&format!(" Did you mean {}?", suggestions.join(", ")));
+        }
+        message.push_str(&format!(" The voice has {} speakers", speakers.len()));
+    }
+    SonataError::OperationError(message)
+}
+
+/// Levenshtein distance between `a` and `b`, in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = Vec::from_iter(b.chars());
+    let mut row = Vec::from_iter(0..=b.len());
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// How the chunks of a synthesis stream are stitched together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkOverlap {
+    /// Samples shared by consecutive chunks, taken from the chunk padding
+    pub samples: usize,
+    pub window: OverlapWindow,
+}
+
+impl Default for ChunkOverlap {
+    fn default() -> Self {
+        Self {
+            samples: 256,
+            window: OverlapWindow::Hann,
+        }
+    }
+}
+
+/// The speed-ups a synthesizer accepts as its rate multiplier
+pub const RATE_MULTIPLIER_RANGE: (f32, f32) = (0.5f32, 3.0f32);
+
+/// Length of the fade-out that ends stopped streams
+pub const STOP_FADE_OUT: Duration = Duration::from_millis(10);
+
+/// A change to the duration the model predicts for one phoneme
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DurationOverride {
+    /// Multiply the predicted duration
+    Scale(f32),
+    /// Replace the predicted duration by a number of decoder frames
+    Frames(usize),
+}
+
+/// A prosodic feature that some voices are conditioned on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProsodyFeature {
+    Pitch,
+    Energy,
+}
+
+/// A pitch or energy contour fed to voices conditioned on one. Values are in the units
+/// the voice was trained with, where `0.0` is the speaker's average
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contour {
+    /// Values spread evenly over each sentence. Empty for a flat contour
+    pub frames: Vec<f32>,
+    /// Added to the whole contour
+    pub shift: f32,
+    /// Scales the distance of each value from the mean of the contour
+    pub range: f32,
+}
+
+impl Contour {
+    /// A flat contour, `shift` away from the average
+    pub fn flat(shift: f32) -> Self {
+        Self {
+            frames: Vec::new(),
+            shift,
+            range: 1.0,
+        }
+    }
+    pub fn from_frames(frames: Vec<f32>) -> Self {
+        Self {
+            frames,
+            shift: 0.0,
+            range: 1.0,
+        }
+    }
+    /// Expand (or compress, below `1.0`) the contour around its mean
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+}
+
+impl Default for Contour {
+    fn default() -> Self {
+        Self::flat(0.0)
+    }
+}
+
+thread_local! {
+    /// The native rate of the calls made on this thread, see [`with_native_rate`]
+    static NATIVE_RATE: Cell<f32> = const { Cell::new(1.0) };
+}
+
+/// Run `speak` with the models it calls on this thread speaking `rate` times faster,
+/// where `rate` is a native rate returned by [`SonataModel::native_rate`].
+///
+/// The rate belongs to the call rather than to the model, so that synthesizers sharing
+/// a voice each keep their own.
+pub fn with_native_rate<T>(rate: f32, speak: impl FnOnce() -> T) -> T {
+    let previous = NATIVE_RATE.with(|native_rate| native_rate.replace(rate));
+    // Restores the previous rate even if `speak` panics
+    struct Restore(f32);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            NATIVE_RATE.with(|native_rate| native_rate.set(self.0));
+        }
+    }
+    let _restore = Restore(previous);
+    speak()
+}
+
+/// The native rate set by [`with_native_rate`] for the calls on this thread
+pub fn current_native_rate() -> f32 {
+    NATIVE_RATE.with(Cell::get)
+}
+
+/// Stops, pauses and resumes a synthesis stream from other threads
+#[derive(Clone, Debug, Default)]
+pub struct StreamControl(Arc<StreamControlState>);
+
+#[derive(Debug, Default)]
+struct StreamControlState {
+    stopped: AtomicBool,
+    fade_out: AtomicBool,
+    paused: Mutex<bool>,
+    unpaused: Condvar,
+}
+
+impl StreamControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Stop the stream without decoding the audio that is left. With `fade_out`, the
+    /// stream ends with a short chunk fading out the audio it produced, rather than on a
+    /// click
+    pub fn stop(&self, fade_out: bool) {
+        self.0.fade_out.store(fade_out, Ordering::SeqCst);
+        self.0.stopped.store(true, Ordering::SeqCst);
+        // Wake up a paused stream, so that it ends
+        let _paused = self.0.paused.lock().unwrap();
+        self.0.unpaused.notify_all();
+    }
+    /// Stop returning chunks until `resume` is called. The consumer of the stream waits,
+    /// while the producer decodes no further than the stream buffers, so the stream
+    /// resumes where it was paused
+    pub fn pause(&self) {
+        *self.0.paused.lock().unwrap() = true;
+    }
+    pub fn resume(&self) {
+        *self.0.paused.lock().unwrap() = false;
+        self.0.unpaused.notify_all();
+    }
+    pub fn is_paused(&self) -> bool {
+        *self.0.paused.lock().unwrap()
+    }
+    /// Block while the stream is paused, and not stopped. Called by the consumer of a
+    /// stream, never by the threads decoding it
+    pub fn wait_while_paused(&self) {
+        let paused = self.0.paused.lock().unwrap();
+        let _paused = self
+            .0
+            .unpaused
+            .wait_while(paused, |paused| *paused && !self.is_stopped())
+            .unwrap();
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.0.stopped.load(Ordering::SeqCst)
+    }
+    pub fn fades_out(&self) -> bool {
+        self.0.fade_out.load(Ordering::SeqCst)
+    }
+}
+
 /// A wrapper type that holds sentence phonemes
-pub struct Phonemes(Vec<String>);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phonemes {
+    sentences: Vec<String>,
+    timings: StageTimings,
+    warnings: Vec<String>,
+}
 
 impl Phonemes {
     pub fn sentences(&self) -> &Vec<String> {
-        &self.0
+        &self.sentences
     }
 
     pub fn to_vec(self) -> Vec<String> {
-        self.0
+        self.sentences
     }
 
     pub fn num_sentences(&self) -> usize {
-        self.0.len()
+        self.sentences.len()
+    }
+
+    /// Time spent in the stages of phonemization the model tracks, e.g. tashkeel
+    pub fn timings(&self) -> StageTimings {
+        self.timings
+    }
+
+    pub fn with_timings(mut self, timings: StageTimings) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// Problems the phonemizer reported, e.g. a missing dictionary for the language
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn with_warnings(mut self, warnings: Vec<String>) -> Self {
+        self.warnings = warnings;
+        self
     }
 }
 
 impl From<Vec<String>> for Phonemes {
     fn from(other: Vec<String>) -> Self {
-        Self(other)
+        Self {
+            sentences: other,
+            timings: StageTimings::default(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Approximate memory held by a loaded voice, as reported by [`SonataModel::memory_usage`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Size of the model's weights, counted once for each copy of them the voice holds
+    pub model_bytes: u64,
+    /// Working memory of the inference sessions, as far as it can be observed
+    pub session_bytes: u64,
+    /// Results kept in memory to be reused, such as synthesized audio
+    pub cache_bytes: u64,
+}
+
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.model_bytes + self.session_bytes + self.cache_bytes
+    }
+}
+
+impl std::ops::Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            model_bytes: self.model_bytes + other.model_bytes,
+            session_bytes: self.session_bytes + other.session_bytes,
+            cache_bytes: self.cache_bytes + other.cache_bytes,
+        }
     }
 }
 
 impl std::string::ToString for Phonemes {
     fn to_string(&self) -> String {
-        self.0.join(" ")
+        self.sentences.join(" ")
     }
 }
 
@@ -84,10 +434,105 @@ pub trait SonataModel {
     fn phonemize_text(&self, text: &str) -> SonataResult<Phonemes>;
     fn speak_batch(&self, phoneme_batches: Vec<String>) -> SonataResult<Vec<Audio>>;
     fn speak_one_sentence(&self, phonemes: String) -> SonataAudioResult;
+    /// Like `speak_batch`, with the voice of `speaker` instead of the current speaker
+    fn speak_batch_as_speaker(
+        &self,
+        #[allow(unused_variables)] phoneme_batches: Vec<String>,
+        #[allow(unused_variables)] speaker: i64,
+    ) -> SonataResult<Vec<Audio>> {
+        Err(SonataError::OperationError(
+            "Choosing the speaker per call is not supported for this model".to_string(),
+        ))
+    }
+    /// Like `speak_batch`, but returns a result for each item rather than failing the
+    /// whole batch, so callers can keep the audio of the successful items
+    fn speak_batch_per_item(&self, phoneme_batches: Vec<String>) -> Vec<SonataAudioResult> {
+        Vec::from_iter(
+            phoneme_batches
+                .into_iter()
+                .map(|phonemes| self.speak_one_sentence(phonemes)),
+        )
+    }
 
     fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>>;
     fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>>;
     fn set_fallback_synthesis_config(&self, synthesis_config: &dyn Any) -> SonataResult<()>;
+    /// The part of speaking `rate` times faster (slower below `1.0`) that the model can
+    /// reach by itself, while it still sounds natural. Calls made inside
+    /// [`with_native_rate`] speak at that rate, and the rest of `rate` is left to apply
+    /// to the audio by time-stretching
+    fn native_rate(&self, #[allow(unused_variables)] rate: f32) -> SonataResult<f32> {
+        Ok(1.0)
+    }
+    /// Re-read the model's config from `config_path` while the model is in use, so that
+    /// tuned synthesis defaults take effect without loading the model again
+    fn reload_config(&self, #[allow(unused_variables)] config_path: &Path) -> SonataResult<()> {
+        Err(SonataError::OperationError(
+            "Reloading the config is not supported for this model".to_string(),
+        ))
+    }
+
+    /// Speak one sentence using the given synthesis config instead of the fallback one
+    fn speak_one_sentence_with_config(
+        &self,
+        #[allow(unused_variables)] phonemes: String,
+        #[allow(unused_variables)] synthesis_config: &dyn Any,
+    ) -> SonataAudioResult {
+        Err(SonataError::OperationError(
+            "Per-call synthesis config is not supported for this model".to_string(),
+        ))
+    }
+
+    /// Number of decoder frames the model predicts for each phoneme (`char`) of
+    /// `phonemes`. Phonemes the model does not know get no frames
+    fn predict_durations(
+        &self,
+        #[allow(unused_variables)] phonemes: String,
+    ) -> SonataResult<Vec<usize>> {
+        Err(SonataError::OperationError(
+            "Phoneme durations are not available for this model".to_string(),
+        ))
+    }
+    /// Speak one sentence with the durations of some of its phonemes changed.
+    /// `overrides` pairs the index of a phoneme (`char`) of `phonemes` with its change.
+    ///
+    /// Editing the durations of the same phonemes again only runs the decoder, and keeps
+    /// the rest of the sentence as it was.
+    fn speak_with_durations(
+        &self,
+        #[allow(unused_variables)] phonemes: String,
+        #[allow(unused_variables)] overrides: &[(usize, DurationOverride)],
+    ) -> SonataAudioResult {
+        Err(SonataError::OperationError(
+            "Phoneme durations are not available for this model".to_string(),
+        ))
+    }
+    /// Speak one sentence, along with the samples of its audio in which each phoneme
+    /// (`char`) of `phonemes` is spoken. Phonemes the model does not know get an empty
+    /// range
+    fn speak_with_alignment(
+        &self,
+        #[allow(unused_variables)] phonemes: String,
+    ) -> SonataResult<(Audio, Vec<Range<usize>>)> {
+        Err(SonataError::OperationError(
+            "Phoneme durations are not available for this model".to_string(),
+        ))
+    }
+
+    /// The prosodic features the model can be given contours for
+    fn prosody_features(&self) -> SonataResult<Vec<ProsodyFeature>> {
+        Ok(Vec::new())
+    }
+    /// Condition the following utterances on `contour`, or clear the contour of `feature`
+    fn set_contour(
+        &self,
+        #[allow(unused_variables)] feature: ProsodyFeature,
+        #[allow(unused_variables)] contour: Option<Contour>,
+    ) -> SonataResult<()> {
+        Err(SonataError::OperationError(
+            "Pitch and energy contours are not supported for this model".to_string(),
+        ))
+    }
 
     fn get_language(&self) -> SonataResult<Option<String>> {
         Ok(None)
@@ -111,9 +556,58 @@ pub trait SonataModel {
             None
         }))
     }
+    /// Like `speaker_name_to_id`, but unknown names are an error that suggests speakers
+    fn resolve_speaker(&self, name: &str) -> SonataResult<i64> {
+        match self.speaker_name_to_id(name)? {
+            Some(sid) => Ok(sid),
+            None => Err(unknown_speaker_error(
+                name,
+                self.get_speakers()?.unwrap_or(&HashMap::new()),
+            )),
+        }
+    }
+    /// The speaker of the fallback synthesis config, for multi-speaker models
+    fn current_speaker(&self) -> SonataResult<Option<i64>> {
+        Ok(None)
+    }
+    /// The ids each phoneme the model accepts is spoken with, for models that take
+    /// phoneme ids
+    fn phoneme_id_map(&self) -> SonataResult<Option<&HashMap<char, Vec<i64>>>> {
+        Ok(None)
+    }
+    /// The ids of the speakers by name, the reverse of `get_speakers`
+    fn speaker_id_map(&self) -> SonataResult<Option<&HashMap<String, i64>>> {
+        Ok(None)
+    }
+    /// The phonemes the model accepts, sorted. Empty if the model does not tell
+    fn supported_phonemes(&self) -> SonataResult<Vec<char>> {
+        let mut phonemes = Vec::from_iter(
+            self.phoneme_id_map()?
+                .into_iter()
+                .flat_map(|map| map.keys().copied()),
+        );
+        phonemes.sort_unstable();
+        Ok(phonemes)
+    }
+    /// Phonemes the model can't speak, which are dropped from its input
+    fn unknown_phonemes(
+        &self,
+        #[allow(unused_variables)] phonemes: &str,
+    ) -> SonataResult<Vec<char>> {
+        Ok(Vec::new())
+    }
     fn properties(&self) -> SonataResult<HashMap<String, String>> {
         Ok(HashMap::with_capacity(0))
     }
+    /// A key that identifies the voice and its current synthesis settings (speaker, scales...).
+    /// Used to cache synthesized audio. `None` means the model's output should not be cached.
+    fn synthesis_cache_key(&self) -> SonataResult<Option<String>> {
+        Ok(None)
+    }
+    /// Approximate memory held by the model. Models that don't track it report nothing
+    fn memory_usage(&self) -> SonataResult<MemoryUsage> {
+        Ok(MemoryUsage::default())
+    }
 
     fn supports_streaming_output(&self) -> bool {
         false
@@ -128,5 +622,120 @@ pub trait SonataModel {
                 "Streaming synthesis is not supported for this model".to_string(),
             ))
     }
+    /// Like `stream_synthesis`, but the stream ends when `control` is stopped. Pausing is
+    /// left to the consumer, so that a paused stream doesn't hold the thread decoding it
+    fn stream_synthesis_with_control(
+        &self,
+        phonemes: String,
+        chunk_size: usize,
+        chunk_padding: usize,
+        control: StreamControl,
+    ) -> SonataResult<AudioStreamIterator<'_>> {
+        let mut stream = self.stream_synthesis(phonemes, chunk_size, chunk_padding)?;
+        Ok(Box::new(std::iter::from_fn(move || {
+            if control.is_stopped() {
+                return None;
+            }
+            stream.next()
+        })))
+    }
+    /// Set how the chunks of streams started from now on are stitched together
+    fn set_chunk_overlap(
+        &self,
+        #[allow(unused_variables)] chunk_overlap: ChunkOverlap,
+    ) -> SonataResult<()> {
+        Err(SonataError::OperationError(
+            "Streaming synthesis is not supported for this model".to_string(),
+        ))
+    }
+    /// Aim for the first chunk of streams to be ready within `latency_target`, and size
+    /// the following chunks from the measured decoding speed instead of `chunk_size`.
+    /// `None` goes back to fixed chunk sizes
+    fn set_latency_target(
+        &self,
+        #[allow(unused_variables)] latency_target: Option<Duration>,
+    ) -> SonataResult<()> {
+        Err(SonataError::OperationError(
+            "Streaming synthesis is not supported for this model".to_string(),
+        ))
+    }
+    /// Decode up to `batch_size` chunks of streams in each decoder run. Batches keep
+    /// GPUs busy, at the cost of some latency
+    fn set_decoder_batch_size(
+        &self,
+        #[allow(unused_variables)] batch_size: usize,
+    ) -> SonataResult<()> {
+        Err(SonataError::OperationError(
+            "Streaming synthesis is not supported for this model".to_string(),
+        ))
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_control() {
+        let control = StreamControl::new();
+        control.pause();
+        assert!(control.is_paused());
+        let waiting = control.clone();
+        let waiter = std::thread::spawn(move || waiting.wait_while_paused());
+        control.resume();
+        waiter.join().unwrap();
+        control.pause();
+        let waiting = control.clone();
+        let waiter = std::thread::spawn(move || waiting.wait_while_paused());
+        control.stop(true);
+        waiter.join().unwrap();
+        assert!(control.is_stopped() && control.fades_out());
+    }
+
+    #[test]
+    fn test_native_rate() {
+        assert_eq!(current_native_rate(), 1.0);
+        let rates = with_native_rate(1.5, || {
+            let inner = with_native_rate(0.8, current_native_rate);
+            (current_native_rate(), inner)
+        });
+        assert_eq!(rates, (1.5, 0.8));
+        assert_eq!(current_native_rate(), 1.0);
+        // Other threads keep their own rate
+        let other = with_native_rate(1.5, || std::thread::spawn(current_native_rate).join());
+        assert_eq!(other.unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_error_causes() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let error =
+            SonataError::OperationError("Failed to read voice".to_string()).caused_by(io_error);
+        assert_eq!(error.to_string(), "Failed to read voice");
+        assert_eq!(error.source().unwrap().to_string(), "no such file");
+        assert!(matches!(
+            error.without_cause(),
+            SonataError::OperationError(_)
+        ));
+        assert_eq!(
+            error.message_with_causes(),
+            "Failed to read voice. Caused by: no such file"
+        );
+    }
+
+    #[test]
+    fn test_unknown_speaker_error() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        let speakers = HashMap::from([(1, "bob".to_string()), (0, "amy".to_string())]);
+        assert_eq!(
+            unknown_speaker_error("eve", &speakers).to_string(),
+            "Speaker `eve` was not found. Available speakers: `amy` (0), `bob` (1)"
+        );
+        let speakers = HashMap::from_iter((0..20).map(|sid| (sid, format!("p{}", 220 + sid))));
+        assert_eq!(
+            unknown_speaker_error("P2300", &speakers).to_string(),
+            "Speaker `P2300` was not found. Did you mean `p230`, `p220`, `p231`? The voice has 20 speakers"
+        );
+    }
+}
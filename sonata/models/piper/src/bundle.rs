@@ -0,0 +1,200 @@
+//! Single-file voice bundles.
+//!
+//! A bundle (`*.sonata`) is a tar archive with the voice's `config.json` and its model:
+//! `model.onnx` (or `model.safetensors`), or `encoder.onnx` and `decoder.onnx` for
+//! streaming voices. It may also carry a `lexicon.txt` and the voice's `LICENSE`.
+//! Bundles are loaded in memory, without being unpacked. Model paths set in a bundled
+//! config are ignored.
+
+use crate::{
+    create_inference_session_from_bytes, load_model_config, model_paths, parse_model_config,
+    VitsModel, VitsStreamingModel,
+};
+use sonata_core::{SonataError, SonataModel, SonataResult};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const BUNDLE_EXTENSION: &str = "sonata";
+
+const CONFIG_ENTRY: &str = "config.json";
+const ONNX_MODEL_ENTRY: &str = "model.onnx";
+const SAFETENSORS_MODEL_ENTRY: &str = "model.safetensors";
+const ENCODER_ENTRY: &str = "encoder.onnx";
+const DECODER_ENTRY: &str = "decoder.onnx";
+const LEXICON_ENTRY: &str = "lexicon.txt";
+const LICENSE_ENTRY: &str = "LICENSE";
+
+/// The files of a voice bundle, read in memory
+pub struct VoiceBundle {
+    path: PathBuf,
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl VoiceBundle {
+    pub fn open(path: &Path) -> SonataResult<Self> {
+        let entries = File::open(path)
+            .and_then(|file| read_entries(BufReader::new(file)))
+            .map_err(|e| {
+                SonataError::FailedToLoadResource(format!(
+                    "Failed to read voice bundle `{}`",
+                    path.display()
+                ))
+                .caused_by(e)
+            })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+    /// The voice's license, if the bundle has one
+    pub fn license(&self) -> Option<&str> {
+        self.entries
+            .get(LICENSE_ENTRY)
+            .and_then(|license| std::str::from_utf8(license).ok())
+    }
+    pub fn lexicon(&self) -> Option<&[u8]> {
+        self.entries.get(LEXICON_ENTRY).map(Vec::as_slice)
+    }
+    pub fn entry_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+    /// Load the bundled voice
+    pub fn load(mut self) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
+        let config_path = self.entry_path(CONFIG_ENTRY);
+        let (config, synth_config) =
+            parse_model_config(self.take(CONFIG_ENTRY)?.as_slice(), &config_path)?;
+        if config.streaming.unwrap_or_default() {
+            let encoder_path = self.entry_path(ENCODER_ENTRY);
+            let encoder =
+                create_inference_session_from_bytes(self.take(ENCODER_ENTRY)?, &encoder_path)?;
+            let decoder_path = self.entry_path(DECODER_ENTRY);
+            let decoder =
+                create_inference_session_from_bytes(self.take(DECODER_ENTRY)?, &decoder_path)?;
+            return Ok(Arc::new(VitsStreamingModel::from_sessions(
+                config,
+                synth_config,
+                encoder,
+                &encoder_path,
+                decoder,
+                &decoder_path,
+            )?));
+        }
+        let model_entry = if self.entries.contains_key(SAFETENSORS_MODEL_ENTRY) {
+            SAFETENSORS_MODEL_ENTRY
+        } else {
+            ONNX_MODEL_ENTRY
+        };
+        let model_path = self.entry_path(model_entry);
+        let session = create_inference_session_from_bytes(self.take(model_entry)?, &model_path)?;
+        Ok(Arc::new(VitsModel::from_session(
+            config,
+            synth_config,
+            session,
+            &model_path,
+        )?))
+    }
+    /// Bundle the voice at `config_path` into `output_path`, along with the `lexicon.txt`
+    /// and `LICENSE` next to the config, if any
+    pub fn pack(config_path: &Path, output_path: &Path) -> SonataResult<()> {
+        let (config, _) = load_model_config(config_path)?;
+        let mut files = vec![(CONFIG_ENTRY, config_path.to_path_buf())];
+        let model_paths = model_paths(config_path, &config)?;
+        if let [encoder_path, decoder_path] = model_paths.as_slice() {
+            files.push((ENCODER_ENTRY, encoder_path.clone()));
+            files.push((DECODER_ENTRY, decoder_path.clone()));
+        } else {
+            let is_safetensors = model_paths[0]
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("safetensors"));
+            let entry = if is_safetensors {
+                SAFETENSORS_MODEL_ENTRY
+            } else {
+                ONNX_MODEL_ENTRY
+            };
+            files.push((entry, model_paths[0].clone()));
+        }
+        for entry in [LEXICON_ENTRY, LICENSE_ENTRY] {
+            let path = config_path.with_file_name(entry);
+            if path.is_file() {
+                files.push((entry, path));
+            }
+        }
+        write_bundle(&files, output_path).map_err(|e| {
+            SonataError::OperationError(format!(
+                "Failed to write voice bundle `{}`",
+                output_path.display()
+            ))
+            .caused_by(e)
+        })
+    }
+    /// A path identifying `entry` in error messages
+    fn entry_path(&self, entry: &str) -> PathBuf {
+        self.path.join(entry)
+    }
+    fn take(&mut self, entry: &str) -> SonataResult<Vec<u8>> {
+        self.entries.remove(entry).ok_or_else(|| {
+            SonataError::FailedToLoadResource(format!(
+                "Voice bundle `{}` has no `{}`",
+                self.path.display(),
+                entry
+            ))
+        })
+    }
+}
+
+fn read_entries(reader: impl Read) -> io::Result<HashMap<String, Vec<u8>>> {
+    let mut entries = HashMap::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        entries.insert(name, data);
+    }
+    Ok(entries)
+}
+
+fn write_bundle(files: &[(&str, PathBuf)], output_path: &Path) -> io::Result<()> {
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(output_path)?));
+    for (entry, path) in files {
+        builder.append_file(entry, &mut File::open(path)?)?;
+    }
+    builder.into_inner()?.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_entries() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in [("./config.json", &b"{}"[..]), ("LICENSE", b"MIT")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        let entries = read_entries(builder.into_inner().unwrap().as_slice()).unwrap();
+        let mut bundle = VoiceBundle {
+            path: PathBuf::from("amy.sonata"),
+            entries,
+        };
+        assert_eq!(bundle.license(), Some("MIT"));
+        assert!(bundle.lexicon().is_none());
+        assert_eq!(bundle.take(CONFIG_ENTRY).unwrap(), b"{}");
+        let error = bundle.take(ONNX_MODEL_ENTRY).unwrap_err().to_string();
+        assert!(error.contains("has no `model.onnx`"), "{}", error);
+    }
+}
@@ -0,0 +1,1112 @@
+//! Native VITS inference using [candle](https://github.com/huggingface/candle).
+//!
+//! Runs Piper voices whose weights were exported to `safetensors` (the generator's `state_dict`,
+//! with or without weight-norm parametrization), without going through ONNX.
+//! Model hyper-parameters are inferred from weight shapes, falling back to Piper's training
+//! defaults for values that cannot be recovered (e.g. resblock dilations).
+//!
+//! Only single-utterance (batch size of one) inference is supported, which is the way
+//! `VitsModel` drives its sessions.
+
+use crate::session::{InferenceSession, ModelIo, ModelIoInfo, SessionInput, SessionOutputs};
+use candle_core::{DType, Device, Module, Tensor, D};
+use candle_nn::VarBuilder;
+use ndarray::ArrayD;
+use sonata_core::{SonataError, SonataResult};
+use std::path::Path;
+
+const LRELU_SLOPE: f64 = 0.1;
+const LAYER_NORM_EPS: f64 = 1e-5;
+const SDP_NUM_BINS: usize = 10;
+const SDP_TAIL_BOUND: f32 = 5.0;
+const SPLINE_MIN_BIN_WIDTH: f32 = 1e-3;
+const SPLINE_MIN_BIN_HEIGHT: f32 = 1e-3;
+const SPLINE_MIN_DERIVATIVE: f32 = 1e-3;
+
+type CandleResult<T> = candle_core::Result<T>;
+
+fn candle_error(error: candle_core::Error) -> SonataError {
+    SonataError::OperationError("Failed to run candle inference".to_string()).caused_by(error)
+}
+
+fn selected_device() -> Device {
+    #[cfg(feature = "candle-cuda")]
+    if let Ok(device) = Device::new_cuda(0) {
+        return device;
+    }
+    Device::Cpu
+}
+
+fn has_tensor(vb: &VarBuilder, name: &str) -> bool {
+    vb.contains_tensor(name)
+}
+
+/// Count consecutive layers, where `name(i)` is a tensor that belongs to the `i`th layer
+fn count_layers(vb: &VarBuilder, name: impl Fn(usize) -> String) -> usize {
+    (0..).take_while(|i| has_tensor(vb, &name(*i))).count()
+}
+
+/// Load a convolution weight, resolving weight-norm parametrization when present.
+fn conv_weight(vb: &VarBuilder) -> CandleResult<Tensor> {
+    if has_tensor(vb, "weight") {
+        return vb.get_unchecked("weight");
+    }
+    let weight_g = vb.get_unchecked("weight_g")?;
+    let weight_v = vb.get_unchecked("weight_v")?;
+    let norm = weight_v.sqr()?.sum_keepdim(1)?.sum_keepdim(2)?.sqrt()?;
+    weight_v.broadcast_mul(&weight_g.broadcast_div(&norm)?)
+}
+
+struct Conv1d {
+    weight: Tensor,
+    bias: Option<Tensor>,
+    padding: usize,
+    dilation: usize,
+    groups: usize,
+}
+
+impl Conv1d {
+    fn load(vb: VarBuilder, padding: usize, dilation: usize, groups: usize) -> CandleResult<Self> {
+        let weight = conv_weight(&vb)?;
+        let bias = if has_tensor(&vb, "bias") {
+            Some(vb.get_unchecked("bias")?)
+        } else {
+            None
+        };
+        Ok(Self {
+            weight,
+            bias,
+            padding,
+            dilation,
+            groups,
+        })
+    }
+    /// Load a convolution with `same` padding
+    fn same(vb: VarBuilder, dilation: usize, groups: usize) -> CandleResult<Self> {
+        let kernel_size = conv_weight(&vb)?.dim(2)?;
+        Self::load(
+            vb,
+            (kernel_size * dilation - dilation) / 2,
+            dilation,
+            groups,
+        )
+    }
+    fn out_channels(&self) -> usize {
+        self.weight.dims()[0]
+    }
+}
+
+impl Module for Conv1d {
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        let x = x.conv1d(&self.weight, self.padding, 1, self.dilation, self.groups)?;
+        match self.bias {
+            Some(ref bias) => x.broadcast_add(&bias.reshape((1, (), 1))?),
+            None => Ok(x),
+        }
+    }
+}
+
+struct ConvTranspose1d {
+    weight: Tensor,
+    bias: Tensor,
+    stride: usize,
+    padding: usize,
+}
+
+impl ConvTranspose1d {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let weight = conv_weight(&vb)?;
+        let bias = vb.get_unchecked("bias")?;
+        // Piper's upsampling layers use `kernel_size = 2 * stride`
+        let kernel_size = weight.dim(2)?;
+        let stride = kernel_size / 2;
+        Ok(Self {
+            weight,
+            bias,
+            stride,
+            padding: (kernel_size - stride) / 2,
+        })
+    }
+}
+
+impl Module for ConvTranspose1d {
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        x.conv_transpose1d(&self.weight, self.padding, 0, self.stride, 1, 1)?
+            .broadcast_add(&self.bias.reshape((1, (), 1))?)
+    }
+}
+
+/// Layer normalization over the channel dimension of `[batch, channels, time]` tensors
+struct ChannelLayerNorm {
+    gamma: Tensor,
+    beta: Tensor,
+}
+
+impl ChannelLayerNorm {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        Ok(Self {
+            gamma: vb.get_unchecked("gamma")?,
+            beta: vb.get_unchecked("beta")?,
+        })
+    }
+}
+
+impl Module for ChannelLayerNorm {
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        let x = x.transpose(1, 2)?;
+        let mean = x.mean_keepdim(D::Minus1)?;
+        let x = x.broadcast_sub(&mean)?;
+        let var = x.sqr()?.mean_keepdim(D::Minus1)?;
+        let x = x.broadcast_div(&(var + LAYER_NORM_EPS)?.sqrt()?)?;
+        x.broadcast_mul(&self.gamma)?
+            .broadcast_add(&self.beta)?
+            .transpose(1, 2)
+    }
+}
+
+struct RelativeMultiHeadAttention {
+    conv_q: Conv1d,
+    conv_k: Conv1d,
+    conv_v: Conv1d,
+    conv_o: Conv1d,
+    emb_rel_k: Tensor,
+    emb_rel_v: Tensor,
+    n_heads: usize,
+    k_channels: usize,
+    window_size: usize,
+}
+
+impl RelativeMultiHeadAttention {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let conv_q = Conv1d::load(vb.pp("conv_q"), 0, 1, 1)?;
+        let emb_rel_k = vb.get_unchecked("emb_rel_k")?.squeeze(0)?;
+        let emb_rel_v = vb.get_unchecked("emb_rel_v")?.squeeze(0)?;
+        let (num_positions, k_channels) = emb_rel_k.dims2()?;
+        Ok(Self {
+            n_heads: conv_q.out_channels() / k_channels,
+            conv_q,
+            conv_k: Conv1d::load(vb.pp("conv_k"), 0, 1, 1)?,
+            conv_v: Conv1d::load(vb.pp("conv_v"), 0, 1, 1)?,
+            conv_o: Conv1d::load(vb.pp("conv_o"), 0, 1, 1)?,
+            emb_rel_k,
+            emb_rel_v,
+            k_channels,
+            window_size: (num_positions - 1) / 2,
+        })
+    }
+    /// Gather indices and validity mask mapping absolute positions to relative ones
+    fn relative_indices(
+        &self,
+        len: usize,
+        device: &Device,
+    ) -> CandleResult<(Tensor, Tensor, Tensor, Tensor)> {
+        let w = self.window_size as isize;
+        let num_rel = 2 * self.window_size + 1;
+        let mut abs_to_rel = Vec::with_capacity(len * len);
+        let mut abs_mask = Vec::with_capacity(len * len);
+        for i in 0..len as isize {
+            for j in 0..len as isize {
+                let rel = j - i + w;
+                abs_to_rel.push(rel.clamp(0, 2 * w) as u32);
+                abs_mask.push(((rel >= 0) && (rel <= 2 * w)) as u8 as f32);
+            }
+        }
+        let mut rel_to_abs = Vec::with_capacity(len * num_rel);
+        let mut rel_mask = Vec::with_capacity(len * num_rel);
+        for i in 0..len as isize {
+            for k in 0..num_rel as isize {
+                let j = i + k - w;
+                rel_to_abs.push(j.clamp(0, len as isize - 1) as u32);
+                rel_mask.push(((j >= 0) && (j < len as isize)) as u8 as f32);
+            }
+        }
+        Ok((
+            Tensor::from_vec(abs_to_rel, (len, len), device)?,
+            Tensor::from_vec(abs_mask, (len, len), device)?,
+            Tensor::from_vec(rel_to_abs, (len, num_rel), device)?,
+            Tensor::from_vec(rel_mask, (len, num_rel), device)?,
+        ))
+    }
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        let (_, channels, len) = x.dims3()?;
+        let split_heads = |t: Tensor| -> CandleResult<Tensor> {
+            t.reshape((self.n_heads, self.k_channels, len))?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let scale = (self.k_channels as f64).sqrt();
+        let query = (split_heads(self.conv_q.forward(x)?)? / scale)?;
+        let key = split_heads(self.conv_k.forward(x)?)?;
+        let value = split_heads(self.conv_v.forward(x)?)?;
+        let (abs_to_rel, abs_mask, rel_to_abs, rel_mask) =
+            self.relative_indices(len, x.device())?;
+
+        let mut scores = query.matmul(&key.t()?.contiguous()?)?;
+        let rel_logits = query.broadcast_matmul(&self.emb_rel_k.t()?.contiguous()?)?;
+        let scores_local = rel_logits
+            .contiguous()?
+            .gather(
+                &abs_to_rel.unsqueeze(0)?.repeat((self.n_heads, 1, 1))?,
+                D::Minus1,
+            )?
+            .broadcast_mul(&abs_mask)?;
+        scores = (scores + scores_local)?;
+        let p_attn = candle_nn::ops::softmax_last_dim(&scores)?;
+
+        let output = p_attn.matmul(&value)?;
+        let relative_weights = p_attn
+            .gather(
+                &rel_to_abs.unsqueeze(0)?.repeat((self.n_heads, 1, 1))?,
+                D::Minus1,
+            )?
+            .broadcast_mul(&rel_mask)?;
+        let output = (output + relative_weights.broadcast_matmul(&self.emb_rel_v)?)?;
+        let output = output
+            .transpose(1, 2)?
+            .contiguous()?
+            .reshape((1, channels, len))?;
+        self.conv_o.forward(&output)
+    }
+}
+
+struct FeedForward {
+    conv_1: Conv1d,
+    conv_2: Conv1d,
+}
+
+impl FeedForward {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        Ok(Self {
+            conv_1: Conv1d::same(vb.pp("conv_1"), 1, 1)?,
+            conv_2: Conv1d::same(vb.pp("conv_2"), 1, 1)?,
+        })
+    }
+}
+
+impl Module for FeedForward {
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        self.conv_2.forward(&self.conv_1.forward(x)?.relu()?)
+    }
+}
+
+struct TextEncoder {
+    emb: Tensor,
+    attn_layers: Vec<RelativeMultiHeadAttention>,
+    norm_layers_1: Vec<ChannelLayerNorm>,
+    ffn_layers: Vec<FeedForward>,
+    norm_layers_2: Vec<ChannelLayerNorm>,
+    proj: Conv1d,
+}
+
+impl TextEncoder {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let num_layers = count_layers(&vb, |i| format!("encoder.attn_layers.{}.conv_q.weight", i));
+        let encoder = vb.pp("encoder");
+        let mut attn_layers = Vec::with_capacity(num_layers);
+        let mut norm_layers_1 = Vec::with_capacity(num_layers);
+        let mut ffn_layers = Vec::with_capacity(num_layers);
+        let mut norm_layers_2 = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            attn_layers.push(RelativeMultiHeadAttention::load(
+                encoder.pp(format!("attn_layers.{}", i)),
+            )?);
+            norm_layers_1.push(ChannelLayerNorm::load(
+                encoder.pp(format!("norm_layers_1.{}", i)),
+            )?);
+            ffn_layers.push(FeedForward::load(encoder.pp(format!("ffn_layers.{}", i)))?);
+            norm_layers_2.push(ChannelLayerNorm::load(
+                encoder.pp(format!("norm_layers_2.{}", i)),
+            )?);
+        }
+        Ok(Self {
+            emb: vb.get_unchecked("emb.weight")?,
+            attn_layers,
+            norm_layers_1,
+            ffn_layers,
+            norm_layers_2,
+            proj: Conv1d::load(vb.pp("proj"), 0, 1, 1)?,
+        })
+    }
+    /// Returns `(x, m_p, logs_p)`
+    fn forward(&self, input_ids: &Tensor) -> CandleResult<(Tensor, Tensor, Tensor)> {
+        let hidden_channels = self.emb.dim(1)?;
+        let x = self.emb.embedding(&input_ids.squeeze(0)?)?;
+        let mut x = (x * (hidden_channels as f64).sqrt())?
+            .t()?
+            .unsqueeze(0)?
+            .contiguous()?;
+        for i in 0..self.attn_layers.len() {
+            let y = self.attn_layers[i].forward(&x)?;
+            x = self.norm_layers_1[i].forward(&(x + y)?)?;
+            let y = self.ffn_layers[i].forward(&x)?;
+            x = self.norm_layers_2[i].forward(&(x + y)?)?;
+        }
+        let stats = self.proj.forward(&x)?;
+        let out_channels = stats.dim(1)? / 2;
+        let m_p = stats.narrow(1, 0, out_channels)?;
+        let logs_p = stats.narrow(1, out_channels, out_channels)?;
+        Ok((x, m_p, logs_p))
+    }
+}
+
+/// Dilated, depth-separable convolutions used by the stochastic duration predictor
+struct DDSConv {
+    convs_sep: Vec<Conv1d>,
+    convs_1x1: Vec<Conv1d>,
+    norms_1: Vec<ChannelLayerNorm>,
+    norms_2: Vec<ChannelLayerNorm>,
+}
+
+impl DDSConv {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let num_layers = count_layers(&vb, |i| format!("convs_1x1.{}.weight", i));
+        let mut convs_sep = Vec::with_capacity(num_layers);
+        let mut convs_1x1 = Vec::with_capacity(num_layers);
+        let mut norms_1 = Vec::with_capacity(num_layers);
+        let mut norms_2 = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            let sep_vb = vb.pp(format!("convs_sep.{}", i));
+            let weight = conv_weight(&sep_vb)?;
+            let (channels, kernel_size) = (weight.dim(0)?, weight.dim(2)?);
+            convs_sep.push(Conv1d::same(sep_vb, kernel_size.pow(i as u32), channels)?);
+            convs_1x1.push(Conv1d::load(vb.pp(format!("convs_1x1.{}", i)), 0, 1, 1)?);
+            norms_1.push(ChannelLayerNorm::load(vb.pp(format!("norms_1.{}", i)))?);
+            norms_2.push(ChannelLayerNorm::load(vb.pp(format!("norms_2.{}", i)))?);
+        }
+        Ok(Self {
+            convs_sep,
+            convs_1x1,
+            norms_1,
+            norms_2,
+        })
+    }
+    fn forward(&self, x: &Tensor, g: Option<&Tensor>) -> CandleResult<Tensor> {
+        let mut x = match g {
+            Some(g) => x.broadcast_add(g)?,
+            None => x.clone(),
+        };
+        for i in 0..self.convs_sep.len() {
+            let y = self.convs_sep[i].forward(&x)?;
+            let y = self.norms_1[i].forward(&y)?.gelu_erf()?;
+            let y = self.convs_1x1[i].forward(&y)?;
+            let y = self.norms_2[i].forward(&y)?.gelu_erf()?;
+            x = (x + y)?;
+        }
+        Ok(x)
+    }
+}
+
+struct ConvFlow {
+    pre: Conv1d,
+    convs: DDSConv,
+    proj: Conv1d,
+    filter_channels: usize,
+}
+
+impl ConvFlow {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let pre = Conv1d::load(vb.pp("pre"), 0, 1, 1)?;
+        Ok(Self {
+            filter_channels: pre.out_channels(),
+            pre,
+            convs: DDSConv::load(vb.pp("convs"))?,
+            proj: Conv1d::load(vb.pp("proj"), 0, 1, 1)?,
+        })
+    }
+    fn reverse(&self, x: &Tensor, g: &Tensor) -> CandleResult<Tensor> {
+        let x0 = x.narrow(1, 0, 1)?;
+        let x1 = x.narrow(1, 1, 1)?;
+        let h = self.pre.forward(&x0)?;
+        let h = self.convs.forward(&h, Some(g))?;
+        let h = self.proj.forward(&h)?;
+        // [1, 3 * num_bins - 1, t] -> [t, 3 * num_bins - 1]
+        let params: Vec<Vec<f32>> = h.squeeze(0)?.t()?.to_dtype(DType::F32)?.to_vec2()?;
+        let inputs: Vec<f32> = x1.flatten_all()?.to_dtype(DType::F32)?.to_vec1()?;
+        let scale = (self.filter_channels as f32).sqrt();
+        let outputs = Vec::from_iter(inputs.into_iter().zip(params).map(|(value, p)| {
+            let widths = Vec::from_iter(p[..SDP_NUM_BINS].iter().map(|v| v / scale));
+            let heights =
+                Vec::from_iter(p[SDP_NUM_BINS..2 * SDP_NUM_BINS].iter().map(|v| v / scale));
+            let derivatives = &p[2 * SDP_NUM_BINS..];
+            inverse_rational_quadratic_spline(value, &widths, &heights, derivatives)
+        }));
+        let x1 = Tensor::from_vec(outputs, x1.shape(), x.device())?.to_dtype(x.dtype())?;
+        Tensor::cat(&[x0, x1], 1)
+    }
+}
+
+fn softplus(x: f32) -> f32 {
+    if x > 20.0 {
+        x
+    } else {
+        x.exp().ln_1p()
+    }
+}
+
+fn softmax(values: &[f32]) -> Vec<f32> {
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps = Vec::from_iter(values.iter().map(|v| (v - max).exp()));
+    let sum: f32 = exps.iter().sum();
+    Vec::from_iter(exps.into_iter().map(|v| v / sum))
+}
+
+/// Bin edges of a rational-quadratic spline over `[-bound, bound]`
+fn spline_knots(unnormalized: &[f32], min_bin_size: f32, bound: f32) -> Vec<f32> {
+    let num_bins = unnormalized.len() as f32;
+    let sizes = Vec::from_iter(
+        softmax(unnormalized)
+            .into_iter()
+            .map(|s| min_bin_size + (1.0 - min_bin_size * num_bins) * s),
+    );
+    let mut knots = Vec::with_capacity(sizes.len() + 1);
+    knots.push(-bound);
+    let mut cumsum = 0.0;
+    for size in sizes.iter() {
+        cumsum += size;
+        knots.push(2.0 * bound * cumsum - bound);
+    }
+    *knots.last_mut().unwrap() = bound;
+    knots
+}
+
+/// Inverse of the piecewise rational-quadratic transform with linear tails,
+/// as used by the stochastic duration predictor's `ConvFlow`.
+fn inverse_rational_quadratic_spline(
+    value: f32,
+    unnormalized_widths: &[f32],
+    unnormalized_heights: &[f32],
+    unnormalized_derivatives: &[f32],
+) -> f32 {
+    let bound = SDP_TAIL_BOUND;
+    if !(-bound..=bound).contains(&value) {
+        return value;
+    }
+    let cumwidths = spline_knots(unnormalized_widths, SPLINE_MIN_BIN_WIDTH, bound);
+    let cumheights = spline_knots(unnormalized_heights, SPLINE_MIN_BIN_HEIGHT, bound);
+    // Linear tails pad the derivatives with a constant that maps to 1
+    let boundary_derivative = (1.0f32 - SPLINE_MIN_DERIVATIVE).exp_m1().ln();
+    let mut derivatives = Vec::with_capacity(unnormalized_derivatives.len() + 2);
+    derivatives.push(boundary_derivative);
+    derivatives.extend_from_slice(unnormalized_derivatives);
+    derivatives.push(boundary_derivative);
+    let derivatives = Vec::from_iter(
+        derivatives
+            .into_iter()
+            .map(|d| SPLINE_MIN_DERIVATIVE + softplus(d)),
+    );
+
+    let num_bins = unnormalized_widths.len();
+    let bin_idx = (0..num_bins)
+        .rev()
+        .find(|&i| value >= cumheights[i])
+        .unwrap_or(0);
+    let input_cumwidth = cumwidths[bin_idx];
+    let input_bin_width = cumwidths[bin_idx + 1] - cumwidths[bin_idx];
+    let input_cumheight = cumheights[bin_idx];
+    let input_height = cumheights[bin_idx + 1] - cumheights[bin_idx];
+    let input_delta = input_height / input_bin_width;
+    let input_derivative = derivatives[bin_idx];
+    let input_derivative_plus_one = derivatives[bin_idx + 1];
+
+    let shifted = value - input_cumheight;
+    let common = input_derivative + input_derivative_plus_one - 2.0 * input_delta;
+    let a = shifted * common + input_height * (input_delta - input_derivative);
+    let b = input_height * input_derivative - shifted * common;
+    let c = -input_delta * shifted;
+    let discriminant = (b * b - 4.0 * a * c).max(0.0);
+    let root = (2.0 * c) / (-b - discriminant.sqrt());
+    root * input_bin_width + input_cumwidth
+}
+
+struct StochasticDurationPredictor {
+    pre: Conv1d,
+    convs: DDSConv,
+    proj: Conv1d,
+    cond: Option<Conv1d>,
+    // Ordered from the last flow to the first, as needed by the reverse pass
+    flows: Vec<ConvFlow>,
+    affine_m: Tensor,
+    affine_logs: Tensor,
+}
+
+impl StochasticDurationPredictor {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        // flows: [ElementwiseAffine, (ConvFlow, Flip) * n]
+        let num_flows = count_layers(&vb, |i| format!("flows.{}.pre.weight", 2 * i + 1));
+        let mut flows = Vec::with_capacity(num_flows);
+        // The first `ConvFlow` is not used at inference
+        for i in (1..num_flows).rev() {
+            flows.push(ConvFlow::load(vb.pp(format!("flows.{}", 2 * i + 1)))?);
+        }
+        let cond = if has_tensor(&vb, "cond.weight") {
+            Some(Conv1d::load(vb.pp("cond"), 0, 1, 1)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            pre: Conv1d::load(vb.pp("pre"), 0, 1, 1)?,
+            convs: DDSConv::load(vb.pp("convs"))?,
+            proj: Conv1d::load(vb.pp("proj"), 0, 1, 1)?,
+            cond,
+            flows,
+            affine_m: vb.get_unchecked("flows.0.m")?.unsqueeze(0)?,
+            affine_logs: vb.get_unchecked("flows.0.logs")?.unsqueeze(0)?,
+        })
+    }
+    fn forward(&self, x: &Tensor, g: Option<&Tensor>, noise_scale: f64) -> CandleResult<Tensor> {
+        let mut x = self.pre.forward(x)?;
+        if let (Some(cond), Some(g)) = (self.cond.as_ref(), g) {
+            x = x.broadcast_add(&cond.forward(g)?)?;
+        }
+        let x = self.convs.forward(&x, None)?;
+        let x = self.proj.forward(&x)?;
+        let len = x.dim(2)?;
+        let mut z = (Tensor::randn(0f32, 1f32, (1, 2, len), x.device())? * noise_scale)?;
+        let flip = |z: &Tensor| -> CandleResult<Tensor> {
+            Tensor::cat(&[z.narrow(1, 1, 1)?, z.narrow(1, 0, 1)?], 1)
+        };
+        for flow in self.flows.iter() {
+            z = flip(&z)?;
+            z = flow.reverse(&z, &x)?;
+        }
+        z = flip(&z)?;
+        // Reverse of `ElementwiseAffine`
+        z = z
+            .broadcast_sub(&self.affine_m)?
+            .broadcast_mul(&self.affine_logs.neg()?.exp()?)?;
+        z.narrow(1, 0, 1)
+    }
+}
+
+struct DurationPredictor {
+    conv_1: Conv1d,
+    norm_1: ChannelLayerNorm,
+    conv_2: Conv1d,
+    norm_2: ChannelLayerNorm,
+    proj: Conv1d,
+    cond: Option<Conv1d>,
+}
+
+impl DurationPredictor {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let cond = if has_tensor(&vb, "cond.weight") {
+            Some(Conv1d::load(vb.pp("cond"), 0, 1, 1)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            conv_1: Conv1d::same(vb.pp("conv_1"), 1, 1)?,
+            norm_1: ChannelLayerNorm::load(vb.pp("norm_1"))?,
+            conv_2: Conv1d::same(vb.pp("conv_2"), 1, 1)?,
+            norm_2: ChannelLayerNorm::load(vb.pp("norm_2"))?,
+            proj: Conv1d::load(vb.pp("proj"), 0, 1, 1)?,
+            cond,
+        })
+    }
+    fn forward(&self, x: &Tensor, g: Option<&Tensor>) -> CandleResult<Tensor> {
+        let mut x = x.clone();
+        if let (Some(cond), Some(g)) = (self.cond.as_ref(), g) {
+            x = x.broadcast_add(&cond.forward(g)?)?;
+        }
+        let x = self.norm_1.forward(&self.conv_1.forward(&x)?.relu()?)?;
+        let x = self.norm_2.forward(&self.conv_2.forward(&x)?.relu()?)?;
+        self.proj.forward(&x)
+    }
+}
+
+enum DurationModel {
+    Stochastic(StochasticDurationPredictor),
+    Deterministic(DurationPredictor),
+}
+
+/// WaveNet-like stack used by the residual coupling flows
+struct WaveNet {
+    in_layers: Vec<Conv1d>,
+    res_skip_layers: Vec<Conv1d>,
+    cond_layer: Option<Conv1d>,
+    hidden_channels: usize,
+}
+
+impl WaveNet {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let num_layers = count_layers(&vb, |i| format!("res_skip_layers.{}.bias", i));
+        let mut in_layers = Vec::with_capacity(num_layers);
+        let mut res_skip_layers = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            in_layers.push(Conv1d::same(vb.pp(format!("in_layers.{}", i)), 1, 1)?);
+            res_skip_layers.push(Conv1d::load(
+                vb.pp(format!("res_skip_layers.{}", i)),
+                0,
+                1,
+                1,
+            )?);
+        }
+        let cond_layer = if has_tensor(&vb, "cond_layer.bias") {
+            Some(Conv1d::load(vb.pp("cond_layer"), 0, 1, 1)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            hidden_channels: in_layers[0].out_channels() / 2,
+            in_layers,
+            res_skip_layers,
+            cond_layer,
+        })
+    }
+    fn forward(&self, x: &Tensor, g: Option<&Tensor>) -> CandleResult<Tensor> {
+        let hidden = self.hidden_channels;
+        let g = match (self.cond_layer.as_ref(), g) {
+            (Some(cond_layer), Some(g)) => Some(cond_layer.forward(g)?),
+            _ => None,
+        };
+        let mut x = x.clone();
+        let mut output = x.zeros_like()?;
+        let num_layers = self.in_layers.len();
+        for i in 0..num_layers {
+            let mut x_in = self.in_layers[i].forward(&x)?;
+            if let Some(ref g) = g {
+                x_in = x_in.broadcast_add(&g.narrow(1, i * 2 * hidden, 2 * hidden)?)?;
+            }
+            let acts = (x_in.narrow(1, 0, hidden)?.tanh()?
+                * candle_nn::ops::sigmoid(&x_in.narrow(1, hidden, hidden)?)?)?;
+            let res_skip_acts = self.res_skip_layers[i].forward(&acts)?;
+            if i < num_layers - 1 {
+                x = (x + res_skip_acts.narrow(1, 0, hidden)?)?;
+                output = (output + res_skip_acts.narrow(1, hidden, hidden)?)?;
+            } else {
+                output = (output + res_skip_acts)?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+struct ResidualCouplingLayer {
+    pre: Conv1d,
+    enc: WaveNet,
+    post: Conv1d,
+}
+
+impl ResidualCouplingLayer {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        Ok(Self {
+            pre: Conv1d::load(vb.pp("pre"), 0, 1, 1)?,
+            enc: WaveNet::load(vb.pp("enc"))?,
+            post: Conv1d::load(vb.pp("post"), 0, 1, 1)?,
+        })
+    }
+    /// Reverse pass of a mean-only coupling layer
+    fn reverse(&self, x: &Tensor, g: Option<&Tensor>) -> CandleResult<Tensor> {
+        let half = x.dim(1)? / 2;
+        let x0 = x.narrow(1, 0, half)?;
+        let x1 = x.narrow(1, half, half)?;
+        let h = self.pre.forward(&x0)?;
+        let h = self.enc.forward(&h, g)?;
+        let m = self.post.forward(&h)?;
+        Tensor::cat(&[x0, (x1 - m)?], 1)
+    }
+}
+
+enum ResBlock {
+    /// Two convolutions (dilated, then plain) per dilation
+    Type1(Vec<(Conv1d, Conv1d)>),
+    /// One dilated convolution per dilation
+    Type2(Vec<Conv1d>),
+}
+
+impl Module for ResBlock {
+    fn forward(&self, x: &Tensor) -> CandleResult<Tensor> {
+        let mut x = x.clone();
+        match self {
+            Self::Type1(convs) => {
+                for (c1, c2) in convs.iter() {
+                    let xt = c1.forward(&candle_nn::ops::leaky_relu(&x, LRELU_SLOPE)?)?;
+                    let xt = c2.forward(&candle_nn::ops::leaky_relu(&xt, LRELU_SLOPE)?)?;
+                    x = (xt + x)?;
+                }
+            }
+            Self::Type2(convs) => {
+                for c in convs.iter() {
+                    let xt = c.forward(&candle_nn::ops::leaky_relu(&x, LRELU_SLOPE)?)?;
+                    x = (xt + x)?;
+                }
+            }
+        }
+        Ok(x)
+    }
+}
+
+/// HiFi-GAN generator
+struct Generator {
+    conv_pre: Conv1d,
+    ups: Vec<ConvTranspose1d>,
+    resblocks: Vec<ResBlock>,
+    conv_post: Conv1d,
+    cond: Option<Conv1d>,
+}
+
+impl Generator {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let num_upsamples = count_layers(&vb, |i| format!("ups.{}.bias", i));
+        let num_resblocks = (0..)
+            .take_while(|i| {
+                has_tensor(&vb, &format!("resblocks.{}.convs1.0.bias", i))
+                    || has_tensor(&vb, &format!("resblocks.{}.convs.0.bias", i))
+            })
+            .count();
+        let is_type1 = has_tensor(&vb, "resblocks.0.convs1.0.bias");
+        let num_kernels = num_resblocks / num_upsamples.max(1);
+        let mut ups = Vec::with_capacity(num_upsamples);
+        for i in 0..num_upsamples {
+            ups.push(ConvTranspose1d::load(vb.pp(format!("ups.{}", i)))?);
+        }
+        let mut resblocks = Vec::with_capacity(num_resblocks);
+        for i in 0..num_resblocks {
+            let rb = vb.pp(format!("resblocks.{}", i));
+            let resblock = if is_type1 {
+                let mut convs = Vec::with_capacity(3);
+                for (j, dilation) in [1, 3, 5].into_iter().enumerate() {
+                    convs.push((
+                        Conv1d::same(rb.pp(format!("convs1.{}", j)), dilation, 1)?,
+                        Conv1d::same(rb.pp(format!("convs2.{}", j)), 1, 1)?,
+                    ));
+                }
+                ResBlock::Type1(convs)
+            } else {
+                // Piper's `x_low` defaults: dilations (1, 2), (2, 6), (3, 12)
+                let dilations = [[1, 2], [2, 6], [3, 12]][(i % num_kernels).min(2)];
+                let mut convs = Vec::with_capacity(2);
+                for (j, dilation) in dilations.into_iter().enumerate() {
+                    convs.push(Conv1d::same(rb.pp(format!("convs.{}", j)), dilation, 1)?);
+                }
+                ResBlock::Type2(convs)
+            };
+            resblocks.push(resblock);
+        }
+        let conv_pre = Conv1d::same(vb.pp("conv_pre"), 1, 1)?;
+        let conv_post = Conv1d::same(vb.pp("conv_post"), 1, 1)?;
+        let cond = if has_tensor(&vb, "cond.weight") {
+            Some(Conv1d::load(vb.pp("cond"), 0, 1, 1)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            conv_pre,
+            ups,
+            resblocks,
+            conv_post,
+            cond,
+        })
+    }
+    fn forward(&self, x: &Tensor, g: Option<&Tensor>) -> CandleResult<Tensor> {
+        let mut x = self.conv_pre.forward(x)?;
+        if let (Some(cond), Some(g)) = (self.cond.as_ref(), g) {
+            x = x.broadcast_add(&cond.forward(g)?)?;
+        }
+        let num_kernels = self.resblocks.len() / self.ups.len();
+        for (i, up) in self.ups.iter().enumerate() {
+            x = up.forward(&candle_nn::ops::leaky_relu(&x, LRELU_SLOPE)?)?;
+            let mut xs = self.resblocks[i * num_kernels].forward(&x)?;
+            for j in 1..num_kernels {
+                xs = (xs + self.resblocks[i * num_kernels + j].forward(&x)?)?;
+            }
+            x = (xs / num_kernels as f64)?;
+        }
+        let x = candle_nn::ops::leaky_relu(&x, 0.01)?;
+        self.conv_post.forward(&x)?.tanh()
+    }
+}
+
+struct SynthesizerTrn {
+    enc_p: TextEncoder,
+    dp: DurationModel,
+    flows: Vec<ResidualCouplingLayer>,
+    dec: Generator,
+    emb_g: Option<Tensor>,
+}
+
+impl SynthesizerTrn {
+    fn load(vb: VarBuilder) -> CandleResult<Self> {
+        let dp = if has_tensor(&vb, "dp.flows.0.m") {
+            DurationModel::Stochastic(StochasticDurationPredictor::load(vb.pp("dp"))?)
+        } else {
+            DurationModel::Deterministic(DurationPredictor::load(vb.pp("dp"))?)
+        };
+        // flows: [(ResidualCouplingLayer, Flip) * n]
+        let num_flows = count_layers(&vb, |i| format!("flow.flows.{}.pre.weight", 2 * i));
+        let mut flows = Vec::with_capacity(num_flows);
+        for i in 0..num_flows {
+            flows.push(ResidualCouplingLayer::load(
+                vb.pp(format!("flow.flows.{}", 2 * i)),
+            )?);
+        }
+        let emb_g = if has_tensor(&vb, "emb_g.weight") {
+            Some(vb.get_unchecked("emb_g.weight")?)
+        } else {
+            None
+        };
+        Ok(Self {
+            enc_p: TextEncoder::load(vb.pp("enc_p"))?,
+            dp,
+            flows,
+            dec: Generator::load(vb.pp("dec"))?,
+            emb_g,
+        })
+    }
+    fn infer(
+        &self,
+        input_ids: &Tensor,
+        noise_scale: f32,
+        length_scale: f32,
+        noise_w: f32,
+        speaker_id: Option<&Tensor>,
+    ) -> CandleResult<Tensor> {
+        let (x, m_p, logs_p) = self.enc_p.forward(input_ids)?;
+        let g = match (self.emb_g.as_ref(), speaker_id) {
+            (Some(emb_g), Some(sid)) => Some(emb_g.embedding(sid)?.unsqueeze(D::Minus1)?),
+            _ => None,
+        };
+        let logw = match self.dp {
+            DurationModel::Stochastic(ref dp) => dp.forward(&x, g.as_ref(), noise_w as f64)?,
+            DurationModel::Deterministic(ref dp) => dp.forward(&x, g.as_ref())?,
+        };
+        let durations: Vec<f32> = (logw.exp()? * length_scale as f64)?
+            .ceil()?
+            .flatten_all()?
+            .to_dtype(DType::F32)?
+            .to_vec1()?;
+        // Expand each phoneme's prior statistics over its predicted number of frames
+        let mut frame_indices: Vec<u32> = Vec::new();
+        for (idx, duration) in durations.into_iter().enumerate() {
+            let num_frames = duration.max(0.0) as usize;
+            frame_indices.extend(std::iter::repeat_n(idx as u32, num_frames));
+        }
+        if frame_indices.is_empty() {
+            frame_indices.push(0);
+        }
+        let num_frames = frame_indices.len();
+        let frame_indices = Tensor::from_vec(frame_indices, num_frames, x.device())?;
+        let m_p = m_p.index_select(&frame_indices, 2)?;
+        let logs_p = logs_p.index_select(&frame_indices, 2)?;
+        let noise = Tensor::randn(0f32, 1f32, m_p.shape(), m_p.device())?.to_dtype(m_p.dtype())?;
+        let mut z = (m_p + (noise * logs_p.exp()?)?.affine(noise_scale as f64, 0.0)?)?;
+        // `Flip` reverses the channel order
+        let num_channels = z.dim(1)? as u32;
+        let flipped = Tensor::from_vec(
+            Vec::from_iter((0..num_channels).rev()),
+            num_channels as usize,
+            z.device(),
+        )?;
+        for flow in self.flows.iter().rev() {
+            z = z.index_select(&flipped, 1)?;
+            z = flow.reverse(&z, g.as_ref())?;
+        }
+        self.dec.forward(&z, g.as_ref())
+    }
+}
+
+pub(crate) struct CandleSession {
+    model: SynthesizerTrn,
+    device: Device,
+}
+
+impl CandleSession {
+    pub(crate) fn from_path(model_path: &Path) -> SonataResult<Self> {
+        let device = selected_device();
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &device) };
+        Self::from_var_builder(vb, device, model_path)
+    }
+    pub(crate) fn from_bytes(model_bytes: Vec<u8>, model_path: &Path) -> SonataResult<Self> {
+        let device = selected_device();
+        let vb = VarBuilder::from_buffered_safetensors(model_bytes, DType::F32, &device);
+        Self::from_var_builder(vb, device, model_path)
+    }
+    fn from_var_builder(
+        vb: CandleResult<VarBuilder<'static>>,
+        device: Device,
+        model_path: &Path,
+    ) -> SonataResult<Self> {
+        let model = vb.and_then(SynthesizerTrn::load).map_err(|err| {
+            SonataError::OperationError(format!(
+                "Failed to load safetensors VITS model `{}`",
+                model_path.display()
+            ))
+            .caused_by(err)
+        })?;
+        Ok(Self { model, device })
+    }
+    fn infer(&self, inputs: Vec<SessionInput<'_>>) -> CandleResult<Vec<f32>> {
+        let mut inputs = inputs.into_iter();
+        let (Some(SessionInput::Int64(input_ids)), Some(_), Some(SessionInput::Float32(scales))) =
+            (inputs.next(), inputs.next(), inputs.next())
+        else {
+            return Err(candle_core::Error::Msg(
+                "Expected inputs: `input`, `input_lengths`, `scales` and optionally `sid`"
+                    .to_string(),
+            ));
+        };
+        let [1, input_len] = input_ids.shape() else {
+            return Err(candle_core::Error::Msg(format!(
+                "Expected `input` of shape [1, num_ids], got {:?}",
+                input_ids.shape()
+            )));
+        };
+        let input_len = *input_len;
+        if input_len == 0 {
+            return Err(candle_core::Error::Msg("`input` has no ids".to_string()));
+        }
+        let scales = Vec::from_iter(scales.iter().copied());
+        let [noise_scale, length_scale, noise_w] = scales[..] else {
+            return Err(candle_core::Error::Msg(format!(
+                "Expected 3 `scales`, got {}",
+                scales.len()
+            )));
+        };
+        let speaker_id = match inputs.next() {
+            Some(SessionInput::Int64(sid)) if sid.len() == 1 => Some(Tensor::from_vec(
+                Vec::from_iter(sid.iter().map(|i| *i as u32)),
+                1,
+                &self.device,
+            )?),
+            Some(SessionInput::Int64(sid)) => {
+                return Err(candle_core::Error::Msg(format!(
+                    "Expected a single `sid`, got {}",
+                    sid.len()
+                )))
+            }
+            _ => None,
+        };
+        let input_ids = Tensor::from_vec(
+            Vec::from_iter(input_ids.iter().map(|i| *i as u32)),
+            (1, input_len),
+            &self.device,
+        )?;
+        let audio = self.model.infer(
+            &input_ids,
+            noise_scale,
+            length_scale,
+            noise_w,
+            speaker_id.as_ref(),
+        )?;
+        audio.flatten_all()?.to_vec1()
+    }
+}
+
+impl InferenceSession for CandleSession {
+    fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+        let audio = self.infer(inputs).map_err(candle_error)?;
+        let num_samples = audio.len();
+        let output = ArrayD::from_shape_vec(vec![1, 1, num_samples], audio)
+            .map_err(|e| SonataError::OperationError(e.to_string()))?;
+        Ok(SessionOutputs::new(vec![("output".to_string(), output)]))
+    }
+    /// The signature of Piper's exported ONNX graph, which this backend mirrors
+    fn io_info(&self) -> ModelIo {
+        let info = |name: &str, dims: &[Option<i64>], dtype: &str| ModelIoInfo {
+            name: name.to_string(),
+            dims: dims.to_vec(),
+            dtype: dtype.to_string(),
+        };
+        let mut inputs = vec![
+            info("input", &[Some(1), None], "int64"),
+            info("input_lengths", &[Some(1)], "int64"),
+            info("scales", &[Some(3)], "float32"),
+        ];
+        if self.model.emb_g.is_some() {
+            inputs.push(info("sid", &[Some(1)], "int64"));
+        }
+        ModelIo {
+            inputs,
+            outputs: vec![info("output", &[Some(1), Some(1), None], "float32")],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{Array1, Array2};
+    use sonata_test_utils::TinyVoice;
+
+    fn inputs(ids: &[i64], scales: &[f32]) -> Vec<SessionInput<'static>> {
+        vec![
+            SessionInput::Int64(
+                Array2::from_shape_vec((1, ids.len()), ids.to_vec())
+                    .unwrap()
+                    .into_dyn()
+                    .into(),
+            ),
+            SessionInput::Int64(Array1::from_elem(1, ids.len() as i64).into_dyn().into()),
+            SessionInput::Float32(Array1::from_vec(scales.to_vec()).into_dyn().into()),
+        ]
+    }
+
+    #[test]
+    fn test_forward() {
+        let session = CandleSession::from_bytes(
+            TinyVoice::new().safetensors_bytes(),
+            Path::new("tiny.safetensors"),
+        )
+        .unwrap();
+        assert_eq!(session.io_info().inputs.len(), 3);
+        let ids = [1, 4, 0, 5, 0, 2];
+        let outputs = session.run(inputs(&ids, &[0.0, 1.0, 0.8])).unwrap();
+        let audio = outputs.into_first().unwrap();
+        // One frame per id, of 4 samples
+        assert_eq!(audio.shape(), &[1, 1, ids.len() * 4]);
+        assert!(audio.iter().all(|sample| sample.is_finite()));
+        let middle = audio[[0, 0, 12]];
+        assert!(
+            middle > 0.1
+                && audio
+                    .iter()
+                    .skip(4)
+                    .take(16)
+                    .all(|sample| *sample == middle)
+        );
+        // Twice as long
+        let audio = session
+            .run(inputs(&ids, &[0.0, 2.0, 0.8]))
+            .unwrap()
+            .into_first()
+            .unwrap();
+        assert_eq!(audio.len(), ids.len() * 8);
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let session = CandleSession::from_bytes(
+            TinyVoice::new().safetensors_bytes(),
+            Path::new("tiny.safetensors"),
+        )
+        .unwrap();
+        assert!(session.run(inputs(&[1, 4, 2], &[0.667, 1.0])).is_err());
+        assert!(session.run(inputs(&[], &[0.667, 1.0, 0.8])).is_err());
+        let mut flat = inputs(&[1, 4, 2], &[0.667, 1.0, 0.8]);
+        flat[0] = SessionInput::Int64(Array1::from_vec(vec![1, 4, 2]).into_dyn().into());
+        assert!(session.run(flat).is_err());
+        assert!(session.run(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_spline_is_identity_outside_tail_bound() {
+        let params = [0.0f32; SDP_NUM_BINS];
+        let derivatives = [0.0f32; SDP_NUM_BINS - 1];
+        assert_eq!(
+            inverse_rational_quadratic_spline(7.5, &params, &params, &derivatives),
+            7.5
+        );
+    }
+
+    #[test]
+    fn test_spline_with_uniform_bins_and_unit_derivatives_is_identity() {
+        let params = [0.0f32; SDP_NUM_BINS];
+        // softplus(d) + min_derivative == 1
+        let unit = (1.0f32 - SPLINE_MIN_DERIVATIVE).exp_m1().ln();
+        let derivatives = [unit; SDP_NUM_BINS - 1];
+        for value in [-4.0f32, -1.3, 0.0, 0.42, 2.5] {
+            let out = inverse_rational_quadratic_spline(value, &params, &params, &derivatives);
+            assert!((out - value).abs() < 1e-4, "{} != {}", out, value);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Pitch and energy contours for voices exported with conditioning inputs.
+//!
+//! Such voices take an `f0` and/or an `energy` input after the standard ones: a
+//! `float32` tensor of shape `[1, num_phoneme_ids]`, with a value for each input id.
+
+use crate::session::{ModelIo, SessionInput};
+use ndarray::Array2;
+use sonata_core::{Contour, ProsodyFeature, SonataError, SonataResult};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Names of the conditioning inputs in the model graph
+const CONTOUR_INPUTS: [(ProsodyFeature, &str); 2] = [
+    (ProsodyFeature::Pitch, "f0"),
+    (ProsodyFeature::Energy, "energy"),
+];
+
+/// The conditioning inputs of a model, and the contours fed to them
+pub(crate) struct ContourInputs {
+    /// In graph order
+    features: Vec<ProsodyFeature>,
+    contours: RwLock<HashMap<ProsodyFeature, Contour>>,
+}
+
+impl ContourInputs {
+    pub(crate) fn new(io: &ModelIo) -> Self {
+        Self {
+            features: contour_features(io),
+            contours: RwLock::default(),
+        }
+    }
+    pub(crate) fn features(&self) -> &[ProsodyFeature] {
+        &self.features
+    }
+    pub(crate) fn set(
+        &self,
+        feature: ProsodyFeature,
+        contour: Option<Contour>,
+    ) -> SonataResult<()> {
+        if !self.features.contains(&feature) {
+            return Err(SonataError::OperationError(format!(
+                "This voice has no `{}` input",
+                input_name(feature)
+            )));
+        }
+        let mut contours = self.contours.write().unwrap();
+        match contour {
+            Some(contour) => {
+                let is_finite = [contour.shift, contour.range]
+                    .iter()
+                    .chain(contour.frames.iter())
+                    .all(|value| value.is_finite());
+                if !is_finite {
+                    return Err(SonataError::OperationError(format!(
+                        "Invalid {:?} contour: it has values that are not finite",
+                        feature
+                    )));
+                }
+                contours.insert(feature, contour);
+            }
+            None => {
+                contours.remove(&feature);
+            }
+        }
+        Ok(())
+    }
+    /// The inputs that follow the standard ones, for `num_ids` input phoneme ids
+    pub(crate) fn session_inputs(&self, num_ids: usize) -> Vec<SessionInput<'static>> {
+        let contours = self.contours.read().unwrap();
+        Vec::from_iter(self.features.iter().map(|feature| {
+            let values = match contours.get(feature) {
+                Some(contour) => contour_values(contour, num_ids),
+                None => vec![0.0; num_ids],
+            };
+            let values = Array2::from_shape_vec((1, num_ids), values).unwrap();
+            SessionInput::Float32(values.into_dyn().into())
+        }))
+    }
+    /// Part of the synthesis cache key, as the contours change the audio
+    pub(crate) fn cache_key(&self) -> String {
+        let contours = self.contours.read().unwrap();
+        let mut key = String::new();
+        for feature in self.features.iter() {
+            if let Some(contour) = contours.get(feature) {
+                key.push_str(&format!(
+                    ":{:?}{:?}{}{}",
+                    feature, contour.frames, contour.shift, contour.range
+                ));
+            }
+        }
+        key
+    }
+}
+
+/// The conditioning inputs of a model, in graph order
+pub(crate) fn contour_features(io: &ModelIo) -> Vec<ProsodyFeature> {
+    Vec::from_iter(io.inputs.iter().filter_map(|input| {
+        CONTOUR_INPUTS
+            .iter()
+            .find(|(_, name)| input.name == *name)
+            .map(|(feature, _)| *feature)
+    }))
+}
+
+pub(crate) fn input_name(feature: ProsodyFeature) -> &'static str {
+    CONTOUR_INPUTS
+        .iter()
+        .find(|(input_feature, _)| *input_feature == feature)
+        .map(|(_, name)| *name)
+        .unwrap()
+}
+
+/// `len` values of `contour`, interpolating its frames linearly
+fn contour_values(contour: &Contour, len: usize) -> Vec<f32> {
+    let frames = &contour.frames;
+    let values = Vec::from_iter((0..len).map(|i| match frames.len() {
+        0 => 0.0,
+        1 => frames[0],
+        num_frames => {
+            let position = match len {
+                1 => 0.0,
+                _ => i as f32 * (num_frames - 1) as f32 / (len - 1) as f32,
+            };
+            let index = (position as usize).min(num_frames - 2);
+            let fraction = position - index as f32;
+            frames[index] * (1.0 - fraction) + frames[index + 1] * fraction
+        }
+    }));
+    let mean = values.iter().sum::<f32>() / len.max(1) as f32;
+    Vec::from_iter(
+        values
+            .into_iter()
+            .map(|value| mean + (value - mean) * contour.range + contour.shift),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contour_values() {
+        assert_eq!(contour_values(&Contour::flat(0.5), 3), vec![0.5; 3]);
+        let rising = Contour::from_frames(vec![0.0, 2.0]);
+        assert_eq!(contour_values(&rising, 5), vec![0.0, 0.5, 1.0, 1.5, 2.0]);
+        let expanded = Contour::from_frames(vec![0.0, 2.0]).with_range(2.0);
+        assert_eq!(contour_values(&expanded, 3), vec![-1.0, 1.0, 3.0]);
+        assert_eq!(contour_values(&rising, 1), vec![0.0]);
+    }
+}
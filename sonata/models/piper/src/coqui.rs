@@ -0,0 +1,384 @@
+//! VITS voices exported to ONNX by Coqui TTS.
+//!
+//! Coqui's ONNX export takes the inputs of a Piper model (`input`, `input_lengths`,
+//! `scales` and, for multi-speaker voices, `sid`), but the voice's `config.json` and its
+//! tokenizer are Coqui's. The config is recognized by its `characters` section, and
+//! mapped onto a [`ModelConfig`]: the vocabulary is rebuilt the way Coqui's
+//! `characters_class` builds it (`VitsCharacters`, `IPAPhonemes` or `Graphemes`), and
+//! phoneme ids are laid out by [`CoquiTokenizer`], with the blanks and `<BOS>`/`<EOS>`
+//! tokens the voice was trained with.
+//!
+//! Voices using phonemes are phonemized with espeak-ng in `phoneme_language`.
+//! Character-based voices (`"use_phonemes": false`) are given their text, lowercased
+//! unless the voice has no text cleaner. Voices phonemized with gruut, multilingual
+//! voices and voices with speaker embeddings from a d-vector file are not supported.
+//!
+//! Name the model and its config like Piper's, e.g. `voice.onnx` and `voice.onnx.json`.
+
+use crate::{
+    AudioConfig, ESpeakConfig, InferenceConfig, ModelConfig, PhonemeType, DEFAULT_NATIVE_RATE_RANGE,
+};
+use serde::de::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct CoquiConfig {
+    model: String,
+    audio: CoquiAudioConfig,
+    #[serde(default)]
+    use_phonemes: bool,
+    phonemizer: Option<String>,
+    phoneme_language: Option<String>,
+    text_cleaner: Option<String>,
+    #[serde(default)]
+    add_blank: bool,
+    #[serde(default)]
+    enable_eos_bos_chars: bool,
+    characters: CoquiCharacters,
+    #[serde(default)]
+    model_args: CoquiModelArgs,
+}
+
+#[derive(Deserialize)]
+struct CoquiAudioConfig {
+    sample_rate: u32,
+}
+
+#[derive(Deserialize)]
+struct CoquiCharacters {
+    characters_class: Option<String>,
+    pad: Option<String>,
+    eos: Option<String>,
+    bos: Option<String>,
+    blank: Option<String>,
+    characters: Option<String>,
+    punctuations: Option<String>,
+    phonemes: Option<String>,
+    #[serde(default)]
+    is_unique: bool,
+    #[serde(default = "default_is_sorted")]
+    is_sorted: bool,
+}
+
+fn default_is_sorted() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct CoquiModelArgs {
+    num_speakers: u32,
+    use_speaker_embedding: bool,
+    use_d_vector_file: bool,
+    num_languages: u32,
+    use_language_embedding: bool,
+    inference_noise_scale: f32,
+    length_scale: f32,
+    inference_noise_scale_dp: f32,
+}
+
+impl Default for CoquiModelArgs {
+    fn default() -> Self {
+        Self {
+            num_speakers: 0,
+            use_speaker_embedding: false,
+            use_d_vector_file: false,
+            num_languages: 0,
+            use_language_embedding: false,
+            inference_noise_scale: 0.667,
+            length_scale: 1.0,
+            inference_noise_scale_dp: 1.0,
+        }
+    }
+}
+
+/// How a voice exported from Coqui TTS takes its phoneme ids and text
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CoquiTokenizer {
+    /// Put between phonemes and around them, if the voice was trained with blanks
+    blank_id: Option<i64>,
+    bos_eos_ids: Option<(i64, i64)>,
+    /// Lowercase the text of character-based voices
+    lowercase: bool,
+}
+
+impl CoquiTokenizer {
+    /// The ids of `phonemes`, as Coqui's `TTSTokenizer.text_to_ids` lays them out.
+    /// Phonemes missing from `phoneme_id_map` are left out
+    pub(crate) fn input_ids(
+        &self,
+        phonemes: &str,
+        phoneme_id_map: &HashMap<char, Vec<i64>>,
+    ) -> Vec<i64> {
+        let ids = phonemes
+            .chars()
+            .filter_map(|phoneme| phoneme_id_map.get(&phoneme).map(|ids| ids[0]));
+        let mut input_ids = Vec::with_capacity((phonemes.len() + 2) * 2);
+        if let Some((bos_id, _)) = self.bos_eos_ids {
+            input_ids.push(bos_id);
+        }
+        match self.blank_id {
+            Some(blank_id) => {
+                input_ids.push(blank_id);
+                for id in ids {
+                    input_ids.push(id);
+                    input_ids.push(blank_id);
+                }
+            }
+            None => input_ids.extend(ids),
+        }
+        if let Some((_, eos_id)) = self.bos_eos_ids {
+            input_ids.push(eos_id);
+        }
+        input_ids
+    }
+    /// Index of the input id of the first phoneme in `input_ids`, and the step from one
+    /// phoneme's id to the next
+    pub(crate) fn phoneme_layout(&self) -> (usize, usize) {
+        let first_index =
+            usize::from(self.bos_eos_ids.is_some()) + usize::from(self.blank_id.is_some());
+        let step = if self.blank_id.is_some() { 2 } else { 1 };
+        (first_index, step)
+    }
+    /// The text a character-based voice is given, cleaned like Coqui's basic cleaners
+    pub(crate) fn clean_text(&self, text: &str) -> String {
+        let text = Vec::from_iter(text.split_whitespace()).join(" ");
+        if self.lowercase {
+            text.to_lowercase()
+        } else {
+            text
+        }
+    }
+}
+
+/// Whether `value` is the config of a voice exported from Coqui TTS
+pub(crate) fn is_coqui_config(value: &serde_json::Value) -> bool {
+    value
+        .get("characters")
+        .is_some_and(|chars| chars.is_object())
+        && value.get("phoneme_id_map").is_none()
+}
+
+/// Map the config of a Coqui voice onto a [`ModelConfig`]
+pub(crate) fn model_config(value: serde_json::Value) -> serde_json::Result<ModelConfig> {
+    let config: CoquiConfig = serde_json::from_value(value)?;
+    let args = &config.model_args;
+    let unsupported = if config.model != "vits" {
+        Some(format!("`{}` models", config.model))
+    } else if config.use_phonemes && config.phonemizer.as_deref().is_some_and(|p| p == "gruut") {
+        Some("the gruut phonemizer".to_string())
+    } else if args.use_language_embedding && args.num_languages > 1 {
+        Some("multilingual voices".to_string())
+    } else if args.use_d_vector_file {
+        Some("speaker embeddings from a d-vector file".to_string())
+    } else {
+        None
+    };
+    if let Some(unsupported) = unsupported {
+        return Err(serde_json::Error::custom(format!(
+            "Coqui TTS voices: {} are not supported",
+            unsupported
+        )));
+    }
+    let vocabulary = vocabulary(&config.characters);
+    let token_id = |token: &Option<String>, name: &str| -> serde_json::Result<i64> {
+        token
+            .as_ref()
+            .and_then(|token| vocabulary.iter().rposition(|entry| entry == token))
+            .map(|id| id as i64)
+            .ok_or_else(|| {
+                serde_json::Error::custom(format!("The vocabulary of the voice has no {}", name))
+            })
+    };
+    let chars = &config.characters;
+    let blank_id = if !config.add_blank {
+        None
+    } else if chars.blank.is_some() {
+        Some(token_id(&chars.blank, "blank")?)
+    } else {
+        Some(token_id(&chars.pad, "padding")?)
+    };
+    let bos_eos_ids = if config.enable_eos_bos_chars {
+        Some((
+            token_id(&chars.bos, "beginning of sentence")?,
+            token_id(&chars.eos, "end of sentence")?,
+        ))
+    } else {
+        None
+    };
+    // Later entries win, as in Coqui's `_char_to_id`
+    let mut phoneme_id_map = HashMap::new();
+    for (id, token) in vocabulary.iter().enumerate() {
+        let mut token_chars = token.chars();
+        if let (Some(phoneme), None) = (token_chars.next(), token_chars.next()) {
+            phoneme_id_map.insert(phoneme, vec![id as i64]);
+        }
+    }
+    let num_speakers = if args.use_speaker_embedding {
+        args.num_speakers.max(1)
+    } else {
+        1
+    };
+    let phoneme_type = if config.use_phonemes {
+        PhonemeType::ESpeak
+    } else {
+        PhonemeType::Text
+    };
+    let lowercase = !matches!(config.text_cleaner.as_deref(), None | Some("no_cleaners"));
+    Ok(ModelConfig {
+        audio: AudioConfig {
+            sample_rate: config.audio.sample_rate,
+            quality: None,
+        },
+        num_speakers,
+        espeak: ESpeakConfig {
+            voice: config.phoneme_language.unwrap_or_default(),
+        },
+        phoneme_type,
+        inference: InferenceConfig {
+            noise_scale: args.inference_noise_scale,
+            length_scale: args.length_scale,
+            noise_w: args.inference_noise_scale_dp,
+            rate_range: DEFAULT_NATIVE_RATE_RANGE,
+            prosody_variation: None,
+        },
+        num_symbols: vocabulary.len() as u32,
+        phoneme_id_map,
+        coqui: Some(CoquiTokenizer {
+            blank_id,
+            bos_eos_ids,
+            lowercase,
+        }),
+        ..Default::default()
+    })
+}
+
+/// The tokens of the voice by id, as its `characters_class` orders them
+fn vocabulary(chars: &CoquiCharacters) -> Vec<String> {
+    let class = chars.characters_class.as_deref().unwrap_or_default();
+    let to_tokens = |string: &Option<String>| -> Vec<String> {
+        string
+            .as_deref()
+            .unwrap_or_default()
+            .chars()
+            .map(String::from)
+            .collect()
+    };
+    let punctuations = to_tokens(&chars.punctuations);
+    if class.ends_with("VitsCharacters") {
+        let mut vocabulary = Vec::from_iter(chars.pad.clone());
+        vocabulary.extend(punctuations);
+        vocabulary.extend(to_tokens(&chars.characters));
+        vocabulary.extend(chars.blank.clone());
+        return vocabulary;
+    }
+    let characters = if class.ends_with("IPAPhonemes") && chars.phonemes.is_some() {
+        &chars.phonemes
+    } else {
+        &chars.characters
+    };
+    let mut characters = to_tokens(characters);
+    if chars.is_unique {
+        let mut seen = std::collections::HashSet::new();
+        characters.retain(|token| seen.insert(token.clone()));
+    }
+    if chars.is_sorted {
+        characters.sort();
+    }
+    let specials = [&chars.pad, &chars.eos, &chars.bos, &chars.blank];
+    let mut vocabulary = Vec::from_iter(
+        specials
+            .into_iter()
+            .filter_map(|token| token.clone().filter(|token| !token.is_empty())),
+    );
+    vocabulary.extend(characters);
+    vocabulary.extend(punctuations);
+    vocabulary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn coqui_config(characters: serde_json::Value) -> serde_json::Value {
+        json!({
+            "model": "vits",
+            "audio": {"sample_rate": 22050, "fft_size": 1024},
+            "use_phonemes": true,
+            "phonemizer": "espeak",
+            "phoneme_language": "en-us",
+            "text_cleaner": "phoneme_cleaners",
+            "add_blank": true,
+            "characters": characters,
+            "model_args": {"num_speakers": 0, "inference_noise_scale": 0.5},
+        })
+    }
+
+    #[test]
+    fn test_vits_characters() {
+        let value = coqui_config(json!({
+            "characters_class": "TTS.tts.models.vits.VitsCharacters",
+            "pad": "_",
+            "eos": "",
+            "bos": "",
+            "blank": null,
+            "characters": "abə",
+            "punctuations": "!. ",
+            "is_unique": false,
+            "is_sorted": true
+        }));
+        assert!(is_coqui_config(&value));
+        let config = model_config(value).unwrap();
+        // `_`, then the punctuations and the characters
+        assert_eq!(config.num_symbols, 7);
+        assert_eq!(config.phoneme_id_map[&' '], [3]);
+        assert_eq!(config.phoneme_id_map[&'ə'], [6]);
+        assert_eq!(config.espeak.voice, "en-us");
+        assert_eq!(config.inference.noise_scale, 0.5);
+        assert_eq!(config.inference.noise_w, 1.0);
+        let tokenizer = config.coqui.unwrap();
+        // Without a blank token, the padding is put between phonemes
+        assert_eq!(
+            tokenizer.input_ids("ab x.", &config.phoneme_id_map),
+            [0, 4, 0, 5, 0, 3, 0, 2, 0]
+        );
+        assert_eq!(tokenizer.phoneme_layout(), (1, 2));
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut value = coqui_config(json!({
+            "characters_class": "TTS.tts.utils.text.characters.Graphemes",
+            "pad": "<PAD>",
+            "eos": "<EOS>",
+            "bos": "<BOS>",
+            "blank": "<BLNK>",
+            "characters": "cbab",
+            "punctuations": "!",
+            "is_unique": true,
+            "is_sorted": true
+        }));
+        value["use_phonemes"] = json!(false);
+        value["add_blank"] = json!(false);
+        value["enable_eos_bos_chars"] = json!(true);
+        let config = ModelConfig::from_value(value.clone()).unwrap();
+        assert!(config.phoneme_type == PhonemeType::Text);
+        // The special tokens, then `abc` and `!`
+        assert_eq!(config.num_symbols, 8);
+        assert_eq!(config.phoneme_id_map[&'a'], [4]);
+        assert_eq!(config.phoneme_id_map[&'!'], [7]);
+        let tokenizer = config.coqui.unwrap();
+        assert_eq!(
+            tokenizer.input_ids("cab!", &config.phoneme_id_map),
+            [2, 6, 4, 5, 7, 1]
+        );
+        assert_eq!(tokenizer.phoneme_layout(), (1, 1));
+        assert_eq!(tokenizer.clean_text("  Hello\n World "), "hello world");
+
+        value["model_args"]["use_d_vector_file"] = json!(true);
+        let error = model_config(value).err().unwrap().to_string();
+        assert!(error.contains("d-vector"), "{}", error);
+    }
+}
@@ -0,0 +1,280 @@
+//! Replicating voices across devices.
+//!
+//! Voices loaded inside [`with_device_pool`] get one inference session per device, and
+//! each inference runs on one of them, picked by the [`DispatchPolicy`]. A server with
+//! several GPUs can so serve a busy voice from all of them in one process.
+
+use crate::onnxruntime::with_execution_providers;
+use crate::session::{InferenceSession, ModelIo, SessionInput, SessionOutputs};
+use crate::ExecutionProvider;
+use sonata_core::{MemoryUsage, SonataError, SonataResult};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    /// The devices the sessions created on this thread are replicated on, if any
+    static DEVICE_POOL: RefCell<Option<(Vec<ExecutionProvider>, DispatchPolicy)>> =
+        const { RefCell::new(None) };
+}
+
+/// How an inference picks the device it runs on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchPolicy {
+    /// Each device in turn
+    RoundRobin,
+    /// The device running the fewest inferences, taking turns between equally busy ones
+    #[default]
+    LeastLoaded,
+}
+
+impl FromStr for DispatchPolicy {
+    type Err = SonataError;
+
+    /// `round_robin` or `least_loaded`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "round_robin" => Ok(Self::RoundRobin),
+            "least_loaded" => Ok(Self::LeastLoaded),
+            _ => Err(SonataError::OperationError(format!(
+                "Unknown dispatch policy `{}`. Expected `round_robin` or `least_loaded`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Run `load` with the sessions it creates on this thread, and so the voices it loads,
+/// replicated on each of `devices`, and dispatched to them by `policy`.
+///
+/// Each replica holds its own copy of the model, in the memory of its device.
+pub fn with_device_pool<T>(
+    devices: Vec<ExecutionProvider>,
+    policy: DispatchPolicy,
+    load: impl FnOnce() -> T,
+) -> T {
+    with_pool(Some((devices, policy)), load)
+}
+
+/// The devices and policy given to [`with_device_pool`] on this thread, if any
+pub(crate) fn current_pool() -> Option<(Vec<ExecutionProvider>, DispatchPolicy)> {
+    DEVICE_POOL.with(|pool| pool.borrow().clone())
+}
+
+/// Run `load` with the sessions it creates on this thread replicated as `pool` says, or
+/// not replicated if `None`
+pub(crate) fn with_pool<T>(
+    pool: Option<(Vec<ExecutionProvider>, DispatchPolicy)>,
+    load: impl FnOnce() -> T,
+) -> T {
+    let previous = DEVICE_POOL.with(|current| current.replace(pool));
+    // Restores the previous pool even if `load` panics
+    struct Restore(Option<(Vec<ExecutionProvider>, DispatchPolicy)>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DEVICE_POOL.with(|pool| *pool.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(previous);
+    load()
+}
+
+/// Create a session with `create`, or one per device of the pool set up by
+/// [`with_device_pool`]
+pub(crate) fn replicate(
+    create: impl Fn() -> SonataResult<Box<dyn InferenceSession>>,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    let Some((devices, policy)) = DEVICE_POOL.with(|pool| pool.borrow().clone()) else {
+        return create();
+    };
+    // The replicas themselves are placed on a single device each, outside of the pool,
+    // which is restored even if `create` panics
+    let mut sessions = with_pool(None, || {
+        devices
+            .iter()
+            .map(|device| with_execution_providers(vec![*device], &create))
+            .collect::<SonataResult<Vec<_>>>()
+    })?;
+    match sessions.len() {
+        0 => create(),
+        1 => Ok(sessions.remove(0)),
+        _ => Ok(Box::new(SessionPool::new(sessions, policy))),
+    }
+}
+
+struct Replica {
+    session: Box<dyn InferenceSession>,
+    /// Inferences running on the replica
+    running: AtomicUsize,
+}
+
+/// Sessions of the same model on several devices
+struct SessionPool {
+    replicas: Vec<Replica>,
+    policy: DispatchPolicy,
+    /// The replica to start looking from, rotating to share the load
+    next: AtomicUsize,
+}
+
+impl SessionPool {
+    fn new(sessions: Vec<Box<dyn InferenceSession>>, policy: DispatchPolicy) -> Self {
+        Self {
+            replicas: Vec::from_iter(sessions.into_iter().map(|session| Replica {
+                session,
+                running: AtomicUsize::new(0),
+            })),
+            policy,
+            next: AtomicUsize::new(0),
+        }
+    }
+    fn pick(&self) -> &Replica {
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+        let index = match self.policy {
+            DispatchPolicy::RoundRobin => start,
+            DispatchPolicy::LeastLoaded => (0..self.replicas.len())
+                .map(|offset| (start + offset) % self.replicas.len())
+                .min_by_key(|index| self.replicas[*index].running.load(Ordering::Relaxed))
+                .unwrap_or(start),
+        };
+        &self.replicas[index]
+    }
+}
+
+impl InferenceSession for SessionPool {
+    fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+        let replica = self.pick();
+        replica.running.fetch_add(1, Ordering::Relaxed);
+        // Counts the inference as finished even if it panics
+        struct Finish<'a>(&'a AtomicUsize);
+        impl Drop for Finish<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        let _finish = Finish(&replica.running);
+        replica.session.run(inputs)
+    }
+    fn io_info(&self) -> ModelIo {
+        self.replicas[0].session.io_info()
+    }
+    fn memory_usage(&self) -> MemoryUsage {
+        self.replicas
+            .iter()
+            .map(|replica| replica.session.memory_usage())
+            .fold(MemoryUsage::default(), |total, usage| total + usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeSession;
+
+    impl InferenceSession for FakeSession {
+        fn run(&self, _inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+            Ok(SessionOutputs::new(Vec::new()))
+        }
+        fn io_info(&self) -> ModelIo {
+            ModelIo::default()
+        }
+    }
+
+    fn pool(policy: DispatchPolicy) -> SessionPool {
+        let sessions: Vec<Box<dyn InferenceSession>> = vec![
+            Box::new(FakeSession),
+            Box::new(FakeSession),
+            Box::new(FakeSession),
+        ];
+        SessionPool::new(sessions, policy)
+    }
+
+    fn picked(pool: &SessionPool) -> usize {
+        let replica = pool.pick();
+        pool.replicas
+            .iter()
+            .position(|other| std::ptr::eq(other, replica))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let round_robin = pool(DispatchPolicy::RoundRobin);
+        round_robin.replicas[1].running.store(5, Ordering::Relaxed);
+        let picks = Vec::from_iter((0..4).map(|_| picked(&round_robin)));
+        assert_eq!(picks, [0, 1, 2, 0]);
+
+        let least_loaded = pool(DispatchPolicy::LeastLoaded);
+        least_loaded.replicas[0].running.store(2, Ordering::Relaxed);
+        least_loaded.replicas[1].running.store(1, Ordering::Relaxed);
+        assert_eq!(picked(&least_loaded), 2);
+        least_loaded.replicas[2].running.store(1, Ordering::Relaxed);
+        // Equally busy replicas take turns
+        let picks = Vec::from_iter((0..4).map(|_| picked(&least_loaded)));
+        assert!(picks.contains(&1) && picks.contains(&2) && !picks.contains(&0));
+        assert!(least_loaded.run(Vec::new()).is_ok());
+        let running = Vec::from_iter(
+            least_loaded
+                .replicas
+                .iter()
+                .map(|replica| replica.running.load(Ordering::Relaxed)),
+        );
+        assert_eq!(running, [2, 1, 1]);
+
+        assert_eq!(
+            "round-robin".parse::<DispatchPolicy>().unwrap(),
+            DispatchPolicy::RoundRobin
+        );
+        assert!("random".parse::<DispatchPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_replicate() {
+        let created = AtomicUsize::new(0);
+        let create = || {
+            created.fetch_add(1, Ordering::Relaxed);
+            Ok(Box::new(FakeSession) as Box<dyn InferenceSession>)
+        };
+        assert!(replicate(create).is_ok());
+        assert_eq!(created.load(Ordering::Relaxed), 1);
+        let devices = vec![
+            ExecutionProvider::Cuda { device_id: 0 },
+            ExecutionProvider::Cuda { device_id: 1 },
+        ];
+        with_device_pool(devices, DispatchPolicy::LeastLoaded, || {
+            assert!(replicate(create).is_ok());
+        });
+        assert_eq!(created.load(Ordering::Relaxed), 3);
+        assert!(DEVICE_POOL.with(|pool| pool.borrow().is_none()));
+    }
+
+    #[test]
+    fn test_replicate_panic() {
+        let devices = vec![ExecutionProvider::Cpu, ExecutionProvider::Cpu];
+        // The pool is restored for the sessions created after a panic
+        with_device_pool(devices.clone(), DispatchPolicy::RoundRobin, || {
+            let _ = std::panic::catch_unwind(|| {
+                replicate(|| -> SonataResult<Box<dyn InferenceSession>> { panic!() })
+            });
+            assert_eq!(current_pool(), Some((devices, DispatchPolicy::RoundRobin)));
+        });
+    }
+
+    #[test]
+    fn test_run_panic() {
+        struct PanickingSession;
+        impl InferenceSession for PanickingSession {
+            fn run(&self, _inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+                panic!("Failed to run the session")
+            }
+            fn io_info(&self) -> ModelIo {
+                ModelIo::default()
+            }
+        }
+        let sessions: Vec<Box<dyn InferenceSession>> = vec![Box::new(PanickingSession)];
+        let pool = SessionPool::new(sessions, DispatchPolicy::LeastLoaded);
+        let run = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.run(Vec::new())));
+        assert!(run.is_err());
+        assert_eq!(pool.replicas[0].running.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,524 @@
+//! Downloading Piper voices from the `rhasspy/piper-voices` repository on HuggingFace.
+//!
+//! Voices are identified by their key, e.g. `en_US-lessac-medium`, which is also the
+//! voice id once the voice is installed. Downloads report their progress through a
+//! callback, so that GUIs can show meaningful progress for large voices.
+//!
+//! Files are downloaded to a `.part` file, which an interrupted download resumes from
+//! with an HTTP range request. Complete files are checked against the size and SHA-256
+//! listed by the HuggingFace API, and corrupt files are removed.
+//!
+//! Verified downloads leave a [`DownloadRecord`] next to the voice, with the git object
+//! ids of its files, which is compared against the repository to find updated voices.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sonata_core::{SonataError, SonataResult};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_VOICES_REPO_URL: &str = "https://huggingface.co/rhasspy/piper-voices";
+pub const DEFAULT_VOICES_REVISION: &str = "main";
+
+/// Appended to the voice key to name the download record of a voice
+pub const DOWNLOAD_RECORD_SUFFIX: &str = ".download.json";
+
+/// Bytes downloaded between two progress reports of the same file
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadStage {
+    Downloading,
+    /// The file is downloaded, and being checked
+    Verifying,
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Name of the file being downloaded
+    pub file: String,
+    /// Index of the file among the files of the voice
+    pub file_index: usize,
+    pub num_files: usize,
+    /// Bytes of the current file downloaded so far
+    pub downloaded: u64,
+    /// Size of the current file, if the server reported it
+    pub total: Option<u64>,
+    pub stage: DownloadStage,
+}
+
+pub struct VoiceDownloader {
+    repo_url: String,
+    revision: String,
+    verify: bool,
+    agent: ureq::Agent,
+}
+
+/// Where an installed voice was downloaded from, and the versions of its files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadRecord {
+    pub voice_key: String,
+    pub repo_url: String,
+    pub revision: String,
+    /// Git object id of each file, by file name
+    pub files: HashMap<String, String>,
+}
+
+impl DownloadRecord {
+    pub fn path(voice_dir: &Path, voice_key: &str) -> PathBuf {
+        voice_dir.join(format!("{}{}", voice_key, DOWNLOAD_RECORD_SUFFIX))
+    }
+    /// The record of the voice `voice_key` in `voice_dir`, if it was downloaded with
+    /// verification
+    pub fn load(voice_dir: &Path, voice_key: &str) -> Option<Self> {
+        let record = std::fs::read(Self::path(voice_dir, voice_key)).ok()?;
+        serde_json::from_slice(&record).ok()
+    }
+    fn save(&self, voice_dir: &Path) -> SonataResult<()> {
+        let path = Self::path(voice_dir, &self.voice_key);
+        serde_json::to_vec_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|record| std::fs::write(&path, record).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                SonataError::OperationError(format!(
+                    "Failed to write download record `{}`",
+                    path.display()
+                ))
+                .caused_by(e)
+            })
+    }
+}
+
+/// A file listed by the HuggingFace tree API
+#[derive(Deserialize)]
+struct RepoFile {
+    path: String,
+    /// Git object id, which changes with the content of the file
+    oid: String,
+    size: u64,
+    /// Set for files stored with git LFS, such as models
+    lfs: Option<LfsInfo>,
+}
+
+#[derive(Deserialize)]
+struct LfsInfo {
+    /// SHA-256 of the file
+    oid: String,
+}
+
+impl Default for VoiceDownloader {
+    fn default() -> Self {
+        Self::new(DEFAULT_VOICES_REPO_URL)
+    }
+}
+
+impl VoiceDownloader {
+    pub fn new(repo_url: impl Into<String>) -> Self {
+        Self {
+            repo_url: repo_url.into().trim_end_matches('/').to_string(),
+            revision: DEFAULT_VOICES_REVISION.to_string(),
+            verify: true,
+            agent: ureq::AgentBuilder::new().build(),
+        }
+    }
+    /// Download from a branch, tag or commit other than `main`
+    pub fn with_revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = revision.into();
+        self
+    }
+    /// Whether to check downloaded files against the repository's file list. Mirrors
+    /// that don't serve the HuggingFace API must disable it.
+    pub fn with_verification(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+    /// Download the model and config of the voice `voice_key` into its own directory
+    /// under `voices_dir`, and return the path of the config
+    pub fn download_voice(
+        &self,
+        voice_key: &str,
+        voices_dir: &Path,
+        on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<PathBuf> {
+        self.download_voice_to(voice_key, &voices_dir.join(voice_key), on_progress)
+    }
+    /// Like [`Self::download_voice`], into `voice_dir` itself
+    pub fn download_voice_to(
+        &self,
+        voice_key: &str,
+        voice_dir: &Path,
+        on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<PathBuf> {
+        self.download_from(
+            &self.repo_url,
+            &self.revision,
+            voice_key,
+            voice_dir,
+            on_progress,
+        )
+    }
+    /// Download the latest files of the voice of `record` into `voice_dir`, from the
+    /// repository and revision it was downloaded from
+    pub fn update_voice(
+        &self,
+        record: &DownloadRecord,
+        voice_dir: &Path,
+        on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<PathBuf> {
+        self.download_from(
+            &record.repo_url,
+            &record.revision,
+            &record.voice_key,
+            voice_dir,
+            on_progress,
+        )
+    }
+    fn download_from(
+        &self,
+        repo_url: &str,
+        revision: &str,
+        voice_key: &str,
+        voice_dir: &Path,
+        mut on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<PathBuf> {
+        let repo_path = voice_repo_path(voice_key)?;
+        std::fs::create_dir_all(voice_dir).map_err(|e| {
+            SonataError::OperationError(format!(
+                "Failed to create voice directory `{}`",
+                voice_dir.display()
+            ))
+            .caused_by(e)
+        })?;
+        let files = [
+            format!("{}.onnx", voice_key),
+            format!("{}.onnx.json", voice_key),
+        ];
+        let repo_files = if self.verify {
+            self.list_repo_files(repo_url, revision, &repo_path)?
+        } else {
+            HashMap::new()
+        };
+        for (file_index, file) in files.iter().enumerate() {
+            let mut progress = DownloadProgress {
+                file: file.clone(),
+                file_index,
+                num_files: files.len(),
+                downloaded: 0,
+                total: None,
+                stage: DownloadStage::Downloading,
+            };
+            let url = format!("{}/resolve/{}/{}/{}", repo_url, revision, repo_path, file);
+            let expected = if self.verify {
+                let expected = repo_files.get(&format!("{}/{}", repo_path, file));
+                Some(expected.ok_or_else(|| {
+                    SonataError::OperationError(format!(
+                        "Voice `{}` has no file `{}` at revision `{}`",
+                        voice_key, file, revision
+                    ))
+                })?)
+            } else {
+                None
+            };
+            self.download_file(
+                &url,
+                &voice_dir.join(file),
+                expected,
+                &mut progress,
+                &mut on_progress,
+            )?;
+        }
+        if self.verify {
+            let record = DownloadRecord {
+                voice_key: voice_key.to_string(),
+                repo_url: repo_url.to_string(),
+                revision: revision.to_string(),
+                files: HashMap::from_iter(files.iter().filter_map(|file| {
+                    let repo_file = repo_files.get(&format!("{}/{}", repo_path, file))?;
+                    Some((file.clone(), repo_file.oid.clone()))
+                })),
+            };
+            record.save(voice_dir)?;
+        }
+        Ok(voice_dir.join(&files[1]))
+    }
+    /// The files of the voice of `record` that changed upstream since it was downloaded
+    pub fn changed_files(&self, record: &DownloadRecord) -> SonataResult<Vec<String>> {
+        let repo_path = voice_repo_path(&record.voice_key)?;
+        let repo_files = self.list_repo_files(&record.repo_url, &record.revision, &repo_path)?;
+        let mut changed = Vec::from_iter(
+            record
+                .files
+                .iter()
+                .filter(|(file, oid)| {
+                    repo_files
+                        .get(&format!("{}/{}", repo_path, file))
+                        .is_none_or(|repo_file| repo_file.oid != **oid)
+                })
+                .map(|(file, _)| file.clone()),
+        );
+        changed.sort();
+        Ok(changed)
+    }
+    /// The files of the repository directory `repo_path`, keyed by path
+    fn list_repo_files(
+        &self,
+        repo_url: &str,
+        revision: &str,
+        repo_path: &str,
+    ) -> SonataResult<HashMap<String, RepoFile>> {
+        let url = format!("{}/tree/{}/{}", api_url(repo_url), revision, repo_path);
+        let files: Vec<RepoFile> = self
+            .agent
+            .get(&url)
+            .call()
+            .map_err(|e| e.to_string())
+            .and_then(|response| response.into_json().map_err(|e| e.to_string()))
+            .map_err(|e| {
+                SonataError::OperationError(format!("Failed to list the files of `{}`", url))
+                    .caused_by(e)
+            })?;
+        Ok(HashMap::from_iter(
+            files.into_iter().map(|file| (file.path.clone(), file)),
+        ))
+    }
+    fn download_file(
+        &self,
+        url: &str,
+        dest: &Path,
+        expected: Option<&RepoFile>,
+        progress: &mut DownloadProgress,
+        on_progress: &mut impl FnMut(&DownloadProgress),
+    ) -> SonataResult<()> {
+        let download_error = |e: Box<dyn std::error::Error + Send + Sync>| {
+            SonataError::OperationError(format!("Failed to download `{}`", url)).caused_by(e)
+        };
+        // Complete files only ever appear under their final name
+        let part_path = part_path(dest);
+        let mut resume_from = part_path.metadata().map(|meta| meta.len()).unwrap_or(0);
+        if expected.is_some_and(|expected| resume_from > expected.size) {
+            resume_from = 0;
+        }
+        let already_complete = expected.is_some_and(|expected| resume_from == expected.size);
+        if !already_complete {
+            let mut request = self.agent.get(url);
+            if resume_from > 0 {
+                request = request.set("Range", &format!("bytes={}-", resume_from));
+            }
+            let response = match request.call() {
+                // The range starts past the end of the file: the partial file is complete,
+                // unless it fails verification, in which case the download starts over
+                Err(ureq::Error::Status(416, _)) if resume_from > 0 => {
+                    if verify_file(&part_path, None, expected).is_ok() {
+                        None
+                    } else {
+                        resume_from = 0;
+                        Some(self.agent.get(url).call())
+                    }
+                }
+                response => Some(response),
+            };
+            if let Some(response) = response {
+                let response = response.map_err(|e| download_error(e.into()))?;
+                self.receive_file(response, &part_path, resume_from, progress, on_progress)
+                    .map_err(download_error)?;
+            }
+        }
+
+        progress.stage = DownloadStage::Verifying;
+        progress.downloaded = part_path.metadata().map(|meta| meta.len()).unwrap_or(0);
+        on_progress(progress);
+        if let Err(e) = verify_file(&part_path, progress.total, expected) {
+            // A corrupt partial file can't be resumed
+            let _ = std::fs::remove_file(&part_path);
+            return Err(download_error(e.into()));
+        }
+        std::fs::rename(&part_path, dest).map_err(|e| download_error(e.into()))?;
+        progress.stage = DownloadStage::Done;
+        on_progress(progress);
+        Ok(())
+    }
+    /// Write the body of `response` to `part_path`, after its first `resume_from` bytes
+    /// if the server sent the requested range
+    fn receive_file(
+        &self,
+        response: ureq::Response,
+        part_path: &Path,
+        mut resume_from: u64,
+        progress: &mut DownloadProgress,
+        on_progress: &mut impl FnMut(&DownloadProgress),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let content_length: Option<u64> = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok());
+        // Servers that ignore the range send the whole file
+        if response.status() != 206 {
+            resume_from = 0;
+        }
+        progress.downloaded = resume_from;
+        progress.total = content_length.map(|len| resume_from + len);
+        on_progress(progress);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume_from > 0)
+            .truncate(resume_from == 0)
+            .open(part_path)?;
+        self.receive(response, BufWriter::new(file), progress, on_progress)?;
+        Ok(())
+    }
+    fn receive(
+        &self,
+        response: ureq::Response,
+        mut writer: BufWriter<File>,
+        progress: &mut DownloadProgress,
+        on_progress: &mut impl FnMut(&DownloadProgress),
+    ) -> std::io::Result<()> {
+        let mut reader = response.into_reader();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut last_report = progress.downloaded;
+        loop {
+            let len = reader.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            writer.write_all(&buf[..len])?;
+            progress.downloaded += len as u64;
+            if progress.downloaded - last_report >= PROGRESS_INTERVAL {
+                last_report = progress.downloaded;
+                on_progress(progress);
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Check the size of the downloaded file at `path` and, for LFS files, its SHA-256
+fn verify_file(path: &Path, total: Option<u64>, expected: Option<&RepoFile>) -> Result<(), String> {
+    let size = path.metadata().map_err(|e| e.to_string())?.len();
+    let expected_size = expected.map(|expected| expected.size).or(total);
+    if let Some(expected_size) = expected_size.filter(|expected_size| *expected_size != size) {
+        return Err(format!("received {} of {} bytes", size, expected_size));
+    }
+    let Some(lfs) = expected.and_then(|expected| expected.lfs.as_ref()) else {
+        return Ok(());
+    };
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher).map_err(|e| e.to_string())?;
+    let digest = to_hex(&hasher.finalize());
+    if !digest.eq_ignore_ascii_case(&lfs.oid) {
+        return Err(format!(
+            "SHA-256 mismatch: expected {}, got {}",
+            lfs.oid, digest
+        ));
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    String::from_iter(bytes.iter().map(|byte| format!("{:02x}", byte)))
+}
+
+/// The API endpoint of a HuggingFace repository, e.g.
+/// `https://huggingface.co/api/models/rhasspy/piper-voices` for
+/// `https://huggingface.co/rhasspy/piper-voices`
+fn api_url(repo_url: &str) -> String {
+    let host_start = repo_url.find("://").map(|pos| pos + 3).unwrap_or(0);
+    match repo_url[host_start..].find('/') {
+        Some(pos) => {
+            let (host, repo_id) = repo_url.split_at(host_start + pos);
+            format!("{}/api/models{}", host, repo_id)
+        }
+        None => format!("{}/api/models", repo_url),
+    }
+}
+
+/// The file a download is written to until it is complete
+fn part_path(dest: &Path) -> PathBuf {
+    let mut filename = dest.file_name().unwrap_or_default().to_os_string();
+    filename.push(".part");
+    dest.with_file_name(filename)
+}
+
+/// The directory of a voice in the repository, e.g. `en/en_US/lessac/medium` for
+/// `en_US-lessac-medium`
+fn voice_repo_path(voice_key: &str) -> SonataResult<String> {
+    let invalid_key = || {
+        SonataError::OperationError(format!(
+            "Invalid voice key `{}`. Expected `<language>-<name>-<quality>`",
+            voice_key
+        ))
+    };
+    // The key names the voice's directory and files, so it can't reach outside of them
+    if voice_key.contains(['/', '\\', ':'])
+        || voice_key.contains("..")
+        || Path::new(voice_key).is_absolute()
+    {
+        return Err(invalid_key());
+    }
+    let (language, rest) = voice_key.split_once('-').ok_or_else(invalid_key)?;
+    let (name, quality) = rest.rsplit_once('-').ok_or_else(invalid_key)?;
+    let family = language.split('_').next().unwrap_or(language);
+    if [family, name, quality].iter().any(|part| part.is_empty()) {
+        return Err(invalid_key());
+    }
+    Ok(format!("{}/{}/{}/{}", family, language, name, quality))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_repo_path() {
+        assert_eq!(
+            voice_repo_path("en_US-lessac-medium").unwrap(),
+            "en/en_US/lessac/medium"
+        );
+        assert_eq!(
+            voice_repo_path("de_DE-thorsten-emotional-medium").unwrap(),
+            "de/de_DE/thorsten-emotional/medium"
+        );
+        assert!(voice_repo_path("en_US-lessac").is_err());
+        for key in [
+            "../en_US-lessac-medium",
+            "en_US-lessac-..",
+            "/tmp/en_US-lessac-medium",
+            "en/en_US-lessac-medium",
+            "en_US-lessac\\..\\medium",
+        ] {
+            assert!(voice_repo_path(key).is_err(), "{}", key);
+        }
+        assert_eq!(
+            part_path(Path::new("/voices/amy.onnx")),
+            Path::new("/voices/amy.onnx.part")
+        );
+        assert_eq!(
+            api_url(DEFAULT_VOICES_REPO_URL),
+            "https://huggingface.co/api/models/rhasspy/piper-voices"
+        );
+    }
+
+    #[test]
+    fn test_verify_file() {
+        let path = std::env::temp_dir().join(format!("sonata-verify-{}", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        let expected = RepoFile {
+            path: "abc".to_string(),
+            oid: "8baef1b4abc478178b004d62031cf7fe6db6f903".to_string(),
+            size: 3,
+            lfs: Some(LfsInfo {
+                oid: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            }),
+        };
+        assert_eq!(verify_file(&path, None, Some(&expected)), Ok(()));
+        assert!(verify_file(&path, Some(4), None).is_err());
+        std::fs::write(&path, b"abd").unwrap();
+        let error = verify_file(&path, None, Some(&expected)).unwrap_err();
+        assert!(error.starts_with("SHA-256 mismatch"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
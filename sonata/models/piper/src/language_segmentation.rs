@@ -0,0 +1,258 @@
+//! Split text into spans of different languages, so that each span can be spoken
+//! by a matching voice (or at least phonemized using the matching espeak language).
+//!
+//! Language switches are detected from changes in the writing system (e.g. Latin
+//! words inside Russian text). Switches within the same script, such as a French quote
+//! in English text, can be marked explicitly using SSML's `<lang xml:lang="fr">...</lang>`.
+
+/// A run of text in a single language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageSpan {
+    pub text: String,
+    /// The span's language code, `None` for the primary language
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Bengali,
+    Tamil,
+    Thai,
+    Georgian,
+    Hangul,
+    Kana,
+    Han,
+}
+
+impl Script {
+    fn of(c: char) -> Option<Self> {
+        let script = match c as u32 {
+            // `×` and `÷`
+            0xD7 | 0xF7 => return None,
+            0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F | 0x1E00..=0x1EFF => Script::Latin,
+            0x370..=0x3FF | 0x1F00..=0x1FFF => Script::Greek,
+            0x400..=0x52F => Script::Cyrillic,
+            0x530..=0x58F => Script::Armenian,
+            0x590..=0x5FF => Script::Hebrew,
+            0x600..=0x6FF | 0x750..=0x77F | 0x8A0..=0x8FF | 0xFB50..=0xFDFF | 0xFE70..=0xFEFF => {
+                Script::Arabic
+            }
+            0x900..=0x97F => Script::Devanagari,
+            0x980..=0x9FF => Script::Bengali,
+            0xB80..=0xBFF => Script::Tamil,
+            0xE00..=0xE7F => Script::Thai,
+            0x10A0..=0x10FF => Script::Georgian,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3040..=0x30FF => Script::Kana,
+            0x3400..=0x4DBF | 0x4E00..=0x9FFF => Script::Han,
+            // Digits, punctuation, whitespace, symbols...
+            _ => return None,
+        };
+        Some(script)
+    }
+    /// The language a span in this script is assumed to be in
+    fn default_language(self) -> &'static str {
+        match self {
+            Script::Latin => "en",
+            Script::Greek => "el",
+            Script::Cyrillic => "ru",
+            Script::Armenian => "hy",
+            Script::Hebrew => "he",
+            Script::Arabic => "ar",
+            Script::Devanagari => "hi",
+            Script::Bengali => "bn",
+            Script::Tamil => "ta",
+            Script::Thai => "th",
+            Script::Georgian => "ka",
+            Script::Hangul => "ko",
+            Script::Kana => "ja",
+            Script::Han => "zh",
+        }
+    }
+    /// Scripts used to write the given language
+    fn for_language(language: &str) -> &'static [Script] {
+        let primary_language = language
+            .split(['_', '-'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match primary_language.as_str() {
+            "el" | "grc" => &[Script::Greek],
+            "ru" | "uk" | "be" | "bg" | "sr" | "mk" | "kk" | "ky" | "mn" | "tt" | "ba" => {
+                &[Script::Cyrillic]
+            }
+            "hy" => &[Script::Armenian],
+            "he" | "yi" => &[Script::Hebrew],
+            "ar" | "fa" | "ur" | "ps" | "ug" | "sd" => &[Script::Arabic],
+            "hi" | "mr" | "ne" | "sa" => &[Script::Devanagari],
+            "bn" | "as" => &[Script::Bengali],
+            "ta" => &[Script::Tamil],
+            "th" => &[Script::Thai],
+            "ka" => &[Script::Georgian],
+            "ko" => &[Script::Hangul, Script::Han],
+            "ja" => &[Script::Kana, Script::Han],
+            "zh" | "cmn" | "yue" => &[Script::Han],
+            _ => &[Script::Latin],
+        }
+    }
+}
+
+/// The espeak-ng voice used to phonemize text in the given language
+pub(crate) fn espeak_voice_for_language(language: &str) -> String {
+    match language {
+        "zh" => "cmn".to_string(),
+        _ => language.to_string(),
+    }
+}
+
+const LANG_OPEN_TAG: &str = "<lang";
+const LANG_CLOSE_TAG: &str = "</lang>";
+
+/// Split `text` into spans by language.
+///
+/// Text written in a script used by `primary_language` is attributed to the primary
+/// language, while text in other scripts is attributed to the script's most common
+/// language. Digits, punctuation and whitespace stay with the surrounding span.
+/// Adjacent spans with the same language are merged.
+pub fn segment_by_language(text: &str, primary_language: &str) -> Vec<LanguageSpan> {
+    let primary_scripts = Script::for_language(primary_language);
+    let mut spans: Vec<LanguageSpan> = Vec::new();
+    let mut remaining = text;
+    while !remaining.is_empty() {
+        let Some((before, language, inner, after)) = split_lang_element(remaining) else {
+            segment_by_script(remaining, primary_scripts, &mut spans);
+            break;
+        };
+        segment_by_script(before, primary_scripts, &mut spans);
+        push_span(&mut spans, inner, language);
+        remaining = after;
+    }
+    spans.retain(|span| !span.text.trim().is_empty());
+    spans
+}
+
+/// Find the first `<lang xml:lang="...">...</lang>` element in `text`, and return
+/// the text before it, its language, its content and the text after it
+fn split_lang_element(text: &str) -> Option<(&str, Option<String>, &str, &str)> {
+    // `<lang` starts the element, while `<language` or `<langue` don't
+    let start = text.match_indices(LANG_OPEN_TAG).find_map(|(start, tag)| {
+        text[start + tag.len()..]
+            .starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .then_some(start)
+    })?;
+    let tag_end = start + text[start..].find('>')?;
+    let content_end = tag_end + text[tag_end..].find(LANG_CLOSE_TAG)?;
+    let attributes = &text[start + LANG_OPEN_TAG.len()..tag_end];
+    let language = attributes
+        .split_once("lang=")
+        .and_then(|(_, value)| {
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            value[1..].split(quote).next()
+        })
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(String::from);
+    Some((
+        &text[..start],
+        language,
+        &text[tag_end + 1..content_end],
+        &text[content_end + LANG_CLOSE_TAG.len()..],
+    ))
+}
+
+fn segment_by_script(text: &str, primary_scripts: &[Script], spans: &mut Vec<LanguageSpan>) {
+    let mut current_language: Option<Option<String>> = None;
+    let mut current_text = String::new();
+    for c in text.chars() {
+        if let Some(script) = Script::of(c) {
+            let language = if primary_scripts.contains(&script) {
+                None
+            } else {
+                Some(script.default_language().to_string())
+            };
+            match current_language {
+                Some(ref current) if *current != language => {
+                    push_span(spans, &current_text, current.clone());
+                    current_text.clear();
+                    current_language = Some(language);
+                }
+                Some(_) => {}
+                None => current_language = Some(language),
+            }
+        }
+        current_text.push(c);
+    }
+    push_span(spans, &current_text, current_language.flatten());
+}
+
+fn push_span(spans: &mut Vec<LanguageSpan>, text: &str, language: Option<String>) {
+    if text.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.language == language => last.text.push_str(text),
+        _ => spans.push(LanguageSpan {
+            text: text.to_string(),
+            language,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, language: Option<&str>) -> LanguageSpan {
+        LanguageSpan {
+            text: text.to_string(),
+            language: language.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_segment_by_script() {
+        assert_eq!(
+            segment_by_language("Он сказал: hello world, и ушёл.", "ru_RU"),
+            vec![
+                span("Он сказал: ", None),
+                span("hello world, ", Some("en")),
+                span("и ушёл.", None),
+            ]
+        );
+        assert_eq!(
+            segment_by_language("Just English text.", "en_US"),
+            vec![span("Just English text.", None)]
+        );
+        assert_eq!(
+            segment_by_language("日本語のテキスト", "ja"),
+            vec![span("日本語のテキスト", None)]
+        );
+    }
+
+    #[test]
+    fn test_segment_explicit_language() {
+        assert_eq!(
+            segment_by_language(
+                r#"He said <lang xml:lang="fr-FR">c'est la vie</lang> and left."#,
+                "en_US"
+            ),
+            vec![
+                span("He said ", None),
+                span("c'est la vie", Some("fr-FR")),
+                span(" and left.", None),
+            ]
+        );
+        // Other elements starting with `<lang` are left alone
+        let text = r#"A <language>tag</language> and <lang xml:lang="de">ja</lang>."#;
+        let spans = segment_by_language(text, "en_US");
+        assert_eq!(spans[0], span("A <language>tag</language> and ", None));
+        assert_eq!(spans[1], span("ja", Some("de")));
+    }
+}
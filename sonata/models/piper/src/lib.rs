@@ -1,22 +1,77 @@
-use espeak_phonemizer::text_to_phonemes;
-use libtashkeel_base::do_tashkeel;
+pub mod bundle;
+#[cfg(feature = "candle")]
+mod candle_backend;
+mod contour;
+mod coqui;
+#[cfg(feature = "ort")]
+mod device_pool;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod language_segmentation;
+pub mod model_decryption;
+#[cfg(feature = "ort")]
+pub mod onnxruntime;
+pub mod phoneme_cache;
+mod prosody_variation;
+mod session;
+pub mod sherpa;
+mod shutdown;
+mod speaker_encoder;
+mod tensor_dump;
+pub mod voice_manager;
+
+use contour::ContourInputs;
 use ndarray::Axis;
-use ndarray::{Array, Array1, Array2, ArrayView, Dim, IxDynImpl};
-use ort::{init, Session, CUDAExecutionProvider, SessionInputs, SessionOutputs, Value, TensorElementType, IntoTensorElementType};
+use ndarray::{Array, Array1, Array2, ArrayView, CowArray, Dim, IxDyn, IxDynImpl};
+use phoneme_cache::PHONEME_CACHE;
 use serde::Deserialize;
+use session::{
+    create_inference_session, create_inference_session_from_bytes, InferenceSession, SessionInput,
+    SessionOutputs, SessionPlacement,
+};
 use sonata_core::{
-    Audio, AudioInfo, AudioSamples, AudioStreamIterator, Phonemes, SonataAudioResult, SonataError,
-    SonataModel, SonataResult,
+    Audio, AudioInfo, AudioSamples, AudioStreamIterator, ChunkOverlap, Contour, DurationOverride,
+    MemoryUsage, OverlapWindow, Phonemes, ProsodyFeature, SonataAudioResult, SonataError,
+    SonataModel, SonataResult, StageTimings, StreamControl, STOP_FADE_OUT,
 };
 use std::any::Any;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
 use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tensor_dump::TensorDump;
+
+pub use bundle::VoiceBundle;
+pub use model_decryption::{clear_model_decryptor, set_model_decryptor, ModelDecryptor};
+#[cfg(feature = "ort")]
+pub use device_pool::{with_device_pool, DispatchPolicy};
+#[cfg(feature = "ort")]
+pub use onnxruntime::{init, with_execution_providers, ExecutionProvider, InitOptions, LogLevel};
+pub use phoneme_cache::{
+    clear_phoneme_cache, phoneme_cache_stats, set_phoneme_cache_capacity, PhonemeCacheStats,
+};
+pub use prosody_variation::{ProsodyVariation, MAX_PROSODY_VARIATION};
+pub use session::{ModelIo, ModelIoInfo};
+pub use shutdown::shutdown;
+pub use speaker_encoder::SpeakerEncoder;
+pub use tensor_dump::DUMP_TENSORS_DIR_ENV_VAR;
+pub use voice_manager::VoiceManager;
 
 const MIN_CHUNK_SIZE: isize = 44;
 const MAX_CHUNK_SIZE: usize = 1024;
+/// Smallest chunk (in mel frames) decoded when chunks are sized for a latency target
+const MIN_ADAPTIVE_CHUNK_SIZE: usize = 8;
+/// Share of the buffered audio's duration the next adaptive chunk may take to decode
+const ADAPTIVE_DECODE_MARGIN: f64 = 0.5;
+/// Audio samples the decoder produces per mel frame
+const DECODER_HOP_LENGTH: isize = 256;
+/// Speaking rates reached by scaling `length_scale`, unless the voice sets its own
+const DEFAULT_NATIVE_RATE_RANGE: (f32, f32) = (0.75, 1.5);
 const BOS: char = '^';
 const EOS: char = '$';
 const PAD: char = '_';
@@ -35,37 +90,93 @@ fn load_model_config(config_path: &Path) -> SonataResult<(ModelConfig, PiperSynt
         Ok(file) => file,
         Err(why) => {
             return Err(SonataError::FailedToLoadResource(format!(
-                "Faild to load model config: `{}`. Caused by: `{}`",
-                config_path.display(),
-                why
-            )))
-        }
-    };
-    let model_config: ModelConfig = match serde_json::from_reader(file) {
-        Ok(config) => config,
-        Err(why) => {
-            return Err(SonataError::FailedToLoadResource(format!(
-                "Faild to parse model config from file: `{}`. Caused by: `{}`",
-                config_path.display(),
-                why
-            )))
+                "Faild to load model config: `{}`",
+                config_path.display()
+            ))
+            .caused_by(why))
         }
     };
-    let synth_config = PiperSynthesisConfig {
-        speaker: None,
-        noise_scale: model_config.inference.noise_scale,
-        length_scale: model_config.inference.length_scale,
-        noise_w: model_config.inference.noise_w,
-    };
+    parse_model_config(file, config_path)
+}
+
+fn parse_model_config(
+    reader: impl Read,
+    config_path: &Path,
+) -> SonataResult<(ModelConfig, PiperSynthesisConfig)> {
+    let parsed = serde_json::from_reader(reader).and_then(deserialize_model_config);
+    let model_config = check_model_config(parsed, Some(config_path))?;
+    let synth_config = model_config.synthesis_config();
     Ok((model_config, synth_config))
 }
 
-fn create_tashkeel_engine(
-    config: &ModelConfig,
-) -> SonataResult<Option<libtashkeel_base::DynamicInferenceEngine>> {
+/// Deserialize the config of a Piper voice, or of a voice exported from Coqui TTS
+fn deserialize_model_config(value: serde_json::Value) -> serde_json::Result<ModelConfig> {
+    if coqui::is_coqui_config(&value) {
+        coqui::model_config(value)
+    } else {
+        serde_json::from_value(value)
+    }
+}
+
+/// Validate a parsed config, naming `config_path` in errors if it was read from a file
+fn check_model_config(
+    parsed: serde_json::Result<ModelConfig>,
+    config_path: Option<&Path>,
+) -> SonataResult<ModelConfig> {
+    let origin = config_path
+        .map(|path| format!(" `{}`", path.display()))
+        .unwrap_or_default();
+    let mut model_config = parsed.map_err(|why| {
+        SonataError::FailedToLoadResource(format!("Failed to parse model config{}", origin))
+            .caused_by(why)
+    })?;
+    model_config.meta_ids = model_config.validate_phoneme_id_map().map_err(|why| {
+        SonataError::FailedToLoadResource(format!("Invalid model config{}: {}", origin, why))
+    })?;
+    Ok(model_config)
+}
+
+/// Phonemize `text` with the espeak-ng voice `espeak_voice`, one entry per sentence
+#[cfg(feature = "espeak")]
+pub(crate) fn espeak_phonemize(text: &str, espeak_voice: &str) -> SonataResult<Phonemes> {
+    let phonemization =
+        espeak_phonemizer::text_to_phonemes_with_warnings(text, espeak_voice, None, true, false)
+            .map_err(|e| {
+                SonataError::PhonemizationError(
+                    "Failed to phonemize given text using espeak-ng".to_string(),
+                )
+                .caused_by(e)
+            })?;
+    Ok(Phonemes::from(phonemization.sentences).with_warnings(phonemization.warnings))
+}
+
+#[cfg(not(feature = "espeak"))]
+pub(crate) fn espeak_phonemize(_text: &str, espeak_voice: &str) -> SonataResult<Phonemes> {
+    Err(SonataError::PhonemizationError(format!(
+        "Can not phonemize `{}` text without the `espeak` feature. Speak phonemes instead",
+        espeak_voice
+    )))
+}
+
+/// A libtashkeel engine, which must be dropped before [`shutdown`]
+#[cfg(feature = "tashkeel")]
+struct TashkeelEngine {
+    engine: libtashkeel_base::DynamicInferenceEngine,
+    _loaded: shutdown::LoadedResource,
+}
+
+/// Without the `tashkeel` feature there is no engine, and Arabic text is phonemized as is
+#[cfg(not(feature = "tashkeel"))]
+enum TashkeelEngine {}
+
+#[cfg(feature = "tashkeel")]
+fn create_tashkeel_engine(config: &ModelConfig) -> SonataResult<Option<TashkeelEngine>> {
     if config.espeak.voice == "ar" {
         match libtashkeel_base::create_inference_engine(None) {
-            Ok(engine) => Ok(Some(engine)),
+            Ok(engine) => Ok(Some(TashkeelEngine {
+                engine,
+                _loaded: shutdown::LoadedResource::new(),
+            })),
             Err(msg) => Err(SonataError::OperationError(format!(
                 "Failed to create inference engine for libtashkeel. {}",
                 msg
@@ -76,40 +187,264 @@ fn create_tashkeel_engine(
     }
 }
 
-fn create_inference_session(model_path: &Path) -> Result<ort::Session, ort::Error> {
-    Session::builder()?
-        .with_execution_providers([
-            CUDAExecutionProvider::default().with_device_id(0).build()?,
-            // Add other execution providers as needed
-        ])?
-        .with_model_from_file(model_path)?
-        .commit()?
+#[cfg(not(feature = "tashkeel"))]
+fn create_tashkeel_engine(_config: &ModelConfig) -> SonataResult<Option<TashkeelEngine>> {
+    Ok(None)
+}
+
+#[cfg(feature = "tashkeel")]
+fn diacritize_text(engine: &TashkeelEngine, text: &str) -> SonataResult<String> {
+    libtashkeel_base::do_tashkeel(&engine.engine, text, None, false).map_err(|msg| {
+        SonataError::OperationError(format!(
+            "Failed to diacritize text using libtashkeel. {}",
+            msg
+        ))
+    })
+}
+
+#[cfg(not(feature = "tashkeel"))]
+fn diacritize_text(engine: &TashkeelEngine, _text: &str) -> SonataResult<String> {
+    match *engine {}
+}
+
+/// Check the inputs of a model, or of the encoder of a streaming model, against the
+/// tensors fed to it: phoneme ids, their count, the scales, for multi-speaker voices the
+/// speaker id and, for voices that have them, the pitch and energy contours.
+fn validate_model_inputs(
+    io: &ModelIo,
+    config: &ModelConfig,
+    model_path: &Path,
+) -> SonataResult<()> {
+    let mut expected = vec![
+        ("input", "int64", 2),
+        ("input_lengths", "int64", 1),
+        ("scales", "float32", 1),
+    ];
+    if config.num_speakers > 1 {
+        expected.push(("sid", "int64", 1));
+    }
+    for feature in contour::contour_features(io) {
+        expected.push((contour::input_name(feature), "float32", 2));
+    }
+    validate_inputs(io, &expected, config, model_path)
+}
+
+/// Check the inputs of the decoder of a streaming model against the encoder outputs
+fn validate_decoder_inputs(
+    io: &ModelIo,
+    config: &ModelConfig,
+    model_path: &Path,
+) -> SonataResult<()> {
+    let mut expected = vec![("z", "float32", 3), ("y_mask", "float32", 3)];
+    if config.num_speakers > 1 {
+        expected.push(("g", "float32", 3));
+    }
+    validate_inputs(io, &expected, config, model_path)
+}
+
+fn validate_inputs(
+    io: &ModelIo,
+    expected: &[(&str, &str, usize)],
+    config: &ModelConfig,
+    model_path: &Path,
+) -> SonataResult<()> {
+    if io.inputs.len() != expected.len() {
+        return Err(SonataError::FailedToLoadResource(format!(
+            "Model `{}` takes {} inputs ({}), but a voice with {} speakers needs {} ({})",
+            model_path.display(),
+            io.inputs.len(),
+            Vec::from_iter(io.inputs.iter().map(|input| input.name.as_str())).join(", "),
+            config.num_speakers,
+            expected.len(),
+            Vec::from_iter(expected.iter().map(|(name, _, _)| *name)).join(", "),
+        )));
+    }
+    for (input, (name, dtype, rank)) in io.inputs.iter().zip(expected) {
+        // Graphs without shape information report no dimensions
+        let rank_matches = input.dims.is_empty() || input.dims.len() == *rank;
+        if input.dtype != *dtype || !rank_matches {
+            return Err(SonataError::FailedToLoadResource(format!(
+                "Input `{}` of model `{}` should be `{}`, a {}-d {} tensor. Found a {}-d {} tensor",
+                input.name,
+                model_path.display(),
+                name,
+                rank,
+                dtype,
+                input.dims.len(),
+                input.dtype
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The path of a model file given in the config, which may be relative to the config
+/// file, or the conventional `default_filename` next to the config file
+fn resolve_model_path(
+    config_path: &Path,
+    model_path: Option<&Path>,
+    default_filename: &OsStr,
+) -> PathBuf {
+    match model_path {
+        Some(model_path) => config_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(model_path),
+        None => config_path.with_file_name(default_filename),
+    }
+}
+
+/// The model files of the voice: the model, or the encoder and decoder of streaming voices
+fn model_paths(config_path: &Path, config: &ModelConfig) -> SonataResult<Vec<PathBuf>> {
+    if config.streaming.unwrap_or_default() {
+        return Ok(vec![
+            resolve_model_path(
+                config_path,
+                config.encoder_path.as_deref(),
+                OsStr::new("encoder.onnx"),
+            ),
+            resolve_model_path(
+                config_path,
+                config.decoder_path.as_deref(),
+                OsStr::new("decoder.onnx"),
+            ),
+        ]);
+    }
+    let Some(onnx_filename) = config_path.file_stem() else {
+        return Err(SonataError::OperationError(format!(
+            "Invalid config filename format `{}`",
+            config_path.display()
+        )));
+    };
+    Ok(vec![resolve_model_path(
+        config_path,
+        config.model_path.as_deref(),
+        onnx_filename,
+    )])
+}
+
+/// Load the config at `config_path` to replace `current`, the config of a voice in use.
+/// Only the inference defaults and the model paths may change: the rest is baked into the
+/// loaded voice
+fn load_replacement_config(config_path: &Path, current: &ModelConfig) -> SonataResult<ModelConfig> {
+    let (config, _) = load_model_config(config_path)?;
+    let changes = [
+        (
+            "streaming",
+            config.streaming.unwrap_or_default() != current.streaming.unwrap_or_default(),
+        ),
+        (
+            "audio.sample_rate",
+            config.audio.sample_rate != current.audio.sample_rate,
+        ),
+        ("espeak.voice", config.espeak.voice != current.espeak.voice),
+        ("phoneme_type", config.phoneme_type != current.phoneme_type),
+        ("num_symbols", config.num_symbols != current.num_symbols),
+        (
+            "phoneme_id_map",
+            config.phoneme_id_map != current.phoneme_id_map,
+        ),
+        ("num_speakers", config.num_speakers != current.num_speakers),
+        ("characters", config.coqui != current.coqui),
+        (
+            "speaker_id_map",
+            config.speaker_id_map != current.speaker_id_map,
+        ),
+    ];
+    if let Some((field, _)) = changes.iter().find(|(_, changed)| *changed) {
+        return Err(SonataError::OperationError(format!(
+            "Config `{}` changes `{}`, which can't be reloaded. Load the voice again instead",
+            config_path.display(),
+            field
+        )));
+    }
+    Ok(config)
+}
+
+/// Check a model that replaces the encoder of a voice in use, which must take the
+/// contours the voice was loaded with
+fn check_replacement_encoder(
+    io: &ModelIo,
+    config: &ModelConfig,
+    model_path: &Path,
+    contour_inputs: &ContourInputs,
+) -> SonataResult<()> {
+    validate_model_inputs(io, config, model_path)?;
+    if contour::contour_features(io) != contour_inputs.features() {
+        return Err(SonataError::OperationError(format!(
+            "Model `{}` takes other contours than the model it replaces",
+            model_path.display()
+        )));
+    }
+    Ok(())
 }
 
 pub fn from_config_path(config_path: &Path) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
     let (config, synth_config) = load_model_config(config_path)?;
-    if config.streaming.unwrap_or_default() {
+    let model_paths = model_paths(config_path, &config)?;
+    if let [encoder_path, decoder_path] = model_paths.as_slice() {
         Ok(Arc::new(VitsStreamingModel::from_config(
             config,
             synth_config,
-            &config_path.with_file_name("encoder.onnx"),
-            &config_path.with_file_name("decoder.onnx"),
+            encoder_path,
+            decoder_path,
         )?))
     } else {
-        let Some(onnx_filename) = config_path.file_stem() else {
-            return Err(SonataError::OperationError(format!(
-                "Invalid config filename format `{}`",
-                config_path.display()
-            )));
-        };
         Ok(Arc::new(VitsModel::from_config(
             config,
             synth_config,
-            &config_path.with_file_name(onnx_filename),
+            &model_paths[0],
         )?))
     }
 }
 
+/// Load a voice from its config and the bytes of its model, without touching the disk.
+/// Streaming voices, which have two models, should be bundled instead
+pub fn from_config(
+    config: ModelConfig,
+    model_bytes: Vec<u8>,
+) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
+    if config.streaming.unwrap_or_default() {
+        return Err(SonataError::FailedToLoadResource(
+            "Streaming voices have an encoder and a decoder. Load them from a bundle".to_string(),
+        ));
+    }
+    // Only names the model in errors
+    let model_path = PathBuf::from(format!("{}.onnx", config.key.as_deref().unwrap_or("voice")));
+    let session = create_inference_session_from_bytes(model_bytes, &model_path)?;
+    let synth_config = config.synthesis_config();
+    Ok(Arc::new(VitsModel::from_session(
+        config,
+        synth_config,
+        session,
+        &model_path,
+    )?))
+}
+
+/// Like [`from_config`], with the config as JSON
+pub fn from_config_value(
+    config: serde_json::Value,
+    model_bytes: Vec<u8>,
+) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
+    from_config(ModelConfig::from_value(config)?, model_bytes)
+}
+
+/// Load a voice from a single-file bundle. See [`bundle`]
+pub fn from_bundle(bundle_path: &Path) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
+    VoiceBundle::open(bundle_path)?.load()
+}
+
+/// Load a Piper voice packaged for sherpa-onnx from its directory. See [`sherpa`]
+pub fn from_sherpa_package(package_dir: &Path) -> SonataResult<Arc<dyn SonataModel + Send + Sync>> {
+    let (config, model_path) = sherpa::load_package(package_dir)?;
+    let synth_config = config.synthesis_config();
+    Ok(Arc::new(VitsModel::from_config(
+        config,
+        synth_config,
+        &model_path,
+    )?))
+}
+
 #[derive(Deserialize, Default)]
 pub struct AudioConfig {
     pub sample_rate: u32,
@@ -121,11 +456,33 @@ pub struct ESpeakConfig {
     voice: String,
 }
 
+/// What the voice was trained on
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PhonemeType {
+    /// IPA phonemes from espeak-ng
+    #[default]
+    ESpeak,
+    /// The characters of the text
+    Text,
+}
+
 #[derive(Deserialize, Default, Clone)]
 pub struct InferenceConfig {
     noise_scale: f32,
     length_scale: f32,
     noise_w: f32,
+    /// The slowest and fastest rates the voice sounds natural at, as multiples of its
+    /// `length_scale` rate
+    #[serde(default = "default_native_rate_range")]
+    rate_range: (f32, f32),
+    /// Bounds of the random changes of the scales from one sentence to the next
+    #[serde(default)]
+    prosody_variation: Option<ProsodyVariation>,
+}
+
+fn default_native_rate_range() -> (f32, f32) {
+    DEFAULT_NATIVE_RATE_RANGE
 }
 
 #[derive(Clone, Deserialize, Default)]
@@ -149,41 +506,142 @@ pub struct ModelConfig {
     pub num_speakers: u32,
     pub speaker_id_map: HashMap<String, i64>,
     streaming: Option<bool>,
+    /// The model file, absolute or relative to the config file. Defaults to the config
+    /// filename without its `.json` extension
+    model_path: Option<PathBuf>,
+    /// The encoder of a streaming model. Defaults to `encoder.onnx` next to the config
+    encoder_path: Option<PathBuf>,
+    /// The decoder of a streaming model. Defaults to `decoder.onnx` next to the config
+    decoder_path: Option<PathBuf>,
     espeak: ESpeakConfig,
+    #[serde(default)]
+    phoneme_type: PhonemeType,
     inference: InferenceConfig,
-    #[allow(dead_code)]
     num_symbols: u32,
     #[allow(dead_code)]
     phoneme_map: HashMap<i64, char>,
     phoneme_id_map: HashMap<char, Vec<i64>>,
+    /// The ids of PAD, BOS and EOS, set once the config is validated
+    #[serde(skip)]
+    meta_ids: (i64, i64, i64),
+    /// The tokenizer of a voice exported from Coqui TTS, which lays out its phoneme ids
+    /// in place of PAD, BOS and EOS
+    #[serde(skip)]
+    coqui: Option<coqui::CoquiTokenizer>,
+}
+
+impl ModelConfig {
+    /// Parse the JSON config of a voice
+    pub fn from_reader(reader: impl Read) -> SonataResult<Self> {
+        check_model_config(
+            serde_json::from_reader(reader).and_then(deserialize_model_config),
+            None,
+        )
+    }
+    pub fn from_value(value: serde_json::Value) -> SonataResult<Self> {
+        check_model_config(deserialize_model_config(value), None)
+    }
+    fn synthesis_config(&self) -> PiperSynthesisConfig {
+        PiperSynthesisConfig {
+            speaker: None,
+            noise_scale: self.inference.noise_scale,
+            length_scale: self.inference.length_scale,
+            noise_w: self.inference.noise_w,
+            prosody_variation: self.inference.prosody_variation,
+        }
+    }
+    /// Check that every phoneme has an id below `num_symbols` and that the padding,
+    /// beginning and end of sentence phonemes are mapped. Returns their ids, which voices
+    /// exported from Coqui TTS don't need.
+    fn validate_phoneme_id_map(&self) -> Result<(i64, i64, i64), String> {
+        for (phoneme, ids) in self.phoneme_id_map.iter() {
+            if ids.is_empty() {
+                return Err(format!("phoneme `{}` is mapped to no id", phoneme));
+            }
+            if let Some(id) = ids
+                .iter()
+                .find(|id| **id < 0 || **id >= self.num_symbols as i64)
+            {
+                return Err(format!(
+                    "id {} of phoneme `{}` is out of range for the model's {} symbols",
+                    id, phoneme, self.num_symbols
+                ));
+            }
+        }
+        if self.coqui.is_some() {
+            return Ok(Default::default());
+        }
+        let meta_id = |phoneme: char, name: &str| {
+            self.phoneme_id_map
+                .get(&phoneme)
+                .map(|ids| ids[0])
+                .ok_or_else(|| format!("`phoneme_id_map` has no {} phoneme `{}`", name, phoneme))
+        };
+        Ok((
+            meta_id(PAD, "padding")?,
+            meta_id(BOS, "beginning of sentence")?,
+            meta_id(EOS, "end of sentence")?,
+        ))
+    }
+    /// The voice's language code, falling back to the espeak voice name
+    pub(crate) fn language_code(&self) -> String {
+        self.language
+            .as_ref()
+            .map(|lang| lang.code.clone())
+            .unwrap_or_else(|| self.espeak.voice.clone())
+    }
+}
+
+impl std::str::FromStr for ModelConfig {
+    type Err = SonataError;
+
+    fn from_str(json: &str) -> SonataResult<Self> {
+        check_model_config(
+            serde_json::from_str(json).and_then(deserialize_model_config),
+            None,
+        )
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct PiperSynthesisConfig {
     pub speaker: Option<i64>,
     pub noise_scale: f32,
     pub length_scale: f32,
     pub noise_w: f32,
+    /// Vary `noise_scale` and `length_scale` from one sentence to the next, see
+    /// [`ProsodyVariation`]
+    pub prosody_variation: Option<ProsodyVariation>,
+}
+
+impl PiperSynthesisConfig {
+    /// `noise_scale` and `length_scale` for the sentence of `input_ids`
+    fn sentence_scales(&self, input_ids: &[i64]) -> (f32, f32) {
+        match self.prosody_variation {
+            Some(ref variation) => variation.vary(self.noise_scale, self.length_scale, input_ids),
+            None => (self.noise_scale, self.length_scale),
+        }
+    }
 }
 
 trait VitsModelCommons {
     fn get_synth_config(&self) -> &RwLock<PiperSynthesisConfig>;
     fn get_config(&self) -> &ModelConfig;
+    /// The inference defaults, which start as those of the config and change on reload
+    fn get_inference(&self) -> &RwLock<InferenceConfig>;
     fn get_speaker_map(&self) -> &HashMap<i64, String>;
-    fn get_tashkeel_engine(&self) -> Option<&libtashkeel_base::DynamicInferenceEngine>;
+    fn get_tashkeel_engine(&self) -> Option<&TashkeelEngine>;
+    fn get_contour_inputs(&self) -> &ContourInputs;
+    fn infer_with_values(
+        &self,
+        input_phonemes: Vec<i64>,
+        synth_config: &PiperSynthesisConfig,
+    ) -> SonataAudioResult;
     fn get_meta_ids(&self) -> (i64, i64, i64) {
-        let config = self.get_config();
-        let pad_id = *config.phoneme_id_map.get(&PAD).unwrap().first().unwrap();
-        let bos_id = *config.phoneme_id_map.get(&BOS).unwrap().first().unwrap();
-        let eos_id = *config.phoneme_id_map.get(&EOS).unwrap().first().unwrap();
-        (pad_id, bos_id, eos_id)
+        self.get_config().meta_ids
     }
     fn language(&self) -> Option<String> {
-        self.get_config()
-            .language
-            .as_ref()
-            .map(|lang| lang.code.clone())
-            .or_else(|| Some(self.get_config().espeak.voice.clone()))
+        Some(self.get_config().language_code())
     }
     fn get_properties(&self) -> HashMap<String, String> {
         HashMap::from([(
@@ -195,8 +653,23 @@ trait VitsModelCommons {
                 .unwrap_or("unknown".to_string()),
         )])
     }
+    fn get_synthesis_cache_key(&self) -> Option<String> {
+        let key = self.get_config().key.as_ref()?;
+        let synth_config = self.get_synth_config().read().unwrap();
+        Some(format!(
+            "{}:{:?}:{}:{}:{}:{:?}{}",
+            key,
+            synth_config.speaker,
+            synth_config.noise_scale,
+            synth_config.length_scale,
+            synth_config.noise_w,
+            synth_config.prosody_variation,
+            self.get_contour_inputs().cache_key()
+        ))
+    }
     fn factory_synthesis_config(&self) -> PiperSynthesisConfig {
         let config = self.get_config();
+        let inference = self.get_inference().read().unwrap();
 
         let speaker = if config.num_speakers > 0 {
             Some(0)
@@ -205,31 +678,108 @@ trait VitsModelCommons {
         };
         PiperSynthesisConfig {
             speaker,
-            length_scale: config.inference.length_scale,
-            noise_scale: config.inference.noise_scale,
-            noise_w: config.inference.noise_w,
+            length_scale: inference.length_scale,
+            noise_scale: inference.noise_scale,
+            noise_w: inference.noise_w,
+            prosody_variation: inference.prosody_variation,
         }
     }
     fn speakers(&self) -> SonataResult<HashMap<i64, String>> {
         Ok(self.get_speaker_map().clone())
     }
+    /// Check that `speaker` is in range for the model, before it reaches inference
+    fn check_speaker(&self, speaker: Option<i64>) -> SonataResult<()> {
+        let num_speakers = self.get_config().num_speakers as i64;
+        match speaker {
+            Some(sid) if num_speakers <= 1 && sid != 0 => {
+                Err(SonataError::OperationError(format!(
+                    "Invalid speaker id `{}`: the voice has a single speaker",
+                    sid
+                )))
+            }
+            Some(sid) if num_speakers > 1 && !(0..num_speakers).contains(&sid) => {
+                Err(SonataError::OperationError(format!(
+                    "Speaker id `{}` is out of range: the voice has {} speakers, with ids 0 to {}",
+                    sid,
+                    num_speakers,
+                    num_speakers - 1
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// The current synthesis config, with the voice of `speaker`
+    fn synth_config_for_speaker(&self, speaker: i64) -> SonataResult<PiperSynthesisConfig> {
+        self.check_speaker(Some(speaker))?;
+        Ok(PiperSynthesisConfig {
+            speaker: Some(speaker),
+            ..self.call_synth_config()
+        })
+    }
     fn _do_set_default_synth_config(&self, new_config: &PiperSynthesisConfig) -> SonataResult<()> {
+        self.check_speaker(new_config.speaker)?;
         let mut synth_config = self.get_synth_config().write().unwrap();
         synth_config.length_scale = new_config.length_scale;
         synth_config.noise_scale = new_config.noise_scale;
         synth_config.noise_w = new_config.noise_w;
-        if let Some(sid) = new_config.speaker {
-            if self.get_speaker_map().contains_key(&sid) {
-                synth_config.speaker = Some(sid);
-            } else {
-                return Err(SonataError::OperationError(format!(
-                    "No speaker was found with the given id `{}`",
-                    sid
-                )));
-            }
+        synth_config.prosody_variation = new_config.prosody_variation;
+        if new_config.speaker.is_some() {
+            synth_config.speaker = new_config.speaker;
         }
         Ok(())
     }
+    /// The part of `rate` within the voice's natural rate range, reached by scaling
+    /// `length_scale`
+    fn do_native_rate(&self, rate: f32) -> SonataResult<f32> {
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(SonataError::OperationError(format!(
+                "Invalid speaking rate `{}`",
+                rate
+            )));
+        }
+        let (min_rate, max_rate) = self.get_inference().read().unwrap().rate_range;
+        Ok(rate.max(min_rate).min(max_rate))
+    }
+    /// The synthesis config of a call: the current one, at the native rate set by
+    /// [`sonata_core::with_native_rate`]
+    fn call_synth_config(&self) -> PiperSynthesisConfig {
+        let mut synth_config = self.get_synth_config().read().unwrap().clone();
+        synth_config.length_scale /= sonata_core::current_native_rate();
+        synth_config
+    }
+    /// Make `inference` the defaults. The scales of the fallback config that are still
+    /// the old defaults follow them, while those the application set are kept. Both
+    /// change under the lock of the fallback config, so no sentence is spoken with a mix
+    /// of old and new scales
+    fn swap_inference(&self, inference: InferenceConfig) {
+        let mut synth_config = self.get_synth_config().write().unwrap();
+        let mut current = self.get_inference().write().unwrap();
+        if synth_config.noise_scale == current.noise_scale {
+            synth_config.noise_scale = inference.noise_scale;
+        }
+        if synth_config.length_scale == current.length_scale {
+            synth_config.length_scale = inference.length_scale;
+        }
+        if synth_config.noise_w == current.noise_w {
+            synth_config.noise_w = inference.noise_w;
+        }
+        if synth_config.prosody_variation == current.prosody_variation {
+            synth_config.prosody_variation = inference.prosody_variation;
+        }
+        *current = inference;
+    }
+    /// Phonemes missing from the model's phoneme map, which are left out of its input
+    fn find_unknown_phonemes(&self, phonemes: &str) -> Vec<char> {
+        let config = self.get_config();
+        let mut unknown = Vec::from_iter(
+            phonemes
+                .chars()
+                .filter(|phoneme| !config.phoneme_id_map.contains_key(phoneme)),
+        );
+        unknown.sort_unstable();
+        unknown.dedup();
+        unknown
+    }
     fn phonemes_to_input_ids(
         &self,
         phonemes: &str,
@@ -238,47 +788,76 @@ trait VitsModelCommons {
         eos_id: i64,
     ) -> Vec<i64> {
         let config = self.get_config();
+        if let Some(ref tokenizer) = config.coqui {
+            return tokenizer.input_ids(phonemes, &config.phoneme_id_map);
+        }
         let mut phoneme_ids: Vec<i64> = Vec::with_capacity((phonemes.len() + 1) * 2);
         phoneme_ids.push(bos_id);
         for phoneme in phonemes.chars() {
             if let Some(id) = config.phoneme_id_map.get(&phoneme) {
-                phoneme_ids.push(*id.first().unwrap());
+                phoneme_ids.push(id[0]);
                 phoneme_ids.push(pad_id);
             }
         }
         phoneme_ids.push(eos_id);
         phoneme_ids
     }
+    /// Speak the sentences with the current synthesis config and the voice of `speaker`
+    fn do_speak_batch_as_speaker(
+        &self,
+        phoneme_batches: Vec<String>,
+        speaker: i64,
+    ) -> SonataResult<Vec<Audio>> {
+        let synth_config = self.synth_config_for_speaker(speaker)?;
+        let (pad_id, bos_id, eos_id) = self.get_meta_ids();
+        Result::from_iter(phoneme_batches.into_iter().map(|phonemes| {
+            let input_ids = self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id);
+            self.infer_with_values(input_ids, &synth_config)
+        }))
+    }
+    /// Speak each sentence with the current synthesis config, keeping going after the
+    /// sentences that fail
+    fn do_speak_batch_per_item(&self, phoneme_batches: Vec<String>) -> Vec<SonataAudioResult> {
+        let (pad_id, bos_id, eos_id) = self.get_meta_ids();
+        let synth_config = self.call_synth_config();
+        Vec::from_iter(phoneme_batches.into_iter().map(|phonemes| {
+            let input_ids = self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id);
+            self.infer_with_values(input_ids, &synth_config)
+        }))
+    }
     fn do_phonemize_text(&self, text: &str) -> SonataResult<Phonemes> {
         let config = self.get_config();
-        let text = if config.espeak.voice == "ar" {
-            let diacritized = self.diacritize_text(text)?;
-            Cow::from(diacritized)
-        } else {
-            Cow::from(text)
-        };
-        let phonemes = match text_to_phonemes(&text, &config.espeak.voice, None, true, false) {
-            Ok(ph) => ph,
-            Err(e) => {
-                return Err(SonataError::PhonemizationError(format!(
-                    "Failed to phonemize given text using espeak-ng. Error: {}",
-                    e
-                )))
-            }
-        };
-        Ok(phonemes.into())
-    }
-    fn diacritize_text(&self, text: &str) -> SonataResult<String> {
-        let diacritized_text = match do_tashkeel(self.get_tashkeel_engine().unwrap(), text, None, false) {
-            Ok(d_text) => d_text,
-            Err(msg) => {
-                return Err(SonataError::OperationError(format!(
-                    "Failed to diacritize text using  libtashkeel. {}",
-                    msg
-                )))
+        if config.phoneme_type == PhonemeType::Text {
+            let text = match config.coqui {
+                Some(ref tokenizer) => tokenizer.clean_text(text),
+                None => text.to_string(),
+            };
+            return Ok(vec![text].into());
+        }
+        let tashkeel_engine = self.get_tashkeel_engine();
+        let tashkeel = tashkeel_engine.is_some();
+        if let Some(phonemes) = PHONEME_CACHE.get(text, &config.espeak.voice, tashkeel) {
+            return Ok(phonemes);
+        }
+        let original_text = text;
+        let mut timings = StageTimings::default();
+        let text = match tashkeel_engine {
+            Some(engine) => {
+                let timer = std::time::Instant::now();
+                let text = diacritize_text(engine, text)?;
+                timings.tashkeel_ms = timer.elapsed().as_secs_f32() * 1000.0;
+                Cow::from(text)
             }
+            None => Cow::from(text),
         };
-        Ok(diacritized_text)
+        let phonemes = espeak_phonemize(&text, &config.espeak.voice)?.with_timings(timings);
+        PHONEME_CACHE.insert(
+            original_text,
+            &config.espeak.voice,
+            tashkeel,
+            phonemes.clone(),
+        );
+        Ok(phonemes)
     }
     fn get_audio_output_info(&self) -> SonataResult<AudioInfo> {
         Ok(AudioInfo {
@@ -292,9 +871,15 @@ trait VitsModelCommons {
 pub struct VitsModel {
     synth_config: RwLock<PiperSynthesisConfig>,
     config: ModelConfig,
+    inference: RwLock<InferenceConfig>,
     speaker_map: HashMap<i64, String>,
-    session: ort::Session,
-    tashkeel_engine: Option<libtashkeel_base::DynamicInferenceEngine>,
+    session: RwLock<Arc<dyn InferenceSession>>,
+    /// The model file, replaced when a reloaded config points to another one
+    model_path: RwLock<PathBuf>,
+    /// Where the sessions of the voice run, kept for the models it reloads
+    placement: SessionPlacement,
+    tashkeel_engine: Option<TashkeelEngine>,
+    contour_inputs: ContourInputs,
 }
 
 impl VitsModel {
@@ -309,48 +894,67 @@ impl VitsModel {
         synth_config: PiperSynthesisConfig,
         onnx_path: &Path,
     ) -> SonataResult<Self> {
-        let session = match create_inference_session(onnx_path) {
-            Ok(session) => session,
-            Err(err) => {
-                return Err(SonataError::OperationError(format!(
-                    "Failed to initialize onnxruntime inference session: `{}`",
-                    err
-                )))
-            }
-        };
+        let session = create_inference_session(onnx_path)?;
+        Self::from_session(config, synth_config, session, onnx_path)
+    }
+    fn from_session(
+        config: ModelConfig,
+        synth_config: PiperSynthesisConfig,
+        session: Box<dyn InferenceSession>,
+        onnx_path: &Path,
+    ) -> SonataResult<Self> {
+        validate_model_inputs(&session.io_info(), &config, onnx_path)?;
         let speaker_map = reversed_mapping(&config.speaker_id_map);
-        let tashkeel_engine = if config.espeak.voice == "ar" {
-            match libtashkeel_base::create_inference_engine(None) {
-                Ok(engine) => Some(engine),
-                Err(msg) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to create inference engine for libtashkeel. {}",
-                        msg
-                    )))
-                }
-            }
-        } else {
-            None
-        };
+        let tashkeel_engine = create_tashkeel_engine(&config)?;
+        let contour_inputs = ContourInputs::new(&session.io_info());
         Ok(Self {
             synth_config: RwLock::new(synth_config),
+            inference: RwLock::new(config.inference.clone()),
             config,
             speaker_map,
-            session,
+            session: RwLock::new(Arc::from(session)),
+            model_path: RwLock::new(onnx_path.to_path_buf()),
+            placement: SessionPlacement::current(),
             tashkeel_engine,
+            contour_inputs,
         })
     }
-    fn infer_with_values(&self, input_phonemes: Vec<i64>) -> SonataAudioResult {
-        let synth_config = self.synth_config.read().unwrap();
+    /// Names, shapes and element types of the inputs and outputs of the model
+    pub fn get_input_output_info(&self) -> ModelIo {
+        self.session.read().unwrap().io_info()
+    }
+}
 
+impl VitsModelCommons for VitsModel {
+    fn get_synth_config(&self) -> &RwLock<PiperSynthesisConfig> {
+        &self.synth_config
+    }
+    fn get_config(&self) -> &ModelConfig {
+        &self.config
+    }
+    fn get_inference(&self) -> &RwLock<InferenceConfig> {
+        &self.inference
+    }
+    fn get_speaker_map(&self) -> &HashMap<i64, String> {
+        &self.speaker_map
+    }
+    fn get_tashkeel_engine(&self) -> Option<&TashkeelEngine> {
+        self.tashkeel_engine.as_ref()
+    }
+    fn get_contour_inputs(&self) -> &ContourInputs {
+        &self.contour_inputs
+    }
+    fn infer_with_values(
+        &self,
+        input_phonemes: Vec<i64>,
+        synth_config: &PiperSynthesisConfig,
+    ) -> SonataAudioResult {
+        self.check_speaker(synth_config.speaker)?;
         let input_len = input_phonemes.len();
+        let (noise_scale, length_scale) = synth_config.sentence_scales(&input_phonemes);
         let phoneme_inputs = Array2::<i64>::from_shape_vec((1, input_len), input_phonemes).unwrap();
         let input_lengths = Array1::<i64>::from_iter([input_len as i64]);
-        let scales = Array1::<f32>::from_iter([
-            synth_config.noise_scale,
-            synth_config.length_scale,
-            synth_config.noise_w,
-        ]);
+        let scales = Array1::<f32>::from_iter([noise_scale, length_scale, synth_config.noise_w]);
         let speaker_id = if self.config.num_speakers > 1 {
             let sid = synth_config.speaker.unwrap_or(0);
             Some(Array1::<i64>::from_iter([sid]))
@@ -358,48 +962,32 @@ impl VitsModel {
             None
         };
 
-        let session = &self.session;
+        let mut dump = TensorDump::start();
+        if let Some(ref mut dump) = dump {
+            dump.add_inputs(&phoneme_inputs, &scales, speaker_id.as_ref());
+        }
+
         let timer = std::time::Instant::now();
         let outputs = {
             let mut inputs = vec![
-                ort::SessionInputValue::from(
-                    Value::from_array(phoneme_inputs).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(input_lengths).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(scales).unwrap()
-                ),
+                SessionInput::Int64(phoneme_inputs.into_dyn().into()),
+                SessionInput::Int64(input_lengths.into_dyn().into()),
+                SessionInput::Float32(scales.into_dyn().into()),
             ];
             if let Some(sid_tensor) = speaker_id {
-                inputs.push(ort::SessionInputValue::from(
-                    Value::from_array(sid_tensor).unwrap()
-                ));
-            }
-            match session.run(SessionInputs::from(inputs.as_slice())) {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
+                inputs.push(SessionInput::Int64(sid_tensor.into_dyn().into()));
             }
+            inputs.extend(self.contour_inputs.session_inputs(input_len));
+            let session = Arc::clone(&self.session.read().unwrap());
+            session.run(inputs)?
         };
         let inference_ms = timer.elapsed().as_millis() as f32;
 
-        let outputs = match outputs[0].try_extract_tensor::<f32>() {
-            Ok(out) => out,
-            Err(e) => {
-                return Err(SonataError::OperationError(format!(
-                    "Failed to run model inference. Error: {}",
-                    e
-                )))
-            }
-        };
-
-        let audio = Vec::from(outputs.view().as_slice().unwrap());
+        let audio = outputs.into_first()?.into_raw_vec();
+        if let Some(mut dump) = dump {
+            dump.add_slice("audio", &audio);
+            dump.save()?;
+        }
 
         Ok(Audio::new(
             audio.into(),
@@ -407,24 +995,6 @@ impl VitsModel {
             Some(inference_ms),
         ))
     }
-    pub fn get_input_output_info(&self) -> SonataResult<Vec<String>> {
-        todo!()
-    }
-}
-
-impl VitsModelCommons for VitsModel {
-    fn get_synth_config(&self) -> &RwLock<PiperSynthesisConfig> {
-        &self.synth_config
-    }
-    fn get_config(&self) -> &ModelConfig {
-        &self.config
-    }
-    fn get_speaker_map(&self) -> &HashMap<i64, String> {
-        &self.speaker_map
-    }
-    fn get_tashkeel_engine(&self) -> Option<&libtashkeel_base::DynamicInferenceEngine> {
-        self.tashkeel_engine.as_ref()
-    }
 }
 
 impl SonataModel for VitsModel {
@@ -439,24 +1009,50 @@ impl SonataModel for VitsModel {
                 .into_iter()
                 .map(|phonemes| self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id)),
         );
+        let synth_config = self.call_synth_config();
         let mut retval = Vec::new();
         for phonemes in phoneme_batches.into_iter() {
-            retval.push(self.infer_with_values(phonemes)?);
+            retval.push(self.infer_with_values(phonemes, &synth_config)?);
         }
         Ok(retval)
     }
+    fn speak_batch_as_speaker(
+        &self,
+        phoneme_batches: Vec<String>,
+        speaker: i64,
+    ) -> SonataResult<Vec<Audio>> {
+        self.do_speak_batch_as_speaker(phoneme_batches, speaker)
+    }
+    fn speak_batch_per_item(&self, phoneme_batches: Vec<String>) -> Vec<SonataAudioResult> {
+        self.do_speak_batch_per_item(phoneme_batches)
+    }
 
     fn speak_one_sentence(&self, phonemes: String) -> SonataAudioResult {
+        let synth_config = self.call_synth_config();
+        self.speak_one_sentence_with_config(phonemes, &synth_config)
+    }
+    fn speak_one_sentence_with_config(
+        &self,
+        phonemes: String,
+        synthesis_config: &dyn Any,
+    ) -> SonataAudioResult {
+        let Some(synth_config) = synthesis_config.downcast_ref::<PiperSynthesisConfig>() else {
+            return Err(SonataError::OperationError(
+                "Invalid configuration for Vits Model".to_string(),
+            ));
+        };
         let (pad_id, bos_id, eos_id) = self.get_meta_ids();
         let phonemes = self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id);
-        self.infer_with_values(phonemes)
+        self.infer_with_values(phonemes, synth_config)
     }
     fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+        let inference = self.inference.read().unwrap();
         Ok(Box::new(PiperSynthesisConfig {
             speaker: Some(0),
-            noise_scale: self.config.inference.noise_scale,
-            noise_w: self.config.inference.noise_w,
-            length_scale: self.config.inference.length_scale,
+            noise_scale: inference.noise_scale,
+            noise_w: inference.noise_w,
+            length_scale: inference.length_scale,
+            prosody_variation: inference.prosody_variation,
         }))
     }
     fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
@@ -470,6 +1066,37 @@ impl SonataModel for VitsModel {
             )),
         }
     }
+    fn native_rate(&self, rate: f32) -> SonataResult<f32> {
+        self.do_native_rate(rate)
+    }
+    /// Swap in the inference defaults of the config, and the model if the config points
+    /// to another model file. Sentences being spoken finish with the old model, and the
+    /// new one runs where the voice was loaded to
+    fn reload_config(&self, config_path: &Path) -> SonataResult<()> {
+        let config = load_replacement_config(config_path, &self.config)?;
+        let model_path = model_paths(config_path, &config)?.remove(0);
+        if *self.model_path.read().unwrap() != model_path {
+            let session = self
+                .placement
+                .scope(|| create_inference_session(&model_path))?;
+            check_replacement_encoder(
+                &session.io_info(),
+                &config,
+                &model_path,
+                &self.contour_inputs,
+            )?;
+            *self.session.write().unwrap() = Arc::from(session);
+            *self.model_path.write().unwrap() = model_path;
+        }
+        self.swap_inference(config.inference);
+        Ok(())
+    }
+    fn prosody_features(&self) -> SonataResult<Vec<ProsodyFeature>> {
+        Ok(self.contour_inputs.features().to_vec())
+    }
+    fn set_contour(&self, feature: ProsodyFeature, contour: Option<Contour>) -> SonataResult<()> {
+        self.contour_inputs.set(feature, contour)
+    }
     fn get_language(&self) -> SonataResult<Option<String>> {
         Ok(self.language())
     }
@@ -479,9 +1106,27 @@ impl SonataModel for VitsModel {
     fn speaker_name_to_id(&self, name: &str) -> SonataResult<Option<i64>> {
         Ok(self.config.speaker_id_map.get(name).copied())
     }
+    fn phoneme_id_map(&self) -> SonataResult<Option<&HashMap<char, Vec<i64>>>> {
+        Ok(Some(&self.config.phoneme_id_map))
+    }
+    fn speaker_id_map(&self) -> SonataResult<Option<&HashMap<String, i64>>> {
+        Ok(Some(&self.config.speaker_id_map))
+    }
+    fn current_speaker(&self) -> SonataResult<Option<i64>> {
+        Ok(self.get_synth_config().read().unwrap().speaker)
+    }
+    fn unknown_phonemes(&self, phonemes: &str) -> SonataResult<Vec<char>> {
+        Ok(self.find_unknown_phonemes(phonemes))
+    }
     fn properties(&self) -> SonataResult<HashMap<String, String>> {
         Ok(self.get_properties())
     }
+    fn synthesis_cache_key(&self) -> SonataResult<Option<String>> {
+        Ok(self.get_synthesis_cache_key())
+    }
+    fn memory_usage(&self) -> SonataResult<MemoryUsage> {
+        Ok(self.session.read().unwrap().memory_usage())
+    }
     fn audio_output_info(&self) -> SonataResult<AudioInfo> {
         self.get_audio_output_info()
     }
@@ -490,10 +1135,36 @@ impl SonataModel for VitsModel {
 pub struct VitsStreamingModel {
     synth_config: RwLock<PiperSynthesisConfig>,
     config: ModelConfig,
+    inference: RwLock<InferenceConfig>,
     speaker_map: HashMap<i64, String>,
-    encoder_model: ort::Session,
-    decoder_model: Arc<ort::Session>,
-    tashkeel_engine: Option<libtashkeel_base::DynamicInferenceEngine>,
+    sessions: RwLock<StreamingSessions>,
+    /// The encoder and decoder files, replaced when a reloaded config points to others
+    model_paths: RwLock<Vec<PathBuf>>,
+    /// Where the sessions of the voice run, kept for the models it reloads
+    placement: SessionPlacement,
+    tashkeel_engine: Option<TashkeelEngine>,
+    chunk_overlap: RwLock<ChunkOverlap>,
+    latency_target: RwLock<Option<Duration>>,
+    /// Mel frames the decoder processes per second, measured while streaming
+    decode_rate: Arc<RwLock<Option<f64>>>,
+    decoder_batch_size: RwLock<usize>,
+    edited_sentence: Mutex<Option<EditedSentence>>,
+    contour_inputs: ContourInputs,
+    /// Used by the decoder instead of the embedding of the current speaker
+    speaker_embedding: RwLock<Option<Vec<f32>>>,
+}
+
+/// The encoder and decoder of a streaming voice, which are replaced together
+struct StreamingSessions {
+    encoder: Arc<dyn InferenceSession>,
+    decoder: Arc<dyn InferenceSession>,
+}
+
+/// The last sentence encoded to edit its durations
+struct EditedSentence {
+    input_phonemes: Vec<i64>,
+    synth_config: PiperSynthesisConfig,
+    encoder_outputs: Arc<EncoderOutputs>,
 }
 
 impl VitsStreamingModel {
@@ -503,59 +1174,151 @@ impl VitsStreamingModel {
         encoder_path: &Path,
         decoder_path: &Path,
     ) -> SonataResult<Self> {
-        let encoder_model = match create_inference_session(encoder_path) {
-            Ok(model) => model,
-            Err(err) => {
-                return Err(SonataError::OperationError(format!(
-                    "Failed to initialize onnxruntime inference session: `{}`",
-                    err
-                )))
-            }
-        };
-        let decoder_model = match create_inference_session(decoder_path) {
-            Ok(model) => Arc::new(model),
-            Err(err) => {
-                return Err(SonataError::OperationError(format!(
-                    "Failed to initialize onnxruntime inference session: `{}`",
-                    err
-                )))
-            }
-        };
+        let encoder_model = create_inference_session(encoder_path)?;
+        let decoder_model = create_inference_session(decoder_path)?;
+        Self::from_sessions(
+            config,
+            synth_config,
+            encoder_model,
+            encoder_path,
+            decoder_model,
+            decoder_path,
+        )
+    }
+    fn from_sessions(
+        config: ModelConfig,
+        synth_config: PiperSynthesisConfig,
+        encoder_model: Box<dyn InferenceSession>,
+        encoder_path: &Path,
+        decoder_model: Box<dyn InferenceSession>,
+        decoder_path: &Path,
+    ) -> SonataResult<Self> {
+        let decoder_model: Arc<dyn InferenceSession> = Arc::from(decoder_model);
+        validate_model_inputs(&encoder_model.io_info(), &config, encoder_path)?;
+        validate_decoder_inputs(&decoder_model.io_info(), &config, decoder_path)?;
         let speaker_map = reversed_mapping(&config.speaker_id_map);
         let tashkeel_engine = create_tashkeel_engine(&config)?;
+        let contour_inputs = ContourInputs::new(&encoder_model.io_info());
         Ok(Self {
             synth_config: RwLock::new(synth_config),
+            inference: RwLock::new(config.inference.clone()),
             config,
             speaker_map,
-            encoder_model,
-            decoder_model,
+            sessions: RwLock::new(StreamingSessions {
+                encoder: Arc::from(encoder_model),
+                decoder: decoder_model,
+            }),
+            model_paths: RwLock::new(vec![encoder_path.to_path_buf(), decoder_path.to_path_buf()]),
+            placement: SessionPlacement::current(),
             tashkeel_engine,
+            chunk_overlap: RwLock::new(ChunkOverlap::default()),
+            latency_target: RwLock::new(None),
+            decode_rate: Arc::new(RwLock::new(None)),
+            decoder_batch_size: RwLock::new(1),
+            edited_sentence: Mutex::new(None),
+            contour_inputs,
+            speaker_embedding: RwLock::new(None),
         })
     }
-
-    fn infer_with_values(&self, input_phonemes: Vec<i64>) -> SonataAudioResult {
-        let timer = std::time::Instant::now();
-        let encoder_output = self.infer_encoder(input_phonemes)?;
-        let audio = encoder_output.infer_decoder(self.decoder_model.as_ref())?;
-        let inference_ms = timer.elapsed().as_millis() as f32;
-        Ok(Audio::new(
-            audio,
-            self.config.audio.sample_rate as usize,
-            Some(inference_ms),
-        ))
+    pub fn get_encoder_input_output_info(&self) -> ModelIo {
+        self.sessions.read().unwrap().encoder.io_info()
+    }
+    pub fn get_decoder_input_output_info(&self) -> ModelIo {
+        self.sessions.read().unwrap().decoder.io_info()
+    }
+    fn decoder(&self) -> Arc<dyn InferenceSession> {
+        Arc::clone(&self.sessions.read().unwrap().decoder)
+    }
+    /// The speaker embedding (`g`) the decoder is conditioned on for `speaker`
+    pub fn speaker_embedding(&self, speaker: i64) -> SonataResult<Vec<f32>> {
+        if self.config.num_speakers <= 1 {
+            return Err(SonataError::OperationError(
+                "The voice has a single speaker, and no speaker embeddings".to_string(),
+            ));
+        }
+        let synth_config = self.synth_config_for_speaker(speaker)?;
+        let (pad_id, bos_id, eos_id) = self.get_meta_ids();
+        let encoder_outputs =
+            self.run_encoder(vec![bos_id, pad_id, eos_id], &synth_config, None)?;
+        Ok(encoder_outputs.g.into_raw_vec())
+    }
+    /// Condition the decoder on `embedding` rather than on the embedding of the current
+    /// speaker, e.g. on the average of the embeddings of several speakers. `None` goes
+    /// back to the current speaker.
+    ///
+    /// The embedding must have the length of those from [`Self::speaker_embedding`].
+    /// Phoneme durations are still predicted for the current speaker.
+    pub fn set_speaker_embedding(&self, embedding: Option<Vec<f32>>) -> SonataResult<()> {
+        if let Some(ref embedding) = embedding {
+            let embedding_len = self.speaker_embedding(0)?.len();
+            if embedding.len() != embedding_len {
+                return Err(SonataError::OperationError(format!(
+                    "Invalid speaker embedding of length {}. The voice's embeddings have {} values",
+                    embedding.len(),
+                    embedding_len
+                )));
+            }
+            if !embedding.iter().all(|value| value.is_finite()) {
+                return Err(SonataError::OperationError(
+                    "Invalid speaker embedding: it has values that are not finite".to_string(),
+                ));
+            }
+        }
+        *self.speaker_embedding.write().unwrap() = embedding;
+        *self.edited_sentence.lock().unwrap() = None;
+        Ok(())
+    }
+    /// Speak with the voice of the wave file at `reference_path`, embedded by
+    /// `speaker_encoder`. The voice must have several speakers, and have been trained on
+    /// the embeddings of the same speaker encoder.
+    ///
+    /// Like [`Self::set_speaker_embedding`], this clones the timbre of the reference but
+    /// not its rhythm, since the phoneme durations are still predicted for the current
+    /// speaker.
+    pub fn clone_voice(
+        &self,
+        speaker_encoder: &SpeakerEncoder,
+        reference_path: &Path,
+    ) -> SonataResult<()> {
+        self.set_speaker_embedding(Some(speaker_encoder.embed_file(reference_path)?))
     }
-    fn infer_encoder(&self, input_phonemes: Vec<i64>) -> SonataResult<EncoderOutputs> {
-        let synth_config = self.synth_config.read().unwrap();
 
+    fn infer_encoder(
+        &self,
+        input_phonemes: Vec<i64>,
+        synth_config: &PiperSynthesisConfig,
+        mut dump: Option<&mut TensorDump>,
+    ) -> SonataResult<EncoderOutputs> {
+        let mut encoder_outputs =
+            self.run_encoder(input_phonemes, synth_config, dump.as_deref_mut())?;
+        if let Some(ref embedding) = *self.speaker_embedding.read().unwrap() {
+            encoder_outputs.g = Array1::from_vec(embedding.clone())
+                .into_shape(encoder_outputs.g.raw_dim())
+                .map_err(|e| {
+                    SonataError::with_message(format!("Invalid speaker embedding: {}", e))
+                })?;
+        }
+        if let Some(dump) = dump {
+            dump.add("z", &encoder_outputs.z);
+            dump.add("y_mask", &encoder_outputs.y_mask);
+            dump.add("g", &encoder_outputs.g);
+        }
+        Ok(encoder_outputs)
+    }
+    /// Run the encoder, whose outputs are conditioned on the current speaker
+    fn run_encoder(
+        &self,
+        input_phonemes: Vec<i64>,
+        synth_config: &PiperSynthesisConfig,
+        mut dump: Option<&mut TensorDump>,
+    ) -> SonataResult<EncoderOutputs> {
+        self.check_speaker(synth_config.speaker)?;
         let input_len = input_phonemes.len();
+        let (noise_scale, length_scale) = synth_config.sentence_scales(&input_phonemes);
         let phoneme_inputs = Array2::<i64>::from_shape_vec((1, input_len), input_phonemes).unwrap();
         let input_lengths = Array1::<i64>::from_iter([input_len as i64]);
 
-        let scales = Array1::<f32>::from_iter([
-            synth_config.noise_scale,
-            synth_config.length_scale,
-            synth_config.noise_w,
-        ]);
+        let scales = Array1::<f32>::from_iter([noise_scale, length_scale, synth_config.noise_w]);
 
         let speaker_id = if self.config.num_speakers > 1 {
             let sid = synth_config.speaker.unwrap_or(0);
@@ -564,32 +1327,58 @@ impl VitsStreamingModel {
             None
         };
 
-        let session = &self.encoder_model;
-        {
-            let mut inputs = vec![
-                ort::SessionInputValue::from(
-                    Value::from_array(phoneme_inputs).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(input_lengths).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(scales).unwrap()
-                ),
-            ];
-            if let Some(sid_tensor) = speaker_id {
-                inputs.push(ort::SessionInputValue::from(
-                    Value::from_array(sid_tensor).unwrap()
-                ));
-            }
-            match session.run(SessionInputs::from(inputs.as_slice())) {
-                Ok(ort_values) => EncoderOutputs::from_values(ort_values),
-                Err(e) => Err(SonataError::OperationError(format!(
-                    "Failed to run model inference. Error: {}",
-                    e
-                ))),
+        if let Some(dump) = dump.as_mut() {
+            dump.add_inputs(&phoneme_inputs, &scales, speaker_id.as_ref());
+        }
+        let mut inputs = vec![
+            SessionInput::Int64(phoneme_inputs.into_dyn().into()),
+            SessionInput::Int64(input_lengths.into_dyn().into()),
+            SessionInput::Float32(scales.into_dyn().into()),
+        ];
+        if let Some(sid_tensor) = speaker_id {
+            inputs.push(SessionInput::Int64(sid_tensor.into_dyn().into()));
+        }
+        inputs.extend(self.contour_inputs.session_inputs(input_len));
+        let encoder = Arc::clone(&self.sessions.read().unwrap().encoder);
+        let outputs = encoder.run(inputs)?;
+        EncoderOutputs::from_values(outputs)
+    }
+    /// Encoder outputs of a sentence whose durations are edited. The outputs of the last
+    /// sentence are reused, so that its edits keep the same random variations
+    fn encode_for_editing(&self, phonemes: &str) -> SonataResult<Arc<EncoderOutputs>> {
+        let (pad_id, bos_id, eos_id) = self.get_meta_ids();
+        let input_phonemes = self.phonemes_to_input_ids(phonemes, pad_id, bos_id, eos_id);
+        let synth_config = self.call_synth_config();
+        let mut edited_sentence = self.edited_sentence.lock().unwrap();
+        if let Some(ref sentence) = *edited_sentence {
+            if sentence.input_phonemes == input_phonemes && sentence.synth_config == synth_config {
+                return Ok(Arc::clone(&sentence.encoder_outputs));
             }
         }
+        let encoder_outputs =
+            Arc::new(self.infer_encoder(input_phonemes.clone(), &synth_config, None)?);
+        *edited_sentence = Some(EditedSentence {
+            input_phonemes,
+            synth_config,
+            encoder_outputs: Arc::clone(&encoder_outputs),
+        });
+        Ok(encoder_outputs)
+    }
+    /// Index of the input id of each phoneme of `phonemes`, `None` for unknown phonemes.
+    /// The padding or blank id following a phoneme's id belongs to the same phoneme
+    fn phoneme_input_indices(&self, phonemes: &str) -> Vec<Option<usize>> {
+        let phoneme_id_map = &self.config.phoneme_id_map;
+        let (mut next_index, step) = match self.config.coqui {
+            Some(ref tokenizer) => tokenizer.phoneme_layout(),
+            // BOS, then each phoneme's id followed by padding
+            None => (1, 2),
+        };
+        Vec::from_iter(phonemes.chars().map(|phoneme| {
+            phoneme_id_map.contains_key(&phoneme).then(|| {
+                next_index += step;
+                next_index - step
+            })
+        }))
     }
 }
 
@@ -600,12 +1389,43 @@ impl VitsModelCommons for VitsStreamingModel {
     fn get_config(&self) -> &ModelConfig {
         &self.config
     }
+    fn get_inference(&self) -> &RwLock<InferenceConfig> {
+        &self.inference
+    }
     fn get_speaker_map(&self) -> &HashMap<i64, String> {
         &self.speaker_map
     }
-    fn get_tashkeel_engine(&self) -> Option<&libtashkeel_base::DynamicInferenceEngine> {
+    fn get_tashkeel_engine(&self) -> Option<&TashkeelEngine> {
         self.tashkeel_engine.as_ref()
     }
+    fn get_contour_inputs(&self) -> &ContourInputs {
+        &self.contour_inputs
+    }
+    fn infer_with_values(
+        &self,
+        input_phonemes: Vec<i64>,
+        synth_config: &PiperSynthesisConfig,
+    ) -> SonataAudioResult {
+        let timer = std::time::Instant::now();
+        let mut dump = TensorDump::start();
+        let encoder_output = self.infer_encoder(input_phonemes, synth_config, dump.as_mut())?;
+        let encoder_ms = timer.elapsed().as_secs_f32() * 1000.0;
+        let audio = encoder_output.infer_decoder(self.decoder().as_ref())?;
+        let decoder_ms = timer.elapsed().as_secs_f32() * 1000.0 - encoder_ms;
+        let inference_ms = timer.elapsed().as_millis() as f32;
+        if let Some(mut dump) = dump {
+            dump.add_slice("audio", audio.as_slice());
+            dump.save()?;
+        }
+        let mut audio = Audio::new(
+            audio,
+            self.config.audio.sample_rate as usize,
+            Some(inference_ms),
+        );
+        audio.timings.encoder_ms = encoder_ms;
+        audio.timings.decoder_ms = decoder_ms;
+        Ok(audio)
+    }
 }
 
 impl SonataModel for VitsStreamingModel {
@@ -620,23 +1440,49 @@ impl SonataModel for VitsStreamingModel {
                 .into_iter()
                 .map(|phonemes| self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id)),
         );
+        let synth_config = self.call_synth_config();
         let mut retval = Vec::new();
         for phonemes in phoneme_batches.into_iter() {
-            retval.push(self.infer_with_values(phonemes)?);
+            retval.push(self.infer_with_values(phonemes, &synth_config)?);
         }
         Ok(retval)
     }
+    fn speak_batch_as_speaker(
+        &self,
+        phoneme_batches: Vec<String>,
+        speaker: i64,
+    ) -> SonataResult<Vec<Audio>> {
+        self.do_speak_batch_as_speaker(phoneme_batches, speaker)
+    }
+    fn speak_batch_per_item(&self, phoneme_batches: Vec<String>) -> Vec<SonataAudioResult> {
+        self.do_speak_batch_per_item(phoneme_batches)
+    }
     fn speak_one_sentence(&self, phonemes: String) -> SonataAudioResult {
+        let synth_config = self.call_synth_config();
+        self.speak_one_sentence_with_config(phonemes, &synth_config)
+    }
+    fn speak_one_sentence_with_config(
+        &self,
+        phonemes: String,
+        synthesis_config: &dyn Any,
+    ) -> SonataAudioResult {
+        let Some(synth_config) = synthesis_config.downcast_ref::<PiperSynthesisConfig>() else {
+            return Err(SonataError::OperationError(
+                "Invalid configuration for Vits Model".to_string(),
+            ));
+        };
         let (pad_id, bos_id, eos_id) = self.get_meta_ids();
         let phonemes = self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id);
-        self.infer_with_values(phonemes)
+        self.infer_with_values(phonemes, synth_config)
     }
     fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+        let inference = self.inference.read().unwrap();
         Ok(Box::new(PiperSynthesisConfig {
             speaker: Some(0),
-            noise_scale: self.config.inference.noise_scale,
-            noise_w: self.config.inference.noise_w,
-            length_scale: self.config.inference.length_scale,
+            noise_scale: inference.noise_scale,
+            noise_w: inference.noise_w,
+            length_scale: inference.length_scale,
+            prosody_variation: inference.prosody_variation,
         }))
     }
     fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
@@ -650,6 +1496,127 @@ impl SonataModel for VitsStreamingModel {
             )),
         }
     }
+    fn native_rate(&self, rate: f32) -> SonataResult<f32> {
+        self.do_native_rate(rate)
+    }
+    /// Swap in the inference defaults of the config, and the encoder and decoder if the
+    /// config points to other model files. Streams being spoken finish with the old
+    /// decoder, and the new models run where the voice was loaded to. New models drop
+    /// the speaker embedding and the measured decoding speed
+    fn reload_config(&self, config_path: &Path) -> SonataResult<()> {
+        let config = load_replacement_config(config_path, &self.config)?;
+        let model_paths = model_paths(config_path, &config)?;
+        if *self.model_paths.read().unwrap() != model_paths {
+            let (encoder_path, decoder_path) = (&model_paths[0], &model_paths[1]);
+            let (encoder_model, decoder_model) = self.placement.scope(|| {
+                SonataResult::Ok((
+                    create_inference_session(encoder_path)?,
+                    create_inference_session(decoder_path)?,
+                ))
+            })?;
+            check_replacement_encoder(
+                &encoder_model.io_info(),
+                &config,
+                encoder_path,
+                &self.contour_inputs,
+            )?;
+            validate_decoder_inputs(&decoder_model.io_info(), &config, decoder_path)?;
+            *self.sessions.write().unwrap() = StreamingSessions {
+                encoder: Arc::from(encoder_model),
+                decoder: Arc::from(decoder_model),
+            };
+            *self.model_paths.write().unwrap() = model_paths.clone();
+            *self.speaker_embedding.write().unwrap() = None;
+            *self.decode_rate.write().unwrap() = None;
+        }
+        self.swap_inference(config.inference);
+        *self.edited_sentence.lock().unwrap() = None;
+        Ok(())
+    }
+    fn prosody_features(&self) -> SonataResult<Vec<ProsodyFeature>> {
+        Ok(self.contour_inputs.features().to_vec())
+    }
+    fn set_contour(&self, feature: ProsodyFeature, contour: Option<Contour>) -> SonataResult<()> {
+        self.contour_inputs.set(feature, contour)?;
+        // Sentences are edited with the contours they were encoded with
+        *self.edited_sentence.lock().unwrap() = None;
+        Ok(())
+    }
+    fn predict_durations(&self, phonemes: String) -> SonataResult<Vec<usize>> {
+        let durations = self.encode_for_editing(&phonemes)?.durations()?;
+        let input_indices = self.phoneme_input_indices(&phonemes);
+        Ok(Vec::from_iter(input_indices.into_iter().map(|index| {
+            index.map_or(0, |index| durations[index] + durations[index + 1])
+        })))
+    }
+    fn speak_with_durations(
+        &self,
+        phonemes: String,
+        overrides: &[(usize, DurationOverride)],
+    ) -> SonataAudioResult {
+        let timer = std::time::Instant::now();
+        let encoder_outputs = self.encode_for_editing(&phonemes)?;
+        let mut durations = encoder_outputs.durations()?;
+        let input_indices = self.phoneme_input_indices(&phonemes);
+        for (phoneme_index, duration_override) in overrides {
+            let Some(input_index) = input_indices.get(*phoneme_index) else {
+                return Err(SonataError::OperationError(format!(
+                    "Phoneme index `{}` is out of range. The sentence has {} phonemes",
+                    phoneme_index,
+                    input_indices.len()
+                )));
+            };
+            let Some(input_index) = *input_index else {
+                continue;
+            };
+            let (phoneme_frames, pad_frames) = override_duration(
+                durations[input_index],
+                durations[input_index + 1],
+                *duration_override,
+            )?;
+            durations[input_index] = phoneme_frames;
+            durations[input_index + 1] = pad_frames;
+        }
+        let audio = encoder_outputs
+            .with_durations(&durations)?
+            .infer_decoder(self.decoder().as_ref())?;
+        Ok(Audio::new(
+            audio,
+            self.config.audio.sample_rate as usize,
+            Some(timer.elapsed().as_millis() as f32),
+        ))
+    }
+    fn speak_with_alignment(&self, phonemes: String) -> SonataResult<(Audio, Vec<Range<usize>>)> {
+        let timer = std::time::Instant::now();
+        let encoder_outputs = self.encode_for_editing(&phonemes)?;
+        let durations = encoder_outputs.durations()?;
+        let samples = encoder_outputs.infer_decoder(self.decoder().as_ref())?;
+        // The first sample of each input id, and the end of the audio
+        let mut starts = Vec::with_capacity(durations.len() + 1);
+        let mut start = 0;
+        for duration in durations.iter() {
+            starts.push(start.min(samples.len()));
+            start += duration * DECODER_HOP_LENGTH as usize;
+        }
+        starts.push(start.min(samples.len()));
+        // Unknown phonemes take no time where the previous phoneme ends, after BOS
+        let mut end = starts[1];
+        let alignment = Vec::from_iter(self.phoneme_input_indices(&phonemes).into_iter().map(
+            |index| match index {
+                Some(index) => {
+                    end = starts[index + 2];
+                    starts[index]..end
+                }
+                None => end..end,
+            },
+        ));
+        let audio = Audio::new(
+            samples,
+            self.config.audio.sample_rate as usize,
+            Some(timer.elapsed().as_millis() as f32),
+        );
+        Ok((audio, alignment))
+    }
     fn get_language(&self) -> SonataResult<Option<String>> {
         Ok(self.language())
     }
@@ -659,9 +1626,46 @@ impl SonataModel for VitsStreamingModel {
     fn speaker_name_to_id(&self, name: &str) -> SonataResult<Option<i64>> {
         Ok(self.config.speaker_id_map.get(name).copied())
     }
+    fn phoneme_id_map(&self) -> SonataResult<Option<&HashMap<char, Vec<i64>>>> {
+        Ok(Some(&self.config.phoneme_id_map))
+    }
+    fn speaker_id_map(&self) -> SonataResult<Option<&HashMap<String, i64>>> {
+        Ok(Some(&self.config.speaker_id_map))
+    }
+    fn current_speaker(&self) -> SonataResult<Option<i64>> {
+        Ok(self.get_synth_config().read().unwrap().speaker)
+    }
+    fn unknown_phonemes(&self, phonemes: &str) -> SonataResult<Vec<char>> {
+        Ok(self.find_unknown_phonemes(phonemes))
+    }
     fn properties(&self) -> SonataResult<HashMap<String, String>> {
         Ok(self.get_properties())
     }
+    fn synthesis_cache_key(&self) -> SonataResult<Option<String>> {
+        // Audio spoken with a custom speaker embedding is not cached
+        if self.speaker_embedding.read().unwrap().is_some() {
+            return Ok(None);
+        }
+        Ok(self.get_synthesis_cache_key())
+    }
+    /// The encoder outputs kept to edit the durations of the last sentence count as
+    /// cached
+    fn memory_usage(&self) -> SonataResult<MemoryUsage> {
+        let sessions = {
+            let sessions = self.sessions.read().unwrap();
+            sessions.encoder.memory_usage() + sessions.decoder.memory_usage()
+        };
+        let cache_bytes = self
+            .edited_sentence
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |edited| edited.encoder_outputs.size_bytes());
+        Ok(MemoryUsage {
+            cache_bytes,
+            ..sessions
+        })
+    }
     fn audio_output_info(&self) -> SonataResult<AudioInfo> {
         self.get_audio_output_info()
     }
@@ -674,218 +1678,358 @@ impl SonataModel for VitsStreamingModel {
         chunk_size: usize,
         chunk_padding: usize,
     ) -> SonataResult<AudioStreamIterator> {
+        self.stream_synthesis_with_control(
+            phonemes,
+            chunk_size,
+            chunk_padding,
+            StreamControl::new(),
+        )
+    }
+    fn stream_synthesis_with_control(
+        &self,
+        phonemes: String,
+        chunk_size: usize,
+        chunk_padding: usize,
+        control: StreamControl,
+    ) -> SonataResult<AudioStreamIterator<'_>> {
         let (pad_id, bos_id, eos_id) = self.get_meta_ids();
         let phonemes = self.phonemes_to_input_ids(&phonemes, pad_id, bos_id, eos_id);
-        let encoder_outputs = self.infer_encoder(phonemes)?;
-        let streamer = Box::new(SpeechStreamer::new(
-            Arc::clone(&self.decoder_model),
+        let synth_config = self.call_synth_config();
+        let mut dump = TensorDump::start();
+        let encoder_outputs = self.infer_encoder(phonemes, &synth_config, dump.as_mut())?;
+        // The audio of a stream is produced in chunks, after the dump is written
+        if let Some(dump) = dump {
+            dump.save()?;
+        }
+        let chunk_sizing = self.latency_target.read().unwrap().map(|latency_target| {
+            AdaptiveChunkSizing::new(
+                latency_target,
+                self.config.audio.sample_rate as f64,
+                chunk_padding,
+                Arc::clone(&self.decode_rate),
+            )
+        });
+        let streamer = SpeechStreamer::new(
+            self.decoder(),
             encoder_outputs,
             chunk_size,
             chunk_padding,
-        ));
-        Ok(streamer)
+            *self.chunk_overlap.read().unwrap(),
+            chunk_sizing,
+            *self.decoder_batch_size.read().unwrap(),
+        )
+        .with_control(control, self.config.audio.sample_rate as usize);
+        Ok(Box::new(streamer))
+    }
+    fn set_chunk_overlap(&self, chunk_overlap: ChunkOverlap) -> SonataResult<()> {
+        *self.chunk_overlap.write().unwrap() = chunk_overlap;
+        Ok(())
+    }
+    fn set_latency_target(&self, latency_target: Option<Duration>) -> SonataResult<()> {
+        *self.latency_target.write().unwrap() = latency_target;
+        Ok(())
+    }
+    fn set_decoder_batch_size(&self, batch_size: usize) -> SonataResult<()> {
+        *self.decoder_batch_size.write().unwrap() = batch_size.max(1);
+        Ok(())
     }
 }
 
 struct EncoderOutputs {
     z: Array<f32, Dim<IxDynImpl>>,
     y_mask: Array<f32, Dim<IxDynImpl>>,
-    #[allow(dead_code)]
+    /// Frames of each input id, if the encoder outputs them
     p_duration: Option<Array<f32, Dim<IxDynImpl>>>,
     g: Array<f32, Dim<IxDynImpl>>,
 }
 
 impl EncoderOutputs {
+    fn size_bytes(&self) -> u64 {
+        let len = self.z.len() + self.y_mask.len() + self.g.len();
+        let len = len + self.p_duration.as_ref().map_or(0, |p_duration| p_duration.len());
+        (len * std::mem::size_of::<f32>()) as u64
+    }
     #[inline(always)]
-    fn from_values(values: SessionOutputs) -> SonataResult<Self> {
-        let z = {
-            let z_t = match values["z"].try_extract_tensor::<f32>() {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
-            };
-            z_t.view().clone().into_owned()
-        };
-        let y_mask = {
-            let y_mask_t = match values["y_mask"].try_extract_tensor::<f32>() {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
-            };
-            y_mask_t.view().clone().into_owned()
-        };
-        let p_duration = if values.contains_key("p_duration") {
-            let p_duration_t = match values["p_duration"].try_extract_tensor::<f32>() {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
-            };
-            Some(p_duration_t.view().clone().into_owned())
-        } else {
-            None
-        };
-        let g = if values.contains_key("g") {
-            let g_t = match values["g"].try_extract_tensor::<f32>() {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
-            };
-            g_t.view().clone().into_owned()
-        } else {
-            Array1::<f32>::from_iter([]).into_dyn()
-        };
-        Ok(Self { z, y_mask, p_duration, g })
+    fn from_values(mut values: SessionOutputs) -> SonataResult<Self> {
+        let z = values.remove("z").ok_or_else(|| {
+            SonataError::with_message("Invalid encoder output. Missing tensor `z`")
+        })?;
+        let y_mask = values.remove("y_mask").ok_or_else(|| {
+            SonataError::with_message("Invalid encoder output. Missing tensor `y_mask`")
+        })?;
+        let p_duration = values.remove("p_duration");
+        let g = values
+            .remove("g")
+            .unwrap_or_else(|| Array1::<f32>::from_iter([]).into_dyn());
+        Ok(Self {
+            z,
+            y_mask,
+            p_duration,
+            g,
+        })
     }
-    fn infer_decoder(&self, session: &ort::Session) -> SonataResult<AudioSamples> {
-        let outputs = {
-            let mut inputs = vec![
-                ort::SessionInputValue::from(
-                    Value::from_array(self.z.view()).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(self.y_mask.view()).unwrap()
-                ),
-            ];
-            if !self.g.is_empty() {
-                inputs.push(ort::SessionInputValue::from(
-                    Value::from_array(self.g.view()).unwrap()
-                ));
-            }
-            match session.run(SessionInputs::from(inputs.as_slice())) {
-                Ok(out) => out,
-                Err(e) => {
-                    return Err(SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    )))
-                }
-            }
-        };
-        match outputs[0].try_extract_tensor::<f32>() {
-            Ok(out) => Ok(Vec::from(out.view().as_slice().unwrap()).into()),
-            Err(e) => Err(SonataError::OperationError(format!(
-                "Failed to run model inference. Error: {}",
-                e
-            ))),
+    /// Number of frames of each input id, as predicted by the encoder
+    fn durations(&self) -> SonataResult<Vec<usize>> {
+        let p_duration = self.p_duration.as_ref().ok_or_else(|| {
+            SonataError::with_message("The encoder of this voice does not output durations")
+        })?;
+        let durations = Vec::from_iter(p_duration.iter().map(|d| d.max(0.0).round() as usize));
+        if durations.iter().sum::<usize>() != self.z.shape()[2] {
+            return Err(SonataError::with_message(
+                "Invalid encoder output. `p_duration` does not match the frames of `z`",
+            ));
         }
+        Ok(durations)
+    }
+    /// The same outputs with the frames of each input id stretched to `durations`
+    fn with_durations(&self, durations: &[usize]) -> SonataResult<Self> {
+        let frame_indices = stretch_frames(&self.durations()?, durations);
+        if frame_indices.is_empty() || self.z.shape()[2] == 0 {
+            return Err(SonataError::with_message(
+                "Can not speak a sentence of no frames",
+            ));
+        }
+        let mut y_mask_shape = self.y_mask.shape().to_vec();
+        y_mask_shape[2] = frame_indices.len();
+        Ok(Self {
+            z: self.z.select(Axis(2), &frame_indices),
+            y_mask: Array::ones(ndarray::IxDyn(&y_mask_shape)),
+            p_duration: Some(Array1::from_iter(durations.iter().map(|d| *d as f32)).into_dyn()),
+            g: self.g.clone(),
+        })
+    }
+    fn infer_decoder(&self, session: &dyn InferenceSession) -> SonataResult<AudioSamples> {
+        let mut inputs = vec![
+            SessionInput::Float32(self.z.view().into()),
+            SessionInput::Float32(self.y_mask.view().into()),
+        ];
+        if !self.g.is_empty() {
+            inputs.push(SessionInput::Float32(self.g.view().into()));
+        }
+        let outputs = session.run(inputs)?;
+        Ok(outputs.into_first()?.into_raw_vec().into())
     }
 }
 
 struct SpeechStreamer {
-    decoder_model: Arc<ort::Session>,
-    encoder_outputs: EncoderOutputs,
+    decoder_model: Arc<dyn InferenceSession>,
+    /// Dropped when the stream is stopped
+    encoder_outputs: Option<EncoderOutputs>,
     mel_chunker: AdaptiveMelChunker,
     one_shot: bool,
+    overlap_window: OverlapWindow,
+    /// Samples shared by consecutive chunks, half on each side of their boundary
+    overlap_len: usize,
+    /// The end of the last chunk, mixed into the start of the next one
+    pending_tail: Vec<f32>,
+    /// Set when chunks are sized for a latency target
+    chunk_sizing: Option<AdaptiveChunkSizing>,
+    /// Chunks decoded in each decoder run
+    batch_size: usize,
+    /// Audio of the decoded chunks that was not returned yet
+    decoded_chunks: VecDeque<AudioSamples>,
+    first_chunk_decoded: bool,
+    control: StreamControl,
+    /// Samples of the fade-out ending the stream when it is stopped
+    fade_out_len: usize,
 }
 
 impl SpeechStreamer {
     fn new(
-        decoder_model: Arc<ort::Session>,
+        decoder_model: Arc<dyn InferenceSession>,
         encoder_outputs: EncoderOutputs,
         chunk_size: usize,
         chunk_padding: usize,
+        chunk_overlap: ChunkOverlap,
+        chunk_sizing: Option<AdaptiveChunkSizing>,
+        batch_size: usize,
     ) -> Self {
         let num_frames = encoder_outputs.z.shape()[2];
-        let mel_chunker = AdaptiveMelChunker::new(
+        let mut mel_chunker = AdaptiveMelChunker::new(
             num_frames as isize,
             chunk_size as isize,
             chunk_padding as isize,
         );
-        let one_shot = num_frames <= (chunk_size * 2 + (chunk_padding * 2));
+        // Decoding everything at once would defeat the latency target
+        let one_shot = match chunk_sizing {
+            Some(ref chunk_sizing) => {
+                mel_chunker.set_chunk_size(chunk_sizing.first_chunk_size());
+                false
+            }
+            None => num_frames <= (chunk_size * 2 + (chunk_padding * 2)),
+        };
+        // Chunks can only be extended into the audio of their padding
+        let max_overlap = chunk_padding * DECODER_HOP_LENGTH as usize * 2;
+        let overlap_len = chunk_overlap.samples.min(max_overlap) & !1;
         Self {
             decoder_model,
-            encoder_outputs,
+            encoder_outputs: Some(encoder_outputs),
             mel_chunker,
             one_shot,
+            overlap_window: chunk_overlap.window,
+            overlap_len,
+            pending_tail: Vec::new(),
+            chunk_sizing,
+            batch_size,
+            decoded_chunks: VecDeque::new(),
+            first_chunk_decoded: false,
+            control: StreamControl::new(),
+            fade_out_len: 0,
         }
     }
-    fn synthesize_chunk(
+    fn with_control(mut self, control: StreamControl, sample_rate: usize) -> Self {
+        self.control = control;
+        self.fade_out_len = sample_rate * STOP_FADE_OUT.as_millis() as usize / 1000;
+        self
+    }
+    /// Stop decoding, and drop the chunks and encoder outputs that are left. With
+    /// `fade_out`, returns the audio that follows what was returned so far, faded out
+    fn stop(&mut self, fade_out: bool) -> Option<AudioSamples> {
+        self.mel_chunker.consume();
+        self.encoder_outputs = None;
+        let mut audio = std::mem::take(&mut self.pending_tail);
+        let decoded_chunks = std::mem::take(&mut self.decoded_chunks);
+        if !fade_out {
+            return None;
+        }
+        for chunk in decoded_chunks {
+            let missing = self.fade_out_len.saturating_sub(audio.len());
+            audio.extend(chunk.into_iter().take(missing));
+        }
+        audio.truncate(self.fade_out_len);
+        if audio.is_empty() {
+            return None;
+        }
+        let mut audio = AudioSamples::from(audio);
+        audio.fade_out(self.fade_out_len);
+        Some(audio)
+    }
+    /// Decode `chunks` in a single run, and queue their audio
+    fn synthesize_chunks(
         &mut self,
-        mel_index: ndarray::Slice,
-        audio_index: ndarray::Slice,
-    ) -> SonataResult<AudioSamples> {
-        // println!("Mel index: {:?}\nAudio Index: {:?}", mel_index, audio_index);
-        let audio = {
-            let session = Arc::clone(&self.decoder_model);
-            let z_view = self.encoder_outputs.z.view();
-            let y_mask_view = self.encoder_outputs.y_mask.view();
-            let z_chunk = z_view.slice_axis(Axis(2), mel_index);
-            let y_mask_chunk = y_mask_view.slice_axis(Axis(2), mel_index);
-            let mut inputs = vec![
-                ort::SessionInputValue::from(
-                    Value::from_array(z_chunk).unwrap()
-                ),
-                ort::SessionInputValue::from(
-                    Value::from_array(y_mask_chunk).unwrap()
-                ),
-            ];
-            if !self.encoder_outputs.g.is_empty() {
-                inputs.push(ort::SessionInputValue::from(
-                    Value::from_array(self.encoder_outputs.g.view()).unwrap()
-                ));
-            }
-            let outputs = session
-                .run(SessionInputs::from(inputs.as_slice()))
-                .map_err(|e| {
-                    SonataError::OperationError(format!(
-                        "Failed to run model inference. Error: {}",
-                        e
-                    ))
-                })?;
-            let audio_t = outputs[0].try_extract_tensor::<f32>().map_err(|e| {
-                SonataError::OperationError(format!("Failed to run model inference. Error: {}", e))
-            })?;
-            self.process_chunk_audio(audio_t.view().view(), audio_index)?
+        chunks: Vec<(ndarray::Slice, ndarray::Slice)>,
+    ) -> SonataResult<()> {
+        let Some(encoder_outputs) = self.encoder_outputs.as_ref() else {
+            return Ok(());
         };
-        Ok(audio)
+        let z_view = encoder_outputs.z.view();
+        let y_mask_view = encoder_outputs.y_mask.view();
+        let z_chunks = Vec::from_iter(
+            chunks
+                .iter()
+                .map(|(mel_index, _)| z_view.slice_axis(Axis(2), *mel_index)),
+        );
+        let y_mask_chunks = Vec::from_iter(
+            chunks
+                .iter()
+                .map(|(mel_index, _)| y_mask_view.slice_axis(Axis(2), *mel_index)),
+        );
+        let mut inputs = vec![
+            SessionInput::Float32(stack_chunks(&z_chunks)),
+            SessionInput::Float32(stack_chunks(&y_mask_chunks)),
+        ];
+        if !encoder_outputs.g.is_empty() {
+            let g_view = encoder_outputs.g.view();
+            let g = match chunks.len() {
+                1 => g_view.into(),
+                num_chunks => ndarray::concatenate(Axis(0), &vec![g_view; num_chunks])
+                    .map_err(|e| {
+                        SonataError::with_message(format!("Invalid speaker input: {}", e))
+                    })?
+                    .into(),
+            };
+            inputs.push(SessionInput::Float32(g));
+        }
+        let chunk_frames = Vec::from_iter(z_chunks.iter().map(|z_chunk| z_chunk.shape()[2]));
+        let total_frames: usize = chunk_frames.iter().sum();
+        let timer = Instant::now();
+        let outputs = self.decoder_model.run(inputs)?;
+        let decode_time = timer.elapsed();
+        let output = outputs[0].view();
+        let chunks = chunk_frames.into_iter().zip(chunks).enumerate();
+        for (i, (num_frames, (_, audio_index))) in chunks {
+            // Drop the audio of the frames added to batch chunks of different sizes
+            let num_samples = (num_frames * DECODER_HOP_LENGTH as usize).min(output.shape()[2]);
+            let mut chunk_output = output.slice_axis(Axis(0), ndarray::Slice::from(i..i + 1));
+            chunk_output.slice_axis_inplace(Axis(2), ndarray::Slice::from(..num_samples));
+            let is_last = audio_index.end.is_none();
+            let audio = self.process_chunk_audio(chunk_output, audio_index)?;
+            let audio = self.stitch_chunk_audio(audio, is_last);
+            if let Some(ref mut chunk_sizing) = self.chunk_sizing {
+                let chunk_time = decode_time.mul_f64(num_frames as f64 / total_frames as f64);
+                chunk_sizing.record_chunk(num_frames, chunk_time, audio.len());
+                self.mel_chunker
+                    .set_chunk_size(chunk_sizing.next_chunk_size());
+            }
+            self.decoded_chunks.push_back(audio);
+        }
+        Ok(())
     }
     #[inline(always)]
     fn process_chunk_audio(
         &mut self,
         audio_view: ArrayView<f32, Dim<IxDynImpl>>,
         audio_index: ndarray::Slice,
-    ) -> SonataResult<AudioSamples> {
-        let mut audio: AudioSamples = audio_view
-            .slice_axis(Axis(2), audio_index)
+    ) -> SonataResult<Vec<f32>> {
+        // Keep half of the overlap from the padding on each side that joins another chunk
+        let half_overlap = (self.overlap_len / 2) as isize;
+        let start = match audio_index.start {
+            0 => 0,
+            start => start - half_overlap,
+        };
+        let end = audio_index
+            .end
+            .map(|end| end + half_overlap)
+            .filter(|end| *end < 0);
+        let audio = audio_view
+            .slice_axis(Axis(2), ndarray::Slice::new(start, end, 1))
             .as_slice()
             .ok_or_else(|| SonataError::with_message("Invalid model audio output"))?
-            .to_vec()
-            .into();
-        audio.crossfade(42);
+            .to_vec();
         Ok(audio)
     }
+    /// Mix the end of the previous chunk into the start of `audio`, and hold back the end
+    /// of `audio` for the next chunk
+    fn stitch_chunk_audio(&mut self, mut audio: Vec<f32>, is_last: bool) -> AudioSamples {
+        let tail = std::mem::take(&mut self.pending_tail);
+        self.overlap_window.overlap_add(&tail, &mut audio);
+        if !is_last {
+            let tail_start = audio.len().saturating_sub(self.overlap_len);
+            self.pending_tail = audio.split_off(tail_start);
+        }
+        audio.into()
+    }
 }
 
 impl Iterator for SpeechStreamer {
     type Item = SonataResult<AudioSamples>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (mel_index, audio_index) = self.mel_chunker.next()?;
+        if self.control.is_stopped() {
+            return self.stop(self.control.fades_out()).map(Ok);
+        }
+        if let Some(audio) = self.decoded_chunks.pop_front() {
+            return Some(Ok(audio));
+        }
+        let chunk = self.mel_chunker.next()?;
         if self.one_shot {
             self.mel_chunker.consume();
-            Some(
+            return Some(
                 self.encoder_outputs
+                    .as_ref()?
                     .infer_decoder(self.decoder_model.as_ref()),
-            )
-        } else {
-            Some(self.synthesize_chunk(mel_index, audio_index))
+            );
+        }
+        let mut chunks = vec![chunk];
+        // The first chunk is decoded alone, not to delay the start of the audio
+        if self.first_chunk_decoded {
+            chunks.extend(self.mel_chunker.by_ref().take(self.batch_size - 1));
         }
+        self.first_chunk_decoded = true;
+        if let Err(e) = self.synthesize_chunks(chunks) {
+            return Some(Err(e));
+        }
+        self.decoded_chunks.pop_front().map(Ok)
     }
 }
 
@@ -894,7 +2038,9 @@ struct AdaptiveMelChunker {
     chunk_size: usize,
     chunk_padding: isize,
     last_end_index: Option<isize>,
-    step: usize
+    step: usize,
+    /// Overrides the growing chunk size
+    fixed_chunk_size: Option<usize>,
 }
 
 impl AdaptiveMelChunker {
@@ -904,12 +2050,17 @@ impl AdaptiveMelChunker {
             chunk_size: chunk_size as usize,
             chunk_padding,
             last_end_index: Some(0),
-            step: 1
+            step: 1,
+            fixed_chunk_size: None,
         }
     }
     fn consume(&mut self) {
         self.last_end_index = None;
     }
+    /// Use `chunk_size` for the following chunks, instead of growing it
+    fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.fixed_chunk_size = Some(chunk_size);
+    }
 }
 
 impl Iterator for AdaptiveMelChunker {
@@ -917,7 +2068,10 @@ impl Iterator for AdaptiveMelChunker {
 
     fn next(&mut self) -> Option<Self::Item> {
         let last_index = self.last_end_index?;
-        let chunk_size = (self.chunk_size * self.step).min(MAX_CHUNK_SIZE);
+        let chunk_size = self
+            .fixed_chunk_size
+            .unwrap_or(self.chunk_size * self.step)
+            .min(MAX_CHUNK_SIZE);
         let (start_index, end_index): (isize, Option<isize>);
         let (start_padding, end_padding): (isize, Option<isize>);
         if last_index == 0 {
@@ -939,7 +2093,458 @@ impl Iterator for AdaptiveMelChunker {
         self.step += 1;
         self.last_end_index = end_index;
         let chunk_index = ndarray::Slice::new(start_index, end_index, 1);
-        let audio_index = ndarray::Slice::new(start_padding * 256, end_padding.map(|i| i * 256), 1);
+        let audio_index = ndarray::Slice::new(
+            start_padding * DECODER_HOP_LENGTH,
+            end_padding.map(|i| i * DECODER_HOP_LENGTH),
+            1,
+        );
         Some((chunk_index, audio_index))
     }
 }
+
+/// Stack the chunks of a decoder input along the batch axis, padding the shorter ones
+/// with zeros. A single chunk is used as is
+fn stack_chunks<'a>(chunks: &[ArrayView<'a, f32, Dim<IxDynImpl>>]) -> CowArray<'a, f32, IxDyn> {
+    if let [chunk] = chunks {
+        return chunk.clone().into();
+    }
+    let mut shape = chunks[0].shape().to_vec();
+    shape[0] = chunks.len();
+    shape[2] = chunks
+        .iter()
+        .map(|chunk| chunk.shape()[2])
+        .max()
+        .unwrap_or_default();
+    let mut stacked = Array::zeros(ndarray::IxDyn(&shape));
+    for (i, chunk) in chunks.iter().enumerate() {
+        stacked
+            .slice_axis_mut(Axis(0), ndarray::Slice::from(i..i + 1))
+            .slice_axis_mut(Axis(2), ndarray::Slice::from(..chunk.shape()[2]))
+            .assign(chunk);
+    }
+    stacked.into()
+}
+
+/// The frames of a phoneme and of the padding that follows it, changed by
+/// `duration_override`
+fn override_duration(
+    phoneme_frames: usize,
+    pad_frames: usize,
+    duration_override: DurationOverride,
+) -> SonataResult<(usize, usize)> {
+    match duration_override {
+        DurationOverride::Scale(scale) if scale.is_finite() && scale >= 0.0 => Ok((
+            (phoneme_frames as f32 * scale).round() as usize,
+            (pad_frames as f32 * scale).round() as usize,
+        )),
+        DurationOverride::Scale(scale) => Err(SonataError::OperationError(format!(
+            "Invalid duration scale `{}`",
+            scale
+        ))),
+        DurationOverride::Frames(frames) => {
+            let total_frames = phoneme_frames + pad_frames;
+            let new_phoneme_frames = match total_frames {
+                0 => frames,
+                _ => (frames * phoneme_frames + total_frames / 2) / total_frames,
+            };
+            Ok((new_phoneme_frames, frames - new_phoneme_frames))
+        }
+    }
+}
+
+/// Indices of the frames that stretch each segment of `durations` frames to the length
+/// given by `new_durations`, repeating or dropping frames evenly
+fn stretch_frames(durations: &[usize], new_durations: &[usize]) -> Vec<usize> {
+    let num_frames: usize = durations.iter().sum();
+    let mut frame_indices = Vec::with_capacity(new_durations.iter().sum());
+    let mut start = 0;
+    for (duration, new_duration) in durations.iter().zip(new_durations) {
+        for i in 0..*new_duration {
+            let index = start + (i * 2 + 1) * duration / (new_duration * 2);
+            frame_indices.push(index.min(num_frames.saturating_sub(1)));
+        }
+        start += duration;
+    }
+    frame_indices
+}
+
+/// Sizes the chunks of a stream so that each one is decoded before the audio of the
+/// previous ones has been played
+struct AdaptiveChunkSizing {
+    latency_target: Duration,
+    sample_rate: f64,
+    chunk_padding: usize,
+    /// Shared with the model, so that streams start from the last measured speed
+    decode_rate: Arc<RwLock<Option<f64>>>,
+    first_chunk_time: Option<Instant>,
+    /// Duration of the audio produced so far, in seconds
+    audio_duration: f64,
+}
+
+impl AdaptiveChunkSizing {
+    fn new(
+        latency_target: Duration,
+        sample_rate: f64,
+        chunk_padding: usize,
+        decode_rate: Arc<RwLock<Option<f64>>>,
+    ) -> Self {
+        Self {
+            latency_target,
+            sample_rate,
+            chunk_padding,
+            decode_rate,
+            first_chunk_time: None,
+            audio_duration: 0.0,
+        }
+    }
+    /// The chunk decodable within the latency target. The smallest chunk until the
+    /// decoding speed is known
+    fn first_chunk_size(&self) -> usize {
+        self.chunk_size_for(self.latency_target.as_secs_f64())
+    }
+    /// The chunk decodable while a share of the buffered audio is played
+    fn next_chunk_size(&self) -> usize {
+        let played = self
+            .first_chunk_time
+            .map_or(0.0, |time| time.elapsed().as_secs_f64());
+        let buffered = (self.audio_duration - played).max(0.0);
+        self.chunk_size_for(buffered * ADAPTIVE_DECODE_MARGIN)
+    }
+    fn chunk_size_for(&self, decode_time: f64) -> usize {
+        let decode_rate = self.decode_rate.read().unwrap().unwrap_or_default();
+        let num_frames = (decode_time * decode_rate) as usize;
+        // Padding frames are decoded too
+        num_frames
+            .saturating_sub(self.chunk_padding * 2)
+            .clamp(MIN_ADAPTIVE_CHUNK_SIZE, MAX_CHUNK_SIZE)
+    }
+    /// Record that `num_frames` were decoded into `num_samples` in `decode_time`
+    fn record_chunk(&mut self, num_frames: usize, decode_time: Duration, num_samples: usize) {
+        let chunk_rate = num_frames as f64 / decode_time.as_secs_f64().max(1e-6);
+        let mut decode_rate = self.decode_rate.write().unwrap();
+        // Smooth out the speed of single runs
+        *decode_rate = Some(decode_rate.map_or(chunk_rate, |rate| rate * 0.7 + chunk_rate * 0.3));
+        self.first_chunk_time.get_or_insert_with(Instant::now);
+        self.audio_duration += num_samples as f64 / self.sample_rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_phoneme_id_map() {
+        let mut config = ModelConfig {
+            num_symbols: 4,
+            phoneme_id_map: HashMap::from([
+                (PAD, vec![0]),
+                (BOS, vec![1]),
+                (EOS, vec![2]),
+                ('a', vec![3]),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(config.validate_phoneme_id_map(), Ok((0, 1, 2)));
+        config.phoneme_id_map.insert('b', vec![4]);
+        let error = config.validate_phoneme_id_map().unwrap_err();
+        assert!(error.contains("out of range"), "{}", error);
+        config.phoneme_id_map.remove(&'b');
+        config.phoneme_id_map.remove(&EOS);
+        let error = config.validate_phoneme_id_map().unwrap_err();
+        assert!(error.contains("end of sentence"), "{}", error);
+    }
+
+    #[test]
+    fn test_model_config_sources() {
+        let value = serde_json::json!({
+            "audio": {"sample_rate": 16000},
+            "espeak": {"voice": "en-us"},
+            "inference": {"noise_scale": 0.667, "length_scale": 1.0, "noise_w": 0.8},
+            "num_speakers": 1,
+            "speaker_id_map": {},
+            "num_symbols": 3,
+            "phoneme_map": {},
+            "phoneme_id_map": {"_": [0], "^": [1], "$": [2]},
+        });
+        let config = ModelConfig::from_value(value.clone()).unwrap();
+        assert_eq!(config.meta_ids, (0, 1, 2));
+        assert_eq!(config.synthesis_config().prosody_variation, None);
+        let mut varied = value.clone();
+        varied["inference"]["prosody_variation"] =
+            serde_json::json!({"noise_scale": 0.1, "length_scale": 0.05});
+        let config = ModelConfig::from_value(varied).unwrap();
+        assert_eq!(
+            config.synthesis_config().prosody_variation,
+            Some(ProsodyVariation::new(0.1, 0.05, 0))
+        );
+        let json = value.to_string();
+        assert!(json.parse::<ModelConfig>().is_ok());
+        assert!(ModelConfig::from_reader(json.as_bytes()).is_ok());
+        let Err(error) = "{}".parse::<ModelConfig>() else {
+            panic!("An empty config was accepted");
+        };
+        let error = error.to_string();
+        assert!(error.contains("Failed to parse model config"), "{}", error);
+        let mut value = value;
+        value["num_symbols"] = serde_json::json!(2);
+        let Err(error) = ModelConfig::from_value(value) else {
+            panic!("An out of range phoneme id was accepted");
+        };
+        let error = error.to_string();
+        assert!(error.contains("out of range"), "{}", error);
+    }
+
+    #[test]
+    fn test_load_replacement_config() {
+        let mut value = serde_json::json!({
+            "audio": {"sample_rate": 16000},
+            "espeak": {"voice": "en-us"},
+            "inference": {"noise_scale": 0.667, "length_scale": 1.0, "noise_w": 0.8},
+            "num_speakers": 1,
+            "speaker_id_map": {},
+            "num_symbols": 3,
+            "phoneme_map": {},
+            "phoneme_id_map": {"_": [0], "^": [1], "$": [2]},
+        });
+        let current = ModelConfig::from_value(value.clone()).unwrap();
+        let config_path =
+            std::env::temp_dir().join(format!("sonata-reload-{}.onnx.json", std::process::id()));
+        let reload = |value: &serde_json::Value| {
+            std::fs::write(&config_path, value.to_string()).unwrap();
+            load_replacement_config(&config_path, &current)
+        };
+        value["inference"]["length_scale"] = serde_json::json!(1.5);
+        value["model_path"] = serde_json::json!("v2.onnx");
+        let tuned = reload(&value);
+        value["audio"]["sample_rate"] = serde_json::json!(22050);
+        let resampled = reload(&value);
+        std::fs::remove_file(&config_path).unwrap();
+        let config = tuned.unwrap();
+        assert_eq!(config.inference.length_scale, 1.5);
+        assert_eq!(config.model_path.as_deref(), Some(Path::new("v2.onnx")));
+        let Err(error) = resampled else {
+            panic!("A new sample rate was reloaded");
+        };
+        let error = error.to_string();
+        assert!(error.contains("`audio.sample_rate`"), "{}", error);
+    }
+
+    #[test]
+    fn test_resolve_model_path() {
+        let config_path = Path::new("/voices/amy/amy.onnx.json");
+        let default = OsStr::new("amy.onnx");
+        assert_eq!(
+            resolve_model_path(config_path, None, default),
+            Path::new("/voices/amy/amy.onnx")
+        );
+        assert_eq!(
+            resolve_model_path(config_path, Some(Path::new("models/v2.onnx")), default),
+            Path::new("/voices/amy/models/v2.onnx")
+        );
+        assert_eq!(
+            resolve_model_path(config_path, Some(Path::new("/models/amy.onnx")), default),
+            Path::new("/models/amy.onnx")
+        );
+    }
+
+    #[test]
+    fn test_stack_chunks() {
+        let long = Array::from_elem(ndarray::IxDyn(&[1, 2, 3]), 1.0f32);
+        let short = Array::from_elem(ndarray::IxDyn(&[1, 2, 1]), 2.0f32);
+        let stacked = stack_chunks(&[long.view(), short.view()]);
+        assert_eq!(stacked.shape(), &[2, 2, 3]);
+        assert_eq!(
+            stacked.into_owned().into_raw_vec(),
+            vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 0.0, 0.0, 2.0, 0.0, 0.0]
+        );
+        assert!(stack_chunks(&[short.view()]).is_view());
+        assert_eq!(stack_chunks(&[short.view()]), short);
+    }
+
+    #[test]
+    fn test_adaptive_chunk_sizing() {
+        let decode_rate = Arc::new(RwLock::new(None));
+        let mut sizing = AdaptiveChunkSizing::new(
+            Duration::from_millis(100),
+            22050.0,
+            3,
+            Arc::clone(&decode_rate),
+        );
+        assert_eq!(sizing.first_chunk_size(), MIN_ADAPTIVE_CHUNK_SIZE);
+        // 1000 frames per second, and 2 seconds of audio buffered
+        sizing.record_chunk(100, Duration::from_millis(100), 44100);
+        assert_eq!(*decode_rate.read().unwrap(), Some(1000.0));
+        assert_eq!(sizing.first_chunk_size(), 94);
+        let next_chunk_size = sizing.next_chunk_size();
+        assert!(
+            (900..=994).contains(&next_chunk_size),
+            "{}",
+            next_chunk_size
+        );
+    }
+
+    #[test]
+    fn test_duration_overrides() {
+        assert_eq!(
+            override_duration(4, 2, DurationOverride::Scale(1.5)).unwrap(),
+            (6, 3)
+        );
+        assert_eq!(
+            override_duration(4, 2, DurationOverride::Frames(9)).unwrap(),
+            (6, 3)
+        );
+        assert_eq!(
+            override_duration(0, 0, DurationOverride::Frames(3)).unwrap(),
+            (3, 0)
+        );
+        assert!(override_duration(4, 2, DurationOverride::Scale(-1.0)).is_err());
+        assert_eq!(stretch_frames(&[2, 3], &[4, 1]), vec![0, 0, 1, 1, 3]);
+        assert_eq!(stretch_frames(&[2, 0], &[2, 1]), vec![0, 1, 1]);
+        assert_eq!(stretch_frames(&[2, 3], &[2, 3]), vec![0, 1, 2, 3, 4]);
+    }
+
+    /// A session that returns a sample per phoneme id and unit of `length_scale`, whose
+    /// value is the speaker id, and fails on sentences with a `?`
+    struct FakeSession {
+        num_speakers: u32,
+        failing_id: i64,
+    }
+
+    impl InferenceSession for FakeSession {
+        fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+            let (SessionInput::Int64(ids), SessionInput::Float32(scales)) =
+                (&inputs[0], &inputs[2])
+            else {
+                unreachable!()
+            };
+            if ids.iter().any(|id| *id == self.failing_id) {
+                return Err(SonataError::with_message("Failing sentence"));
+            }
+            let speaker = match inputs.get(3) {
+                Some(SessionInput::Int64(sid)) => sid[0] as f32,
+                _ => 0.0,
+            };
+            let len = (ids.len() as f32 * scales[1]).round() as usize;
+            let audio = ndarray::ArrayD::from_elem(ndarray::IxDyn(&[1, 1, len]), speaker);
+            Ok(SessionOutputs::new(vec![("output".to_string(), audio)]))
+        }
+        fn io_info(&self) -> ModelIo {
+            let info = |name: &str, dims: &[Option<i64>], dtype: &str| ModelIoInfo {
+                name: name.to_string(),
+                dims: dims.to_vec(),
+                dtype: dtype.to_string(),
+            };
+            let mut inputs = vec![
+                info("input", &[Some(1), None], "int64"),
+                info("input_lengths", &[Some(1)], "int64"),
+                info("scales", &[Some(3)], "float32"),
+            ];
+            if self.num_speakers > 1 {
+                inputs.push(info("sid", &[Some(1)], "int64"));
+            }
+            ModelIo {
+                inputs,
+                outputs: vec![info("output", &[Some(1), Some(1), None], "float32")],
+            }
+        }
+    }
+
+    fn fake_voice(num_speakers: u32) -> VitsModel {
+        let tiny_voice = sonata_test_utils::TinyVoice::new().with_speakers(num_speakers);
+        let failing_id = tiny_voice
+            .phoneme_id_map()
+            .into_iter()
+            .find_map(|(phoneme, id)| (phoneme == '?').then_some(id))
+            .unwrap();
+        let config: ModelConfig = tiny_voice.config_json().parse().unwrap();
+        let synth_config = config.synthesis_config();
+        let session = Box::new(FakeSession {
+            num_speakers,
+            failing_id,
+        });
+        VitsModel::from_session(config, synth_config, session, Path::new("fake.onnx")).unwrap()
+    }
+
+    #[test]
+    fn test_speak_batch_per_item() {
+        let voice = fake_voice(1);
+        let results = voice.speak_batch_per_item(vec!["ab".into(), "a?".into(), "abc".into()]);
+        // BOS, the phonemes each followed by padding, and EOS
+        assert_eq!(results[0].as_ref().unwrap().samples.len(), 6);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().samples.len(), 8);
+    }
+
+    #[test]
+    fn test_speak_batch_as_speaker() {
+        let voice = fake_voice(3);
+        let sentences = vec!["ab".to_string(), "c".to_string()];
+        let audio = voice.speak_batch_as_speaker(sentences.clone(), 2).unwrap();
+        assert_eq!(audio.len(), 2);
+        assert!(audio.iter().all(|audio| audio
+            .samples
+            .as_slice()
+            .iter()
+            .all(|sample| *sample == 2.0)));
+        // The current speaker is left as is
+        assert_eq!(voice.synth_config.read().unwrap().speaker, None);
+        assert!(voice.speak_batch_as_speaker(sentences.clone(), 3).is_err());
+        assert!(fake_voice(1).speak_batch_as_speaker(sentences, 1).is_err());
+    }
+
+    #[test]
+    fn test_native_rate() {
+        let voice = fake_voice(1);
+        assert_eq!(voice.native_rate(1.2).unwrap(), 1.2);
+        // Clamped to the default rate range of the voice
+        assert_eq!(voice.native_rate(3.0).unwrap(), 1.5);
+        assert_eq!(voice.native_rate(0.5).unwrap(), 0.75);
+        assert!(voice.native_rate(0.0).is_err());
+        assert!(voice.native_rate(f32::NAN).is_err());
+        let len = |voice: &VitsModel| voice.speak_one_sentence("abc".into()).unwrap().len();
+        assert_eq!(len(&voice), 8);
+        assert_eq!(sonata_core::with_native_rate(1.5, || len(&voice)), 5);
+        assert_eq!(sonata_core::with_native_rate(0.8, || len(&voice)), 10);
+        // The rate is the call's, the voice's config is left as is
+        assert_eq!(voice.synth_config.read().unwrap().length_scale, 1.0);
+        assert_eq!(len(&voice), 8);
+    }
+
+    #[test]
+    fn test_swap_inference() {
+        let voice = fake_voice(1);
+        let mut synth_config = voice.synth_config.read().unwrap().clone();
+        synth_config.noise_scale = 0.3;
+        voice.set_fallback_synthesis_config(&synth_config).unwrap();
+        voice.swap_inference(InferenceConfig {
+            noise_scale: 0.5,
+            length_scale: 1.25,
+            noise_w: 0.6,
+            ..Default::default()
+        });
+        let synth_config = voice.synth_config.read().unwrap().clone();
+        // The scale set by the application is kept, the others are the new defaults
+        assert_eq!(synth_config.noise_scale, 0.3);
+        assert_eq!(synth_config.length_scale, 1.25);
+        assert_eq!(synth_config.noise_w, 0.6);
+        assert_eq!(voice.inference.read().unwrap().noise_scale, 0.5);
+    }
+
+    #[cfg(feature = "ort")]
+    #[test]
+    fn test_session_placement() {
+        let gpu = vec![ExecutionProvider::Cuda { device_id: 1 }];
+        let placed = with_execution_providers(gpu.clone(), || fake_voice(1));
+        let unplaced = fake_voice(1);
+        let placement = onnxruntime::current_placement;
+        assert_eq!(placed.placement.scope(placement), Some(gpu.clone()));
+        // The placement of the voice wins over that of the thread
+        let cpu = vec![ExecutionProvider::Cpu];
+        with_execution_providers(cpu.clone(), || {
+            assert_eq!(unplaced.placement.scope(placement), None);
+            assert_eq!(placed.placement.scope(placement), Some(gpu));
+            assert_eq!(placement(), Some(cpu));
+        });
+    }
+}
@@ -0,0 +1,79 @@
+//! A process-wide hook to decrypt model files before they are loaded.
+//!
+//! Vendors of protected voices ship encrypted models and register a
+//! [`ModelDecryptor`] in the host application. Once one is set, every model (including
+//! the models of voice bundles) is read in memory and passed through it before the
+//! inference session is created. Decrypted bytes are never written to disk.
+
+use once_cell::sync::Lazy;
+use sonata_core::{SonataError, SonataResult};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+pub trait ModelDecryptor: Send + Sync {
+    /// Turn the bytes of the model file at `model_path` into a loadable model
+    fn decrypt(&self, model_path: &Path, model_bytes: Vec<u8>) -> SonataResult<Vec<u8>>;
+}
+
+impl<F> ModelDecryptor for F
+where
+    F: Fn(&Path, Vec<u8>) -> SonataResult<Vec<u8>> + Send + Sync,
+{
+    fn decrypt(&self, model_path: &Path, model_bytes: Vec<u8>) -> SonataResult<Vec<u8>> {
+        self(model_path, model_bytes)
+    }
+}
+
+static MODEL_DECRYPTOR: Lazy<RwLock<Option<Arc<dyn ModelDecryptor>>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/// Pass the models loaded from now on through `decryptor`
+pub fn set_model_decryptor(decryptor: Arc<dyn ModelDecryptor>) {
+    *MODEL_DECRYPTOR.write().unwrap() = Some(decryptor);
+}
+
+/// Load models as they are stored
+pub fn clear_model_decryptor() {
+    *MODEL_DECRYPTOR.write().unwrap() = None;
+}
+
+pub(crate) fn model_decryptor() -> Option<Arc<dyn ModelDecryptor>> {
+    MODEL_DECRYPTOR.read().unwrap().clone()
+}
+
+/// Decrypt `model_bytes` with `decryptor`, naming the model in errors
+pub(crate) fn decrypt_model(
+    decryptor: &dyn ModelDecryptor,
+    model_path: &Path,
+    model_bytes: Vec<u8>,
+) -> SonataResult<Vec<u8>> {
+    decryptor.decrypt(model_path, model_bytes).map_err(|e| {
+        SonataError::FailedToLoadResource(format!(
+            "Failed to decrypt model `{}`",
+            model_path.display()
+        ))
+        .caused_by(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrypt_model() {
+        let xor = |_: &Path, bytes: Vec<u8>| -> SonataResult<Vec<u8>> {
+            Ok(Vec::from_iter(bytes.into_iter().map(|byte| byte ^ 0x5a)))
+        };
+        let model_path = Path::new("amy.onnx");
+        assert_eq!(
+            decrypt_model(&xor, model_path, vec![0x5a, 0x5b]).unwrap(),
+            vec![0, 1]
+        );
+        let wrong_key = |_: &Path, _: Vec<u8>| -> SonataResult<Vec<u8>> {
+            Err(SonataError::with_message("bad key"))
+        };
+        let error = decrypt_model(&wrong_key, model_path, Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("`amy.onnx`"), "{}", error);
+    }
+}
@@ -0,0 +1,423 @@
+//! Setting up the onnxruntime environment and sessions.
+//!
+//! The environment is set up once per process, by [`init`] or, failing that, with the
+//! default [`InitOptions`] when the first voice is loaded.
+//!
+//! With the `ort-dylib` feature, onnxruntime is not linked in but loaded from a shared
+//! library at runtime. The library is taken from the path given by the application, then
+//! from `ORT_DYLIB_PATH`, then from the directory of the executable, so that packaged
+//! applications can ship their own onnxruntime. Otherwise the platform's library search
+//! path is used.
+//!
+//! Voices loaded inside [`with_execution_providers`] run on the given providers instead
+//! of those of the environment, e.g. to pin voices to different GPUs.
+
+use ort::{
+    CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+    ExecutionProviderDispatch, NNAPIExecutionProvider, Session, SessionBuilder,
+};
+use sonata_core::{SonataError, SonataResult};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub const ORT_DYLIB_PATH_ENV_VAR: &str = "ORT_DYLIB_PATH";
+
+/// File name of the onnxruntime shared library on this platform
+#[cfg(target_os = "windows")]
+pub const ORT_DYLIB_NAME: &str = "onnxruntime.dll";
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub const ORT_DYLIB_NAME: &str = "libonnxruntime.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "ios")))]
+pub const ORT_DYLIB_NAME: &str = "libonnxruntime.so";
+
+/// The execution providers of the environment, once it is set up
+static EXECUTION_PROVIDERS: Mutex<Option<Vec<ExecutionProvider>>> = Mutex::new(None);
+
+thread_local! {
+    /// The execution providers of the sessions created on this thread, if not those of
+    /// the environment
+    static PLACEMENT: RefCell<Option<Vec<ExecutionProvider>>> = const { RefCell::new(None) };
+}
+
+/// Hardware to run the models on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionProvider {
+    Cpu,
+    /// Needs onnxruntime built with CUDA, and the `cuda` feature of `ort`
+    Cuda {
+        device_id: i32,
+    },
+    CoreMl,
+    Nnapi,
+}
+
+impl ExecutionProvider {
+    fn dispatch(self) -> ExecutionProviderDispatch {
+        match self {
+            Self::Cpu => CPUExecutionProvider::default().build(),
+            Self::Cuda { device_id } => CUDAExecutionProvider::default()
+                .with_device_id(device_id)
+                .build(),
+            Self::CoreMl => CoreMLExecutionProvider::default().build(),
+            Self::Nnapi => NNAPIExecutionProvider::default().build(),
+        }
+    }
+}
+
+impl FromStr for ExecutionProvider {
+    type Err = SonataError;
+
+    /// `cpu`, `cuda` (the first GPU), `cuda:<device id>`, `coreml` or `nnapi`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let provider = match s.split_once(':') {
+            None if s == "cpu" => Self::Cpu,
+            None if s == "cuda" => Self::Cuda { device_id: 0 },
+            None if s == "coreml" => Self::CoreMl,
+            None if s == "nnapi" => Self::Nnapi,
+            Some(("cuda", device_id)) => match device_id.parse() {
+                Ok(device_id) if device_id >= 0 => Self::Cuda { device_id },
+                _ => {
+                    return Err(SonataError::OperationError(format!(
+                        "Invalid CUDA device id `{}`",
+                        device_id
+                    )))
+                }
+            },
+            _ => {
+                return Err(SonataError::OperationError(format!(
+                    "Unknown execution provider `{}`. Expected one of `cpu`, `cuda`, `cuda:<device id>`, `coreml` or `nnapi`",
+                    s
+                )))
+            }
+        };
+        Ok(provider)
+    }
+}
+
+/// Least severe onnxruntime messages that are logged
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Verbose,
+    Info,
+    #[default]
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    fn severity(self) -> ort::sys::OrtLoggingLevel {
+        use ort::sys::OrtLoggingLevel::*;
+        match self {
+            Self::Verbose => ORT_LOGGING_LEVEL_VERBOSE,
+            Self::Info => ORT_LOGGING_LEVEL_INFO,
+            Self::Warning => ORT_LOGGING_LEVEL_WARNING,
+            Self::Error => ORT_LOGGING_LEVEL_ERROR,
+            Self::Fatal => ORT_LOGGING_LEVEL_FATAL,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = SonataError;
+
+    /// `verbose`, `info`, `warning` (or `warn`), `error` or `fatal`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = match s.trim().to_ascii_lowercase().as_str() {
+            "verbose" => Self::Verbose,
+            "info" => Self::Info,
+            "warning" | "warn" => Self::Warning,
+            "error" => Self::Error,
+            "fatal" => Self::Fatal,
+            _ => {
+                return Err(SonataError::OperationError(format!(
+                    "Unknown log level `{}`. Expected one of `verbose`, `info`, `warning`, `error` or `fatal`",
+                    s
+                )))
+            }
+        };
+        Ok(level)
+    }
+}
+
+/// How to set up onnxruntime, see [`init`]
+#[derive(Clone, Debug)]
+pub struct InitOptions {
+    /// The onnxruntime library, or the directory holding it. Requires the `ort-dylib`
+    /// feature
+    pub dylib_path: Option<PathBuf>,
+    /// Execution providers in order of preference. Those that are not available are
+    /// skipped, so the CPU should come last
+    pub execution_providers: Vec<ExecutionProvider>,
+    /// Whether onnxruntime may send telemetry, which only its Windows builds do
+    pub telemetry: bool,
+    /// Least severe onnxruntime messages that are logged. `ort` passes them on to
+    /// `tracing`
+    pub log_level: LogLevel,
+}
+
+impl Default for InitOptions {
+    /// The platform's accelerator if any, then CUDA, then the CPU, without telemetry,
+    /// logging warnings and errors
+    fn default() -> Self {
+        let execution_providers = vec![
+            #[cfg(target_os = "android")]
+            ExecutionProvider::Nnapi,
+            #[cfg(target_os = "ios")]
+            ExecutionProvider::CoreMl,
+            ExecutionProvider::Cuda { device_id: 0 },
+            ExecutionProvider::Cpu,
+        ];
+        Self {
+            dylib_path: None,
+            execution_providers,
+            telemetry: false,
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+/// Set up onnxruntime for the process. Call this before loading any voice, as voices
+/// set it up with the default options otherwise. Fails if it is already set up
+pub fn init(options: InitOptions) -> SonataResult<()> {
+    let mut execution_providers = EXECUTION_PROVIDERS.lock().unwrap();
+    if execution_providers.is_some() {
+        return Err(SonataError::OperationError(
+            "onnxruntime is already initialized".to_string(),
+        ));
+    }
+    commit_environment(&options)?;
+    *execution_providers = Some(options.execution_providers);
+    Ok(())
+}
+
+/// Whether onnxruntime was set up, by [`init`] or by loading a voice
+pub fn is_initialized() -> bool {
+    EXECUTION_PROVIDERS.lock().unwrap().is_some()
+}
+
+/// Forget the environment, so that it is set up again, by [`init`] or with the default
+/// options, before the next session is created. See [`crate::shutdown`]
+pub(crate) fn release_environment() {
+    *EXECUTION_PROVIDERS.lock().unwrap() = None;
+}
+
+/// Run `load` with the sessions it creates on this thread, and so the voices it loads,
+/// placed on `execution_providers` instead of the providers of the environment.
+///
+/// Unavailable providers are skipped as in [`InitOptions::execution_providers`], so
+/// listing the CPU last keeps a voice loadable on a machine without the GPU.
+pub fn with_execution_providers<T>(
+    execution_providers: Vec<ExecutionProvider>,
+    load: impl FnOnce() -> T,
+) -> T {
+    with_placement(Some(execution_providers), load)
+}
+
+/// The execution providers given to [`with_execution_providers`] on this thread, if any
+pub(crate) fn current_placement() -> Option<Vec<ExecutionProvider>> {
+    PLACEMENT.with(|placement| placement.borrow().clone())
+}
+
+/// Run `load` with the sessions it creates on this thread placed on `execution_providers`,
+/// or on the providers of the environment if `None`
+pub(crate) fn with_placement<T>(
+    execution_providers: Option<Vec<ExecutionProvider>>,
+    load: impl FnOnce() -> T,
+) -> T {
+    let previous = PLACEMENT.with(|placement| placement.replace(execution_providers));
+    // Restores the previous placement even if `load` panics
+    struct Restore(Option<Vec<ExecutionProvider>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            PLACEMENT.with(|placement| *placement.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(previous);
+    load()
+}
+
+/// A session builder with the execution providers of the environment, or those given
+/// to [`with_execution_providers`], setting the environment up with the default
+/// options if needed
+pub fn session_builder() -> SonataResult<SessionBuilder> {
+    let execution_providers = {
+        let mut execution_providers = EXECUTION_PROVIDERS.lock().unwrap();
+        if execution_providers.is_none() {
+            let options = InitOptions::default();
+            commit_environment(&options)?;
+            *execution_providers = Some(options.execution_providers);
+        }
+        let placement = PLACEMENT.with(|placement| placement.borrow().clone());
+        Vec::from_iter(
+            placement
+                .as_ref()
+                .or(execution_providers.as_ref())
+                .into_iter()
+                .flatten()
+                .map(|provider| provider.dispatch()),
+        )
+    };
+    Session::builder()
+        .and_then(|builder| builder.with_execution_providers(execution_providers))
+        .map_err(|e| {
+            SonataError::OperationError("Failed to create onnxruntime session".to_string())
+                .caused_by(e)
+        })
+}
+
+fn commit_environment(options: &InitOptions) -> SonataResult<()> {
+    let execution_providers = Vec::from_iter(
+        options
+            .execution_providers
+            .iter()
+            .map(|provider| provider.dispatch()),
+    );
+    ort_environment(options.dylib_path.as_deref())?
+        .with_name("sonata")
+        .with_telemetry(options.telemetry)
+        .with_execution_providers(execution_providers)
+        .commit()
+        .map_err(|e| {
+            SonataError::FailedToLoadResource("Failed to initialize onnxruntime".to_string())
+                .caused_by(e)
+        })?;
+    set_log_level(options.log_level)
+}
+
+/// Change the log level of the committed environment, which `ort` creates logging
+/// everything
+fn set_log_level(log_level: LogLevel) -> SonataResult<()> {
+    let environment = ort::get_environment().map_err(|e| {
+        SonataError::FailedToLoadResource("Failed to initialize onnxruntime".to_string())
+            .caused_by(e)
+    })?;
+    unsafe {
+        let api = ort::api();
+        let api = api.as_ref();
+        let update = api
+            .UpdateEnvWithCustomLogLevel
+            .expect("onnxruntime has no `UpdateEnvWithCustomLogLevel`");
+        let status = update(environment.ptr(), log_level.severity());
+        if !status.is_null() {
+            let message = std::ffi::CStr::from_ptr(api.GetErrorMessage.unwrap()(status))
+                .to_string_lossy()
+                .into_owned();
+            api.ReleaseStatus.unwrap()(status);
+            return Err(SonataError::OperationError(format!(
+                "Failed to set the onnxruntime log level: {}",
+                message
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Start configuring the onnxruntime environment, loading onnxruntime from `dylib_path`
+/// if given
+fn ort_environment(dylib_path: Option<&Path>) -> SonataResult<ort::EnvironmentBuilder> {
+    #[cfg(feature = "ort-dylib")]
+    if let Some(path) = resolve_dylib_path(dylib_path)? {
+        return Ok(ort::init_from(path.to_string_lossy()));
+    }
+    #[cfg(not(feature = "ort-dylib"))]
+    if let Some(path) = dylib_path {
+        return Err(SonataError::FailedToLoadResource(format!(
+            "Can not load onnxruntime from `{}` without the `ort-dylib` feature",
+            path.display()
+        )));
+    }
+    Ok(ort::init())
+}
+
+/// The onnxruntime library to load, `None` to leave it to `ORT_DYLIB_PATH` or the
+/// library search path
+#[cfg(feature = "ort-dylib")]
+fn resolve_dylib_path(dylib_path: Option<&Path>) -> SonataResult<Option<PathBuf>> {
+    if let Some(path) = dylib_path {
+        let path = if path.is_dir() {
+            path.join(ORT_DYLIB_NAME)
+        } else {
+            path.to_path_buf()
+        };
+        if !path.is_file() {
+            return Err(SonataError::FailedToLoadResource(format!(
+                "onnxruntime library `{}` was not found",
+                path.display()
+            )));
+        }
+        return Ok(Some(path));
+    }
+    if std::env::var_os(ORT_DYLIB_PATH_ENV_VAR).is_some() {
+        return Ok(None);
+    }
+    Ok(std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(ORT_DYLIB_NAME)))
+        .filter(|path| path.is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_execution_provider() {
+        assert_eq!(
+            "cpu".parse::<ExecutionProvider>().unwrap(),
+            ExecutionProvider::Cpu
+        );
+        assert_eq!(
+            "CUDA".parse::<ExecutionProvider>().unwrap(),
+            ExecutionProvider::Cuda { device_id: 0 }
+        );
+        assert_eq!(
+            "cuda:1".parse::<ExecutionProvider>().unwrap(),
+            ExecutionProvider::Cuda { device_id: 1 }
+        );
+        assert_eq!(
+            "coreml".parse::<ExecutionProvider>().unwrap(),
+            ExecutionProvider::CoreMl
+        );
+        for invalid in ["gpu", "cuda:", "cuda:-1", "cpu:0"] {
+            assert!(invalid.parse::<ExecutionProvider>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!("Verbose".parse::<LogLevel>().unwrap(), LogLevel::Verbose);
+        assert_eq!("warn".parse::<LogLevel>().unwrap(), LogLevel::Warning);
+        assert_eq!(LogLevel::default(), LogLevel::Warning);
+        assert!("debug".parse::<LogLevel>().is_err());
+    }
+
+    #[test]
+    fn test_with_execution_providers() {
+        let placement = || PLACEMENT.with(|placement| placement.borrow().clone());
+        let inner = with_execution_providers(vec![ExecutionProvider::Cpu], || {
+            with_execution_providers(vec![ExecutionProvider::Cuda { device_id: 1 }], placement)
+        });
+        assert_eq!(inner, Some(vec![ExecutionProvider::Cuda { device_id: 1 }]));
+        assert_eq!(placement(), None);
+    }
+
+    #[cfg(feature = "ort-dylib")]
+    #[test]
+    fn test_resolve_dylib_path() {
+        let dir = std::env::temp_dir().join(format!("sonata-ort-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = dir.join(ORT_DYLIB_NAME);
+        std::fs::write(&library, b"").unwrap();
+        let from_dir = resolve_dylib_path(Some(&dir)).unwrap();
+        let from_file = resolve_dylib_path(Some(&library)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(from_dir, Some(library.clone()));
+        assert_eq!(from_file, Some(library.clone()));
+        let error = resolve_dylib_path(Some(&library)).unwrap_err().to_string();
+        assert!(error.contains("was not found"), "{}", error);
+    }
+}
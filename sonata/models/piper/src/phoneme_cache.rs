@@ -0,0 +1,183 @@
+//! A process-wide LRU cache of phonemization results, keyed by (text, espeak voice,
+//! whether the text was diacritized by tashkeel).
+//!
+//! Phonemizing short strings that are spoken repeatedly (menus, notifications...)
+//! takes a noticeable share of the total latency, so results are cached in memory.
+//! The cache is shared by all voices, since it is keyed by the espeak voice.
+//! Entries keep the warnings of the phonemization that produced them, but not its
+//! timings, since a hit takes none of that time.
+
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use sonata_core::{Phonemes, StageTimings};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Number of entries kept by default
+pub const DEFAULT_PHONEME_CACHE_CAPACITY: usize = 512;
+
+pub(crate) static PHONEME_CACHE: Lazy<PhonemeCache> =
+    Lazy::new(|| PhonemeCache::new(DEFAULT_PHONEME_CACHE_CAPACITY));
+
+/// Hit/miss statistics of the phoneme cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhonemeCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl PhonemeCacheStats {
+    /// Fraction of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+type CacheKey = (String, String, bool);
+
+pub(crate) struct PhonemeCache {
+    /// `None` when caching is disabled (capacity of zero)
+    entries: Mutex<Option<LruCache<CacheKey, Phonemes>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl PhonemeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(NonZeroUsize::new(capacity).map(LruCache::new)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+    pub(crate) fn get(&self, text: &str, espeak_voice: &str, tashkeel: bool) -> Option<Phonemes> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = entries.as_mut()?;
+        let key = (text.to_string(), espeak_voice.to_string(), tashkeel);
+        match entries.get(&key) {
+            Some(phonemes) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(phonemes.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    pub(crate) fn insert(
+        &self,
+        text: &str,
+        espeak_voice: &str,
+        tashkeel: bool,
+        phonemes: Phonemes,
+    ) {
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            entries.put(
+                (text.to_string(), espeak_voice.to_string(), tashkeel),
+                phonemes.with_timings(StageTimings::default()),
+            );
+        }
+    }
+    fn set_capacity(&self, capacity: usize) {
+        let mut entries = self.entries.lock().unwrap();
+        match (NonZeroUsize::new(capacity), entries.as_mut()) {
+            (Some(capacity), Some(cache)) => cache.resize(capacity),
+            (capacity, _) => *entries = capacity.map(LruCache::new),
+        }
+    }
+    fn clear(&self) {
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            entries.clear();
+        }
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+    fn stats(&self) -> PhonemeCacheStats {
+        let entries = self.entries.lock().unwrap();
+        PhonemeCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: entries.as_ref().map(LruCache::len).unwrap_or(0),
+            capacity: entries.as_ref().map(|cache| cache.cap().get()).unwrap_or(0),
+        }
+    }
+}
+
+/// Set the maximum number of cached phonemization results. Zero disables the cache.
+///
+/// Shrinking the cache evicts the least recently used entries.
+pub fn set_phoneme_cache_capacity(capacity: usize) {
+    PHONEME_CACHE.set_capacity(capacity)
+}
+
+/// Remove all cached phonemization results, and reset the statistics
+pub fn clear_phoneme_cache() {
+    PHONEME_CACHE.clear()
+}
+
+pub fn phoneme_cache_stats() -> PhonemeCacheStats {
+    PHONEME_CACHE.stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phonemes(sentence: &str) -> Phonemes {
+        Phonemes::from(vec![sentence.to_string()])
+    }
+
+    #[test]
+    fn test_phoneme_cache() {
+        let cache = PhonemeCache::new(2);
+        assert_eq!(cache.get("hello", "en-us", false), None);
+        cache.insert("hello", "en-us", false, phonemes("həlˈoʊ."));
+        cache.insert("hello", "de", false, phonemes("hɛlˈoː."));
+        assert_eq!(
+            cache.get("hello", "en-us", false),
+            Some(phonemes("həlˈoʊ."))
+        );
+        // `("hello", "de")` is the least recently used entry
+        cache.insert("bye", "en-us", false, phonemes("bˈaɪ."));
+        assert_eq!(cache.get("hello", "de", false), None);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (1, 2, 2));
+
+        cache.set_capacity(0);
+        cache.insert("hello", "en-us", false, phonemes("həlˈoʊ."));
+        assert_eq!(cache.get("hello", "en-us", false), None);
+        assert_eq!(cache.stats().capacity, 0);
+    }
+
+    #[test]
+    fn test_cached_warnings() {
+        let cache = PhonemeCache::new(2);
+        let timings = StageTimings {
+            tashkeel_ms: 3.0,
+            ..Default::default()
+        };
+        let warnings = vec!["Full dictionary is not installed for 'xx'".to_string()];
+        cache.insert(
+            "hello",
+            "xx",
+            false,
+            phonemes("hello")
+                .with_warnings(warnings.clone())
+                .with_timings(timings),
+        );
+        let cached = cache.get("hello", "xx", false).unwrap();
+        assert_eq!(cached.warnings(), warnings);
+        // A hit takes none of the time of the phonemization that produced it
+        assert_eq!(cached.timings(), StageTimings::default());
+        // Diacritized text is cached apart from the same text phonemized as is
+        assert_eq!(cache.get("hello", "xx", true), None);
+    }
+}
@@ -0,0 +1,122 @@
+//! Varying the delivery of a voice from one sentence to the next.
+//!
+//! Spoken with the same scales throughout, hours of narration sound monotonous. A
+//! [`ProsodyVariation`] changes `noise_scale` and `length_scale` by a small random amount
+//! for each sentence, within bounds set per voice in the `inference` section of its
+//! config:
+//!
+//! ```json
+//! "inference": {
+//!     "noise_scale": 0.667,
+//!     "length_scale": 1.0,
+//!     "noise_w": 0.8,
+//!     "prosody_variation": {"noise_scale": 0.1, "length_scale": 0.05, "seed": 7}
+//! }
+//! ```
+//!
+//! or at runtime, in the voice's [`crate::PiperSynthesisConfig`]. The changes are drawn
+//! from the seed and the phonemes of the sentence, so a text is spoken the same way every
+//! time, whatever the order its sentences are synthesized in.
+
+use serde::Deserialize;
+
+/// The largest change of a scale, as a fraction of it. Larger bounds are capped
+pub const MAX_PROSODY_VARIATION: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub struct ProsodyVariation {
+    /// The largest change of `noise_scale`, as a fraction of it, e.g. `0.1` for up to 10%
+    /// more or less
+    #[serde(default)]
+    pub noise_scale: f32,
+    /// The largest change of `length_scale`, as a fraction of it
+    #[serde(default)]
+    pub length_scale: f32,
+    /// Another seed draws other changes for the same sentences
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl ProsodyVariation {
+    pub fn new(noise_scale: f32, length_scale: f32, seed: u64) -> Self {
+        Self {
+            noise_scale,
+            length_scale,
+            seed,
+        }
+    }
+    /// `noise_scale` and `length_scale` varied for the sentence of `input_ids`
+    pub(crate) fn vary(
+        &self,
+        noise_scale: f32,
+        length_scale: f32,
+        input_ids: &[i64],
+    ) -> (f32, f32) {
+        let hash = input_ids
+            .iter()
+            .fold(mix(self.seed), |hash, id| mix(hash ^ *id as u64));
+        let noise_change = uniform(mix(hash ^ 1)) * bound(self.noise_scale);
+        let length_change = uniform(mix(hash ^ 2)) * bound(self.length_scale);
+        (
+            noise_scale * (1.0 + noise_change),
+            length_scale * (1.0 + length_change),
+        )
+    }
+}
+
+/// The finalizer of splitmix64, which is stable across platforms and releases
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9e3779b97f4a7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+/// A number between `-1.0` and `1.0` drawn from `hash`
+fn uniform(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+fn bound(fraction: f32) -> f32 {
+    if fraction.is_finite() {
+        fraction.clamp(0.0, MAX_PROSODY_VARIATION)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vary() {
+        let variation = ProsodyVariation::new(0.1, 0.05, 7);
+        let sentences = Vec::from_iter((0..200).map(|index| vec![1, index, 0, index * 3, 2]));
+        let varied = Vec::from_iter(
+            sentences
+                .iter()
+                .map(|input_ids| variation.vary(0.667, 1.0, input_ids)),
+        );
+        for (noise_scale, length_scale) in varied.iter() {
+            assert!((0.6003..=0.7337).contains(noise_scale), "{}", noise_scale);
+            assert!((0.95..=1.05).contains(length_scale), "{}", length_scale);
+        }
+        // Spread over the range, not stuck at one end
+        let slower = varied.iter().filter(|(_, length)| *length > 1.025).count();
+        let faster = varied.iter().filter(|(_, length)| *length < 0.975).count();
+        assert!(slower > 20 && faster > 20, "{} {}", slower, faster);
+        // Reproducible, and changed by the seed
+        assert_eq!(variation.vary(0.667, 1.0, &sentences[3]), varied[3]);
+        let reseeded = ProsodyVariation {
+            seed: 8,
+            ..variation
+        };
+        assert_ne!(reseeded.vary(0.667, 1.0, &sentences[3]), varied[3]);
+        // Bounds are capped
+        let wild = ProsodyVariation::new(f32::NAN, 4.0, 7);
+        let (noise_scale, length_scale) = wild.vary(0.667, 1.0, &sentences[0]);
+        assert_eq!(noise_scale, 0.667);
+        assert!((0.5..=1.5).contains(&length_scale));
+    }
+}
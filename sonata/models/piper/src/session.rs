@@ -0,0 +1,546 @@
+//! Inference backends used to run Piper's ONNX graphs.
+//!
+//! Models are driven through the [`InferenceSession`] trait, so the rest of the crate
+//! does not depend on a particular runtime. `onnxruntime` (via `ort`) is used by default.
+//! The pure-Rust `tract` backend is available through the `tract` feature, for targets
+//! where onnxruntime binaries are not available, at the cost of slower inference.
+//! Voices exported to `safetensors` are run natively by the `candle` backend.
+//! Encrypted models are decrypted in memory, see [`crate::model_decryption`].
+
+#[cfg(not(any(feature = "ort", feature = "tract", feature = "candle")))]
+compile_error!(
+    "At least one inference backend feature (`ort`, `tract` or `candle`) must be enabled"
+);
+
+#[cfg(all(feature = "ort", feature = "tract"))]
+compile_error!(
+    "The `ort` and `tract` features both run ONNX models: build with `--no-default-features --features tract` to use `tract`"
+);
+
+use crate::model_decryption::{decrypt_model, model_decryptor};
+use ndarray::{ArrayD, CowArray, IxDyn};
+use sonata_core::{MemoryUsage, SonataError, SonataResult};
+use std::ops::Index;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A single input tensor, passed to the session positionally. Tensors that are used
+/// again, such as the encoder outputs decoded chunk by chunk, are passed as views
+pub(crate) enum SessionInput<'a> {
+    Int64(CowArray<'a, i64, IxDyn>),
+    Float32(CowArray<'a, f32, IxDyn>),
+}
+
+/// Named model outputs, in graph order
+pub(crate) struct SessionOutputs(Vec<(String, ArrayD<f32>)>);
+
+impl SessionOutputs {
+    pub(crate) fn new(outputs: Vec<(String, ArrayD<f32>)>) -> Self {
+        Self(outputs)
+    }
+    pub(crate) fn remove(&mut self, name: &str) -> Option<ArrayD<f32>> {
+        let index = self
+            .0
+            .iter()
+            .position(|(output_name, _)| output_name == name)?;
+        Some(self.0.remove(index).1)
+    }
+    pub(crate) fn into_first(self) -> SonataResult<ArrayD<f32>> {
+        self.0
+            .into_iter()
+            .next()
+            .map(|(_, value)| value)
+            .ok_or_else(|| SonataError::with_message("Model returned no outputs"))
+    }
+}
+
+impl Index<usize> for SessionOutputs {
+    type Output = ArrayD<f32>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.0[index].1
+    }
+}
+
+/// Name, shape and element type of an input or output of a model
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelIoInfo {
+    pub name: String,
+    /// `None` for dynamic dimensions, e.g. the number of phonemes
+    pub dims: Vec<Option<i64>>,
+    /// ONNX element type name, e.g. `int64` or `float32`
+    pub dtype: String,
+}
+
+/// The inputs and outputs of a model, in graph order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelIo {
+    pub inputs: Vec<ModelIoInfo>,
+    pub outputs: Vec<ModelIoInfo>,
+}
+
+pub(crate) trait InferenceSession: Send + Sync {
+    fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs>;
+    fn io_info(&self) -> ModelIo;
+    /// Approximate memory held by the session. Sessions not created by
+    /// [`create_inference_session`] report nothing
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+}
+
+/// A session that reports the size of its model, and the largest tensors it was given
+/// and returned as its working memory. The memory onnxruntime keeps in its arenas is
+/// not exposed, but grows with the tensors of the longest sentence spoken.
+struct MeasuredSession<S> {
+    session: S,
+    model_bytes: u64,
+    peak_tensor_bytes: AtomicU64,
+}
+
+fn measured(
+    session: impl InferenceSession + 'static,
+    model_bytes: u64,
+) -> Box<dyn InferenceSession> {
+    Box::new(MeasuredSession {
+        session,
+        model_bytes,
+        peak_tensor_bytes: AtomicU64::new(0),
+    })
+}
+
+/// Size of the model file, `0` if it can't be read
+fn file_size(model_path: &Path) -> u64 {
+    std::fs::metadata(model_path).map_or(0, |meta| meta.len())
+}
+
+impl<S: InferenceSession> InferenceSession for MeasuredSession<S> {
+    fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+        let input_bytes: usize = inputs
+            .iter()
+            .map(|input| match input {
+                SessionInput::Int64(array) => array.len() * std::mem::size_of::<i64>(),
+                SessionInput::Float32(array) => array.len() * std::mem::size_of::<f32>(),
+            })
+            .sum();
+        let outputs = self.session.run(inputs)?;
+        let output_bytes: usize = outputs
+            .0
+            .iter()
+            .map(|(_, value)| value.len() * std::mem::size_of::<f32>())
+            .sum();
+        self.peak_tensor_bytes
+            .fetch_max((input_bytes + output_bytes) as u64, Ordering::Relaxed);
+        Ok(outputs)
+    }
+    fn io_info(&self) -> ModelIo {
+        self.session.io_info()
+    }
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            model_bytes: self.model_bytes,
+            session_bytes: self.peak_tensor_bytes.load(Ordering::Relaxed),
+            cache_bytes: 0,
+        }
+    }
+}
+
+/// Where the sessions created on a thread are placed, as set by
+/// [`crate::with_execution_providers`] and [`crate::with_device_pool`]. Voices keep the
+/// placement they were loaded with, for the models they load later, e.g. on reload
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SessionPlacement {
+    #[cfg(feature = "ort")]
+    execution_providers: Option<Vec<crate::ExecutionProvider>>,
+    #[cfg(feature = "ort")]
+    device_pool: Option<(Vec<crate::ExecutionProvider>, crate::DispatchPolicy)>,
+}
+
+impl SessionPlacement {
+    /// The placement of the sessions created on this thread
+    pub(crate) fn current() -> Self {
+        Self {
+            #[cfg(feature = "ort")]
+            execution_providers: crate::onnxruntime::current_placement(),
+            #[cfg(feature = "ort")]
+            device_pool: crate::device_pool::current_pool(),
+        }
+    }
+    /// Run `create` with the sessions it creates placed as when the placement was taken,
+    /// whatever the placement of this thread
+    pub(crate) fn scope<T>(&self, create: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "ort")]
+        return crate::onnxruntime::with_placement(self.execution_providers.clone(), || {
+            crate::device_pool::with_pool(self.device_pool.clone(), create)
+        });
+        #[cfg(not(feature = "ort"))]
+        create()
+    }
+}
+
+#[inline(always)]
+fn inference_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> SonataError {
+    SonataError::OperationError("Failed to run model inference".to_string()).caused_by(error)
+}
+
+/// Create an inference session for the model at `model_path` using the preferred backend.
+pub(crate) fn create_inference_session(
+    model_path: &Path,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    if model_decryptor().is_some() {
+        let model_bytes = std::fs::read(model_path).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to read model `{}`",
+                model_path.display()
+            ))
+            .caused_by(e)
+        })?;
+        return create_inference_session_from_bytes(model_bytes, model_path);
+    }
+    let is_safetensors = model_path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("safetensors"))
+        .unwrap_or(false);
+    if is_safetensors {
+        #[cfg(feature = "candle")]
+        return Ok(measured(
+            crate::candle_backend::CandleSession::from_path(model_path)?,
+            file_size(model_path),
+        ));
+        #[cfg(not(feature = "candle"))]
+        return Err(SonataError::FailedToLoadResource(format!(
+            "Model `{}` is in safetensors format. Enable the `candle` feature to load it",
+            model_path.display()
+        )));
+    }
+    create_onnx_session(model_path)
+}
+
+/// Like [`create_inference_session`], for a model that is already in memory, e.g. one
+/// read from a voice bundle. `model_path` identifies the model in error messages.
+pub(crate) fn create_inference_session_from_bytes(
+    model_bytes: Vec<u8>,
+    model_path: &Path,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    let model_bytes = match model_decryptor() {
+        Some(decryptor) => decrypt_model(decryptor.as_ref(), model_path, model_bytes)?,
+        None => model_bytes,
+    };
+    #[cfg(feature = "candle")]
+    let model_size = model_bytes.len() as u64;
+    let is_safetensors = model_path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("safetensors"))
+        .unwrap_or(false);
+    if is_safetensors {
+        #[cfg(feature = "candle")]
+        return Ok(measured(
+            crate::candle_backend::CandleSession::from_bytes(model_bytes, model_path)?,
+            model_size,
+        ));
+        #[cfg(not(feature = "candle"))]
+        return Err(SonataError::FailedToLoadResource(format!(
+            "Model `{}` is in safetensors format. Enable the `candle` feature to load it",
+            model_path.display()
+        )));
+    }
+    create_onnx_session_from_bytes(model_bytes, model_path)
+}
+
+#[cfg(feature = "ort")]
+fn create_onnx_session(model_path: &Path) -> SonataResult<Box<dyn InferenceSession>> {
+    let model_size = file_size(model_path);
+    crate::device_pool::replicate(|| {
+        Ok(measured(
+            ort_backend::OrtSession::from_path(model_path)?,
+            model_size,
+        ))
+    })
+}
+
+#[cfg(feature = "ort")]
+fn create_onnx_session_from_bytes(
+    model_bytes: Vec<u8>,
+    model_path: &Path,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    crate::device_pool::replicate(|| {
+        Ok(measured(
+            ort_backend::OrtSession::from_bytes(&model_bytes, model_path)?,
+            model_bytes.len() as u64,
+        ))
+    })
+}
+
+#[cfg(all(feature = "tract", not(feature = "ort")))]
+fn create_onnx_session(model_path: &Path) -> SonataResult<Box<dyn InferenceSession>> {
+    Ok(measured(
+        tract_backend::TractSession::from_path(model_path)?,
+        file_size(model_path),
+    ))
+}
+
+#[cfg(all(feature = "tract", not(feature = "ort")))]
+fn create_onnx_session_from_bytes(
+    model_bytes: Vec<u8>,
+    model_path: &Path,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    Ok(measured(
+        tract_backend::TractSession::from_bytes(&model_bytes, model_path)?,
+        model_bytes.len() as u64,
+    ))
+}
+
+#[cfg(not(any(feature = "ort", feature = "tract")))]
+fn create_onnx_session(model_path: &Path) -> SonataResult<Box<dyn InferenceSession>> {
+    Err(SonataError::FailedToLoadResource(format!(
+        "Cannot load ONNX model `{}`: neither the `ort` nor the `tract` feature is enabled",
+        model_path.display()
+    )))
+}
+
+#[cfg(not(any(feature = "ort", feature = "tract")))]
+fn create_onnx_session_from_bytes(
+    _model_bytes: Vec<u8>,
+    model_path: &Path,
+) -> SonataResult<Box<dyn InferenceSession>> {
+    create_onnx_session(model_path)
+}
+
+#[cfg(feature = "ort")]
+mod ort_backend {
+    use super::*;
+    use crate::onnxruntime::session_builder;
+    use crate::shutdown::LoadedResource;
+    use ort::{
+        AllocationDevice, AllocatorType, MemoryInfo, MemoryType, PrimitiveTensorElementType,
+        Session, SessionInputValue, SessionInputs, TensorElementType, TensorRefMut, Value,
+        ValueType,
+    };
+
+    pub(crate) struct OrtSession(Session, LoadedResource);
+
+    impl OrtSession {
+        pub(crate) fn from_path(model_path: &Path) -> SonataResult<Self> {
+            session_builder()?
+                .commit_from_file(model_path)
+                .map(|session| Self(session, LoadedResource::new()))
+                .map_err(session_error)
+        }
+        pub(crate) fn from_bytes(model_bytes: &[u8], model_path: &Path) -> SonataResult<Self> {
+            session_builder()?
+                .commit_from_memory(model_bytes)
+                .map(|session| Self(session, LoadedResource::new()))
+                .map_err(|err| {
+                    SonataError::OperationError(format!(
+                        "Failed to load model `{}`",
+                        model_path.display()
+                    ))
+                    .caused_by(session_error(err))
+                })
+        }
+    }
+
+    fn session_error(err: ort::Error) -> SonataError {
+        SonataError::OperationError(
+            "Failed to initialize onnxruntime inference session".to_string(),
+        )
+        .caused_by(err)
+    }
+
+    impl InferenceSession for OrtSession {
+        fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+            let mut values: Vec<SessionInputValue> = Vec::with_capacity(inputs.len());
+            for input in inputs {
+                let value = match input {
+                    SessionInput::Int64(array) => input_value(array),
+                    SessionInput::Float32(array) => input_value(array),
+                };
+                values.push(value.map_err(inference_error)?);
+            }
+            let outputs = self
+                .0
+                .run(SessionInputs::from(values.as_slice()))
+                .map_err(inference_error)?;
+            let mut retval = Vec::with_capacity(self.0.outputs.len());
+            for output in self.0.outputs.iter() {
+                let Some(value) = outputs.get(output.name.as_str()) else {
+                    continue;
+                };
+                let tensor = value.try_extract_tensor::<f32>().map_err(inference_error)?;
+                retval.push((output.name.clone(), tensor.view().to_owned()));
+            }
+            Ok(SessionOutputs::new(retval))
+        }
+        fn io_info(&self) -> ModelIo {
+            ModelIo {
+                inputs: Vec::from_iter(
+                    self.0
+                        .inputs
+                        .iter()
+                        .map(|input| value_info(&input.name, &input.input_type)),
+                ),
+                outputs: Vec::from_iter(
+                    self.0
+                        .outputs
+                        .iter()
+                        .map(|output| value_info(&output.name, &output.output_type)),
+                ),
+            }
+        }
+    }
+
+    /// The tensor of `array`, which refers to the data of contiguous views instead of
+    /// copying it
+    fn input_value<'a, T>(array: CowArray<'a, T, IxDyn>) -> ort::Result<SessionInputValue<'a>>
+    where
+        T: PrimitiveTensorElementType + Clone + std::fmt::Debug + 'static,
+    {
+        if !(array.is_view() && array.is_standard_layout()) {
+            return Value::from_array(array.into_owned()).map(SessionInputValue::from);
+        }
+        let shape = Vec::from_iter(array.shape().iter().map(|dim| *dim as i64));
+        let info = MemoryInfo::new(
+            AllocationDevice::CPU,
+            0,
+            AllocatorType::Arena,
+            MemoryType::Default,
+        )?;
+        // SAFETY: the view borrows the data for `'a`, which the tensor doesn't outlive,
+        // and onnxruntime doesn't write to the inputs of a run
+        let tensor = unsafe { TensorRefMut::<T>::from_raw(info, array.as_ptr() as *mut _, shape) }?;
+        Ok(SessionInputValue::from(tensor))
+    }
+
+    fn value_info(name: &str, value_type: &ValueType) -> ModelIoInfo {
+        let (dims, dtype) = match value_type {
+            ValueType::Tensor { ty, dimensions, .. } => (
+                // onnxruntime reports dynamic dimensions as -1
+                Vec::from_iter(dimensions.iter().map(|dim| (*dim >= 0).then_some(*dim))),
+                element_type_name(*ty),
+            ),
+            // Sequences and maps, which Piper models don't use
+            _ => (Vec::new(), "non-tensor".to_string()),
+        };
+        ModelIoInfo {
+            name: name.to_string(),
+            dims,
+            dtype,
+        }
+    }
+
+    fn element_type_name(ty: TensorElementType) -> String {
+        match ty {
+            TensorElementType::Float32 => "float32".to_string(),
+            TensorElementType::Int64 => "int64".to_string(),
+            other => format!("{:?}", other).to_lowercase(),
+        }
+    }
+}
+
+#[cfg(feature = "tract")]
+mod tract_backend {
+    use super::*;
+    use tract_onnx::prelude::*;
+
+    pub(crate) struct TractSession {
+        model: TypedSimplePlan<TypedModel>,
+        output_names: Vec<String>,
+    }
+
+    fn outlet_name(graph: &TypedModel, outlet: OutletId) -> String {
+        graph
+            .outlet_label(outlet)
+            .map(String::from)
+            .unwrap_or_else(|| graph.node(outlet.node).name.clone())
+    }
+
+    fn outlet_info(graph: &TypedModel, outlet: OutletId) -> ModelIoInfo {
+        let (dims, dtype) = match graph.outlet_fact(outlet) {
+            Ok(fact) => (
+                // Symbolic dimensions are dynamic
+                Vec::from_iter(fact.shape.iter().map(|dim| dim.to_i64().ok())),
+                match fact.datum_type {
+                    DatumType::F32 => "float32".to_string(),
+                    DatumType::I64 => "int64".to_string(),
+                    other => format!("{:?}", other).to_lowercase(),
+                },
+            ),
+            Err(_) => (Vec::new(), "unknown".to_string()),
+        };
+        ModelIoInfo {
+            name: outlet_name(graph, outlet),
+            dims,
+            dtype,
+        }
+    }
+
+    impl TractSession {
+        pub(crate) fn from_path(model_path: &Path) -> SonataResult<Self> {
+            Self::from_model(tract_onnx::onnx().model_for_path(model_path), model_path)
+        }
+        pub(crate) fn from_bytes(mut model_bytes: &[u8], model_path: &Path) -> SonataResult<Self> {
+            Self::from_model(
+                tract_onnx::onnx().model_for_read(&mut model_bytes),
+                model_path,
+            )
+        }
+        fn from_model(model: TractResult<InferenceModel>, model_path: &Path) -> SonataResult<Self> {
+            let model = model
+                .and_then(|model| model.into_optimized())
+                .and_then(|model| model.into_runnable())
+                .map_err(|err| {
+                    SonataError::OperationError(format!(
+                        "Failed to initialize tract inference session for model `{}`",
+                        model_path.display()
+                    ))
+                    .caused_by(err)
+                })?;
+            let graph = model.model();
+            let output_names = Vec::from_iter(
+                graph
+                    .output_outlets()
+                    .unwrap_or(&[])
+                    .iter()
+                    .map(|outlet| outlet_name(graph, *outlet)),
+            );
+            Ok(Self {
+                model,
+                output_names,
+            })
+        }
+    }
+
+    impl InferenceSession for TractSession {
+        fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+            let inputs: TVec<TValue> = inputs
+                .into_iter()
+                .map(|input| match input {
+                    SessionInput::Int64(array) => Tensor::from(array.into_owned()).into(),
+                    SessionInput::Float32(array) => Tensor::from(array.into_owned()).into(),
+                })
+                .collect();
+            let outputs = self.model.run(inputs).map_err(inference_error)?;
+            let mut retval = Vec::with_capacity(outputs.len());
+            for (name, value) in self.output_names.iter().zip(outputs.iter()) {
+                let array = value.to_array_view::<f32>().map_err(inference_error)?;
+                retval.push((name.clone(), array.to_owned()));
+            }
+            Ok(SessionOutputs::new(retval))
+        }
+        fn io_info(&self) -> ModelIo {
+            let graph = self.model.model();
+            let infos = |outlets: TractResult<&[OutletId]>| {
+                Vec::from_iter(
+                    outlets
+                        .unwrap_or(&[])
+                        .iter()
+                        .map(|outlet| outlet_info(graph, *outlet)),
+                )
+            };
+            ModelIo {
+                inputs: infos(graph.input_outlets()),
+                outputs: infos(graph.output_outlets()),
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! Voices packaged for sherpa-onnx.
+//!
+//! sherpa-onnx ships Piper voices as a directory with the model (`*.onnx`), its symbols
+//! in `tokens.txt` (one `<symbol> <id>` per line) and a copy of `espeak-ng-data`. What
+//! Piper keeps in the voice's JSON config, such as the sample rate, the espeak-ng voice
+//! and the number of speakers, is stored in the metadata of the model instead.
+//!
+//! The package's `espeak-ng-data` is not used: text is phonemized with the espeak-ng
+//! data Sonata is set up with. Voices phonemized with a `lexicon.txt` instead of
+//! espeak-ng, and models exported from other toolkits, are not supported.
+
+use crate::{
+    check_model_config, AudioConfig, ESpeakConfig, InferenceConfig, ModelConfig,
+    DEFAULT_NATIVE_RATE_RANGE,
+};
+use sonata_core::{SonataError, SonataResult};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub const TOKENS_FILENAME: &str = "tokens.txt";
+const LEXICON_FILENAME: &str = "lexicon.txt";
+
+/// The inference defaults of sherpa-onnx, which are not part of the package
+const NOISE_SCALE: f32 = 0.667;
+const LENGTH_SCALE: f32 = 1.0;
+const NOISE_W: f32 = 0.8;
+
+/// Field number of `metadata_props` in ONNX's `ModelProto`
+const METADATA_PROPS_FIELD: u64 = 14;
+
+/// Whether `path` is the directory of a sherpa-onnx voice
+pub fn is_sherpa_package(path: &Path) -> bool {
+    path.join(TOKENS_FILENAME).is_file()
+}
+
+/// Read the package at `package_dir` as the config of a Piper voice. Returns the
+/// config and the path of the model
+pub fn load_package(package_dir: &Path) -> SonataResult<(ModelConfig, PathBuf)> {
+    let model_path = find_model(package_dir)?;
+    let metadata = File::open(&model_path)
+        .and_then(|file| read_onnx_metadata(BufReader::new(file)))
+        .map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to read the metadata of model `{}`",
+                model_path.display()
+            ))
+            .caused_by(e)
+        })?;
+    let tokens_path = package_dir.join(TOKENS_FILENAME);
+    let phoneme_id_map = File::open(&tokens_path)
+        .and_then(|file| parse_tokens(BufReader::new(file)))
+        .map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to read tokens `{}`",
+                tokens_path.display()
+            ))
+            .caused_by(e)
+        })?;
+    let has_lexicon = package_dir.join(LEXICON_FILENAME).is_file();
+    let config = package_config(&metadata, phoneme_id_map, has_lexicon).map_err(|why| {
+        SonataError::FailedToLoadResource(format!(
+            "Unsupported sherpa-onnx voice `{}`: {}",
+            package_dir.display(),
+            why
+        ))
+    })?;
+    Ok((
+        check_model_config(Ok(config), Some(&tokens_path))?,
+        model_path,
+    ))
+}
+
+/// The model of the package: `model.onnx`, or else its only `.onnx` file other than
+/// quantized (`*.int8.onnx`) ones
+fn find_model(package_dir: &Path) -> SonataResult<PathBuf> {
+    let default_model = package_dir.join("model.onnx");
+    if default_model.is_file() {
+        return Ok(default_model);
+    }
+    let entries = std::fs::read_dir(package_dir).map_err(|e| {
+        SonataError::FailedToLoadResource(format!(
+            "Failed to list sherpa-onnx voice `{}`",
+            package_dir.display()
+        ))
+        .caused_by(e)
+    })?;
+    let mut models = Vec::from_iter(
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let filename = path.file_name().unwrap_or_default().to_string_lossy();
+                filename.ends_with(".onnx") && !filename.ends_with(".int8.onnx") && path.is_file()
+            }),
+    );
+    match models.len() {
+        1 => Ok(models.remove(0)),
+        0 => Err(SonataError::FailedToLoadResource(format!(
+            "Sherpa-onnx voice `{}` has no model",
+            package_dir.display()
+        ))),
+        _ => Err(SonataError::FailedToLoadResource(format!(
+            "Sherpa-onnx voice `{}` has several models. Name the one to use `model.onnx`",
+            package_dir.display()
+        ))),
+    }
+}
+
+fn package_config(
+    metadata: &HashMap<String, String>,
+    phoneme_id_map: HashMap<char, Vec<i64>>,
+    has_lexicon: bool,
+) -> Result<ModelConfig, String> {
+    if let Some(model_type) = metadata.get("model_type").filter(|ty| *ty != "vits") {
+        return Err(format!("`{}` models are not supported", model_type));
+    }
+    if let Some(comment) = metadata
+        .get("comment")
+        .filter(|comment| *comment != "piper")
+    {
+        return Err(format!(
+            "the model was exported from `{}`, only Piper voices are supported",
+            comment
+        ));
+    }
+    let espeak_voice = match metadata.get("voice") {
+        Some(voice) => voice.clone(),
+        None if has_lexicon => {
+            return Err("voices phonemized with a lexicon are not supported".to_string())
+        }
+        None => return Err("the model metadata has no espeak-ng `voice`".to_string()),
+    };
+    let number = |key: &str| -> Result<Option<u32>, String> {
+        metadata
+            .get(key)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid `{}` in the model metadata: `{}`", key, value))
+            })
+            .transpose()
+    };
+    let sample_rate = number("sample_rate")?
+        .ok_or_else(|| "the model metadata has no `sample_rate`".to_string())?;
+    let num_symbols = phoneme_id_map
+        .values()
+        .flatten()
+        .max()
+        .map_or(0, |max_id| *max_id as u32 + 1);
+    Ok(ModelConfig {
+        audio: AudioConfig {
+            sample_rate,
+            quality: None,
+        },
+        num_speakers: number("n_speakers")?.unwrap_or(1),
+        espeak: ESpeakConfig {
+            voice: espeak_voice,
+        },
+        inference: InferenceConfig {
+            noise_scale: NOISE_SCALE,
+            length_scale: LENGTH_SCALE,
+            noise_w: NOISE_W,
+            rate_range: DEFAULT_NATIVE_RATE_RANGE,
+            prosody_variation: None,
+        },
+        num_symbols,
+        phoneme_id_map,
+        ..Default::default()
+    })
+}
+
+/// Parse `tokens.txt`. A symbol may be a space, e.g. ` 3`
+fn parse_tokens(reader: impl BufRead) -> io::Result<HashMap<char, Vec<i64>>> {
+    let mut phoneme_id_map: HashMap<char, Vec<i64>> = HashMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        let invalid_line = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid token on line {}: `{}`", index + 1, line),
+            )
+        };
+        let (symbol, id) = line.rsplit_once(' ').ok_or_else(invalid_line)?;
+        let id: i64 = id.parse().map_err(|_| invalid_line())?;
+        let mut chars = symbol.chars();
+        let (Some(phoneme), None) = (chars.next(), chars.next()) else {
+            return Err(invalid_line());
+        };
+        phoneme_id_map.entry(phoneme).or_default().push(id);
+    }
+    Ok(phoneme_id_map)
+}
+
+/// Read the `metadata_props` of an ONNX model, skipping over the graph
+fn read_onnx_metadata(mut reader: impl Read + Seek) -> io::Result<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    while let Some(key) = read_varint(&mut reader)? {
+        let (field, wire_type) = (key >> 3, key & 0x7);
+        match wire_type {
+            0 => {
+                read_field_varint(&mut reader)?;
+            }
+            1 => skip(&mut reader, 8)?,
+            2 => {
+                let len = read_field_varint(&mut reader)?;
+                if field == METADATA_PROPS_FIELD {
+                    let mut entry = vec![0; len as usize];
+                    reader.read_exact(&mut entry)?;
+                    let (key, value) = parse_string_entry(&entry)?;
+                    metadata.insert(key, value);
+                } else {
+                    skip(&mut reader, len)?;
+                }
+            }
+            5 => skip(&mut reader, 4)?,
+            _ => return Err(invalid_protobuf()),
+        }
+    }
+    Ok(metadata)
+}
+
+/// Parse a `StringStringEntryProto`
+fn parse_string_entry(mut entry: &[u8]) -> io::Result<(String, String)> {
+    let (mut key, mut value) = (String::new(), String::new());
+    while let Some(tag) = read_varint(&mut entry)? {
+        if tag & 0x7 != 2 {
+            return Err(invalid_protobuf());
+        }
+        let len = read_field_varint(&mut entry)? as usize;
+        if len > entry.len() {
+            return Err(invalid_protobuf());
+        }
+        let (bytes, rest) = entry.split_at(len);
+        let string = String::from_utf8_lossy(bytes).into_owned();
+        match tag >> 3 {
+            1 => key = string,
+            2 => value = string,
+            _ => {}
+        }
+        entry = rest;
+    }
+    Ok((key, value))
+}
+
+/// Read a varint, or `None` at the end of the input
+fn read_varint(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            return match shift {
+                0 => Ok(None),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(invalid_protobuf())
+}
+
+fn read_field_varint(reader: &mut impl Read) -> io::Result<u64> {
+    read_varint(reader)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+fn skip(reader: &mut impl Seek, len: u64) -> io::Result<()> {
+    let offset = i64::try_from(len).map_err(|_| invalid_protobuf())?;
+    reader.seek(SeekFrom::Current(offset)).map(|_| ())
+}
+
+fn invalid_protobuf() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Not an ONNX model")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn length_delimited(field: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![field << 3 | 2, bytes.len() as u8];
+        encoded.extend_from_slice(bytes);
+        encoded
+    }
+
+    fn metadata_entry(key: &str, value: &str) -> Vec<u8> {
+        let mut entry = length_delimited(1, key.as_bytes());
+        entry.extend(length_delimited(2, value.as_bytes()));
+        length_delimited(METADATA_PROPS_FIELD as u8, &entry)
+    }
+
+    #[test]
+    fn test_read_onnx_metadata() {
+        // `ir_version`, a graph, and the metadata
+        let mut model = vec![1 << 3, 8];
+        model.extend(length_delimited(7, &[0xff; 100]));
+        model.extend(metadata_entry("sample_rate", "22050"));
+        model.extend(metadata_entry("voice", "en-us"));
+        let metadata = read_onnx_metadata(Cursor::new(model)).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["sample_rate"], "22050");
+        assert_eq!(metadata["voice"], "en-us");
+        assert!(read_onnx_metadata(Cursor::new(vec![1 << 3])).is_err());
+        assert!(read_onnx_metadata(Cursor::new(b"{\"voices\": []}".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_package_config() {
+        let phoneme_id_map = parse_tokens("_ 0\n^ 1\n$ 2\n  3\nə 4\r\n".as_bytes()).unwrap();
+        assert_eq!(phoneme_id_map[&' '], [3]);
+        assert_eq!(phoneme_id_map[&'ə'], [4]);
+        assert!(parse_tokens("ab 5\n".as_bytes()).is_err());
+        let metadata = HashMap::from_iter(
+            [
+                ("model_type", "vits"),
+                ("comment", "piper"),
+                ("voice", "en-us"),
+                ("n_speakers", "1"),
+                ("sample_rate", "16000"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        let config = package_config(&metadata, phoneme_id_map.clone(), false).unwrap();
+        let config = check_model_config(Ok(config), None).unwrap();
+        assert_eq!(config.audio.sample_rate, 16000);
+        assert_eq!(config.num_symbols, 5);
+        assert_eq!(config.language_code(), "en-us");
+        assert_eq!(config.meta_ids, (0, 1, 2));
+
+        let mut coqui = metadata.clone();
+        coqui.insert("comment".to_string(), "coqui".to_string());
+        assert!(package_config(&coqui, phoneme_id_map.clone(), false).is_err());
+        let mut lexicon = metadata;
+        lexicon.remove("voice");
+        match package_config(&lexicon, phoneme_id_map, true) {
+            Err(error) => assert!(error.contains("lexicon"), "{}", error),
+            Ok(_) => panic!("Lexicon voices should be rejected"),
+        }
+    }
+}
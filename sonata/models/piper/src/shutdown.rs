@@ -0,0 +1,66 @@
+//! Releasing what Sonata sets up for the whole process.
+//!
+//! espeak-ng and onnxruntime are set up on first use and kept until the process exits,
+//! when their globals are torn down in no particular order. Applications that load and
+//! unload Sonata, such as plugins and test harnesses, drop their voices and call
+//! [`shutdown`] instead, which releases them in order while the libraries are loaded.
+
+use sonata_core::{SonataError, SonataResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Inference sessions and tashkeel engines that were not dropped yet
+static NUM_LOADED: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by a resource that must be dropped before shutting down, for as long as it is
+/// alive. Declare it after the resource, so that it is dropped last
+pub(crate) struct LoadedResource(());
+
+impl LoadedResource {
+    pub(crate) fn new() -> Self {
+        NUM_LOADED.fetch_add(1, Ordering::SeqCst);
+        Self(())
+    }
+}
+
+impl Drop for LoadedResource {
+    fn drop(&mut self) {
+        NUM_LOADED.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Release the process-wide state of Sonata: the cached phonemes, then espeak-ng, then
+/// the onnxruntime environment. Drop all voices and speaker encoders first, and don't
+/// use Sonata from other threads meanwhile; fails, releasing nothing, while inference
+/// sessions or tashkeel engines are still loaded.
+///
+/// Sonata can be used again afterwards, espeak-ng and onnxruntime are set up again
+/// when the next voice is loaded, with [`crate::init`] if called again. `ort` only lets
+/// go of the environment when it sets up the next one, or at exit.
+pub fn shutdown() -> SonataResult<()> {
+    let num_loaded = NUM_LOADED.load(Ordering::SeqCst);
+    if num_loaded > 0 {
+        return Err(SonataError::OperationError(format!(
+            "Can not shut down while {} inference sessions or tashkeel engines are loaded. Drop all voices first",
+            num_loaded
+        )));
+    }
+    crate::clear_phoneme_cache();
+    #[cfg(feature = "espeak")]
+    espeak_phonemizer::terminate();
+    #[cfg(feature = "ort")]
+    crate::onnxruntime::release_environment();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_with_loaded_resources() {
+        let resource = LoadedResource::new();
+        let error = shutdown().err().unwrap().to_string();
+        assert!(error.contains("Drop all voices"), "{}", error);
+        drop(resource);
+    }
+}
@@ -0,0 +1,130 @@
+//! Speaker embeddings computed from reference audio, to clone a voice.
+//!
+//! A speaker encoder (e.g. an x-vector or ECAPA-TDNN model exported to ONNX) takes mono
+//! audio as a `float32` tensor of shape `[1, num_samples]`, and returns the embedding of
+//! the speaker as its first output. Voices that were trained on the embeddings of the
+//! same encoder can speak with the voice of the reference, see
+//! [`crate::VitsStreamingModel::clone_voice`].
+//!
+//! Only the decoder takes the embedding, so the timbre of the reference is cloned, but
+//! not its rhythm: the exported encoder derives the speaker of the duration predictor
+//! from the speaker id, and durations stay those of the current speaker.
+
+use crate::session::{create_inference_session, InferenceSession, SessionInput};
+use ndarray::Array2;
+use sonata_core::{read_wave_file, Audio, AudioSamples, SonataError, SonataResult};
+use std::path::Path;
+use std::time::Duration;
+
+/// References shorter than this do not hold enough of the voice to clone it
+const MIN_REFERENCE_DURATION: Duration = Duration::from_secs(1);
+
+pub struct SpeakerEncoder {
+    session: Box<dyn InferenceSession>,
+    sample_rate: usize,
+}
+
+impl SpeakerEncoder {
+    /// Load the speaker encoder at `model_path`, which takes audio at `sample_rate`
+    pub fn new(model_path: &Path, sample_rate: usize) -> SonataResult<Self> {
+        Ok(Self {
+            session: create_inference_session(model_path)?,
+            sample_rate,
+        })
+    }
+    /// The embedding of the speaker of `reference`
+    pub fn embed(&self, reference: &Audio) -> SonataResult<Vec<f32>> {
+        if reference.duration() < MIN_REFERENCE_DURATION {
+            return Err(SonataError::OperationError(format!(
+                "The reference audio is too short to clone a voice from: {} ms",
+                reference.duration().as_millis()
+            )));
+        }
+        // The voice is in every channel
+        let samples = match reference.info.num_channels {
+            0 | 1 => reference.samples.clone(),
+            num_channels => AudioSamples::from(Vec::from_iter(
+                reference
+                    .iter_frames()
+                    .map(|frame| frame.iter().sum::<f32>() / num_channels as f32),
+            )),
+        };
+        let samples = samples
+            .resample(reference.info.sample_rate, self.sample_rate)
+            .into_vec();
+        let num_samples = samples.len();
+        let input = Array2::from_shape_vec((1, num_samples), samples).unwrap();
+        let outputs = self
+            .session
+            .run(vec![SessionInput::Float32(input.into_dyn().into())])?;
+        Ok(outputs.into_first()?.into_raw_vec())
+    }
+    /// The embedding of the speaker of the wave file at `reference_path`
+    pub fn embed_file(&self, reference_path: &Path) -> SonataResult<Vec<f32>> {
+        self.embed(&read_wave_file(reference_path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{ModelIo, SessionOutputs};
+    use ndarray::{ArrayD, IxDyn};
+
+    /// An encoder whose embedding is the mean and the number of the samples it is given
+    struct MeanEncoder;
+
+    impl InferenceSession for MeanEncoder {
+        fn run(&self, inputs: Vec<SessionInput<'_>>) -> SonataResult<SessionOutputs> {
+            let SessionInput::Float32(ref samples) = inputs[0] else {
+                unreachable!()
+            };
+            let num_samples = samples.len() as f32;
+            let embedding = ArrayD::from_shape_vec(
+                IxDyn(&[1, 2]),
+                vec![samples.sum() / num_samples, num_samples],
+            )
+            .unwrap();
+            Ok(SessionOutputs::new(vec![(
+                "embedding".to_string(),
+                embedding,
+            )]))
+        }
+        fn io_info(&self) -> ModelIo {
+            ModelIo::default()
+        }
+    }
+
+    fn encoder() -> SpeakerEncoder {
+        SpeakerEncoder {
+            session: Box::new(MeanEncoder),
+            sample_rate: 16000,
+        }
+    }
+
+    #[test]
+    fn test_embed() {
+        let reference = Audio::new(vec![0.5; 22050 * 2].into(), 22050, None);
+        // Resampled to the rate of the encoder
+        assert_eq!(encoder().embed(&reference).unwrap(), [0.5, 32000.0]);
+    }
+
+    #[test]
+    fn test_embed_stereo() {
+        let mut reference = Audio::new(
+            AudioSamples::from(Vec::from_iter((0..32000).map(|i| (i % 2) as f32))),
+            16000,
+            None,
+        );
+        reference.info.num_channels = 2;
+        // The channels are mixed down
+        assert_eq!(encoder().embed(&reference).unwrap(), [0.5, 16000.0]);
+    }
+
+    #[test]
+    fn test_short_reference() {
+        let reference = Audio::new(vec![0.5; 8000].into(), 16000, None);
+        let error = encoder().embed(&reference).unwrap_err().to_string();
+        assert!(error.contains("too short"), "{}", error);
+    }
+}
@@ -0,0 +1,204 @@
+//! Dumps of the tensors of each inference, for diagnosing voices.
+//!
+//! When `SONATA_DUMP_TENSORS_DIR` is set, every synthesized sentence writes an `.npz`
+//! file to that directory holding the model inputs (`phoneme_ids`, `scales`, `sid`),
+//! the encoder outputs of streaming models (`z`, `y_mask`, `g`) and the raw float
+//! `audio`, before any post-processing. Load them with `numpy.load`.
+
+use ndarray::{Array1, Array2, ArrayBase, Data, Dimension};
+use once_cell::sync::Lazy;
+use sonata_core::{SonataError, SonataResult};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const DUMP_TENSORS_DIR_ENV_VAR: &str = "SONATA_DUMP_TENSORS_DIR";
+
+static DUMP_DIR: Lazy<Option<PathBuf>> =
+    Lazy::new(|| std::env::var_os(DUMP_TENSORS_DIR_ENV_VAR).map(PathBuf::from));
+static NUM_DUMPS: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) trait NpyElement: Copy {
+    /// The numpy dtype
+    const DESCR: &'static str;
+    fn extend_le_bytes(&self, buf: &mut Vec<u8>);
+}
+
+impl NpyElement for f32 {
+    const DESCR: &'static str = "<f4";
+    fn extend_le_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.to_le_bytes());
+    }
+}
+
+impl NpyElement for i64 {
+    const DESCR: &'static str = "<i8";
+    fn extend_le_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.to_le_bytes());
+    }
+}
+
+/// The named tensors of one inference
+pub(crate) struct TensorDump {
+    arrays: Vec<(String, Vec<u8>)>,
+}
+
+impl TensorDump {
+    /// Start a dump, if dumping is enabled
+    pub(crate) fn start() -> Option<Self> {
+        DUMP_DIR.as_ref()?;
+        Some(Self { arrays: Vec::new() })
+    }
+    pub(crate) fn add<A, S, D>(&mut self, name: &str, array: &ArrayBase<S, D>)
+    where
+        A: NpyElement,
+        S: Data<Elem = A>,
+        D: Dimension,
+    {
+        self.arrays
+            .push((format!("{}.npy", name), to_npy(array.shape(), array.iter())));
+    }
+    /// Add the inputs of the model (or of the encoder of a streaming model)
+    pub(crate) fn add_inputs(
+        &mut self,
+        phoneme_ids: &Array2<i64>,
+        scales: &Array1<f32>,
+        speaker_id: Option<&Array1<i64>>,
+    ) {
+        self.add("phoneme_ids", phoneme_ids);
+        self.add("scales", scales);
+        if let Some(speaker_id) = speaker_id {
+            self.add("sid", speaker_id);
+        }
+    }
+    pub(crate) fn add_slice<A: NpyElement>(&mut self, name: &str, values: &[A]) {
+        self.arrays.push((
+            format!("{}.npy", name),
+            to_npy(&[values.len()], values.iter()),
+        ));
+    }
+    pub(crate) fn save(self) -> SonataResult<()> {
+        let Some(ref dir) = *DUMP_DIR else {
+            return Ok(());
+        };
+        let index = NUM_DUMPS.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{}-{:06}.npz", std::process::id(), index));
+        std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&path, to_zip(&self.arrays)))
+            .map_err(|e| {
+                SonataError::OperationError(format!(
+                    "Failed to write tensor dump `{}`",
+                    path.display()
+                ))
+                .caused_by(e)
+            })
+    }
+}
+
+fn to_npy<'a, A>(shape: &[usize], values: impl Iterator<Item = &'a A>) -> Vec<u8>
+where
+    A: NpyElement + 'a,
+{
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            Vec::from_iter(shape.iter().map(|dim| dim.to_string())).join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        A::DESCR,
+        shape
+    );
+    // The data starts at a multiple of 64 bytes, after the 10 bytes of preamble
+    let padded_len = (10 + header.len() + 1).next_multiple_of(64) - 10;
+    header.extend(std::iter::repeat_n(' ', padded_len - header.len() - 1));
+    header.push('\n');
+    let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+    npy.extend((header.len() as u16).to_le_bytes());
+    npy.extend(header.as_bytes());
+    for value in values {
+        value.extend_le_bytes(&mut npy);
+    }
+    npy
+}
+
+/// An uncompressed zip archive of `files`, which is what `numpy.savez` writes
+fn to_zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut central_directory = Vec::new();
+    for (name, data) in files {
+        let offset = zip.len() as u32;
+        // Version, flags, method (stored), time, date (1980-01-01), crc and sizes
+        let mut fields = Vec::new();
+        fields.extend(20u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0x21u16.to_le_bytes());
+        fields.extend(crc32(data).to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        zip.extend(0x04034b50u32.to_le_bytes());
+        zip.extend(&fields);
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+
+        central_directory.extend(0x02014b50u32.to_le_bytes());
+        // Made by version
+        central_directory.extend(20u16.to_le_bytes());
+        central_directory.extend(&fields);
+        // Comment length, disk number, internal and external attributes
+        central_directory.extend([0u8; 10]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+    }
+    let central_directory_offset = zip.len() as u32;
+    zip.extend(&central_directory);
+    zip.extend(0x06054b50u32.to_le_bytes());
+    zip.extend([0u8; 4]);
+    zip.extend((files.len() as u16).to_le_bytes());
+    zip.extend((files.len() as u16).to_le_bytes());
+    zip.extend((central_directory.len() as u32).to_le_bytes());
+    zip.extend(central_directory_offset.to_le_bytes());
+    zip.extend(0u16.to_le_bytes());
+    zip
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npz() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let array = ndarray::Array2::<i64>::from_shape_vec((1, 3), vec![1, 2, 3]).unwrap();
+        let npy = to_npy(array.shape(), array.iter());
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        let expected = "{'descr': '<i8', 'fortran_order': False, 'shape': (1, 3), }";
+        assert!(header.starts_with(expected) && header.ends_with(" \n"));
+        assert_eq!(npy.len(), 10 + header_len + 24);
+
+        let zip = to_zip(&[("a.npy".to_string(), npy.clone())]);
+        assert_eq!(&zip[0..4], b"PK\x03\x04");
+        assert_eq!(&zip[30..35], b"a.npy");
+        assert_eq!(&zip[35..35 + npy.len()], npy.as_slice());
+        assert_eq!(&zip[zip.len() - 22..zip.len() - 18], b"PK\x05\x06");
+    }
+}
@@ -0,0 +1,1138 @@
+//! A registry of loaded voices, keyed by voice id.
+//!
+//! Besides keeping voices around, the manager provides helpers commonly needed by
+//! voice pickers, such as generating (and caching) short audio previews.
+//!
+//! Voices can be registered by config path without being loaded; registered voices are
+//! loaded on first use. See [`VoiceManager::from_voices_dir`].
+//!
+//! Applications can refer to voices by aliases such as `narrator`, which stand for an
+//! installed voice with a speaker and rate, so that they don't depend on voice files.
+//! See [`VoiceManager::set_alias`]. Aliases are resolved by the application's calls to
+//! [`VoiceManager::resolve_voice`]; SSML `<voice>` elements are not parsed.
+//!
+//! With a memory budget, the least recently used voices are unloaded when loading a
+//! voice takes the loaded voices over budget. See [`VoiceManager::set_memory_budget`].
+
+#[cfg(feature = "download")]
+use crate::download::{DownloadProgress, DownloadRecord, VoiceDownloader};
+use crate::language_segmentation::{espeak_voice_for_language, segment_by_language};
+use crate::{espeak_phonemize, from_config_path, load_model_config, PiperSynthesisConfig};
+use serde::Deserialize;
+use sonata_core::{
+    Audio, AudioSamples, MemoryUsage, SonataError, SonataModel, SonataResult, RATE_MULTIPLIER_RANGE,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub type Voice = Arc<dyn SonataModel + Send + Sync>;
+
+/// Sample sentences used for previews, keyed by ISO 639-1 language code
+const PREVIEW_SENTENCES: &[(&str, &str)] = &[
+    ("ar", "هذا نموذج من صوتي."),
+    ("ca", "Aquesta és una mostra de la meva veu."),
+    ("cs", "Toto je ukázka mého hlasu."),
+    ("da", "Dette er en prøve på min stemme."),
+    ("de", "Dies ist eine Hörprobe meiner Stimme."),
+    ("el", "Αυτό είναι ένα δείγμα της φωνής μου."),
+    ("en", "This is a sample of my voice."),
+    ("es", "Esta es una muestra de mi voz."),
+    ("fa", "این نمونه‌ای از صدای من است."),
+    ("fi", "Tämä on näyte äänestäni."),
+    ("fr", "Voici un échantillon de ma voix."),
+    ("hu", "Ez egy minta a hangomból."),
+    ("it", "Questo è un campione della mia voce."),
+    ("nb", "Dette er en prøve av stemmen min."),
+    ("nl", "Dit is een voorbeeld van mijn stem."),
+    ("no", "Dette er en prøve av stemmen min."),
+    ("pl", "To jest próbka mojego głosu."),
+    ("pt", "Esta é uma amostra da minha voz."),
+    ("ro", "Aceasta este o mostră a vocii mele."),
+    ("ru", "Это образец моего голоса."),
+    ("sv", "Det här är ett prov på min röst."),
+    ("tr", "Bu, sesimin bir örneğidir."),
+    ("uk", "Це зразок мого голосу."),
+    ("vi", "Đây là một mẫu giọng nói của tôi."),
+    ("zh", "这是我的声音样本。"),
+];
+const DEFAULT_PREVIEW_SENTENCE: &str = "This is a sample of my voice.";
+
+/// Get the preview sentence for the given language code (e.g. `en_US`, `de-de`, `fr`)
+pub fn preview_sentence(language: Option<&str>) -> &'static str {
+    let Some(language) = language else {
+        return DEFAULT_PREVIEW_SENTENCE;
+    };
+    let primary_language = language
+        .split(['_', '-'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    PREVIEW_SENTENCES
+        .iter()
+        .find(|(code, _)| *code == primary_language)
+        .map(|(_, sentence)| *sentence)
+        .unwrap_or(DEFAULT_PREVIEW_SENTENCE)
+}
+
+/// Derive a voice id from the voice's config filename,
+/// e.g. `en_US-lessac-medium.onnx.json` becomes `en_US-lessac-medium`
+pub fn voice_id_from_config_path(config_path: &Path) -> Option<String> {
+    let model_filename = Path::new(config_path.file_stem()?);
+    let voice_id = match model_filename.extension() {
+        Some(ext) if ext == "onnx" || ext == "safetensors" => model_filename.file_stem()?,
+        _ => model_filename.as_os_str(),
+    };
+    Some(voice_id.to_string_lossy().into_owned())
+}
+
+/// Recursively find voice configs (`*.onnx.json`) under `dir`, sorted by path
+pub fn discover_voice_configs(dir: &Path) -> SonataResult<Vec<PathBuf>> {
+    let mut configs = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current_dir) = pending.pop() {
+        let entries = std::fs::read_dir(&current_dir).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to read voices directory `{}`",
+                current_dir.display()
+            ))
+            .caused_by(e)
+        })?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_voice_config(&path) {
+                configs.push(path);
+            }
+        }
+    }
+    configs.sort();
+    Ok(configs)
+}
+
+fn is_voice_config(path: &Path) -> bool {
+    let Some(filename) = path.file_name().and_then(|f| f.to_str()) else {
+        return false;
+    };
+    filename.ends_with(".onnx.json")
+        || (cfg!(feature = "candle") && filename.ends_with(".safetensors.json"))
+}
+
+/// Most common region of a language, used to rank voices when the requested region is unavailable
+const DEFAULT_REGIONS: &[(&str, &str)] = &[
+    ("ar", "jo"),
+    ("ca", "es"),
+    ("cs", "cz"),
+    ("da", "dk"),
+    ("de", "de"),
+    ("el", "gr"),
+    ("en", "us"),
+    ("es", "es"),
+    ("fa", "ir"),
+    ("fi", "fi"),
+    ("fr", "fr"),
+    ("hu", "hu"),
+    ("it", "it"),
+    ("nb", "no"),
+    ("nl", "nl"),
+    ("no", "no"),
+    ("pl", "pl"),
+    ("pt", "br"),
+    ("ro", "ro"),
+    ("ru", "ru"),
+    ("sv", "se"),
+    ("tr", "tr"),
+    ("uk", "ua"),
+    ("vi", "vn"),
+    ("zh", "cn"),
+];
+
+/// A BCP-47 language tag, normalized to lowercase subtags.
+/// Piper's `en_US` style codes are accepted as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LanguageTag(Vec<String>);
+
+impl LanguageTag {
+    fn parse(tag: &str) -> Option<Self> {
+        let subtags = Vec::from_iter(
+            tag.split(['_', '-'])
+                .filter(|subtag| !subtag.is_empty())
+                .map(|subtag| subtag.to_ascii_lowercase()),
+        );
+        if subtags.is_empty() {
+            None
+        } else {
+            Some(Self(subtags))
+        }
+    }
+    fn primary_language(&self) -> &str {
+        &self.0[0]
+    }
+    fn region(&self) -> Option<&str> {
+        self.0[1..]
+            .iter()
+            .find(|subtag| {
+                (subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+                    || (subtag.len() == 3 && subtag.chars().all(|c| c.is_ascii_digit()))
+            })
+            .map(String::as_str)
+    }
+    /// How well a voice with language `available` serves a request for `self`.
+    /// Higher is better, `None` means the voice can not be used.
+    fn match_score(&self, available: &LanguageTag) -> Option<usize> {
+        if self.primary_language() != available.primary_language() {
+            return None;
+        }
+        if self == available {
+            return Some(100);
+        }
+        // Lookup-style truncation, e.g. `zh-hant-tw` → `zh-hant`
+        if available.0.len() > 1 && self.0.starts_with(&available.0) {
+            return Some(50 + available.0.len());
+        }
+        let default_region = DEFAULT_REGIONS
+            .iter()
+            .find(|(language, _)| *language == self.primary_language())
+            .map(|(_, region)| *region);
+        if default_region.is_some() && available.region() == default_region {
+            return Some(20);
+        }
+        if available.0.len() == 1 {
+            return Some(10);
+        }
+        Some(1)
+    }
+}
+
+/// Piper voice ids start with the language code, e.g. `en_US-lessac-medium`
+fn language_from_voice_id(voice_id: &str) -> Option<String> {
+    let language = voice_id.split('-').next()?;
+    LanguageTag::parse(language).map(|_| language.to_string())
+}
+
+/// The download record next to the config of the voice `voice_id`
+#[cfg(feature = "download")]
+fn download_record(voice_id: &str, config_path: &Path) -> Option<DownloadRecord> {
+    DownloadRecord::load(config_path.parent()?, voice_id)
+}
+
+fn voice_has_speaker(voice: &Voice, sid: i64) -> SonataResult<bool> {
+    Ok(voice
+        .get_speakers()?
+        .map(|speakers| speakers.contains_key(&sid))
+        .unwrap_or(false))
+}
+
+/// A step of the fallback chain applied by [`VoiceManager::resolve_voice`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoiceFallback {
+    /// The requested voice, with its default speaker (when only the speaker is missing)
+    DefaultSpeaker,
+    /// The best matching voice of the requested voice's language, regardless of quality
+    SameLanguage,
+    /// A specific voice, e.g. an application-wide default voice
+    Voice(String),
+}
+
+/// A stable name for a voice, spoken by one of its speakers at some rate, e.g. `narrator`
+/// for speaker 0 of `en_US-hfc_female-medium` at 1.1 times the normal rate.
+///
+/// In alias files, the voice id is given as `voice`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct VoiceAlias {
+    #[serde(rename = "voice")]
+    pub voice_id: String,
+    /// The speaker, `None` for the voice's default speaker
+    pub speaker: Option<i64>,
+    /// How many times faster to speak, as a rate multiplier, between `0.5` and `3.0`
+    pub rate: Option<f32>,
+}
+
+impl VoiceAlias {
+    pub fn new(voice_id: impl Into<String>) -> Self {
+        Self {
+            voice_id: voice_id.into(),
+            speaker: None,
+            rate: None,
+        }
+    }
+    pub fn with_speaker(mut self, speaker: i64) -> Self {
+        self.speaker = Some(speaker);
+        self
+    }
+    pub fn with_rate(mut self, rate: f32) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+}
+
+/// Aliases' rates are rate multipliers, so they must be in [`RATE_MULTIPLIER_RANGE`]
+fn check_alias(name: &str, alias: &VoiceAlias) -> SonataResult<()> {
+    let (min_rate, max_rate) = RATE_MULTIPLIER_RANGE;
+    match alias.rate {
+        Some(rate) if !(min_rate..=max_rate).contains(&rate) => {
+            Err(SonataError::OperationError(format!(
+                "Invalid rate `{}` for voice alias `{}`. Expected a value between {} and {}",
+                rate, name, min_rate, max_rate
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// An installed voice with newer files upstream, see [`VoiceManager::check_updates`]
+#[cfg(feature = "download")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoiceUpdate {
+    pub voice_id: String,
+    pub changed_files: Vec<String>,
+}
+
+/// A voice returned by [`VoiceManager::resolve_voice`]
+pub struct ResolvedVoice {
+    pub voice_id: String,
+    pub voice: Voice,
+    /// The speaker to use, `None` for the voice's default speaker
+    pub speaker: Option<i64>,
+    /// The rate of the alias the voice was requested by, if any. It is the rate
+    /// multiplier of the synthesizer built with
+    /// `SonataSpeechSynthesizerBuilder::resolved_voice`
+    pub rate: Option<f32>,
+    /// Whether this is a fallback rather than the requested voice and speaker
+    pub is_fallback: bool,
+}
+
+#[derive(Default)]
+pub struct VoiceManager {
+    voices: RwLock<HashMap<String, Voice>>,
+    /// Config paths of voices that are registered but not loaded yet
+    registered: RwLock<HashMap<String, PathBuf>>,
+    /// Config paths of all voices that were registered or loaded from disk
+    config_paths: RwLock<HashMap<String, PathBuf>>,
+    /// Language codes of loaded and registered voices
+    languages: RwLock<HashMap<String, String>>,
+    fallback_chain: RwLock<Vec<VoiceFallback>>,
+    aliases: RwLock<HashMap<String, VoiceAlias>>,
+    previews: RwLock<HashMap<(String, Option<i64>), Audio>>,
+    /// The tick each loaded voice was last used at, to unload the least recently used
+    last_used: Mutex<HashMap<String, u64>>,
+    ticks: AtomicU64,
+    memory_budget: RwLock<Option<u64>>,
+}
+
+impl VoiceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Register all voices found (recursively) under `voices_dir`.
+    ///
+    /// Voices are keyed by id, and loaded lazily on first use. If several configs
+    /// map to the same voice id, the first one (by path) is used.
+    pub fn from_voices_dir(voices_dir: &Path) -> SonataResult<Self> {
+        let manager = Self::new();
+        for config_path in discover_voice_configs(voices_dir)? {
+            manager.register_voice(&config_path)?;
+        }
+        Ok(manager)
+    }
+    /// Register the voice at `config_path` without loading it, and return its id
+    pub fn register_voice(&self, config_path: &Path) -> SonataResult<String> {
+        let Some(voice_id) = voice_id_from_config_path(config_path) else {
+            return Err(SonataError::FailedToLoadResource(format!(
+                "Invalid config filename format `{}`",
+                config_path.display()
+            )));
+        };
+        if self.voices.read().unwrap().contains_key(&voice_id) {
+            return Ok(voice_id);
+        }
+        let mut registered = self.registered.write().unwrap();
+        if registered.contains_key(&voice_id) {
+            return Ok(voice_id);
+        }
+        // Invalid configs are reported when the voice is loaded
+        if let Ok((config, _)) = load_model_config(config_path) {
+            self.languages
+                .write()
+                .unwrap()
+                .insert(voice_id.clone(), config.language_code());
+        }
+        registered.insert(voice_id.clone(), config_path.to_path_buf());
+        self.config_paths
+            .write()
+            .unwrap()
+            .insert(voice_id.clone(), config_path.to_path_buf());
+        Ok(voice_id)
+    }
+    /// Download the voice `voice_key` (e.g. `en_US-lessac-medium`) into `voices_dir` and
+    /// register it. `on_progress` is called as the files of the voice are downloaded.
+    #[cfg(feature = "download")]
+    pub fn download_voice(
+        &self,
+        downloader: &VoiceDownloader,
+        voice_key: &str,
+        voices_dir: &Path,
+        on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<String> {
+        let config_path = downloader.download_voice(voice_key, voices_dir, on_progress)?;
+        self.register_voice(&config_path)
+    }
+    /// Find the downloaded voices whose files changed in their repository since they were
+    /// downloaded. Voices that were not downloaded with verification are skipped.
+    #[cfg(feature = "download")]
+    pub fn check_updates(&self, downloader: &VoiceDownloader) -> SonataResult<Vec<VoiceUpdate>> {
+        let mut config_paths = Vec::from_iter(self.config_paths.read().unwrap().clone());
+        config_paths.sort();
+        let mut updates = Vec::new();
+        for (voice_id, config_path) in config_paths {
+            let Some(record) = download_record(&voice_id, &config_path) else {
+                continue;
+            };
+            let changed_files = downloader.changed_files(&record)?;
+            if !changed_files.is_empty() {
+                updates.push(VoiceUpdate {
+                    voice_id,
+                    changed_files,
+                });
+            }
+        }
+        Ok(updates)
+    }
+    /// Download the latest files of the downloaded voice `voice_id` in place, from the
+    /// repository and revision it was downloaded from. The voice is reloaded on next use.
+    #[cfg(feature = "download")]
+    pub fn update(
+        &self,
+        downloader: &VoiceDownloader,
+        voice_id: &str,
+        on_progress: impl FnMut(&DownloadProgress),
+    ) -> SonataResult<()> {
+        let config_path = self.config_path(voice_id);
+        let record = config_path
+            .as_ref()
+            .and_then(|config_path| download_record(voice_id, config_path));
+        let (Some(config_path), Some(record)) = (config_path, record) else {
+            return Err(SonataError::OperationError(format!(
+                "Voice `{}` was not downloaded, and can not be updated",
+                voice_id
+            )));
+        };
+        let voice_dir = config_path.parent().unwrap_or(Path::new(""));
+        let config_path = downloader.update_voice(&record, voice_dir, on_progress)?;
+        self.remove_voice(voice_id);
+        self.register_voice(&config_path)?;
+        Ok(())
+    }
+    /// Load all registered voices that are not loaded yet
+    pub fn load_all(&self) -> SonataResult<()> {
+        let pending = Vec::from_iter(self.registered.read().unwrap().keys().cloned());
+        for voice_id in pending {
+            self.get_voice(&voice_id)?;
+        }
+        Ok(())
+    }
+    pub fn is_loaded(&self, voice_id: &str) -> bool {
+        self.voices.read().unwrap().contains_key(voice_id)
+    }
+    /// Load the voice at `config_path`, and return its id
+    pub fn load_voice(&self, config_path: &Path) -> SonataResult<String> {
+        let Some(voice_id) = voice_id_from_config_path(config_path) else {
+            return Err(SonataError::FailedToLoadResource(format!(
+                "Invalid config filename format `{}`",
+                config_path.display()
+            )));
+        };
+        let voice = from_config_path(config_path)?;
+        self.add_voice(voice_id.clone(), voice);
+        self.config_paths
+            .write()
+            .unwrap()
+            .insert(voice_id.clone(), config_path.to_path_buf());
+        self.enforce_memory_budget(&voice_id)?;
+        Ok(voice_id)
+    }
+    pub fn add_voice(&self, voice_id: impl Into<String>, voice: Voice) {
+        let voice_id = voice_id.into();
+        self.clear_previews_for(&voice_id);
+        self.registered.write().unwrap().remove(&voice_id);
+        self.config_paths.write().unwrap().remove(&voice_id);
+        let language = voice.get_language().ok().flatten();
+        {
+            let mut languages = self.languages.write().unwrap();
+            match language {
+                Some(language) => languages.insert(voice_id.clone(), language),
+                None => languages.remove(&voice_id),
+            };
+        }
+        self.touch(&voice_id);
+        self.voices.write().unwrap().insert(voice_id, voice);
+    }
+    pub fn remove_voice(&self, voice_id: &str) -> Option<Voice> {
+        self.clear_previews_for(voice_id);
+        self.registered.write().unwrap().remove(voice_id);
+        self.config_paths.write().unwrap().remove(voice_id);
+        self.languages.write().unwrap().remove(voice_id);
+        self.last_used.lock().unwrap().remove(voice_id);
+        self.voices.write().unwrap().remove(voice_id)
+    }
+    /// Unload the voice, keeping it registered so that it is loaded again on next use.
+    /// Returns `false` if the voice is not loaded, or was not loaded from a config path
+    /// and could not be loaded again.
+    ///
+    /// Requests holding the voice finish with it, its memory is released afterwards.
+    pub fn unload_voice(&self, voice_id: &str) -> bool {
+        let Some(config_path) = self.config_path(voice_id) else {
+            return false;
+        };
+        if self.voices.write().unwrap().remove(voice_id).is_none() {
+            return false;
+        }
+        self.clear_previews_for(voice_id);
+        self.last_used.lock().unwrap().remove(voice_id);
+        self.registered
+            .write()
+            .unwrap()
+            .insert(voice_id.to_string(), config_path);
+        true
+    }
+    /// The config path of the voice, if it was registered or loaded from disk
+    pub fn config_path(&self, voice_id: &str) -> Option<PathBuf> {
+        self.config_paths.read().unwrap().get(voice_id).cloned()
+    }
+    /// Get the voice with the given id, loading it first if it is only registered
+    pub fn get_voice(&self, voice_id: &str) -> SonataResult<Voice> {
+        if let Some(voice) = self.voices.read().unwrap().get(voice_id) {
+            self.touch(voice_id);
+            return Ok(Arc::clone(voice));
+        }
+        let Some(config_path) = self.registered.read().unwrap().get(voice_id).cloned() else {
+            return Err(SonataError::OperationError(format!(
+                "A voice with id `{}` is not loaded",
+                voice_id
+            )));
+        };
+        let voice = from_config_path(&config_path)?;
+        self.registered.write().unwrap().remove(voice_id);
+        self.touch(voice_id);
+        let voice = {
+            let mut voices = self.voices.write().unwrap();
+            // Another thread may have loaded the voice in the meantime
+            Arc::clone(voices.entry(voice_id.to_string()).or_insert(voice))
+        };
+        self.enforce_memory_budget(voice_id)?;
+        Ok(voice)
+    }
+    /// Ids of all loaded and registered voices, sorted
+    pub fn voice_ids(&self) -> Vec<String> {
+        let mut voice_ids = Vec::from_iter(self.voices.read().unwrap().keys().cloned());
+        voice_ids.extend(self.registered.read().unwrap().keys().cloned());
+        voice_ids.sort();
+        voice_ids.dedup();
+        voice_ids
+    }
+    /// Select the id of the voice that best matches the BCP-47 language tag `lang_tag`.
+    ///
+    /// An exact match is preferred, followed by voices of the same language in the
+    /// language's most common region (e.g. `en-GB` falls back to `en_US`), voices
+    /// tagged with the bare language (`en`), then any other voice of that language.
+    /// Ties are broken by voice id.
+    pub fn select_voice_id(&self, lang_tag: &str) -> Option<String> {
+        self.ranked_voices_for_language(lang_tag).into_iter().next()
+    }
+    /// Ids of the voices that can speak `lang_tag`, best match first
+    fn ranked_voices_for_language(&self, lang_tag: &str) -> Vec<String> {
+        let Some(requested) = LanguageTag::parse(lang_tag) else {
+            return Vec::new();
+        };
+        let languages = self.languages.read().unwrap();
+        let mut candidates = Vec::from_iter(languages.iter().filter_map(|(voice_id, code)| {
+            let score = requested.match_score(&LanguageTag::parse(code)?)?;
+            Some((score, voice_id))
+        }));
+        candidates.sort_by(|(score_a, id_a), (score_b, id_b)| {
+            score_b.cmp(score_a).then_with(|| id_a.cmp(id_b))
+        });
+        Vec::from_iter(candidates.into_iter().map(|(_, voice_id)| voice_id.clone()))
+    }
+    /// Get (loading if needed) the voice that best matches the language tag `lang_tag`.
+    /// See [`VoiceManager::select_voice_id`].
+    pub fn select_voice(&self, lang_tag: &str) -> SonataResult<Voice> {
+        match self.select_voice_id(lang_tag) {
+            Some(voice_id) => self.get_voice(&voice_id),
+            None => Err(SonataError::OperationError(format!(
+                "No voice is available for language `{}`",
+                lang_tag
+            ))),
+        }
+    }
+    /// Set the fallbacks tried, in order, by [`VoiceManager::resolve_voice`]
+    pub fn set_fallback_chain(&self, fallback_chain: Vec<VoiceFallback>) {
+        *self.fallback_chain.write().unwrap() = fallback_chain;
+    }
+    pub fn fallback_chain(&self) -> Vec<VoiceFallback> {
+        self.fallback_chain.read().unwrap().clone()
+    }
+    /// Let `name` stand for the voice, speaker and rate of `alias`. Aliases take
+    /// precedence over voice ids, and point to voice ids rather than to other aliases
+    pub fn set_alias(&self, name: impl Into<String>, alias: VoiceAlias) -> SonataResult<()> {
+        let name = name.into();
+        check_alias(&name, &alias)?;
+        self.aliases.write().unwrap().insert(name, alias);
+        Ok(())
+    }
+    pub fn remove_alias(&self, name: &str) -> Option<VoiceAlias> {
+        self.aliases.write().unwrap().remove(name)
+    }
+    pub fn alias(&self, name: &str) -> Option<VoiceAlias> {
+        self.aliases.read().unwrap().get(name).cloned()
+    }
+    /// Names of all aliases, sorted
+    pub fn alias_names(&self) -> Vec<String> {
+        let mut names = Vec::from_iter(self.aliases.read().unwrap().keys().cloned());
+        names.sort();
+        names
+    }
+    /// Add the aliases of the JSON file at `path`, which maps names to aliases, e.g.
+    /// `{"narrator": {"voice": "en_US-hfc_female-medium", "speaker": 0, "rate": 1.1}}`.
+    /// No alias is added if any of them is invalid
+    pub fn load_aliases(&self, path: &Path) -> SonataResult<()> {
+        let file = std::fs::File::open(path).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to open voice aliases `{}`",
+                path.display()
+            ))
+            .caused_by(e)
+        })?;
+        let aliases: HashMap<String, VoiceAlias> = serde_json::from_reader(file).map_err(|e| {
+            SonataError::FailedToLoadResource(format!("Invalid voice aliases `{}`", path.display()))
+                .caused_by(e)
+        })?;
+        for (name, alias) in aliases.iter() {
+            check_alias(name, alias)?;
+        }
+        self.aliases.write().unwrap().extend(aliases);
+        Ok(())
+    }
+    /// Get the requested voice, applying the fallback chain if the voice (or speaker)
+    /// is missing or fails to load.
+    ///
+    /// `voice_id` may be an alias, whose speaker is used unless `speaker` is given.
+    /// Returns the error of the last attempt if every fallback fails.
+    pub fn resolve_voice(
+        &self,
+        voice_id: &str,
+        speaker: Option<i64>,
+    ) -> SonataResult<ResolvedVoice> {
+        match self.alias(voice_id) {
+            Some(alias) => Ok(ResolvedVoice {
+                rate: alias.rate,
+                ..self.resolve_voice_id(&alias.voice_id, speaker.or(alias.speaker))?
+            }),
+            None => self.resolve_voice_id(voice_id, speaker),
+        }
+    }
+    fn resolve_voice_id(
+        &self,
+        voice_id: &str,
+        speaker: Option<i64>,
+    ) -> SonataResult<ResolvedVoice> {
+        let (requested_voice, mut last_error) = match self.get_voice(voice_id) {
+            Ok(voice) => {
+                // Speakers that can't be looked up are missing too, and fall back
+                let speaker_error = match speaker.map(|sid| (sid, voice_has_speaker(&voice, sid))) {
+                    Some((_, Ok(true))) | None => None,
+                    Some((sid, Ok(false))) => Some(SonataError::OperationError(format!(
+                        "No speaker was found with the given id `{}`",
+                        sid
+                    ))),
+                    Some((_, Err(e))) => Some(e),
+                };
+                match speaker_error {
+                    Some(error) => (Some(voice), error),
+                    None => {
+                        return Ok(ResolvedVoice {
+                            voice_id: voice_id.to_string(),
+                            voice,
+                            speaker,
+                            rate: None,
+                            is_fallback: false,
+                        })
+                    }
+                }
+            }
+            Err(e) => (None, e),
+        };
+        let mut tried = vec![voice_id.to_string()];
+        for fallback in self.fallback_chain() {
+            let candidates = match fallback {
+                VoiceFallback::DefaultSpeaker => {
+                    if let Some(ref voice) = requested_voice {
+                        return Ok(ResolvedVoice {
+                            voice_id: voice_id.to_string(),
+                            voice: Arc::clone(voice),
+                            speaker: None,
+                            rate: None,
+                            is_fallback: true,
+                        });
+                    }
+                    continue;
+                }
+                VoiceFallback::SameLanguage => {
+                    let language = self
+                        .languages
+                        .read()
+                        .unwrap()
+                        .get(voice_id)
+                        .cloned()
+                        .or_else(|| language_from_voice_id(voice_id));
+                    match language {
+                        Some(language) => self.ranked_voices_for_language(&language),
+                        None => continue,
+                    }
+                }
+                VoiceFallback::Voice(fallback_voice_id) => vec![fallback_voice_id],
+            };
+            for candidate in candidates {
+                if tried.contains(&candidate) {
+                    continue;
+                }
+                match self.get_voice(&candidate) {
+                    Ok(voice) => {
+                        return Ok(ResolvedVoice {
+                            voice_id: candidate,
+                            voice,
+                            speaker: None,
+                            rate: None,
+                            is_fallback: true,
+                        })
+                    }
+                    Err(e) => last_error = e,
+                }
+                tried.push(candidate);
+            }
+        }
+        Err(last_error)
+    }
+    /// Synthesize a short sample sentence, in the voice's language, using the given speaker.
+    ///
+    /// When `speaker` is `None`, the voice's current speaker is used.
+    /// Previews are cached, so subsequent calls for the same voice and speaker are cheap.
+    pub fn generate_preview(&self, voice_id: &str, speaker: Option<i64>) -> SonataResult<Audio> {
+        let cache_key = (voice_id.to_string(), speaker);
+        if let Some(audio) = self.previews.read().unwrap().get(&cache_key) {
+            return Ok(audio.clone());
+        }
+        let voice = self.get_voice(voice_id)?;
+        let language = voice.get_language()?;
+        let sentence = preview_sentence(language.as_deref());
+
+        let fallback_config = voice.get_fallback_synthesis_config()?;
+        let Some(fallback_config) = fallback_config.downcast_ref::<PiperSynthesisConfig>() else {
+            return Err(SonataError::OperationError(format!(
+                "Voice `{}` does not support generating previews",
+                voice_id
+            )));
+        };
+        let mut synth_config = fallback_config.clone();
+        if let Some(sid) = speaker {
+            if !voice_has_speaker(&voice, sid)? {
+                return Err(SonataError::OperationError(format!(
+                    "No speaker was found with the given id `{}`",
+                    sid
+                )));
+            }
+            synth_config.speaker = Some(sid);
+        }
+
+        let sample_rate = voice.audio_output_info()?.sample_rate;
+        let mut samples = AudioSamples::default();
+        let mut inference_ms = 0f32;
+        for phonemes in voice.phonemize_text(sentence)?.to_vec() {
+            let audio = voice.speak_one_sentence_with_config(phonemes, &synth_config)?;
+            inference_ms += audio.inference_ms().unwrap_or_default();
+            samples.merge(audio.samples);
+        }
+        let preview = Audio::new(samples, sample_rate, Some(inference_ms));
+        self.previews
+            .write()
+            .unwrap()
+            .insert(cache_key, preview.clone());
+        Ok(preview)
+    }
+    /// Speak `text` using the voice `voice_id`, switching voices for spans in other languages.
+    ///
+    /// The text is split using [`segment_by_language`]. Each foreign span is spoken by
+    /// the installed voice that best matches its language; if there is none, the span is
+    /// phonemized using the matching espeak-ng language and spoken by the primary voice.
+    /// The audio is concatenated (and resampled if needed) at the primary voice's sample rate.
+    pub fn speak_mixed_language(&self, voice_id: &str, text: &str) -> SonataResult<Audio> {
+        let voice = self.get_voice(voice_id)?;
+        let primary_language = voice
+            .get_language()?
+            .or_else(|| language_from_voice_id(voice_id))
+            .unwrap_or_default();
+        let sample_rate = voice.audio_output_info()?.sample_rate;
+        let mut samples = AudioSamples::default();
+        let mut inference_ms = 0f32;
+        for span in segment_by_language(text, &primary_language) {
+            let span_voice = match span.language {
+                Some(ref language) => self.select_voice(language).ok(),
+                None => Some(Arc::clone(&voice)),
+            };
+            let (span_voice, phonemes) = match (span_voice, span.language) {
+                (Some(span_voice), _) => {
+                    let phonemes = span_voice.phonemize_text(&span.text)?.to_vec();
+                    (span_voice, phonemes)
+                }
+                (None, language) => {
+                    let language = espeak_voice_for_language(&language.unwrap_or_default());
+                    let phonemes = espeak_phonemize(&span.text, &language)?.to_vec();
+                    (Arc::clone(&voice), phonemes)
+                }
+            };
+            let span_sample_rate = span_voice.audio_output_info()?.sample_rate;
+            for sentence_phonemes in phonemes {
+                let audio = span_voice.speak_one_sentence(sentence_phonemes)?;
+                inference_ms += audio.inference_ms().unwrap_or_default();
+                samples.merge(audio.samples.resample(span_sample_rate, sample_rate));
+            }
+        }
+        Ok(Audio::new(samples, sample_rate, Some(inference_ms)))
+    }
+    /// Approximate memory held by the loaded voice `voice_id`, including its cached
+    /// previews. `None` if the voice is not loaded.
+    ///
+    /// Phonemes are cached for all voices together, and are not included.
+    pub fn memory_usage(&self, voice_id: &str) -> SonataResult<Option<MemoryUsage>> {
+        let Some(voice) = self.voices.read().unwrap().get(voice_id).cloned() else {
+            return Ok(None);
+        };
+        let mut usage = voice.memory_usage()?;
+        usage.cache_bytes += self
+            .previews
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((cached_voice_id, _), _)| cached_voice_id == voice_id)
+            .map(|(_, audio)| (audio.len() * std::mem::size_of::<f32>()) as u64)
+            .sum::<u64>();
+        Ok(Some(usage))
+    }
+    /// Approximate memory held by each loaded voice, by voice id
+    pub fn memory_usages(&self) -> SonataResult<Vec<(String, MemoryUsage)>> {
+        let mut voice_ids = Vec::from_iter(self.voices.read().unwrap().keys().cloned());
+        voice_ids.sort();
+        let mut usages = Vec::with_capacity(voice_ids.len());
+        for voice_id in voice_ids {
+            if let Some(usage) = self.memory_usage(&voice_id)? {
+                usages.push((voice_id, usage));
+            }
+        }
+        Ok(usages)
+    }
+    /// Keep the memory held by loaded voices under `budget_bytes`, as reported by
+    /// [`Self::memory_usage`], or remove the budget with `None`.
+    ///
+    /// When loading a voice takes the loaded voices over budget, the least recently used
+    /// ones are unloaded (see [`Self::unload_voice`]) until they fit. The voice just
+    /// loaded is kept, even if it does not fit by itself. Voices added with
+    /// [`Self::add_voice`] can't be loaded again, and are never unloaded.
+    ///
+    /// Returns the ids of the voices unloaded to fit the new budget.
+    pub fn set_memory_budget(&self, budget_bytes: Option<u64>) -> SonataResult<Vec<String>> {
+        *self.memory_budget.write().unwrap() = budget_bytes;
+        self.enforce_memory_budget("")
+    }
+    pub fn memory_budget(&self) -> Option<u64> {
+        *self.memory_budget.read().unwrap()
+    }
+    /// Unload the least recently used voices other than `keep_voice_id` while the
+    /// loaded voices are over budget
+    fn enforce_memory_budget(&self, keep_voice_id: &str) -> SonataResult<Vec<String>> {
+        let Some(budget_bytes) = self.memory_budget() else {
+            return Ok(Vec::new());
+        };
+        let usages = self.memory_usages()?;
+        let mut total_bytes: u64 = usages.iter().map(|(_, usage)| usage.total_bytes()).sum();
+        let mut candidates = {
+            let config_paths = self.config_paths.read().unwrap();
+            let last_used = self.last_used.lock().unwrap();
+            Vec::from_iter(
+                usages
+                    .into_iter()
+                    .filter(|(voice_id, _)| {
+                        voice_id != keep_voice_id && config_paths.contains_key(voice_id)
+                    })
+                    .map(|(voice_id, usage)| {
+                        let tick = last_used.get(&voice_id).copied().unwrap_or_default();
+                        (tick, voice_id, usage)
+                    }),
+            )
+        };
+        candidates.sort_by_key(|(tick, _, _)| *tick);
+        let mut unloaded = Vec::new();
+        for (_, voice_id, usage) in candidates {
+            if total_bytes <= budget_bytes {
+                break;
+            }
+            if self.unload_voice(&voice_id) {
+                total_bytes = total_bytes.saturating_sub(usage.total_bytes());
+                unloaded.push(voice_id);
+            }
+        }
+        Ok(unloaded)
+    }
+    fn touch(&self, voice_id: &str) {
+        let tick = self.ticks.fetch_add(1, Ordering::Relaxed);
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(voice_id.to_string(), tick);
+    }
+    pub fn clear_previews(&self) {
+        self.previews.write().unwrap().clear();
+    }
+    fn clear_previews_for(&self, voice_id: &str) {
+        self.previews
+            .write()
+            .unwrap()
+            .retain(|(cached_voice_id, _), _| cached_voice_id != voice_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_sentence_lookup() {
+        assert_eq!(
+            preview_sentence(Some("de_DE")),
+            "Dies ist eine Hörprobe meiner Stimme."
+        );
+        assert_eq!(
+            preview_sentence(Some("fr-fr")),
+            "Voici un échantillon de ma voix."
+        );
+        assert_eq!(preview_sentence(Some("xx")), DEFAULT_PREVIEW_SENTENCE);
+        assert_eq!(preview_sentence(None), DEFAULT_PREVIEW_SENTENCE);
+    }
+
+    #[test]
+    fn test_voice_id_from_config_path() {
+        let voice_id = |path: &str| voice_id_from_config_path(Path::new(path));
+        assert_eq!(
+            voice_id("voices/en_US-lessac-medium.onnx.json").as_deref(),
+            Some("en_US-lessac-medium")
+        );
+        assert_eq!(
+            voice_id("ar_JO-kareem.safetensors.json").as_deref(),
+            Some("ar_JO-kareem")
+        );
+        assert_eq!(voice_id("voice/config.json").as_deref(), Some("config"));
+    }
+
+    #[test]
+    fn test_language_tag_matching() {
+        let tag = |t: &str| LanguageTag::parse(t).unwrap();
+        let requested = tag("en-GB");
+        assert_eq!(requested.region(), Some("gb"));
+        assert!(requested.match_score(&tag("en_GB")) > requested.match_score(&tag("en_US")));
+        assert!(requested.match_score(&tag("en_US")) > requested.match_score(&tag("en")));
+        assert!(requested.match_score(&tag("en")) > requested.match_score(&tag("en_AU")));
+        assert_eq!(requested.match_score(&tag("fr_FR")), None);
+        let requested = tag("zh-Hant-TW");
+        assert!(requested.match_score(&tag("zh-Hant")) > requested.match_score(&tag("zh_CN")));
+    }
+
+    #[test]
+    fn test_discover_voice_configs() {
+        let root = std::env::temp_dir().join(format!("sonata-voices-{}", std::process::id()));
+        let nested = root.join("en").join("en_US");
+        std::fs::create_dir_all(&nested).unwrap();
+        for path in [
+            root.join("de_DE-thorsten-medium.onnx.json"),
+            root.join("de_DE-thorsten-medium.onnx"),
+            nested.join("en_US-lessac-medium.onnx.json"),
+            nested.join("MODEL_CARD"),
+        ] {
+            std::fs::write(path, b"").unwrap();
+        }
+        let configs = discover_voice_configs(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            configs,
+            vec![
+                root.join("de_DE-thorsten-medium.onnx.json"),
+                nested.join("en_US-lessac-medium.onnx.json"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_voice_aliases() {
+        let path = std::env::temp_dir().join(format!("sonata-aliases-{}.json", std::process::id()));
+        let manager = VoiceManager::new();
+        std::fs::write(
+            &path,
+            r#"{"narrator": {"voice": "en_US-hfc_female-medium", "speaker": 0, "rate": 1.1}}"#,
+        )
+        .unwrap();
+        let loaded = manager.load_aliases(&path);
+        std::fs::write(
+            &path,
+            r#"{"a": {"voice": "a"}, "b": {"voice": "b", "rate": 0}}"#,
+        )
+        .unwrap();
+        let invalid = manager.load_aliases(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        assert_eq!(
+            manager.alias("narrator"),
+            Some(
+                VoiceAlias::new("en_US-hfc_female-medium")
+                    .with_speaker(0)
+                    .with_rate(1.1)
+            )
+        );
+        assert!(invalid.is_err());
+        assert_eq!(manager.alias_names(), ["narrator"]);
+        // Rates are rate multipliers
+        for rate in [0.4, 3.5, f32::NAN] {
+            let alias = VoiceAlias::new("en_US-hfc_female-medium").with_rate(rate);
+            assert!(manager.set_alias("fast", alias).is_err(), "{}", rate);
+        }
+        // Aliases resolve to their voice, which is not installed here
+        let Err(error) = manager.resolve_voice("narrator", None) else {
+            panic!("An alias of a missing voice was resolved");
+        };
+        assert!(
+            error.to_string().contains("`en_US-hfc_female-medium`"),
+            "{}",
+            error
+        );
+        assert!(manager.remove_alias("narrator").is_some());
+        assert!(manager.alias_names().is_empty());
+    }
+
+    /// A voice holding `model_bytes` of memory, which can't speak
+    struct SizedModel {
+        model_bytes: u64,
+        /// Whether looking up its speakers fails
+        failing_speakers: bool,
+    }
+
+    impl SizedModel {
+        fn new(model_bytes: u64) -> Self {
+            Self {
+                model_bytes,
+                failing_speakers: false,
+            }
+        }
+    }
+
+    impl SonataModel for SizedModel {
+        fn audio_output_info(&self) -> SonataResult<sonata_core::AudioInfo> {
+            Err(SonataError::with_message("Not a real voice"))
+        }
+        fn phonemize_text(&self, _text: &str) -> SonataResult<sonata_core::Phonemes> {
+            Err(SonataError::with_message("Not a real voice"))
+        }
+        fn speak_batch(&self, _phoneme_batches: Vec<String>) -> SonataResult<Vec<Audio>> {
+            Err(SonataError::with_message("Not a real voice"))
+        }
+        fn speak_one_sentence(&self, _phonemes: String) -> sonata_core::SonataAudioResult {
+            Err(SonataError::with_message("Not a real voice"))
+        }
+        fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn std::any::Any>> {
+            Ok(Box::new(()))
+        }
+        fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn std::any::Any>> {
+            Ok(Box::new(()))
+        }
+        fn set_fallback_synthesis_config(
+            &self,
+            _synthesis_config: &dyn std::any::Any,
+        ) -> SonataResult<()> {
+            Ok(())
+        }
+        fn memory_usage(&self) -> SonataResult<MemoryUsage> {
+            Ok(MemoryUsage {
+                model_bytes: self.model_bytes,
+                ..Default::default()
+            })
+        }
+        fn get_speakers(&self) -> SonataResult<Option<&HashMap<i64, String>>> {
+            if self.failing_speakers {
+                return Err(SonataError::with_message("Speakers unavailable"));
+            }
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_missing_speaker_falls_back() {
+        let manager = VoiceManager::new();
+        manager.add_voice(
+            "broken",
+            Arc::new(SizedModel {
+                failing_speakers: true,
+                ..SizedModel::new(0)
+            }),
+        );
+        manager.add_voice("single", Arc::new(SizedModel::new(0)));
+        manager.set_fallback_chain(vec![VoiceFallback::DefaultSpeaker]);
+        for voice_id in ["broken", "single"] {
+            let resolved = manager.resolve_voice(voice_id, Some(3)).unwrap();
+            assert_eq!(resolved.voice_id, voice_id);
+            assert_eq!(resolved.speaker, None);
+            assert!(resolved.is_fallback);
+        }
+        manager.set_fallback_chain(Vec::new());
+        assert!(manager.resolve_voice("broken", Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let manager = VoiceManager::new();
+        for (voice_id, model_bytes) in [("a", 100), ("b", 200), ("c", 300)] {
+            manager.add_voice(voice_id, Arc::new(SizedModel::new(model_bytes)));
+            // As if loaded from disk, so that they can be unloaded
+            manager.config_paths.write().unwrap().insert(
+                voice_id.to_string(),
+                PathBuf::from(format!("{}.onnx.json", voice_id)),
+            );
+        }
+        manager.add_voice("pinned", Arc::new(SizedModel::new(1000)));
+        let audio = Audio::new(vec![0.0; 25].into(), 16000, None);
+        manager
+            .previews
+            .write()
+            .unwrap()
+            .insert(("a".to_string(), None), audio);
+        assert_eq!(
+            manager.memory_usage("a").unwrap(),
+            Some(MemoryUsage {
+                model_bytes: 100,
+                session_bytes: 0,
+                cache_bytes: 100,
+            })
+        );
+        assert_eq!(manager.memory_usage("missing").unwrap(), None);
+        assert_eq!(manager.memory_usages().unwrap().len(), 4);
+
+        // `a` was used last, `b` is the least recently used
+        manager.get_voice("a").unwrap();
+        let unloaded = manager.set_memory_budget(Some(1500)).unwrap();
+        assert_eq!(unloaded, ["b"]);
+        assert!(!manager.is_loaded("b"));
+        assert!(manager.voice_ids().contains(&"b".to_string()));
+        // Voices that can't be loaded again are kept, even over budget
+        let unloaded = manager.set_memory_budget(Some(0)).unwrap();
+        assert_eq!(unloaded, ["c", "a"]);
+        assert!(manager.is_loaded("pinned"));
+        assert!(manager.previews.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,486 @@
+//! A cache of synthesized utterances.
+//!
+//! Entries are keyed by a hash of the text, the model's synthesis cache key
+//! (voice, speaker, scales...) and the audio output config, and evicted in
+//! least-recently-used order once the cache exceeds its maximum size.
+//!
+//! Optionally, entries are also persisted to a cache directory (one file per entry,
+//! named after the key), so they survive restarts. The directory is garbage collected
+//! by removing the least recently used files once it exceeds its maximum size.
+
+use crate::AudioOutputConfig;
+use lru::LruCache;
+use sonata_core::{Audio, AudioSamples, SonataError, SonataResult};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use xxhash_rust::xxh3::xxh3_128;
+
+const CACHE_FILE_EXTENSION: &str = "sonata-audio";
+const CACHE_FILE_MAGIC: &[u8; 4] = b"SNAC";
+const CACHE_FILE_VERSION: u32 = 1;
+
+/// Hit/miss statistics of an [`AudioCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits served from the disk cache (included in `hits`)
+    pub disk_hits: u64,
+    pub len: usize,
+    pub size_bytes: usize,
+    pub max_size_bytes: usize,
+    pub disk_size_bytes: u64,
+    pub max_disk_size_bytes: u64,
+}
+
+struct CacheEntries {
+    entries: LruCache<u128, Vec<Audio>>,
+    size_bytes: usize,
+    hits: u64,
+    misses: u64,
+    disk_hits: u64,
+}
+
+struct DiskCache {
+    cache_dir: PathBuf,
+    max_size_bytes: u64,
+    size_bytes: Mutex<u64>,
+}
+
+pub struct AudioCache {
+    max_size_bytes: usize,
+    inner: Mutex<CacheEntries>,
+    disk_cache: Option<DiskCache>,
+}
+
+impl AudioCache {
+    /// Create a cache that holds at most `max_size_bytes` of audio samples in memory
+    pub fn new(max_size_bytes: usize) -> Self {
+        Self {
+            max_size_bytes,
+            inner: Mutex::new(CacheEntries {
+                entries: LruCache::unbounded(),
+                size_bytes: 0,
+                hits: 0,
+                misses: 0,
+                disk_hits: 0,
+            }),
+            disk_cache: None,
+        }
+    }
+    /// Also persist entries under `cache_dir`, keeping at most `max_disk_size_bytes` of files.
+    ///
+    /// The directory is created if it does not exist. Entries written by previous runs are reused.
+    pub fn with_disk_cache(
+        mut self,
+        cache_dir: impl Into<PathBuf>,
+        max_disk_size_bytes: u64,
+    ) -> SonataResult<Self> {
+        let cache_dir = cache_dir.into();
+        let size_bytes = fs::create_dir_all(&cache_dir)
+            .and_then(|_| cache_files(&cache_dir))
+            .map_err(|e| {
+                SonataError::FailedToLoadResource(format!(
+                    "Failed to open audio cache directory `{}`",
+                    cache_dir.display()
+                ))
+                .caused_by(e)
+            })?
+            .iter()
+            .map(|(_, size, _)| size)
+            .sum();
+        let disk_cache = DiskCache {
+            cache_dir,
+            max_size_bytes: max_disk_size_bytes,
+            size_bytes: Mutex::new(size_bytes),
+        };
+        disk_cache.collect_garbage();
+        self.disk_cache = Some(disk_cache);
+        Ok(self)
+    }
+    pub(crate) fn key(
+        text: &str,
+        synthesis_cache_key: &str,
+        output_config: Option<&AudioOutputConfig>,
+    ) -> u128 {
+        let mut key_bytes = Vec::with_capacity(text.len() + synthesis_cache_key.len() + 16);
+        for part in [text, synthesis_cache_key] {
+            key_bytes.extend_from_slice(part.as_bytes());
+            key_bytes.push(0);
+        }
+        if let Some(config) = output_config {
+            for value in [config.rate, config.volume, config.pitch] {
+                key_bytes
+                    .extend_from_slice(&value.map(u16::from).unwrap_or(u16::MAX).to_le_bytes());
+            }
+            key_bytes.extend_from_slice(
+                &config
+                    .appended_silence_ms
+                    .map(u64::from)
+                    .unwrap_or(u64::MAX)
+                    .to_le_bytes(),
+            );
+            for value in [config.time_stretch, config.gain_db, config.rate_multiplier] {
+                key_bytes.extend_from_slice(&value.unwrap_or(f32::NAN).to_le_bytes());
+            }
+            key_bytes.push(config.trim_leading_silence as u8);
+            key_bytes.push(config.trim_trailing_silence as u8);
+        }
+        xxh3_128(&key_bytes)
+    }
+    pub(crate) fn get(&self, key: u128) -> Option<Vec<Audio>> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(audio) = inner.entries.get(&key).cloned() {
+            inner.hits += 1;
+            return Some(audio);
+        }
+        drop(inner);
+        let from_disk = self.disk_cache.as_ref().and_then(|disk| disk.read(key));
+        let mut inner = self.inner.lock().unwrap();
+        match from_disk {
+            Some(audio) => {
+                inner.hits += 1;
+                inner.disk_hits += 1;
+                drop(inner);
+                self.insert_in_memory(key, audio.clone());
+                Some(audio)
+            }
+            None => {
+                inner.misses += 1;
+                None
+            }
+        }
+    }
+    pub(crate) fn insert(&self, key: u128, audio: Vec<Audio>) {
+        if let Some(ref disk_cache) = self.disk_cache {
+            // The cache is best-effort, failing to persist an entry is not an error
+            disk_cache.write(key, &audio).ok();
+        }
+        self.insert_in_memory(key, audio);
+    }
+    fn insert_in_memory(&self, key: u128, audio: Vec<Audio>) {
+        let entry_size = size_in_bytes(&audio);
+        if entry_size > self.max_size_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(replaced) = inner.entries.put(key, audio) {
+            inner.size_bytes -= size_in_bytes(&replaced);
+        }
+        inner.size_bytes += entry_size;
+        while inner.size_bytes > self.max_size_bytes {
+            let Some((_, evicted)) = inner.entries.pop_lru() else {
+                break;
+            };
+            inner.size_bytes -= size_in_bytes(&evicted);
+        }
+    }
+    /// Remove all cached audio (including the disk cache), and reset the statistics
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.size_bytes = 0;
+        inner.hits = 0;
+        inner.misses = 0;
+        inner.disk_hits = 0;
+        if let Some(ref disk_cache) = self.disk_cache {
+            disk_cache.clear();
+        }
+    }
+    pub fn stats(&self) -> AudioCacheStats {
+        let inner = self.inner.lock().unwrap();
+        let (disk_size_bytes, max_disk_size_bytes) = self
+            .disk_cache
+            .as_ref()
+            .map(|disk| (*disk.size_bytes.lock().unwrap(), disk.max_size_bytes))
+            .unwrap_or_default();
+        AudioCacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            disk_hits: inner.disk_hits,
+            len: inner.entries.len(),
+            size_bytes: inner.size_bytes,
+            max_size_bytes: self.max_size_bytes,
+            disk_size_bytes,
+            max_disk_size_bytes,
+        }
+    }
+}
+
+impl DiskCache {
+    fn entry_path(&self, key: u128) -> PathBuf {
+        self.cache_dir
+            .join(format!("{:032x}.{}", key, CACHE_FILE_EXTENSION))
+    }
+    fn read(&self, key: u128) -> Option<Vec<Audio>> {
+        let path = self.entry_path(key);
+        let file = File::options().read(true).write(true).open(&path).ok()?;
+        // Bump the modification time, which is used to find the least recently used entries
+        file.set_modified(SystemTime::now()).ok();
+        let len = file.metadata().ok()?.len();
+        match read_audio(&mut BufReader::new(file), len) {
+            Ok(audio) => Some(audio),
+            Err(_) => {
+                // A corrupt or truncated entry would never be read, remove it
+                let mut size_bytes = self.size_bytes.lock().unwrap();
+                if fs::remove_file(&path).is_ok() {
+                    *size_bytes = size_bytes.saturating_sub(len);
+                }
+                None
+            }
+        }
+    }
+    fn write(&self, key: u128, audio: &[Audio]) -> io::Result<()> {
+        let path = self.entry_path(key);
+        // Write to a temporary file first, so that readers never see partial entries
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write_audio(&mut writer, audio)?;
+        writer.into_inner()?.sync_all()?;
+        let replaced_size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        fs::rename(&tmp_path, &path)?;
+        let entry_size = fs::metadata(&path)?.len();
+        let exceeds_max_size = {
+            let mut size_bytes = self.size_bytes.lock().unwrap();
+            *size_bytes = (*size_bytes + entry_size).saturating_sub(replaced_size);
+            *size_bytes > self.max_size_bytes
+        };
+        if exceeds_max_size {
+            self.collect_garbage();
+        }
+        Ok(())
+    }
+    /// Remove the least recently used entries until the cache fits its maximum size
+    fn collect_garbage(&self) {
+        let mut size_bytes = self.size_bytes.lock().unwrap();
+        let Ok(mut files) = cache_files(&self.cache_dir) else {
+            return;
+        };
+        *size_bytes = files.iter().map(|(_, size, _)| size).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in files {
+            if *size_bytes <= self.max_size_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                *size_bytes -= size;
+            }
+        }
+    }
+    fn clear(&self) {
+        let mut size_bytes = self.size_bytes.lock().unwrap();
+        for (path, size, _) in cache_files(&self.cache_dir).unwrap_or_default() {
+            if fs::remove_file(&path).is_ok() {
+                *size_bytes = size_bytes.saturating_sub(size);
+            }
+        }
+    }
+}
+
+/// Cache entries in `cache_dir`, with their size and modification time
+fn cache_files(cache_dir: &Path) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(cache_dir)?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(CACHE_FILE_EXTENSION) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        files.push((path, metadata.len(), modified));
+    }
+    Ok(files)
+}
+
+fn write_audio(writer: &mut impl Write, audio: &[Audio]) -> io::Result<()> {
+    writer.write_all(CACHE_FILE_MAGIC)?;
+    writer.write_all(&CACHE_FILE_VERSION.to_le_bytes())?;
+    writer.write_all(&(audio.len() as u32).to_le_bytes())?;
+    for sentence in audio {
+        writer.write_all(&(sentence.info.sample_rate as u32).to_le_bytes())?;
+        writer.write_all(&(sentence.samples.len() as u64).to_le_bytes())?;
+        for sample in sentence.samples.as_slice() {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+/// Read an entry of `len` bytes, whose counts are checked against its length before
+/// anything is allocated
+fn read_audio(reader: &mut impl Read, len: u64) -> io::Result<Vec<Audio>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_FILE_MAGIC || read_u32(reader)? != CACHE_FILE_VERSION {
+        return Err(invalid("Not an audio cache entry"));
+    }
+    // Magic, version and number of sentences
+    let mut remaining = len.saturating_sub(12);
+    let num_sentences = read_u32(reader)? as u64;
+    // Each sentence has a sample rate and a number of samples
+    if num_sentences * 12 > remaining {
+        return Err(invalid("Too many sentences for the size of the entry"));
+    }
+    let mut audio = Vec::with_capacity(num_sentences as usize);
+    for _ in 0..num_sentences {
+        let sample_rate = read_u32(reader)? as usize;
+        let mut num_samples = [0u8; 8];
+        reader.read_exact(&mut num_samples)?;
+        remaining -= 12;
+        let size = u64::from_le_bytes(num_samples)
+            .checked_mul(4)
+            .filter(|size| *size <= remaining)
+            .ok_or_else(|| invalid("Too many samples for the size of the entry"))?;
+        remaining -= size;
+        let mut bytes = vec![0u8; size as usize];
+        reader.read_exact(&mut bytes)?;
+        let samples = Vec::from_iter(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        audio.push(Audio::new(AudioSamples::new(samples), sample_rate, None));
+    }
+    Ok(audio)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn size_in_bytes(audio: &[Audio]) -> usize {
+    audio
+        .iter()
+        .map(|a| a.len() * std::mem::size_of::<f32>())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(num_samples: usize) -> Vec<Audio> {
+        vec![Audio::new(
+            AudioSamples::new(vec![0.5; num_samples]),
+            22050,
+            None,
+        )]
+    }
+
+    #[test]
+    fn test_size_based_eviction() {
+        let cache = AudioCache::new(100 * 4);
+        let key_a = AudioCache::key("a", "voice", None);
+        let key_b = AudioCache::key("b", "voice", None);
+        let key_c = AudioCache::key("c", "voice", None);
+        cache.insert(key_a, audio(40));
+        cache.insert(key_b, audio(40));
+        assert!(cache.get(key_a).is_some());
+        // `b` is the least recently used entry
+        cache.insert(key_c, audio(40));
+        assert!(cache.get(key_b).is_none());
+        assert!(cache.get(key_c).is_some());
+        // Entries larger than the cache are not stored
+        cache.insert(key_b, audio(101));
+        assert!(cache.get(key_b).is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (2, 2, 2));
+        assert_eq!(stats.size_bytes, 80 * 4);
+    }
+
+    #[test]
+    fn test_key_depends_on_output_config() {
+        let output_config = AudioOutputConfig {
+            rate: Some(70),
+            volume: None,
+            pitch: None,
+            appended_silence_ms: None,
+            time_stretch: None,
+            gain_db: None,
+            rate_multiplier: None,
+            trim_leading_silence: false,
+            trim_trailing_silence: false,
+        };
+        assert_ne!(
+            AudioCache::key("text", "voice", None),
+            AudioCache::key("text", "voice", Some(&output_config))
+        );
+        let stretched = AudioOutputConfig {
+            time_stretch: Some(1.5),
+            ..output_config.clone()
+        };
+        assert_ne!(
+            AudioCache::key("text", "voice", Some(&output_config)),
+            AudioCache::key("text", "voice", Some(&stretched))
+        );
+        let trimmed = AudioOutputConfig {
+            trim_trailing_silence: true,
+            ..output_config.clone()
+        };
+        assert_ne!(
+            AudioCache::key("text", "voice", Some(&output_config)),
+            AudioCache::key("text", "voice", Some(&trimmed))
+        );
+        assert_ne!(
+            AudioCache::key("text", "voice:0", None),
+            AudioCache::key("text", "voice:1", None)
+        );
+    }
+
+    #[test]
+    fn test_disk_cache() -> SonataResult<()> {
+        let cache_dir =
+            std::env::temp_dir().join(format!("sonata-audio-cache-{}", std::process::id()));
+        let key = AudioCache::key("text", "voice", None);
+        let cache = AudioCache::new(1024).with_disk_cache(&cache_dir, 1024)?;
+        cache.insert(key, audio(40));
+
+        // A new cache (e.g. after a restart) finds the entry on disk
+        let cache = AudioCache::new(1024).with_disk_cache(&cache_dir, 1024)?;
+        let cached = cache.get(key).expect("entry should be persisted");
+        assert_eq!(
+            cached[0].samples.as_slice(),
+            audio(40)[0].samples.as_slice()
+        );
+        assert_eq!(cache.stats().disk_hits, 1);
+
+        // Entries are garbage collected once the directory exceeds its size
+        cache.insert(AudioCache::key("other text", "voice", None), audio(200));
+        cache.insert(AudioCache::key("more text", "voice", None), audio(200));
+        assert!(cache.stats().disk_size_bytes <= 1024);
+
+        cache.clear();
+        assert_eq!(cache.stats().disk_size_bytes, 0);
+        fs::remove_dir_all(&cache_dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_disk_entry() -> SonataResult<()> {
+        let cache_dir =
+            std::env::temp_dir().join(format!("sonata-corrupt-cache-{}", std::process::id()));
+        let key = AudioCache::key("text", "voice", None);
+        let cache = AudioCache::new(1024).with_disk_cache(&cache_dir, 4096)?;
+        cache.insert(key, audio(40));
+        let path = cache.disk_cache.as_ref().unwrap().entry_path(key);
+        // A single sentence claiming far more samples than the entry holds
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let cache = AudioCache::new(1024).with_disk_cache(&cache_dir, 4096)?;
+        assert!(cache.get(key).is_none());
+        assert!(!path.exists());
+        assert_eq!(cache.stats().disk_size_bytes, 0);
+        fs::remove_dir_all(&cache_dir).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Configuring a [`SonataSpeechSynthesizer`] in one go.
+//!
+//! ```ignore
+//! let synth = SonataSpeechSynthesizer::builder()
+//!     .voice(sonata_piper::from_config_path(&config_path)?)
+//!     .text_normalizer(Arc::new(TextNormalizer::standard()))
+//!     .sample_format(WaveSampleFormat::S24)
+//!     .rate_multiplier(1.2)
+//!     .num_threads(4)
+//!     .build()?;
+//! ```
+
+use crate::{
+    AudioCache, ChannelLayout, ErrorRecovery, InputSanitizer, ReplacementDictionary,
+    SentenceCallback, SonataSpeechSynthesizer, SplitPolicy, TextNormalizer, Watermark,
+};
+use rayon::ThreadPoolBuilder;
+use sonata_core::{Audio, ChunkOverlap, SonataError, SonataModel, SonataResult, WaveSampleFormat};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options of a [`SonataSpeechSynthesizer`], see [`SonataSpeechSynthesizer::builder`].
+///
+/// Options left unset keep the defaults of [`SonataSpeechSynthesizer::new`].
+#[derive(Default)]
+#[must_use]
+pub struct SonataSpeechSynthesizerBuilder {
+    voice: Option<Arc<dyn SonataModel + Send + Sync>>,
+    audio_cache: Option<Arc<AudioCache>>,
+    input_sanitizer: Option<InputSanitizer>,
+    replacements: Option<Arc<ReplacementDictionary>>,
+    text_normalizer: Option<Arc<TextNormalizer>>,
+    stream_buffer_depth: Option<usize>,
+    error_recovery: Option<ErrorRecovery>,
+    sample_format: Option<WaveSampleFormat>,
+    channel_layout: Option<ChannelLayout>,
+    memory_limit: Option<usize>,
+    rate_multiplier: Option<f32>,
+    num_threads: Option<usize>,
+    chunk_overlap: Option<ChunkOverlap>,
+    latency_target: Option<Duration>,
+    decoder_batch_size: Option<usize>,
+    on_sentence: Option<SentenceCallback>,
+    watermark: Option<Watermark>,
+    split_policy: Option<SplitPolicy>,
+}
+
+impl SonataSpeechSynthesizerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The voice to speak with. Required
+    pub fn voice(mut self, voice: Arc<dyn SonataModel + Send + Sync>) -> Self {
+        self.voice = Some(voice);
+        self
+    }
+    /// The voice a voice id or alias resolved to, and the alias' rate as the
+    /// [`Self::rate_multiplier`] if it has one. The speaker is left to the caller, as
+    /// the voice may be shared
+    #[cfg(feature = "piper")]
+    pub fn resolved_voice(mut self, resolved: sonata_piper::voice_manager::ResolvedVoice) -> Self {
+        self.voice = Some(resolved.voice);
+        if resolved.rate.is_some() {
+            self.rate_multiplier = resolved.rate;
+        }
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_audio_cache`]
+    pub fn audio_cache(mut self, audio_cache: Arc<AudioCache>) -> Self {
+        self.audio_cache = Some(audio_cache);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_input_sanitizer`]
+    pub fn input_sanitizer(mut self, input_sanitizer: InputSanitizer) -> Self {
+        self.input_sanitizer = Some(input_sanitizer);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_replacements`]
+    pub fn replacements(mut self, replacements: Arc<ReplacementDictionary>) -> Self {
+        self.replacements = Some(replacements);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_text_normalizer`]
+    pub fn text_normalizer(mut self, text_normalizer: Arc<TextNormalizer>) -> Self {
+        self.text_normalizer = Some(text_normalizer);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_stream_buffer_depth`]
+    pub fn stream_buffer_depth(mut self, depth: usize) -> Self {
+        self.stream_buffer_depth = Some(depth);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_error_recovery`]
+    pub fn error_recovery(mut self, error_recovery: ErrorRecovery) -> Self {
+        self.error_recovery = Some(error_recovery);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_sample_format`]
+    pub fn sample_format(mut self, sample_format: WaveSampleFormat) -> Self {
+        self.sample_format = Some(sample_format);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_channel_layout`]
+    pub fn channel_layout(mut self, channel_layout: ChannelLayout) -> Self {
+        self.channel_layout = Some(channel_layout);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_memory_limit`]
+    pub fn memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::set_rate_multiplier`]. Out of range rates fail
+    /// the build
+    pub fn rate_multiplier(mut self, rate: f32) -> Self {
+        self.rate_multiplier = Some(rate);
+        self
+    }
+    /// Synthesize the sentences of an utterance on a pool of `num_threads` threads of
+    /// its own, instead of the pools shared by all synthesizers. Realtime streams
+    /// always run on a thread of their own
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = Some(num_threads.max(1));
+        self
+    }
+    /// See [`SonataModel::set_chunk_overlap`]. Fails the build if the voice does not
+    /// support streaming
+    pub fn chunk_overlap(mut self, chunk_overlap: ChunkOverlap) -> Self {
+        self.chunk_overlap = Some(chunk_overlap);
+        self
+    }
+    /// See [`SonataModel::set_latency_target`]. Fails the build if the voice does not
+    /// support streaming
+    pub fn latency_target(mut self, latency_target: Duration) -> Self {
+        self.latency_target = Some(latency_target);
+        self
+    }
+    /// See [`SonataModel::set_decoder_batch_size`]. Fails the build if the voice does
+    /// not support streaming
+    pub fn decoder_batch_size(mut self, batch_size: usize) -> Self {
+        self.decoder_batch_size = Some(batch_size);
+        self
+    }
+    /// Call `on_sentence` with the index and audio of each sentence once it is
+    /// synthesized, e.g. to report progress. Applies to lazy and parallel synthesis
+    /// (and so `synthesize_to_file`), in the order sentences complete, which is not
+    /// their order in parallel synthesis
+    pub fn on_sentence(
+        mut self,
+        on_sentence: impl Fn(usize, &Audio) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_sentence = Some(Arc::new(on_sentence));
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_watermark`]
+    pub fn watermark(mut self, watermark: Watermark) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+    /// See [`SonataSpeechSynthesizer::with_split_policy`]
+    pub fn split_policy(mut self, split_policy: SplitPolicy) -> Self {
+        self.split_policy = Some(split_policy);
+        self
+    }
+    pub fn build(self) -> SonataResult<SonataSpeechSynthesizer> {
+        let Some(voice) = self.voice else {
+            return Err(SonataError::OperationError(
+                "No voice was given to the synthesizer builder".to_string(),
+            ));
+        };
+        let mut synth = SonataSpeechSynthesizer::new(voice)?;
+        synth.audio_cache = self.audio_cache;
+        synth.replacements = self.replacements;
+        synth.text_normalizer = self.text_normalizer;
+        synth.memory_limit = self.memory_limit;
+        synth.on_sentence = self.on_sentence;
+        synth.watermark = self.watermark.map(Arc::new);
+        synth.split_policy = self.split_policy;
+        if let Some(input_sanitizer) = self.input_sanitizer {
+            synth.input_sanitizer = input_sanitizer;
+        }
+        if let Some(depth) = self.stream_buffer_depth {
+            synth = synth.with_stream_buffer_depth(depth);
+        }
+        if let Some(error_recovery) = self.error_recovery {
+            synth.error_recovery = error_recovery;
+        }
+        if let Some(sample_format) = self.sample_format {
+            synth.sample_format = sample_format;
+        }
+        if let Some(channel_layout) = self.channel_layout {
+            synth.channel_layout = channel_layout;
+        }
+        if let Some(num_threads) = self.num_threads {
+            let thread_pool = ThreadPoolBuilder::new()
+                .thread_name(|i| format!("sonata_synth_{}", i))
+                .num_threads(num_threads)
+                .build()
+                .map_err(|e| {
+                    SonataError::OperationError("Failed to create synthesis threads".to_string())
+                        .caused_by(e)
+                })?;
+            synth.thread_pool = Some(Arc::new(thread_pool));
+        }
+        if let Some(chunk_overlap) = self.chunk_overlap {
+            synth.set_chunk_overlap(chunk_overlap)?;
+        }
+        if let Some(latency_target) = self.latency_target {
+            synth.set_latency_target(Some(latency_target))?;
+        }
+        if let Some(batch_size) = self.decoder_batch_size {
+            synth.set_decoder_batch_size(batch_size)?;
+        }
+        if let Some(rate) = self.rate_multiplier {
+            synth.set_rate_multiplier(rate)?;
+        }
+        Ok(synth)
+    }
+}
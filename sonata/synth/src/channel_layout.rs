@@ -0,0 +1,67 @@
+//! Channel layout of the files written by the synthesizer.
+
+use crate::Audio;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ChannelLayout {
+    /// Keep the model's (mono) output
+    #[default]
+    Mono,
+    /// Render the output into stereo, panned per speaker
+    Stereo(StereoPanning),
+}
+
+impl ChannelLayout {
+    /// Render `audio`, spoken by `speaker`, in this layout
+    pub fn apply(&self, audio: Audio, speaker: Option<i64>) -> Audio {
+        match self {
+            Self::Mono => audio,
+            Self::Stereo(panning) => audio.to_stereo(panning.pan_for(speaker)),
+        }
+    }
+    /// The channels of audio of `model_channels` channels rendered in this layout
+    pub(crate) fn num_channels(&self, model_channels: usize) -> usize {
+        match self {
+            Self::Mono => model_channels,
+            Self::Stereo(_) => 2,
+        }
+    }
+    /// Identifies the rendering of `speaker`, e.g. to tell apart the outputs of two jobs
+    pub(crate) fn describe(&self, speaker: Option<i64>) -> String {
+        match self {
+            Self::Mono => "mono".to_string(),
+            Self::Stereo(panning) => format!("stereo {}", panning.pan_for(speaker)),
+        }
+    }
+}
+
+/// Position of a voice in the stereo field, from `-1.0` (left) to `1.0` (right).
+///
+/// Speakers of multi-speaker voices can be placed separately, e.g. to spread the
+/// characters of a dialogue scene.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StereoPanning {
+    pub pan: f32,
+    pub speaker_pans: HashMap<i64, f32>,
+}
+
+impl StereoPanning {
+    pub fn new(pan: f32) -> Self {
+        Self {
+            pan,
+            speaker_pans: HashMap::new(),
+        }
+    }
+    pub fn with_speaker_pan(mut self, speaker: i64, pan: f32) -> Self {
+        self.speaker_pans.insert(speaker, pan);
+        self
+    }
+    /// The pan of `speaker`, or of the voice if the speaker has none
+    pub fn pan_for(&self, speaker: Option<i64>) -> f32 {
+        speaker
+            .and_then(|speaker| self.speaker_pans.get(&speaker))
+            .copied()
+            .unwrap_or(self.pan)
+    }
+}
@@ -0,0 +1,147 @@
+//! Resuming the synthesis of a long document to a file after it was interrupted.
+//!
+//! The samples synthesized so far are kept in a raw file next to the checkpoint, which
+//! records how many sentences and samples of it are complete. Both files are removed
+//! once the wave file is written.
+
+use crate::spill::SpilledAudio;
+use serde::{Deserialize, Serialize};
+use sonata_core::{SonataError, SonataResult};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    /// Hash of the sentences of the document and of the output, so that the checkpoint of
+    /// another job is not resumed
+    job: u64,
+    completed_sentences: usize,
+    /// Offset of the next sentence in the samples file, in samples
+    num_samples: usize,
+    peak: f32,
+}
+
+/// The progress of a synthesis to a file, saved at `path`
+pub(crate) struct SynthesisCheckpoint {
+    path: PathBuf,
+    checkpoint: Checkpoint,
+}
+
+impl SynthesisCheckpoint {
+    /// Load the checkpoint at `path` if it is a checkpoint of the same job, or start over.
+    ///
+    /// The job is the synthesis of `sentence_phonemes` to the output described by
+    /// `output`, which covers everything else the audio depends on: the voice and its
+    /// synthesis config, the rate, and the output config
+    pub(crate) fn load(path: &Path, sentence_phonemes: &[String], output: &str) -> Self {
+        let mut job_bytes = Vec::from(output.as_bytes());
+        for phonemes in sentence_phonemes {
+            job_bytes.extend_from_slice(phonemes.as_bytes());
+            job_bytes.push(0);
+        }
+        let job = xxh3_64(&job_bytes);
+        let samples_len = std::fs::metadata(samples_path(path))
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let checkpoint = std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Checkpoint>(&json).ok())
+            .filter(|checkpoint| checkpoint.job == job)
+            .filter(|checkpoint| checkpoint.completed_sentences <= sentence_phonemes.len())
+            .filter(|checkpoint| {
+                (checkpoint.num_samples * std::mem::size_of::<f32>()) as u64 <= samples_len
+            })
+            .unwrap_or(Checkpoint {
+                job,
+                completed_sentences: 0,
+                num_samples: 0,
+                peak: 0.0,
+            });
+        Self {
+            path: path.to_path_buf(),
+            checkpoint,
+        }
+    }
+    pub(crate) fn completed_sentences(&self) -> usize {
+        self.checkpoint.completed_sentences
+    }
+    /// The samples completed so far, to which the next sentences are appended
+    pub(crate) fn resume_audio(&self) -> SonataResult<SpilledAudio> {
+        SpilledAudio::resume(
+            &self.samples_path(),
+            self.checkpoint.num_samples,
+            self.checkpoint.peak,
+        )
+    }
+    /// Record that the next sentence is complete, once its samples are pushed to `audio`
+    pub(crate) fn complete_sentence(&mut self, audio: &mut SpilledAudio) -> SonataResult<()> {
+        audio.sync()?;
+        self.checkpoint.completed_sentences += 1;
+        self.checkpoint.num_samples = audio.len();
+        self.checkpoint.peak = audio.peak();
+        let json = serde_json::to_vec(&self.checkpoint)
+            .map_err(|e| SonataError::OperationError(e.to_string()))?;
+        // Replace the checkpoint at once, so that an interruption leaves either checkpoint
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, json)
+            .and_then(|_| std::fs::rename(&temp_path, &self.path))
+            .map_err(|e| {
+                SonataError::OperationError(format!(
+                    "Failed to write checkpoint `{}`",
+                    self.path.display()
+                ))
+                .caused_by(e)
+            })
+    }
+    /// Remove the checkpoint and its samples, once the job is done
+    pub(crate) fn remove(self) {
+        std::fs::remove_file(self.samples_path()).ok();
+        std::fs::remove_file(&self.path).ok();
+    }
+    fn samples_path(&self) -> PathBuf {
+        samples_path(&self.path)
+    }
+}
+
+fn samples_path(checkpoint_path: &Path) -> PathBuf {
+    checkpoint_path.with_extension("samples")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesis_checkpoint() -> SonataResult<()> {
+        let path =
+            std::env::temp_dir().join(format!("sonata-checkpoint-{}.json", std::process::id()));
+        let sentences = vec!["həloʊ".to_string(), "wɜːld".to_string()];
+        let mut checkpoint = SynthesisCheckpoint::load(&path, &sentences, "mono");
+        assert_eq!(checkpoint.completed_sentences(), 0);
+        let mut audio = checkpoint.resume_audio()?;
+        audio.push(&[0.1, -0.5, 0.2])?;
+        checkpoint.complete_sentence(&mut audio)?;
+        // Samples of an interrupted sentence are discarded when resuming
+        audio.push(&[0.9, 0.9])?;
+        audio.sync()?;
+        drop(audio);
+
+        let checkpoint = SynthesisCheckpoint::load(&path, &sentences, "mono");
+        assert_eq!(checkpoint.completed_sentences(), 1);
+        let audio = checkpoint.resume_audio()?;
+        assert_eq!(audio.len(), 3);
+        assert_eq!(audio.peak(), 0.5);
+        drop(audio);
+        // Another document starts over
+        let other = SynthesisCheckpoint::load(&path, &sentences[..1], "mono");
+        assert_eq!(other.completed_sentences(), 0);
+        // So does the same document spoken otherwise
+        let other = SynthesisCheckpoint::load(&path, &sentences, "mono 1.5x");
+        assert_eq!(other.completed_sentences(), 0);
+
+        checkpoint.remove();
+        assert!(!path.exists());
+        assert!(!path.with_extension("samples").exists());
+        Ok(())
+    }
+}
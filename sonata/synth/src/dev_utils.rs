@@ -39,6 +39,11 @@ pub fn gen_params(kind: &str) -> (SonataSpeechSynthesizer, String, Option<AudioO
         volume: Some(50),
         pitch: Some(50),
         appended_silence_ms: None,
+        time_stretch: None,
+        gain_db: None,
+        rate_multiplier: None,
+        trim_leading_silence: false,
+        trim_trailing_silence: false,
     });
     let text = TEXT.join("\n");
     if kind == "std" {
@@ -0,0 +1,45 @@
+//! Re-synthesis of the sentences of a document that changed since it was synthesized.
+//!
+//! Sentences are segmented by the phonemizer, so the same text always gives the same
+//! sentences. A sentence of the new document is unchanged if the old document had a
+//! sentence with the same phonemes, wherever it was.
+//!
+//! Only the segmentation is deterministic, not the audio: unchanged sentences keep their
+//! old audio rather than being synthesized again.
+
+use std::collections::HashMap;
+
+/// For each sentence of `new_sentences`, the index of a sentence with the same phonemes
+/// in `old_sentences`, if any
+pub(crate) fn unchanged_sentences(
+    old_sentences: &[String],
+    new_sentences: &[String],
+) -> Vec<Option<usize>> {
+    let mut old_indices: HashMap<&str, usize> = HashMap::new();
+    for (index, phonemes) in old_sentences.iter().enumerate() {
+        old_indices.entry(phonemes.as_str()).or_insert(index);
+    }
+    Vec::from_iter(
+        new_sentences
+            .iter()
+            .map(|phonemes| old_indices.get(phonemes.as_str()).copied()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unchanged_sentences() {
+        let sentences = |s: &[&str]| Vec::from_iter(s.iter().map(|s| s.to_string()));
+        let old = sentences(&["a", "b", "c", "b"]);
+        let new = sentences(&["a", "x", "c", "b", "a"]);
+        assert_eq!(
+            unchanged_sentences(&old, &new),
+            vec![Some(0), None, Some(2), Some(1), Some(0)]
+        );
+        assert_eq!(unchanged_sentences(&[], &new), vec![None; 5]);
+        assert!(unchanged_sentences(&old, &[]).is_empty());
+    }
+}
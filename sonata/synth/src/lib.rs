@@ -1,18 +1,61 @@
+mod audio_cache;
+mod builder;
+mod channel_layout;
+mod checkpoint;
+mod incremental;
+pub mod normalization;
+mod recovery;
+mod replacements;
+mod report;
+mod sanitize;
+mod speech_queue;
+mod spill;
+mod split;
+mod stats;
+mod textgrid;
 mod utils;
+mod watermark;
+pub use audio_cache::{AudioCache, AudioCacheStats};
+pub use builder::SonataSpeechSynthesizerBuilder;
+pub use channel_layout::{ChannelLayout, StereoPanning};
+pub use normalization::TextNormalizer;
+pub use recovery::{ErrorRecovery, FailedSentenceAction, RecoveryReport, SkippedSentence};
+pub use replacements::{ReplacementDictionary, ReplacementRule};
+pub use report::{SentenceReport, SynthesisReport, SynthesisWarning};
+pub use sanitize::{InputSanitizer, SanitizePolicy, DEFAULT_MAX_TOKEN_LEN};
+pub use speech_queue::{SpeechPriority, SpeechQueue, SpeechQueueEvent, UtteranceId};
+pub use split::{SplitFile, SplitIndex, SplitPolicy};
+pub use stats::SynthesisStats;
+pub use textgrid::{TextGrid, TextGridInterval};
+pub use watermark::{Watermark, WatermarkDetection, DETECTION_THRESHOLD};
 pub use sonata_core::*;
+/// The entry point setting up onnxruntime, before any piper voice is loaded
+#[cfg(feature = "piper")]
+pub use sonata_piper::{init, ExecutionProvider, InitOptions, LogLevel};
 
+use checkpoint::SynthesisCheckpoint;
 use flume::{Receiver, SendError, Sender};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
 use rayon::{ThreadPool, ThreadPoolBuilder};
+use spill::SpilledAudio;
+use split::SplitWriter;
 use std::any::Any;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 const RATE_RANGE: (f32, f32) = (0.5f32, 5.5f32);
 const VOLUME_RANGE: (f32, f32) = (0.0f32, 1.0f32);
 const PITCH_RANGE: (f32, f32) = (0.5f32, 1.5f32);
+const DEFAULT_STREAM_BUFFER_DEPTH: usize = 8;
+/// Samples up to about -60 dBFS count as silence when trimming
+const SILENCE_THRESHOLD: f32 = 0.001;
+
+/// Called with the index and audio of each sentence once it is synthesized
+pub type SentenceCallback = Arc<dyn Fn(usize, &Audio) + Send + Sync>;
 
 pub static SYNTHESIS_THREAD_POOL: Lazy<ThreadPool> = Lazy::new(|| {
     let num_cpus = std::thread::available_parallelism()
@@ -25,16 +68,47 @@ pub static SYNTHESIS_THREAD_POOL: Lazy<ThreadPool> = Lazy::new(|| {
         .unwrap()
 });
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct AudioOutputConfig {
     pub rate: Option<u8>,
     pub volume: Option<u8>,
     pub pitch: Option<u8>,
     pub appended_silence_ms: Option<u32>,
+    /// Speed-up applied on top of `rate`, by time-stretching the audio
+    pub time_stretch: Option<f32>,
+    /// Gain in decibels, on top of `volume`
+    pub gain_db: Option<f32>,
+    /// Speed-up of this call, on top of the synthesizer's rate multiplier. Accepts the
+    /// range of [`SonataSpeechSynthesizer::set_rate_multiplier`]
+    pub rate_multiplier: Option<f32>,
+    /// Remove the silence the voice leaves before each sentence. Not applied to
+    /// realtime streams, whose sentences are not complete when they are processed
+    pub trim_leading_silence: bool,
+    /// Remove the silence the voice leaves after each sentence, e.g. so that
+    /// `appended_silence_ms` sets the pause between sentences. Not applied to
+    /// realtime streams
+    pub trim_trailing_silence: bool,
 }
 
 impl AudioOutputConfig {
+    fn validate(&self) -> SonataResult<()> {
+        if let Some(rate) = self.rate_multiplier {
+            check_rate_multiplier(rate)?;
+        }
+        if self.gain_db.is_some_and(|gain_db| !gain_db.is_finite()) {
+            return Err(SonataError::OperationError(
+                "Gain must be a finite number of decibels".to_string(),
+            ));
+        }
+        Ok(())
+    }
     fn apply(&self, mut audio: Audio) -> SonataAudioResult {
+        if self.trim_leading_silence {
+            audio.trim_leading_silence(SILENCE_THRESHOLD);
+        }
+        if self.trim_trailing_silence {
+            audio.trim_trailing_silence(SILENCE_THRESHOLD);
+        }
         let mut samples = audio.samples.take();
         if let Some(time_ms) = self.appended_silence_ms {
             let mut silence_samples = self.generate_silence(
@@ -52,6 +126,39 @@ impl AudioOutputConfig {
         audio.samples.as_mut_vec().append(samples.as_mut_vec());
         Ok(audio)
     }
+    /// Like `apply`, moving the sample ranges of `alignment` along with the audio
+    fn apply_aligned(
+        &self,
+        mut audio: Audio,
+        alignment: Vec<Range<usize>>,
+    ) -> SonataResult<(Audio, Vec<Range<usize>>)> {
+        let mut config = self.clone();
+        let num_frames = audio.num_frames();
+        if self.trim_leading_silence {
+            audio.trim_leading_silence(SILENCE_THRESHOLD);
+            config.trim_leading_silence = false;
+        }
+        let num_trimmed = num_frames - audio.num_frames();
+        let audio = config.apply(audio)?;
+        let speed = self.speed();
+        let num_frames = audio.num_frames();
+        let moved = |sample: usize| {
+            ((sample.saturating_sub(num_trimmed) as f32 / speed).round() as usize).min(num_frames)
+        };
+        let alignment = Vec::from_iter(
+            alignment
+                .into_iter()
+                .map(|span| moved(span.start)..moved(span.end)),
+        );
+        Ok((audio, alignment))
+    }
+    /// The speed-up of time-stretching
+    fn speed(&self) -> f32 {
+        let rate = self.rate.map_or(1.0, |rate| {
+            utils::percent_to_param(rate, RATE_RANGE.0, RATE_RANGE.1)
+        });
+        rate * self.time_stretch.unwrap_or(1.0) * self.rate_multiplier.unwrap_or(1.0)
+    }
     fn apply_to_raw_samples(
         &self,
         samples: AudioSamples,
@@ -66,17 +173,18 @@ impl AudioOutputConfig {
         let mut out_buf: Vec<f32> = Vec::new();
         unsafe {
             let stream = sonic_sys::sonicCreateStream(sample_rate as i32, num_channels as i32);
-            if let Some(rate) = self.rate {
-                sonic_sys::sonicSetSpeed(
-                    stream,
-                    utils::percent_to_param(rate, RATE_RANGE.0, RATE_RANGE.1),
-                );
+            if self.rate.is_some() || self.time_stretch.is_some() || self.rate_multiplier.is_some()
+            {
+                sonic_sys::sonicSetSpeed(stream, self.speed());
             }
-            if let Some(volume) = self.volume {
-                sonic_sys::sonicSetVolume(
-                    stream,
-                    utils::percent_to_param(volume, VOLUME_RANGE.0, VOLUME_RANGE.1),
-                );
+            if self.volume.is_some() || self.gain_db.is_some() {
+                let volume = self.volume.map_or(1.0, |volume| {
+                    utils::percent_to_param(volume, VOLUME_RANGE.0, VOLUME_RANGE.1)
+                });
+                let gain = self
+                    .gain_db
+                    .map_or(1.0, |gain_db| 10f32.powf(gain_db / 20.0));
+                sonic_sys::sonicSetVolume(stream, volume * gain);
             }
             if let Some(pitch) = self.pitch {
                 sonic_sys::sonicSetPitch(
@@ -116,23 +224,295 @@ impl AudioOutputConfig {
     }
 }
 
-pub struct SonataSpeechSynthesizer(Arc<dyn SonataModel + Sync + Send>);
+fn check_rate_multiplier(rate: f32) -> SonataResult<()> {
+    if !(RATE_MULTIPLIER_RANGE.0..=RATE_MULTIPLIER_RANGE.1).contains(&rate) {
+        return Err(SonataError::OperationError(format!(
+            "Rate multiplier `{}` is out of range. Expected a value between {} and {}",
+            rate, RATE_MULTIPLIER_RANGE.0, RATE_MULTIPLIER_RANGE.1
+        )));
+    }
+    Ok(())
+}
+
+pub struct SonataSpeechSynthesizer {
+    model: Arc<dyn SonataModel + Sync + Send>,
+    audio_cache: Option<Arc<AudioCache>>,
+    input_sanitizer: InputSanitizer,
+    replacements: Option<Arc<ReplacementDictionary>>,
+    text_normalizer: Option<Arc<TextNormalizer>>,
+    stream_buffer_depth: usize,
+    error_recovery: ErrorRecovery,
+    sample_format: WaveSampleFormat,
+    channel_layout: ChannelLayout,
+    /// The native rate of the model, and the time-stretch making up the rest of the
+    /// rate multiplier
+    rate: RwLock<(f32, f32)>,
+    memory_limit: Option<usize>,
+    /// Threads of this synthesizer alone, instead of the shared ones
+    thread_pool: Option<Arc<ThreadPool>>,
+    on_sentence: Option<SentenceCallback>,
+    watermark: Option<Arc<Watermark>>,
+    split_policy: Option<SplitPolicy>,
+}
 
 impl SonataSpeechSynthesizer {
     pub fn new(model: Arc<dyn SonataModel + Sync + Send>) -> SonataResult<Self> {
-        Ok(Self(model))
+        Ok(Self {
+            model,
+            audio_cache: None,
+            input_sanitizer: InputSanitizer::default(),
+            replacements: None,
+            text_normalizer: None,
+            stream_buffer_depth: DEFAULT_STREAM_BUFFER_DEPTH,
+            error_recovery: ErrorRecovery::default(),
+            sample_format: WaveSampleFormat::default(),
+            channel_layout: ChannelLayout::default(),
+            rate: RwLock::new((1.0, 1.0)),
+            memory_limit: None,
+            thread_pool: None,
+            on_sentence: None,
+            watermark: None,
+            split_policy: None,
+        })
+    }
+    pub fn builder() -> SonataSpeechSynthesizerBuilder {
+        SonataSpeechSynthesizerBuilder::new()
+    }
+    /// Serve repeated utterances from `audio_cache` instead of synthesizing them again.
+    ///
+    /// Only models that provide a synthesis cache key are cached, which for Piper
+    /// voices means a config with a `key`; a warning is logged otherwise. Realtime
+    /// streams are not cached.
+    pub fn with_audio_cache(mut self, audio_cache: Arc<AudioCache>) -> Self {
+        if let Ok(None) = self.model.synthesis_cache_key() {
+            log::warn!("The voice has no synthesis cache key, so its audio will not be cached");
+        }
+        self.audio_cache = Some(audio_cache);
+        self
+    }
+    pub fn audio_cache(&self) -> Option<&Arc<AudioCache>> {
+        self.audio_cache.as_ref()
+    }
+    /// Clean up the text with `input_sanitizer` before anything else. By default,
+    /// control, bidirectional formatting and invisible characters are removed.
+    pub fn with_input_sanitizer(mut self, input_sanitizer: InputSanitizer) -> Self {
+        self.input_sanitizer = input_sanitizer;
+        self
+    }
+    pub fn input_sanitizer(&self) -> &InputSanitizer {
+        &self.input_sanitizer
+    }
+    /// Apply the rules of `replacements` to the text before phonemization.
+    ///
+    /// The dictionary is shared, so rules edited at runtime take effect on the next utterance.
+    pub fn with_replacements(mut self, replacements: Arc<ReplacementDictionary>) -> Self {
+        self.replacements = Some(replacements);
+        self
+    }
+    pub fn replacements(&self) -> Option<&Arc<ReplacementDictionary>> {
+        self.replacements.as_ref()
+    }
+    /// Normalize the text using `text_normalizer` before phonemization.
+    /// User replacements are applied first.
+    pub fn with_text_normalizer(mut self, text_normalizer: Arc<TextNormalizer>) -> Self {
+        self.text_normalizer = Some(text_normalizer);
+        self
+    }
+    /// Buffer at most `depth` chunks of a realtime stream ahead of its consumer (default `8`).
+    ///
+    /// Once the buffer is full, synthesis pauses until the consumer catches up, so a
+    /// slow consumer doesn't make the buffered audio grow without bounds.
+    pub fn with_stream_buffer_depth(mut self, depth: usize) -> Self {
+        self.stream_buffer_depth = depth.max(1);
+        self
+    }
+    pub fn stream_buffer_depth(&self) -> usize {
+        self.stream_buffer_depth
+    }
+    /// Retry or replace sentences that fail to synthesize instead of failing the stream.
+    ///
+    /// Applies to lazy and parallel synthesis, and so `synthesize_to_file`, which returns
+    /// the sentences that were replaced. Use the stream's `recovery_report` to find them
+    /// otherwise. Realtime streams apply it to the sentences whose stream fails to start,
+    /// which are then synthesized in one go.
+    pub fn with_error_recovery(mut self, error_recovery: ErrorRecovery) -> Self {
+        self.error_recovery = error_recovery;
+        self
+    }
+    /// Sample encoding of the files written by `synthesize_to_file` (default 16-bit PCM).
+    ///
+    /// Files in the G.711 telephony formats are written at 8 kHz, whatever the sample
+    /// rate of the voice
+    pub fn with_sample_format(mut self, sample_format: WaveSampleFormat) -> Self {
+        self.sample_format = sample_format;
+        self
+    }
+    pub fn sample_format(&self) -> WaveSampleFormat {
+        self.sample_format
+    }
+    /// Channel layout of the files written by `synthesize_to_file` (default mono).
+    ///
+    /// The pan is chosen by the current speaker when the file is written. Streams are
+    /// not affected.
+    pub fn with_channel_layout(mut self, channel_layout: ChannelLayout) -> Self {
+        self.channel_layout = channel_layout;
+        self
+    }
+    pub fn channel_layout(&self) -> &ChannelLayout {
+        &self.channel_layout
+    }
+    /// Turn the audio of the voice into that of the files written by
+    /// `synthesize_to_file`: resampled for the sample format if needed, then laid out
+    /// in channels
+    pub fn prepare_output(&self, mut audio: Audio) -> SonataResult<Audio> {
+        let sample_rate = self.output_sample_rate(audio.info.sample_rate);
+        if sample_rate != audio.info.sample_rate {
+            audio.samples = audio
+                .samples
+                .resample_antialiased(audio.info.sample_rate, sample_rate);
+            audio.info.sample_rate = sample_rate;
+        }
+        let speaker = self.model.current_speaker()?;
+        Ok(self.channel_layout.apply(audio, speaker))
+    }
+    /// The sample rate of written files for a voice of `sample_rate`
+    fn output_sample_rate(&self, sample_rate: usize) -> usize {
+        if self.sample_format.is_g711() {
+            TELEPHONY_SAMPLE_RATE
+        } else {
+            sample_rate
+        }
+    }
+    /// Hold at most about `memory_limit` bytes of audio in memory while synthesizing to a
+    /// file, and keep the rest in a temporary file until the file is written, e.g. to
+    /// synthesize a book on a small device.
+    ///
+    /// Sentences are then synthesized one after the other, and not cached.
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+    /// Write the output of `synthesize_to_file` to numbered files cut by `split_policy`,
+    /// e.g. `book-001.wav`, `book-002.wav`, and list them in an index next to them,
+    /// `book.json`, see [`SplitIndex`].
+    ///
+    /// The files are written once the whole text is synthesized, all scaled alike so
+    /// that they are as loud as each other. Until then the audio is held as with
+    /// [`Self::with_memory_limit`]. Checkpoints are not supported.
+    pub fn with_split_policy(mut self, split_policy: SplitPolicy) -> Self {
+        self.split_policy = Some(split_policy);
+        self
+    }
+    pub fn split_policy(&self) -> Option<&SplitPolicy> {
+        self.split_policy.as_ref()
+    }
+    /// Embed `watermark` into the audio of each sentence, and of realtime streams, so
+    /// that it can be told apart from other speech later, see [`Watermark::detect`]
+    pub fn with_watermark(mut self, watermark: Watermark) -> Self {
+        self.watermark = Some(Arc::new(watermark));
+        self
+    }
+    pub fn watermark(&self) -> Option<&Watermark> {
+        self.watermark.as_deref()
+    }
+
+    /// Speak `rate` times faster, between `0.5` and `3.0`.
+    ///
+    /// The model changes its own speaking rate as far as it still sounds natural, and
+    /// the audio is time-stretched for the rest of `rate`. The rate is this
+    /// synthesizer's own, even if its voice is shared with others.
+    pub fn set_rate_multiplier(&self, rate: f32) -> SonataResult<()> {
+        check_rate_multiplier(rate)?;
+        let native_rate = self.model.native_rate(rate)?;
+        *self.rate.write().unwrap() = (native_rate, rate / native_rate);
+        Ok(())
     }
 
     fn create_synthesis_task_provider(
         &self,
         text: String,
         output_config: Option<AudioOutputConfig>,
-    ) -> SpeechSynthesisTaskProvider {
-        SpeechSynthesisTaskProvider {
+    ) -> SonataResult<SpeechSynthesisTaskProvider> {
+        let timer = std::time::Instant::now();
+        let text = self.preprocess_text(text)?;
+        let timings = StageTimings {
+            normalization_ms: timer.elapsed().as_secs_f32() * 1000.0,
+            ..Default::default()
+        };
+        if let Some(ref output_config) = output_config {
+            output_config.validate()?;
+        }
+        let (native_rate, time_stretch) = *self.rate.read().unwrap();
+        let output_config = if time_stretch != 1.0 {
+            let mut output_config = output_config.unwrap_or_default();
+            output_config.time_stretch =
+                Some(output_config.time_stretch.unwrap_or(1.0) * time_stretch);
+            Some(output_config)
+        } else {
+            output_config
+        };
+        let cache_entry = self.audio_cache.as_ref().and_then(|cache| {
+            let model_key = self.model.synthesis_cache_key().ok()??;
+            let synthesis_cache_key = self.synthesis_key(model_key, native_rate);
+            let key = AudioCache::key(&text, &synthesis_cache_key, output_config.as_ref());
+            Some((Arc::clone(cache), key))
+        });
+        Ok(SpeechSynthesisTaskProvider {
             model: self.clone_model(),
             text,
             output_config,
+            native_rate,
+            cache_entry,
+            error_recovery: self.error_recovery.clone(),
+            recovery_report: RecoveryReport::default(),
+            timings,
+            thread_pool: self.thread_pool.clone(),
+            on_sentence: self.on_sentence.clone(),
+            watermark: self.watermark.clone(),
+        })
+    }
+
+    /// The model's synthesis cache key, along with the watermark and the native rate the
+    /// audio also depends on
+    fn synthesis_key(&self, mut model_key: String, native_rate: f32) -> String {
+        if let Some(ref watermark) = self.watermark {
+            model_key += &format!("\0watermark {:x}", watermark.key_hash());
+        }
+        if native_rate != 1.0 {
+            model_key += &format!("\0native rate {}", native_rate);
         }
+        model_key
+    }
+
+    /// Sanitize the text, then apply user replacements and text normalization
+    fn preprocess_text(&self, text: String) -> SonataResult<String> {
+        let text = self.input_sanitizer.sanitize(&text)?;
+        if self.replacements.is_none() && self.text_normalizer.is_none() {
+            return Ok(text);
+        }
+        let language = self.model.get_language().ok().flatten();
+        let text = match self.replacements {
+            Some(ref replacements) => replacements.apply(&text, language.as_deref()),
+            None => text,
+        };
+        Ok(match self.text_normalizer {
+            Some(ref normalizer) => normalizer.normalize(&text, language.as_deref()),
+            None => text,
+        })
+    }
+
+    /// The text as it is phonemized, after sanitation, user replacements and text
+    /// normalization
+    pub fn normalize_text(&self, text: &str) -> SonataResult<String> {
+        self.preprocess_text(text.to_string())
+    }
+    /// Phonemize `text` the way the synthesis methods do, i.e. after replacements and
+    /// normalization. Each item of the result is one sentence.
+    pub fn phonemize(&self, text: &str) -> SonataResult<Phonemes> {
+        self.model.phonemize_text(&self.normalize_text(text)?)
     }
 
     pub fn synthesize_lazy(
@@ -140,14 +520,14 @@ impl SonataSpeechSynthesizer {
         text: String,
         output_config: Option<AudioOutputConfig>,
     ) -> SonataResult<SonataSpeechStreamLazy> {
-        SonataSpeechStreamLazy::new(self.create_synthesis_task_provider(text, output_config))
+        SonataSpeechStreamLazy::new(self.create_synthesis_task_provider(text, output_config)?)
     }
     pub fn synthesize_parallel(
         &self,
         text: String,
         output_config: Option<AudioOutputConfig>,
     ) -> SonataResult<SonataSpeechStreamParallel> {
-        SonataSpeechStreamParallel::new(self.create_synthesis_task_provider(text, output_config))
+        SonataSpeechStreamParallel::new(self.create_synthesis_task_provider(text, output_config)?)
     }
     pub fn synthesize_streamed(
         &self,
@@ -156,25 +536,440 @@ impl SonataSpeechSynthesizer {
         chunk_size: usize,
         chunk_padding: usize,
     ) -> SonataResult<RealtimeSpeechStream> {
-        let provider = self.create_synthesis_task_provider(text, output_config);
-        let wavinfo = self.0.audio_output_info()?;
+        self.synthesize_streamed_from(text, output_config, chunk_size, chunk_padding, 0)
+    }
+    /// Like `synthesize_streamed`, starting from the sentence `first_sentence` of `text`.
+    /// Used to resume interrupted speech at a sentence boundary
+    pub fn synthesize_streamed_from(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        chunk_size: usize,
+        chunk_padding: usize,
+        first_sentence: usize,
+    ) -> SonataResult<RealtimeSpeechStream> {
+        self.synthesize_streamed_with_control(
+            text,
+            output_config,
+            chunk_size,
+            chunk_padding,
+            first_sentence,
+            StreamControl::new(),
+        )
+    }
+    /// Like `synthesize_streamed_from`, with a `control` that can be handed out before
+    /// the text is phonemized
+    pub(crate) fn synthesize_streamed_with_control(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        chunk_size: usize,
+        chunk_padding: usize,
+        first_sentence: usize,
+        control: StreamControl,
+    ) -> SonataResult<RealtimeSpeechStream> {
+        let provider = self.create_synthesis_task_provider(text, output_config)?;
         RealtimeSpeechStream::new(
             provider,
+            first_sentence,
+            control,
             chunk_size,
             chunk_padding,
-            wavinfo.sample_rate,
-            wavinfo.num_channels,
+            self.stream_buffer_depth,
         )
     }
 
+    /// Synthesize `text` into one piece of audio, along with a report of each sentence
+    pub fn synthesize_with_report(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<(Audio, SynthesisReport)> {
+        // The text is phonemized once, for both the synthesis and the report
+        let mut provider = self.create_synthesis_task_provider(text, output_config)?;
+        let phonemes = provider.phonemize()?;
+        let mut report = SynthesisReport::new(provider.text.clone(), self.model.current_speaker()?);
+        report.phonemizer_warnings = phonemes.warnings().to_vec();
+        let sentence_phonemes = phonemes.to_vec();
+        let mut stream =
+            SonataSpeechStreamParallel::with_phonemes(provider, Some(sentence_phonemes.clone()))?;
+        let mut sentences = Vec::with_capacity(sentence_phonemes.len());
+        for (phonemes, result) in sentence_phonemes.into_iter().zip(stream.by_ref()) {
+            let audio = result?;
+            let unknown_phonemes = self.model.unknown_phonemes(&phonemes)?;
+            report.add_sentence(phonemes, &audio, unknown_phonemes);
+            sentences.push(audio);
+        }
+        for skipped in stream.recovery_report().skipped() {
+            report.add_skipped(skipped);
+        }
+        // Include the stages that ran once for the whole text
+        report.stats.timings = stream.stats().timings;
+        if sentences.is_empty() {
+            return Err(SonataError::OperationError(
+                "No speech data to write".to_string(),
+            ));
+        }
+        Ok((Audio::concat(&sentences)?, report))
+    }
+    /// Synthesize `text` into one piece of audio, along with a Praat TextGrid of the
+    /// words and phones of each sentence, timed by the durations the model predicted.
+    ///
+    /// Requires a voice that predicts durations, i.e. a streaming voice. Sentences are
+    /// synthesized one after the other, and error recovery does not apply.
+    pub fn synthesize_with_textgrid(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<(Audio, TextGrid)> {
+        let mut provider = self.create_synthesis_task_provider(text, output_config)?;
+        let sentence_phonemes = provider.get_phonemes()?;
+        let mut sentences = Vec::with_capacity(sentence_phonemes.len());
+        let mut textgrid = TextGrid::default();
+        for phonemes in sentence_phonemes {
+            let (audio, alignment) =
+                provider.speak(|| self.model.speak_with_alignment(phonemes.clone()))?;
+            let (mut audio, alignment) = match provider.output_config {
+                Some(ref config) => config.apply_aligned(audio, alignment)?,
+                None => (audio, alignment),
+            };
+            if let Some(ref watermark) = provider.watermark {
+                watermark.embed(audio.samples.as_mut_vec(), audio.info.num_channels, 0);
+            }
+            let sample_rate = audio.info.sample_rate as f64;
+            let alignment = Vec::from_iter(
+                alignment
+                    .into_iter()
+                    .map(|span| span.start as f64 / sample_rate..span.end as f64 / sample_rate),
+            );
+            textgrid.add_sentence(&phonemes, &alignment, audio.duration().as_secs_f64());
+            sentences.push(audio);
+        }
+        if sentences.is_empty() {
+            return Err(SonataError::OperationError(
+                "No speech data to write".to_string(),
+            ));
+        }
+        Ok((Audio::concat(&sentences)?, textgrid))
+    }
+    /// The audio of each sentence of `new_text`, synthesizing only the sentences that are
+    /// not in `old_text`, e.g. to update the audio of a document after a small edit.
+    ///
+    /// `old_sentences` is the audio of each sentence of `old_text`, as returned by this
+    /// method or yielded by [`Self::synthesize_lazy`], with the same voice and
+    /// `output_config`. Unchanged sentences reuse their audio as is, and the others are
+    /// synthesized in parallel. Error recovery does not apply, so that placeholders are
+    /// never reused as the audio of a sentence.
+    ///
+    /// The audio of a sentence is not deterministic: the voice's noise is drawn inside
+    /// the model, so synthesizing the same sentence twice gives slightly different audio.
+    /// Only the reused sentences are guaranteed to be the same as before.
+    pub fn resynthesize_changed(
+        &self,
+        old_text: String,
+        old_sentences: Vec<Audio>,
+        new_text: String,
+        output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<Vec<Audio>> {
+        let old_phonemes = self
+            .create_synthesis_task_provider(old_text, None)?
+            .get_phonemes()?;
+        if old_phonemes.len() != old_sentences.len() {
+            return Err(SonataError::OperationError(format!(
+                "The old text has {} sentences, but audio was given for {}",
+                old_phonemes.len(),
+                old_sentences.len()
+            )));
+        }
+        let mut provider = self.create_synthesis_task_provider(new_text, output_config)?;
+        let new_phonemes = provider.get_phonemes()?;
+        let unchanged = incremental::unchanged_sentences(&old_phonemes, &new_phonemes);
+        let mut synthesized: Vec<Option<Audio>> = self.thread_pool().install(|| {
+            new_phonemes
+                .into_par_iter()
+                .zip(unchanged.par_iter())
+                .map(|(phonemes, old_index)| match old_index {
+                    Some(_) => Ok(None),
+                    None => provider.process_one_sentence(phonemes).map(Some),
+                })
+                .collect::<SonataResult<_>>()
+        })?;
+        Ok(Vec::from_iter(unchanged.into_iter().enumerate().map(
+            |(index, old_index)| match old_index {
+                Some(old_index) => old_sentences[old_index].clone(),
+                None => synthesized[index].take().unwrap(),
+            },
+        )))
+    }
+    /// Synthesize `text` with each speaker of a multi-speaker voice, e.g. to audition its
+    /// speakers. The text is prepared and phonemized once, as for the other synthesis
+    /// methods, and the speakers are synthesized in parallel. Results are ordered by
+    /// speaker id
+    pub fn speak_all_speakers(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<Vec<(i64, Audio)>> {
+        let mut speakers = match self.model.get_speakers()? {
+            Some(speakers) if !speakers.is_empty() => Vec::from_iter(speakers.keys().copied()),
+            _ => {
+                return Err(SonataError::OperationError(
+                    "The voice has a single speaker".to_string(),
+                ))
+            }
+        };
+        speakers.sort_unstable();
+        let mut provider = self.create_synthesis_task_provider(text, output_config)?;
+        let sentence_phonemes = provider.get_phonemes()?;
+        self.thread_pool().install(|| {
+            speakers
+                .into_par_iter()
+                .map(|speaker| {
+                    let sentences = provider.speak(|| {
+                        self.model
+                            .speak_batch_as_speaker(sentence_phonemes.clone(), speaker)
+                    })?;
+                    let sentences: Vec<Audio> = Result::from_iter(
+                        sentences
+                            .into_iter()
+                            .map(|audio| provider.postprocess(audio)),
+                    )?;
+                    Ok((speaker, Audio::concat(&sentences)?))
+                })
+                .collect()
+        })
+    }
     pub fn synthesize_to_file(
         &self,
         filename: &Path,
         text: String,
         output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<RecoveryReport> {
+        if let Some(ref split_policy) = self.split_policy {
+            return self.synthesize_to_split_files(
+                filename,
+                text,
+                output_config,
+                split_policy,
+                None,
+            );
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            return self.synthesize_to_file_spilling(
+                filename,
+                text,
+                output_config,
+                None,
+                Some(memory_limit),
+                None,
+            );
+        }
+        let (audio, recovery_report) = self.synthesize_to_samples(text, output_config)?;
+        self.write_file(filename, audio, None)?;
+        Ok(recovery_report)
+    }
+    /// Like [`Self::synthesize_to_file`], embedding `metadata` in the file.
+    ///
+    /// The language, the source text (as the comment) and the software name are
+    /// filled in unless set in `metadata`. The source text is left out of files cut by
+    /// the split policy. The voice name is not known to the synthesizer, so set it as
+    /// `metadata.artist`.
+    pub fn synthesize_to_file_with_metadata(
+        &self,
+        filename: &Path,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        mut metadata: WaveMetadata,
+    ) -> SonataResult<RecoveryReport> {
+        if metadata.language.is_none() {
+            metadata.language = self.model.get_language()?;
+        }
+        if metadata.software.is_none() {
+            metadata.software = Some(format!("sonata {}", env!("CARGO_PKG_VERSION")));
+        }
+        if let Some(ref split_policy) = self.split_policy {
+            return self.synthesize_to_split_files(
+                filename,
+                text,
+                output_config,
+                split_policy,
+                Some(&metadata),
+            );
+        }
+        if metadata.comment.is_none() {
+            metadata.comment = Some(text.clone());
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            return self.synthesize_to_file_spilling(
+                filename,
+                text,
+                output_config,
+                Some(&metadata),
+                Some(memory_limit),
+                None,
+            );
+        }
+        let (audio, recovery_report) = self.synthesize_to_samples(text, output_config)?;
+        self.write_file(filename, audio, Some(&metadata))?;
+        Ok(recovery_report)
+    }
+    /// Like [`Self::synthesize_to_file`], saving the progress of the job to
+    /// `checkpoint_path` after every sentence.
+    ///
+    /// If the synthesis of the same text was interrupted (e.g. by a crash or a power
+    /// loss), it resumes after the last completed sentence instead of starting over. The
+    /// audio synthesized so far is kept in a file next to the checkpoint, and both are
+    /// removed once the file is written. Sentences are synthesized one after the other,
+    /// and not cached.
+    pub fn synthesize_to_file_with_checkpoint(
+        &self,
+        filename: &Path,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        checkpoint_path: &Path,
+    ) -> SonataResult<RecoveryReport> {
+        if self.split_policy.is_some() {
+            return Err(SonataError::OperationError(
+                "Checkpoints are not supported when splitting the output into several files"
+                    .to_string(),
+            ));
+        }
+        self.synthesize_to_file_spilling(
+            filename,
+            text,
+            output_config,
+            None,
+            None,
+            Some(checkpoint_path),
+        )
+    }
+    /// Write the audio of `text` to a file sentence by sentence, keeping at most
+    /// `memory_limit` bytes of it in memory (see [`Self::with_memory_limit`]), or all of
+    /// it on disk next to the checkpoint at `checkpoint_path`
+    fn synthesize_to_file_spilling(
+        &self,
+        filename: &Path,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        metadata: Option<&WaveMetadata>,
+        memory_limit: Option<usize>,
+        checkpoint_path: Option<&Path>,
+    ) -> SonataResult<RecoveryReport> {
+        let wavinfo = self.model.audio_output_info()?;
+        let speaker = self.model.current_speaker()?;
+        let num_channels = self.channel_layout.num_channels(wavinfo.num_channels);
+        let sample_rate = self.output_sample_rate(wavinfo.sample_rate);
+        let mut provider = self.create_synthesis_task_provider(text, output_config)?;
+        // The cache would keep the whole utterance in memory
+        provider.cache_entry = None;
+        // Voices without a cache key are told apart by their speaker alone
+        let model_key = self.model.synthesis_cache_key()?.unwrap_or_default();
+        let synthesis_key = self.synthesis_key(model_key, provider.native_rate);
+        let output_key = AudioCache::key("", &synthesis_key, provider.output_config.as_ref());
+        let mut stream = SonataSpeechStreamLazy::new(provider)?;
+        let recovery_report = stream.recovery_report().clone();
+        let mut checkpoint = checkpoint_path.map(|path| {
+            let output = format!(
+                "{} Hz {} {:x}",
+                sample_rate,
+                self.channel_layout.describe(speaker),
+                output_key
+            );
+            SynthesisCheckpoint::load(path, stream.sentence_phonemes.as_slice(), &output)
+        });
+        let mut audio = match checkpoint {
+            Some(ref checkpoint) => {
+                stream.skip_sentences(checkpoint.completed_sentences());
+                checkpoint.resume_audio()?
+            }
+            None => SpilledAudio::new(memory_limit.unwrap_or(usize::MAX)),
+        };
+        for result in stream {
+            let mut sentence = result?;
+            sentence.info = wavinfo.clone();
+            let sentence = self.prepare_output(sentence)?;
+            audio.push(sentence.samples.as_slice())?;
+            if let Some(ref mut checkpoint) = checkpoint {
+                checkpoint.complete_sentence(&mut audio)?;
+            }
+        }
+        if audio.len() == 0 {
+            return Err(SonataError::OperationError(
+                "No speech data to write".to_string(),
+            ));
+        }
+        audio.write_wave(
+            filename,
+            sample_rate,
+            num_channels,
+            self.sample_format,
+            metadata,
+        )?;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.remove();
+        }
+        Ok(recovery_report)
+    }
+    /// Write the audio of `text` to the files cut by `split_policy`, and their index
+    fn synthesize_to_split_files(
+        &self,
+        filename: &Path,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+        split_policy: &SplitPolicy,
+        metadata: Option<&WaveMetadata>,
+    ) -> SonataResult<RecoveryReport> {
+        let wavinfo = self.model.audio_output_info()?;
+        let mut writer = SplitWriter::new(
+            filename,
+            self.sample_format,
+            metadata,
+            self.memory_limit.unwrap_or(usize::MAX),
+        );
+        let recovery_report = RecoveryReport::default();
+        // Skipped sentences are numbered from the start of the text, not of their section
+        let mut num_sentences = 0;
+        for (title, text) in split_policy.sections(&text) {
+            let stream = self.synthesize_lazy(text, output_config.clone())?;
+            let section_report = stream.recovery_report().clone();
+            let first_sentence = num_sentences;
+            for result in stream {
+                num_sentences += 1;
+                let mut sentence = result?;
+                sentence.info = wavinfo.clone();
+                let sentence = self.prepare_output(sentence)?;
+                if split_policy.splits_before(writer.part_duration(), sentence.duration()) {
+                    writer.finish_part(title.clone())?;
+                }
+                writer.push(sentence)?;
+            }
+            writer.finish_part(title)?;
+            recovery_report.extend(&section_report, first_sentence);
+        }
+        writer.finish()?;
+        Ok(recovery_report)
+    }
+    fn write_file(
+        &self,
+        filename: &Path,
+        samples: AudioSamples,
+        metadata: Option<&WaveMetadata>,
     ) -> SonataResult<()> {
+        let wavinfo = self.model.audio_output_info()?;
+        let mut audio = Audio::new(samples, wavinfo.sample_rate, None);
+        audio.info = wavinfo;
+        let audio = self.prepare_output(audio)?;
+        Ok(audio.save_to_file_as(filename, self.sample_format, metadata)?)
+    }
+    fn synthesize_to_samples(
+        &self,
+        text: String,
+        output_config: Option<AudioOutputConfig>,
+    ) -> SonataResult<(AudioSamples, RecoveryReport)> {
         let mut samples: Vec<f32> = Vec::new();
-        for result in self.synthesize_parallel(text, output_config)? {
+        let stream = self.synthesize_parallel(text, output_config)?;
+        let recovery_report = stream.recovery_report().clone();
+        for result in stream {
             match result {
                 Ok(ws) => {
                     samples.append(&mut ws.into_vec());
@@ -187,54 +982,96 @@ impl SonataSpeechSynthesizer {
                 "No speech data to write".to_string(),
             ));
         }
-        let audio = AudioSamples::from(samples);
-        Ok(audio_ops::write_wave_samples_to_file(
-            filename,
-            audio.to_i16_vec().iter(),
-            self.0.audio_output_info()?.sample_rate as u32,
-            self.0.audio_output_info()?.num_channels.try_into().unwrap(),
-            self.0.audio_output_info()?.sample_width.try_into().unwrap(),
-        )?)
+        Ok((AudioSamples::from(samples), recovery_report))
+    }
+    fn thread_pool(&self) -> &ThreadPool {
+        self.thread_pool
+            .as_deref()
+            .unwrap_or(&SYNTHESIS_THREAD_POOL)
     }
     #[inline(always)]
     pub fn clone_model(&self) -> Arc<dyn SonataModel + Send + Sync> {
-        Arc::clone(&self.0)
+        Arc::clone(&self.model)
     }
 }
 
 impl SonataModel for SonataSpeechSynthesizer {
     fn audio_output_info(&self) -> SonataResult<AudioInfo> {
-        self.0.audio_output_info()
+        self.model.audio_output_info()
     }
     fn phonemize_text(&self, text: &str) -> SonataResult<Phonemes> {
-        self.0.phonemize_text(text)
+        self.model.phonemize_text(text)
     }
     fn speak_batch(&self, phoneme_batches: Vec<String>) -> SonataResult<Vec<Audio>> {
-        self.0.speak_batch(phoneme_batches)
+        self.model.speak_batch(phoneme_batches)
+    }
+    fn speak_batch_per_item(&self, phoneme_batches: Vec<String>) -> Vec<SonataAudioResult> {
+        self.model.speak_batch_per_item(phoneme_batches)
+    }
+    fn speak_batch_as_speaker(
+        &self,
+        phoneme_batches: Vec<String>,
+        speaker: i64,
+    ) -> SonataResult<Vec<Audio>> {
+        self.model.speak_batch_as_speaker(phoneme_batches, speaker)
     }
     fn speak_one_sentence(&self, phonemes: String) -> SonataAudioResult {
-        self.0.speak_one_sentence(phonemes)
+        self.model.speak_one_sentence(phonemes)
     }
     fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
-        self.0.get_default_synthesis_config()
+        self.model.get_default_synthesis_config()
     }
     fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
-        self.0.get_fallback_synthesis_config()
+        self.model.get_fallback_synthesis_config()
     }
     fn set_fallback_synthesis_config(&self, synthesis_config: &dyn Any) -> SonataResult<()> {
-        self.0.set_fallback_synthesis_config(synthesis_config)
+        self.model.set_fallback_synthesis_config(synthesis_config)
+    }
+    fn speak_one_sentence_with_config(
+        &self,
+        phonemes: String,
+        synthesis_config: &dyn Any,
+    ) -> SonataAudioResult {
+        self.model.speak_one_sentence_with_config(phonemes, synthesis_config)
     }
     fn get_language(&self) -> SonataResult<Option<String>> {
-        self.0.get_language()
+        self.model.get_language()
     }
     fn get_speakers(&self) -> SonataResult<Option<&HashMap<i64, String>>> {
-        self.0.get_speakers()
+        self.model.get_speakers()
+    }
+    fn current_speaker(&self) -> SonataResult<Option<i64>> {
+        self.model.current_speaker()
+    }
+    fn phoneme_id_map(&self) -> SonataResult<Option<&HashMap<char, Vec<i64>>>> {
+        self.model.phoneme_id_map()
+    }
+    fn speaker_id_map(&self) -> SonataResult<Option<&HashMap<String, i64>>> {
+        self.model.speaker_id_map()
+    }
+    fn supported_phonemes(&self) -> SonataResult<Vec<char>> {
+        self.model.supported_phonemes()
+    }
+    fn unknown_phonemes(&self, phonemes: &str) -> SonataResult<Vec<char>> {
+        self.model.unknown_phonemes(phonemes)
     }
     fn properties(&self) -> SonataResult<HashMap<String, String>> {
-        self.0.properties()
+        self.model.properties()
+    }
+    fn synthesis_cache_key(&self) -> SonataResult<Option<String>> {
+        self.model.synthesis_cache_key()
+    }
+    /// Includes the audio held in memory by the audio cache, which is counted in full
+    /// by each synthesizer sharing it
+    fn memory_usage(&self) -> SonataResult<MemoryUsage> {
+        let mut usage = self.model.memory_usage()?;
+        if let Some(ref audio_cache) = self.audio_cache {
+            usage.cache_bytes += audio_cache.stats().size_bytes as u64;
+        }
+        Ok(usage)
     }
     fn supports_streaming_output(&self) -> bool {
-        self.0.supports_streaming_output()
+        self.model.supports_streaming_output()
     }
     fn stream_synthesis<'a>(
         &'a self,
@@ -242,7 +1079,62 @@ impl SonataModel for SonataSpeechSynthesizer {
         #[allow(unused_variables)] chunk_size: usize,
         #[allow(unused_variables)] chunk_padding: usize,
     ) -> SonataResult<Box<dyn Iterator<Item = SonataResult<AudioSamples>> + Send + Sync + 'a>> {
-        self.0.stream_synthesis(phonemes, chunk_size, chunk_padding)
+        self.model.stream_synthesis(phonemes, chunk_size, chunk_padding)
+    }
+    fn stream_synthesis_with_control(
+        &self,
+        phonemes: String,
+        chunk_size: usize,
+        chunk_padding: usize,
+        control: StreamControl,
+    ) -> SonataResult<AudioStreamIterator<'_>> {
+        self.model
+            .stream_synthesis_with_control(phonemes, chunk_size, chunk_padding, control)
+    }
+    fn set_chunk_overlap(&self, chunk_overlap: ChunkOverlap) -> SonataResult<()> {
+        self.model.set_chunk_overlap(chunk_overlap)
+    }
+    fn set_latency_target(&self, latency_target: Option<Duration>) -> SonataResult<()> {
+        self.model.set_latency_target(latency_target)
+    }
+    fn set_decoder_batch_size(&self, batch_size: usize) -> SonataResult<()> {
+        self.model.set_decoder_batch_size(batch_size)
+    }
+    fn native_rate(&self, rate: f32) -> SonataResult<f32> {
+        self.model.native_rate(rate)
+    }
+    /// Also re-reads the replacements if they come from a file, and drops the cached
+    /// audio, which may have been spoken by a model the config no longer points to
+    fn reload_config(&self, config_path: &Path) -> SonataResult<()> {
+        self.model.reload_config(config_path)?;
+        if let Some(ref replacements) = self.replacements {
+            if replacements.path().is_some_and(|path| path.exists()) {
+                replacements.reload()?;
+            }
+        }
+        if let Some(ref audio_cache) = self.audio_cache {
+            audio_cache.clear();
+        }
+        Ok(())
+    }
+    fn prosody_features(&self) -> SonataResult<Vec<ProsodyFeature>> {
+        self.model.prosody_features()
+    }
+    fn set_contour(&self, feature: ProsodyFeature, contour: Option<Contour>) -> SonataResult<()> {
+        self.model.set_contour(feature, contour)
+    }
+    fn predict_durations(&self, phonemes: String) -> SonataResult<Vec<usize>> {
+        self.model.predict_durations(phonemes)
+    }
+    fn speak_with_durations(
+        &self,
+        phonemes: String,
+        overrides: &[(usize, DurationOverride)],
+    ) -> SonataAudioResult {
+        self.model.speak_with_durations(phonemes, overrides)
+    }
+    fn speak_with_alignment(&self, phonemes: String) -> SonataResult<(Audio, Vec<Range<usize>>)> {
+        self.model.speak_with_alignment(phonemes)
     }
 }
 
@@ -250,22 +1142,89 @@ struct SpeechSynthesisTaskProvider {
     model: Arc<dyn SonataModel + Sync + Send>,
     text: String,
     output_config: Option<AudioOutputConfig>,
+    /// The native rate the model speaks at, see [`SonataModel::native_rate`]
+    native_rate: f32,
+    /// The audio cache and this utterance's key, if caching is enabled
+    cache_entry: Option<(Arc<AudioCache>, u128)>,
+    error_recovery: ErrorRecovery,
+    recovery_report: RecoveryReport,
+    /// Time spent in the stages that run once for the utterance
+    timings: StageTimings,
+    thread_pool: Option<Arc<ThreadPool>>,
+    on_sentence: Option<SentenceCallback>,
+    watermark: Option<Arc<Watermark>>,
 }
 
 impl SpeechSynthesisTaskProvider {
-    fn get_phonemes(&self) -> SonataResult<Vec<String>> {
-        Ok(self.model.phonemize_text(&self.text)?.to_vec())
+    fn get_cached_audio(&self) -> Option<Vec<Audio>> {
+        let (ref cache, key) = self.cache_entry.as_ref()?;
+        cache.get(*key)
+    }
+    fn store_in_cache(&self, audio: Vec<Audio>) {
+        if let Some((ref cache, key)) = self.cache_entry {
+            cache.insert(key, audio);
+        }
+    }
+    fn get_phonemes(&mut self) -> SonataResult<Vec<String>> {
+        Ok(self.phonemize()?.to_vec())
+    }
+    /// Phonemize the text, along with the phonemizer's warnings
+    fn phonemize(&mut self) -> SonataResult<Phonemes> {
+        let timer = std::time::Instant::now();
+        let phonemes = self.model.phonemize_text(&self.text)?;
+        let model_timings = phonemes.timings();
+        // The model's own stages, e.g. tashkeel, are not part of phonemization
+        let phonemization_ms = timer.elapsed().as_secs_f32() * 1000.0 - model_timings.total_ms();
+        self.timings += model_timings;
+        self.timings.phonemization_ms += phonemization_ms.max(0.0);
+        Ok(phonemes)
+    }
+    /// Call the model at the native rate of the utterance
+    fn speak<T>(&self, speak: impl FnOnce() -> T) -> T {
+        with_native_rate(self.native_rate, speak)
     }
     fn process_one_sentence(&self, phonemes: String) -> SonataAudioResult {
-        let wave_samples = self.model.speak_one_sentence(phonemes)?;
-        match self.output_config {
-            Some(ref config) => config.apply(wave_samples),
-            None => Ok(wave_samples),
+        self.postprocess(self.speak(|| self.model.speak_one_sentence(phonemes))?)
+    }
+    /// Apply the output config and the watermark to the audio of a sentence
+    fn postprocess(&self, wave_samples: Audio) -> SonataAudioResult {
+        if self.output_config.is_none() && self.watermark.is_none() {
+            return Ok(wave_samples);
         }
+        let timings = wave_samples.timings;
+        let timer = std::time::Instant::now();
+        let mut audio = match self.output_config {
+            Some(ref config) => config.apply(wave_samples)?,
+            None => wave_samples,
+        };
+        if let Some(ref watermark) = self.watermark {
+            watermark.embed(audio.samples.as_mut_vec(), audio.info.num_channels, 0);
+        }
+        audio.timings = timings;
+        audio.timings.postprocessing_ms += timer.elapsed().as_secs_f32() * 1000.0;
+        Ok(audio)
+    }
+    /// Process the sentence at `index`, applying the error recovery policy
+    fn process_sentence(&self, index: usize, phonemes: String) -> SonataAudioResult {
+        let audio = self.error_recovery.run(
+            index,
+            &phonemes,
+            || self.model.audio_output_info(),
+            &self.recovery_report,
+            || self.process_one_sentence(phonemes.clone()),
+        )?;
+        if let Some(ref on_sentence) = self.on_sentence {
+            on_sentence(index, &audio);
+        }
+        Ok(audio)
+    }
+    /// Whether the utterance can be cached: sentences replaced by placeholders are not
+    fn is_complete(&self) -> bool {
+        self.recovery_report.is_empty()
     }
     #[allow(dead_code)]
     fn process_batches(&self, phonemes: Vec<String>) -> SonataResult<Vec<Audio>> {
-        let wave_samples = self.model.speak_batch(phonemes)?;
+        let wave_samples = self.speak(|| self.model.speak_batch(phonemes))?;
         match self.output_config {
             Some(ref config) => {
                 let mut processed: Vec<Audio> = Vec::with_capacity(wave_samples.len());
@@ -282,26 +1241,88 @@ impl SpeechSynthesisTaskProvider {
 pub struct SonataSpeechStreamLazy {
     provider: SpeechSynthesisTaskProvider,
     sentence_phonemes: std::vec::IntoIter<String>,
+    cached_audio: std::vec::IntoIter<Audio>,
+    /// Audio synthesized so far, stored in the audio cache once the stream is exhausted
+    synthesized_audio: Option<Vec<Audio>>,
+    next_index: usize,
+    stats: SynthesisStats,
 }
 
 impl SonataSpeechStreamLazy {
-    fn new(provider: SpeechSynthesisTaskProvider) -> SonataResult<Self> {
+    fn new(mut provider: SpeechSynthesisTaskProvider) -> SonataResult<Self> {
+        if let Some(cached_audio) = provider.get_cached_audio() {
+            let stats = SynthesisStats::for_utterance(provider.timings);
+            return Ok(Self {
+                provider,
+                sentence_phonemes: Vec::new().into_iter(),
+                cached_audio: cached_audio.into_iter(),
+                synthesized_audio: None,
+                next_index: 0,
+                stats,
+            });
+        }
         let sentence_phonemes = provider.get_phonemes()?.into_iter();
+        let synthesized_audio = provider.cache_entry.as_ref().map(|_| Vec::new());
+        let stats = SynthesisStats::for_utterance(provider.timings);
         Ok(Self {
             provider,
             sentence_phonemes,
+            cached_audio: Vec::new().into_iter(),
+            synthesized_audio,
+            next_index: 0,
+            stats,
         })
     }
+    /// Start after the first `count` sentences, which are then not cached
+    fn skip_sentences(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.sentence_phonemes.nth(count - 1);
+        self.next_index += count;
+        self.synthesized_audio = None;
+    }
+    /// The sentences replaced by placeholders so far, see [`ErrorRecovery`]
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.provider.recovery_report
+    }
+    /// Audio duration and inference time of the sentences yielded so far
+    pub fn stats(&self) -> &SynthesisStats {
+        &self.stats
+    }
 }
 
 impl Iterator for SonataSpeechStreamLazy {
     type Item = SonataAudioResult;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let phonemes = self.sentence_phonemes.next()?;
-        match self.provider.process_one_sentence(phonemes) {
-            Ok(ws) => Some(Ok(ws)),
-            Err(e) => Some(Err(e)),
+        if let Some(audio) = self.cached_audio.next() {
+            self.stats.add(&audio);
+            return Some(Ok(audio));
+        }
+        let Some(phonemes) = self.sentence_phonemes.next() else {
+            if let Some(synthesized_audio) = self.synthesized_audio.take() {
+                if self.provider.is_complete() {
+                    self.provider.store_in_cache(synthesized_audio);
+                }
+            }
+            return None;
+        };
+        let index = self.next_index;
+        self.next_index += 1;
+        match self.provider.process_sentence(index, phonemes) {
+            Ok(ws) => {
+                self.stats.add(&ws);
+                if let Some(ref mut synthesized_audio) = self.synthesized_audio {
+                    synthesized_audio.push(ws.clone());
+                }
+                Some(Ok(ws))
+            }
+            Err(e) => {
+                // Incomplete utterances are not cached
+                self.synthesized_audio = None;
+                Some(Err(e))
+            }
         }
     }
 }
@@ -309,111 +1330,293 @@ impl Iterator for SonataSpeechStreamLazy {
 #[must_use]
 pub struct SonataSpeechStreamParallel {
     precalculated_results: std::vec::IntoIter<SonataAudioResult>,
+    recovery_report: RecoveryReport,
+    stats: SynthesisStats,
 }
 
 impl SonataSpeechStreamParallel {
     fn new(provider: SpeechSynthesisTaskProvider) -> SonataResult<Self> {
-        let calculated_result: Vec<SonataAudioResult> = provider
-            .get_phonemes()?
-            .par_iter()
-            .map(|ph| provider.process_one_sentence(ph.to_string()))
-            .collect();
+        Self::with_phonemes(provider, None)
+    }
+    /// Like `new`, with the sentences of the provider's text already phonemized, if any
+    fn with_phonemes(
+        mut provider: SpeechSynthesisTaskProvider,
+        phonemes: Option<Vec<String>>,
+    ) -> SonataResult<Self> {
+        if let Some(cached_audio) = provider.get_cached_audio() {
+            return Ok(Self {
+                precalculated_results: Vec::from_iter(cached_audio.into_iter().map(Ok)).into_iter(),
+                recovery_report: provider.recovery_report,
+                stats: SynthesisStats::for_utterance(provider.timings),
+            });
+        }
+        let phonemes = match phonemes {
+            Some(phonemes) => phonemes,
+            None => provider.get_phonemes()?,
+        };
+        let synthesize = || -> Vec<SonataAudioResult> {
+            phonemes
+                .into_par_iter()
+                .enumerate()
+                .map(|(index, ph)| provider.process_sentence(index, ph))
+                .collect()
+        };
+        let calculated_result = match provider.thread_pool {
+            Some(ref thread_pool) => thread_pool.install(synthesize),
+            None => synthesize(),
+        };
+        if provider.cache_entry.is_some()
+            && provider.is_complete()
+            && calculated_result.iter().all(Result::is_ok)
+        {
+            provider.store_in_cache(Vec::from_iter(calculated_result.iter().flatten().cloned()));
+        }
         Ok(Self {
             precalculated_results: calculated_result.into_iter(),
+            recovery_report: provider.recovery_report,
+            stats: SynthesisStats::for_utterance(provider.timings),
         })
     }
+    /// The sentences replaced by placeholders, see [`ErrorRecovery`]
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+    /// Audio duration and inference time of the sentences yielded so far
+    pub fn stats(&self) -> &SynthesisStats {
+        &self.stats
+    }
 }
 
 impl Iterator for SonataSpeechStreamParallel {
     type Item = SonataAudioResult;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.precalculated_results.next()
+        let result = self.precalculated_results.next()?;
+        if let Ok(ref audio) = result {
+            self.stats.add(audio);
+        }
+        Some(result)
     }
 }
 
-pub struct RealtimeSpeechStream(Receiver<SonataResult<AudioSamples>>);
+/// Audio chunks, along with the index of their sentence
+type SentenceChunk = (usize, SonataResult<AudioSamples>);
+
+pub struct RealtimeSpeechStream {
+    receiver: Receiver<SentenceChunk>,
+    control: StreamControl,
+    recovery_report: RecoveryReport,
+    /// The sentence of the last returned chunk
+    sentence_index: usize,
+    /// Samples of the fade-out ending the stream when it is stopped
+    fade_out_len: usize,
+    finished: bool,
+    /// Whether all the audio was returned, before the stream was stopped if it was
+    complete: bool,
+}
 
 impl RealtimeSpeechStream {
     fn new(
-        provider: SpeechSynthesisTaskProvider,
+        mut provider: SpeechSynthesisTaskProvider,
+        first_sentence: usize,
+        control: StreamControl,
         chunk_size: usize,
         chunk_padding: usize,
-        sample_rate: usize,
-        num_channels: usize,
+        buffer_depth: usize,
     ) -> SonataResult<Self> {
-        let phonemes = provider.get_phonemes()?.into_iter();
-        let (tx, rx) = flume::unbounded();
-        SYNTHESIS_THREAD_POOL.spawn(move || {
+        let wavinfo = provider.model.audio_output_info()?;
+        let (sample_rate, num_channels) = (wavinfo.sample_rate, wavinfo.num_channels);
+        let phonemes = provider
+            .get_phonemes()?
+            .into_iter()
+            .enumerate()
+            .skip(first_sentence);
+        // Sending blocks while the buffer is full, pausing inference until the consumer
+        // catches up. Dropping the stream disconnects the channel and stops synthesis.
+        let (tx, rx) = flume::bounded(buffer_depth);
+        let stream_control = control.clone();
+        let recovery_report = provider.recovery_report.clone();
+        let synthesize = move || {
             let mut chunk_size = chunk_size;
             let chunk_factor = 1;
             let mut num_processed_chunks = 0;
-            for ph_sent in phonemes {
+            for (sentence_index, ph_sent) in phonemes {
+                if stream_control.is_stopped() {
+                    return;
+                }
                 chunk_size = if num_processed_chunks != 0 {
-                    chunk_size  * chunk_factor * num_processed_chunks
+                    chunk_size * chunk_factor * num_processed_chunks
                 } else {
                     chunk_size
                 };
-                match provider
-                    .model
-                    .stream_synthesis(ph_sent, chunk_size, chunk_padding)
-                {
+                let stream = provider.speak(|| {
+                    provider.model.stream_synthesis_with_control(
+                        ph_sent.clone(),
+                        chunk_size,
+                        chunk_padding,
+                        stream_control.clone(),
+                    )
+                });
+                match stream {
                     Ok(stream) => {
                         let send_result = RealtimeSpeechStream::process_rt_stream(
                             stream,
+                            sentence_index,
                             &tx,
-                            provider.output_config.as_ref(),
+                            &provider,
                             sample_rate,
                             num_channels,
+                            &stream_control,
                         );
                         match send_result {
                             Ok(num_chunks) => num_processed_chunks += num_chunks,
-                            Err(_) => return
+                            Err(_) => return,
                         };
                     }
                     Err(e) => {
-                        tx.send(Err(e)).ok();
-                        return;
+                        // The failed stream counts as the first attempt of the sentence,
+                        // which is then synthesized in one go
+                        let mut stream_error = Some(e);
+                        let result = provider.error_recovery.run(
+                            sentence_index,
+                            &ph_sent,
+                            || provider.model.audio_output_info(),
+                            &provider.recovery_report,
+                            || match stream_error.take() {
+                                Some(e) => Err(e),
+                                None => provider.process_one_sentence(ph_sent.clone()),
+                            },
+                        );
+                        let failed = result.is_err();
+                        let sent = tx.send((sentence_index, result.map(|audio| audio.samples)));
+                        if failed || sent.is_err() {
+                            return;
+                        }
+                        num_processed_chunks += 1;
                     }
                 };
             }
-        });
-        Ok(Self(rx))
+        };
+        // Synthesis runs on a thread of its own rather than on a thread pool, since it
+        // blocks while the buffer is full, e.g. for as long as the stream is paused
+        std::thread::Builder::new()
+            .name("sonata_realtime".to_string())
+            .spawn(synthesize)
+            .map_err(|e| {
+                SonataError::OperationError("Failed to start synthesis thread".to_string())
+                    .caused_by(e)
+            })?;
+        let fade_out_len = (sample_rate * num_channels) * STOP_FADE_OUT.as_millis() as usize / 1000;
+        Ok(Self {
+            receiver: rx,
+            control,
+            recovery_report,
+            sentence_index: first_sentence,
+            fade_out_len,
+            finished: false,
+            complete: false,
+        })
+    }
+    /// A handle that stops, pauses and resumes the stream, from any thread
+    pub fn stop_handle(&self) -> StreamControl {
+        self.control.clone()
+    }
+    /// Stop the stream, like `stop_handle().stop(fade_out)`
+    pub fn stop(&self, fade_out: bool) {
+        self.control.stop(fade_out);
+    }
+    /// Hold back the chunks that were synthesized until the stream is resumed. Synthesis
+    /// stops once the buffer is full
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+    /// Index of the sentence of the text the last returned chunk belongs to
+    pub fn sentence_index(&self) -> usize {
+        self.sentence_index
+    }
+    /// The sentences replaced by placeholders so far, see
+    /// [`SonataSpeechSynthesizer::with_error_recovery`]
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+    /// Whether the stream ended after returning all of its audio. Stopping the stream
+    /// once it ended doesn't change that
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+    fn receive(&mut self, chunk: SentenceChunk) -> SonataResult<AudioSamples> {
+        let (sentence_index, result) = chunk;
+        self.sentence_index = sentence_index;
+        result
+    }
+    /// The chunk ending a stopped stream: the beginning of the audio that follows what was
+    /// returned, faded out
+    fn fade_out_chunk(&mut self) -> Option<SonataResult<AudioSamples>> {
+        let next = match self.receiver.try_recv() {
+            Ok(chunk) => Some(chunk),
+            // Synthesis is stopping, and may send the end of the last chunk
+            Err(_) => self.receiver.recv().ok(),
+        };
+        let mut samples = self.receive(next?).ok()?;
+        samples.as_mut_vec().truncate(self.fade_out_len);
+        samples.fade_out(self.fade_out_len);
+        Some(Ok(samples))
     }
     #[inline(always)]
     fn process_rt_stream(
         stream: AudioStreamIterator,
-        tx: &Sender<SonataResult<AudioSamples>>,
-        audio_output_config: Option<&AudioOutputConfig>,
+        sentence_index: usize,
+        tx: &Sender<SentenceChunk>,
+        provider: &SpeechSynthesisTaskProvider,
         sample_rate: usize,
         num_channels: usize,
-    ) -> Result<usize, SendError<SonataResult<AudioSamples>>> {
+        control: &StreamControl,
+    ) -> Result<usize, SendError<SentenceChunk>> {
         let mut num_chunks = 0;
-        if let Some(output_config) = audio_output_config {
+        let watermark = provider.watermark.as_deref();
+        // Frames of the sentence sent so far, where the watermark of the next chunk starts
+        let mut offset = 0;
+        let mut mark = |result: SonataResult<AudioSamples>| {
+            result.map(|mut samples| {
+                if let Some(watermark) = watermark {
+                    watermark.embed(samples.as_mut_vec(), num_channels, offset);
+                }
+                offset += samples.len() / num_channels.max(1);
+                samples
+            })
+        };
+        if let Some(ref output_config) = provider.output_config {
             for result in stream {
                 match result {
                     Ok(samples) => {
-                        tx.send(output_config.apply_to_raw_samples(
+                        let samples = mark(output_config.apply_to_raw_samples(
                             samples,
                             sample_rate,
                             num_channels,
-                        ))?;
+                        ));
+                        tx.send((sentence_index, samples))?;
                         num_chunks += 1;
                     }
                     Err(e) => {
-                        tx.send(Err(e))?;
+                        tx.send((sentence_index, Err(e)))?;
                     }
                 };
             }
+            if control.is_stopped() {
+                return Ok(num_chunks);
+            }
             if let Some(silence_ms) = output_config.appended_silence_ms {
                 let silence_result =
                     output_config.generate_silence(silence_ms as usize, sample_rate, num_channels);
-                tx.send(silence_result)?;
+                tx.send((sentence_index, silence_result))?;
             }
             Ok(num_chunks)
         } else {
             for result in stream {
-                tx.send(result)?;
+                tx.send((sentence_index, mark(result)))?;
                 num_chunks += 1;
             }
             Ok(num_chunks)
@@ -425,6 +1628,146 @@ impl Iterator for RealtimeSpeechStream {
     type Item = SonataResult<AudioSamples>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.recv().ok()
+        if self.finished {
+            return None;
+        }
+        self.control.wait_while_paused();
+        if !self.control.is_stopped() {
+            let Ok(chunk) = self.receiver.recv() else {
+                self.complete = true;
+                return None;
+            };
+            return Some(self.receive(chunk));
+        }
+        self.finished = true;
+        let fade_out = if self.control.fades_out() {
+            self.fade_out_chunk()
+        } else {
+            None
+        };
+        // Unblock synthesis, so that it sees the stream was stopped
+        self.receiver.drain();
+        fade_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SAMPLE_RATE: usize = 16000;
+
+    /// Streams chunks of a constant signal, until it is stopped. Sentences last 1600
+    /// samples, divided by the native rate, which goes up to `2.0`
+    #[derive(Default)]
+    struct ConstantModel {
+        /// Number of times text was phonemized
+        phonemized: AtomicUsize,
+    }
+
+    impl SonataModel for ConstantModel {
+        fn audio_output_info(&self) -> SonataResult<AudioInfo> {
+            Ok(AudioInfo {
+                sample_rate: SAMPLE_RATE,
+                num_channels: 1,
+                sample_width: 2,
+            })
+        }
+        fn phonemize_text(&self, text: &str) -> SonataResult<Phonemes> {
+            self.phonemized.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![text.to_string()].into())
+        }
+        fn speak_batch(&self, phoneme_batches: Vec<String>) -> SonataResult<Vec<Audio>> {
+            Result::from_iter(
+                phoneme_batches
+                    .into_iter()
+                    .map(|phonemes| self.speak_one_sentence(phonemes)),
+            )
+        }
+        fn speak_one_sentence(&self, _phonemes: String) -> SonataAudioResult {
+            let len = (1600.0 / current_native_rate()) as usize;
+            Ok(Audio::new(vec![1.0; len].into(), SAMPLE_RATE, None))
+        }
+        fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+            Ok(Box::new(()))
+        }
+        fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+            Ok(Box::new(()))
+        }
+        fn set_fallback_synthesis_config(&self, _synthesis_config: &dyn Any) -> SonataResult<()> {
+            Ok(())
+        }
+        fn native_rate(&self, rate: f32) -> SonataResult<f32> {
+            Ok(rate.min(2.0))
+        }
+        fn supports_streaming_output(&self) -> bool {
+            true
+        }
+        fn stream_synthesis(
+            &self,
+            _phonemes: String,
+            _chunk_size: usize,
+            _chunk_padding: usize,
+        ) -> SonataResult<AudioStreamIterator<'_>> {
+            Ok(Box::new(std::iter::repeat_with(|| {
+                Ok(vec![1.0; 1600].into())
+            })))
+        }
+    }
+
+    #[test]
+    fn test_stop_with_fade_out() {
+        let synth = SonataSpeechSynthesizer::new(Arc::new(ConstantModel::default())).unwrap();
+        let mut stream = synth
+            .synthesize_streamed("a".to_string(), None, 72, 3)
+            .unwrap();
+        let first = stream.next().unwrap().unwrap();
+        assert!(first.as_slice().iter().all(|sample| *sample == 1.0));
+        // The fade-out is made of audio that was synthesized but not returned yet
+        while stream.receiver.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stream.stop(true);
+        let fade_out = stream.next().unwrap().unwrap().into_vec();
+        let fade_out_len = SAMPLE_RATE * STOP_FADE_OUT.as_millis() as usize / 1000;
+        assert_eq!(fade_out.len(), fade_out_len);
+        // Ramps down from the level of the audio to silence
+        assert!(fade_out[0] > 0.99);
+        assert!(fade_out.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(fade_out[fade_out_len - 1], 0.0);
+        assert!(stream.next().is_none());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_rate_multiplier_per_synthesizer() {
+        let voice: Arc<dyn SonataModel + Send + Sync> = Arc::new(ConstantModel::default());
+        let fast = SonataSpeechSynthesizer::new(Arc::clone(&voice)).unwrap();
+        let slow = SonataSpeechSynthesizer::new(voice).unwrap();
+        fast.set_rate_multiplier(1.6).unwrap();
+        slow.set_rate_multiplier(0.8).unwrap();
+        assert!(fast.set_rate_multiplier(5.0).is_err());
+        let len = |synth: &SonataSpeechSynthesizer| {
+            let audio = synth.synthesize_lazy("a".to_string(), None).unwrap();
+            audio.map(|audio| audio.unwrap().len()).sum::<usize>()
+        };
+        // Each synthesizer speaks at its own rate, though they share the voice
+        assert_eq!(len(&fast), 1000);
+        assert_eq!(len(&slow), 2000);
+        // The model goes up to twice as fast, the audio is time-stretched for the rest
+        fast.set_rate_multiplier(3.0).unwrap();
+        assert_eq!(*fast.rate.read().unwrap(), (2.0, 1.5));
+        assert_eq!(*slow.rate.read().unwrap(), (0.8, 1.0));
+    }
+
+    #[test]
+    fn test_report_phonemizes_once() {
+        let model = Arc::new(ConstantModel::default());
+        let synth = SonataSpeechSynthesizer::new(Arc::clone(&model) as _).unwrap();
+        let (audio, report) = synth.synthesize_with_report("a".to_string(), None).unwrap();
+        assert_eq!(model.phonemized.load(Ordering::Relaxed), 1);
+        assert_eq!(report.sentences.len(), 1);
+        assert_eq!(audio.len(), 1600);
     }
 }
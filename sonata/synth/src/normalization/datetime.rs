@@ -0,0 +1,310 @@
+//! Locale-aware expansion of numeric dates and times.
+//!
+//! espeak-ng reads `2024-05-03` as a subtraction in some languages and as a date in
+//! others. Dates and times are rewritten into the spoken form of the voice's
+//! locale, e.g. `May 3rd, 2024` for `en_US` and `3. Mai 2024` for `de_DE`, leaving
+//! the numbers themselves to espeak-ng.
+
+use super::{primary_language, NormalizationStep};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static ISO_DATE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})\b").unwrap());
+static NUMERIC_DATE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(\d{1,2})([/.])(\d{1,2})[/.](\d{4}|\d{2})\b").unwrap());
+static TIME_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b([01]?\d|2[0-3]):([0-5]\d)(?::([0-5]\d))?(?:\s*([ap])\.?m\.?\b)?").unwrap()
+});
+
+#[rustfmt::skip]
+const MONTH_NAMES: &[(&str, [&str; 12])] = &[
+    ("en", ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"]),
+    ("de", ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"]),
+    ("fr", ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"]),
+    ("es", ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"]),
+];
+
+/// The order of the day and month in dates such as `3/5/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+}
+
+impl DateOrder {
+    /// Month first in the US and a few other English-speaking regions, day first elsewhere
+    fn for_language(language: Option<&str>) -> Self {
+        let Some(language) = language else {
+            return Self::MonthDayYear;
+        };
+        let mut parts = language.split(['_', '-']);
+        let primary = parts.next().unwrap_or_default().to_lowercase();
+        let region = parts.next().map(str::to_uppercase);
+        match (primary.as_str(), region.as_deref()) {
+            ("en", None | Some("US" | "PH" | "CA")) => Self::MonthDayYear,
+            _ => Self::DayMonthYear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpokenDate {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpokenTime {
+    /// 0 to 23
+    pub hour: u32,
+    pub minute: u32,
+    pub second: Option<u32>,
+}
+
+type DateFormatter = dyn Fn(&SpokenDate, Option<&str>) -> Option<String> + Send + Sync;
+type TimeFormatter = dyn Fn(&SpokenTime, Option<&str>) -> Option<String> + Send + Sync;
+
+#[derive(Default)]
+pub struct DateTimeExpander {
+    date_order: Option<DateOrder>,
+    date_formatter: Option<Box<DateFormatter>>,
+    time_formatter: Option<Box<TimeFormatter>>,
+}
+
+impl DateTimeExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Override the day/month order of numeric dates, which otherwise follows the
+    /// voice's region
+    pub fn with_date_order(mut self, date_order: DateOrder) -> Self {
+        self.date_order = Some(date_order);
+        self
+    }
+    /// Format dates with `formatter`, which receives the voice's language code.
+    /// Returning `None` falls back to the built-in format.
+    pub fn with_date_formatter(
+        mut self,
+        formatter: impl Fn(&SpokenDate, Option<&str>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.date_formatter = Some(Box::new(formatter));
+        self
+    }
+    /// Format times with `formatter`, which receives the voice's language code.
+    /// Returning `None` falls back to the built-in format.
+    pub fn with_time_formatter(
+        mut self,
+        formatter: impl Fn(&SpokenTime, Option<&str>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.time_formatter = Some(Box::new(formatter));
+        self
+    }
+    fn format_date(&self, date: &SpokenDate, language: Option<&str>) -> Option<String> {
+        if !(1..=12).contains(&date.month) || !(1..=31).contains(&date.day) {
+            return None;
+        }
+        if let Some(formatted) = self
+            .date_formatter
+            .as_ref()
+            .and_then(|formatter| formatter(date, language))
+        {
+            return Some(formatted);
+        }
+        let primary = primary_language(language).unwrap_or_else(|| "en".to_string());
+        let (_, months) = MONTH_NAMES.iter().find(|(code, _)| *code == primary)?;
+        let month = months[date.month as usize - 1];
+        let (year, day) = (date.year, date.day);
+        let formatted = match primary.as_str() {
+            "en" => {
+                let suffix = match (day % 10, day % 100) {
+                    (1, 11) | (2, 12) | (3, 13) => "th",
+                    (1, _) => "st",
+                    (2, _) => "nd",
+                    (3, _) => "rd",
+                    _ => "th",
+                };
+                match DateOrder::for_language(language) {
+                    DateOrder::MonthDayYear => format!("{month} {day}{suffix}, {year}"),
+                    DateOrder::DayMonthYear => format!("the {day}{suffix} of {month} {year}"),
+                }
+            }
+            "de" => format!("{day}. {month} {year}"),
+            "fr" if day == 1 => format!("1er {month} {year}"),
+            "fr" => format!("{day} {month} {year}"),
+            "es" => format!("{day} de {month} de {year}"),
+            _ => return None,
+        };
+        Some(formatted)
+    }
+    fn format_time(
+        &self,
+        time: &SpokenTime,
+        meridiem: Option<char>,
+        language: Option<&str>,
+    ) -> Option<String> {
+        if let Some(formatted) = self
+            .time_formatter
+            .as_ref()
+            .and_then(|formatter| formatter(time, language))
+        {
+            return Some(formatted);
+        }
+        let primary = primary_language(language).unwrap_or_else(|| "en".to_string());
+        let SpokenTime {
+            hour,
+            minute,
+            second,
+        } = *time;
+        let formatted = match primary.as_str() {
+            "en" => {
+                let (hour, meridiem) = match meridiem {
+                    Some(meridiem) if hour > 12 => (hour - 12, meridiem.to_ascii_uppercase()),
+                    Some(meridiem) => (hour, meridiem.to_ascii_uppercase()),
+                    None if hour < 12 => (hour, 'A'),
+                    None => (hour - 12, 'P'),
+                };
+                let hour = if hour == 0 { 12 } else { hour };
+                let mut formatted = match minute {
+                    0 => format!("{hour} o'clock"),
+                    1..=9 => format!("{hour} oh {minute}"),
+                    _ => format!("{hour} {minute}"),
+                };
+                if let Some(second) = second {
+                    formatted.push_str(&format!(" and {second} seconds"));
+                }
+                format!("{formatted} {meridiem} M")
+            }
+            "de" => match second {
+                Some(second) => format!("{hour} Uhr {minute} und {second} Sekunden"),
+                None if minute == 0 => format!("{hour} Uhr"),
+                None => format!("{hour} Uhr {minute}"),
+            },
+            "fr" => {
+                let heures = if hour <= 1 { "heure" } else { "heures" };
+                match second {
+                    Some(second) => format!("{hour} {heures} {minute} et {second} secondes"),
+                    None if minute == 0 => format!("{hour} {heures}"),
+                    None => format!("{hour} {heures} {minute}"),
+                }
+            }
+            "es" => match second {
+                Some(second) => format!("{hour} horas {minute} minutos y {second} segundos"),
+                None if minute == 0 => format!("{hour} horas"),
+                None => format!("{hour} y {minute}"),
+            },
+            _ => return None,
+        };
+        Some(formatted)
+    }
+}
+
+impl NormalizationStep for DateTimeExpander {
+    fn normalize(&self, text: &str, language: Option<&str>) -> String {
+        let text = ISO_DATE_PATTERN.replace_all(text, |caps: &Captures| {
+            let date = SpokenDate {
+                year: caps[1].parse().unwrap_or_default(),
+                month: caps[2].parse().unwrap_or_default(),
+                day: caps[3].parse().unwrap_or_default(),
+            };
+            self.format_date(&date, language)
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        let text = NUMERIC_DATE_PATTERN.replace_all(&text, |caps: &Captures| {
+            let (first, second): (u32, u32) = (
+                caps[1].parse().unwrap_or_default(),
+                caps[3].parse().unwrap_or_default(),
+            );
+            // Dotted dates are always day first
+            let order = match &caps[2] {
+                "." => DateOrder::DayMonthYear,
+                _ => self
+                    .date_order
+                    .unwrap_or_else(|| DateOrder::for_language(language)),
+            };
+            let (day, month) = match order {
+                DateOrder::DayMonthYear => (first, second),
+                DateOrder::MonthDayYear => (second, first),
+            };
+            let year: i32 = caps[4].parse().unwrap_or_default();
+            let date = SpokenDate {
+                year: if caps[4].len() == 2 {
+                    2000 + year
+                } else {
+                    year
+                },
+                month,
+                day,
+            };
+            self.format_date(&date, language)
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        let text = TIME_PATTERN.replace_all(&text, |caps: &Captures| {
+            let time = SpokenTime {
+                hour: caps[1].parse().unwrap_or_default(),
+                minute: caps[2].parse().unwrap_or_default(),
+                second: caps.get(3).and_then(|second| second.as_str().parse().ok()),
+            };
+            let meridiem = caps
+                .get(4)
+                .and_then(|meridiem| meridiem.as_str().chars().next());
+            self.format_time(&time, meridiem, language)
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        text.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_dates() {
+        let expander = DateTimeExpander::new();
+        assert_eq!(
+            expander.normalize("Due 2024-05-03 or 3/5/24.", Some("en_US")),
+            "Due May 3rd, 2024 or March 5th, 2024."
+        );
+        assert_eq!(
+            expander.normalize("Due 3/5/24", Some("en_GB")),
+            "Due the 3rd of May 2024"
+        );
+        assert_eq!(
+            expander.normalize("Am 03.05.2024", Some("de_DE")),
+            "Am 3. Mai 2024"
+        );
+        assert_eq!(
+            expander.normalize("Le 1/5/2024", Some("fr_FR")),
+            "Le 1er mai 2024"
+        );
+        assert_eq!(expander.normalize("2024-13-40", Some("en")), "2024-13-40");
+    }
+
+    #[test]
+    fn test_expand_times() {
+        let expander = DateTimeExpander::new();
+        assert_eq!(
+            expander.normalize("At 14:30 or 9:05 am", Some("en_US")),
+            "At 2 30 P M or 9 oh 5 A M"
+        );
+        assert_eq!(expander.normalize("Um 14:00", Some("de_DE")), "Um 14 Uhr");
+    }
+
+    #[test]
+    fn test_override_formats() {
+        let expander = DateTimeExpander::new()
+            .with_date_order(DateOrder::DayMonthYear)
+            .with_date_formatter(|date, _| Some(format!("{}/{}", date.month, date.day)))
+            .with_time_formatter(|time, language| {
+                (language == Some("en_US")).then(|| format!("{} hundred hours", time.hour))
+            });
+        assert_eq!(
+            expander.normalize("3/5/24 at 14:00", Some("en_US")),
+            "5/3 at 14 hundred hours"
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Emoji and emoticon verbalization.
+//!
+//! espeak-ng either skips emoji or reads out their code points, which ruins
+//! the narration of chat messages. Emoji are either replaced with a short description
+//! in the voice's language, or removed.
+
+use super::{collapse_spaces, primary_language, NormalizationStep};
+use std::collections::HashMap;
+
+/// Descriptions of common emoji, in English, German, French and Spanish
+#[rustfmt::skip]
+const EMOJI_DESCRIPTIONS: &[(&str, [&str; 4])] = &[
+    ("😀", ["grinning face", "grinsendes Gesicht", "visage rieur", "cara sonriendo"]),
+    ("😁", ["beaming face", "strahlendes Gesicht", "visage souriant aux yeux rieurs", "cara radiante"]),
+    ("😂", ["face with tears of joy", "Gesicht mit Freudentränen", "visage riant aux larmes", "cara llorando de risa"]),
+    ("🤣", ["rolling on the floor laughing", "sich vor Lachen auf dem Boden wälzen", "se rouler par terre de rire", "revolcándose de la risa"]),
+    ("😃", ["grinning face with big eyes", "grinsendes Gesicht mit großen Augen", "visage souriant avec de grands yeux", "cara sonriendo con ojos grandes"]),
+    ("😄", ["grinning face with smiling eyes", "grinsendes Gesicht mit lachenden Augen", "visage souriant avec des yeux rieurs", "cara sonriendo con ojos sonrientes"]),
+    ("😅", ["grinning face with sweat", "grinsendes Gesicht mit Schweißtropfen", "visage souriant avec une goutte de sueur", "cara sonriendo con sudor frío"]),
+    ("😆", ["grinning squinting face", "grinsendes Gesicht mit zugekniffenen Augen", "visage souriant aux yeux plissés", "cara sonriendo con los ojos cerrados"]),
+    ("😉", ["winking face", "zwinkerndes Gesicht", "visage faisant un clin d'œil", "cara guiñando el ojo"]),
+    ("😊", ["smiling face with smiling eyes", "lächelndes Gesicht mit lachenden Augen", "visage souriant avec yeux rieurs", "cara feliz con ojos sonrientes"]),
+    ("🙂", ["slightly smiling face", "leicht lächelndes Gesicht", "visage avec un léger sourire", "cara sonriendo ligeramente"]),
+    ("🙃", ["upside-down face", "umgekehrtes Gesicht", "visage à l'envers", "cara al revés"]),
+    ("😍", ["smiling face with heart-eyes", "lächelndes Gesicht mit Herzaugen", "visage souriant avec yeux en forme de cœur", "cara sonriendo con ojos de corazón"]),
+    ("😘", ["face blowing a kiss", "Kuss zuwerfendes Gesicht", "visage envoyant un bisou", "cara lanzando un beso"]),
+    ("😎", ["smiling face with sunglasses", "lächelndes Gesicht mit Sonnenbrille", "visage avec lunettes de soleil", "cara sonriendo con gafas de sol"]),
+    ("🤔", ["thinking face", "nachdenkendes Gesicht", "visage en pleine réflexion", "cara pensativa"]),
+    ("😐", ["neutral face", "neutrales Gesicht", "visage neutre", "cara neutral"]),
+    ("😑", ["expressionless face", "ausdrucksloses Gesicht", "visage sans expression", "cara sin expresión"]),
+    ("🙄", ["face with rolling eyes", "Augen verdrehendes Gesicht", "visage roulant des yeux", "cara con ojos en blanco"]),
+    ("😏", ["smirking face", "süffisant lächelndes Gesicht", "visage avec un sourire malin", "cara sonriendo con superioridad"]),
+    ("😛", ["face with tongue", "Gesicht mit herausgestreckter Zunge", "visage qui tire la langue", "cara sacando la lengua"]),
+    ("😮", ["face with open mouth", "Gesicht mit offenem Mund", "visage avec la bouche ouverte", "cara con la boca abierta"]),
+    ("😕", ["confused face", "verwundertes Gesicht", "visage confus", "cara de confusión"]),
+    ("😴", ["sleeping face", "schlafendes Gesicht", "visage endormi", "cara durmiendo"]),
+    ("😢", ["crying face", "weinendes Gesicht", "visage qui pleure", "cara llorando"]),
+    ("😭", ["loudly crying face", "heulendes Gesicht", "visage qui pleure à chaudes larmes", "cara llorando fuerte"]),
+    ("😞", ["disappointed face", "enttäuschtes Gesicht", "visage déçu", "cara decepcionada"]),
+    ("😟", ["worried face", "besorgtes Gesicht", "visage inquiet", "cara preocupada"]),
+    ("😠", ["angry face", "verärgertes Gesicht", "visage en colère", "cara enfadada"]),
+    ("😡", ["enraged face", "wütendes Gesicht", "visage boudeur", "cara cabreada"]),
+    ("😱", ["face screaming in fear", "vor Angst schreiendes Gesicht", "visage qui hurle de peur", "cara gritando de miedo"]),
+    ("😳", ["flushed face", "errötetes Gesicht", "visage qui rougit", "cara sonrojada"]),
+    ("🥳", ["partying face", "Partygesicht", "visage de fête", "cara de fiesta"]),
+    ("🥺", ["pleading face", "bittendes Gesicht", "visage suppliant", "cara suplicante"]),
+    ("🤗", ["hugging face", "Gesicht mit umarmenden Händen", "visage qui fait un câlin", "cara con manos abrazando"]),
+    ("🤦", ["person facepalming", "sich an den Kopf fassende Person", "personne avec la paume sur le visage", "persona con la mano en la frente"]),
+    ("🤷", ["person shrugging", "schulterzuckende Person", "personne qui hausse les épaules", "persona encogida de hombros"]),
+    ("👍", ["thumbs up", "Daumen hoch", "pouce vers le haut", "pulgar hacia arriba"]),
+    ("👎", ["thumbs down", "Daumen runter", "pouce vers le bas", "pulgar hacia abajo"]),
+    ("👏", ["clapping hands", "klatschende Hände", "applaudissements", "manos aplaudiendo"]),
+    ("🙏", ["folded hands", "zusammengelegte Handflächen", "mains en prière", "manos en oración"]),
+    ("👋", ["waving hand", "winkende Hand", "main qui fait coucou", "mano saludando"]),
+    ("👌", ["OK hand", "OK-Zeichen", "main faisant un signe d'approbation", "señal de aprobación con la mano"]),
+    ("💪", ["flexed biceps", "angespannter Bizeps", "biceps contracté", "bíceps flexionado"]),
+    ("❤", ["red heart", "rotes Herz", "cœur rouge", "corazón rojo"]),
+    ("💔", ["broken heart", "gebrochenes Herz", "cœur brisé", "corazón roto"]),
+    ("🔥", ["fire", "Feuer", "feu", "fuego"]),
+    ("✨", ["sparkles", "funkelnde Sterne", "étincelles", "chispas"]),
+    ("🎉", ["party popper", "Konfettibombe", "cotillons", "cañón de confeti"]),
+    ("🎂", ["birthday cake", "Geburtstagskuchen", "gâteau d'anniversaire", "tarta de cumpleaños"]),
+    ("💯", ["hundred points", "100 Punkte", "cent points", "cien puntos"]),
+    ("✅", ["check mark", "Häkchen", "coche", "marca de verificación"]),
+    ("❌", ["cross mark", "Kreuz", "croix", "marca de cruz"]),
+    ("⚠", ["warning", "Warnung", "avertissement", "advertencia"]),
+    ("⭐", ["star", "Stern", "étoile", "estrella"]),
+    ("☀", ["sun", "Sonne", "soleil", "sol"]),
+    ("🌧", ["cloud with rain", "Wolke mit Regen", "nuage avec pluie", "nube con lluvia"]),
+    ("☕", ["hot beverage", "Heißgetränk", "boisson chaude", "bebida caliente"]),
+    ("🍕", ["pizza", "Pizza", "pizza", "pizza"]),
+    ("🍺", ["beer mug", "Bierkrug", "chope de bière", "jarra de cerveza"]),
+    ("🚀", ["rocket", "Rakete", "fusée", "cohete"]),
+    ("👀", ["eyes", "Augen", "yeux", "ojos"]),
+    ("💡", ["light bulb", "Glühbirne", "ampoule", "bombilla"]),
+    ("📞", ["telephone receiver", "Telefonhörer", "combiné téléphonique", "auricular de teléfono"]),
+    ("📧", ["e-mail", "E-Mail", "e-mail", "correo electrónico"]),
+    ("🐶", ["dog face", "Hundegesicht", "tête de chien", "cara de perro"]),
+    ("🐱", ["cat face", "Katzengesicht", "tête de chat", "cara de gato"]),
+];
+
+/// Languages of the columns in [`EMOJI_DESCRIPTIONS`]
+const DESCRIPTION_LANGUAGES: [&str; 4] = ["en", "de", "fr", "es"];
+
+/// Emoticons, keyed to an emoji with the same meaning
+const EMOTICONS: &[(&str, &str)] = &[
+    (":)", "🙂"),
+    (":-)", "🙂"),
+    ("(:", "🙂"),
+    (":D", "😃"),
+    (":-D", "😃"),
+    ("xD", "😆"),
+    ("XD", "😆"),
+    (";)", "😉"),
+    (";-)", "😉"),
+    (":(", "😞"),
+    (":-(", "😞"),
+    (":'(", "😢"),
+    (":P", "😛"),
+    (":-P", "😛"),
+    (":p", "😛"),
+    (":O", "😮"),
+    (":-O", "😮"),
+    (":/", "😕"),
+    (":-/", "😕"),
+    (":|", "😐"),
+    ("<3", "❤"),
+    ("</3", "💔"),
+];
+
+/// Variation selectors, skin tones and joiners that modify the preceding emoji
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(
+        c as u32,
+        0xFE0E | 0xFE0F | 0x200D | 0x1F3FB..=0x1F3FF | 0x20E3
+    )
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x2300..=0x23FF
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmojiMode {
+    /// Replace emoji with their description (or remove them if no description is known)
+    #[default]
+    Describe,
+    /// Remove emoji and emoticons
+    Strip,
+}
+
+/// Converts emoji (and common emoticons such as `:-)`) to descriptions, or strips them
+pub struct EmojiVerbalizer {
+    mode: EmojiMode,
+    /// Descriptions keyed by primary language, then by emoji
+    descriptions: HashMap<String, HashMap<String, String>>,
+    verbalize_emoticons: bool,
+}
+
+impl Default for EmojiVerbalizer {
+    fn default() -> Self {
+        Self::new(EmojiMode::default())
+    }
+}
+
+impl EmojiVerbalizer {
+    pub fn new(mode: EmojiMode) -> Self {
+        let mut descriptions: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for (emoji, localized) in EMOJI_DESCRIPTIONS {
+            for (language, description) in DESCRIPTION_LANGUAGES.iter().zip(localized) {
+                descriptions
+                    .entry(language.to_string())
+                    .or_default()
+                    .insert(emoji.to_string(), description.to_string());
+            }
+        }
+        Self {
+            mode,
+            descriptions,
+            verbalize_emoticons: true,
+        }
+    }
+    /// Whether emoticons such as `:-)` are handled like the matching emoji (default `true`)
+    pub fn with_emoticons(mut self, verbalize_emoticons: bool) -> Self {
+        self.verbalize_emoticons = verbalize_emoticons;
+        self
+    }
+    /// Add or override descriptions for `language` (e.g. `it`)
+    pub fn with_descriptions<I, E, D>(mut self, language: &str, descriptions: I) -> Self
+    where
+        I: IntoIterator<Item = (E, D)>,
+        E: Into<String>,
+        D: Into<String>,
+    {
+        let language = primary_language(Some(language)).unwrap_or_default();
+        self.descriptions.entry(language).or_default().extend(
+            descriptions
+                .into_iter()
+                .map(|(emoji, description)| (emoji.into(), description.into())),
+        );
+        self
+    }
+    fn describe(&self, emoji: &str, language: Option<&str>) -> Option<&str> {
+        if self.mode == EmojiMode::Strip {
+            return None;
+        }
+        let language = primary_language(language).unwrap_or_else(|| "en".to_string());
+        self.descriptions
+            .get(&language)?
+            .get(emoji)
+            .map(String::as_str)
+    }
+    fn replace_emoticons(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        for (i, token) in text.split(' ').enumerate() {
+            if i > 0 {
+                output.push(' ');
+            }
+            match EMOTICONS.iter().find(|(emoticon, _)| *emoticon == token) {
+                Some((_, emoji)) => output.push_str(emoji),
+                None => output.push_str(token),
+            }
+        }
+        output
+    }
+}
+
+impl NormalizationStep for EmojiVerbalizer {
+    fn normalize(&self, text: &str, language: Option<&str>) -> String {
+        let text = if self.verbalize_emoticons {
+            self.replace_emoticons(text)
+        } else {
+            text.to_string()
+        };
+        if !text.chars().any(is_emoji) {
+            return text;
+        }
+        let mut output = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !is_emoji(c) {
+                if !is_emoji_modifier(c) {
+                    output.push(c);
+                }
+                continue;
+            }
+            // Skip skin tones, ZWJ sequences... and describe the base emoji
+            while let Some(next) = chars.peek() {
+                if is_emoji_modifier(*next) {
+                    let modifier = chars.next().unwrap();
+                    if modifier == '\u{200D}' {
+                        chars.next_if(|next| is_emoji(*next));
+                    }
+                } else {
+                    break;
+                }
+            }
+            if let Some(description) = self.describe(&c.to_string(), language) {
+                output.push(' ');
+                output.push_str(description);
+            }
+            output.push(' ');
+        }
+        collapse_spaces(&output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_emoji() {
+        let verbalizer = EmojiVerbalizer::new(EmojiMode::Describe);
+        assert_eq!(
+            verbalizer.normalize("See you soon 😀", Some("en_US")),
+            "See you soon grinning face"
+        );
+        assert_eq!(
+            verbalizer.normalize("Bis bald 👍🏽!", Some("de_DE")),
+            "Bis bald Daumen hoch !"
+        );
+        assert_eq!(
+            verbalizer.normalize("Nice :) 👨‍👩‍👧", Some("en")),
+            "Nice slightly smiling face"
+        );
+    }
+
+    #[test]
+    fn test_strip_emoji() {
+        let verbalizer = EmojiVerbalizer::new(EmojiMode::Strip);
+        assert_eq!(
+            verbalizer.normalize("Great job 🎉🎉 :D", Some("en_US")),
+            "Great job"
+        );
+    }
+
+    #[test]
+    fn test_custom_descriptions() {
+        let verbalizer =
+            EmojiVerbalizer::default().with_descriptions("it_IT", [("😀", "faccina sorridente")]);
+        assert_eq!(
+            verbalizer.normalize("Ciao 😀", Some("it_IT")),
+            "Ciao faccina sorridente"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Text normalization, applied before phonemization.
+//!
+//! A [`TextNormalizer`] runs a sequence of [`NormalizationStep`]s over the input text,
+//! turning things espeak-ng reads poorly (emoji, URLs, acronyms, dates, units...) into
+//! plain words in the voice's language.
+
+mod datetime;
+mod emoji;
+mod spell_out;
+mod units;
+mod url;
+
+pub use datetime::{DateOrder, DateTimeExpander, SpokenDate, SpokenTime};
+pub use emoji::{EmojiMode, EmojiVerbalizer};
+pub use spell_out::SpellOut;
+pub use units::{CurrencyWords, UnitExpander, UnitWords};
+pub use url::{UrlPolicy, UrlVerbalizer};
+
+pub trait NormalizationStep: Send + Sync {
+    /// Normalize `text`. `language` is the voice's language code (e.g. `en_US`), if known.
+    fn normalize(&self, text: &str, language: Option<&str>) -> String;
+}
+
+/// An ordered pipeline of normalization steps
+#[derive(Default)]
+pub struct TextNormalizer {
+    steps: Vec<Box<dyn NormalizationStep>>,
+}
+
+impl TextNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// All the built-in steps with their default settings
+    pub fn standard() -> Self {
+        // URLs go first since they contain digits, and acronyms last so that currency
+        // codes such as `USD` are not spelled out
+        Self::new()
+            .with_step(EmojiVerbalizer::default())
+            .with_step(UrlVerbalizer::default())
+            .with_step(DateTimeExpander::default())
+            .with_step(UnitExpander::default())
+            .with_step(SpellOut::default())
+    }
+    /// Append a step, which runs after the existing ones
+    pub fn with_step(mut self, step: impl NormalizationStep + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+    pub fn normalize(&self, text: &str, language: Option<&str>) -> String {
+        let mut text = text.to_string();
+        for step in self.steps.iter() {
+            text = step.normalize(&text, language);
+        }
+        text
+    }
+}
+
+/// The lowercase primary language subtag of a language code, e.g. `en` for `en_US`
+pub(crate) fn primary_language(language: Option<&str>) -> Option<String> {
+    language?
+        .split(['_', '-'])
+        .next()
+        .filter(|code| !code.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Collapse runs of spaces left behind by replacements, and trim the text
+pub(crate) fn collapse_spaces(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == ' ' && (collapsed.is_empty() || collapsed.ends_with(' ')) {
+            continue;
+        }
+        collapsed.push(c);
+    }
+    collapsed.truncate(collapsed.trim_end().len());
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_pipeline() {
+        assert_eq!(
+            TextNormalizer::standard().normalize(
+                "Call the API at 14:30 for $5, see www.example.com",
+                Some("en_US")
+            ),
+            "Call the A P I at 2 30 P M for 5 dollars, see w w w dot example dot com"
+        );
+    }
+}
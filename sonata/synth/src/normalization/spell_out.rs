@@ -0,0 +1,161 @@
+//! Spelling out acronyms and tagged spans letter by letter.
+//!
+//! espeak-ng guesses a pronunciation for unknown all-caps words, so `SQL` may come out
+//! as one syllable. [`SpellOut`] spells such words (`S Q L`), as well as anything wrapped
+//! in `<say-as interpret-as="characters">...</say-as>`, which is useful for serial
+//! numbers and codes.
+
+use super::NormalizationStep;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+
+static SAY_AS_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?is)<say-as\s+interpret-as\s*=\s*["']([^"']*)["'][^>]*>(.*?)</say-as>"#).unwrap()
+});
+static ACRONYM_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b[A-Z][A-Z0-9]+\b").unwrap());
+
+/// `interpret-as` values that mean "read letter by letter"
+const SPELL_OUT_INTERPRETATIONS: &[&str] = &["characters", "spell-out", "letters", "verbatim"];
+
+/// Acronyms that are pronounced as words
+const WORD_ACRONYMS: &[&str] = &[
+    "AIDS", "ASCII", "COVID", "FIFA", "GIF", "JPEG", "LASER", "NASA", "NATO", "OPEC", "PIN",
+    "RADAR", "SCUBA", "SIM", "UNESCO", "UNICEF",
+];
+
+const DEFAULT_MAX_ACRONYM_LENGTH: usize = 5;
+
+pub struct SpellOut {
+    spell_acronyms: bool,
+    max_acronym_length: usize,
+    words: HashSet<String>,
+    tokens: HashSet<String>,
+}
+
+impl Default for SpellOut {
+    fn default() -> Self {
+        Self {
+            spell_acronyms: true,
+            max_acronym_length: DEFAULT_MAX_ACRONYM_LENGTH,
+            words: HashSet::from_iter(WORD_ACRONYMS.iter().map(|word| word.to_string())),
+            tokens: HashSet::new(),
+        }
+    }
+}
+
+impl SpellOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Whether to spell all-caps words such as `SQL` or `MP3` (enabled by default).
+    /// Tagged spans and tokens added with [`SpellOut::with_token`] are always spelled.
+    pub fn with_acronyms(mut self, spell_acronyms: bool) -> Self {
+        self.spell_acronyms = spell_acronyms;
+        self
+    }
+    /// All-caps words longer than this are assumed to be emphasis rather than acronyms
+    pub fn with_max_acronym_length(mut self, max_acronym_length: usize) -> Self {
+        self.max_acronym_length = max_acronym_length;
+        self
+    }
+    /// An acronym that is pronounced as a word, e.g. `NASA`
+    pub fn with_word(mut self, word: impl Into<String>) -> Self {
+        let word = word.into();
+        self.tokens.remove(&word);
+        self.words.insert(word);
+        self
+    }
+    /// A token that is always spelled, whatever its case, e.g. `nginx`
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        let token = token.into();
+        self.words.remove(&token);
+        self.tokens.insert(token);
+        self
+    }
+    fn is_acronym(&self, word: &str) -> bool {
+        word.chars().filter(char::is_ascii_uppercase).count() >= 2
+            && word.chars().count() <= self.max_acronym_length
+            && !self.words.contains(word)
+    }
+}
+
+/// Spell `text` letter by letter. Punctuation and spaces inside the text become
+/// short pauses, so `AB-1234` is read as `A B, 1 2 3 4`.
+pub(crate) fn spell(text: &str) -> String {
+    let groups = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|group| !group.is_empty())
+        .map(|group| Vec::from_iter(group.chars().map(String::from)).join(" "));
+    Vec::from_iter(groups).join(", ")
+}
+
+impl NormalizationStep for SpellOut {
+    fn normalize(&self, text: &str, _language: Option<&str>) -> String {
+        let text = SAY_AS_PATTERN.replace_all(text, |caps: &Captures| {
+            let interpretation = caps[1].trim().to_lowercase();
+            if SPELL_OUT_INTERPRETATIONS.contains(&interpretation.as_str()) {
+                spell(&caps[2])
+            } else {
+                // Other interpretations are left to the remaining steps
+                caps[2].to_string()
+            }
+        });
+        // A text without any lowercase letter is shouting, not a list of acronyms
+        let spell_acronyms = self.spell_acronyms && text.chars().any(char::is_lowercase);
+        let text = if spell_acronyms {
+            ACRONYM_PATTERN.replace_all(&text, |caps: &Captures| {
+                if self.is_acronym(&caps[0]) {
+                    spell(&caps[0])
+                } else {
+                    caps[0].to_string()
+                }
+            })
+        } else {
+            text
+        };
+        if self.tokens.is_empty() {
+            return text.into_owned();
+        }
+        let words = text.split(' ').map(|word| {
+            let token = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if !token.is_empty() && self.tokens.contains(token) {
+                word.replace(token, &spell(token))
+            } else {
+                word.to_string()
+            }
+        });
+        Vec::from_iter(words).join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spell_acronyms() {
+        let spell_out = SpellOut::new().with_token("nginx");
+        assert_eq!(
+            spell_out.normalize("Put SQL and nginx on the NASA server.", None),
+            "Put S Q L and n g i n x on the NASA server."
+        );
+        assert_eq!(spell_out.normalize("Call the API", None), "Call the A P I");
+        assert_eq!(spell_out.normalize("WE ARE HERE", None), "WE ARE HERE");
+        assert_eq!(
+            spell_out.normalize("An ABSOLUTELY fine MP3", None),
+            "An ABSOLUTELY fine M P 3"
+        );
+    }
+
+    #[test]
+    fn test_say_as() {
+        assert_eq!(
+            SpellOut::new().with_acronyms(false).normalize(
+                r#"Serial <say-as interpret-as="characters">XK-42a</say-as>, <say-as interpret-as="date">today</say-as>"#,
+                None
+            ),
+            "Serial X K, 4 2 a, today"
+        );
+    }
+}
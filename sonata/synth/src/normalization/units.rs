@@ -0,0 +1,307 @@
+//! Currency amounts and measurement units.
+//!
+//! `$4.99`, `12 €`, `10kg` and `5°C` are rewritten into words in the voice's language
+//! (`4 dollars and 99 cents`, `12 Euro`...). The numbers are left as digits for
+//! espeak-ng, which reads them correctly in every language it supports.
+
+use super::{primary_language, NormalizationStep};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+/// Singular and plural forms of a unit name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitWords {
+    pub singular: String,
+    pub plural: String,
+}
+
+impl UnitWords {
+    pub fn new(singular: impl Into<String>, plural: impl Into<String>) -> Self {
+        Self {
+            singular: singular.into(),
+            plural: plural.into(),
+        }
+    }
+    fn for_amount(&self, amount: &str) -> &str {
+        if amount == "1" {
+            &self.singular
+        } else {
+            &self.plural
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyWords {
+    pub major: UnitWords,
+    /// The subunit (cents...), if the currency has one
+    pub minor: Option<UnitWords>,
+}
+
+impl CurrencyWords {
+    pub fn new(singular: impl Into<String>, plural: impl Into<String>) -> Self {
+        Self {
+            major: UnitWords::new(singular, plural),
+            minor: None,
+        }
+    }
+    pub fn with_minor(mut self, singular: impl Into<String>, plural: impl Into<String>) -> Self {
+        self.minor = Some(UnitWords::new(singular, plural));
+        self
+    }
+}
+
+/// language, symbols, singular, plural, minor singular, minor plural
+type CurrencyRow = (
+    &'static str,
+    &'static [&'static str],
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+);
+
+#[rustfmt::skip]
+const CURRENCIES: &[CurrencyRow] = &[
+    ("en", &["$", "USD"], "dollar", "dollars", "cent", "cents"),
+    ("en", &["€", "EUR"], "euro", "euros", "cent", "cents"),
+    ("en", &["£", "GBP"], "pound", "pounds", "penny", "pence"),
+    ("en", &["¥", "JPY"], "yen", "yen", "", ""),
+    ("de", &["$", "USD"], "Dollar", "Dollar", "Cent", "Cent"),
+    ("de", &["€", "EUR"], "Euro", "Euro", "Cent", "Cent"),
+    ("de", &["£", "GBP"], "Pfund", "Pfund", "Penny", "Pence"),
+    ("de", &["¥", "JPY"], "Yen", "Yen", "", ""),
+    ("fr", &["$", "USD"], "dollar", "dollars", "cent", "cents"),
+    ("fr", &["€", "EUR"], "euro", "euros", "centime", "centimes"),
+    ("fr", &["£", "GBP"], "livre", "livres", "penny", "pence"),
+    ("fr", &["¥", "JPY"], "yen", "yens", "", ""),
+    ("es", &["$", "USD"], "dólar", "dólares", "centavo", "centavos"),
+    ("es", &["€", "EUR"], "euro", "euros", "céntimo", "céntimos"),
+    ("es", &["£", "GBP"], "libra", "libras", "penique", "peniques"),
+    ("es", &["¥", "JPY"], "yen", "yenes", "", ""),
+];
+
+/// Word joining the major and minor amounts, e.g. `4 dollars and 99 cents`
+const CONJUNCTIONS: &[(&str, &str)] = &[("en", "and"), ("de", "und"), ("fr", "et"), ("es", "con")];
+
+/// language, symbol, singular, plural
+#[rustfmt::skip]
+const UNITS: &[(&str, &str, &str, &str)] = &[
+    ("en", "mg", "milligram", "milligrams"), ("en", "g", "gram", "grams"), ("en", "kg", "kilogram", "kilograms"),
+    ("en", "mm", "millimeter", "millimeters"), ("en", "cm", "centimeter", "centimeters"), ("en", "m", "meter", "meters"),
+    ("en", "km", "kilometer", "kilometers"), ("en", "ml", "milliliter", "milliliters"), ("en", "l", "liter", "liters"),
+    ("en", "km/h", "kilometer per hour", "kilometers per hour"), ("en", "mph", "mile per hour", "miles per hour"),
+    ("en", "°C", "degree Celsius", "degrees Celsius"), ("en", "°F", "degree Fahrenheit", "degrees Fahrenheit"),
+    ("en", "%", "percent", "percent"),
+    ("de", "mg", "Milligramm", "Milligramm"), ("de", "g", "Gramm", "Gramm"), ("de", "kg", "Kilogramm", "Kilogramm"),
+    ("de", "mm", "Millimeter", "Millimeter"), ("de", "cm", "Zentimeter", "Zentimeter"), ("de", "m", "Meter", "Meter"),
+    ("de", "km", "Kilometer", "Kilometer"), ("de", "ml", "Milliliter", "Milliliter"), ("de", "l", "Liter", "Liter"),
+    ("de", "km/h", "Kilometer pro Stunde", "Kilometer pro Stunde"), ("de", "mph", "Meile pro Stunde", "Meilen pro Stunde"),
+    ("de", "°C", "Grad Celsius", "Grad Celsius"), ("de", "°F", "Grad Fahrenheit", "Grad Fahrenheit"),
+    ("de", "%", "Prozent", "Prozent"),
+    ("fr", "mg", "milligramme", "milligrammes"), ("fr", "g", "gramme", "grammes"), ("fr", "kg", "kilogramme", "kilogrammes"),
+    ("fr", "mm", "millimètre", "millimètres"), ("fr", "cm", "centimètre", "centimètres"), ("fr", "m", "mètre", "mètres"),
+    ("fr", "km", "kilomètre", "kilomètres"), ("fr", "ml", "millilitre", "millilitres"), ("fr", "l", "litre", "litres"),
+    ("fr", "km/h", "kilomètre heure", "kilomètres heure"), ("fr", "mph", "mile par heure", "miles par heure"),
+    ("fr", "°C", "degré Celsius", "degrés Celsius"), ("fr", "°F", "degré Fahrenheit", "degrés Fahrenheit"),
+    ("fr", "%", "pour cent", "pour cent"),
+    ("es", "mg", "miligramo", "miligramos"), ("es", "g", "gramo", "gramos"), ("es", "kg", "kilogramo", "kilogramos"),
+    ("es", "mm", "milímetro", "milímetros"), ("es", "cm", "centímetro", "centímetros"), ("es", "m", "metro", "metros"),
+    ("es", "km", "kilómetro", "kilómetros"), ("es", "ml", "mililitro", "mililitros"), ("es", "l", "litro", "litros"),
+    ("es", "km/h", "kilómetro por hora", "kilómetros por hora"), ("es", "mph", "milla por hora", "millas por hora"),
+    ("es", "°C", "grado Celsius", "grados Celsius"), ("es", "°F", "grado Fahrenheit", "grados Fahrenheit"),
+    ("es", "%", "por ciento", "por ciento"),
+];
+
+const NUMBER_PATTERN: &str = r"\d+(?:[.,]\d+)*";
+
+/// A regex alternation of `symbols`, longest first so that `km/h` wins over `km`
+fn alternation<'a>(symbols: impl Iterator<Item = &'a str>) -> String {
+    let mut symbols = Vec::from_iter(symbols);
+    symbols.sort_unstable();
+    symbols.dedup();
+    symbols.sort_by_key(|symbol| std::cmp::Reverse(symbol.len()));
+    Vec::from_iter(symbols.into_iter().map(regex::escape)).join("|")
+}
+
+/// Split an amount such as `1,299.99` into its integer and decimal digits, treating a
+/// final group of one or two digits as the decimal part
+fn split_amount(amount: &str) -> (String, Option<String>) {
+    match amount.rfind(['.', ',']) {
+        Some(index) if (1..=2).contains(&(amount.len() - index - 1)) => (
+            amount[..index].replace(['.', ','], ""),
+            Some(amount[index + 1..].to_string()),
+        ),
+        _ => (amount.replace(['.', ','], ""), None),
+    }
+}
+
+pub struct UnitExpander {
+    currencies: HashMap<(String, String), CurrencyWords>,
+    units: HashMap<(String, String), UnitWords>,
+    currency_pattern: Regex,
+    unit_pattern: Regex,
+}
+
+impl Default for UnitExpander {
+    fn default() -> Self {
+        let mut currencies = HashMap::new();
+        for (language, symbols, singular, plural, minor_singular, minor_plural) in CURRENCIES {
+            let mut words = CurrencyWords::new(*singular, *plural);
+            if !minor_singular.is_empty() {
+                words = words.with_minor(*minor_singular, *minor_plural);
+            }
+            for symbol in symbols.iter() {
+                currencies.insert((language.to_string(), symbol.to_string()), words.clone());
+            }
+        }
+        let units = HashMap::from_iter(UNITS.iter().map(|(language, symbol, singular, plural)| {
+            (
+                (language.to_string(), symbol.to_string()),
+                UnitWords::new(*singular, *plural),
+            )
+        }));
+        Self::from_tables(currencies, units)
+    }
+}
+
+impl UnitExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn from_tables(
+        currencies: HashMap<(String, String), CurrencyWords>,
+        units: HashMap<(String, String), UnitWords>,
+    ) -> Self {
+        let currency_symbols = alternation(currencies.keys().map(|(_, symbol)| symbol.as_str()));
+        let currency_pattern = Regex::new(&format!(
+            r"(?:({currency_symbols})\s?({NUMBER_PATTERN})|\b({NUMBER_PATTERN})\s?({currency_symbols}))"
+        ))
+        .unwrap();
+        // The unit must not be followed by a letter, so `5 m` matches but `5 min` doesn't
+        let unit_symbols = alternation(units.keys().map(|(_, symbol)| symbol.as_str()));
+        let unit_pattern = Regex::new(&format!(
+            r"\b({NUMBER_PATTERN})\s?({unit_symbols})([^\p{{L}}\p{{N}}]|$)"
+        ))
+        .unwrap();
+        Self {
+            currencies,
+            units,
+            currency_pattern,
+            unit_pattern,
+        }
+    }
+    /// Add or replace the words for a currency symbol or code in `language`
+    /// (a primary language subtag such as `en`)
+    pub fn with_currency(
+        mut self,
+        language: impl Into<String>,
+        symbol: impl Into<String>,
+        words: CurrencyWords,
+    ) -> Self {
+        self.currencies
+            .insert((language.into(), symbol.into()), words);
+        Self::from_tables(self.currencies, self.units)
+    }
+    /// Add or replace the words for a unit symbol in `language`
+    /// (a primary language subtag such as `en`)
+    pub fn with_unit(
+        mut self,
+        language: impl Into<String>,
+        symbol: impl Into<String>,
+        words: UnitWords,
+    ) -> Self {
+        self.units.insert((language.into(), symbol.into()), words);
+        Self::from_tables(self.currencies, self.units)
+    }
+    fn expand_currency(&self, amount: &str, words: &CurrencyWords, language: &str) -> String {
+        let (major, minor) = split_amount(amount);
+        let major = major.trim_start_matches('0');
+        let major = if major.is_empty() { "0" } else { major };
+        let minor = match (minor, &words.minor) {
+            (Some(minor), Some(_)) if minor.len() == 1 => Some(format!("{}0", minor)),
+            (Some(minor), Some(_)) => Some(minor.trim_start_matches('0').to_string()),
+            _ => None,
+        };
+        let major_words = format!("{} {}", major, words.major.for_amount(major));
+        match (minor.filter(|minor| !minor.is_empty()), &words.minor) {
+            (Some(minor), Some(minor_words)) => {
+                let minor_words = format!("{} {}", minor, minor_words.for_amount(&minor));
+                if major == "0" {
+                    return minor_words;
+                }
+                let conjunction = CONJUNCTIONS
+                    .iter()
+                    .find(|(code, _)| *code == language)
+                    .map(|(_, conjunction)| format!(" {} ", conjunction))
+                    .unwrap_or_else(|| " ".to_string());
+                format!("{}{}{}", major_words, conjunction, minor_words)
+            }
+            _ => major_words,
+        }
+    }
+}
+
+impl NormalizationStep for UnitExpander {
+    fn normalize(&self, text: &str, language: Option<&str>) -> String {
+        let language = primary_language(language).unwrap_or_else(|| "en".to_string());
+        let text = self.currency_pattern.replace_all(text, |caps: &Captures| {
+            let (symbol, amount) = match (caps.get(1), caps.get(2)) {
+                (Some(symbol), Some(amount)) => (symbol.as_str(), amount.as_str()),
+                _ => (&caps[4], &caps[3]),
+            };
+            match self.currencies.get(&(language.clone(), symbol.to_string())) {
+                Some(words) => self.expand_currency(amount, words, &language),
+                None => caps[0].to_string(),
+            }
+        });
+        let text = self.unit_pattern.replace_all(&text, |caps: &Captures| {
+            match self.units.get(&(language.clone(), caps[2].to_string())) {
+                Some(words) => format!("{} {}{}", &caps[1], words.for_amount(&caps[1]), &caps[3]),
+                None => caps[0].to_string(),
+            }
+        });
+        text.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_currencies() {
+        let expander = UnitExpander::new();
+        assert_eq!(
+            expander.normalize("Only $4.99, or €12 and £1,250.", Some("en_US")),
+            "Only 4 dollars and 99 cents, or 12 euros and 1250 pounds."
+        );
+        assert_eq!(
+            expander.normalize("Nur 4,50 € statt 0,99 €", Some("de_DE")),
+            "Nur 4 Euro und 50 Cent statt 99 Cent"
+        );
+    }
+
+    #[test]
+    fn test_expand_units() {
+        let expander = UnitExpander::new();
+        assert_eq!(
+            expander.normalize("10kg at 5°C, 1 l, 50 km/h for 5 min", Some("en")),
+            "10 kilograms at 5 degrees Celsius, 1 liter, 50 kilometers per hour for 5 min"
+        );
+        assert_eq!(
+            expander.normalize("Il fait 5°C", Some("fr_FR")),
+            "Il fait 5 degrés Celsius"
+        );
+        assert_eq!(expander.normalize("10kg", Some("it_IT")), "10kg");
+    }
+
+    #[test]
+    fn test_custom_words() {
+        let expander = UnitExpander::new()
+            .with_unit("it", "kg", UnitWords::new("chilogrammo", "chilogrammi"))
+            .with_currency("en", "BTC", CurrencyWords::new("bitcoin", "bitcoins"));
+        assert_eq!(expander.normalize("10kg", Some("it_IT")), "10 chilogrammi");
+        assert_eq!(expander.normalize("2 BTC", Some("en_US")), "2 bitcoins");
+    }
+}
@@ -0,0 +1,352 @@
+//! URL and e-mail address reading rules.
+//!
+//! Left alone, espeak-ng reads URLs character by character, or skips the punctuation
+//! and merges the words. URLs and e-mail addresses are rewritten so that words are
+//! spoken naturally, separators are named (`dot`, `slash`, `at`) and tokens that
+//! don't look like words (`xkcd`, `a1b2`) are spelled out.
+
+use super::spell_out::spell;
+use super::{collapse_spaces, primary_language, NormalizationStep};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static URL_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?i)\b(?:https?://|ftp://|www\.)[^\s<>"']+"#).unwrap());
+static EMAIL_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b[a-z0-9][a-z0-9._%+-]*@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b").unwrap()
+});
+
+/// Top-level domains that are read as words rather than spelled out
+const SPOKEN_TLDS: &[&str] = &["com", "org", "net", "gov", "edu", "info", "mil", "biz"];
+
+/// Names of the separators found in URLs: `.`, `/`, `@`, `-`, `_`, `:`, `?`, `=`, `&`
+struct SeparatorNames {
+    dot: &'static str,
+    slash: &'static str,
+    at: &'static str,
+    dash: &'static str,
+    underscore: &'static str,
+    colon: &'static str,
+    question_mark: &'static str,
+    equals: &'static str,
+    and: &'static str,
+}
+
+const SEPARATOR_NAMES: &[(&str, SeparatorNames)] = &[
+    (
+        "en",
+        SeparatorNames {
+            dot: "dot",
+            slash: "slash",
+            at: "at",
+            dash: "dash",
+            underscore: "underscore",
+            colon: "colon",
+            question_mark: "question mark",
+            equals: "equals",
+            and: "and",
+        },
+    ),
+    (
+        "de",
+        SeparatorNames {
+            dot: "Punkt",
+            slash: "Schrägstrich",
+            at: "at",
+            dash: "Bindestrich",
+            underscore: "Unterstrich",
+            colon: "Doppelpunkt",
+            question_mark: "Fragezeichen",
+            equals: "gleich",
+            and: "und",
+        },
+    ),
+    (
+        "fr",
+        SeparatorNames {
+            dot: "point",
+            slash: "barre oblique",
+            at: "arobase",
+            dash: "tiret",
+            underscore: "tiret bas",
+            colon: "deux-points",
+            question_mark: "point d'interrogation",
+            equals: "égal",
+            and: "et",
+        },
+    ),
+    (
+        "es",
+        SeparatorNames {
+            dot: "punto",
+            slash: "barra",
+            at: "arroba",
+            dash: "guion",
+            underscore: "guion bajo",
+            colon: "dos puntos",
+            question_mark: "signo de interrogación",
+            equals: "igual",
+            and: "y",
+        },
+    ),
+];
+
+fn separator_names(language: Option<&str>) -> &'static SeparatorNames {
+    let language = primary_language(language);
+    let (_, names) = SEPARATOR_NAMES
+        .iter()
+        .find(|(code, _)| Some(*code) == language.as_deref())
+        .unwrap_or(&SEPARATOR_NAMES[0]);
+    names
+}
+
+/// How much of a URL is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UrlPolicy {
+    /// Read the host, path and query (subject to the query length limit)
+    #[default]
+    Full,
+    /// Read the host only, e.g. `example dot com`
+    DomainOnly,
+    /// Remove URLs and e-mail addresses from the text
+    Skip,
+}
+
+pub struct UrlVerbalizer {
+    policy: UrlPolicy,
+    max_query_length: Option<usize>,
+}
+
+impl Default for UrlVerbalizer {
+    fn default() -> Self {
+        Self::new(UrlPolicy::default())
+    }
+}
+
+impl UrlVerbalizer {
+    pub fn new(policy: UrlPolicy) -> Self {
+        Self {
+            policy,
+            max_query_length: None,
+        }
+    }
+    /// Drop query strings (`?...`) longer than `max_query_length` characters
+    pub fn with_max_query_length(mut self, max_query_length: usize) -> Self {
+        self.max_query_length = Some(max_query_length);
+        self
+    }
+    fn verbalize_url(&self, url: &str, names: &SeparatorNames) -> String {
+        let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+        // Fragments (`#section`) are never read
+        let address = without_scheme.split('#').next().unwrap_or_default();
+        let (address, query) = match address.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (address, None),
+        };
+        let (host, path) = match address.split_once('/') {
+            Some((host, path)) => (host, Some(path)),
+            None => (address, None),
+        };
+        let mut words = vec![verbalize_host(host, names)];
+        if self.policy == UrlPolicy::DomainOnly {
+            return words.join(" ");
+        }
+        if let Some(path) = path.filter(|path| !path.is_empty()) {
+            for segment in path.split('/') {
+                words.push(names.slash.to_string());
+                if !segment.is_empty() {
+                    words.push(verbalize_token(segment, names));
+                }
+            }
+        }
+        let query = query.filter(|query| {
+            !query.is_empty()
+                && self
+                    .max_query_length
+                    .map(|max_length| query.chars().count() <= max_length)
+                    .unwrap_or(true)
+        });
+        if let Some(query) = query {
+            words.push(names.question_mark.to_string());
+            for (i, parameter) in query.split('&').enumerate() {
+                if i > 0 {
+                    words.push(names.and.to_string());
+                }
+                let mut parts = parameter.splitn(2, '=');
+                words.push(verbalize_token(parts.next().unwrap_or_default(), names));
+                if let Some(value) = parts.next() {
+                    words.push(names.equals.to_string());
+                    words.push(verbalize_token(value, names));
+                }
+            }
+        }
+        words.join(" ")
+    }
+}
+
+fn verbalize_email(email: &str, names: &SeparatorNames) -> String {
+    let (user, domain) = email.split_once('@').unwrap_or((email, ""));
+    format!(
+        "{} {} {}",
+        verbalize_token(user, names),
+        names.at,
+        verbalize_host(domain, names)
+    )
+}
+
+fn verbalize_host(host: &str, names: &SeparatorNames) -> String {
+    let host = host
+        .split_once(':')
+        .map(|(host, _port)| host)
+        .unwrap_or(host);
+    let labels = Vec::from_iter(host.split('.').filter(|label| !label.is_empty()));
+    let last = labels.len().saturating_sub(1);
+    let spoken = labels.iter().enumerate().map(|(i, label)| {
+        let label_lower = label.to_lowercase();
+        if i == last && i > 0 && SPOKEN_TLDS.contains(&label_lower.as_str()) {
+            label_lower
+        } else if i == last && i > 0 {
+            spell(label)
+        } else {
+            verbalize_token(label, names)
+        }
+    });
+    Vec::from_iter(spoken).join(&format!(" {} ", names.dot))
+}
+
+/// Read a token, naming the separators inside it and spelling parts that are not words
+fn verbalize_token(token: &str, names: &SeparatorNames) -> String {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for c in token.chars() {
+        let separator = match c {
+            '.' => Some(names.dot),
+            '-' => Some(names.dash),
+            '_' => Some(names.underscore),
+            ':' => Some(names.colon),
+            '+' | '%' | '~' => Some(""),
+            _ => None,
+        };
+        match separator {
+            Some(separator) => {
+                if !current.is_empty() {
+                    words.push(speak_word(&std::mem::take(&mut current)));
+                }
+                if !separator.is_empty() {
+                    words.push(separator.to_string());
+                }
+            }
+            None => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(speak_word(&current));
+    }
+    words.join(" ")
+}
+
+/// A token is read as a word if it is made of letters, and is pronounceable
+fn looks_like_word(token: &str) -> bool {
+    let is_alphabetic = token.chars().all(char::is_alphabetic);
+    let has_vowel = token
+        .to_lowercase()
+        .chars()
+        .any(|c| "aeiouyàáâäèéêëìíîïòóôöùúûü".contains(c));
+    // Letters only, with a vowel, and no long consonant clusters
+    let mut consonant_run = 0;
+    let mut max_consonant_run = 0;
+    for c in token.to_lowercase().chars() {
+        if "aeiouyàáâäèéêëìíîïòóôöùúûü".contains(c) {
+            consonant_run = 0;
+        } else {
+            consonant_run += 1;
+            max_consonant_run = max_consonant_run.max(consonant_run);
+        }
+    }
+    is_alphabetic && has_vowel && token.chars().count() > 1 && max_consonant_run <= 4
+}
+
+fn speak_word(token: &str) -> String {
+    if looks_like_word(token) || token.chars().all(|c| c.is_ascii_digit()) {
+        token.to_string()
+    } else {
+        spell(token)
+    }
+}
+
+/// Split trailing sentence punctuation from a matched URL
+fn split_trailing_punctuation(url: &str) -> (&str, &str) {
+    let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']']);
+    url.split_at(trimmed.len())
+}
+
+impl NormalizationStep for UrlVerbalizer {
+    fn normalize(&self, text: &str, language: Option<&str>) -> String {
+        let names = separator_names(language);
+        let text = URL_PATTERN.replace_all(text, |caps: &Captures| {
+            let (url, punctuation) = split_trailing_punctuation(&caps[0]);
+            match self.policy {
+                UrlPolicy::Skip => punctuation.to_string(),
+                _ => format!("{}{}", self.verbalize_url(url, names), punctuation),
+            }
+        });
+        let text = EMAIL_PATTERN.replace_all(&text, |caps: &Captures| match self.policy {
+            UrlPolicy::Skip => String::new(),
+            _ => verbalize_email(&caps[0], names),
+        });
+        if self.policy == UrlPolicy::Skip {
+            collapse_spaces(&text)
+        } else {
+            text.into_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_url() {
+        let verbalizer = UrlVerbalizer::new(UrlPolicy::Full).with_max_query_length(20);
+        assert_eq!(
+            verbalizer.normalize(
+                "Visit https://www.example.com/docs/getting-started.",
+                Some("en_US")
+            ),
+            "Visit w w w dot example dot com slash docs slash getting dash started."
+        );
+        assert_eq!(
+            verbalizer.normalize("https://xkcd.com/?id=42", Some("en_US")),
+            "x k c d dot com question mark id equals 42"
+        );
+        assert_eq!(
+            verbalizer.normalize(
+                "https://shop.example.de/cart?session=a8f7c6e5d4b3a2918f7e6d5c4b3a2918",
+                Some("de_DE")
+            ),
+            "shop Punkt example Punkt d e Schrägstrich cart"
+        );
+    }
+
+    #[test]
+    fn test_url_policies() {
+        let text = "See https://example.org/a/b for details";
+        assert_eq!(
+            UrlVerbalizer::new(UrlPolicy::DomainOnly).normalize(text, None),
+            "See example dot org for details"
+        );
+        assert_eq!(
+            UrlVerbalizer::new(UrlPolicy::Skip).normalize(text, None),
+            "See for details"
+        );
+    }
+
+    #[test]
+    fn test_read_email() {
+        assert_eq!(
+            UrlVerbalizer::default().normalize("Mail john.doe@example.com today", Some("en")),
+            "Mail john dot doe at example dot com today"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Recovery from sentences that fail to synthesize.
+//!
+//! By default a failing sentence fails the whole stream. For long documents, an
+//! [`ErrorRecovery`] policy can retry the sentence and then replace it with a
+//! placeholder, so the rest of the document is still synthesized. Replaced sentences
+//! are listed in the stream's [`RecoveryReport`].
+
+use crate::{Audio, AudioInfo, AudioSamples, SonataAudioResult, SonataResult};
+use std::sync::{Arc, Mutex};
+
+/// What to put in place of a sentence that failed all its attempts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum FailedSentenceAction {
+    /// Fail with the sentence's error
+    #[default]
+    Abort,
+    /// Replace the sentence with silence
+    Silence { duration_ms: u32 },
+    /// Replace the sentence with a sine tone, so the gap is audible when reviewing
+    Beep { duration_ms: u32, frequency_hz: f32 },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ErrorRecovery {
+    /// Number of times to retry a failing sentence
+    pub retries: u32,
+    pub action: FailedSentenceAction,
+}
+
+impl ErrorRecovery {
+    pub fn new(action: FailedSentenceAction) -> Self {
+        Self { retries: 0, action }
+    }
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+    /// Synthesize one sentence using `synthesize`, applying the policy on failure
+    pub(crate) fn run(
+        &self,
+        index: usize,
+        phonemes: &str,
+        audio_info: impl FnOnce() -> SonataResult<AudioInfo>,
+        report: &RecoveryReport,
+        mut synthesize: impl FnMut() -> SonataAudioResult,
+    ) -> SonataAudioResult {
+        let mut result = synthesize();
+        let mut attempts = 1;
+        while result.is_err() && attempts <= self.retries {
+            result = synthesize();
+            attempts += 1;
+        }
+        let error = match result {
+            Ok(audio) => return Ok(audio),
+            Err(e) => e,
+        };
+        let (duration_ms, frequency_hz) = match self.action {
+            FailedSentenceAction::Abort => return Err(error),
+            FailedSentenceAction::Silence { duration_ms } => (duration_ms, None),
+            FailedSentenceAction::Beep {
+                duration_ms,
+                frequency_hz,
+            } => (duration_ms, Some(frequency_hz)),
+        };
+        let info = audio_info()?;
+        report.record(SkippedSentence {
+            index,
+            phonemes: phonemes.to_string(),
+            error: error.to_string(),
+            attempts,
+        });
+        Ok(placeholder(&info, duration_ms, frequency_hz))
+    }
+}
+
+fn placeholder(info: &AudioInfo, duration_ms: u32, frequency_hz: Option<f32>) -> Audio {
+    let num_frames = info.sample_rate * duration_ms as usize / 1000;
+    let mut samples = Vec::with_capacity(num_frames * info.num_channels);
+    // Short fades avoid clicks at the edges of the tone
+    let fade_frames = (info.sample_rate / 100).min(num_frames / 2).max(1);
+    for i in 0..num_frames {
+        let value = match frequency_hz {
+            Some(frequency_hz) => {
+                let t = i as f32 / info.sample_rate as f32;
+                let fade = (i.min(num_frames - 1 - i) as f32 / fade_frames as f32).min(1.0);
+                0.3 * fade * (2.0 * std::f32::consts::PI * frequency_hz * t).sin()
+            }
+            None => 0.0,
+        };
+        samples.extend(std::iter::repeat_n(value, info.num_channels));
+    }
+    let mut audio = Audio::new(AudioSamples::from(samples), info.sample_rate, None);
+    audio.info = info.clone();
+    audio
+}
+
+/// A sentence that was replaced by a placeholder
+#[derive(Clone, Debug)]
+pub struct SkippedSentence {
+    /// Position of the sentence in the utterance
+    pub index: usize,
+    pub phonemes: String,
+    /// The error of the last attempt
+    pub error: String,
+    pub attempts: u32,
+}
+
+/// The sentences of an utterance that were replaced by placeholders
+#[derive(Clone, Default)]
+pub struct RecoveryReport(Arc<Mutex<Vec<SkippedSentence>>>);
+
+impl RecoveryReport {
+    fn record(&self, skipped: SkippedSentence) {
+        self.0.lock().unwrap().push(skipped);
+    }
+    /// Add the sentences skipped in `other`, a part of the utterance that starts at
+    /// sentence `first_sentence`
+    pub(crate) fn extend(&self, other: &RecoveryReport, first_sentence: usize) {
+        for skipped in other.skipped() {
+            self.record(SkippedSentence {
+                index: first_sentence + skipped.index,
+                ..skipped
+            });
+        }
+    }
+    /// The skipped sentences, in utterance order
+    pub fn skipped(&self) -> Vec<SkippedSentence> {
+        let mut skipped = self.0.lock().unwrap().clone();
+        skipped.sort_by_key(|sentence| sentence.index);
+        skipped
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SonataError;
+
+    #[test]
+    fn test_error_recovery() {
+        let info = AudioInfo {
+            sample_rate: 1000,
+            num_channels: 1,
+            sample_width: 2,
+        };
+        let failing = || Err(SonataError::OperationError("inference failed".to_string()));
+        let report = RecoveryReport::default();
+
+        let abort = ErrorRecovery::default();
+        assert!(abort
+            .run(0, "a", || Ok(info.clone()), &report, failing)
+            .is_err());
+        assert!(report.is_empty());
+
+        // Succeeds on the last retry
+        let mut attempts = 0;
+        let retry = ErrorRecovery::new(FailedSentenceAction::Abort).with_retries(2);
+        let result = retry.run(
+            0,
+            "a",
+            || Ok(info.clone()),
+            &report,
+            || {
+                attempts += 1;
+                if attempts < 3 {
+                    failing()
+                } else {
+                    Ok(placeholder(&info, 10, None))
+                }
+            },
+        );
+        assert!(result.is_ok());
+        assert!(report.is_empty());
+
+        let beep = ErrorRecovery::new(FailedSentenceAction::Beep {
+            duration_ms: 100,
+            frequency_hz: 440.0,
+        })
+        .with_retries(1);
+        let audio = beep
+            .run(3, "b", || Ok(info.clone()), &report, failing)
+            .unwrap();
+        assert_eq!(audio.duration_ms(), 100.0);
+        assert!(audio.samples.as_slice().iter().any(|sample| *sample != 0.0));
+        let skipped = report.skipped();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!((skipped[0].index, skipped[0].attempts), (3, 2));
+
+        // Sentences of a later section are numbered from the start of the text
+        let whole = RecoveryReport::default();
+        whole.extend(&report, 10);
+        assert_eq!(whole.skipped()[0].index, 13);
+    }
+}
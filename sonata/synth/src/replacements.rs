@@ -0,0 +1,267 @@
+//! User-defined text replacements, applied before phonemization.
+//!
+//! Rules are either literal (`km/h` → `kilometers per hour`) or regular expressions
+//! (`\b(\d+)\s*kg\b` → `$1 kilograms`), and may be restricted to a language.
+//! Dictionaries are stored as JSON files of the form:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "pattern": "km/h", "replacement": "kilometers per hour", "language": "en" },
+//!     { "pattern": "\\bGmbH\\b", "replacement": "G m b H", "regex": true }
+//!   ]
+//! }
+//! ```
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sonata_core::{SonataError, SonataResult};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplacementRule {
+    pub pattern: String,
+    /// The replacement text. For regex rules, `$1`, `$name`... refer to capture groups
+    pub replacement: String,
+    #[serde(default)]
+    pub regex: bool,
+    /// Only apply the rule to voices of this language (e.g. `en`, `de_DE`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl ReplacementRule {
+    pub fn literal(pattern: impl Into<String>, replacement: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            regex: false,
+            language: None,
+        }
+    }
+    pub fn regex(pattern: impl Into<String>, replacement: impl Into<String>) -> Self {
+        Self {
+            regex: true,
+            ..Self::literal(pattern, replacement)
+        }
+    }
+    pub fn for_language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+    fn applies_to(&self, language: Option<&str>) -> bool {
+        let Some(ref rule_language) = self.language else {
+            return true;
+        };
+        let Some(language) = language else {
+            return false;
+        };
+        let normalize = |code: &str| code.replace('-', "_").to_lowercase();
+        let (rule_language, language) = (normalize(rule_language), normalize(language));
+        language == rule_language || language.starts_with(&format!("{}_", rule_language))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplacementsFile {
+    rules: Vec<ReplacementRule>,
+}
+
+struct CompiledRule {
+    rule: ReplacementRule,
+    regex: Option<Regex>,
+}
+
+impl CompiledRule {
+    fn new(rule: ReplacementRule) -> SonataResult<Self> {
+        let regex = if rule.regex {
+            Some(Regex::new(&rule.pattern).map_err(|e| {
+                SonataError::OperationError(format!(
+                    "Invalid replacement pattern `{}`. Error: {}",
+                    rule.pattern, e
+                ))
+            })?)
+        } else {
+            None
+        };
+        Ok(Self { rule, regex })
+    }
+}
+
+/// An ordered list of replacement rules, editable at runtime
+#[derive(Default)]
+pub struct ReplacementDictionary {
+    rules: RwLock<Vec<CompiledRule>>,
+    /// The file the dictionary was loaded from, used by [`ReplacementDictionary::save`]
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl ReplacementDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn from_rules(rules: Vec<ReplacementRule>) -> SonataResult<Self> {
+        let dictionary = Self::new();
+        dictionary.set_rules(rules)?;
+        Ok(dictionary)
+    }
+    /// Load the dictionary from a JSON file. If the file does not exist, the dictionary
+    /// starts empty and is created on [`ReplacementDictionary::save`].
+    pub fn from_file(path: &Path) -> SonataResult<Self> {
+        let dictionary = Self::new();
+        *dictionary.path.write().unwrap() = Some(path.to_path_buf());
+        if path.exists() {
+            dictionary.reload()?;
+        }
+        Ok(dictionary)
+    }
+    /// Re-read the rules from the dictionary's file
+    pub fn reload(&self) -> SonataResult<()> {
+        let Some(path) = self.path.read().unwrap().clone() else {
+            return Err(SonataError::OperationError(
+                "The replacement dictionary was not loaded from a file".to_string(),
+            ));
+        };
+        let file = std::fs::File::open(&path).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Failed to open replacements file `{}`",
+                path.display()
+            ))
+            .caused_by(e)
+        })?;
+        let contents: ReplacementsFile = serde_json::from_reader(file).map_err(|e| {
+            SonataError::FailedToLoadResource(format!(
+                "Invalid replacements file `{}`",
+                path.display()
+            ))
+            .caused_by(e)
+        })?;
+        self.set_rules(contents.rules)
+    }
+    /// The file the dictionary was loaded from or last saved to
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.read().unwrap().clone()
+    }
+    /// Save the rules to the file the dictionary was loaded from
+    pub fn save(&self) -> SonataResult<()> {
+        let Some(path) = self.path.read().unwrap().clone() else {
+            return Err(SonataError::OperationError(
+                "The replacement dictionary was not loaded from a file".to_string(),
+            ));
+        };
+        self.save_to(&path)
+    }
+    /// Save the rules to `path`, which becomes the dictionary's file
+    pub fn save_to(&self, path: &Path) -> SonataResult<()> {
+        let contents = ReplacementsFile {
+            rules: self.rules(),
+        };
+        let json = serde_json::to_string_pretty(&contents)
+            .map_err(|e| SonataError::OperationError(e.to_string()))?;
+        std::fs::write(path, json).map_err(|e| {
+            SonataError::OperationError(format!(
+                "Failed to write replacements file `{}`",
+                path.display()
+            ))
+            .caused_by(e)
+        })?;
+        *self.path.write().unwrap() = Some(path.to_path_buf());
+        Ok(())
+    }
+    pub fn rules(&self) -> Vec<ReplacementRule> {
+        Vec::from_iter(
+            self.rules
+                .read()
+                .unwrap()
+                .iter()
+                .map(|compiled| compiled.rule.clone()),
+        )
+    }
+    /// Replace all rules. Nothing is changed if any of the rules is invalid.
+    pub fn set_rules(&self, rules: Vec<ReplacementRule>) -> SonataResult<()> {
+        let compiled = rules
+            .into_iter()
+            .map(CompiledRule::new)
+            .collect::<SonataResult<Vec<_>>>()?;
+        *self.rules.write().unwrap() = compiled;
+        Ok(())
+    }
+    /// Append a rule, which is applied after the existing ones
+    pub fn add_rule(&self, rule: ReplacementRule) -> SonataResult<()> {
+        let compiled = CompiledRule::new(rule)?;
+        self.rules.write().unwrap().push(compiled);
+        Ok(())
+    }
+    /// Remove all rules with the given pattern, returning whether any rule was removed
+    pub fn remove_rule(&self, pattern: &str) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        let num_rules = rules.len();
+        rules.retain(|compiled| compiled.rule.pattern != pattern);
+        rules.len() != num_rules
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.read().unwrap().is_empty()
+    }
+    /// Apply the rules that match `language`, in order
+    pub fn apply(&self, text: &str, language: Option<&str>) -> String {
+        let mut text = text.to_string();
+        for compiled in self.rules.read().unwrap().iter() {
+            if !compiled.rule.applies_to(language) {
+                continue;
+            }
+            text = match compiled.regex {
+                Some(ref regex) => regex
+                    .replace_all(&text, compiled.rule.replacement.as_str())
+                    .into_owned(),
+                None => text.replace(&compiled.rule.pattern, &compiled.rule.replacement),
+            };
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_rules() -> SonataResult<()> {
+        let dictionary = ReplacementDictionary::from_rules(vec![
+            ReplacementRule::literal("km/h", "kilometers per hour").for_language("en"),
+            ReplacementRule::literal("km/h", "Kilometer pro Stunde").for_language("de"),
+            ReplacementRule::regex(r"\bGmbH\b", "G m b H"),
+            ReplacementRule::regex(r"(\d+)\s*kg\b", "$1 kilograms").for_language("en"),
+        ])?;
+        assert_eq!(
+            dictionary.apply("Max. 30 km/h, 5kg", Some("en_US")),
+            "Max. 30 kilometers per hour, 5 kilograms"
+        );
+        assert_eq!(
+            dictionary.apply("30 km/h, Muster GmbH", Some("de-DE")),
+            "30 Kilometer pro Stunde, Muster G m b H"
+        );
+        assert_eq!(dictionary.apply("30 km/h", None), "30 km/h");
+
+        assert!(dictionary.remove_rule(r"\bGmbH\b"));
+        assert_eq!(dictionary.apply("GmbH", None), "GmbH");
+        assert!(dictionary
+            .add_rule(ReplacementRule::regex("(", ""))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load() -> SonataResult<()> {
+        let path =
+            std::env::temp_dir().join(format!("sonata-replacements-{}.json", std::process::id()));
+        let dictionary = ReplacementDictionary::from_file(&path)?;
+        assert!(dictionary.is_empty());
+        dictionary.add_rule(ReplacementRule::literal("e.g.", "for example").for_language("en"))?;
+        dictionary.save()?;
+        let loaded = ReplacementDictionary::from_file(&path)?;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.rules(), dictionary.rules());
+        Ok(())
+    }
+}
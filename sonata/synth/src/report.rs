@@ -0,0 +1,122 @@
+//! A per-sentence account of a synthesized document, for auditing.
+//!
+//! Reports serialize to JSON. Sentences are identified by their phonemes: the text
+//! of individual sentences is not known once the document has been phonemized.
+
+use crate::{Audio, SkippedSentence, StageTimings, SynthesisStats};
+use serde::Serialize;
+
+/// Something that made the audio of a sentence differ from what was asked for
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SynthesisWarning {
+    /// Phonemes the voice can't speak were left out
+    DroppedPhonemes { phonemes: String },
+    /// The sentence failed to synthesize and was replaced by a placeholder
+    Placeholder { error: String, attempts: u32 },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SentenceReport {
+    pub index: usize,
+    pub phonemes: String,
+    /// Position of the sentence's audio in the output
+    pub offset_ms: f32,
+    pub duration_ms: f32,
+    pub inference_ms: Option<f32>,
+    pub timings: StageTimings,
+    pub warnings: Vec<SynthesisWarning>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SynthesisReport {
+    /// The text as it was phonemized, after replacements and normalization
+    pub text: String,
+    pub speaker: Option<i64>,
+    pub sentences: Vec<SentenceReport>,
+    /// What the phonemizer reported about the whole text, e.g. a missing dictionary
+    pub phonemizer_warnings: Vec<String>,
+    pub stats: SynthesisStats,
+}
+
+impl SynthesisReport {
+    pub(crate) fn new(text: String, speaker: Option<i64>) -> Self {
+        Self {
+            text,
+            speaker,
+            sentences: Vec::new(),
+            phonemizer_warnings: Vec::new(),
+            stats: SynthesisStats::default(),
+        }
+    }
+    pub(crate) fn add_sentence(
+        &mut self,
+        phonemes: String,
+        audio: &Audio,
+        unknown_phonemes: Vec<char>,
+    ) {
+        let mut warnings = Vec::new();
+        if !unknown_phonemes.is_empty() {
+            warnings.push(SynthesisWarning::DroppedPhonemes {
+                phonemes: String::from_iter(unknown_phonemes),
+            });
+        }
+        self.sentences.push(SentenceReport {
+            index: self.sentences.len(),
+            phonemes,
+            offset_ms: self.stats.audio_duration_ms,
+            duration_ms: audio.duration_ms(),
+            inference_ms: audio.inference_ms(),
+            timings: audio.timings,
+            warnings,
+        });
+        self.stats.add(audio);
+    }
+    pub(crate) fn add_skipped(&mut self, skipped: SkippedSentence) {
+        if let Some(sentence) = self.sentences.get_mut(skipped.index) {
+            // Placeholders are not synthesized
+            sentence.inference_ms = None;
+            sentence.warnings.push(SynthesisWarning::Placeholder {
+                error: skipped.error,
+                attempts: skipped.attempts,
+            });
+        }
+    }
+    pub fn warnings(&self) -> impl Iterator<Item = (usize, &SynthesisWarning)> {
+        self.sentences
+            .iter()
+            .flat_map(|sentence| sentence.warnings.iter().map(|w| (sentence.index, w)))
+    }
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesis_report() {
+        let mut report = SynthesisReport::new("Hi. There.".to_string(), Some(3));
+        let audio = Audio::new(vec![0.0; 500].into(), 1000, Some(50.0));
+        report.add_sentence("hˈaɪ.".to_string(), &audio, Vec::new());
+        report.add_sentence("ðˈɛɹ.".to_string(), &audio, vec!['ð']);
+        report.add_skipped(SkippedSentence {
+            index: 1,
+            phonemes: "ðˈɛɹ.".to_string(),
+            error: "inference failed".to_string(),
+            attempts: 2,
+        });
+        assert_eq!(report.sentences[1].offset_ms, 500.0);
+        assert_eq!(report.warnings().count(), 2);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["speaker"], 3);
+        assert_eq!(
+            json["sentences"][1]["warnings"][0]["kind"],
+            "dropped_phonemes"
+        );
+        assert_eq!(json["sentences"][1]["warnings"][1]["attempts"], 2);
+        assert!(json["sentences"][1]["inference_ms"].is_null());
+    }
+}
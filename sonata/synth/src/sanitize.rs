@@ -0,0 +1,229 @@
+//! Cleaning up input text before it reaches the phonemizer.
+//!
+//! Text from documents and the web often carries characters that are invisible when
+//! displayed but not to a phonemizer: control characters, bidirectional formatting,
+//! zero-width spaces and byte order marks. Runs of thousands of characters without a
+//! space, e.g. base64 blobs, make phonemizers slow or worse. The sanitizer removes,
+//! replaces or rejects the former, and breaks up the latter.
+
+use sonata_core::{SonataError, SonataResult};
+
+/// Longest run of characters without whitespace that is kept in one piece
+pub const DEFAULT_MAX_TOKEN_LEN: usize = 100;
+
+/// What to do with characters that should not be phonemized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SanitizePolicy {
+    /// Remove them
+    #[default]
+    Strip,
+    /// Replace each of them with the given character, e.g. a space
+    Replace(char),
+    /// Fail the synthesis, naming the first of them. Overlong tokens fail as well
+    Error,
+}
+
+/// Removes, replaces or rejects control characters, bidirectional formatting and
+/// invisible characters, and breaks up overlong tokens
+#[derive(Clone, Debug)]
+pub struct InputSanitizer {
+    policy: SanitizePolicy,
+    max_token_len: usize,
+}
+
+impl Default for InputSanitizer {
+    fn default() -> Self {
+        Self::new(SanitizePolicy::default())
+    }
+}
+
+impl InputSanitizer {
+    pub fn new(policy: SanitizePolicy) -> Self {
+        Self {
+            policy,
+            max_token_len: DEFAULT_MAX_TOKEN_LEN,
+        }
+    }
+    /// Break runs of more than `max_token_len` characters without whitespace with a
+    /// space (default [`DEFAULT_MAX_TOKEN_LEN`])
+    pub fn with_max_token_len(mut self, max_token_len: usize) -> Self {
+        self.max_token_len = max_token_len.max(1);
+        self
+    }
+    pub fn policy(&self) -> SanitizePolicy {
+        self.policy
+    }
+    pub fn sanitize(&self, text: &str) -> SonataResult<String> {
+        let chars = Vec::from_iter(text.chars());
+        let mut sanitized = String::with_capacity(text.len());
+        let mut token_len = 0;
+        for (i, c) in chars.iter().copied().enumerate() {
+            let is_unwanted = match c {
+                ZERO_WIDTH_JOINER | ZERO_WIDTH_NON_JOINER => {
+                    // Joiners shape the letters of Persian or Indic words and join
+                    // emoji, so only those outside of a word go
+                    let is_joined = |c: Option<&char>| c.is_some_and(|c| is_word_char(*c));
+                    !(is_joined(i.checked_sub(1).and_then(|i| chars.get(i)))
+                        && is_joined(chars.get(i + 1)))
+                }
+                c => is_unwanted(c),
+            };
+            let c = match (is_unwanted, self.policy) {
+                (false, _) => c,
+                (true, SanitizePolicy::Strip) => continue,
+                (true, SanitizePolicy::Replace(replacement)) => replacement,
+                (true, SanitizePolicy::Error) => {
+                    return Err(SonataError::OperationError(format!(
+                        "The text contains the unexpected character U+{:04X} at position {}",
+                        c as u32, i
+                    )))
+                }
+            };
+            if c.is_whitespace() {
+                token_len = 0;
+            } else if is_joiner(c) {
+                // Joiners are part of the character before them
+            } else if token_len == self.max_token_len {
+                if self.policy == SanitizePolicy::Error {
+                    return Err(SonataError::OperationError(format!(
+                        "The text contains a word longer than {} characters at position {}",
+                        self.max_token_len, i
+                    )));
+                }
+                // A joiner next to a break would have nothing to join
+                if sanitized.ends_with(is_joiner) {
+                    sanitized.pop();
+                }
+                sanitized.push(' ');
+                token_len = 1;
+            } else {
+                token_len += 1;
+            }
+            sanitized.push(c);
+        }
+        Ok(sanitized)
+    }
+}
+
+const ZERO_WIDTH_NON_JOINER: char = '\u{200C}';
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+fn is_unwanted(c: char) -> bool {
+    match c {
+        '\n' | '\r' | '\t' => false,
+        c if c.is_control() => true,
+        // Bidirectional marks, embeddings, overrides and isolates
+        '\u{061C}'
+        | '\u{200E}'
+        | '\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2066}'..='\u{2069}' => true,
+        // Zero-width space, word joiner, invisible operators and byte order mark
+        '\u{200B}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' => true,
+        // Soft hyphen, and noncharacters
+        '\u{00AD}' | '\u{FFFE}' | '\u{FFFF}' => true,
+        _ => false,
+    }
+}
+
+fn is_joiner(c: char) -> bool {
+    c == ZERO_WIDTH_JOINER || c == ZERO_WIDTH_NON_JOINER
+}
+
+/// Letters, marks and symbols: what joiners may join
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !is_unwanted(c) && !is_joiner(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_policies() {
+        let text = "a\u{0}b\u{202E}c\u{200B}d\ne";
+        let strip = InputSanitizer::default();
+        assert_eq!(strip.sanitize(text).unwrap(), "abcd\ne");
+        let replace = InputSanitizer::new(SanitizePolicy::Replace(' '));
+        assert_eq!(replace.sanitize(text).unwrap(), "a b c d\ne");
+        let error = InputSanitizer::new(SanitizePolicy::Error)
+            .sanitize(text)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("U+0000 at position 1"),
+            "{}",
+            error
+        );
+        assert!(InputSanitizer::new(SanitizePolicy::Error)
+            .sanitize("Hello there")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_joiners() {
+        let sanitizer = InputSanitizer::default();
+        // Persian uses the non-joiner inside words
+        assert_eq!(
+            sanitizer.sanitize("می\u{200C}خواهم").unwrap(),
+            "می\u{200C}خواهم"
+        );
+        assert_eq!(sanitizer.sanitize("👩\u{200D}💻").unwrap(), "👩\u{200D}💻");
+        assert_eq!(sanitizer.sanitize("\u{200D}a \u{200C} b").unwrap(), "a  b");
+    }
+
+    #[test]
+    fn test_sanitize_long_tokens() {
+        let sanitizer = InputSanitizer::default().with_max_token_len(4);
+        assert_eq!(
+            sanitizer.sanitize("abcdefghij kl").unwrap(),
+            "abcd efgh ij kl"
+        );
+        let error = InputSanitizer::new(SanitizePolicy::Error)
+            .with_max_token_len(4)
+            .sanitize("abcdefghij")
+            .unwrap_err();
+        assert!(error.to_string().contains("longer than 4"), "{}", error);
+    }
+
+    #[test]
+    fn test_sanitize_fuzz() {
+        const ALPHABET: &[char] = &[
+            'a', 'Z', ' ', '\n', '\t', '.', '\u{0}', '\u{1B}', '\u{85}', '\u{200B}', '\u{200C}',
+            '\u{200D}', '\u{202E}', '\u{2067}', '\u{FEFF}', '\u{FFFF}', 'é', 'ش', '👩', '\u{301}',
+        ];
+        // xorshift, so that failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let sanitizer = InputSanitizer::default().with_max_token_len(8);
+        for _ in 0..2000 {
+            let len = (next() % 64) as usize;
+            let text = String::from_iter(
+                (0..len).map(|_| ALPHABET[(next() % ALPHABET.len() as u64) as usize]),
+            );
+            let sanitized = sanitizer.sanitize(&text).unwrap();
+            assert!(
+                !sanitized.chars().any(is_unwanted),
+                "{:?} -> {:?}",
+                text,
+                sanitized
+            );
+            assert!(
+                sanitized.split(char::is_whitespace).all(|token| token
+                    .chars()
+                    .filter(|c| !is_joiner(*c))
+                    .count()
+                    <= 8),
+                "{:?} -> {:?}",
+                text,
+                sanitized
+            );
+            // Sanitizing is idempotent
+            assert_eq!(sanitizer.sanitize(&sanitized).unwrap(), sanitized);
+        }
+    }
+}
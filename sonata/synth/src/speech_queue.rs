@@ -0,0 +1,456 @@
+//! A queue of utterances, spoken one after the other.
+//!
+//! Modeled on speech-dispatcher: utterances are queued with a priority and spoken in
+//! priority order (first in, first out within a priority), through the streaming path.
+//! The audio and the progress of each utterance are reported to a callback, which
+//! typically writes the audio to an output device. The queue can be purged, the current
+//! utterance skipped, and speech paused and resumed. Urgent utterances interrupt the
+//! current one, which may then resume from the start of the sentence it was in.
+
+use crate::{AudioOutputConfig, SonataSpeechSynthesizer};
+use sonata_core::{AudioSamples, SonataError, StreamControl};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+pub type UtteranceId = u64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpeechPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// What happens to the utterances of a [`SpeechQueue`]
+#[derive(Debug)]
+pub enum SpeechQueueEvent {
+    Started(UtteranceId),
+    Audio(UtteranceId, AudioSamples),
+    Finished(UtteranceId),
+    /// The utterance was skipped or purged while it was spoken
+    Cancelled(UtteranceId),
+    /// The utterance was stopped for an urgent one, and queued again after it. It starts
+    /// again from the sentence it was in
+    Interrupted(UtteranceId),
+    Failed(UtteranceId, SonataError),
+}
+
+struct QueuedUtterance {
+    id: UtteranceId,
+    text: String,
+    priority: SpeechPriority,
+    output_config: Option<AudioOutputConfig>,
+    /// Sentence to start from, for resumed utterances
+    first_sentence: usize,
+}
+
+struct CurrentUtterance {
+    id: UtteranceId,
+    control: StreamControl,
+    /// The urgent utterance to resume this one after, once interrupted
+    resume_after: Option<UtteranceId>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: VecDeque<QueuedUtterance>,
+    current: Option<CurrentUtterance>,
+    next_id: UtteranceId,
+    paused: bool,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct SharedQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+/// Speaks queued utterances on a background thread
+pub struct SpeechQueue {
+    shared: Arc<SharedQueue>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SpeechQueue {
+    /// Speak the utterances with `synth`, streaming them in chunks of `chunk_size` mel
+    /// frames, and report them to `on_event`
+    pub fn new(
+        synth: Arc<SonataSpeechSynthesizer>,
+        chunk_size: usize,
+        chunk_padding: usize,
+        on_event: impl FnMut(SpeechQueueEvent) + Send + 'static,
+    ) -> Self {
+        let shared = Arc::new(SharedQueue::default());
+        let worker_shared = Arc::clone(&shared);
+        let worker = std::thread::spawn(move || {
+            speak_queued(worker_shared, synth, chunk_size, chunk_padding, on_event)
+        });
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+    /// Queue `text` after the utterances of the same or higher priority
+    pub fn enqueue(&self, text: String, priority: SpeechPriority) -> UtteranceId {
+        self.enqueue_with_config(text, priority, None)
+    }
+    pub fn enqueue_with_config(
+        &self,
+        text: String,
+        priority: SpeechPriority,
+        output_config: Option<AudioOutputConfig>,
+    ) -> UtteranceId {
+        let mut state = self.shared.state.lock().unwrap();
+        let utterance = state.new_utterance(text, priority, output_config);
+        let id = utterance.id;
+        let position = state
+            .pending
+            .iter()
+            .position(|queued| queued.priority < priority)
+            .unwrap_or(state.pending.len());
+        state.pending.insert(position, utterance);
+        self.shared.changed.notify_all();
+        id
+    }
+    /// Stop the current utterance and speak `text` right away, ahead of the queue. With
+    /// `resume_interrupted`, the interrupted utterance is spoken again after `text`, from
+    /// the start of the sentence it was in
+    pub fn interrupt(&self, text: String, resume_interrupted: bool) -> UtteranceId {
+        let mut state = self.shared.state.lock().unwrap();
+        let utterance = state.new_utterance(text, SpeechPriority::High, None);
+        let id = utterance.id;
+        state.pending.push_front(utterance);
+        if let Some(current) = state.current.as_mut() {
+            if resume_interrupted {
+                current.resume_after = Some(id);
+            }
+            current.control.stop(true);
+        }
+        self.shared.changed.notify_all();
+        id
+    }
+    /// Drop the queued utterances and stop the current one
+    pub fn purge(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pending.clear();
+        if let Some(current) = state.current.as_ref() {
+            current.control.stop(true);
+        }
+    }
+    /// Stop the current utterance, and go on with the next one
+    pub fn skip_current(&self) {
+        let state = self.shared.state.lock().unwrap();
+        if let Some(current) = state.current.as_ref() {
+            current.control.stop(true);
+        }
+    }
+    /// Pause the current utterance where it is, and hold back the queued ones
+    pub fn pause(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.paused = true;
+        if let Some(current) = state.current.as_ref() {
+            current.control.pause();
+        }
+    }
+    pub fn resume(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.paused = false;
+        if let Some(current) = state.current.as_ref() {
+            current.control.resume();
+        }
+        self.shared.changed.notify_all();
+    }
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+    /// The utterance being spoken
+    pub fn current(&self) -> Option<UtteranceId> {
+        let state = self.shared.state.lock().unwrap();
+        state.current.as_ref().map(|current| current.id)
+    }
+    /// Number of utterances waiting to be spoken
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl QueueState {
+    fn new_utterance(
+        &mut self,
+        text: String,
+        priority: SpeechPriority,
+        output_config: Option<AudioOutputConfig>,
+    ) -> QueuedUtterance {
+        let id = self.next_id;
+        self.next_id += 1;
+        QueuedUtterance {
+            id,
+            text,
+            priority,
+            output_config,
+            first_sentence: 0,
+        }
+    }
+}
+
+impl Drop for SpeechQueue {
+    fn drop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.closed = true;
+            state.pending.clear();
+            if let Some(current) = state.current.as_ref() {
+                current.control.stop(false);
+            }
+            self.shared.changed.notify_all();
+        }
+        if let Some(worker) = self.worker.take() {
+            worker.join().ok();
+        }
+    }
+}
+
+fn speak_queued(
+    shared: Arc<SharedQueue>,
+    synth: Arc<SonataSpeechSynthesizer>,
+    chunk_size: usize,
+    chunk_padding: usize,
+    mut on_event: impl FnMut(SpeechQueueEvent),
+) {
+    loop {
+        let (utterance, control) = {
+            let state = shared.state.lock().unwrap();
+            let mut state = shared
+                .changed
+                .wait_while(state, |state| {
+                    !state.closed && (state.paused || state.pending.is_empty())
+                })
+                .unwrap();
+            if state.closed {
+                return;
+            }
+            let utterance = state.pending.pop_front().unwrap();
+            // The control is known before the stream starts, so that the utterance can
+            // be skipped or paused while its text is phonemized
+            let control = StreamControl::new();
+            state.current = Some(CurrentUtterance {
+                id: utterance.id,
+                control: control.clone(),
+                resume_after: None,
+            });
+            (utterance, control)
+        };
+        let stream = synth.synthesize_streamed_with_control(
+            utterance.text.clone(),
+            utterance.output_config.clone(),
+            chunk_size,
+            chunk_padding,
+            utterance.first_sentence,
+            control,
+        );
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                shared.state.lock().unwrap().current = None;
+                on_event(SpeechQueueEvent::Failed(utterance.id, e));
+                continue;
+            }
+        };
+        let id = utterance.id;
+        on_event(SpeechQueueEvent::Started(id));
+        let mut failed = false;
+        for result in stream.by_ref() {
+            match result {
+                Ok(samples) => on_event(SpeechQueueEvent::Audio(id, samples)),
+                Err(e) => {
+                    on_event(SpeechQueueEvent::Failed(id, e));
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        // An utterance that was spoken to the end isn't resumed, even if it was
+        // interrupted after its last chunk
+        let complete = !failed && stream.is_complete();
+        let resumed = {
+            let mut state = shared.state.lock().unwrap();
+            let current = state.current.take();
+            let urgent_id = current
+                .and_then(|current| current.resume_after)
+                .filter(|_| !complete);
+            // The urgent utterance is gone if the queue was purged since
+            let urgent_position = urgent_id.and_then(|urgent_id| {
+                state
+                    .pending
+                    .iter()
+                    .position(|queued| queued.id == urgent_id)
+            });
+            if let Some(position) = urgent_position {
+                let resumed = QueuedUtterance {
+                    first_sentence: stream.sentence_index(),
+                    ..utterance
+                };
+                state.pending.insert(position + 1, resumed);
+            }
+            urgent_position.is_some()
+        };
+        if resumed {
+            on_event(SpeechQueueEvent::Interrupted(id));
+        } else if complete {
+            on_event(SpeechQueueEvent::Finished(id));
+        } else if stream.stop_handle().is_stopped() {
+            on_event(SpeechQueueEvent::Cancelled(id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonata_core::{
+        Audio, AudioInfo, AudioStreamIterator, Phonemes, SonataAudioResult, SonataModel,
+        SonataResult,
+    };
+    use std::any::Any;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Streams one chunk of silence per phonemized sentence
+    struct SilentModel;
+
+    impl SonataModel for SilentModel {
+        fn audio_output_info(&self) -> SonataResult<AudioInfo> {
+            Ok(AudioInfo {
+                sample_rate: 16000,
+                num_channels: 1,
+                sample_width: 2,
+            })
+        }
+        fn phonemize_text(&self, text: &str) -> SonataResult<Phonemes> {
+            Ok(vec![text.to_string()].into())
+        }
+        fn speak_batch(&self, phoneme_batches: Vec<String>) -> SonataResult<Vec<Audio>> {
+            phoneme_batches
+                .into_iter()
+                .map(|phonemes| self.speak_one_sentence(phonemes))
+                .collect()
+        }
+        fn speak_one_sentence(&self, _phonemes: String) -> SonataAudioResult {
+            Ok(Audio::new(vec![0.0; 160].into(), 16000, None))
+        }
+        fn get_default_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+            Ok(Box::new(()))
+        }
+        fn get_fallback_synthesis_config(&self) -> SonataResult<Box<dyn Any>> {
+            Ok(Box::new(()))
+        }
+        fn set_fallback_synthesis_config(&self, _synthesis_config: &dyn Any) -> SonataResult<()> {
+            Ok(())
+        }
+        fn supports_streaming_output(&self) -> bool {
+            true
+        }
+        fn stream_synthesis(
+            &self,
+            _phonemes: String,
+            _chunk_size: usize,
+            _chunk_padding: usize,
+        ) -> SonataResult<AudioStreamIterator<'_>> {
+            Ok(Box::new(std::iter::once(Ok(vec![0.0; 160].into()))))
+        }
+    }
+
+    #[test]
+    fn test_speech_queue_priorities() {
+        let synth = Arc::new(SonataSpeechSynthesizer::new(Arc::new(SilentModel)).unwrap());
+        let (tx, rx) = mpsc::channel();
+        let queue = SpeechQueue::new(synth, 10, 1, move |event| {
+            if let SpeechQueueEvent::Finished(id) = event {
+                tx.send(id).ok();
+            }
+        });
+        queue.pause();
+        let low = queue.enqueue("low".to_string(), SpeechPriority::Low);
+        let first = queue.enqueue("first".to_string(), SpeechPriority::Normal);
+        let high = queue.enqueue("high".to_string(), SpeechPriority::High);
+        let second = queue.enqueue("second".to_string(), SpeechPriority::Normal);
+        assert_eq!(queue.len(), 4);
+        queue.resume();
+        let finished =
+            Vec::from_iter((0..4).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()));
+        assert_eq!(finished, vec![high, first, second, low]);
+        queue.pause();
+        queue.enqueue("purged".to_string(), SpeechPriority::Normal);
+        queue.purge();
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_speech_queue_interrupt() {
+        let synth = Arc::new(SonataSpeechSynthesizer::new(Arc::new(SilentModel)).unwrap());
+        let (tx, rx) = mpsc::channel();
+        let (go_tx, go_rx) = mpsc::channel::<()>();
+        let mut blocked = false;
+        let queue = SpeechQueue::new(synth, 10, 1, move |event| {
+            let label = match event {
+                SpeechQueueEvent::Started(id) => ("started", id),
+                SpeechQueueEvent::Audio(id, _) => {
+                    // Hold the first utterance while it is spoken, until it is interrupted
+                    if !blocked {
+                        blocked = true;
+                        tx.send(("audio", id)).ok();
+                        go_rx.recv().ok();
+                    }
+                    return;
+                }
+                SpeechQueueEvent::Finished(id) => ("finished", id),
+                SpeechQueueEvent::Cancelled(id) => ("cancelled", id),
+                SpeechQueueEvent::Interrupted(id) => ("interrupted", id),
+                SpeechQueueEvent::Failed(id, _) => ("failed", id),
+            };
+            tx.send(label).ok();
+        });
+        let long = queue.enqueue("long".to_string(), SpeechPriority::Normal);
+        let next_event = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(next_event(), ("started", long));
+        assert_eq!(next_event(), ("audio", long));
+        let alert = queue.interrupt("alert".to_string(), true);
+        go_tx.send(()).unwrap();
+        let events = Vec::from_iter((0..5).map(|_| next_event()));
+        assert_eq!(
+            events,
+            vec![
+                ("interrupted", long),
+                ("started", alert),
+                ("finished", alert),
+                ("started", long),
+                ("finished", long)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stop_after_last_chunk() {
+        let synth = SonataSpeechSynthesizer::new(Arc::new(SilentModel)).unwrap();
+        let mut stream = synth
+            .synthesize_streamed("done".to_string(), None, 10, 1)
+            .unwrap();
+        assert!(stream.by_ref().all(|result| result.is_ok()));
+        assert!(stream.is_complete());
+        stream.stop(true);
+        assert!(stream.next().is_none());
+        assert!(stream.is_complete());
+        let mut stream = synth
+            .synthesize_streamed("stopped".to_string(), None, 10, 1)
+            .unwrap();
+        stream.stop(false);
+        assert!(stream.next().is_none());
+        assert!(!stream.is_complete());
+    }
+}
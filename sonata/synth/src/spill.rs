@@ -0,0 +1,269 @@
+//! Holding the audio of long syntheses on disk instead of in memory.
+
+use sonata_core::{SonataError, SonataResult, WaveFileWriter, WaveMetadata, WaveSampleFormat};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Samples read back from the spill file at once
+const READ_BLOCK_SAMPLES: usize = 64 * 1024;
+
+static NEXT_SPILL_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// Audio samples kept in memory up to a limit, beyond which they are moved to a
+/// temporary file. Samples are only scaled to full scale when the wave file is written,
+/// once the peak of the whole audio is known
+pub(crate) struct SpilledAudio {
+    in_memory: Vec<f32>,
+    max_in_memory: usize,
+    spill_file: Option<SpillFile>,
+    num_spilled: usize,
+    peak: f32,
+}
+
+impl SpilledAudio {
+    /// Keep at most `memory_limit` bytes of samples in memory
+    pub(crate) fn new(memory_limit: usize) -> Self {
+        Self {
+            in_memory: Vec::new(),
+            max_in_memory: (memory_limit / std::mem::size_of::<f32>()).max(1),
+            spill_file: None,
+            num_spilled: 0,
+            peak: f32::EPSILON,
+        }
+    }
+    /// Keep the samples in the file at `path`, which is not removed when dropped, after
+    /// its first `num_samples` samples. Any samples beyond those are discarded
+    pub(crate) fn resume(path: &Path, num_samples: usize, peak: f32) -> SonataResult<Self> {
+        Ok(Self {
+            in_memory: Vec::new(),
+            max_in_memory: 1,
+            spill_file: Some(SpillFile::open_persistent(path, num_samples)?),
+            num_spilled: num_samples,
+            peak: peak.max(f32::EPSILON),
+        })
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.num_spilled + self.in_memory.len()
+    }
+    pub(crate) fn peak(&self) -> f32 {
+        self.peak
+    }
+    /// Make sure that the samples pushed so far are on disk
+    pub(crate) fn sync(&mut self) -> SonataResult<()> {
+        if let Some(ref mut spill_file) = self.spill_file {
+            if !self.in_memory.is_empty() {
+                spill_file.write(&self.in_memory)?;
+                self.num_spilled += self.in_memory.len();
+                self.in_memory.clear();
+            }
+            spill_file.sync()?;
+        }
+        Ok(())
+    }
+    pub(crate) fn push(&mut self, samples: &[f32]) -> SonataResult<()> {
+        self.peak = samples.iter().fold(self.peak, |peak, f| peak.max(f.abs()));
+        self.in_memory.extend_from_slice(samples);
+        if self.in_memory.len() < self.max_in_memory {
+            return Ok(());
+        }
+        let spill_file = match self.spill_file {
+            Some(ref mut spill_file) => spill_file,
+            None => self.spill_file.insert(SpillFile::create()?),
+        };
+        spill_file.write(&self.in_memory)?;
+        self.num_spilled += self.in_memory.len();
+        self.in_memory.clear();
+        Ok(())
+    }
+    /// Write the audio to a wave file, scaled so that its peak is at full scale
+    pub(crate) fn write_wave(
+        self,
+        filename: &Path,
+        sample_rate: usize,
+        num_channels: usize,
+        format: WaveSampleFormat,
+        metadata: Option<&WaveMetadata>,
+    ) -> SonataResult<()> {
+        let num_samples = self.len();
+        self.write_waves(
+            [(filename, num_samples, metadata)],
+            sample_rate,
+            num_channels,
+            format,
+        )
+    }
+    /// Write the audio to consecutive wave files, given with their number of samples, all
+    /// scaled alike so that the peak of the whole audio is at full scale
+    pub(crate) fn write_waves<'f>(
+        self,
+        files: impl IntoIterator<Item = (&'f Path, usize, Option<&'f WaveMetadata>)>,
+        sample_rate: usize,
+        num_channels: usize,
+        format: WaveSampleFormat,
+    ) -> SonataResult<()> {
+        let scale = 1.0 / self.peak;
+        let mut reader = self.spill_file.map(SpillFile::into_reader).transpose()?;
+        let mut num_spilled = self.num_spilled;
+        let mut in_memory = self.in_memory.as_slice();
+        let mut bytes = vec![0u8; READ_BLOCK_SAMPLES * 4];
+        for (filename, num_samples, metadata) in files {
+            let mut writer = WaveFileWriter::create(
+                filename,
+                num_samples,
+                sample_rate as u32,
+                num_channels as u32,
+                format,
+                metadata,
+            )?;
+            let mut remaining = num_samples;
+            while remaining > 0 {
+                let samples = match reader {
+                    Some(ref mut reader) if num_spilled > 0 => {
+                        let block_len = remaining.min(num_spilled).min(READ_BLOCK_SAMPLES);
+                        reader
+                            .read_exact(&mut bytes[..block_len * 4])
+                            .map_err(spill_error)?;
+                        num_spilled -= block_len;
+                        Vec::from_iter(
+                            bytes[..block_len * 4]
+                                .chunks_exact(4)
+                                .map(|b| f32::from_le_bytes(b.try_into().unwrap()) * scale),
+                        )
+                    }
+                    _ => {
+                        if in_memory.is_empty() {
+                            return Err(SonataError::OperationError(format!(
+                                "Not enough samples to write `{}`",
+                                filename.display()
+                            )));
+                        }
+                        let (block, rest) = in_memory.split_at(remaining.min(in_memory.len()));
+                        in_memory = rest;
+                        Vec::from_iter(block.iter().map(|f| f * scale))
+                    }
+                };
+                writer.write_samples(&samples)?;
+                remaining -= samples.len();
+            }
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// A file of raw samples, removed when dropped unless it is persistent
+struct SpillFile {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    is_persistent: bool,
+}
+
+impl SpillFile {
+    fn create() -> SonataResult<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "sonata-spill-{}-{}.raw",
+            std::process::id(),
+            NEXT_SPILL_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&path).map_err(spill_error)?;
+        Ok(Self {
+            path,
+            writer: Some(BufWriter::new(file)),
+            is_persistent: false,
+        })
+    }
+    fn open_persistent(path: &Path, num_samples: usize) -> SonataResult<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(spill_error)?;
+        let len = (num_samples * std::mem::size_of::<f32>()) as u64;
+        if file.metadata().map_err(spill_error)?.len() < len {
+            return Err(SonataError::OperationError(format!(
+                "The samples file `{}` is shorter than expected",
+                path.display()
+            )));
+        }
+        file.set_len(len).map_err(spill_error)?;
+        file.seek(SeekFrom::End(0)).map_err(spill_error)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: Some(BufWriter::new(file)),
+            is_persistent: true,
+        })
+    }
+    fn write(&mut self, samples: &[f32]) -> SonataResult<()> {
+        let writer = self.writer.as_mut().unwrap();
+        let bytes = Vec::from_iter(samples.iter().flat_map(|f| f.to_le_bytes()));
+        writer.write_all(&bytes).map_err(spill_error)
+    }
+    fn sync(&mut self) -> SonataResult<()> {
+        let writer = self.writer.as_mut().unwrap();
+        writer.flush().map_err(spill_error)?;
+        writer.get_ref().sync_data().map_err(spill_error)
+    }
+    fn into_reader(mut self) -> SonataResult<SpillReader> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush().map_err(spill_error)?;
+        }
+        let file = File::open(&self.path).map_err(spill_error)?;
+        Ok(SpillReader {
+            reader: BufReader::new(file),
+            _spill_file: self,
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        self.writer = None;
+        if !self.is_persistent {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Reads a spill file back, which is removed once read unless it is persistent
+struct SpillReader {
+    reader: BufReader<File>,
+    _spill_file: SpillFile,
+}
+
+impl Read for SpillReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+fn spill_error(error: std::io::Error) -> SonataError {
+    SonataError::OperationError("Failed to keep audio in a file".to_string()).caused_by(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonata_core::{read_wave_file, AudioSamples};
+
+    #[test]
+    fn test_spilled_audio() -> SonataResult<()> {
+        let samples = Vec::from_iter((0..1000).map(|i| (i as f32 / 100.0).sin() * 0.5));
+        let mut audio = SpilledAudio::new(256);
+        for part in samples.chunks(30) {
+            audio.push(part)?;
+        }
+        assert!(audio.spill_file.is_some());
+        assert_eq!(audio.len(), samples.len());
+        let filename =
+            std::env::temp_dir().join(format!("sonata-spilled-{}.wav", std::process::id()));
+        audio.write_wave(&filename, 16000, 1, WaveSampleFormat::F32, None)?;
+        let read = read_wave_file(&filename)?;
+        std::fs::remove_file(&filename).unwrap();
+        let expected = AudioSamples::from(samples).to_full_scale_f32_vec();
+        assert_eq!(read.samples.as_slice(), expected.as_slice());
+        Ok(())
+    }
+}
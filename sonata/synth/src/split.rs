@@ -0,0 +1,361 @@
+//! Writing a document to several files, e.g. the chapters of a podcast or the lessons
+//! of a course, instead of a single one.
+//!
+//! The files are numbered after the output file, `book.wav` giving `book-001.wav`,
+//! `book-002.wav` and so on, and listed in an index next to them, `book.json`, along
+//! with their place in the whole document. Files are only ever cut between sentences.
+
+use crate::spill::SpilledAudio;
+use crate::Audio;
+use regex::Regex;
+use serde::Serialize;
+use sonata_core::{SonataError, SonataResult, WaveMetadata, WaveSampleFormat};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Markdown headings, and lines starting with "Chapter"
+const DEFAULT_CHAPTER_HEADING: &str = r"(?i)^\s*(#{1,6}\s|chapter\b)";
+
+/// Where to cut the output of `synthesize_to_file` into files, see
+/// [`crate::SonataSpeechSynthesizer::with_split_policy`]
+#[derive(Clone, Debug)]
+pub enum SplitPolicy {
+    /// One file per sentence
+    Sentence,
+    /// One file per chapter, each starting at a line that matches the pattern, see
+    /// [`SplitPolicy::chapters`]
+    Chapter(Regex),
+    /// As many sentences per file as fit in the duration. Sentences longer than that get
+    /// a file of their own
+    MaxDuration(Duration),
+}
+
+impl SplitPolicy {
+    /// One file per chapter, starting at Markdown headings and at lines starting with
+    /// "Chapter". The text before the first chapter, if any, gets a file of its own
+    pub fn chapters() -> Self {
+        Self::Chapter(Regex::new(DEFAULT_CHAPTER_HEADING).unwrap())
+    }
+    /// One file per chapter, starting at the lines that match `heading`
+    pub fn chapters_at(heading: &str) -> SonataResult<Self> {
+        Regex::new(heading).map(Self::Chapter).map_err(|e| {
+            SonataError::OperationError(format!("Invalid chapter heading pattern `{}`", heading))
+                .caused_by(e)
+        })
+    }
+    /// The parts of `text` that are synthesized separately, with their title
+    pub(crate) fn sections(&self, text: &str) -> Vec<(Option<String>, String)> {
+        let Self::Chapter(ref heading) = self else {
+            return vec![(None, text.to_string())];
+        };
+        let mut sections = Vec::new();
+        let mut title = None;
+        let mut section = String::new();
+        for line in text.lines() {
+            if heading.is_match(line) {
+                if title.is_some() || !section.trim().is_empty() {
+                    sections.push((title.take(), std::mem::take(&mut section)));
+                }
+                // The heading is spoken, without its Markdown marks
+                let heading = line.trim().trim_start_matches('#').trim();
+                title = Some(heading.to_string());
+                section.push_str(heading);
+            } else {
+                section.push_str(line);
+            }
+            section.push('\n');
+        }
+        if title.is_some() || !section.trim().is_empty() {
+            sections.push((title, section));
+        }
+        sections
+    }
+    /// Whether the next sentence, of `duration`, starts a new file after the sentences
+    /// of `part_duration` written to the current one, if any
+    pub(crate) fn splits_before(
+        &self,
+        part_duration: Option<Duration>,
+        duration: Duration,
+    ) -> bool {
+        match self {
+            Self::Sentence => part_duration.is_some(),
+            Self::Chapter(_) => false,
+            Self::MaxDuration(max_duration) => {
+                part_duration.is_some_and(|part_duration| part_duration + duration > *max_duration)
+            }
+        }
+    }
+}
+
+impl FromStr for SplitPolicy {
+    type Err = SonataError;
+
+    /// `sentence`, `chapter`, or a maximum duration in seconds, minutes or hours, e.g.
+    /// `90s`, `5m` or `1h` (seconds by default)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.as_str() {
+            "sentence" => return Ok(Self::Sentence),
+            "chapter" => return Ok(Self::chapters()),
+            _ => (),
+        }
+        let (value, unit_secs) = match s.strip_suffix('h') {
+            Some(hours) => (hours, 3600.0),
+            None => match s.strip_suffix('m') {
+                Some(minutes) => (minutes, 60.0),
+                None => (s.strip_suffix('s').unwrap_or(&s), 1.0),
+            },
+        };
+        let invalid = || {
+            SonataError::OperationError(format!(
+                "Unknown split policy `{}`. Expected `sentence`, `chapter` or a duration such as `5m`",
+                s
+            ))
+        };
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| invalid().caused_by(e))?;
+        match Duration::try_from_secs_f64(value * unit_secs) {
+            Ok(duration) if !duration.is_zero() => Ok(Self::MaxDuration(duration)),
+            Ok(_) => Err(invalid()),
+            Err(e) => Err(invalid().caused_by(e)),
+        }
+    }
+}
+
+/// A file of the output, as listed in the index
+#[derive(Clone, Debug, Serialize)]
+pub struct SplitFile {
+    /// File name, in the directory of the index
+    pub file: String,
+    /// The heading of the chapter
+    pub title: Option<String>,
+    /// Index of the first sentence of the file in the document
+    pub first_sentence: usize,
+    pub num_sentences: usize,
+    /// Position of the file's audio in the whole document
+    pub offset_ms: f32,
+    pub duration_ms: f32,
+}
+
+/// The files a document was written to, in order
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SplitIndex {
+    pub files: Vec<SplitFile>,
+    pub duration_ms: f32,
+}
+
+/// Writes the sentences of a document to numbered files, and the index of the files.
+///
+/// The audio is only written once the whole document is synthesized, so that all the
+/// files are scaled alike and are as loud as each other
+pub(crate) struct SplitWriter<'a> {
+    filename: &'a Path,
+    sample_format: WaveSampleFormat,
+    metadata: Option<&'a WaveMetadata>,
+    audio: SpilledAudio,
+    sample_rate: usize,
+    num_channels: usize,
+    /// Samples and sentences of the file being written
+    part_len: usize,
+    part_sentences: usize,
+    num_sentences: usize,
+    /// The files listed in the index, with their number of samples and metadata
+    parts: Vec<(PathBuf, usize, Option<WaveMetadata>)>,
+    index: SplitIndex,
+}
+
+impl<'a> SplitWriter<'a> {
+    /// Keep at most `memory_limit` bytes of samples in memory until the files are written
+    pub(crate) fn new(
+        filename: &'a Path,
+        sample_format: WaveSampleFormat,
+        metadata: Option<&'a WaveMetadata>,
+        memory_limit: usize,
+    ) -> Self {
+        Self {
+            filename,
+            sample_format,
+            metadata,
+            audio: SpilledAudio::new(memory_limit),
+            sample_rate: 0,
+            num_channels: 0,
+            part_len: 0,
+            part_sentences: 0,
+            num_sentences: 0,
+            parts: Vec::new(),
+            index: SplitIndex::default(),
+        }
+    }
+    /// Duration of the file being written, or `None` until a sentence is added to it
+    pub(crate) fn part_duration(&self) -> Option<Duration> {
+        (self.part_sentences > 0).then(|| {
+            Duration::from_secs_f64(
+                self.part_len as f64 / (self.sample_rate * self.num_channels) as f64,
+            )
+        })
+    }
+    pub(crate) fn push(&mut self, sentence: Audio) -> SonataResult<()> {
+        self.sample_rate = sentence.info.sample_rate;
+        self.num_channels = sentence.info.num_channels;
+        self.audio.push(sentence.samples.as_slice())?;
+        self.part_len += sentence.len();
+        self.part_sentences += 1;
+        Ok(())
+    }
+    /// End the file of the sentences added since the last file, if any
+    pub(crate) fn finish_part(&mut self, title: Option<String>) -> SonataResult<()> {
+        if self.part_sentences == 0 {
+            return Ok(());
+        }
+        let path = self.part_path(self.parts.len() + 1);
+        let metadata = self.metadata.map(|metadata| WaveMetadata {
+            title: metadata.title.clone().or_else(|| title.clone()),
+            ..metadata.clone()
+        });
+        let duration_ms = self.part_duration().unwrap_or_default().as_secs_f32() * 1000.0;
+        self.index.files.push(SplitFile {
+            file: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            title,
+            first_sentence: self.num_sentences,
+            num_sentences: self.part_sentences,
+            offset_ms: self.index.duration_ms,
+            duration_ms,
+        });
+        self.parts.push((path, self.part_len, metadata));
+        self.index.duration_ms += duration_ms;
+        self.num_sentences += self.part_sentences;
+        self.part_len = 0;
+        self.part_sentences = 0;
+        Ok(())
+    }
+    /// Write the last file and the index
+    pub(crate) fn finish(mut self) -> SonataResult<SplitIndex> {
+        self.finish_part(None)?;
+        if self.audio.len() == 0 {
+            return Err(SonataError::OperationError(
+                "No speech data to write".to_string(),
+            ));
+        }
+        self.audio.write_waves(
+            self.parts.iter().map(|(path, num_samples, metadata)| {
+                (path.as_path(), *num_samples, metadata.as_ref())
+            }),
+            self.sample_rate,
+            self.num_channels,
+            self.sample_format,
+        )?;
+        let index_path = self.filename.with_extension("json");
+        let json = serde_json::to_vec_pretty(&self.index).map_err(|e| {
+            SonataError::OperationError("Failed to serialize the index of the files".to_string())
+                .caused_by(e)
+        })?;
+        std::fs::write(&index_path, json).map_err(|e| {
+            SonataError::OperationError(format!(
+                "Failed to write the index of the files to `{}`",
+                index_path.display()
+            ))
+            .caused_by(e)
+        })?;
+        Ok(self.index)
+    }
+    /// `book.wav` gives `book-001.wav` for the first file
+    fn part_path(&self, number: usize) -> PathBuf {
+        let stem = self
+            .filename
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let name = match self.filename.extension() {
+            Some(extension) => format!("{}-{:03}.{}", stem, number, extension.to_string_lossy()),
+            None => format!("{}-{:03}", stem, number),
+        };
+        self.filename.with_file_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonata_core::{read_wave_file, AudioSamples};
+
+    #[test]
+    fn test_chapter_sections() {
+        let text = "A preface.\n\n# Chapter one\nIt begins.\n\nChapter 2: The end\nIt ends.\n";
+        let sections = SplitPolicy::chapters().sections(text);
+        let titles = Vec::from_iter(sections.iter().map(|(title, _)| title.as_deref()));
+        assert_eq!(
+            titles,
+            [None, Some("Chapter one"), Some("Chapter 2: The end")]
+        );
+        assert_eq!(sections[1].1, "Chapter one\nIt begins.\n\n");
+        // Without headings, the document is a single section
+        let sections = SplitPolicy::chapters().sections("No chapters here.");
+        assert_eq!(sections, [(None, "No chapters here.\n".to_string())]);
+        let sections = SplitPolicy::Sentence.sections(text);
+        assert_eq!(sections, [(None, text.to_string())]);
+        let sections = SplitPolicy::chapters_at("^Lesson").unwrap().sections(text);
+        assert_eq!(sections.len(), 1);
+        assert!(SplitPolicy::chapters_at("(").is_err());
+    }
+
+    #[test]
+    fn test_splits_before() {
+        let second = Duration::from_secs(1);
+        let policy = "5m".parse::<SplitPolicy>().unwrap();
+        assert!(matches!(policy, SplitPolicy::MaxDuration(duration) if duration.as_secs() == 300));
+        assert!(!policy.splits_before(None, second * 400));
+        assert!(!policy.splits_before(Some(second * 290), second * 10));
+        assert!(policy.splits_before(Some(second * 290), second * 11));
+        let sentence = "sentence".parse::<SplitPolicy>().unwrap();
+        assert!(!sentence.splits_before(None, second));
+        assert!(sentence.splits_before(Some(second), second));
+        assert!(!SplitPolicy::chapters().splits_before(Some(second * 3600), second));
+        assert!(matches!(
+            "90".parse::<SplitPolicy>().unwrap(),
+            SplitPolicy::MaxDuration(duration) if duration.as_secs() == 90
+        ));
+        assert!("0s".parse::<SplitPolicy>().is_err());
+        for invalid in ["-5m", "nans", "1e300h"] {
+            assert!(invalid.parse::<SplitPolicy>().is_err(), "{}", invalid);
+        }
+        assert!("paragraph".parse::<SplitPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parts_scaled_alike() -> SonataResult<()> {
+        let dir = std::env::temp_dir().join(format!("sonata-split-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("book.wav");
+        let sentence = |amplitude: f32| {
+            let samples = Vec::from_iter((0..1600).map(|i| (i as f32 / 10.0).sin() * amplitude));
+            Audio::new(AudioSamples::from(samples), 16000, None)
+        };
+        let mut writer = SplitWriter::new(&filename, WaveSampleFormat::F32, None, 1024);
+        writer.push(sentence(0.8))?;
+        writer.finish_part(None)?;
+        writer.push(sentence(0.2))?;
+        let index = writer.finish()?;
+        assert_eq!(index.files.len(), 2);
+        assert_eq!(index.files[1].first_sentence, 1);
+        assert!((index.duration_ms - 200.0).abs() < 0.01);
+        let peak = |file: &str| {
+            let audio = read_wave_file(&dir.join(file)).unwrap();
+            audio
+                .samples
+                .as_slice()
+                .iter()
+                .fold(0.0f32, |peak, f| peak.max(f.abs()))
+        };
+        let (loud, quiet) = (peak("book-001.wav"), peak("book-002.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!((loud - 1.0).abs() < 0.01);
+        assert!((quiet - 0.25).abs() < 0.01);
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Timing statistics of synthesized utterances.
+
+use crate::{Audio, StageTimings};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+/// Audio duration and inference time of the sentences of an utterance
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SynthesisStats {
+    pub num_sentences: usize,
+    pub audio_duration_ms: f32,
+    /// Total inference time of the sentences. For parallel synthesis this is the
+    /// compute time, which is longer than the wall-clock time.
+    pub inference_ms: f32,
+    /// Time spent in each stage, including the stages that run once per utterance
+    pub timings: StageTimings,
+}
+
+impl SynthesisStats {
+    /// Stats of an utterance whose stages before synthesis took `timings`
+    pub(crate) fn for_utterance(timings: StageTimings) -> Self {
+        Self {
+            timings,
+            ..Default::default()
+        }
+    }
+    pub fn add(&mut self, audio: &Audio) {
+        self.num_sentences += 1;
+        self.audio_duration_ms += audio.duration_ms();
+        self.inference_ms += audio.inference_ms().unwrap_or_default();
+        self.timings += audio.timings;
+    }
+    pub fn audio_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.audio_duration_ms as f64 / 1000.0)
+    }
+    pub fn inference_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.inference_ms as f64 / 1000.0)
+    }
+    /// Inference time divided by audio duration. Below `1.0` is faster than realtime.
+    pub fn real_time_factor(&self) -> f32 {
+        if self.audio_duration_ms > 0.0 {
+            self.inference_ms / self.audio_duration_ms
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for SynthesisStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "generated {:.1} s in {:.1} s (RTF {:.2})",
+            self.audio_duration_ms / 1000.0,
+            self.inference_ms / 1000.0,
+            self.real_time_factor()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesis_stats() {
+        let mut stats = SynthesisStats::default();
+        assert_eq!(stats.real_time_factor(), 0.0);
+        stats.add(&Audio::new(vec![0.0; 8000].into(), 1000, Some(400.0)));
+        let mut audio = Audio::new(vec![0.0; 4400].into(), 1000, Some(500.0));
+        audio.timings.postprocessing_ms = 2.0;
+        stats.add(&audio);
+        assert_eq!(stats.num_sentences, 2);
+        assert_eq!(stats.timings.postprocessing_ms, 2.0);
+        assert_eq!(stats.audio_duration(), Duration::from_millis(12400));
+        assert_eq!(stats.to_string(), "generated 12.4 s in 0.9 s (RTF 0.07)");
+    }
+}
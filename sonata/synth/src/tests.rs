@@ -1,6 +1,14 @@
 mod dev_utils;
 
-use sonata_synth::SonataResult;
+use sonata_piper::voice_manager::VoiceAlias;
+use sonata_piper::{PiperSynthesisConfig, VoiceManager};
+use sonata_synth::{
+    read_wave_file, AudioOutputConfig, ReplacementDictionary, ReplacementRule, SonataModel,
+    SonataResult, SonataSpeechSynthesizer, SplitPolicy, WaveSampleFormat, TELEPHONY_SAMPLE_RATE,
+};
+use sonata_test_utils::{compare_samples, tiny_voice_samples, TinyVoice, Tolerance};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 #[test]
 fn test_lazy_stream() -> SonataResult<()> {
@@ -26,3 +34,249 @@ fn test_realtime_stream() -> SonataResult<()> {
     let stream = synth.synthesize_streamed(text, output_config, 72, 3)?;
     dev_utils::iterate_stream(stream)
 }
+
+#[test]
+fn test_tiny_voice() -> SonataResult<()> {
+    let voice = sonata_piper::from_config_path(TinyVoice::new().load().config_path())?;
+    // Beginning of sentence, `h`, pad, `i`, pad and end of sentence
+    let audio = voice.speak_one_sentence("hi".to_string())?;
+    let expected = tiny_voice_samples(&[1, 11, 0, 12, 0, 2], 0);
+    compare_samples(audio.samples.as_slice(), &expected, Tolerance::default()).unwrap();
+
+    let synth = SonataSpeechSynthesizer::new(voice)?;
+    let text = "Hello there".to_string();
+    let mut lazy = Vec::new();
+    for audio in synth.synthesize_lazy(text.clone(), None)? {
+        lazy.extend_from_slice(audio?.samples.as_slice());
+    }
+    let mut parallel = Vec::new();
+    for audio in synth.synthesize_parallel(text, None)? {
+        parallel.extend_from_slice(audio?.samples.as_slice());
+    }
+    compare_samples(&parallel, &lazy, Tolerance::exact()).unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_builder() -> SonataResult<()> {
+    assert!(SonataSpeechSynthesizer::builder().build().is_err());
+    let voice = sonata_piper::from_config_path(TinyVoice::new().load().config_path())?;
+    let spoken = Arc::new(AtomicUsize::new(0));
+    let synth = SonataSpeechSynthesizer::builder()
+        .voice(voice)
+        .sample_format(WaveSampleFormat::F32)
+        .num_threads(2)
+        .on_sentence({
+            let spoken = Arc::clone(&spoken);
+            move |_, _| {
+                spoken.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .build()?;
+    assert_eq!(synth.sample_format(), WaveSampleFormat::F32);
+    for audio in synth.synthesize_parallel("Hello there".to_string(), None)? {
+        assert!(!audio?.is_empty());
+    }
+    // Text phonemes make one sentence
+    assert_eq!(spoken.load(Ordering::Relaxed), 1);
+    Ok(())
+}
+
+#[test]
+fn test_per_call_output_config() -> SonataResult<()> {
+    let voice = sonata_piper::from_config_path(TinyVoice::new().load().config_path())?;
+    let synth = SonataSpeechSynthesizer::new(voice)?;
+    let peak = |output_config: Option<AudioOutputConfig>| -> SonataResult<f32> {
+        let mut peak = 0.0f32;
+        for audio in synth.synthesize_lazy("hi".to_string(), output_config)? {
+            peak = audio?
+                .samples
+                .as_slice()
+                .iter()
+                .fold(peak, |peak, s| peak.max(s.abs()));
+        }
+        Ok(peak)
+    };
+    let quieter = AudioOutputConfig {
+        gain_db: Some(-6.0),
+        ..Default::default()
+    };
+    let ratio = peak(Some(quieter))? / peak(None)?;
+    assert!((ratio - 0.5).abs() < 0.01, "{}", ratio);
+    let too_fast = AudioOutputConfig {
+        rate_multiplier: Some(10.0),
+        ..Default::default()
+    };
+    assert!(synth
+        .synthesize_lazy("hi".to_string(), Some(too_fast))
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_voice_maps() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new().with_speakers(2);
+    let voice = sonata_piper::from_config_path(tiny_voice.load().config_path())?;
+    let synth = SonataSpeechSynthesizer::new(voice)?;
+    let phoneme_id_map = synth.phoneme_id_map()?.unwrap();
+    for (phoneme, id) in tiny_voice.phoneme_id_map() {
+        assert_eq!(phoneme_id_map[&phoneme], [id]);
+    }
+    let mut phonemes = Vec::from_iter(tiny_voice.phoneme_id_map().into_iter().map(|(p, _)| p));
+    phonemes.sort_unstable();
+    assert_eq!(synth.supported_phonemes()?, phonemes);
+    assert_eq!(synth.speaker_id_map()?.unwrap()["speaker_1"], 1);
+    Ok(())
+}
+
+#[test]
+fn test_voice_from_memory() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new();
+    let config = serde_json::from_str(&tiny_voice.config_json()).unwrap();
+    let voice = sonata_piper::from_config_value(config, tiny_voice.model_bytes())?;
+    let audio = voice.speak_one_sentence("hi".to_string())?;
+    assert_eq!(audio.len(), tiny_voice.expected_len("hi"));
+    Ok(())
+}
+
+#[test]
+fn test_speak_all_speakers() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new().with_speakers(2);
+    let config = serde_json::from_str(&tiny_voice.config_json()).unwrap();
+    let voice = sonata_piper::from_config_value(config, tiny_voice.model_bytes())?;
+    let replacements = vec![ReplacementRule::literal("x", "hi")];
+    let synth = SonataSpeechSynthesizer::builder()
+        .voice(voice)
+        .replacements(Arc::new(ReplacementDictionary::from_rules(replacements)?))
+        .build()?;
+    let audio = synth.speak_all_speakers("x".to_string(), None)?;
+    assert_eq!(audio.len(), 2);
+    for (expected_speaker, (speaker, audio)) in audio.into_iter().enumerate() {
+        assert_eq!(speaker, expected_speaker as i64);
+        let expected = tiny_voice_samples(&[1, 11, 0, 12, 0, 2], speaker);
+        compare_samples(audio.samples.as_slice(), &expected, Tolerance::default()).unwrap();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reload_config() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new();
+    let dir = tiny_voice.load();
+    let config_path = dir.config_path();
+    let replacements_path = dir.join("replacements.json");
+    let replacements = Arc::new(ReplacementDictionary::from_file(&replacements_path)?);
+    let synth = SonataSpeechSynthesizer::builder()
+        .voice(sonata_piper::from_config_path(config_path)?)
+        .replacements(Arc::clone(&replacements))
+        .build()?;
+    // Tune the voice, move its model and edit its replacements
+    let mut config: serde_json::Value = serde_json::from_str(&tiny_voice.config_json()).unwrap();
+    config["inference"]["length_scale"] = serde_json::json!(1.25);
+    config["model_path"] = serde_json::json!("tiny-v2.onnx");
+    std::fs::write(config_path, config.to_string()).unwrap();
+    std::fs::write(dir.join("tiny-v2.onnx"), tiny_voice.model_bytes()).unwrap();
+    ReplacementDictionary::from_rules(vec![ReplacementRule::literal("hi", "hello")])?
+        .save_to(&replacements_path)?;
+    synth.reload_config(config_path)?;
+    let synth_config = synth.get_fallback_synthesis_config()?;
+    let synth_config = synth_config.downcast_ref::<PiperSynthesisConfig>().unwrap();
+    assert_eq!(synth_config.length_scale, 1.25);
+    assert_eq!(replacements.rules().len(), 1);
+    let audio = synth.speak_one_sentence("hi".to_string())?;
+    assert_eq!(audio.len(), tiny_voice.expected_len("hi"));
+    let multi_speaker = TinyVoice::new().with_speakers(2);
+    std::fs::write(config_path, multi_speaker.config_json()).unwrap();
+    assert!(synth.reload_config(config_path).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_voice_alias() -> SonataResult<()> {
+    let manager = VoiceManager::new();
+    let voice_id = manager.load_voice(TinyVoice::new().with_speakers(2).load().config_path())?;
+    manager.set_alias(
+        "narrator",
+        VoiceAlias::new(&voice_id).with_speaker(1).with_rate(1.1),
+    )?;
+    let narrator = manager.resolve_voice("narrator", None)?;
+    assert_eq!(narrator.voice_id, voice_id);
+    assert_eq!((narrator.speaker, narrator.rate), (Some(1), Some(1.1)));
+    assert!(!narrator.is_fallback);
+    let synth = SonataSpeechSynthesizer::new(narrator.voice)?;
+    let output_config = AudioOutputConfig {
+        rate_multiplier: narrator.rate,
+        ..Default::default()
+    };
+    for audio in synth.synthesize_parallel("hi".to_string(), Some(output_config))? {
+        assert!(!audio?.is_empty());
+    }
+    // A speaker given along with the alias wins over the alias' speaker
+    let narrator = manager.resolve_voice("narrator", Some(0))?;
+    assert_eq!(narrator.speaker, Some(0));
+    Ok(())
+}
+
+#[cfg(feature = "piper")]
+#[test]
+fn test_resolved_voice() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new();
+    let manager = VoiceManager::new();
+    let voice_id = manager.load_voice(tiny_voice.load().config_path())?;
+    manager.set_alias("hasty", VoiceAlias::new(&voice_id).with_rate(3.0))?;
+    let synth = SonataSpeechSynthesizer::builder()
+        .resolved_voice(manager.resolve_voice("hasty", None)?)
+        .build()?;
+    // The tiny voice ignores its scales, so the whole rate is time-stretched
+    let mut len = 0;
+    for audio in synth.synthesize_lazy("hi".to_string(), None)? {
+        len += audio?.len();
+    }
+    assert!(len < tiny_voice.expected_len("hi") * 2 / 3, "{}", len);
+    Ok(())
+}
+
+#[test]
+fn test_telephony_output() -> SonataResult<()> {
+    let tiny_voice = TinyVoice::new();
+    let dir = tiny_voice.load();
+    let voice = sonata_piper::from_config_path(dir.config_path())?;
+    let synth = SonataSpeechSynthesizer::new(voice)?.with_sample_format(WaveSampleFormat::MuLaw);
+    let filename = dir.join("mulaw.wav");
+    synth.synthesize_to_file(&filename, "hi".to_string(), None)?;
+    // Writing sentence by sentence resamples the same way
+    let spilling = synth.with_memory_limit(1024);
+    let spilled_filename = dir.join("mulaw-spilled.wav");
+    spilling.synthesize_to_file(&spilled_filename, "hi".to_string(), None)?;
+    for audio in [
+        read_wave_file(&filename)?,
+        read_wave_file(&spilled_filename)?,
+    ] {
+        assert_eq!(audio.info.sample_rate, TELEPHONY_SAMPLE_RATE);
+        assert_eq!(audio.info.sample_width, 1);
+        // The tiny voice speaks at 16 kHz
+        assert_eq!(audio.len(), tiny_voice.expected_len("hi") / 2);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_split_output() -> SonataResult<()> {
+    let dir = TinyVoice::new().load();
+    let voice = sonata_piper::from_config_path(dir.config_path())?;
+    let synth = SonataSpeechSynthesizer::new(voice)?.with_split_policy(SplitPolicy::chapters());
+    let filename = dir.join("book.wav");
+    synth.synthesize_to_file(&filename, "# One\nhi\n# Two\nhi".to_string(), None)?;
+    let index = std::fs::read_to_string(dir.join("book.json")).unwrap();
+    let index: serde_json::Value = serde_json::from_str(&index).unwrap();
+    let files = index["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[1]["file"], "book-002.wav");
+    assert_eq!(files[1]["title"], "Two");
+    assert_eq!(files[1]["offset_ms"], files[0]["duration_ms"]);
+    for name in ["book-001.wav", "book-002.wav"] {
+        assert!(!read_wave_file(&dir.join(name))?.is_empty());
+    }
+    assert!(!filename.exists());
+    Ok(())
+}
@@ -0,0 +1,252 @@
+//! Praat TextGrids of synthesized speech, timed by the durations the model predicted.
+//!
+//! A TextGrid has a `words` and a `phones` tier, in that order. Labels are phonemes,
+//! since the text of each word is not known once the document has been phonemized.
+//! Stress marks belong to the phone after them, and length marks, diacritics and tied
+//! phonemes to the phone before them. Pauses, i.e. spaces and punctuation, are left as
+//! empty intervals, as are the silences around sentences.
+
+use sonata_core::{SonataError, SonataResult};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+/// A labelled span of a tier, in seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextGridInterval {
+    pub start: f64,
+    pub end: f64,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TextGrid {
+    duration: f64,
+    words: Vec<TextGridInterval>,
+    phones: Vec<TextGridInterval>,
+}
+
+impl TextGrid {
+    /// Total duration of the audio, in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+    /// The words, without the pauses between them
+    pub fn words(&self) -> &[TextGridInterval] {
+        &self.words
+    }
+    /// The phones, without the pauses between them
+    pub fn phones(&self) -> &[TextGridInterval] {
+        &self.phones
+    }
+    /// Add a sentence of `duration` seconds after the sentences added before.
+    /// `alignment` is the span of each phoneme (`char`) of `phonemes`, in seconds from
+    /// the start of the sentence
+    pub(crate) fn add_sentence(&mut self, phonemes: &str, alignment: &[Range<f64>], duration: f64) {
+        let offset = self.duration;
+        let mut phone: Option<TextGridInterval> = None;
+        let mut word: Option<TextGridInterval> = None;
+        // Stress marks waiting for their phone, and where they start
+        let mut stress: Option<(f64, String)> = None;
+        let mut is_tied = false;
+        for (phoneme, span) in phonemes.chars().zip(alignment) {
+            let (start, end) = (offset + span.start, offset + span.end);
+            if is_pause(phoneme) {
+                push_interval(&mut self.phones, phone.take());
+                push_interval(&mut self.words, word.take());
+                stress = None;
+                is_tied = false;
+                continue;
+            }
+            let word = word.get_or_insert_with(|| TextGridInterval {
+                start: stress.as_ref().map_or(start, |(start, _)| *start),
+                end,
+                label: String::new(),
+            });
+            word.end = end;
+            word.label.push(phoneme);
+            if is_stress_mark(phoneme) {
+                stress.get_or_insert((start, String::new())).1.push(phoneme);
+                continue;
+            }
+            match phone {
+                Some(ref mut phone) if is_tied || is_modifier(phoneme) => {
+                    phone.end = end;
+                    phone.label.push(phoneme);
+                }
+                _ => {
+                    push_interval(&mut self.phones, phone.take());
+                    let (start, mut label) = stress.take().unwrap_or((start, String::new()));
+                    label.push(phoneme);
+                    phone = Some(TextGridInterval { start, end, label });
+                }
+            }
+            is_tied = is_tie(phoneme);
+        }
+        push_interval(&mut self.phones, phone);
+        push_interval(&mut self.words, word);
+        self.duration = offset + duration;
+    }
+    pub fn save_to_file(&self, filename: &Path) -> SonataResult<()> {
+        std::fs::write(filename, self.to_string()).map_err(|e| {
+            SonataError::OperationError(format!(
+                "Failed to write TextGrid `{}`",
+                filename.display()
+            ))
+            .caused_by(e)
+        })
+    }
+}
+
+/// The long text format of Praat
+impl fmt::Display for TextGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File type = \"ooTextFile\"")?;
+        writeln!(f, "Object class = \"TextGrid\"")?;
+        writeln!(f)?;
+        writeln!(f, "xmin = 0")?;
+        writeln!(f, "xmax = {}", self.duration)?;
+        writeln!(f, "tiers? <exists>")?;
+        writeln!(f, "size = 2")?;
+        writeln!(f, "item []:")?;
+        for (index, (name, intervals)) in [("words", &self.words), ("phones", &self.phones)]
+            .into_iter()
+            .enumerate()
+        {
+            let intervals = with_pauses(intervals, self.duration);
+            writeln!(f, "    item [{}]:", index + 1)?;
+            writeln!(f, "        class = \"IntervalTier\"")?;
+            writeln!(f, "        name = \"{}\"", name)?;
+            writeln!(f, "        xmin = 0")?;
+            writeln!(f, "        xmax = {}", self.duration)?;
+            writeln!(f, "        intervals: size = {}", intervals.len())?;
+            for (index, interval) in intervals.iter().enumerate() {
+                writeln!(f, "        intervals [{}]:", index + 1)?;
+                writeln!(f, "            xmin = {}", interval.start)?;
+                writeln!(f, "            xmax = {}", interval.end)?;
+                // Praat doubles the quotes in strings
+                let label = interval.label.replace('"', "\"\"");
+                writeln!(f, "            text = \"{}\"", label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add `interval` to `tier` unless it takes no time, which Praat does not allow
+fn push_interval(tier: &mut Vec<TextGridInterval>, interval: Option<TextGridInterval>) {
+    if let Some(interval) = interval.filter(|interval| interval.end > interval.start) {
+        tier.push(interval);
+    }
+}
+
+/// `intervals` with empty intervals filling the time between them, up to `duration`
+fn with_pauses(intervals: &[TextGridInterval], duration: f64) -> Vec<TextGridInterval> {
+    let mut filled = Vec::with_capacity(intervals.len() * 2 + 1);
+    let mut end = 0.0;
+    for interval in intervals {
+        if interval.start > end {
+            filled.push(TextGridInterval {
+                start: end,
+                end: interval.start,
+                label: String::new(),
+            });
+        }
+        filled.push(interval.clone());
+        end = interval.end;
+    }
+    if duration > end || filled.is_empty() {
+        filled.push(TextGridInterval {
+            start: end,
+            end: duration.max(end),
+            label: String::new(),
+        });
+    }
+    filled
+}
+
+fn is_pause(phoneme: char) -> bool {
+    phoneme.is_whitespace()
+        || phoneme.is_ascii_punctuation()
+        || matches!(
+            phoneme,
+            '…' | '—' | '–' | '¡' | '¿' | '«' | '»' | '“' | '”' | '„' | '、' | '。' | '，'
+        )
+}
+
+fn is_stress_mark(phoneme: char) -> bool {
+    matches!(phoneme, 'ˈ' | 'ˌ')
+}
+
+/// Length marks, modifier letters (e.g. aspiration) and combining diacritics
+fn is_modifier(phoneme: char) -> bool {
+    matches!(phoneme, '\u{02B0}'..='\u{02FF}' | '\u{0300}'..='\u{036F}' | '\u{1D2C}'..='\u{1D6A}')
+}
+
+/// Tie bars, which join the phonemes around them, e.g. `t͡ʃ`
+fn is_tie(phoneme: char) -> bool {
+    matches!(phoneme, '\u{0361}' | '\u{035C}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each phoneme takes 0.1 s
+    fn uniform_alignment(phonemes: &str) -> Vec<Range<f64>> {
+        Vec::from_iter(
+            (0..phonemes.chars().count()).map(|i| i as f64 / 10.0..(i + 1) as f64 / 10.0),
+        )
+    }
+
+    fn labels(intervals: &[TextGridInterval]) -> Vec<&str> {
+        Vec::from_iter(intervals.iter().map(|interval| interval.label.as_str()))
+    }
+
+    #[test]
+    fn test_textgrid_tiers() {
+        let mut textgrid = TextGrid::default();
+        let phonemes = "ˈaːt͡ʃ bʰe.";
+        textgrid.add_sentence(phonemes, &uniform_alignment(phonemes), 1.5);
+        textgrid.add_sentence("o", &[0.5..0.75], 1.0);
+        assert_eq!(labels(textgrid.words()), ["ˈaːt͡ʃ", "bʰe", "o"]);
+        assert_eq!(labels(textgrid.phones()), ["ˈaː", "t͡ʃ", "bʰ", "e", "o"]);
+        assert_eq!(textgrid.duration(), 2.5);
+        let word = &textgrid.words()[0];
+        assert_eq!((word.start, word.end), (0.0, 0.6));
+        let phone = &textgrid.phones()[4];
+        assert_eq!((phone.start, phone.end), (2.0, 2.25));
+    }
+
+    #[test]
+    fn test_textgrid_format() {
+        let mut textgrid = TextGrid::default();
+        textgrid.add_sentence("a\"", &[0.5..1.0, 1.0..1.0], 2.0);
+        // Phonemes the voice doesn't know take no time, and are left out
+        textgrid.add_sentence("x", &[0.0..0.0], 1.0);
+        let textgrid = textgrid.to_string();
+        assert!(textgrid.starts_with("File type = \"ooTextFile\"\nObject class = \"TextGrid\"\n"));
+        assert!(textgrid.contains("xmax = 3\ntiers? <exists>\nsize = 2\n"));
+        assert!(textgrid.contains(
+            "        intervals: size = 3\n        intervals [1]:\n            xmin = 0\n            xmax = 0.5\n            text = \"\"\n        intervals [2]:\n            xmin = 0.5\n            xmax = 1\n            text = \"a\"\n"
+        ));
+        assert_eq!(textgrid.matches("name = ").count(), 2);
+        assert!(!textgrid.contains("\"x\""));
+
+        let mut quoted = TextGrid::default();
+        quoted.words.push(TextGridInterval {
+            start: 0.0,
+            end: 1.0,
+            label: "say \"a\"".to_string(),
+        });
+        assert!(quoted.to_string().contains("text = \"say \"\"a\"\"\""));
+        // An empty grid still has one interval per tier
+        assert_eq!(
+            TextGrid::default()
+                .to_string()
+                .matches("intervals: size = 1")
+                .count(),
+            2
+        );
+    }
+}
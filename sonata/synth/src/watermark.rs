@@ -0,0 +1,271 @@
+//! Marking synthesized speech, so that it can be recognized later.
+//!
+//! A [`Watermark`] adds a faint noise to the audio, a pseudo-random sequence of `±1`
+//! drawn from a secret key and the index of each frame in its sentence. The sequence
+//! doesn't repeat, so it can't be recovered by averaging the audio over a period, and
+//! doesn't sound like a tone. Its level follows the loudness of the speech, about 40 dB
+//! below it by default, so silence stays silent. Whoever holds the key can find the
+//! sequence again with [`Watermark::detect`], in a file written by the synthesizer or in
+//! an excerpt of a few seconds of it.
+//!
+//! The sequence survives changes of volume and 16-bit encoding, but not resampling or
+//! lossy compression.
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use sonata_core::Audio;
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+/// Frames over which the loudness of the speech is measured
+const BLOCK: usize = 256;
+/// Frames searched for the sequence at once, about 3 seconds at 22050 Hz. Spans
+/// overlap by half, so that a sentence shorter than a span fills one of them
+const SPAN: usize = 1 << 16;
+/// Frames correlated at once: a span, against the sequence from any offset up to
+/// [`MAX_OFFSET`]
+const CORRELATION_LEN: usize = 1 << 19;
+/// Frames into a sentence the sequence is found up to, about 20 seconds at 22050 Hz.
+/// Longer sentences are found by their beginning
+const MAX_OFFSET: usize = CORRELATION_LEN - SPAN;
+const DEFAULT_STRENGTH: f32 = 0.01;
+/// Scores of unmarked audio stay below this, even in hours of it
+pub const DETECTION_THRESHOLD: f32 = 7.0;
+
+/// A noise sequence embedded into the audio of a synthesizer, keyed by a secret, see
+/// [`crate::SonataSpeechSynthesizer::with_watermark`]
+#[derive(Clone)]
+pub struct Watermark {
+    key_hash: u64,
+    strength: f32,
+}
+
+impl Watermark {
+    /// The watermark of `key`, a secret of the deployment
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key_hash: xxh3_64(key),
+            strength: DEFAULT_STRENGTH,
+        }
+    }
+    /// Level of the sequence relative to the speech, between `0.001` and `0.1` (default
+    /// `0.01`). Stronger watermarks are found in shorter excerpts, but are more audible
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength.clamp(0.001, 0.1);
+        self
+    }
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+    /// Tells watermarks apart in cache keys
+    pub(crate) fn key_hash(&self) -> u64 {
+        self.key_hash
+    }
+    /// The value of the sequence at `frame` of a sentence
+    fn chip(&self, frame: usize) -> f32 {
+        if xxh3_64_with_seed(&(frame as u64).to_le_bytes(), self.key_hash) & 1 == 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+    /// Add the sequence to `samples`, of `num_channels` interleaved channels, starting
+    /// at frame `offset` of the sequence. Sentences start at frame `0`, and the chunks
+    /// of a sentence where the previous chunk ended
+    pub fn embed(&self, samples: &mut [f32], num_channels: usize, offset: usize) {
+        let num_channels = num_channels.max(1);
+        for (block_index, block) in samples.chunks_mut(BLOCK * num_channels).enumerate() {
+            let rms = (block.iter().map(|sample| sample * sample).sum::<f32>()
+                / block.len() as f32)
+                .sqrt();
+            let amplitude = self.strength * rms;
+            if amplitude == 0.0 {
+                continue;
+            }
+            let first_frame = offset + block_index * BLOCK;
+            for (index, frame) in block.chunks_mut(num_channels).enumerate() {
+                let mark = amplitude * self.chip(first_frame + index);
+                frame.iter_mut().for_each(|sample| *sample += mark);
+            }
+        }
+    }
+    /// Look for the sequence in `audio`.
+    ///
+    /// Each sentence is marked from its own start, which is found by correlating spans
+    /// of about 3 seconds with the sequence at every offset of its first 20 seconds.
+    /// The score is that of the best span and offset.
+    pub fn detect(&self, audio: &Audio) -> WatermarkDetection {
+        let num_channels = audio.info.num_channels.max(1);
+        let mono = Vec::from_iter(
+            audio
+                .samples
+                .as_slice()
+                .chunks(num_channels)
+                .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32),
+        );
+        // The sequence is white, while speech is mostly low-pitched: the differences of
+        // consecutive frames bring it out
+        let mut differences = Vec::from_iter(
+            std::iter::once(0.0).chain(mono.windows(2).map(|pair| pair[1] - pair[0])),
+        );
+        // The sequence follows the loudness of the speech, so loud and quiet passages
+        // weigh the same
+        for block in differences.chunks_mut(BLOCK) {
+            let rms = (block.iter().map(|sample| sample * sample).sum::<f32>()
+                / block.len() as f32)
+                .sqrt();
+            let scale = if rms > 1e-7 { rms.recip() } else { 0.0 };
+            block.iter_mut().for_each(|sample| *sample *= scale);
+        }
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(CORRELATION_LEN);
+        let inverse = planner.plan_fft_inverse(CORRELATION_LEN);
+        // The differences of the sequence, whose spectrum each span is correlated with
+        let mut sequence = Vec::from_iter((0..CORRELATION_LEN).map(|frame| {
+            let previous = frame.checked_sub(1).map_or(0.0, |frame| self.chip(frame));
+            Complex::new(self.chip(frame) - previous, 0.0)
+        }));
+        forward.process(&mut sequence);
+        let score = (0..differences.len().max(1))
+            .step_by(SPAN / 2)
+            .filter_map(|start| {
+                let span = &differences[start..differences.len().min(start + SPAN)];
+                let mut buffer = vec![Complex::new(0.0, 0.0); CORRELATION_LEN];
+                for (value, sample) in buffer.iter_mut().zip(span) {
+                    value.re = *sample;
+                }
+                forward.process(&mut buffer);
+                for (value, sequence) in buffer.iter_mut().zip(&sequence) {
+                    *value = value.conj() * sequence;
+                }
+                inverse.process(&mut buffer);
+                // Offsets past `MAX_OFFSET` would wrap around
+                let correlations =
+                    Vec::from_iter(buffer[..=MAX_OFFSET].iter().map(|value| value.re));
+                standard_scores(&correlations).reduce(f32::max)
+            })
+            .fold(0.0, f32::max);
+        WatermarkDetection {
+            score,
+            detected: score >= DETECTION_THRESHOLD,
+        }
+    }
+}
+
+/// Standard scores of the correlations of a span with the sequence, empty for silence
+fn standard_scores(correlations: &[f32]) -> impl Iterator<Item = f32> + '_ {
+    let mean = correlations.iter().sum::<f32>() / correlations.len() as f32;
+    let deviation = (correlations
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f32>()
+        / correlations.len() as f32)
+        .sqrt();
+    let scale = if deviation > f32::EPSILON {
+        deviation.recip()
+    } else {
+        0.0
+    };
+    correlations
+        .iter()
+        .filter(move |_| scale > 0.0)
+        .map(move |value| (value - mean) * scale)
+}
+
+/// The result of [`Watermark::detect`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatermarkDetection {
+    /// How strongly the sequence stands out of the audio, in standard deviations
+    pub score: f32,
+    /// Whether the score reaches [`DETECTION_THRESHOLD`]
+    pub detected: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sentences of a voiced, noisy signal whose loudness varies, with pauses between them
+    fn sentences(lengths: &[usize]) -> Vec<Vec<f32>> {
+        let mut seed = 1u64;
+        Vec::from_iter(lengths.iter().map(|length| {
+            Vec::from_iter((0..*length).map(|index| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let noise = (seed >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+                let time = index as f32 / 22050.0;
+                let loudness = 0.3 + 0.25 * (time * 3.0).sin();
+                let voiced = (1..6)
+                    .map(|harmonic| {
+                        (time * 140.0 * harmonic as f32 * std::f32::consts::TAU).sin()
+                            / harmonic as f32
+                    })
+                    .sum::<f32>();
+                loudness * (0.5 * voiced + 0.2 * noise)
+            }))
+        }))
+    }
+
+    fn concat(sentences: Vec<Vec<f32>>) -> Audio {
+        let mut samples = Vec::new();
+        for sentence in sentences {
+            samples.extend(sentence);
+            samples.extend(std::iter::repeat_n(0.0, 3001));
+        }
+        Audio::new(samples.into(), 22050, None)
+    }
+
+    #[test]
+    fn test_detect() {
+        let watermark = Watermark::new(b"secret");
+        let mut marked = sentences(&[50000, 70000, 41234]);
+        for sentence in marked.iter_mut() {
+            // In chunks, as in realtime streams
+            let mut offset = 0;
+            for chunk in sentence.chunks_mut(7000) {
+                watermark.embed(chunk, 1, offset);
+                offset += chunk.len();
+            }
+        }
+        let marked = concat(marked);
+        let detection = watermark.detect(&marked);
+        assert!(detection.detected, "{:?}", detection);
+        assert!(!Watermark::new(b"other secret").detect(&marked).detected);
+        let unmarked = concat(sentences(&[50000, 70000, 41234]));
+        let detection = watermark.detect(&unmarked);
+        assert!(!detection.detected, "{:?}", detection);
+        // A few seconds are enough
+        let excerpt = Audio::new(
+            marked.samples.as_slice()[60000..120000].to_vec().into(),
+            22050,
+            None,
+        );
+        assert!(watermark.detect(&excerpt).detected);
+        let silence = Audio::new(vec![0.0; 30000].into(), 22050, None);
+        assert_eq!(watermark.detect(&silence).score, 0.0);
+    }
+
+    #[test]
+    fn test_sequence_does_not_repeat() {
+        let watermark = Watermark::new(b"secret");
+        for period in [512, 4096, SPAN] {
+            let agreement = (0..SPAN)
+                .map(|frame| watermark.chip(frame) * watermark.chip(frame + period))
+                .sum::<f32>()
+                / SPAN as f32;
+            assert!(agreement.abs() < 0.02, "{}: {}", period, agreement);
+        }
+    }
+
+    #[test]
+    fn test_embed_follows_loudness() {
+        let watermark = Watermark::new(b"secret");
+        let mut samples = vec![0.0; BLOCK * 2];
+        samples[BLOCK..].fill(0.5);
+        watermark.embed(&mut samples, 1, 0);
+        assert!(samples[..BLOCK].iter().all(|sample| *sample == 0.0));
+        assert!(samples[BLOCK..]
+            .iter()
+            .all(|sample| (sample - 0.5).abs() <= 0.5 * DEFAULT_STRENGTH + f32::EPSILON));
+    }
+}
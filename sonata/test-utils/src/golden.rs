@@ -0,0 +1,212 @@
+//! Comparing synthesized audio against golden files.
+//!
+//! Golden files are 32-bit float wave files, so that they hold the samples exactly as
+//! synthesized. Inference differs slightly between machines and backends, hence the
+//! tolerances. Set `SONATA_UPDATE_GOLDEN=1` to write the golden files instead of
+//! checking against them, then review the changed files before committing them.
+
+use sonata_core::{read_wave_file, Audio, WaveFileWriter, WaveSampleFormat};
+use std::fmt;
+use std::path::Path;
+
+pub const UPDATE_GOLDEN_ENV_VAR: &str = "SONATA_UPDATE_GOLDEN";
+
+/// How far audio may be from its golden file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// Largest difference of any one sample
+    pub max_sample_error: f32,
+    /// Largest root mean square of the differences
+    pub max_rms_error: f32,
+    /// Largest difference in length, in samples. Samples beyond the shorter audio
+    /// count as differences from silence
+    pub max_len_difference: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            max_sample_error: 0.05,
+            max_rms_error: 0.01,
+            max_len_difference: 0,
+        }
+    }
+}
+
+impl Tolerance {
+    /// Audio that must match to the precision of 32-bit floats
+    pub fn exact() -> Self {
+        Self {
+            max_sample_error: 1e-6,
+            max_rms_error: 1e-6,
+            max_len_difference: 0,
+        }
+    }
+}
+
+/// Why audio does not match what was expected
+#[derive(Debug)]
+pub struct GoldenMismatch(String);
+
+impl std::error::Error for GoldenMismatch {}
+
+impl fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Check that `actual` matches `expected` within `tolerance`
+pub fn compare_samples(
+    actual: &[f32],
+    expected: &[f32],
+    tolerance: Tolerance,
+) -> Result<(), GoldenMismatch> {
+    let len_difference = actual.len().abs_diff(expected.len());
+    if len_difference > tolerance.max_len_difference {
+        return Err(GoldenMismatch(format!(
+            "Expected {} samples, got {}",
+            expected.len(),
+            actual.len()
+        )));
+    }
+    let len = actual.len().max(expected.len());
+    let sample_at = |samples: &[f32], i: usize| samples.get(i).copied().unwrap_or(0.0);
+    let mut worst = (0, 0.0f32);
+    let mut sum_of_squares = 0.0f64;
+    for i in 0..len {
+        let error = (sample_at(actual, i) - sample_at(expected, i)).abs();
+        if error > worst.1 {
+            worst = (i, error);
+        }
+        sum_of_squares += (error as f64).powi(2);
+    }
+    if worst.1 > tolerance.max_sample_error {
+        return Err(GoldenMismatch(format!(
+            "Sample {} is off by {} (expected {}, got {})",
+            worst.0,
+            worst.1,
+            sample_at(expected, worst.0),
+            sample_at(actual, worst.0)
+        )));
+    }
+    let rms_error = match len {
+        0 => 0.0,
+        _ => (sum_of_squares / len as f64).sqrt() as f32,
+    };
+    if rms_error > tolerance.max_rms_error {
+        return Err(GoldenMismatch(format!(
+            "Samples are off by {} RMS",
+            rms_error
+        )));
+    }
+    Ok(())
+}
+
+/// Check `audio` against the golden file at `golden_path`, or write it there if
+/// [`UPDATE_GOLDEN_ENV_VAR`] is set
+pub fn check_golden(
+    audio: &Audio,
+    golden_path: &Path,
+    tolerance: Tolerance,
+) -> Result<(), GoldenMismatch> {
+    if std::env::var_os(UPDATE_GOLDEN_ENV_VAR).is_some_and(|value| value != "0") {
+        return write_golden(audio, golden_path);
+    }
+    let golden = read_wave_file(golden_path).map_err(|e| {
+        GoldenMismatch(format!(
+            "{}. Set {}=1 to create it",
+            e, UPDATE_GOLDEN_ENV_VAR
+        ))
+    })?;
+    if (golden.info.sample_rate, golden.info.num_channels)
+        != (audio.info.sample_rate, audio.info.num_channels)
+    {
+        return Err(GoldenMismatch(format!(
+            "Expected {} Hz with {} channels as in `{}`, got {} Hz with {} channels",
+            golden.info.sample_rate,
+            golden.info.num_channels,
+            golden_path.display(),
+            audio.info.sample_rate,
+            audio.info.num_channels
+        )));
+    }
+    compare_samples(
+        audio.samples.as_slice(),
+        golden.samples.as_slice(),
+        tolerance,
+    )
+    .map_err(|e| GoldenMismatch(format!("{} (golden file `{}`)", e, golden_path.display())))
+}
+
+/// Like [`check_golden`], panicking if the audio does not match
+#[track_caller]
+pub fn assert_golden(audio: &Audio, golden_path: &Path, tolerance: Tolerance) {
+    if let Err(e) = check_golden(audio, golden_path, tolerance) {
+        panic!("Audio does not match its golden file: {}", e);
+    }
+}
+
+fn write_golden(audio: &Audio, golden_path: &Path) -> Result<(), GoldenMismatch> {
+    let write_error = |e: &dyn fmt::Display| {
+        GoldenMismatch(format!(
+            "Failed to write golden file `{}`. Error: {}",
+            golden_path.display(),
+            e
+        ))
+    };
+    if let Some(parent) = golden_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| write_error(&e))?;
+    }
+    let samples = audio.samples.as_slice();
+    let mut writer = WaveFileWriter::create(
+        golden_path,
+        samples.len(),
+        audio.info.sample_rate as u32,
+        audio.info.num_channels as u32,
+        WaveSampleFormat::F32,
+        None,
+    )
+    .map_err(|e| write_error(&e))?;
+    writer.write_samples(samples).map_err(|e| write_error(&e))?;
+    writer.finish().map_err(|e| write_error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_samples() {
+        let expected = vec![0.0, 0.5, -0.5, 0.0];
+        assert!(compare_samples(&expected, &expected, Tolerance::exact()).is_ok());
+        let actual = vec![0.0, 0.51, -0.5, 0.0];
+        assert!(compare_samples(&actual, &expected, Tolerance::default()).is_ok());
+        let error = compare_samples(&actual, &expected, Tolerance::exact()).unwrap_err();
+        assert!(
+            error.to_string().starts_with("Sample 1 is off"),
+            "{}",
+            error
+        );
+        let error = compare_samples(&expected[..3], &expected, Tolerance::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Expected 4 samples, got 3");
+        let tolerance = Tolerance {
+            max_len_difference: 1,
+            ..Default::default()
+        };
+        assert!(compare_samples(&expected[..3], &expected, tolerance).is_ok());
+    }
+
+    #[test]
+    fn test_golden_file() {
+        let path = std::env::temp_dir().join(format!("sonata-golden-{}.wav", std::process::id()));
+        let audio = Audio::new(vec![0.0, 0.25, -1.0, 0.125].into(), 16000, None);
+        write_golden(&audio, &path).unwrap();
+        let result = check_golden(&audio, &path, Tolerance::exact());
+        let other = Audio::new(vec![0.0, 0.25, -1.0, 0.125].into(), 22050, None);
+        let mismatch = check_golden(&other, &path, Tolerance::default());
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert!(mismatch.is_err());
+    }
+}
@@ -0,0 +1,11 @@
+//! Test helpers for sonata and the projects that use it.
+
+pub mod golden;
+mod onnx;
+mod safetensors;
+mod tiny_voice;
+
+pub use golden::{assert_golden, check_golden, compare_samples, GoldenMismatch, Tolerance};
+pub use tiny_voice::{
+    tiny_voice_samples, TinyVoice, TinyVoiceDir, TINY_VOICE_SAMPLES_PER_ID, TINY_VOICE_SAMPLE_RATE,
+};
@@ -0,0 +1,173 @@
+//! Just enough of the ONNX protobuf encoding to write small graphs.
+//!
+//! Field numbers are those of `onnx.proto`. Only the fields the tiny voice needs are
+//! covered.
+
+/// `TensorProto.DataType`
+pub(crate) const FLOAT: i64 = 1;
+pub(crate) const INT64: i64 = 7;
+/// `AttributeProto.AttributeType`
+const ATTRIBUTE_INT: u64 = 2;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+/// A protobuf message, built field by field
+#[derive(Default)]
+pub(crate) struct Message(Vec<u8>);
+
+impl Message {
+    fn key(&mut self, field: u64, wire_type: u64) {
+        write_varint(&mut self.0, field << 3 | wire_type);
+    }
+    fn varint(mut self, field: u64, value: u64) -> Self {
+        self.key(field, WIRE_VARINT);
+        write_varint(&mut self.0, value);
+        self
+    }
+    fn bytes(mut self, field: u64, bytes: &[u8]) -> Self {
+        self.key(field, WIRE_LEN);
+        write_varint(&mut self.0, bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+        self
+    }
+    fn string(self, field: u64, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+    fn message(self, field: u64, message: Message) -> Self {
+        self.bytes(field, &message.0)
+    }
+    fn packed_i64(self, field: u64, values: &[i64]) -> Self {
+        let mut bytes = Vec::new();
+        for value in values {
+            write_varint(&mut bytes, *value as u64);
+        }
+        self.bytes(field, &bytes)
+    }
+    fn packed_f32(self, field: u64, values: &[f32]) -> Self {
+        let bytes = Vec::from_iter(values.iter().flat_map(|f| f.to_le_bytes()));
+        self.bytes(field, &bytes)
+    }
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// `ModelProto` of `graph`, for the default domain at `opset`
+pub(crate) fn model(graph: Message, opset: i64) -> Message {
+    let opset_import = Message::default().string(1, "").varint(2, opset as u64);
+    Message::default()
+        .varint(1, 8)
+        .string(2, "sonata-test-utils")
+        .message(7, graph)
+        .message(8, opset_import)
+}
+
+/// `GraphProto` with the given nodes, constant tensors, inputs and outputs
+pub(crate) fn graph(
+    name: &str,
+    nodes: Vec<Message>,
+    initializers: Vec<Message>,
+    inputs: Vec<Message>,
+    outputs: Vec<Message>,
+) -> Message {
+    let mut graph = Message::default();
+    for node in nodes {
+        graph = graph.message(1, node);
+    }
+    graph = graph.string(2, name);
+    for initializer in initializers {
+        graph = graph.message(5, initializer);
+    }
+    for input in inputs {
+        graph = graph.message(11, input);
+    }
+    for output in outputs {
+        graph = graph.message(12, output);
+    }
+    graph
+}
+
+/// `NodeProto` applying `op_type` to `inputs`, with integer attributes
+pub(crate) fn node(
+    op_type: &str,
+    inputs: &[&str],
+    output: &str,
+    attributes: &[(&str, i64)],
+) -> Message {
+    let mut node = Message::default();
+    for input in inputs {
+        node = node.string(1, input);
+    }
+    node = node.string(2, output).string(3, output).string(4, op_type);
+    for (name, value) in attributes {
+        let attribute = Message::default()
+            .string(1, name)
+            .varint(3, *value as u64)
+            .varint(20, ATTRIBUTE_INT);
+        node = node.message(5, attribute);
+    }
+    node
+}
+
+/// `ValueInfoProto` of a tensor. Dimensions without a size are left unnamed, so that
+/// runtimes don't assume that dimensions of different tensors are related
+pub(crate) fn value_info(name: &str, elem_type: i64, dims: &[Option<i64>]) -> Message {
+    let mut shape = Message::default();
+    for dim in dims {
+        let dimension = match dim {
+            Some(size) => Message::default().varint(1, *size as u64),
+            None => Message::default(),
+        };
+        shape = shape.message(1, dimension);
+    }
+    let tensor_type = Message::default()
+        .varint(1, elem_type as u64)
+        .message(2, shape);
+    Message::default()
+        .string(1, name)
+        .message(2, Message::default().message(1, tensor_type))
+}
+
+/// `TensorProto` of float values
+pub(crate) fn float_tensor(name: &str, dims: &[i64], values: &[f32]) -> Message {
+    Message::default()
+        .packed_i64(1, dims)
+        .varint(2, FLOAT as u64)
+        .packed_f32(4, values)
+        .string(8, name)
+}
+
+/// `TensorProto` of int64 values
+pub(crate) fn int64_tensor(name: &str, dims: &[i64], values: &[i64]) -> Message {
+    Message::default()
+        .packed_i64(1, dims)
+        .varint(2, INT64 as u64)
+        .packed_i64(7, values)
+        .string(8, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_encoding() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, [0xac, 0x02]);
+        // Negative values take ten bytes, as in protobuf
+        let bytes = Message::default().varint(1, -1i64 as u64).into_bytes();
+        assert_eq!(bytes.len(), 11);
+        let bytes = Message::default().string(2, "ab").into_bytes();
+        assert_eq!(bytes, [0x12, 2, b'a', b'b']);
+    }
+}
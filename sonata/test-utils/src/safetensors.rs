@@ -0,0 +1,36 @@
+//! Just enough of the safetensors format to write small `float32` state dicts: the
+//! length of a JSON header, the header, then the tensors' data, little-endian.
+
+use serde_json::json;
+
+/// Tensors by name, written in order
+#[derive(Default)]
+pub(crate) struct StateDict(Vec<(String, Vec<usize>, Vec<f32>)>);
+
+impl StateDict {
+    /// Add a tensor of `shape` with every element `value`
+    pub(crate) fn fill(&mut self, name: &str, shape: &[usize], value: f32) -> &mut Self {
+        let len = shape.iter().product();
+        self.0
+            .push((name.to_string(), shape.to_vec(), vec![value; len]));
+        self
+    }
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        let mut header = serde_json::Map::new();
+        let mut data = Vec::new();
+        for (name, shape, values) in self.0 {
+            let start = data.len();
+            data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            header.insert(
+                name,
+                json!({"dtype": "F32", "shape": shape, "data_offsets": [start, data.len()]}),
+            );
+        }
+        let header = serde_json::to_vec(&header).unwrap();
+        let mut bytes = Vec::with_capacity(8 + header.len() + data.len());
+        bytes.extend((header.len() as u64).to_le_bytes());
+        bytes.extend(header);
+        bytes.extend(data);
+        bytes
+    }
+}
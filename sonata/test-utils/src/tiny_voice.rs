@@ -0,0 +1,274 @@
+//! A voice small enough to build on the fly, with the inputs and outputs of a piper VITS
+//! model.
+//!
+//! Each phoneme id becomes [`TINY_VOICE_SAMPLES_PER_ID`] samples of a sine whose
+//! frequency grows with the id, so padding is silent and the length of the audio tells
+//! how many ids were spoken. The scales are accepted but ignored. Voices with several
+//! speakers speak more quietly the higher the speaker id.
+
+use crate::onnx;
+use crate::safetensors::StateDict;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const TINY_VOICE_SAMPLE_RATE: u32 = 16000;
+pub const TINY_VOICE_SAMPLES_PER_ID: usize = 256;
+/// Peak of the audio of the first speaker
+const AMPLITUDE: f32 = 0.5;
+/// Phase increment per sample, per unit of phoneme id
+const PHASE_STEP: f32 = 0.02;
+const PUNCTUATION: &str = ".,!?'-";
+/// Numbers the directories of the voices written by [`TinyVoice::load`]
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A dummy voice with text phonemes, which needs neither espeak-ng nor a download
+#[derive(Clone, Debug)]
+pub struct TinyVoice {
+    num_speakers: u32,
+}
+
+impl Default for TinyVoice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TinyVoice {
+    pub fn new() -> Self {
+        Self { num_speakers: 1 }
+    }
+    pub fn with_speakers(mut self, num_speakers: u32) -> Self {
+        self.num_speakers = num_speakers.max(1);
+        self
+    }
+    /// The phoneme ids of the voice: padding, beginning and end of sentence, space,
+    /// ASCII letters and some punctuation
+    pub fn phoneme_id_map(&self) -> Vec<(char, i64)> {
+        let chars = ['_', '^', '$', ' ']
+            .into_iter()
+            .chain('a'..='z')
+            .chain('A'..='Z')
+            .chain(PUNCTUATION.chars());
+        Vec::from_iter(chars.zip(0..))
+    }
+    /// The piper config of the voice
+    pub fn config_json(&self) -> String {
+        let phoneme_id_map = serde_json::Map::from_iter(
+            self.phoneme_id_map()
+                .into_iter()
+                .map(|(phoneme, id)| (phoneme.to_string(), json!([id]))),
+        );
+        let speaker_id_map = serde_json::Map::from_iter(
+            (0..self.num_speakers as i64).map(|id| (format!("speaker_{}", id), json!(id))),
+        );
+        let config = json!({
+            "key": "tiny",
+            "language": {"code": "en_US"},
+            "audio": {"sample_rate": TINY_VOICE_SAMPLE_RATE, "quality": "x_low"},
+            "espeak": {"voice": "en-us"},
+            "phoneme_type": "text",
+            "inference": {"noise_scale": 0.667, "length_scale": 1.0, "noise_w": 0.8},
+            "num_symbols": phoneme_id_map.len(),
+            "num_speakers": self.num_speakers,
+            "speaker_id_map": speaker_id_map,
+            "phoneme_map": {},
+            "phoneme_id_map": phoneme_id_map,
+        });
+        serde_json::to_string_pretty(&config).unwrap()
+    }
+    /// The ONNX model of the voice
+    pub fn model_bytes(&self) -> Vec<u8> {
+        let frames_per_id = TINY_VOICE_SAMPLES_PER_ID as i64;
+        let ramp = Vec::from_iter((0..frames_per_id).map(|i| i as f32 * PHASE_STEP));
+        let mut initializers = vec![
+            onnx::int64_tensor("frames_shape", &[3], &[1, -1, 1]),
+            onnx::int64_tensor("output_shape", &[3], &[1, 1, -1]),
+            onnx::float_tensor("ramp", &[frames_per_id], &ramp),
+            onnx::float_tensor("amplitude", &[1], &[AMPLITUDE]),
+        ];
+        let mut nodes = vec![
+            onnx::node("Cast", &["input"], "ids", &[("to", onnx::FLOAT)]),
+            onnx::node("Reshape", &["ids", "frames_shape"], "frames", &[]),
+            onnx::node("Mul", &["frames", "ramp"], "phase", &[]),
+            onnx::node("Sin", &["phase"], "wave", &[]),
+        ];
+        let mut inputs = vec![
+            onnx::value_info("input", onnx::INT64, &[Some(1), None]),
+            onnx::value_info("input_lengths", onnx::INT64, &[Some(1)]),
+            onnx::value_info("scales", onnx::FLOAT, &[Some(3)]),
+        ];
+        if self.num_speakers > 1 {
+            inputs.push(onnx::value_info("sid", onnx::INT64, &[Some(1)]));
+            initializers.push(onnx::float_tensor("one", &[1], &[1.0]));
+            nodes.extend([
+                onnx::node("Cast", &["sid"], "speaker", &[("to", onnx::FLOAT)]),
+                onnx::node("Add", &["speaker", "one"], "divisor", &[]),
+                onnx::node("Div", &["amplitude", "divisor"], "gain", &[]),
+            ]);
+        } else {
+            nodes.push(onnx::node("Identity", &["amplitude"], "gain", &[]));
+        }
+        nodes.extend([
+            onnx::node("Mul", &["wave", "gain"], "samples", &[]),
+            onnx::node("Reshape", &["samples", "output_shape"], "output", &[]),
+        ]);
+        let outputs = vec![onnx::value_info(
+            "output",
+            onnx::FLOAT,
+            &[Some(1), Some(1), None],
+        )];
+        let graph = onnx::graph("tiny_voice", nodes, initializers, inputs, outputs);
+        onnx::model(graph, 13).into_bytes()
+    }
+    /// The weights of a minimal VITS generator for the voice, in safetensors, for the
+    /// `candle` backend of `sonata-piper`. Unlike the ONNX model, it runs the layers of a
+    /// real voice, with constant weights: each phoneme id lasts one frame per unit of
+    /// `length_scale`, and each frame gives 4 samples of the same value, except at the
+    /// edges of the audio
+    pub fn safetensors_bytes(&self) -> Vec<u8> {
+        let num_symbols = self.phoneme_id_map().len();
+        let channels = 2;
+        let mut state_dict = StateDict::default();
+        state_dict
+            .fill("enc_p.emb.weight", &[num_symbols, channels], 0.1)
+            .fill("enc_p.proj.weight", &[2 * channels, channels, 1], 0.0)
+            .fill("enc_p.proj.bias", &[2 * channels], 0.5);
+        // A deterministic duration predictor, whose log durations are all zero
+        for layer in ["conv_1", "conv_2"] {
+            state_dict
+                .fill(
+                    &format!("dp.{}.weight", layer),
+                    &[channels, channels, 1],
+                    0.0,
+                )
+                .fill(&format!("dp.{}.bias", layer), &[channels], 0.0);
+        }
+        for norm in ["norm_1", "norm_2"] {
+            state_dict
+                .fill(&format!("dp.{}.gamma", norm), &[channels], 1.0)
+                .fill(&format!("dp.{}.beta", norm), &[channels], 0.0);
+        }
+        state_dict
+            .fill("dp.proj.weight", &[1, channels, 1], 0.0)
+            .fill("dp.proj.bias", &[1], 0.0);
+        // A decoder upsampling each frame to 4 samples
+        state_dict
+            .fill("dec.conv_pre.weight", &[channels, channels, 1], 0.0)
+            .fill("dec.conv_pre.bias", &[channels], 0.5)
+            .fill("dec.ups.0.weight", &[channels, channels, 8], 0.25)
+            .fill("dec.ups.0.bias", &[channels], 0.0);
+        for conv in 0..2 {
+            state_dict
+                .fill(
+                    &format!("dec.resblocks.0.convs.{}.weight", conv),
+                    &[channels, channels, 1],
+                    0.0,
+                )
+                .fill(
+                    &format!("dec.resblocks.0.convs.{}.bias", conv),
+                    &[channels],
+                    0.0,
+                );
+        }
+        state_dict.fill("dec.conv_post.weight", &[1, channels, 1], 1.0);
+        if self.num_speakers > 1 {
+            state_dict.fill("emb_g.weight", &[self.num_speakers as usize, channels], 0.0);
+        }
+        state_dict.into_bytes()
+    }
+    /// Write the model and its config to `dir`, returning the path of the config, which
+    /// is what voices are loaded from
+    pub fn write_to(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let model_path = dir.join("tiny.onnx");
+        std::fs::write(&model_path, self.model_bytes())?;
+        let config_path = dir.join("tiny.onnx.json");
+        std::fs::write(&config_path, self.config_json())?;
+        Ok(config_path)
+    }
+    /// Write the voice to a directory of its own, which is removed along with the
+    /// returned guard, even when a test fails
+    pub fn load(&self) -> TinyVoiceDir {
+        let dir = std::env::temp_dir().join(format!(
+            "sonata-tiny-voice-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let config_path = self.write_to(&dir).unwrap();
+        TinyVoiceDir { dir, config_path }
+    }
+    /// The number of samples the voice outputs for `text`, which piper turns into the
+    /// beginning of sentence id, an id and a pad per known character, and the end id
+    pub fn expected_len(&self, text: &str) -> usize {
+        let phoneme_id_map = self.phoneme_id_map();
+        let num_known = text
+            .chars()
+            .filter(|c| phoneme_id_map.iter().any(|(phoneme, _)| phoneme == c))
+            .count();
+        (num_known * 2 + 2) * TINY_VOICE_SAMPLES_PER_ID
+    }
+}
+
+/// The directory of a voice written by [`TinyVoice::load`], removed on drop
+#[derive(Debug)]
+pub struct TinyVoiceDir {
+    dir: PathBuf,
+    config_path: PathBuf,
+}
+
+impl TinyVoiceDir {
+    /// The path of the config, which is what voices are loaded from
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+    /// The path of `name` in the directory, for other files of the test
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for TinyVoiceDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The expected samples of the tiny voice for `ids` with the given speaker
+pub fn tiny_voice_samples(ids: &[i64], speaker: i64) -> Vec<f32> {
+    let gain = AMPLITUDE / (speaker as f32 + 1.0);
+    Vec::from_iter(ids.iter().flat_map(|id| {
+        (0..TINY_VOICE_SAMPLES_PER_ID)
+            .map(move |i| (*id as f32 * (i as f32 * PHASE_STEP)).sin() * gain)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiny_voice_config() {
+        let voice = TinyVoice::new().with_speakers(2);
+        let config: serde_json::Value = serde_json::from_str(&voice.config_json()).unwrap();
+        assert_eq!(config["phoneme_id_map"]["_"], json!([0]));
+        assert_eq!(config["phoneme_id_map"]["a"], json!([4]));
+        assert_eq!(config["num_symbols"], json!(voice.phoneme_id_map().len()));
+        assert_eq!(config["speaker_id_map"]["speaker_1"], json!(1));
+        assert_eq!(voice.expected_len("Hi!"), 8 * TINY_VOICE_SAMPLES_PER_ID);
+        // Characters the voice has no id for are dropped
+        assert_eq!(voice.expected_len("é"), 2 * TINY_VOICE_SAMPLES_PER_ID);
+    }
+
+    #[test]
+    fn test_tiny_voice_dir() {
+        let voice = TinyVoice::new();
+        let (first, second) = (voice.load(), voice.load());
+        assert_ne!(first.config_path(), second.config_path());
+        assert!(first.config_path().exists());
+        let dir = first.join("");
+        drop(first);
+        assert!(!dir.exists());
+        assert!(second.config_path().exists());
+    }
+}